@@ -0,0 +1,76 @@
+//! Benchmarks the access point list aggregation of the portal.
+//! Dense environments (apartment buildings) easily report 200+ BSS entries for a few dozen SSIDs.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+use wifi_captive::{WifiConnection, WifiConnectionEvent, WifiConnectionEventType, WifiConnections};
+
+const BSS_ENTRIES: usize = 250;
+const DISTINCT_SSIDS: usize = 60;
+
+fn access_point(index: usize) -> WifiConnection {
+    WifiConnection {
+        ssid: format!("Network {}", index % DISTINCT_SSIDS).into(),
+        hw: format!("00:11:22:33:{:02X}:{:02X}", index / 256, index % 256),
+        security: "wpa",
+        strength: (index % 100) as u8,
+        frequency: if index % 2 == 0 { 2412 } else { 5180 },
+        is_own: false,
+        vendor: None,
+    }
+}
+
+fn events(event: WifiConnectionEventType) -> Vec<WifiConnectionEvent> {
+    (0..BSS_ENTRIES)
+        .map(|index| WifiConnectionEvent {
+            access_point: access_point(index),
+            event,
+        })
+        .collect()
+}
+
+fn aggregate_added(c: &mut Criterion) {
+    let added = events(WifiConnectionEventType::Added);
+    c.bench_function("aggregate 250 added access points", |b| {
+        b.iter_batched(
+            || WifiConnections(Vec::new()),
+            |mut connections| {
+                for event in &added {
+                    connections.update(black_box(event));
+                }
+                connections
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn aggregate_added_and_removed(c: &mut Criterion) {
+    let added = events(WifiConnectionEventType::Added);
+    let removed = events(WifiConnectionEventType::Removed);
+    c.bench_function("aggregate 250 added and removed access points", |b| {
+        b.iter_batched(
+            || WifiConnections(Vec::new()),
+            |mut connections| {
+                for (add, remove) in added.iter().zip(removed.iter().rev()) {
+                    connections.update(black_box(add));
+                    connections.update(black_box(remove));
+                }
+                connections
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn serialize_list(c: &mut Criterion) {
+    let mut connections = WifiConnections(Vec::new());
+    // Do not aggregate here: This is the worst case of the json list that is send to the browser
+    connections.0.extend((0..BSS_ENTRIES).map(access_point));
+    c.bench_function("serialize 250 access points", |b| {
+        b.iter(|| serde_json::to_string(black_box(&connections)).unwrap())
+    });
+}
+
+criterion_group!(benches, aggregate_added, aggregate_added_and_removed, serialize_list);
+criterion_main!(benches);
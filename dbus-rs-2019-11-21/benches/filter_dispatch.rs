@@ -0,0 +1,66 @@
+//! Benchmarks the nonblock signal dispatch: Each incoming message is matched
+//! against all registered match rules (see `Process::process_one`).
+//! Requires the "bench" feature: `cargo bench -p dbus_copy --features bench`
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use dbus_copy::filters::Filters;
+use dbus_copy::message::MatchRule;
+use dbus_copy::Message;
+
+const INTERFACE: &str = "org.freedesktop.NetworkManager.Connection.Active";
+const MEMBER: &str = "StateChanged";
+
+fn rule(index: usize) -> MatchRule<'static> {
+    let mut rule = MatchRule::new_signal(INTERFACE, MEMBER);
+    rule.path = Some(format!("/org/freedesktop/NetworkManager/ActiveConnection/{}", index).into());
+    rule.static_clone()
+}
+
+fn signal(index: usize) -> Message {
+    Message::new_signal(
+        format!("/org/freedesktop/NetworkManager/ActiveConnection/{}", index),
+        INTERFACE,
+        MEMBER,
+    )
+    .unwrap()
+}
+
+/// Performs the same steps as the nonblock connections do for an incoming signal:
+/// Take the matching filter out, call it, and put it back in.
+fn dispatch(filters: &mut Filters<Box<dyn FnMut(&Message) -> bool>>, msg: &Message) {
+    if let Some(mut filter) = filters.remove_matching(msg) {
+        if (filter.2)(msg) {
+            filters.insert(filter);
+        }
+    }
+}
+
+fn dispatch_with_rules(c: &mut Criterion, rules: usize) {
+    let mut filters: Filters<Box<dyn FnMut(&Message) -> bool>> = Default::default();
+    for index in 0..rules {
+        filters.add(rule(index), Box::new(|_| true));
+    }
+    let first = signal(0);
+    let last = signal(rules - 1);
+    let unmatched = signal(rules);
+
+    c.bench_function(&format!("dispatch first of {} rules", rules), |b| {
+        b.iter(|| dispatch(&mut filters, black_box(&first)))
+    });
+    c.bench_function(&format!("dispatch last of {} rules", rules), |b| {
+        b.iter(|| dispatch(&mut filters, black_box(&last)))
+    });
+    c.bench_function(&format!("dispatch unmatched with {} rules", rules), |b| {
+        b.iter(|| dispatch(&mut filters, black_box(&unmatched)))
+    });
+}
+
+fn dispatch_benches(c: &mut Criterion) {
+    dispatch_with_rules(c, 10);
+    dispatch_with_rules(c, 100);
+    dispatch_with_rules(c, 1000);
+}
+
+criterion_group!(benches, dispatch_benches);
+criterion_main!(benches);
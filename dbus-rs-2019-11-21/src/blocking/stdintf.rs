@@ -294,6 +294,30 @@ impl<'a, T: blocking::BlockingSender, C: ::std::ops::Deref<Target=T>> DBus for b
     }
 }
 
+pub trait DBusDebugStats {
+    fn get_stats(&self) -> Result<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>, dbus::Error>;
+    fn get_connection_stats(&self, arg0: &str) -> Result<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>, dbus::Error>;
+    fn get_all_match_rules(&self) -> Result<::std::collections::HashMap<String, Vec<String>>, dbus::Error>;
+}
+
+impl<'a, T: blocking::BlockingSender, C: ::std::ops::Deref<Target=T>> DBusDebugStats for blocking::Proxy<'a, C> {
+
+    fn get_stats(&self) -> Result<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>, dbus::Error> {
+        self.method_call("org.freedesktop.DBus.Debug.Stats", "GetStats", ())
+            .and_then(|r: (::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>,)| Ok(r.0))
+    }
+
+    fn get_connection_stats(&self, arg0: &str) -> Result<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>, dbus::Error> {
+        self.method_call("org.freedesktop.DBus.Debug.Stats", "GetConnectionStats", (arg0, ))
+            .and_then(|r: (::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>,)| Ok(r.0))
+    }
+
+    fn get_all_match_rules(&self) -> Result<::std::collections::HashMap<String, Vec<String>>, dbus::Error> {
+        self.method_call("org.freedesktop.DBus.Debug.Stats", "GetAllMatchRules", ())
+            .and_then(|r: (::std::collections::HashMap<String, Vec<String>>,)| Ok(r.0))
+    }
+}
+
 #[derive(Debug)]
 pub struct DBusNameOwnerChanged {
     pub arg0: String,
@@ -156,6 +156,17 @@ impl Drop for WatchMap {
 pub struct Channel {
     handle: ConnHandle,
     watchmap: Option<Box<WatchMap>>,
+    monitor: Option<Monitor>,
+}
+
+/// Observes the messages of a channel, see [`Channel::set_monitor`].
+/// The flag is true for sent messages and false for received ones.
+pub type MonitorCb = Box<dyn Fn(&Message, bool) + Send + Sync + 'static>;
+
+struct Monitor(MonitorCb);
+
+impl std::fmt::Debug for Monitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { write!(f, "Monitor") }
 }
 
 impl Drop for Channel {
@@ -176,7 +187,7 @@ impl Channel {
         /* No, we don't want our app to suddenly quit if dbus goes down */
         unsafe { ffi::dbus_connection_set_exit_on_disconnect(ptr, 0) };
 
-        let c = Channel { handle, watchmap: None };
+        let c = Channel { handle, watchmap: None, monitor: None };
 
         Ok(c)
     }
@@ -255,9 +266,17 @@ impl Channel {
         let mut serial = 0u32;
         let r = unsafe { ffi::dbus_connection_send(self.conn(), msg.ptr(), &mut serial) };
         if r == 0 { return Err(()); }
+        if let Some(m) = &self.monitor { (m.0)(&msg, true) }
         Ok(serial)
     }
 
+    /// Calls the given function with every message that is sent or popped from the incoming queue.
+    ///
+    /// Messages sent with "send_with_reply_and_block" are not observed.
+    pub fn set_monitor(&mut self, f: Option<MonitorCb>) {
+        self.monitor = f.map(Monitor);
+    }
+
     /// Sends a message over the D-Bus and waits for a reply. This is used for method calls.
     ///
     /// Blocking: until a reply is received or the timeout expires.
@@ -309,7 +328,7 @@ impl Channel {
             None
         } else {
             let msg = Message::from_ptr(mptr, false);
-            // println!("Incoming: {:?}", msg);
+            if let Some(m) = &self.monitor { (m.0)(&msg, false) }
             Some(msg)
         }
     }
@@ -17,6 +17,10 @@ impl<F> Default for Filters<F> {
 }
 
 impl<F> Filters<F> {
+    pub fn len(&self) -> usize { self.list.len() }
+
+    pub fn is_empty(&self) -> bool { self.list.is_empty() }
+
     pub fn add(&mut self, m: MatchRule<'static>, f: F) -> Token {
         let id = self.nextid;
         self.nextid.0 += 1;
@@ -31,7 +31,12 @@ pub use error::Error;
 
 pub mod channel;
 
+#[cfg(not(feature = "bench"))]
 mod filters;
+// Public with the "bench" feature, for benchmarking the dispatch only
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod filters;
 
 // Not ready for release yet
 #[doc(hidden)]
@@ -145,6 +145,11 @@ impl Message {
         if s == 0 { None } else { Some(s) }
     }
 
+    /// Get the error name of an error message, e g 'org.freedesktop.DBus.Error.Failed'.
+    pub fn error_name(&self) -> Option<String> {
+        self.set_error_from_msg().err().and_then(|e| e.name().map(|n| n.to_owned()))
+    }
+
     /// Returns true if the message does not expect a reply.
     pub fn get_no_reply(&self) -> bool { unsafe { ffi::dbus_message_get_no_reply(self.msg) != 0 } }
 
@@ -139,6 +139,12 @@ impl Process for $c {
 }
 
 impl $c {
+    /// Number of method calls that wait for a reply.
+    pub fn pending_replies(&self) -> usize { self.replies_mut().len() }
+
+    /// Number of registered message filters (match rules).
+    pub fn filter_count(&self) -> usize { self.filters_mut().len() }
+
     /// Get the connection's unique name.
     ///
     /// It's usually something like ":1.54"
@@ -130,6 +130,30 @@ impl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target=T>> DBus for no
     }
 }
 
+pub trait DBusDebugStats {
+    fn get_stats(&self) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>>;
+    fn get_connection_stats(&self, arg0: &str) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>>;
+    fn get_all_match_rules(&self) -> nonblock::MethodReply<::std::collections::HashMap<String, Vec<String>>>;
+}
+
+impl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target=T>> DBusDebugStats for nonblock::Proxy<'a, C> {
+
+    fn get_stats(&self) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>> {
+        self.method_call("org.freedesktop.DBus.Debug.Stats", "GetStats", ())
+            .and_then(|r: (::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>, )| Ok(r.0, ))
+    }
+
+    fn get_connection_stats(&self, arg0: &str) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>> {
+        self.method_call("org.freedesktop.DBus.Debug.Stats", "GetConnectionStats", (arg0, ))
+            .and_then(|r: (::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>, )| Ok(r.0, ))
+    }
+
+    fn get_all_match_rules(&self) -> nonblock::MethodReply<::std::collections::HashMap<String, Vec<String>>> {
+        self.method_call("org.freedesktop.DBus.Debug.Stats", "GetAllMatchRules", ())
+            .and_then(|r: (::std::collections::HashMap<String, Vec<String>>, )| Ok(r.0, ))
+    }
+}
+
 #[derive(Debug)]
 pub struct DBusNameOwnerChanged {
     pub arg0: String,
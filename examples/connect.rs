@@ -2,7 +2,7 @@ pub mod shared;
 
 use structopt::StructOpt;
 
-use wifi_captive::{credentials_from_data, NetworkBackend, Security};
+use wifi_captive::{credentials_from_data, ConnectOptions, NetworkBackend, Security};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -11,10 +11,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let manager = NetworkBackend::new(&config.interface).await?;
     let state = manager
         .connect_to(
-            config.ssid,
-            credentials_from_data(config.passphrase, None, Security::WPA2)?,
+            config.ssid.into(),
+            credentials_from_data(config.passphrase, None, None, None, Security::WPA2)?,
             None,
-            true,
+            ConnectOptions { overwrite_same_ssid_connection: true, ..ConnectOptions::default() },
         )
         .await?;
 
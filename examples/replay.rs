@@ -0,0 +1,19 @@
+use wifi_captive::dbus_record::{self, Replay};
+use wifi_captive::dbus_tokio;
+
+use log::{info, LevelFilter};
+
+/// Replays a trace of `--record-dbus` as network manager on the system bus.
+/// Point `DBUS_SYSTEM_BUS_ADDRESS` of this example and of the service to a private bus.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::builder().filter_level(LevelFilter::Info).init();
+
+    let path = std::env::args().nth(1).ok_or("Usage: replay <trace file>")?;
+    let replay = Replay::parse(&std::fs::read_to_string(path)?)?;
+    let (resource, conn) = dbus_tokio::new_system_default()?;
+    let resource = tokio::spawn(resource);
+    dbus_record::serve(replay, conn).await?;
+    info!("Replaying until the bus connection is lost");
+    Err(resource.await?.to_string().into())
+}
@@ -18,6 +18,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let _ = nm_clone.print_connectivity_changes().await;
     });
 
+    let nm_clone = manager.clone();
+    tokio::spawn(async move {
+        let _ = nm_clone.print_device_changes().await;
+    });
+
     manager.print_connection_changes().await?;
 
     Ok(())
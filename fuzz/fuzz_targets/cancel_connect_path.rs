@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use wifi_captive::http_server::fuzz::cancel_connect_path;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(path) = std::str::from_utf8(data) {
+        if cancel_connect_path(path).is_some() {
+            assert!(path.starts_with("/api/connect/") && path.ends_with("/cancel"));
+        }
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use wifi_captive::dhcp_server::packet::decode;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(packet) = decode(data) {
+        let _ = packet.message_type();
+    }
+});
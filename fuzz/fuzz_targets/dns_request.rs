@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use wifi_captive::dns_server::decode_request;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decode_request(data);
+});
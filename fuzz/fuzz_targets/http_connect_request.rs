@@ -0,0 +1,22 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use std::convert::TryInto;
+use wifi_captive::credentials_from_data;
+use wifi_captive::http_server::parse_connection_request;
+
+// Follows the path of a "/connect" body: Parse the json request and
+// convert it into credentials, like the state machine does.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(request) = parse_connection_request(data) {
+        if let Ok(mode) = request.mode.try_into() {
+            let _ = credentials_from_data(
+                request.passphrase.unwrap_or_default(),
+                request.identity,
+                request.anonymous_identity,
+                request.eap,
+                mode,
+            );
+        }
+    }
+});
@@ -0,0 +1,14 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use wifi_captive::http_server::fuzz::{network_query, query_param};
+
+// Follows the path of a "/api/networks" query: Parse the uri like hyper does, then read the parameters.
+fuzz_target!(|data: &[u8]| {
+    let mut uri = b"/api/networks?".to_vec();
+    uri.extend_from_slice(data);
+    if let Ok(uri) = hyper::Uri::from_maybe_shared(uri) {
+        let _ = query_param(&uri, "lang");
+        let _ = network_query(&uri);
+    }
+});
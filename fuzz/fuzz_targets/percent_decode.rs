@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use wifi_captive::http_server::fuzz::percent_decode_bytes;
+
+// Decoding never grows the value: An escape of three bytes becomes one byte.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(value) = std::str::from_utf8(data) {
+        assert!(percent_decode_bytes(value).len() <= value.len());
+    }
+});
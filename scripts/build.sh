@@ -40,107 +40,23 @@ prerequirements() {
         say "Download aarch64 musl cross compiler toolchain"
         download https://musl.cc/aarch64-linux-musl-cross.tgz $DEST/aarch64 2
     fi
-
-    local libexecinfo="$DEST/libexecinfo"
-    if [ ! -d $libexecinfo ]; then
-        say "Download libexecinfo"
-        download https://github.com/resslinux/libexecinfo/archive/master.zip $libexecinfo 1 zip
-        ensure mv $libexecinfo/libexecinfo-master/* $libexecinfo
-        ensure rm -rf $libexecinfo/libexecinfo-master
-    fi
-
-    local dbusdir="$DEST/libdbus"
-    if [ ! -d $dbusdir ]; then
-        say "Download libdbus"
-        download https://gitlab.freedesktop.org/dbus/dbus/-/archive/master/dbus-master.tar.gz $DEST/libdbus_all
-        ensure mv $DEST/libdbus_all/dbus $dbusdir
-        ensure rm -rf $DEST/libdbus_all
-        ensure mkdir -p $dbusdir/dbus
-
-        # Move all header files to dbus directory
-        ensure mv $dbusdir/*.h $dbusdir/dbus
-
-        # Remove all non-c files
-        ensure find $dbusdir -maxdepth 1 -type f ! -name "*.c" -exec rm {} \;
-
-        pushd $dbusdir > /dev/null
-        mkdir -p unix; mkdir -p win; mkdir -p wince
-        ensure find . -maxdepth 1 -type f -name '*unix*' -or -name '*userdb*' -or -name '*epoll*' -exec mv {} unix/{} \;
-        ensure find . -maxdepth 1 -type f -name '*wince*' -exec mv {} wince/{} \;
-        ensure find . -maxdepth 1 -type f -name '*win*' -exec mv {} win/{} \;
-        popd > /dev/null
-    fi
-
-    ensure cp scripts/config.h $dbusdir/dbus
-    ensure cp scripts/dbus-arch-deps.h $dbusdir/dbus
-}
-
-compile() {
-    # Inputs
-    local ARCH="$1"
-    local BIN_PREFIX="$2"
-    local ADD_COMP="$3"
-    local libexecinfo="$DEST/libexecinfo"
-    local dbusdir="$DEST/libdbus"
-
-    say "Build libdbus for $ARCH"
-
-    local LINK="$DEST/$ARCH/lib"
-    # variant is something like: aarch64-linux-musl
-    local compiler_variant=$(ls $LINK/gcc)
-    # The path depends on the compiler variant and version, eg: ./lib/gcc/aarch64-linux-musl/9.1.1/include
-    local compiler_inc_path="$LINK/gcc/$compiler_variant/$(ls $LINK/gcc/$compiler_variant)/include"
-    local INCLUDES="-I$DEST/$ARCH/include -I$compiler_inc_path"
-    local CC=$(realpath "$DEST/$ARCH/bin/${BIN_PREFIX}gcc")
-    local AR=$(realpath "$DEST/$ARCH/bin/${BIN_PREFIX}ar")
-    local dbusbuilddir="$DEST/libdbus_build"
-
-    if [ ! -f "$DEST/libdbus_static_$ARCH.a" ]; then
-        mkdir -p $dbusbuilddir
-        rm -rf $dbusbuilddir/* > /dev/null
-
-        # Compile
-        pushd $dbusbuilddir > /dev/null
-        [ "$ARCH" = "x86_64" ] && $CC -c $(ls $libexecinfo/*.c) "-l$LINK" $INCLUDES "-I$libexecinfo"  -std=gnu11
-        $CC -c $(ls $dbusdir/*.c) $(ls $dbusdir/unix/*.c) $(ls $dbusdir/unix/*.c) \
-            "-l$LINK" $INCLUDES "-I$dbusdir" "-I$dbusdir/dbus" "-I$libexecinfo" \
-            -DDBUS_COMPILATION -D_GNU_SOURCE -pthread -Wno-cpp -std=gnu11 $ADD_COMP
-        popd > /dev/null
-
-        # Link
-        # Linking conventions: Start library with "lib"
-        # c: create, r: insert with replacement, s: object-file index
-        $AR crs $DEST/libdbus_static_$ARCH.a $(ls $dbusbuilddir/*.o)
-    fi
-    #objdump -f $dbusbuilddir/dbus_static.a
-
-    # Create pkg-config file for the libdbus-sys crate
-    local DEST_ARCH=$(realpath "$DEST/$ARCH")
-printf "
-prefix=$DEST_ARCH
-exec_prefix=\${prefix}
-libdir=\${prefix}/lib/$compiler_variant
-includedir=\${prefix}/include
-
-Name: dbus-1
-Description: Static dbus library
-Version: 1.13
-Requires.private:
-Libs: -L$DEST
-Libs.private: -lgcc -ldbus_static_$ARCH
-Cflags: -I\${includedir}
-" > $DEST/$ARCH/dbus-1.pc
 }
 
 compile_crate() {
     local ARCH="$1"
     local TARGET="$2"
+    local BIN_PREFIX="$3"
     local DEST_ARCH="$DEST/$ARCH"
     export PATH=$PATH:$DEST_ARCH/bin
-    export PKG_CONFIG_PATH="$DEST_ARCH"
-    export PKG_CONFIG_LIBDIR="$DEST_ARCH/lib"
+
+    # The "vendored-dbus" feature compiles libdbus in the build script of libdbus-sys and links it statically.
+    # The cc crate picks the musl compiler of the target from these variables.
+    local TARGET_VAR=$(echo $TARGET | tr '-' '_')
+    export CC_$TARGET_VAR=$(realpath "$DEST_ARCH/bin/${BIN_PREFIX}gcc")
+    export AR_$TARGET_VAR=$(realpath "$DEST_ARCH/bin/${BIN_PREFIX}ar")
+
     say "Build crate for $ARCH"
-    ensure cargo build --release --features includeui --target $TARGET
+    ensure cargo build --release --features includeui,vendored-dbus --target $TARGET
     local METADATA=$(cargo metadata --format-version 1 | jq -r '.workspace_members[]' | tail -n1)
     CRATE_NAME=$(echo $METADATA | cut -d' ' -f1)
     CRATE_VERSION=$(echo $METADATA | cut -d' ' -f2)
@@ -194,18 +110,12 @@ err() {
 }
 
 prerequirements
-compile "x86_64" "" ""
-compile "aarch64" "aarch64-linux-musl-" "-DHAVE_BACKTRACE=0"
-compile "armv7" "armv7l-linux-musleabihf-" "-DHAVE_BACKTRACE=0"
-
-export PKG_CONFIG_ALLOW_CROSS=1
-export PKG_CONFIG_ALL_STATIC=1
 
 rustup target add armv7-unknown-linux-musleabihf
 rustup target add aarch64-unknown-linux-musl
 
-compile_crate "x86_64" "x86_64-unknown-linux-musl"
-compile_crate "aarch64" "aarch64-unknown-linux-musl"
-compile_crate "armv7" "armv7-unknown-linux-musleabihf"
+compile_crate "x86_64" "x86_64-unknown-linux-musl" ""
+compile_crate "aarch64" "aarch64-unknown-linux-musl" "aarch64-linux-musl-"
+compile_crate "armv7" "armv7-unknown-linux-musleabihf" "armv7l-linux-musleabihf-"
 
 exit 0
@@ -0,0 +1,14 @@
+#!/bin/sh -e
+# Downloads the IEEE MA-L (OUI) registry and writes the vendor table that is embedded
+# with the "oui" feature. The bundled table only contains common access point vendors.
+# Requires curl and gawk.
+
+DEST="$(dirname "$0")/../data/oui.txt"
+
+{
+    echo "# IEEE MA-L (OUI) assignments: prefix <tab> organization. Sorted by prefix."
+    echo "# Generated by scripts/update_oui.sh from https://standards-oui.ieee.org/oui/oui.csv"
+    curl -sSL https://standards-oui.ieee.org/oui/oui.csv |
+        gawk -v FPAT='([^,]*)|("[^"]*")' 'NR > 1 && $1 == "MA-L" { gsub(/"/, "", $3); print $2 "\t" $3 }' |
+        sort -u -t "$(printf '\t')" -k1,1
+} > "$DEST"
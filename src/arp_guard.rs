@@ -0,0 +1,159 @@
+//! # ARP spoof detection
+//! The hotspot is an open network during provisioning. A client that answers ARP requests for the gateway
+//! address receives the traffic of the other clients, including the credentials entered in the portal.
+//!
+//! While the portal is open, the ARP packets on the wifi interface are watched. If another hardware address
+//! claims the gateway address, a warning is logged, the "wifi_captive_arp_spoof_packets" metric is counted
+//! up and the portal shows a warning banner (`arp_spoof` in the status).
+
+use crate::netlink;
+use crate::runtime::{FdRegistration, Runtime, Tokio};
+use crate::status::StatusSender;
+use crate::CaptivePortalError;
+use futures_util::future::poll_fn;
+use nix::libc;
+use std::fs::File;
+use std::io;
+use std::net::Ipv4Addr;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const ETH_P_ARP: u16 = 0x0806;
+const ETH_P_IP: u16 = 0x0800;
+const ARPHRD_ETHER: u16 = 1;
+
+/// Spoofed ARP packets since the start of the service
+static SPOOFED_PACKETS: AtomicU64 = AtomicU64::new(0);
+
+/// The number of ARP packets that claimed the gateway address for another hardware address
+pub fn spoofed_packets() -> u64 {
+    SPOOFED_PACKETS.load(Ordering::Relaxed)
+}
+
+/// The sender hardware and protocol address of an ethernet / IPv4 ARP packet. Requests and replies
+/// both announce the address of their sender.
+fn sender(packet: &[u8]) -> Option<(String, Ipv4Addr)> {
+    if packet.len() < 28
+        || u16::from_be_bytes([packet[0], packet[1]]) != ARPHRD_ETHER
+        || u16::from_be_bytes([packet[2], packet[3]]) != ETH_P_IP
+        || packet[4] != 6
+        || packet[5] != 4
+    {
+        return None;
+    }
+    let hw: Vec<String> = packet[8..14].iter().map(|b| format!("{:02x}", b)).collect();
+    let ip = Ipv4Addr::new(packet[14], packet[15], packet[16], packet[17]);
+    Some((hw.join(":"), ip))
+}
+
+/// A packet socket for the ARP packets of the given interface, without the link layer header
+fn arp_socket(interface_name: &str) -> Result<File, CaptivePortalError> {
+    let index = netlink::interface_index(interface_name)?;
+    let protocol = ETH_P_ARP.to_be();
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_PACKET,
+            libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            protocol as libc::c_int,
+        )
+    };
+    if fd < 0 {
+        return Err(CaptivePortalError::IO(io::Error::last_os_error(), "Failed to open the ARP socket"));
+    }
+    // Closes the socket when dropped
+    let socket = unsafe { File::from_raw_fd(fd) };
+
+    let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+    address.sll_family = libc::AF_PACKET as u16;
+    address.sll_protocol = protocol;
+    address.sll_ifindex = index as i32;
+    let r = unsafe {
+        libc::bind(
+            fd,
+            &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+        )
+    };
+    if r < 0 {
+        return Err(CaptivePortalError::IO(io::Error::last_os_error(), "Failed to bind the ARP socket"));
+    }
+    Ok(socket)
+}
+
+/// Watches the ARP packets of the hotspot interface until the future is dropped.
+/// Clears a previous alert first. Resolves if the packets cannot be read, for example without CAP_NET_RAW.
+pub async fn watch(interface_name: &str, gateway: Ipv4Addr, status: &StatusSender) {
+    status.set_arp_spoof(None);
+    if let Err(e) = watch_inner(interface_name, gateway, status).await {
+        warn!("ARP spoof detection is not available: {}", e);
+    }
+}
+
+async fn watch_inner(
+    interface_name: &str,
+    gateway: Ipv4Addr,
+    status: &StatusSender,
+) -> Result<(), CaptivePortalError> {
+    let own_hw = std::fs::read_to_string(format!("/sys/class/net/{}/address", interface_name))
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to read the wifi interface address"))?
+        .trim()
+        .to_lowercase();
+    let socket = arp_socket(interface_name)?;
+    let registration = Tokio::register(socket.as_raw_fd())
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to register the ARP socket"))?;
+    info!("Watching ARP packets on {} for spoofing of {}", interface_name, gateway);
+
+    let mut buffer = [0u8; 128];
+    let mut spoofer: Option<String> = None;
+    loop {
+        poll_fn(|cx| registration.poll_readable(cx))
+            .await
+            .and_then(|_| registration.clear_readiness())
+            .map_err(|e| CaptivePortalError::IO(e, "Failed to wait for ARP packets"))?;
+        // Packets that arrive from now on mark the socket readable again
+        loop {
+            let data = buffer.as_mut_ptr() as *mut libc::c_void;
+            let len = unsafe { libc::recv(socket.as_raw_fd(), data, buffer.len(), 0) };
+            if len < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::WouldBlock {
+                    break;
+                }
+                return Err(CaptivePortalError::IO(e, "Failed to read ARP packets"));
+            }
+            let (hw, ip) = match sender(&buffer[..len as usize]) {
+                Some(sender) => sender,
+                None => continue,
+            };
+            if ip != gateway || hw == own_hw {
+                continue;
+            }
+            SPOOFED_PACKETS.fetch_add(1, Ordering::Relaxed);
+            if spoofer.as_ref() != Some(&hw) {
+                warn!("ARP spoofing: {} claims the gateway address {}", hw, gateway);
+                status.set_arp_spoof(Some(hw.clone()));
+                spoofer = Some(hw);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arp_sender() {
+        let mut packet = vec![0, 1, 8, 0, 6, 4, 0, 2];
+        packet.extend_from_slice(&[0xaa, 0xbb, 0xcc, 0, 1, 2]);
+        packet.extend_from_slice(&[192, 168, 42, 1]);
+        packet.extend_from_slice(&[0; 10]);
+        assert_eq!(
+            sender(&packet),
+            Some(("aa:bb:cc:00:01:02".to_owned(), Ipv4Addr::new(192, 168, 42, 1)))
+        );
+        assert_eq!(sender(&packet[..20]), None);
+        packet[3] = 0xdd;
+        assert_eq!(sender(&packet), None);
+    }
+}
@@ -0,0 +1,439 @@
+//! # Connection attempts
+//! Every connect request gets an attempt id. The http server submits requests, the state machine
+//! takes them over and runs them as cancellable attempts. Attempts can be cancelled by id
+//! via the http server and the D-Bus control interface.
+//!
+//! A second request while an attempt is pending or running (the user tapped "connect" twice) is handled
+//! according to the [`DuplicateConnectPolicy`]: It is either attached to the current attempt,
+//! or the current attempt is cancelled and replaced by the new request.
+//!
+//! A request whose connection did not pass the quality gate (see [`crate::quality`]) is kept until the user
+//! decides to continue with the weak connection anyway or submits another request.
+//!
+//! The last attempt of each portal client is kept in a history, keyed by the client hardware address
+//! (see [`crate::sessions::Sessions::client_id`]). When a connection fails and the portal opens again,
+//! the portal page selects the network of the client's last attempt and shows why it failed.
+//! With a state directory, the history is persisted in "attempt_history.json".
+
+use crate::config::DuplicateConnectPolicy;
+use crate::http_server::WifiConnectionRequest;
+use crate::network_interface::SSID;
+use crate::state_dir::StateFile;
+use crate::trace::Phase;
+use crate::CaptivePortalError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+
+/// File name of the persisted attempt history within the state directory
+pub const ATTEMPT_HISTORY_FILE: &str = "attempt_history.json";
+/// The history keeps the last attempt of at most this many clients
+const MAX_HISTORY: usize = 64;
+
+/// The last connection attempt of a portal client
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AttemptRecord {
+    pub attempt: u64,
+    pub ssid: SSID,
+    /// The access point of the attempt, if the client picked one from the list
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hw: Option<String>,
+    /// The failure reason, a message key of [`crate::i18n`]. None if the attempt is running or succeeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The stage in which the attempt failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<FailureStage>,
+    pub finished: bool,
+    /// Unix timestamp in seconds
+    pub time: u64,
+}
+
+/// The stage in which a connection attempt failed
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureStage {
+    /// The access point has not been found or did not accept the association
+    Association,
+    /// The password has been rejected or the handshake timed out
+    Authentication,
+    /// No ip address has been assigned in time
+    Dhcp,
+    /// Connected, but the internet is not reachable, see `--internet-connectivity`
+    NoInternet,
+}
+
+impl FailureStage {
+    /// Correlates the failure reason of an attempt with the connect phase that failed, see [`crate::trace`].
+    /// Outcomes that are no failures of the connection, like "cancelled" or "weak_connection", have no stage.
+    pub fn of(reason: &str, failed_phase: Option<Phase>) -> Option<Self> {
+        match reason {
+            "wrong_password" | "auth_timeout" => Some(FailureStage::Authentication),
+            "no_ip_address" | "ip_address_conflict" => Some(FailureStage::Dhcp),
+            "no_internet" => Some(FailureStage::NoInternet),
+            "network_not_found" | "invalid_settings" => Some(FailureStage::Association),
+            "connected" | "cancelled" | "replaced" | "weak_connection" => None,
+            _ => match failed_phase {
+                Some(Phase::Dhcp) => Some(FailureStage::Dhcp),
+                Some(Phase::ConnectivityCheck) => Some(FailureStage::NoInternet),
+                _ => Some(FailureStage::Association),
+            },
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FailureStage::Association => "association",
+            FailureStage::Authentication => "authentication",
+            FailureStage::Dhcp => "dhcp",
+            FailureStage::NoInternet => "no_internet",
+        }
+    }
+}
+
+/// The outcome of [`ConnectAttempts::submit`]
+pub enum Submitted {
+    /// A new attempt. The request must be handed over to the state machine.
+    New(u64, WifiConnectionRequest),
+    /// The request has been dropped, the current attempt with this id continues.
+    Attached(u64),
+    /// The current attempt is cancelled. The state machine continues with the new request.
+    Replaced(u64),
+}
+
+struct Current {
+    id: u64,
+    /// Set while the state machine runs the attempt
+    cancel: Option<oneshot::Sender<()>>,
+    /// Cancelled before the state machine started the attempt
+    cancelled: bool,
+}
+
+pub struct ConnectAttempts {
+    policy: DuplicateConnectPolicy,
+    last_id: u64,
+    current: Option<Current>,
+    /// The request that replaces the current attempt
+    replacement: Option<WifiConnectionRequest>,
+    /// The last request that failed the quality gate
+    weak: Option<WifiConnectionRequest>,
+    /// The last attempt by client id
+    history: HashMap<String, AttemptRecord>,
+    history_file: Option<StateFile>,
+}
+
+/// The thread safe wrapper, shared by the http server and the state machine.
+pub type ConnectAttemptsSync = Arc<Mutex<ConnectAttempts>>;
+
+impl ConnectAttempts {
+    pub fn new(policy: DuplicateConnectPolicy) -> Self {
+        ConnectAttempts {
+            policy,
+            last_id: 0,
+            current: None,
+            replacement: None,
+            weak: None,
+            history: HashMap::new(),
+            history_file: None,
+        }
+    }
+
+    pub fn new_sync(policy: DuplicateConnectPolicy) -> ConnectAttemptsSync {
+        Arc::new(Mutex::new(ConnectAttempts::new(policy)))
+    }
+
+    /// Restores the attempt history from the given file, if it exists. Changes are written at most once per
+    /// flush interval and by [`ConnectAttempts::store_history`].
+    /// Attempt ids start at 1 again, so unfinished attempts of the last run are dropped.
+    pub fn set_history_file(&mut self, path: PathBuf, flush_interval: Duration) -> Result<(), CaptivePortalError> {
+        let history: HashMap<String, AttemptRecord> = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(_) => HashMap::new(),
+        };
+        self.history = history.into_iter().filter(|(_, r)| r.finished).collect();
+        self.history_file = Some(StateFile::new(path, flush_interval));
+        Ok(())
+    }
+
+    /// The id of the pending or running attempt
+    pub fn current(&self) -> Option<u64> {
+        self.current.as_ref().map(|c| c.id)
+    }
+
+    /// Registers a connect request. See [`Submitted`] for what the caller has to do with the request.
+    pub fn submit(&mut self, request: WifiConnectionRequest) -> Submitted {
+        self.weak = None;
+        match (self.current.as_mut(), self.policy) {
+            (None, _) => {
+                self.last_id += 1;
+                self.current = Some(Current {
+                    id: self.last_id,
+                    cancel: None,
+                    cancelled: false,
+                });
+                Submitted::New(self.last_id, request)
+            },
+            (Some(current), DuplicateConnectPolicy::Attach) => Submitted::Attached(current.id),
+            (Some(current), DuplicateConnectPolicy::Replace) => {
+                self.last_id += 1;
+                current.id = self.last_id;
+                current.cancelled = false;
+                if let Some(cancel) = current.cancel.take() {
+                    let _ = cancel.send(());
+                }
+                self.replacement = Some(request);
+                Submitted::Replaced(self.last_id)
+            },
+        }
+    }
+
+    /// Cancels the attempt with the given id. Returns false if that attempt is not pending or running.
+    pub fn cancel(&mut self, id: u64) -> bool {
+        match self.current.as_mut() {
+            Some(current) if current.id == id => {
+                match current.cancel.take() {
+                    Some(cancel) => {
+                        let _ = cancel.send(());
+                    },
+                    None => current.cancelled = true,
+                }
+                self.replacement = None;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Called by the state machine when it starts to run the current attempt.
+    /// Returns the attempt id and a receiver that resolves when the attempt is cancelled.
+    /// Returns None if the attempt has been cancelled before.
+    pub fn begin(&mut self) -> Option<(u64, oneshot::Receiver<()>)> {
+        let (cancel, cancelled) = oneshot::channel();
+        let id = match self.current.as_ref() {
+            Some(current) if current.cancelled => return None,
+            Some(current) => current.id,
+            None => {
+                self.last_id += 1;
+                self.last_id
+            },
+        };
+        self.current = Some(Current {
+            id,
+            cancel: Some(cancel),
+            cancelled: false,
+        });
+        Some((id, cancelled))
+    }
+
+    /// Takes the request that replaced the current attempt, if any
+    pub fn take_replacement(&mut self) -> Option<WifiConnectionRequest> {
+        self.replacement.take()
+    }
+
+    /// Called by the state machine when the connection of the current attempt failed the quality gate.
+    pub fn set_weak(&mut self, request: WifiConnectionRequest) {
+        self.weak = Some(request);
+    }
+
+    /// Takes the last request that failed the quality gate, if no other request has been submitted since
+    pub fn take_weak(&mut self) -> Option<WifiConnectionRequest> {
+        self.weak.take()
+    }
+
+    /// Called by the state machine when the current attempt succeeded or failed.
+    pub fn finish(&mut self) {
+        self.current = None;
+        self.replacement = None;
+    }
+
+    /// Remembers the attempt as the last one of the given client, replacing its previous attempt
+    pub fn record(&mut self, client: String, attempt: u64, ssid: SSID, hw: Option<String>) {
+        if !self.history.contains_key(&client) && self.history.len() >= MAX_HISTORY {
+            // Forget the client with the oldest attempt
+            let oldest = self.history.iter().min_by_key(|(_, r)| r.time).map(|(c, _)| c.clone());
+            if let Some(oldest) = oldest {
+                self.history.remove(&oldest);
+            }
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.history.insert(
+            client,
+            AttemptRecord {
+                attempt,
+                ssid,
+                hw,
+                error: None,
+                stage: None,
+                finished: false,
+                time,
+            },
+        );
+        self.store_history(false);
+    }
+
+    /// Called by the state machine with the outcome of an attempt: The failure reason or None if connected
+    pub fn record_outcome(&mut self, attempt: u64, error: Option<&str>, stage: Option<FailureStage>) {
+        let record = self.history.values_mut().find(|r| r.attempt == attempt && !r.finished);
+        if let Some(record) = record {
+            record.error = error.map(|e| e.to_owned());
+            record.stage = stage;
+            record.finished = true;
+            self.store_history(false);
+        }
+    }
+
+    /// The last attempt of the given client
+    pub fn last_of(&self, client: &str) -> Option<&AttemptRecord> {
+        self.history.get(client)
+    }
+
+    /// Stores the history, see [`StateFile::store`].
+    pub fn store_history(&mut self, force: bool) {
+        if let Some(file) = self.history_file.as_mut() {
+            file.store(&self.history, force);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(ssid: &str) -> WifiConnectionRequest {
+        WifiConnectionRequest {
+            mode: "open".to_owned(),
+            ssid: ssid.into(),
+            identity: None,
+            passphrase: None,
+            anonymous_identity: None,
+            eap: None,
+            hw: None,
+            ipv4_address: None,
+            ipv4_prefix: None,
+            ipv4_gateway: None,
+            ipv4_dns: Vec::new(),
+            ipv6_mode: None,
+            ipv6_address: None,
+            ipv6_prefix: None,
+            ipv6_gateway: None,
+            ipv6_dns: Vec::new(),
+            hidden: false,
+            accept_weak: false,
+            dpp_uri: None,
+            priority: None,
+            paired: false,
+        }
+    }
+
+    fn id(submitted: Submitted) -> u64 {
+        match submitted {
+            Submitted::New(id, _) | Submitted::Attached(id) | Submitted::Replaced(id) => id,
+        }
+    }
+
+    #[test]
+    fn attach() {
+        let mut attempts = ConnectAttempts::new(DuplicateConnectPolicy::Attach);
+        assert_eq!(id(attempts.submit(request("a"))), 1);
+        assert_eq!(id(attempts.submit(request("b"))), 1);
+        let (attempt, _cancelled) = attempts.begin().unwrap();
+        assert_eq!(attempt, 1);
+        assert!(attempts.take_replacement().is_none());
+        attempts.finish();
+        assert_eq!(id(attempts.submit(request("b"))), 2);
+    }
+
+    #[test]
+    fn replace() {
+        let mut attempts = ConnectAttempts::new(DuplicateConnectPolicy::Replace);
+        assert_eq!(id(attempts.submit(request("a"))), 1);
+        let (_, mut cancelled) = attempts.begin().unwrap();
+        assert_eq!(id(attempts.submit(request("b"))), 2);
+        assert!(cancelled.try_recv().is_ok());
+        assert_eq!(attempts.current(), Some(2));
+        assert_eq!(attempts.take_replacement().map(|r| r.ssid), Some("b".into()));
+        let (attempt, _) = attempts.begin().unwrap();
+        assert_eq!(attempt, 2);
+    }
+
+    #[test]
+    fn cancel() {
+        let mut attempts = ConnectAttempts::new(DuplicateConnectPolicy::Attach);
+        assert_eq!(id(attempts.submit(request("a"))), 1);
+        assert!(!attempts.cancel(2));
+        assert!(attempts.cancel(1));
+        assert!(attempts.begin().is_none());
+        attempts.finish();
+
+        assert_eq!(id(attempts.submit(request("b"))), 2);
+        let (_, mut cancelled) = attempts.begin().unwrap();
+        assert!(attempts.cancel(2));
+        assert!(cancelled.try_recv().is_ok());
+    }
+
+    #[test]
+    fn weak() {
+        let mut attempts = ConnectAttempts::new(DuplicateConnectPolicy::Attach);
+        attempts.set_weak(request("a"));
+        assert_eq!(attempts.take_weak().map(|r| r.ssid), Some("a".into()));
+        assert!(attempts.take_weak().is_none());
+        attempts.set_weak(request("a"));
+        attempts.submit(request("b"));
+        assert!(attempts.take_weak().is_none());
+    }
+
+    #[test]
+    fn history() {
+        let mut attempts = ConnectAttempts::new(DuplicateConnectPolicy::Attach);
+        attempts.record("AA:BB:CC:00:11:22".to_owned(), 1, "a".into(), None);
+        attempts.record("192.168.42.11".to_owned(), 2, "b".into(), Some("00:11:22:33:44:55".to_owned()));
+        attempts.record_outcome(1, Some("wrong_password"), Some(FailureStage::Authentication));
+        attempts.record_outcome(2, None, None);
+
+        let record = attempts.last_of("AA:BB:CC:00:11:22").unwrap();
+        assert_eq!(record.ssid, "a");
+        assert_eq!(record.error.as_deref(), Some("wrong_password"));
+        assert_eq!(record.stage, Some(FailureStage::Authentication));
+        assert!(record.finished);
+        assert_eq!(attempts.last_of("192.168.42.11").unwrap().error, None);
+
+        // A finished attempt keeps its outcome, a new attempt replaces it
+        attempts.record_outcome(1, Some("cancelled"), None);
+        assert_eq!(attempts.last_of("AA:BB:CC:00:11:22").unwrap().error.as_deref(), Some("wrong_password"));
+        attempts.record("AA:BB:CC:00:11:22".to_owned(), 3, "c".into(), None);
+        assert!(!attempts.last_of("AA:BB:CC:00:11:22").unwrap().finished);
+        assert!(attempts.last_of("192.168.42.12").is_none());
+    }
+
+    #[test]
+    fn failure_stage() {
+        assert_eq!(FailureStage::of("wrong_password", Some(Phase::Dhcp)), Some(FailureStage::Authentication));
+        assert_eq!(FailureStage::of("connection_failed", Some(Phase::Dhcp)), Some(FailureStage::Dhcp));
+        assert_eq!(FailureStage::of("connection_failed", Some(Phase::Associate)), Some(FailureStage::Association));
+        assert_eq!(FailureStage::of("no_internet", None), Some(FailureStage::NoInternet));
+        assert_eq!(FailureStage::of("cancelled", Some(Phase::Associate)), None);
+    }
+
+    #[test]
+    fn persisted_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(ATTEMPT_HISTORY_FILE);
+        let mut attempts = ConnectAttempts::new(DuplicateConnectPolicy::Attach);
+        attempts.set_history_file(path.clone(), Duration::from_secs(0)).unwrap();
+        attempts.record("a".to_owned(), 1, "a".into(), None);
+        attempts.record_outcome(1, Some("connection_failed"), Some(FailureStage::Association));
+        attempts.record("b".to_owned(), 2, "b".into(), None);
+
+        let mut restored = ConnectAttempts::new(DuplicateConnectPolicy::Attach);
+        restored.set_history_file(path, Duration::from_secs(0)).unwrap();
+        assert_eq!(restored.last_of("a"), attempts.last_of("a"));
+        // Unfinished attempts of the last run are dropped
+        assert!(restored.last_of("b").is_none());
+    }
+}
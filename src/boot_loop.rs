@@ -0,0 +1,141 @@
+//! # Boot loop detection
+//! Every start is recorded in the run state file of the state directory and a clean shutdown is recorded
+//! as well. A start without a clean shutdown before counts as crash. Exiting on SIGINT or SIGTERM, like
+//! `systemctl stop`, a restart or a reboot, is a clean shutdown. If the service crashed too often
+//! within the configured window, it starts in safe mode: Only the hotspot and portal are offered,
+//! network connections are not touched automatically and the portal shows a diagnostics banner.
+//!
+//! Requires a state directory. Without one, crashes cannot be counted.
+
+use crate::state_dir::write_atomic;
+use crate::CaptivePortalError;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// File name of the run state within the state directory
+pub const RUN_STATE_FILE: &str = "run_state.json";
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct RunState {
+    /// Set on start and cleared on a clean shutdown
+    running: bool,
+    /// Unix timestamps in seconds of the starts after a crash
+    crashes: Vec<u64>,
+}
+
+impl RunState {
+    /// Records a start and returns the number of crashes within the window
+    fn start(&mut self, now: u64, window: Duration) -> usize {
+        if self.running {
+            self.crashes.push(now);
+        }
+        self.running = true;
+        let since = now.saturating_sub(window.as_secs());
+        self.crashes.retain(|t| *t >= since);
+        self.crashes.len()
+    }
+}
+
+/// Why the service runs in safe mode. Shown on the portal.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SafeMode {
+    /// Crashes within the window
+    pub crashes: usize,
+    pub window_minutes: u64,
+}
+
+/// Records the start on creation. [`BootLoopGuard::clean_shutdown`] must be called on a regular exit.
+pub struct BootLoopGuard {
+    path: PathBuf,
+    state: RunState,
+}
+
+impl BootLoopGuard {
+    /// Records the start. Returns the safe mode reason if the service crashed `max_crashes` times
+    /// within the window.
+    pub fn start(
+        state_dir: &Path,
+        max_crashes: usize,
+        window: Duration,
+    ) -> Result<(BootLoopGuard, Option<SafeMode>), CaptivePortalError> {
+        let path = state_dir.join(RUN_STATE_FILE);
+        let mut state: RunState = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                warn!("Ignoring the invalid run state file: {}", e);
+                RunState::default()
+            }),
+            Err(_) => RunState::default(),
+        };
+        let crashes = state.start(now(), window);
+        write_atomic(&path, &serde_json::to_vec(&state)?)?;
+        let safe_mode = match crashes >= max_crashes {
+            true => Some(SafeMode {
+                crashes,
+                window_minutes: window.as_secs() / 60,
+            }),
+            false => None,
+        };
+        Ok((BootLoopGuard { path, state }, safe_mode))
+    }
+
+    /// Records a clean shutdown
+    pub fn clean_shutdown(mut self) -> Result<(), CaptivePortalError> {
+        self.state.running = false;
+        write_atomic(&self.path, &serde_json::to_vec(&self.state)?)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crashes() {
+        let window = Duration::from_secs(600);
+        let mut state = RunState::default();
+        assert_eq!(state.start(1000, window), 0);
+        // Crashed
+        assert_eq!(state.start(1100, window), 1);
+        assert_eq!(state.start(1200, window), 2);
+        // Clean shutdown
+        state.running = false;
+        assert_eq!(state.start(1300, window), 2);
+        // Crashed, the first crash is outside of the window
+        assert_eq!(state.start(1750, window), 2);
+    }
+
+    #[test]
+    fn guard() {
+        let dir = tempfile::tempdir().unwrap();
+        let window = Duration::from_secs(600);
+        let (_, safe_mode) = BootLoopGuard::start(dir.path(), 2, window).unwrap();
+        assert!(safe_mode.is_none());
+        let (guard, safe_mode) = BootLoopGuard::start(dir.path(), 2, window).unwrap();
+        assert!(safe_mode.is_none());
+        guard.clean_shutdown().unwrap();
+        let (_, safe_mode) = BootLoopGuard::start(dir.path(), 2, window).unwrap();
+        assert!(safe_mode.is_none());
+        let (_, safe_mode) = BootLoopGuard::start(dir.path(), 2, window).unwrap();
+        assert_eq!(safe_mode.map(|s| s.crashes), Some(2));
+    }
+
+    /// Regular stops and restarts, like those of systemd, never lead to the safe mode
+    #[test]
+    fn clean_stops() {
+        let dir = tempfile::tempdir().unwrap();
+        let window = Duration::from_secs(600);
+        for _ in 0..5 {
+            let (guard, safe_mode) = BootLoopGuard::start(dir.path(), 2, window).unwrap();
+            assert!(safe_mode.is_none());
+            guard.clean_shutdown().unwrap();
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! # Channel utilization report
+//! Estimates the congestion of the wifi channels from the scanned access points. Every access point
+//! on a channel adds its signal strength to the load of that channel. On 2.4 Ghz, channels that are
+//! less than 25 Mhz apart overlap and share their load.
+//!
+//! The portal warns about a congested channel when the user selects a network, so that installers
+//! can choose the less congested band of a dual band access point.
+//! The hotspot is started on the least congested channel of its band, see [`least_congested`].
+
+use crate::network_interface::{HotspotBand, WifiConnection};
+use serde::Serialize;
+
+/// A channel is congested from this load on. For example three access points with full signal strength.
+pub const CONGESTED_LOAD: u32 = 300;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ChannelUtilization {
+    pub channel: u32,
+    /// The center frequency in Mhz
+    pub frequency: u32,
+    /// The number of access points on this channel and overlapping channels
+    pub access_points: usize,
+    /// The summed up signal strengths of these access points in percent
+    pub load: u32,
+    pub congested: bool,
+}
+
+/// The wifi channel number of the given frequency in Mhz
+pub fn frequency_to_channel(frequency: u32) -> u32 {
+    match frequency {
+        2484 => 14,
+        2412..=2472 => (frequency - 2407) / 5,
+        _ if frequency > 5000 => (frequency - 5000) / 5,
+        _ => 0,
+    }
+}
+
+/// The center frequency in Mhz of the given 2.4 or 5 Ghz wifi channel
+pub fn channel_to_frequency(channel: u32) -> u32 {
+    match channel {
+        14 => 2484,
+        1..=13 => 2407 + 5 * channel,
+        _ => 5000 + 5 * channel,
+    }
+}
+
+/// True if the channels of the given frequencies interfere with each other
+fn overlaps(a: u32, b: u32) -> bool {
+    a == b || (a < 3000 && b < 3000 && (a as i64 - b as i64).abs() < 25)
+}
+
+/// The hotspot channels: The non-overlapping 2.4 Ghz channels and the 5 Ghz channels without radar detection
+pub fn candidates(band: HotspotBand) -> &'static [u32] {
+    match band {
+        HotspotBand::Bg => &[1, 6, 11],
+        HotspotBand::A => &[36, 40, 44, 48],
+    }
+}
+
+/// The channel of the band with the lowest load of the given access points. The first channel on a tie.
+pub fn least_congested(access_points: &[WifiConnection], band: HotspotBand) -> u32 {
+    let channels = candidates(band);
+    least_congested_of(access_points, channels).unwrap_or(channels[0])
+}
+
+/// The channel with the lowest load of the given access points. None if no channel is given.
+pub fn least_congested_of(access_points: &[WifiConnection], channels: &[u32]) -> Option<u32> {
+    let load = |channel: u32| -> u32 {
+        let frequency = channel_to_frequency(channel);
+        access_points
+            .iter()
+            .filter(|ap| !ap.is_own && overlaps(ap.frequency, frequency))
+            .map(|ap| ap.strength as u32)
+            .sum()
+    };
+    channels.iter().copied().min_by_key(|c| load(*c))
+}
+
+/// Returns the utilization of every channel with at least one access point, ordered by frequency
+pub fn report(access_points: &[WifiConnection]) -> Vec<ChannelUtilization> {
+    let mut frequencies: Vec<u32> = access_points.iter().map(|ap| ap.frequency).filter(|f| *f > 0).collect();
+    frequencies.sort();
+    frequencies.dedup();
+    frequencies
+        .into_iter()
+        .map(|frequency| {
+            let neighbors = access_points.iter().filter(|ap| overlaps(ap.frequency, frequency));
+            let (count, load) = neighbors.fold((0, 0), |(count, load), ap| (count + 1, load + ap.strength as u32));
+            ChannelUtilization {
+                channel: frequency_to_channel(frequency),
+                frequency,
+                access_points: count,
+                load,
+                congested: load >= CONGESTED_LOAD,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ap(frequency: u32, strength: u8) -> WifiConnection {
+        WifiConnection {
+            ssid: format!("{}", frequency).into(),
+            hw: String::new(),
+            security: "none",
+            strength,
+            frequency,
+            is_own: false,
+            vendor: None,
+        }
+    }
+
+    #[test]
+    fn utilization() {
+        let report = report(&[ap(2412, 100), ap(2417, 100), ap(2452, 100), ap(2422, 100), ap(5180, 40)]);
+        assert_eq!(report.len(), 5);
+        assert_eq!(report[0].channel, 1);
+        assert_eq!(report[0].access_points, 3);
+        assert!(report[0].congested);
+        assert_eq!(report[3].channel, 9);
+        assert_eq!(report[3].load, 100);
+        assert!(!report[3].congested);
+        assert_eq!(report[4].channel, 36);
+        assert_eq!(report[4].load, 40);
+    }
+
+    #[test]
+    fn least_congested_channel() {
+        assert_eq!(least_congested(&[], HotspotBand::Bg), 1);
+        assert_eq!(least_congested(&[ap(2412, 80), ap(2437, 30), ap(2462, 50)], HotspotBand::Bg), 6);
+        assert_eq!(least_congested(&[ap(2417, 100), ap(2457, 10)], HotspotBand::Bg), 11);
+        assert_eq!(least_congested(&[ap(5180, 60)], HotspotBand::A), 40);
+    }
+
+    #[test]
+    fn channel_frequency() {
+        for channel in &[1, 6, 13, 14, 36, 149] {
+            assert_eq!(frequency_to_channel(channel_to_frequency(*channel)), *channel);
+        }
+    }
+}
@@ -0,0 +1,435 @@
+//! # Provisioning commands
+//! Commands of the control surfaces (the portal, the D-Bus control interface and, for embedding
+//! applications, MQTT bridges or a local CLI) are not executed by the surfaces themselves.
+//! They are queued and the state machine executes them one after the other, so that they do not race
+//! each other against the network backend. Commands with a higher priority are executed first,
+//! commands of the same priority in submission order.
+//!
+//! Every command carries its origin. Submissions and executions are logged with the log target "audit".
+//! Cancelling a connection attempt is not queued, it must preempt the running attempt. It is audited as well.
+//! The last audited actions are kept in the [`AuditLog`], which is persisted with a state directory.
+//!
+//! Submissions are checked against the authorization [`Policy`] of the surfaces. Denied commands are audited
+//! and never reach the queue.
+
+use crate::dispatcher::DispatcherEvent;
+use crate::http_server::WifiConnectionRequest;
+use crate::mac_filter::MacFilter;
+use crate::network_interface::SSID;
+use crate::policy::{Action, Policy, Subject};
+use crate::state_dir::StateFile;
+use crate::status::UpdateNotice;
+use crate::CaptivePortalError;
+use futures_util::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+/// File name of the persisted audit log within the state directory
+pub const AUDIT_LOG_FILE: &str = "audit.json";
+
+/// The number of kept audit log entries
+const AUDIT_LOG_CAPACITY: usize = 500;
+
+/// The control surface a command has been submitted by
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Surface {
+    /// The REST and GraphQL routes of the http server
+    Portal,
+    Dbus,
+    Mqtt,
+    Cli,
+}
+
+impl Surface {
+    pub fn name(self) -> &'static str {
+        match self {
+            Surface::Portal => "portal",
+            Surface::Dbus => "dbus",
+            Surface::Mqtt => "mqtt",
+            Surface::Cli => "cli",
+        }
+    }
+}
+
+/// Who submitted a command
+#[derive(Clone, Debug)]
+pub struct Origin {
+    pub surface: Surface,
+    /// For example the client ip address of the portal or the D-Bus sender
+    pub caller: Option<String>,
+    /// The unix user id of a D-Bus caller
+    pub uid: Option<u32>,
+    /// The process id of a D-Bus caller
+    pub pid: Option<u32>,
+    /// A portal request with the admin token
+    pub admin: bool,
+}
+
+impl Origin {
+    pub fn new(surface: Surface, caller: Option<String>) -> Self {
+        Origin {
+            surface,
+            caller,
+            uid: None,
+            pid: None,
+            admin: false,
+        }
+    }
+
+    /// The policy subjects of this origin, from specific to general
+    pub fn subjects(&self) -> Vec<Subject> {
+        match self.surface {
+            Surface::Portal if self.admin => vec![Subject::PortalAdmin],
+            Surface::Portal => vec![Subject::Portal],
+            Surface::Dbus => match self.uid {
+                Some(uid) => vec![Subject::Dbus(Some(uid)), Subject::Dbus(None)],
+                None => vec![Subject::Dbus(None)],
+            },
+            Surface::Mqtt => vec![Subject::Mqtt],
+            Surface::Cli => vec![Subject::Cli],
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.surface.name())?;
+        if self.admin {
+            write!(f, " admin")?;
+        }
+        let details: Vec<String> = self
+            .caller
+            .iter()
+            .cloned()
+            .chain(self.uid.map(|uid| format!("uid {}", uid)))
+            .chain(self.pid.map(|pid| format!("pid {}", pid)))
+            .collect();
+        match details.is_empty() {
+            true => Ok(()),
+            false => write!(f, " ({})", details.join(", ")),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Normal,
+    High,
+}
+
+pub enum Command {
+    /// Connect to a network. The attempt id is assigned by [`crate::attempts::ConnectAttempts::submit`].
+    Connect {
+        attempt: u64,
+        request: WifiConnectionRequest,
+    },
+    /// Remove the stored connection for the given SSID
+    Forget(SSID),
+    /// Close the portal and start the hotspot again
+    RestartHotspot,
+    /// Show an update banner on the portal, or remove it with None. Submitted by the embedding firmware.
+    SetUpdateNotice(Option<UpdateNotice>),
+    /// A connection went up or down, reported by the NetworkManager dispatcher
+    NetworkEvent(DispatcherEvent),
+    /// Replace the MAC filter of the hotspot
+    SetMacFilter(MacFilter),
+    /// Switch wifi off and on again. The hotspot is restarted afterwards, if it was up.
+    RestartWifi,
+    /// Reboot the device
+    Reboot,
+}
+
+impl Command {
+    pub fn priority(&self) -> Priority {
+        match self {
+            Command::Connect { .. } => Priority::High,
+            Command::Forget(_)
+            | Command::RestartHotspot
+            | Command::SetUpdateNotice(_)
+            | Command::NetworkEvent(_)
+            | Command::SetMacFilter(_)
+            | Command::RestartWifi
+            | Command::Reboot => Priority::Normal,
+        }
+    }
+
+    /// The action that must be allowed by the policy
+    pub fn action(&self) -> Action {
+        match self {
+            Command::Connect { .. } => Action::Connect,
+            Command::Forget(_) => Action::Forget,
+            Command::RestartHotspot => Action::RestartHotspot,
+            Command::SetUpdateNotice(_) => Action::UpdateNotice,
+            Command::NetworkEvent(_) => Action::NetworkEvent,
+            Command::SetMacFilter(_) => Action::MacFilter,
+            Command::RestartWifi => Action::RestartWifi,
+            Command::Reboot => Action::Reboot,
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Connect { attempt, request } => write!(f, "connect to {} (attempt {})", request.ssid, attempt),
+            Command::Forget(ssid) => write!(f, "forget {}", ssid),
+            Command::RestartHotspot => write!(f, "restart hotspot"),
+            Command::SetUpdateNotice(Some(notice)) => write!(f, "set update notice {:?}", notice.state),
+            Command::SetUpdateNotice(None) => write!(f, "clear update notice"),
+            Command::NetworkEvent(event) => write!(f, "network event {}", event),
+            Command::SetMacFilter(filter) => write!(
+                f,
+                "set mac filter ({} allowed, {} denied)",
+                filter.allow.len(),
+                filter.deny.len()
+            ),
+            Command::RestartWifi => write!(f, "restart wifi"),
+            Command::Reboot => write!(f, "reboot"),
+        }
+    }
+}
+
+pub struct QueuedCommand {
+    pub id: u64,
+    pub command: Command,
+    pub origin: Origin,
+}
+
+impl PartialEq for QueuedCommand {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for QueuedCommand {}
+
+impl PartialOrd for QueuedCommand {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Higher priorities first, then lower ids first
+impl Ord for QueuedCommand {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.command
+            .priority()
+            .cmp(&other.command.priority())
+            .then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+/// An audited action of a control surface
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    /// Seconds since the unix epoch
+    pub time: u64,
+    /// The origin, like "dbus (:1.42, uid 0, pid 1234)"
+    pub origin: String,
+    pub action: String,
+}
+
+/// The last audited actions, oldest first
+#[derive(Default)]
+pub struct AuditLog {
+    entries: VecDeque<AuditEntry>,
+    file: Option<StateFile>,
+}
+
+impl AuditLog {
+    /// Restores the entries from the given file, if it exists. Changes are written at most once per
+    /// flush interval and by [`AuditLog::store`].
+    pub fn with_file(path: PathBuf, flush_interval: Duration) -> Result<Self, CaptivePortalError> {
+        let entries: VecDeque<AuditEntry> = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(_) => VecDeque::new(),
+        };
+        Ok(AuditLog {
+            entries,
+            file: Some(StateFile::new(path, flush_interval)),
+        })
+    }
+
+    pub fn entries(&self) -> &VecDeque<AuditEntry> {
+        &self.entries
+    }
+
+    fn record(&mut self, origin: &Origin, action: String) {
+        self.entries.push_back(AuditEntry {
+            time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            origin: origin.to_string(),
+            action,
+        });
+        while self.entries.len() > AUDIT_LOG_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.store(false);
+    }
+
+    /// Stores the entries, see [`StateFile::store`].
+    pub fn store(&mut self, force: bool) {
+        if let Some(file) = self.file.as_mut() {
+            file.store(&self.entries, force);
+        }
+    }
+}
+
+pub type AuditLogSync = Arc<Mutex<AuditLog>>;
+
+/// The submitting side of the queue. Cloned into every control surface.
+#[derive(Clone)]
+pub struct CommandSender {
+    tx: mpsc::UnboundedSender<QueuedCommand>,
+    last_id: Arc<AtomicU64>,
+    policy: Arc<Policy>,
+    audit_log: AuditLogSync,
+}
+
+impl CommandSender {
+    /// Logs an action of a control surface with the log target "audit" and records it in the audit log
+    pub fn audit(&self, origin: &Origin, action: fmt::Arguments) {
+        info!(target: "audit", "{} by {}", action, origin);
+        self.audit_log.lock().expect("audit log mutex lock").record(origin, action.to_string());
+    }
+
+    /// Checks the policy. Denials are audited.
+    pub fn authorize(&self, origin: &Origin, action: Action) -> Result<(), CaptivePortalError> {
+        if self.policy.allows(&origin.subjects(), action) {
+            return Ok(());
+        }
+        self.audit(origin, format_args!("Denied {}", action.name()));
+        Err(CaptivePortalError::NotAuthorized(format!(
+            "{} is not allowed to {}",
+            origin.surface.name(),
+            action.name()
+        )))
+    }
+
+    /// Queues the command and returns its id
+    pub fn submit(&self, command: Command, origin: Origin) -> Result<u64, CaptivePortalError> {
+        self.authorize(&origin, command.action())?;
+        let id = self.last_id.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+        self.audit(&origin, format_args!("Command {} queued: {}", id, command));
+        self.tx
+            .send(QueuedCommand { id, command, origin })
+            .map_err(|_| CaptivePortalError::Generic("The command queue has been closed".to_owned()))?;
+        Ok(id)
+    }
+}
+
+/// The executing side of the queue. Owned by the state machine loop.
+pub struct CommandQueue {
+    rx: mpsc::UnboundedReceiver<QueuedCommand>,
+    sender: CommandSender,
+    pending: BinaryHeap<QueuedCommand>,
+}
+
+impl CommandQueue {
+    pub fn new(policy: Policy) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        CommandQueue {
+            rx,
+            sender: CommandSender {
+                tx,
+                last_id: Arc::new(AtomicU64::new(0)),
+                policy: Arc::new(policy),
+                audit_log: AuditLogSync::default(),
+            },
+            pending: BinaryHeap::new(),
+        }
+    }
+
+    pub fn sender(&self) -> CommandSender {
+        self.sender.clone()
+    }
+
+    /// The audit log of all senders of this queue
+    pub fn audit_log(&self) -> AuditLogSync {
+        self.sender.audit_log.clone()
+    }
+
+    /// Returns the queued command with the highest priority. Waits for a command if none is queued.
+    pub async fn next(&mut self) -> QueuedCommand {
+        while let Some(Some(queued)) = self.rx.recv().now_or_never() {
+            self.pending.push(queued);
+        }
+        if let Some(queued) = self.pending.pop() {
+            self.sender.audit(&queued.origin, format_args!("Command {} executed: {}", queued.id, queued.command));
+            return queued;
+        }
+        // The queue owns a sender, the channel is never closed
+        let queued = self.rx.recv().await.expect("command queue sender");
+        self.sender.audit(&queued.origin, format_args!("Command {} executed: {}", queued.id, queued.command));
+        queued
+    }
+}
+
+impl Default for CommandQueue {
+    fn default() -> Self {
+        CommandQueue::new(Policy::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn priorities() {
+        let mut queue = CommandQueue::default();
+        let sender = queue.sender();
+        assert!(sender.submit(Command::RestartHotspot, Origin::new(Surface::Mqtt, None)).is_err());
+        let origin = Origin::new(Surface::Cli, None);
+        sender.submit(Command::Forget("a".into()), origin.clone()).unwrap();
+        sender.submit(Command::Forget("b".into()), origin.clone()).unwrap();
+        let request = WifiConnectionRequest {
+            mode: "open".to_owned(),
+            ssid: "c".into(),
+            identity: None,
+            passphrase: None,
+            anonymous_identity: None,
+            eap: None,
+            hw: None,
+            ipv4_address: None,
+            ipv4_prefix: None,
+            ipv4_gateway: None,
+            ipv4_dns: Vec::new(),
+            ipv6_mode: None,
+            ipv6_address: None,
+            ipv6_prefix: None,
+            ipv6_gateway: None,
+            ipv6_dns: Vec::new(),
+            hidden: false,
+            accept_weak: false,
+            dpp_uri: None,
+            priority: None,
+            paired: false,
+        };
+        sender.submit(Command::Connect { attempt: 1, request }, origin).unwrap();
+
+        let ids: Vec<u64> = vec![queue.next().await.id, queue.next().await.id, queue.next().await.id];
+        assert_eq!(ids, vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn persisted_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(AUDIT_LOG_FILE);
+        let queue = CommandQueue::default();
+        *queue.audit_log().lock().unwrap() = AuditLog::with_file(path.clone(), Duration::from_secs(0)).unwrap();
+        let origin = Origin::new(Surface::Portal, Some("192.168.42.10".to_owned()));
+        assert!(queue.sender().submit(Command::Reboot, origin).is_err());
+
+        let restored = AuditLog::with_file(path, Duration::from_secs(0)).unwrap();
+        let entry = restored.entries().back().unwrap();
+        assert_eq!(entry.origin, "portal (192.168.42.10)");
+        assert_eq!(entry.action, "Denied reboot");
+    }
+}
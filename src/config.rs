@@ -1,15 +1,52 @@
 //! # The command line configuration is defined in this module.
 
+use crate::credential_policy::CredentialPolicy;
+use crate::dpp::DppUri;
+use crate::log_filter::LogFilter;
+use crate::mac_filter::MacFilterSync;
+use crate::network_interface::{HotspotBand, HotspotRadio, HotspotSecurity, Pmf};
+use crate::policy::Rule;
+use crate::quality::QualityGate;
+use crate::setup_window::{SetupWindow, TimeWindow};
+use crate::stats::Budget;
+use crate::watchdog::Watchdog;
 use std::net::Ipv4Addr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use structopt::StructOpt;
 
+/// What happens if a connect request arrives while another connection attempt is pending or running
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DuplicateConnectPolicy {
+    /// The request is answered with the id of the current attempt
+    Attach,
+    /// The current attempt is cancelled and the new request is used instead
+    Replace,
+}
+
+impl FromStr for DuplicateConnectPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "attach" => Ok(DuplicateConnectPolicy::Attach),
+            "replace" => Ok(DuplicateConnectPolicy::Replace),
+            _ => Err(format!("Expected attach or replace, got {}", value)),
+        }
+    }
+}
+
 #[derive(StructOpt, Debug, Clone)] //
 pub struct Config {
     /// Wireless network interface to be used by WiFi Connect
     #[structopt(short, long = "interface", env = "PORTAL_INTERFACE")]
     pub interface: Option<String>,
 
+    /// A second wifi adapter, like a USB dongle, dedicated to connection attempts. The hotspot and portal stay
+    /// online on the other adapter. Only supported by the NetworkManager backend.
+    #[structopt(long = "station-interface", env = "STATION_INTERFACE")]
+    pub station_interface: Option<String>,
+
     /// ssid of the captive portal WiFi network
     #[structopt(short, long = "portal-ssid", default_value = "WiFi Connect", env = "PORTAL_SSID")]
     pub ssid: String,
@@ -38,7 +75,7 @@ pub struct Config {
     #[structopt(long = "portal-identity", env = "PORTAL_IDENTITY")]
     pub identity: Option<String>,
 
-    /// Gateway of the captive portal WiFi network
+    /// Gateway of the captive portal WiFi network. The hotspot serves the /24 subnet of this address.
     #[structopt(
         short,
         long = "portal-gateway",
@@ -47,6 +84,16 @@ pub struct Config {
     )]
     pub gateway: Ipv4Addr,
 
+    /// Always use the portal gateway, even if its subnet clashes with the subnet of an upstream connection.
+    /// Otherwise another private subnet is picked on a clash.
+    #[structopt(long = "fixed-portal-gateway", env = "FIXED_PORTAL_GATEWAY")]
+    pub fixed_gateway: bool,
+
+    /// A friendly host name of the portal, like "setup.device". The dns server answers it with the gateway address
+    /// and captive portal redirects point to it, so that users can type it in after dismissing the captive sheet.
+    #[structopt(long = "portal-hostname", env = "PORTAL_HOSTNAME")]
+    pub portal_hostname: Option<String>,
+
     /// Listening port of the captive portal web server
     #[structopt(
         short,
@@ -64,19 +111,107 @@ pub struct Config {
     #[structopt(default_value = "67", long = "dhcp-port")]
     pub dhcp_port: u16,
 
+    /// If the dhcp server fails, the portal falls back to a link-local address (169.254.0.0/16) and announces it
+    /// via mDNS with this host name. Clients reach the portal at http://<name>.local then.
+    #[structopt(long = "link-local-name", default_value = "wifi-captive", env = "LINK_LOCAL_NAME")]
+    pub link_local_name: String,
+
     /// Time in seconds before the portal is opened for re-configuration, if no connection can be established.
     /// During this time, the application is listening to network manager connection state changes.
     #[structopt(short, long, default_value = "10", env = "WAIT_BEFORE_RECONFIGURE")]
     pub wait_before_reconfigure: u64,
 
+    /// Time in seconds after system boot during which the hotspot is not started. The service only waits
+    /// for network manager to reconnect on its own, so that the portal SSID does not show up on every reboot.
+    #[structopt(long = "boot-grace-period", default_value = "0", env = "BOOT_GRACE_PERIOD")]
+    pub boot_grace_period: u64,
+
     /// Time in seconds before retrying to connect to a configured WiFi SSID.
     /// The attempt happens independently if a portal is currently open or not,
     /// but if a portal and access point is set up, it will be temporarily shut down
-    /// for the connection attempt.
-    /// The timer is reset whenever a client connects to the captive portal.
+    /// for the connection attempt. With a station interface (`--concurrent-ap-sta`, `--station-interface`)
+    /// the saved networks are retried every interval while the portal stays online.
+    /// Clients of the captive portal do not reset the timer: Without a station interface,
+    /// the portal also closes for the retry while a client is using it.
     #[structopt(short, long, default_value = "360", env = "RETRY_IN")]
     pub retry_in: u64,
 
+    /// Offer an open hotspot (empty passphrase) as an OWE transition mode pair: The open SSID and a hidden
+    /// OWE ("Enhanced Open") SSID. Clients that support OWE connect encrypted, legacy clients connect to the open SSID.
+    /// Only supported by the OpenWrt backend, the other backends refuse to start with it.
+    #[structopt(long = "hotspot-owe", env = "HOTSPOT_OWE")]
+    pub hotspot_owe: bool,
+
+    /// The key management of the hotspot if it has a passphrase: "wpa2", "wpa3" (SAE only) or "transition"
+    /// (WPA2 and WPA3). Falls back to "wpa2" if the wifi device does not support SAE.
+    /// Only supported by the NetworkManager and OpenWrt backends.
+    #[structopt(long = "hotspot-security", default_value = "wpa2", env = "HOTSPOT_SECURITY")]
+    pub hotspot_security: HotspotSecurity,
+
+    /// The frequency band of the hotspot: "2.4" or "5". Defaults to the band of `--hotspot-channel`, or 2.4 Ghz.
+    /// 5 Ghz is less crowded, but not every client supports it.
+    #[structopt(long = "hotspot-band", env = "HOTSPOT_BAND")]
+    pub hotspot_band: Option<HotspotBand>,
+
+    /// The channel of the hotspot, like 6 or 36. If not set, the least congested channel of the band is picked
+    /// from the scan results. Not supported by the iwd backend.
+    #[structopt(long = "hotspot-channel", env = "HOTSPOT_CHANNEL")]
+    pub hotspot_channel: Option<u32>,
+
+    /// Append a random suffix to the hotspot SSID if other access points broadcast it (evil twins),
+    /// see [`crate::rogue_ap`]
+    #[structopt(long = "randomize-ssid", env = "RANDOMIZE_SSID")]
+    pub randomize_ssid: bool,
+
+    /// Do not pick the least congested hotspot channel. The wifi driver picks a channel instead.
+    #[structopt(long = "no-auto-channel", env = "NO_AUTO_CHANNEL")]
+    pub no_auto_channel: bool,
+
+    /// The maximum number of hotspot clients. The dhcp server offers no lease to further clients and the access
+    /// point rejects them if the backend supports a client limit (OpenWrt).
+    #[structopt(long = "max-clients", env = "MAX_CLIENTS")]
+    pub max_clients: Option<u32>,
+
+    /// Keep the hotspot and portal online while connecting: The connection is attempted on a second, virtual
+    /// station interface if the wifi device can run an access point and a station at the same time.
+    /// Only supported by the NetworkManager backend.
+    #[structopt(long = "concurrent-ap-sta", env = "CONCURRENT_AP_STA")]
+    pub concurrent_ap_sta: bool,
+
+    /// Try WPS push-button on startup if there is no connection: The strongest access point that advertises
+    /// WPS push-button is tried for two minutes before the portal opens. Only supported by the NetworkManager backend.
+    #[structopt(long = "wps", env = "WPS")]
+    pub wps: bool,
+
+    /// Enroll via Wi-Fi Easy Connect on startup if there is no connection. The URI is the content of the QR code
+    /// of the router, like "DPP:C:81/6;K:...;;". Requires wpa_supplicant with a control interface in
+    /// /run/wpa_supplicant. Only supported by the NetworkManager backend.
+    #[structopt(long = "dpp-uri", env = "DPP_URI")]
+    pub dpp_uri: Option<DppUri>,
+
+    /// Open an ad-hoc (IBSS) network instead of the access point if the wifi device does not support
+    /// access point mode. The ad-hoc network is always unencrypted. Only supported by the NetworkManager backend.
+    #[structopt(long = "adhoc-fallback", env = "ADHOC_FALLBACK")]
+    pub adhoc_fallback: bool,
+
+    /// Protected management frames (802.11w) of new connections and the hotspot: "auto", "disabled",
+    /// "optional" or "required". "auto" requires them for WPA3 and uses the backend default otherwise.
+    #[structopt(long = "pmf", default_value = "auto", env = "PMF")]
+    pub pmf: Pmf,
+
+    /// Minimum signal strength in percent of a new connection. Below that, the user is asked in the portal
+    /// whether to continue with the weak connection anyway.
+    #[structopt(long = "min-signal", env = "MIN_SIGNAL")]
+    pub min_signal: Option<u8>,
+
+    /// Minimum bitrate in Mbit/s of a new connection, an estimate of the downlink throughput
+    #[structopt(long = "min-bitrate", env = "MIN_BITRATE")]
+    pub min_bitrate: Option<u32>,
+
+    /// Maximum round trip time in milliseconds to the default gateway of a new connection
+    #[structopt(long = "max-gateway-rtt", env = "MAX_GATEWAY_RTT")]
+    pub max_gateway_rtt: Option<u32>,
+
     /// Exit after a connection has been established.
     #[structopt(short, long)]
     pub quit_after_connected: bool,
@@ -85,6 +220,215 @@ pub struct Config {
     #[structopt(long)]
     pub internet_connectivity: bool,
 
+    /// Directory for files that are generated at runtime, like the dhcp leases.
+    /// It is created if it does not exist. Nothing is persisted if not set.
+    #[structopt(parse(from_os_str), long = "state-dir", env = "STATE_DIR")]
+    pub state_dir: Option<PathBuf>,
+
+    /// Write the status (phase, SSID, ip address and failure reason) as json to this file on every change.
+    /// The file is replaced atomically. For shell scripts and status bars on the device.
+    #[structopt(parse(from_os_str), long = "status-file", env = "STATUS_FILE")]
+    pub status_file: Option<PathBuf>,
+
+    /// Time in seconds between writes of state files, like the dhcp leases.
+    /// Changes within this interval are batched and written once it has passed, or on shutdown.
+    #[structopt(long, default_value = "60", env = "FLUSH_INTERVAL")]
+    pub flush_interval: u64,
+
+    /// The number of status changes that are kept for "/api/events". The history is persisted in the state
+    /// directory, if set. 0 disables the history.
+    #[structopt(long = "event-history", default_value = "100", env = "EVENT_HISTORY")]
+    pub event_history: usize,
+
+    /// Start in safe mode if the service crashed this many times within the safe mode window: Only the hotspot
+    /// is offered and connections are not touched automatically. Requires a state directory. 0 disables the check.
+    #[structopt(long = "safe-mode-crashes", default_value = "5", env = "SAFE_MODE_CRASHES")]
+    pub safe_mode_crashes: usize,
+
+    /// The window in minutes for counting crashes, see "safe-mode-crashes"
+    #[structopt(long = "safe-mode-window", default_value = "10", env = "SAFE_MODE_WINDOW")]
+    pub safe_mode_window: u64,
+
+    /// Set at start up if the service crashed too often, see [`crate::boot_loop`]
+    #[structopt(skip)]
+    pub safe_mode: bool,
+
+    /// Keep all state in memory only. Nothing is written to the state directory and new network manager
+    /// connections are stored as in-memory connections. For kiosk images that must not write to disk.
+    #[structopt(long)]
+    pub in_memory: bool,
+
+    /// Size limits of long-lived internal collections, like "sessions=500,dhcp_leases=250".
+    /// A warning is logged if a collection exceeds its limit. Collections: dbus_replies, dbus_filters,
+    /// sessions, dhcp_leases, network_history, sse_clients.
+    #[structopt(long = "memory-budget", env = "MEMORY_BUDGET", use_delimiter = true)]
+    pub memory_budget: Vec<Budget>,
+
+    /// How a connect request is handled while another connection attempt is pending or running:
+    /// "attach" answers with the id of the current attempt, "replace" cancels the current attempt.
+    #[structopt(long = "duplicate-connect", default_value = "attach", env = "DUPLICATE_CONNECT")]
+    pub duplicate_connect: DuplicateConnectPolicy,
+
+    /// Authorization rules of the control surfaces, like "portal=connect,dbus:1000=connect+forget".
+    /// A rule replaces the default rule of its subject. See the policy module for subjects and actions.
+    #[structopt(long = "policy", env = "POLICY", use_delimiter = true)]
+    pub policy: Vec<Rule>,
+
+    /// Requests to the http server with the header "Authorization: Bearer <token>" are
+    /// authorized as "portal-admin" by the policy.
+    #[structopt(long = "admin-token", env = "ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    /// Install a NetworkManager dispatcher script that reports connections established outside of the portal,
+    /// for example with nmcli. See the dispatcher module.
+    #[structopt(long = "install-dispatcher", env = "INSTALL_DISPATCHER")]
+    pub install_dispatcher: bool,
+
+    /// Hours of the day (local time) in which the setup hotspot may be started, like "08:00-20:00,21:00-22:00".
+    /// The hotspot is always allowed if neither setup hours nor a presence file are configured.
+    #[structopt(long = "setup-hours", env = "SETUP_HOURS", use_delimiter = true)]
+    pub setup_hours: Vec<TimeWindow>,
+
+    /// A file that is touched on a physical button press. The setup hotspot is allowed for
+    /// "presence-minutes" after the last press, also outside of the setup hours.
+    #[structopt(parse(from_os_str), long = "presence-file", env = "PRESENCE_FILE")]
+    pub presence_file: Option<PathBuf>,
+
+    /// How long a button press allows the setup hotspot, in minutes
+    #[structopt(long = "presence-minutes", default_value = "10", env = "PRESENCE_MINUTES")]
+    pub presence_minutes: u64,
+
+    /// Push the metrics (see /metrics) to this Prometheus push gateway url,
+    /// like "http://gateway:9091/metrics/job/wifi-captive". Requires the "metrics-push" feature.
+    #[structopt(long = "metrics-push-url", env = "METRICS_PUSH_URL")]
+    pub metrics_push_url: Option<String>,
+
+    /// Send the metrics to this Prometheus remote write url instead of a push gateway.
+    /// Requires the "metrics-push" feature.
+    #[structopt(long = "metrics-remote-write-url", env = "METRICS_REMOTE_WRITE_URL")]
+    pub metrics_remote_write_url: Option<String>,
+
+    /// Metrics are pushed every this many seconds
+    #[structopt(long = "metrics-push-interval", default_value = "60", env = "METRICS_PUSH_INTERVAL")]
+    pub metrics_push_interval: u64,
+
+    /// How many metric snapshots are kept for remote write while the receiver is not reachable
+    #[structopt(long = "metrics-buffer", default_value = "60", env = "METRICS_BUFFER")]
+    pub metrics_buffer: usize,
+
+    /// Publish the status to this MQTT broker and accept commands from it, like "broker:1883".
+    /// Requires the "mqtt" feature.
+    #[structopt(long = "mqtt-broker", env = "MQTT_BROKER")]
+    pub mqtt_broker: Option<String>,
+
+    /// The device specific topic prefix, also used as MQTT client id
+    #[structopt(long = "mqtt-topic", default_value = "wifi-captive", env = "MQTT_TOPIC")]
+    pub mqtt_topic: String,
+
+    /// Check every this many seconds whether network manager still answers. 0 disables the watchdog.
+    #[structopt(long = "watchdog-interval", default_value = "60", env = "WATCHDOG_INTERVAL")]
+    pub watchdog_interval: u64,
+
+    /// Restart network manager via systemd if it stopped answering
+    #[structopt(long = "watchdog-restart", env = "WATCHDOG_RESTART")]
+    pub watchdog_restart: bool,
+
+    /// How many seconds network manager has to come back on the bus after a restart by the watchdog
+    #[structopt(long = "watchdog-restart-timeout", default_value = "120", env = "WATCHDOG_RESTART_TIMEOUT")]
+    pub watchdog_restart_timeout: u64,
+
+    /// Export each connect attempt as trace to this OTLP/HTTP endpoint, like "http://collector:4318/v1/traces".
+    /// Requires the "otlp" feature.
+    #[structopt(long = "otlp-endpoint", env = "OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Passphrases entered in the portal must have at least this many characters
+    #[structopt(long = "min-passphrase-length", default_value = "0", env = "MIN_PASSPHRASE_LENGTH")]
+    pub min_passphrase_length: usize,
+
+    /// A file with passphrases that are rejected in the portal, one per line, like vendor default passwords
+    #[structopt(parse(from_os_str), long = "denied-passphrases-file", env = "DENIED_PASSPHRASES_FILE")]
+    pub denied_passphrases_file: Option<PathBuf>,
+
+    /// The credential policy of the embedding application, see [`Config::with_credential_policy`]
+    #[structopt(skip)]
+    pub credential_policy: CredentialPolicy,
+
+    /// A file with the hotspot MAC filter: One "allow <address>" or "deny <address>" per line.
+    /// If addresses are allowed, only those clients may use the hotspot.
+    #[structopt(parse(from_os_str), long = "mac-filter-file", env = "MAC_FILTER_FILE")]
+    pub mac_filter_file: Option<PathBuf>,
+
+    /// The hotspot MAC filter, read from the "mac-filter-file" and replaced at runtime. Shared by all clones.
+    #[structopt(skip)]
+    pub mac_filter: MacFilterSync,
+
+    /// The log filter, replaced at runtime via the portal or D-Bus. Shared by all clones.
+    #[structopt(skip)]
+    pub log_filter: LogFilter,
+
+    /// A JSON file with additional captive portal probe urls and their responses, like
+    /// `[{"host": "check.example.com", "path": "/ping", "status": 204}]`. Read again if it changes.
+    #[structopt(parse(from_os_str), long = "probe-file", env = "PROBE_FILE")]
+    pub probe_file: Option<PathBuf>,
+
+    /// Record the D-Bus traffic of the network manager backend to this file, with secrets redacted.
+    /// For bug reports: The trace can be replayed without the device, see [`crate::dbus_record`].
+    #[structopt(parse(from_os_str), long = "record-dbus", env = "RECORD_DBUS")]
+    pub record_dbus: Option<PathBuf>,
+
+    /// The device model for "/api/device". Read from the device tree or DMI tables if not set.
+    #[structopt(long = "device-model", env = "DEVICE_MODEL")]
+    pub device_model: Option<String>,
+
+    /// The serial number for "/api/device". Read from the device tree or DMI tables if not set.
+    #[structopt(long = "device-serial", env = "DEVICE_SERIAL")]
+    pub device_serial: Option<String>,
+
+    /// The firmware version for "/api/device". The VERSION_ID of "/etc/os-release" if not set.
+    #[structopt(long = "firmware-version", env = "FIRMWARE_VERSION")]
+    pub firmware_version: Option<String>,
+
+    /// A shell command that signs the device identity with a device key. It gets the data on stdin
+    /// and must print the signature. See the device module.
+    #[structopt(long = "device-sign-command", env = "DEVICE_SIGN_COMMAND")]
+    pub device_sign_command: Option<String>,
+
+    /// The token of the deep link in the printed device QR code ("http://<gateway>/?pair=<token>").
+    /// Clients that open the link are paired and the portal shows the device they pair with.
+    #[structopt(long = "pairing-token", env = "PAIRING_TOKEN")]
+    pub pairing_token: Option<String>,
+
+    /// Close http connections of the portal that make no progress for this many seconds.
+    /// Not applied while a request is handled, like a long-poll status request.
+    #[structopt(long = "http-idle-timeout", default_value = "20", env = "HTTP_IDLE_TIMEOUT")]
+    pub http_idle_timeout: u64,
+
+    /// Maximum number of concurrent http connections to the portal
+    #[structopt(long = "http-max-connections", default_value = "64", env = "HTTP_MAX_CONNECTIONS")]
+    pub http_max_connections: usize,
+
+    /// Maximum number of concurrent http connections of one hotspot client
+    #[structopt(long = "http-max-client-connections", default_value = "8", env = "HTTP_MAX_CLIENT_CONNECTIONS")]
+    pub http_max_client_connections: usize,
+
+    /// Maximum size of an http request body in bytes
+    #[structopt(long = "http-max-body-size", default_value = "65536", env = "HTTP_MAX_BODY_SIZE")]
+    pub http_max_body_size: usize,
+
+    /// Also serve the portal via https with this PEM encoded certificate chain. Requires "tls-key" and the
+    /// "tls" feature. HTTP/2 is offered via ALPN.
+    #[structopt(parse(from_os_str), long = "tls-certificate", env = "TLS_CERTIFICATE")]
+    pub tls_certificate: Option<PathBuf>,
+
+    /// The PEM encoded private key of the https certificate
+    #[structopt(parse(from_os_str), long = "tls-key", env = "TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Listening port of the https server
+    #[structopt(long = "tls-port", default_value = "443", env = "TLS_PORT")]
+    pub tls_port: u16,
+
     /// The directory where the html files reside.
     #[structopt(parse(from_os_str), short, long, env = "UI_DIRECTORY")]
     #[cfg(all(not(feature = "includeui"), debug_assertions))]
@@ -95,22 +439,198 @@ impl Config {
     pub fn new() -> Config {
         Config {
             interface: None,
+            station_interface: None,
             ssid: "".to_string(),
             passphrase: "".to_string(),
             passphrase_file: None,
             identity: None,
             gateway: Ipv4Addr::new(0, 0, 0, 0),
+            fixed_gateway: false,
+            portal_hostname: None,
             listening_port: 0,
             dns_port: 0,
             dhcp_port: 0,
+            link_local_name: "wifi-captive".to_owned(),
             wait_before_reconfigure: 0,
+            boot_grace_period: 0,
             retry_in: 0,
+            hotspot_owe: false,
+            hotspot_security: HotspotSecurity::Wpa2,
+            hotspot_band: None,
+            hotspot_channel: None,
+            randomize_ssid: false,
+            no_auto_channel: false,
+            max_clients: None,
+            concurrent_ap_sta: false,
+            wps: false,
+            dpp_uri: None,
+            adhoc_fallback: false,
+            pmf: Pmf::Auto,
+            min_signal: None,
+            min_bitrate: None,
+            max_gateway_rtt: None,
             quit_after_connected: false,
             internet_connectivity: false,
+            state_dir: None,
+            status_file: None,
+            flush_interval: 60,
+            event_history: 0,
+            safe_mode_crashes: 0,
+            safe_mode_window: 0,
+            safe_mode: false,
+            in_memory: false,
+            memory_budget: Vec::new(),
+            duplicate_connect: DuplicateConnectPolicy::Attach,
+            policy: Vec::new(),
+            admin_token: None,
+            install_dispatcher: false,
+            setup_hours: Vec::new(),
+            presence_file: None,
+            presence_minutes: 0,
+            metrics_push_url: None,
+            metrics_remote_write_url: None,
+            metrics_push_interval: 0,
+            metrics_buffer: 0,
+            mqtt_broker: None,
+            mqtt_topic: String::new(),
+            otlp_endpoint: None,
+            watchdog_interval: 0,
+            watchdog_restart: false,
+            watchdog_restart_timeout: 0,
+            min_passphrase_length: 0,
+            denied_passphrases_file: None,
+            credential_policy: CredentialPolicy::default(),
+            mac_filter_file: None,
+            mac_filter: MacFilterSync::default(),
+            log_filter: LogFilter::default(),
+            probe_file: None,
+            record_dbus: None,
+            device_model: None,
+            device_serial: None,
+            firmware_version: None,
+            device_sign_command: None,
+            pairing_token: None,
+            http_idle_timeout: 0,
+            http_max_connections: 0,
+            http_max_client_connections: 0,
+            http_max_body_size: 0,
+            tls_certificate: None,
+            tls_key: None,
+            tls_port: 443,
             #[cfg(all(not(feature = "includeui"), debug_assertions))]
             ui_directory: None,
         }
     }
+    /// The connection limits of the portal http server
+    #[cfg(feature = "ui")]
+    pub fn http_limits(&self) -> crate::http_server::limits::HttpLimits {
+        crate::http_server::limits::HttpLimits {
+            idle_timeout: std::time::Duration::from_secs(self.http_idle_timeout),
+            max_connections: self.http_max_connections,
+            max_connections_per_client: self.http_max_client_connections,
+            max_body_size: self.http_max_body_size,
+        }
+    }
+
+    /// Sets the credential policy with the own rules of the embedding application.
+    /// The command line options are applied on top, see [`Config::credential_policy`].
+    pub fn with_credential_policy(mut self, policy: CredentialPolicy) -> Self {
+        self.credential_policy = policy;
+        self
+    }
+
+    /// The credential policy with the minimum passphrase length and the denied passphrases of the command line
+    pub fn credential_policy(&self) -> CredentialPolicy {
+        let mut policy = self.credential_policy.clone();
+        if self.min_passphrase_length > 0 {
+            policy = policy.min_length(self.min_passphrase_length);
+        }
+        if let Some(path) = &self.denied_passphrases_file {
+            match std::fs::read_to_string(path) {
+                Ok(content) => policy = policy.deny_passphrases(content.lines().filter(|l| !l.trim().is_empty())),
+                Err(e) => warn!("Could not read the denied passphrases {}: {}", path.display(), e),
+            }
+        }
+        policy
+    }
+
+    /// When the setup hotspot may be started
+    pub fn setup_window(&self) -> SetupWindow {
+        SetupWindow {
+            hours: self.setup_hours.clone(),
+            presence_file: self.presence_file.clone(),
+            presence_validity: std::time::Duration::from_secs(self.presence_minutes * 60),
+        }
+    }
+
+    /// The band and channel of the hotspot. None if neither is configured.
+    /// An error if the channel is unknown or not within the configured band.
+    pub fn hotspot_radio(&self) -> Result<Option<HotspotRadio>, String> {
+        HotspotRadio::new(self.hotspot_band, self.hotspot_channel)
+    }
+
+    /// The network manager watchdog, see [`crate::watchdog`]
+    pub fn watchdog(&self) -> Watchdog {
+        Watchdog {
+            interval: Some(std::time::Duration::from_secs(self.watchdog_interval)).filter(|i| i.as_secs() > 0),
+            restart_service: self.watchdog_restart,
+            restart_timeout: std::time::Duration::from_secs(self.watchdog_restart_timeout),
+        }
+    }
+
+    /// The https listener of the portal, if a certificate and a key are configured
+    #[cfg(feature = "tls")]
+    pub fn tls_settings(&self) -> Option<crate::http_server::tls::TlsSettings> {
+        match (&self.tls_certificate, &self.tls_key) {
+            (Some(certificate), Some(key)) => Some(crate::http_server::tls::TlsSettings {
+                server_addr: std::net::SocketAddrV4::new(self.gateway, self.tls_port),
+                certificate: certificate.clone(),
+                key: key.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Where metrics are pushed to, if configured. Remote write takes precedence over a push gateway.
+    #[cfg(feature = "metrics-push")]
+    pub fn metrics_push_target(&self) -> Option<crate::metrics::PushTarget> {
+        use crate::metrics::PushTarget;
+        match (&self.metrics_remote_write_url, &self.metrics_push_url) {
+            (Some(url), _) => Some(PushTarget::RemoteWrite(url.clone())),
+            (None, Some(url)) => Some(PushTarget::PushGateway(url.clone())),
+            (None, None) => None,
+        }
+    }
+
+    /// The directory for runtime files. None if no state directory is configured or in-memory mode is enabled.
+    pub fn persistence_dir(&self) -> Option<&Path> {
+        match self.in_memory {
+            true => None,
+            false => self.state_dir.as_ref().map(|p| p.as_path()),
+        }
+    }
+
+    /// The thresholds of the connection quality gate
+    pub fn quality_gate(&self) -> QualityGate {
+        QualityGate {
+            min_signal: self.min_signal,
+            min_bitrate: self.min_bitrate.map(|mbit| mbit.saturating_mul(1000)),
+            max_gateway_rtt: self.max_gateway_rtt,
+        }
+    }
+
+    /// The address of the portal page: The portal host name, if the dns server answers it, or the gateway address
+    pub fn portal_url(&self) -> String {
+        let host = match self.portal_hostname.as_ref() {
+            Some(hostname) if cfg!(feature = "dns") => hostname.clone(),
+            _ => self.gateway.to_string(),
+        };
+        match self.listening_port {
+            80 => format!("http://{}", host),
+            port => format!("http://{}:{}", host, port),
+        }
+    }
+
     #[cfg(all(not(feature = "includeui"), debug_assertions))]
     pub fn get_ui_directory(&self) -> PathBuf {
         self.ui_directory.clone().unwrap_or("ui".into())
@@ -0,0 +1,176 @@
+//! # Credential policy
+//! Entered credentials are checked before a connection attempt is queued. The policy has a minimum
+//! passphrase length (beyond the 8 characters of the WPA specification), a list of denied passphrases
+//! like vendor defaults, and hooks of the embedding application for own rules.
+//!
+//! ```
+//! use wifi_captive::credential_policy::{CredentialPolicy, Violation};
+//!
+//! let policy = CredentialPolicy::new()
+//!     .min_length(12)
+//!     .deny_passphrases(vec!["password1234", "admin1234567"])
+//!     .hook(|credentials| match credentials.passphrase {
+//!         Some(p) if !p.chars().any(|c| c.is_ascii_digit()) => {
+//!             Err(Violation::new("passphrase", "digit-required", "The passphrase must contain a digit"))
+//!         },
+//!         _ => Ok(()),
+//!     });
+//! ```
+//!
+//! A violation is answered with a "credential-policy" problem, whose "violation" member names the
+//! form field, the rule and a message for the user. See doc/errors.md.
+
+use serde::Serialize;
+use std::fmt;
+use std::sync::Arc;
+
+/// A rejected credential
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Violation {
+    /// The form field: "ssid", "identity" or "passphrase"
+    pub field: &'static str,
+    /// A stable rule code, for example "min-length" or "denied"
+    pub rule: &'static str,
+    /// A message for the user
+    pub message: String,
+}
+
+impl Violation {
+    pub fn new(field: &'static str, rule: &'static str, message: impl Into<String>) -> Self {
+        Violation {
+            field,
+            rule,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.message.fmt(f)
+    }
+}
+
+/// The credentials of a connect request
+pub struct Credentials<'a> {
+    pub ssid: &'a str,
+    /// wpa, wpa3, wep, open, enterprise
+    pub mode: &'a str,
+    pub identity: Option<&'a str>,
+    pub passphrase: Option<&'a str>,
+}
+
+/// An own rule of the embedding application
+pub type CredentialHook = Arc<dyn Fn(&Credentials) -> Result<(), Violation> + Send + Sync>;
+
+/// The policy for entered credentials. The default policy accepts everything.
+#[derive(Clone, Default)]
+pub struct CredentialPolicy {
+    min_length: usize,
+    /// Lower case
+    denied: Vec<String>,
+    hooks: Vec<CredentialHook>,
+}
+
+impl CredentialPolicy {
+    pub fn new() -> Self {
+        CredentialPolicy::default()
+    }
+
+    /// Passphrases must have at least this many characters
+    pub fn min_length(mut self, min_length: usize) -> Self {
+        self.min_length = min_length;
+        self
+    }
+
+    /// Passphrases that are rejected, compared case-insensitively
+    pub fn deny_passphrases<I, S>(mut self, passphrases: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.denied
+            .extend(passphrases.into_iter().map(|p| p.as_ref().trim().to_lowercase()));
+        self
+    }
+
+    /// Adds a hook. Hooks are called in order after the built-in rules.
+    pub fn hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Credentials) -> Result<(), Violation> + Send + Sync + 'static,
+    {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Returns the first violation of the given credentials. Open networks have no passphrase to check,
+    /// but are passed to the hooks.
+    pub fn check(&self, credentials: &Credentials) -> Result<(), Violation> {
+        if let Some(passphrase) = credentials.passphrase.filter(|_| credentials.mode != "open") {
+            if passphrase.chars().count() < self.min_length {
+                return Err(Violation::new(
+                    "passphrase",
+                    "min-length",
+                    format!("The passphrase must have at least {} characters", self.min_length),
+                ));
+            }
+            if self.denied.contains(&passphrase.to_lowercase()) {
+                return Err(Violation::new(
+                    "passphrase",
+                    "denied",
+                    "This passphrase is a known default password and not allowed",
+                ));
+            }
+        }
+        for hook in &self.hooks {
+            hook(credentials)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for CredentialPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CredentialPolicy")
+            .field("min_length", &self.min_length)
+            .field("denied", &self.denied.len())
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wpa(passphrase: &str) -> Credentials {
+        Credentials {
+            ssid: "home",
+            mode: "wpa",
+            identity: None,
+            passphrase: Some(passphrase),
+        }
+    }
+
+    #[test]
+    fn rules() {
+        let policy = CredentialPolicy::new()
+            .min_length(10)
+            .deny_passphrases(vec!["Password1234"])
+            .hook(|c| match c.ssid {
+                "guest" => Err(Violation::new("ssid", "guest-network", "Guest networks are not allowed")),
+                _ => Ok(()),
+            });
+        assert_eq!(policy.check(&wpa("short1234")).unwrap_err().rule, "min-length");
+        assert_eq!(policy.check(&wpa("PASSWORD1234")).unwrap_err().rule, "denied");
+        assert!(policy.check(&wpa("correct horse")).is_ok());
+        let guest = Credentials {
+            ssid: "guest",
+            mode: "open",
+            identity: None,
+            passphrase: None,
+        };
+        assert_eq!(policy.check(&guest).unwrap_err().field, "ssid");
+        assert!(CredentialPolicy::default().check(&wpa("")).is_ok());
+    }
+}
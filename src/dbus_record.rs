@@ -0,0 +1,546 @@
+//! # D-Bus recording and replay
+//! With `--record-dbus <file>` the network manager backend writes all its D-Bus messages to the file,
+//! one json object per line. Secrets of connection settings, like "psk", are redacted. Messages of the bus
+//! itself, like "AddMatch", are not recorded.
+//!
+//! A trace of a user-reported network manager quirk can be replayed without the device: [`serve`] owns the
+//! network manager bus name on a private bus and answers the method calls of the service with the recorded
+//! replies. Recorded signals are emitted as soon as all method calls before them have been answered.
+//! Start a bus with `dbus-daemon --session --print-address` and point the service to it with
+//! `DBUS_SYSTEM_BUS_ADDRESS`. Method calls of network manager to the service, like secret agent requests,
+//! are not replayed.
+
+use crate::dbus_tokio::DbusConnection;
+use crate::dpp::hex_decode;
+use crate::CaptivePortalError;
+use dbus::arg::messageitem::{ArrayError, MessageItem, MessageItemArray, MessageItemDict};
+use dbus::channel::{Channel, MatchingReceiver, Sender};
+use dbus::message::{MatchRule, MessageType};
+use dbus::{nonblock, Message, Path, Signature};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+const BUS_NAME: &str = "org.freedesktop.DBus";
+const NM_BUSNAME: &str = "org.freedesktop.NetworkManager";
+const ERROR_UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+const ERROR_FAILED: &str = "org.freedesktop.DBus.Error.Failed";
+/// DBUS_NAME_FLAG_DO_NOT_QUEUE
+const NAME_FLAG_DO_NOT_QUEUE: u32 = 0x4;
+/// DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER
+const NAME_PRIMARY_OWNER: u32 = 1;
+const REDACTED: &str = "<redacted>";
+
+/// A message argument. The variants are named after the D-Bus type codes, byte arrays are hex encoded.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Arg {
+    #[serde(rename = "ay")]
+    Bytes(String),
+    /// The array signature, like "as", and the items
+    #[serde(rename = "a")]
+    Array(String, Vec<Arg>),
+    /// The dictionary signature, like "a{sv}", and the entries
+    #[serde(rename = "a{}")]
+    Dict(String, Vec<(Arg, Arg)>),
+    #[serde(rename = "r")]
+    Struct(Vec<Arg>),
+    #[serde(rename = "v")]
+    Variant(Box<Arg>),
+    #[serde(rename = "o")]
+    ObjectPath(String),
+    #[serde(rename = "g")]
+    Signature(String),
+    #[serde(rename = "s")]
+    Str(String),
+    #[serde(rename = "b")]
+    Bool(bool),
+    #[serde(rename = "y")]
+    Byte(u8),
+    #[serde(rename = "n")]
+    Int16(i16),
+    #[serde(rename = "i")]
+    Int32(i32),
+    #[serde(rename = "x")]
+    Int64(i64),
+    #[serde(rename = "q")]
+    UInt16(u16),
+    #[serde(rename = "u")]
+    UInt32(u32),
+    #[serde(rename = "t")]
+    UInt64(u64),
+    #[serde(rename = "d")]
+    Double(f64),
+    /// File descriptors are not recorded and cannot be replayed
+    #[serde(rename = "h")]
+    UnixFd,
+}
+
+/// Connection setting keys with secrets, like "psk" of "802-11-wireless-security" or "wep-key0".
+/// The "-flags" and "-type" keys of the secrets are kept.
+fn is_secret(key: &str) -> bool {
+    key == "psk"
+        || key == "pin"
+        || key.ends_with("password")
+        || (key.starts_with("wep-key") && key.ends_with(|c: char| c.is_ascii_digit()))
+}
+
+/// Keeps the type of a secret value, but not its content
+fn redacted(value: Arg) -> Arg {
+    match value {
+        Arg::Variant(v) => Arg::Variant(Box::new(redacted(*v))),
+        Arg::Str(_) => Arg::Str(REDACTED.to_owned()),
+        Arg::Bytes(_) => Arg::Bytes(String::new()),
+        other => other,
+    }
+}
+
+fn array_error(e: ArrayError) -> CaptivePortalError {
+    CaptivePortalError::Generic(format!("Invalid recorded array: {:?}", e))
+}
+
+impl From<&MessageItem> for Arg {
+    fn from(item: &MessageItem) -> Self {
+        match item {
+            MessageItem::Array(a) if &**a.signature() == "ay" => Arg::Bytes(
+                a.iter()
+                    .filter_map(|i| match i {
+                        MessageItem::Byte(b) => Some(format!("{:02x}", b)),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            MessageItem::Array(a) => Arg::Array(a.signature().to_string(), a.iter().map(Arg::from).collect()),
+            MessageItem::Dict(d) => Arg::Dict(
+                d.signature().to_string(),
+                d.iter()
+                    .map(|(k, v)| match Arg::from(k) {
+                        Arg::Str(key) if is_secret(&key) => (Arg::Str(key), redacted(Arg::from(v))),
+                        key => (key, Arg::from(v)),
+                    })
+                    .collect(),
+            ),
+            MessageItem::Struct(v) => Arg::Struct(v.iter().map(Arg::from).collect()),
+            MessageItem::Variant(v) => Arg::Variant(Box::new(Arg::from(&**v))),
+            MessageItem::ObjectPath(p) => Arg::ObjectPath(p.to_string()),
+            MessageItem::Signature(s) => Arg::Signature(s.to_string()),
+            MessageItem::Str(s) => Arg::Str(s.clone()),
+            MessageItem::Bool(b) => Arg::Bool(*b),
+            MessageItem::Byte(b) => Arg::Byte(*b),
+            MessageItem::Int16(n) => Arg::Int16(*n),
+            MessageItem::Int32(n) => Arg::Int32(*n),
+            MessageItem::Int64(n) => Arg::Int64(*n),
+            MessageItem::UInt16(n) => Arg::UInt16(*n),
+            MessageItem::UInt32(n) => Arg::UInt32(*n),
+            MessageItem::UInt64(n) => Arg::UInt64(*n),
+            MessageItem::Double(n) => Arg::Double(*n),
+            MessageItem::UnixFd(_) => Arg::UnixFd,
+        }
+    }
+}
+
+impl Arg {
+    pub fn to_item(&self) -> Result<MessageItem, CaptivePortalError> {
+        Ok(match self {
+            Arg::Bytes(hex) => {
+                let bytes = hex_decode(hex).ok_or_else(|| format!("Invalid recorded byte array {}", hex))?;
+                let items = bytes.into_iter().map(MessageItem::Byte).collect();
+                MessageItem::Array(MessageItemArray::new(items, Signature::new("ay")?).map_err(array_error)?)
+            },
+            Arg::Array(sig, items) => {
+                let items = items.iter().map(Arg::to_item).collect::<Result<Vec<_>, _>>()?;
+                MessageItem::Array(MessageItemArray::new(items, Signature::new(sig.as_str())?).map_err(array_error)?)
+            },
+            Arg::Dict(sig, entries) => {
+                // Dictionary keys are basic types with a single character signature
+                if sig.len() < 5 || !sig.is_ascii() || !sig.starts_with("a{") || !sig.ends_with('}') {
+                    return Err(CaptivePortalError::Generic(format!("Invalid dictionary signature {}", sig)));
+                }
+                let (key_sig, value_sig) = (&sig[2..3], &sig[3..sig.len() - 1]);
+                let entries = entries
+                    .iter()
+                    .map(|(k, v)| Ok((k.to_item()?, v.to_item()?)))
+                    .collect::<Result<Vec<_>, CaptivePortalError>>()?;
+                let dict = MessageItemDict::new(entries, Signature::new(key_sig)?, Signature::new(value_sig)?);
+                MessageItem::Dict(dict.map_err(array_error)?)
+            },
+            Arg::Struct(v) => MessageItem::Struct(v.iter().map(Arg::to_item).collect::<Result<Vec<_>, _>>()?),
+            Arg::Variant(v) => MessageItem::Variant(Box::new(v.to_item()?)),
+            Arg::ObjectPath(p) => MessageItem::ObjectPath(Path::new(p.as_str())?),
+            Arg::Signature(s) => MessageItem::Signature(Signature::new(s.as_str())?),
+            Arg::Str(s) => MessageItem::Str(s.clone()),
+            Arg::Bool(b) => MessageItem::Bool(*b),
+            Arg::Byte(b) => MessageItem::Byte(*b),
+            Arg::Int16(n) => MessageItem::Int16(*n),
+            Arg::Int32(n) => MessageItem::Int32(*n),
+            Arg::Int64(n) => MessageItem::Int64(*n),
+            Arg::UInt16(n) => MessageItem::UInt16(*n),
+            Arg::UInt32(n) => MessageItem::UInt32(*n),
+            Arg::UInt64(n) => MessageItem::UInt64(*n),
+            Arg::Double(n) => MessageItem::Double(*n),
+            Arg::UnixFd => return Err(CaptivePortalError::Generic("File descriptors cannot be replayed".into())),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    MethodCall,
+    MethodReturn,
+    Error,
+    Signal,
+}
+
+/// A line of a trace
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RecordedMessage {
+    /// Milliseconds since the recording started
+    pub time: u64,
+    /// True for messages of the service, false for messages of network manager
+    pub sent: bool,
+    pub kind: Kind,
+    pub serial: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_serial: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub member: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_name: Option<String>,
+    pub args: Vec<Arg>,
+}
+
+impl RecordedMessage {
+    pub fn from_message(msg: &Message, sent: bool, time: u64) -> Self {
+        let (msg_type, path, interface, member) = msg.headers();
+        let kind = match msg_type {
+            MessageType::MethodCall => Kind::MethodCall,
+            MessageType::MethodReturn => Kind::MethodReturn,
+            MessageType::Error => Kind::Error,
+            MessageType::Signal => Kind::Signal,
+        };
+        RecordedMessage {
+            time,
+            sent,
+            kind,
+            serial: msg.get_serial().unwrap_or_default(),
+            reply_serial: msg.get_reply_serial(),
+            path,
+            interface,
+            member,
+            error_name: msg.error_name(),
+            args: msg.get_items().iter().map(Arg::from).collect(),
+        }
+    }
+
+    /// Builds the recorded signal or the recorded reply to the given method call
+    pub fn to_message(&self, call: Option<&Message>) -> Result<Message, CaptivePortalError> {
+        let mut msg = match (self.kind, call) {
+            (Kind::Signal, _) => Message::new_signal(
+                self.path.clone().unwrap_or_default(),
+                self.interface.clone().unwrap_or_default(),
+                self.member.clone().unwrap_or_default(),
+            )?,
+            (Kind::MethodReturn, Some(call)) => call.method_return(),
+            (Kind::Error, Some(call)) => {
+                let text = match self.args.first() {
+                    Some(Arg::Str(text)) => text.as_str(),
+                    _ => "",
+                };
+                let name = self.error_name.as_deref().unwrap_or(ERROR_FAILED);
+                return Message::new_error(call, name, text)
+                    .ok_or_else(|| CaptivePortalError::Generic(format!("Invalid recorded error {}", name)));
+            },
+            (Kind::MethodCall, _) => return Err(CaptivePortalError::Generic("Method calls are not replayed".into())),
+            (_, None) => return Err(CaptivePortalError::Generic("A reply needs the method call".into())),
+        };
+        let items = self.args.iter().map(Arg::to_item).collect::<Result<Vec<_>, _>>()?;
+        msg.append_items(&items);
+        Ok(msg)
+    }
+}
+
+struct Recorder {
+    file: Mutex<File>,
+    started: Instant,
+}
+
+impl Recorder {
+    fn record(&self, msg: &Message, sent: bool) {
+        let peer = if sent { msg.destination() } else { msg.sender() };
+        if peer.map(|p| &*p == BUS_NAME).unwrap_or(false) {
+            return;
+        }
+        let time = self.started.elapsed().as_millis() as u64;
+        let result = serde_json::to_string(&RecordedMessage::from_message(msg, sent, time))
+            .map_err(CaptivePortalError::from)
+            .and_then(|line| Ok(writeln!(self.file.lock().expect("recorder lock"), "{}", line)?));
+        if let Err(e) = result {
+            warn!("Failed to record a D-Bus message: {}", e);
+        }
+    }
+}
+
+static RECORDER: Mutex<Option<Arc<Recorder>>> = Mutex::new(None);
+
+/// Records the traffic of the D-Bus connections that are created afterwards with
+/// [`crate::dbus_tokio::new_system_recorded`] to the given file. The file is replaced.
+pub fn record_to(path: &std::path::Path) -> Result<(), CaptivePortalError> {
+    let file = File::create(path).map_err(|e| CaptivePortalError::IO(e, "Failed to create the D-Bus recording"))?;
+    *RECORDER.lock().expect("recorder lock") = Some(Arc::new(Recorder {
+        file: Mutex::new(file),
+        started: Instant::now(),
+    }));
+    Ok(())
+}
+
+/// Records the traffic of the given channel, if a recording has been started with [`record_to`]
+pub(crate) fn monitor(channel: &mut Channel) {
+    if let Some(recorder) = RECORDER.lock().expect("recorder lock").clone() {
+        channel.set_monitor(Some(Box::new(move |msg, sent| recorder.record(msg, sent))));
+    }
+}
+
+/// Answers method calls with the replies of a recorded trace, in the recorded order
+pub struct Replay {
+    messages: Vec<RecordedMessage>,
+    /// Recorded method calls that have been answered and signals that have been emitted
+    done: Vec<bool>,
+}
+
+impl Replay {
+    pub fn new(messages: Vec<RecordedMessage>) -> Self {
+        let done = vec![false; messages.len()];
+        Replay { messages, done }
+    }
+
+    /// Parses a trace file of [`record_to`]
+    pub fn parse(trace: &str) -> Result<Self, CaptivePortalError> {
+        let messages = trace
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<RecordedMessage>, _>>()?;
+        Ok(Replay::new(messages))
+    }
+
+    /// The recorded signals that are due, because all recorded method calls before them have been answered
+    pub fn due_signals(&mut self) -> Vec<RecordedMessage> {
+        let mut due = Vec::new();
+        for (m, done) in self.messages.iter().zip(self.done.iter_mut()) {
+            match (m.sent, m.kind) {
+                (true, Kind::MethodCall) if !*done => break,
+                (false, Kind::Signal) if !*done => {
+                    *done = true;
+                    due.push(m.clone());
+                },
+                _ => {},
+            }
+        }
+        due
+    }
+
+    /// The recorded reply to the first unanswered call with the same path, interface and member, followed by the
+    /// signals that are due. A call with the same arguments is preferred. None if there is no such call.
+    pub fn call(&mut self, path: &str, interface: &str, member: &str, args: &[Arg]) -> Option<Vec<RecordedMessage>> {
+        let messages = &self.messages;
+        let done = &self.done;
+        let candidates: Vec<usize> = (0..messages.len())
+            .filter(|&i| {
+                let m = &messages[i];
+                m.sent
+                    && m.kind == Kind::MethodCall
+                    && !done[i]
+                    && m.path.as_deref() == Some(path)
+                    && m.interface.as_deref() == Some(interface)
+                    && m.member.as_deref() == Some(member)
+            })
+            .collect();
+        let index = candidates
+            .iter()
+            .find(|&&i| messages[i].args == args)
+            .or_else(|| candidates.first())
+            .copied()?;
+        self.done[index] = true;
+
+        let serial = self.messages[index].serial;
+        let reply = self.messages.iter().skip(index + 1).find(|m| {
+            !m.sent && m.reply_serial == Some(serial) && (m.kind == Kind::MethodReturn || m.kind == Kind::Error)
+        });
+        let mut replies: Vec<RecordedMessage> = reply.cloned().into_iter().collect();
+        replies.extend(self.due_signals());
+        Some(replies)
+    }
+}
+
+/// The replies to the given method call. Calls that are not part of the trace fail with "UnknownMethod".
+fn answer(replay: &Mutex<Replay>, call: &Message) -> Vec<Message> {
+    let (_, path, interface, member) = call.headers();
+    let path = path.unwrap_or_default();
+    let (interface, member) = (interface.unwrap_or_default(), member.unwrap_or_default());
+    let args: Vec<Arg> = call.get_items().iter().map(Arg::from).collect();
+    let recorded = replay.lock().expect("replay lock").call(&path, &interface, &member, &args);
+    match recorded {
+        Some(recorded) => recorded
+            .iter()
+            .filter_map(|m| match m.to_message(Some(call)) {
+                Ok(msg) => Some(msg),
+                Err(e) => {
+                    warn!("Cannot replay {:?}: {}", m, e);
+                    None
+                },
+            })
+            .collect(),
+        None => {
+            warn!("Not part of the trace: {} {}.{}", path, interface, member);
+            Message::new_error(call, ERROR_UNKNOWN_METHOD, "Not part of the recorded trace")
+                .into_iter()
+                .collect()
+        },
+    }
+}
+
+/// Acts as network manager on the bus of the given connection and replays the trace. The I/O resource of the
+/// connection must be running. The replay stops when the connection is closed.
+pub async fn serve(replay: Replay, conn: Arc<DbusConnection>) -> Result<(), CaptivePortalError> {
+    let replay = Arc::new(Mutex::new(replay));
+    let replay_clone = replay.clone();
+    let mut rule = MatchRule::new();
+    rule.msg_type = Some(MessageType::MethodCall);
+    conn.start_receive(
+        rule,
+        Box::new(move |msg, conn| {
+            for reply in answer(&replay_clone, &msg) {
+                let _ = conn.send(reply);
+            }
+            true
+        }),
+    );
+
+    let p = nonblock::Proxy::new(BUS_NAME, "/org/freedesktop/DBus", conn.clone());
+    let (reply,): (u32,) = p.method_call(BUS_NAME, "RequestName", (NM_BUSNAME, NAME_FLAG_DO_NOT_QUEUE)).await?;
+    if reply != NAME_PRIMARY_OWNER {
+        return Err(CaptivePortalError::Generic(format!("The bus name {} is taken", NM_BUSNAME)));
+    }
+
+    let signals = replay.lock().expect("replay lock").due_signals();
+    for signal in signals {
+        match signal.to_message(None) {
+            Ok(msg) => {
+                let _ = conn.send(msg);
+            },
+            Err(e) => warn!("Cannot replay {:?}: {}", signal, e),
+        }
+    }
+    Ok(())
+}
+
+/// A bus daemon of its own, to replay traces in tests without a session bus. Killed on drop.
+#[cfg(test)]
+pub(crate) struct PrivateBus {
+    daemon: std::process::Child,
+    address: String,
+}
+
+#[cfg(test)]
+impl PrivateBus {
+    /// Starts `dbus-daemon --session`. None if the daemon is not installed.
+    pub fn start() -> Option<Self> {
+        use std::io::BufRead;
+        let daemon = std::process::Command::new("dbus-daemon")
+            .args(&["--session", "--nofork", "--print-address"])
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .ok()?;
+        let mut bus = PrivateBus {
+            daemon,
+            address: String::new(),
+        };
+        let stdout = bus.daemon.stdout.take()?;
+        std::io::BufReader::new(stdout).read_line(&mut bus.address).ok()?;
+        bus.address = bus.address.trim().to_owned();
+        Some(bus).filter(|bus| !bus.address.is_empty())
+    }
+
+    /// A new connection to the bus. The I/O resource is spawned.
+    pub fn connect(&self) -> Result<Arc<DbusConnection>, CaptivePortalError> {
+        let (resource, conn) = crate::dbus_tokio::new_address::<DbusConnection>(&self.address)?;
+        crate::runtime::spawn(async move {
+            let _ = resource.await;
+        });
+        Ok(conn)
+    }
+}
+
+#[cfg(test)]
+impl Drop for PrivateBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACE: &str = r#"
+{"time":0,"sent":true,"kind":"method_call","serial":5,"path":"/d","interface":"p","member":"Get","args":[{"s":"Type"}]}
+{"time":1,"sent":true,"kind":"method_call","serial":6,"path":"/d","interface":"p","member":"Get","args":[{"s":"Mtu"}]}
+{"time":2,"sent":false,"kind":"method_return","serial":90,"reply_serial":6,"args":[{"v":{"u":1500}}]}
+{"time":3,"sent":false,"kind":"method_return","serial":91,"reply_serial":5,"args":[{"v":{"u":2}}]}
+{"time":4,"sent":false,"kind":"signal","serial":92,"path":"/d","interface":"d","member":"Changed","args":[{"u":100}]}
+"#;
+
+    #[test]
+    fn replay_in_order() {
+        let mut replay = Replay::parse(TRACE).unwrap();
+        assert!(replay.due_signals().is_empty());
+        assert!(replay.call("/d", "p", "Set", &[]).is_none());
+
+        let mtu = [Arg::Str("Mtu".into())];
+        let replies = replay.call("/d", "p", "Get", &mtu).unwrap();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].args, vec![Arg::Variant(Box::new(Arg::UInt32(1500)))]);
+
+        // Another argument matches the remaining call. The signal follows the reply.
+        let replies = replay.call("/d", "p", "Get", &mtu).unwrap();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].reply_serial, Some(5));
+        assert_eq!(replies[1].member.as_deref(), Some("Changed"));
+        assert!(replay.call("/d", "p", "Get", &mtu).is_none());
+    }
+
+    #[test]
+    fn redact_secrets() {
+        let security = MessageItem::from_dict::<(), _>(
+            vec![
+                Ok(("key-mgmt".to_owned(), MessageItem::Variant(Box::new("wpa-psk".into())))),
+                Ok(("psk".to_owned(), MessageItem::Variant(Box::new("my secret".into())))),
+                Ok(("psk-flags".to_owned(), MessageItem::Variant(Box::new(MessageItem::UInt32(0))))),
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+        let msg = Message::new_method_call(NM_BUSNAME, "/org/freedesktop/NetworkManager/Settings/1", "a.b", "Update")
+            .unwrap()
+            .append1(security);
+
+        let recorded = RecordedMessage::from_message(&msg, true, 0);
+        let json = serde_json::to_string(&recorded).unwrap();
+        assert!(!json.contains("my secret"));
+        assert!(json.contains(r#"[{"s":"psk"},{"v":{"s":"<redacted>"}}]"#));
+        assert!(json.contains(r#"[{"s":"key-mgmt"},{"v":{"s":"wpa-psk"}}]"#));
+
+        // The recorded arguments can be sent again
+        let parsed: RecordedMessage = serde_json::from_str(&json).unwrap();
+        let items = parsed.args.iter().map(Arg::to_item).collect::<Result<Vec<_>, _>>().unwrap();
+        let args: Vec<Arg> = items.iter().map(Arg::from).collect();
+        assert_eq!(args, recorded.args);
+    }
+}
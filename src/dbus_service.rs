@@ -0,0 +1,236 @@
+//! # D-Bus control interface
+//! The service owns the name "org.openhab.WifiCaptive" on the system bus and exports
+//! the interface of the same name at "/org/openhab/WifiCaptive".
+//!
+//! Methods:
+//! * `CancelConnect(t attempt) -> b`: Cancels the given connection attempt, see [`crate::attempts`].
+//!   Returns false if that attempt is not pending or running.
+//! * `Forget(s ssid) -> t`: Queues a command to remove the stored connection for the given SSID,
+//!   see [`crate::commands`]. Returns the command id. SSIDs that are not utf8 are given as bytes, `Forget(ay ssid)`.
+//! * `RestartHotspot() -> t`: Queues a command to restart the hotspot. Returns the command id.
+//! * `RestartWifi() -> t`: Queues a command to switch wifi off and on again. Returns the command id.
+//! * `Reboot() -> t`: Queues a command to reboot the device via logind. Returns the command id.
+//! * `SetUpdateNotice(s state, s message) -> t`: Queues a command to show an update banner on the portal.
+//!   The state is "available" or "in_progress", an empty state removes the banner. Returns the command id.
+//! * `NetworkEvent(s interface, s action, s connection) -> t`: Queues a NetworkManager dispatcher event,
+//!   see [`crate::dispatcher`]. Returns the command id.
+//! * `SetLogFilter(s filter, t minutes) -> s`: Replaces the log filter right away, see [`crate::log_filter`].
+//!   The startup filter applies again after the given minutes, never with 0. At most a week can be requested.
+//!   An empty filter applies the startup filter. Returns the active filter.
+//!
+//! The unix user and process id of the caller are resolved via the bus daemon ("GetConnectionCredentials").
+//! The policy authorizes callers by the user id, see [`crate::policy`]. Denied calls are answered with
+//! "org.freedesktop.DBus.Error.AccessDenied". The unique bus name, the user and the process id of the caller
+//! are recorded in the audit log.
+//!
+//! With debug logging, the match rules of the service are logged after start up. This requires a bus daemon
+//! with the "org.freedesktop.DBus.Debug.Stats" interface, which is usually restricted to root.
+//!
+//! The bus policy `data/org.openhab.WifiCaptive.conf` must be installed to "/etc/dbus-1/system.d/",
+//! otherwise the name cannot be acquired. The interface is optional, the portal works without it.
+
+use crate::attempts::ConnectAttemptsSync;
+use crate::commands::{Command, CommandSender, Origin, Surface};
+use crate::dbus_tokio::{self, DbusConnection};
+use crate::dispatcher::DispatcherEvent;
+use crate::log_filter::{self, LogFilter};
+use crate::network_interface::SSID;
+use crate::policy::Action;
+use crate::runtime;
+use crate::status::UpdateNotice;
+use crate::CaptivePortalError;
+use dbus::arg::{RefArg, Variant};
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::{MatchRule, MessageType};
+use dbus::nonblock;
+use dbus::nonblock::stdintf::org_freedesktop_dbus::{DBus, DBusDebugStats, RequestNameReply};
+use dbus::Message;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::Arc;
+
+pub const BUSNAME: &str = "org.openhab.WifiCaptive";
+pub const PATH: &str = "/org/openhab/WifiCaptive";
+pub const INTERFACE: &str = "org.openhab.WifiCaptive";
+
+/// Connects to the system bus, acquires the bus name and answers method calls in the background.
+pub async fn start(
+    attempts: ConnectAttemptsSync,
+    commands: CommandSender,
+    log_filter: LogFilter,
+) -> Result<(), CaptivePortalError> {
+    let (resource, conn) = dbus_tokio::new_system_default()?;
+    runtime::spawn(async move {
+        let err = resource.await;
+        warn!("Lost connection to D-Bus, the control interface is gone: {}", err);
+    });
+
+    let reply = conn.request_name(BUSNAME, false, true, true).await?;
+    if reply != RequestNameReply::PrimaryOwner {
+        return Err(CaptivePortalError::Generic(format!(
+            "The D-Bus name {} is owned by another process",
+            BUSNAME
+        )));
+    }
+
+    let mut rule = MatchRule::new();
+    rule.msg_type = Some(MessageType::MethodCall);
+    rule.path = Some(PATH.into());
+    rule.interface = Some(INTERFACE.into());
+    let caller_conn = conn.clone();
+    conn.start_receive(
+        rule,
+        Box::new(move |msg, _| {
+            let (conn, attempts, commands) = (caller_conn.clone(), attempts.clone(), commands.clone());
+            let log_filter = log_filter.clone();
+            runtime::spawn(async move {
+                let origin = caller(&conn, &msg).await;
+                let _ = conn.send(method_call(&attempts, &commands, &log_filter, origin, &msg));
+            });
+            true
+        }),
+    );
+    info!("D-Bus control interface available as {}", BUSNAME);
+    if log_enabled!(log::Level::Debug) {
+        log_match_rules(&conn).await;
+    }
+    Ok(())
+}
+
+/// Logs the match rules the bus daemon holds for this connection
+async fn log_match_rules(conn: &Arc<DbusConnection>) {
+    let p = nonblock::Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus", conn.clone());
+    let unique_name = conn.unique_name().to_string();
+    match p.get_all_match_rules().await {
+        Ok(mut rules) => {
+            let rules = rules.remove(&unique_name).unwrap_or_default();
+            debug!("{} match rules of {}: {:?}", rules.len(), unique_name, rules);
+        },
+        Err(e) => debug!("Failed to get the match rules: {}", e),
+    }
+}
+
+fn error(msg: &Message, name: &'static str, text: String) -> Message {
+    msg.error(&name.into(), &CString::new(text).unwrap_or_default())
+}
+
+/// Returns the unix user and process id of a "GetConnectionCredentials" reply
+fn credentials(map: &HashMap<String, Variant<Box<dyn RefArg + 'static>>>) -> (Option<u32>, Option<u32>) {
+    let get = |key| map.get(key).and_then(|v| v.0.as_u64()).map(|v| v as u32);
+    (get("UnixUserID"), get("ProcessID"))
+}
+
+/// The origin of a method call. The unix user and process id of the sender are asked from the bus daemon.
+/// Bus daemons without "GetConnectionCredentials" are asked for the user id only.
+async fn caller(conn: &Arc<DbusConnection>, msg: &Message) -> Origin {
+    let sender = msg.sender().map(|s| s.to_string());
+    let mut origin = Origin::new(Surface::Dbus, sender.clone());
+    if let Some(sender) = sender {
+        let p = nonblock::Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus", conn.clone());
+        // The credentials dictionary is not thread safe (RefArg). Only keep the ids.
+        match p.get_connection_credentials(&sender).and_then(|m| Ok(credentials(&m))).await {
+            Ok((uid, pid)) => {
+                origin.uid = uid;
+                origin.pid = pid;
+            },
+            Err(e) => {
+                info!("Failed to get the credentials of {}: {}", sender, e);
+                match p.get_connection_unix_user(&sender).await {
+                    Ok(uid) => origin.uid = Some(uid),
+                    Err(e) => warn!("Failed to resolve the unix user of {}: {}", sender, e),
+                }
+            },
+        }
+    }
+    origin
+}
+
+fn submit(commands: &CommandSender, command: Command, origin: Origin, msg: &Message) -> Message {
+    match commands.submit(command, origin) {
+        Ok(id) => msg.method_return().append1(id),
+        Err(CaptivePortalError::NotAuthorized(e)) => error(msg, "org.freedesktop.DBus.Error.AccessDenied", e),
+        Err(e) => error(msg, "org.freedesktop.DBus.Error.Failed", e.to_string()),
+    }
+}
+
+/// Returns the reply for the given method call
+fn method_call(
+    attempts: &ConnectAttemptsSync,
+    commands: &CommandSender,
+    log_filter: &LogFilter,
+    origin: Origin,
+    msg: &Message,
+) -> Message {
+    match msg.member().as_ref().map(|m| &**m) {
+        Some("CancelConnect") => match msg.read1::<u64>() {
+            Ok(attempt) => {
+                if let Err(CaptivePortalError::NotAuthorized(e)) = commands.authorize(&origin, Action::Connect) {
+                    return error(msg, "org.freedesktop.DBus.Error.AccessDenied", e);
+                }
+                let cancelled = attempts.lock().expect("attempts mutex lock").cancel(attempt);
+                commands.audit(&origin, format_args!("Cancel connection attempt {}: {}", attempt, cancelled));
+                msg.method_return().append1(cancelled)
+            },
+            Err(e) => error(msg, "org.freedesktop.DBus.Error.InvalidArgs", e.to_string()),
+        },
+        Some("Forget") => match msg
+            .read1::<&str>()
+            .map(SSID::from)
+            .or_else(|_| msg.read1::<Vec<u8>>().map(SSID::from))
+        {
+            Ok(ssid) => submit(commands, Command::Forget(ssid), origin, msg),
+            Err(e) => error(msg, "org.freedesktop.DBus.Error.InvalidArgs", e.to_string()),
+        },
+        Some("RestartHotspot") => submit(commands, Command::RestartHotspot, origin, msg),
+        Some("RestartWifi") => submit(commands, Command::RestartWifi, origin, msg),
+        Some("Reboot") => submit(commands, Command::Reboot, origin, msg),
+        Some("SetUpdateNotice") => match msg.read2::<&str, &str>() {
+            Ok(("", _)) => submit(commands, Command::SetUpdateNotice(None), origin, msg),
+            Ok((state, message)) => match state.parse() {
+                Ok(state) => {
+                    let notice = UpdateNotice {
+                        state,
+                        message: Some(message.to_owned()).filter(|m| !m.is_empty()),
+                    };
+                    submit(commands, Command::SetUpdateNotice(Some(notice)), origin, msg)
+                },
+                Err(e) => error(msg, "org.freedesktop.DBus.Error.InvalidArgs", e),
+            },
+            Err(e) => error(msg, "org.freedesktop.DBus.Error.InvalidArgs", e.to_string()),
+        },
+        Some("NetworkEvent") => match msg.read3::<&str, &str, &str>() {
+            Ok((interface, action, connection)) => {
+                let event = DispatcherEvent::new(interface, action, connection);
+                submit(commands, Command::NetworkEvent(event), origin, msg)
+            },
+            Err(e) => error(msg, "org.freedesktop.DBus.Error.InvalidArgs", e.to_string()),
+        },
+        Some("SetLogFilter") => match msg.read2::<&str, u64>() {
+            Ok((filter, minutes)) => {
+                if let Err(CaptivePortalError::NotAuthorized(e)) = commands.authorize(&origin, Action::LogFilter) {
+                    return error(msg, "org.freedesktop.DBus.Error.AccessDenied", e);
+                }
+                let duration = match log_filter::duration(minutes) {
+                    Ok(duration) => Some(duration).filter(|_| minutes > 0),
+                    Err(e) => return error(msg, "org.freedesktop.DBus.Error.InvalidArgs", e.to_string()),
+                };
+                match filter {
+                    "" => log_filter.reset(),
+                    filter => {
+                        if let Err(e) = log_filter.set(filter, duration) {
+                            return error(msg, "org.freedesktop.DBus.Error.InvalidArgs", e.to_string());
+                        }
+                    },
+                }
+                commands.audit(&origin, format_args!("Log filter {:?} for {} minutes", filter, minutes));
+                msg.method_return().append1(log_filter.status().filter)
+            },
+            Err(e) => error(msg, "org.freedesktop.DBus.Error.InvalidArgs", e.to_string()),
+        },
+        member => error(
+            msg,
+            "org.freedesktop.DBus.Error.UnknownMethod",
+            format!("Unknown method {:?}", member),
+        ),
+    }
+}
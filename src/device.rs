@@ -0,0 +1,226 @@
+//! # Device identity
+//! Companion apps configure a device over the open setup network. To verify that they talk to the right
+//! physical device, "/api/device" returns the model, serial number and firmware version.
+//!
+//! The values are configured or read from the device tree ("/proc/device-tree"), the DMI tables
+//! ("/sys/class/dmi/id") and "/etc/os-release".
+//!
+//! If a sign command is configured, the identity and a nonce of the app are signed with a device key.
+//! The command gets the signed data on stdin and prints the signature, for example
+//! `openssl dgst -sha256 -sign /etc/device.key | base64 -w0`. The key never leaves the command,
+//! so it can be kept in a secure element or TPM.
+//!
+//! Any client of the setup network may ask for a signature. Signatures of recent nonces are cached and at most
+//! [`MAX_SIGNATURES_PER_MINUTE`] sign commands are started, see [`Signatures`].
+
+use crate::runtime;
+use crate::CaptivePortalError;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// The maximum length of a nonce
+pub const MAX_NONCE_LEN: usize = 64;
+
+/// The sign command is killed if it has not finished by then
+const SIGN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// At most this many sign commands are started within a minute
+pub const MAX_SIGNATURES_PER_MINUTE: usize = 10;
+
+/// The number of signatures that are answered from the cache
+const CACHED_SIGNATURES: usize = 16;
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct DeviceIdentity {
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub firmware: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SignedData<'a> {
+    #[serde(flatten)]
+    identity: &'a DeviceIdentity,
+    nonce: &'a str,
+}
+
+/// Reads a value of the device tree or DMI tables. Device tree strings are zero terminated.
+fn read_value(path: &str) -> Option<String> {
+    let value = std::fs::read_to_string(path).ok()?;
+    Some(value.trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_owned()).filter(|v| !v.is_empty())
+}
+
+/// Returns the VERSION_ID, or VERSION, of the given "/etc/os-release" content
+pub fn parse_os_release(content: &str) -> Option<String> {
+    let value = |key: &str| {
+        content
+            .lines()
+            .filter_map(|line| {
+                let mut key_value = line.splitn(2, '=');
+                Some((key_value.next()?.trim(), key_value.next()?))
+            })
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.trim().trim_matches(|c: char| c == '"' || c == '\'').to_owned())
+            .filter(|v| !v.is_empty())
+    };
+    value("VERSION_ID").or_else(|| value("VERSION"))
+}
+
+impl DeviceIdentity {
+    /// Configured values are used first, the system is asked for the others
+    pub fn detect(model: Option<String>, serial: Option<String>, firmware: Option<String>) -> Self {
+        DeviceIdentity {
+            model: model
+                .or_else(|| read_value("/proc/device-tree/model"))
+                .or_else(|| read_value("/sys/class/dmi/id/product_name")),
+            serial: serial
+                .or_else(|| read_value("/proc/device-tree/serial-number"))
+                .or_else(|| read_value("/sys/class/dmi/id/product_serial")),
+            firmware: firmware.or_else(|| {
+                std::fs::read_to_string("/etc/os-release")
+                    .ok()
+                    .and_then(|c| parse_os_release(&c))
+            }),
+        }
+    }
+
+    /// The data that is signed: The identity and the nonce as compact json, in this field order.
+    /// Apps verify the signature over the "signed" field of the response.
+    pub fn signed_data(&self, nonce: &str) -> Result<String, CaptivePortalError> {
+        Ok(serde_json::to_string(&SignedData { identity: self, nonce })?)
+    }
+}
+
+/// A nonce must be 1 to [`MAX_NONCE_LEN`] characters of a-z, A-Z, 0-9, "-" and "_"
+pub fn valid_nonce(nonce: &str) -> bool {
+    !nonce.is_empty()
+        && nonce.len() <= MAX_NONCE_LEN
+        && nonce.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Recent signatures and the start times of the sign commands of the last minute
+#[derive(Default)]
+pub struct Signatures {
+    /// The signed data and its signature, the most recent last
+    cache: VecDeque<(String, String)>,
+    started: VecDeque<Instant>,
+}
+
+impl Signatures {
+    /// The signature of the given data, if it has been signed recently
+    pub fn cached(&self, signed: &str) -> Option<String> {
+        self.cache.iter().find(|(data, _)| data == signed).map(|(_, signature)| signature.clone())
+    }
+
+    /// Returns true and counts the start if another sign command may be started at the given time
+    pub fn start(&mut self, now: Instant) -> bool {
+        while let Some(started) = self.started.front() {
+            match now.duration_since(*started) >= Duration::from_secs(60) {
+                true => self.started.pop_front(),
+                false => break,
+            };
+        }
+        if self.started.len() >= MAX_SIGNATURES_PER_MINUTE {
+            return false;
+        }
+        self.started.push_back(now);
+        true
+    }
+
+    pub fn insert(&mut self, signed: String, signature: String) {
+        if self.cache.len() >= CACHED_SIGNATURES {
+            self.cache.pop_front();
+        }
+        self.cache.push_back((signed, signature));
+    }
+}
+
+/// Signs the data with the given shell command and returns the signature it printed.
+/// The command is killed after [`SIGN_TIMEOUT`].
+pub async fn sign(command: &str, data: &str) -> Result<String, CaptivePortalError> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to start the device sign command"))?;
+    let stdin = child.stdin.take();
+    let output = runtime::timeout(SIGN_TIMEOUT, async move {
+        if let Some(mut stdin) = stdin {
+            stdin
+                .write_all(data.as_bytes())
+                .await
+                .map_err(|e| CaptivePortalError::IO(e, "Failed to write to the device sign command"))?;
+        }
+        child
+            .wait_with_output()
+            .await
+            .map_err(|e| CaptivePortalError::IO(e, "Failed to read the device signature"))
+    })
+    .await
+    .ok_or_else(|| CaptivePortalError::Generic("The device sign command timed out".to_owned()))??;
+    if !output.status.success() {
+        return Err(CaptivePortalError::Generic(format!(
+            "The device sign command failed: {}",
+            output.status
+        )));
+    }
+    let signature = String::from_utf8(output.stdout)?.trim().to_owned();
+    match signature.is_empty() {
+        true => Err(CaptivePortalError::Generic(
+            "The device sign command printed no signature".to_owned(),
+        )),
+        false => Ok(signature),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn identity() {
+        let os_release = "NAME=\"Raspbian GNU/Linux\"\nVERSION_ID=\"10\"\nVERSION=\"10 (buster)\"\n";
+        assert_eq!(parse_os_release(os_release), Some("10".to_owned()));
+        assert_eq!(parse_os_release("VERSION='2.1 (stable)'"), Some("2.1 (stable)".to_owned()));
+        assert_eq!(parse_os_release("NAME=x"), None);
+
+        assert!(valid_nonce("a1-B_"));
+        assert!(!valid_nonce(""));
+        assert!(!valid_nonce("a\"b"));
+
+        let identity = DeviceIdentity {
+            model: Some("Pi 4".to_owned()),
+            serial: None,
+            firmware: Some("2.1".to_owned()),
+        };
+        let data = identity.signed_data("n1").unwrap();
+        assert_eq!(data, r#"{"model":"Pi 4","serial":null,"firmware":"2.1","nonce":"n1"}"#);
+        assert_eq!(sign("tr a-z A-Z", &data).await.unwrap(), data.to_uppercase());
+        assert!(sign("true", &data).await.is_err());
+    }
+
+    #[test]
+    fn signatures() {
+        let mut signatures = Signatures::default();
+        let now = Instant::now();
+        for _ in 0..MAX_SIGNATURES_PER_MINUTE {
+            assert!(signatures.start(now));
+        }
+        assert!(!signatures.start(now + Duration::from_secs(59)));
+        assert!(signatures.start(now + Duration::from_secs(60)));
+
+        for i in 0..=CACHED_SIGNATURES {
+            signatures.insert(format!("data{}", i), format!("signature{}", i));
+        }
+        assert_eq!(signatures.cached("data0"), None);
+        assert_eq!(signatures.cached("data1").as_deref(), Some("signature1"));
+    }
+}
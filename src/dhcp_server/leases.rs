@@ -0,0 +1,109 @@
+//! Persisted dhcp leases.
+//!
+//! Leases are stored as json with an absolute expiry timestamp (seconds since the unix epoch),
+//! because an [`Instant`] has no meaning outside of the current process. Expired leases are not stored.
+
+use super::super::CaptivePortalError;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The lease table of the dhcp server: IP (big endian) -> (Mac address, expiry)
+pub type Leases = HashMap<u32, ([u8; 6], Instant)>;
+
+/// A persisted lease
+#[derive(Serialize, Deserialize)]
+pub struct LeaseEntry {
+    ip: Ipv4Addr,
+    mac: [u8; 6],
+    expires: u64,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Loads the leases from the given file. A non existing file results in an empty lease table.
+pub fn load(path: &Path) -> Result<Leases, CaptivePortalError> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Leases::new()),
+        Err(e) => return Err(CaptivePortalError::IO(e, "Failed to read dhcp leases")),
+    };
+    let entries: Vec<LeaseEntry> = serde_json::from_slice(&data)?;
+
+    let now = Instant::now();
+    let now_secs = unix_now();
+    Ok(entries
+        .into_iter()
+        .filter(|entry| entry.expires > now_secs)
+        .map(|entry| {
+            let expires = now + Duration::from_secs(entry.expires - now_secs);
+            (u32::from(entry.ip), (entry.mac, expires))
+        })
+        .collect())
+}
+
+/// Returns all not yet expired leases in their persisted form.
+pub fn entries(leases: &Leases) -> Vec<LeaseEntry> {
+    let now = Instant::now();
+    let now_secs = unix_now();
+    leases
+        .iter()
+        .filter(|(_, (_, expires))| *expires > now)
+        .map(|(ip, (mac, expires))| LeaseEntry {
+            ip: Ipv4Addr::from(*ip),
+            mac: *mac,
+            expires: now_secs + expires.duration_since(now).as_secs(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dhcp_leases.json");
+        assert!(load(&path).unwrap().is_empty());
+
+        let now = Instant::now();
+        let mut leases = Leases::new();
+        let phone = u32::from(Ipv4Addr::new(192, 168, 42, 10));
+        leases.insert(phone, ([2, 0, 0, 0, 0, 1], now + Duration::from_secs(3600)));
+        // Expired leases are not stored
+        leases.insert(u32::from(Ipv4Addr::new(192, 168, 42, 11)), ([2, 0, 0, 0, 0, 2], now));
+        std::fs::write(&path, serde_json::to_vec(&entries(&leases)).unwrap()).unwrap();
+
+        let restored = load(&path).unwrap();
+        assert_eq!(restored.len(), 1);
+        let (mac, expires) = restored[&phone];
+        assert_eq!(mac, [2, 0, 0, 0, 0, 1]);
+        let remaining = expires.duration_since(Instant::now()).as_secs();
+        assert!(remaining > 3590 && remaining <= 3600, "{}", remaining);
+    }
+
+    #[test]
+    fn expired_on_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dhcp_leases.json");
+        let data = format!(
+            r#"[{{"ip":"192.168.42.10","mac":[2,0,0,0,0,1],"expires":{}}},
+                {{"ip":"192.168.42.11","mac":[2,0,0,0,0,2],"expires":1577836800}}]"#,
+            unix_now() + 600
+        );
+        std::fs::write(&path, data).unwrap();
+
+        let restored = load(&path).unwrap();
+        assert_eq!(restored.len(), 1);
+        assert!(restored.contains_key(&u32::from(Ipv4Addr::new(192, 168, 42, 10))));
+    }
+}
@@ -1,6 +1,7 @@
 //! An async dhcp server implementation for a given gateway address. This is a very
 //! rudimentary implementation (no timeouts or lease refreshes), with a fixed /24 subnet.
 //! Client request IP addresses are considered.
+pub mod leases;
 pub mod options;
 pub mod packet;
 
@@ -10,6 +11,11 @@ use options::{DhcpOption, MessageType};
 use packet::*;
 use std::collections::HashMap;
 use std::ops::Add;
+use std::path::PathBuf;
+use crate::mac_filter::{hw_string, MacFilterSync};
+use crate::sessions::SessionsSync;
+use crate::stats;
+use crate::state_dir::StateFile;
 use std::time::{Duration, Instant};
 
 /// Converts u32 to 4 bytes (Big endian)
@@ -33,9 +39,21 @@ const SUBNET_MASK: [u8; 4] = [255, 255, 255, 0];
 const LEASE_DURATION_SECS: u32 = 7200;
 const LEASE_NUM: u8 = 100;
 const LEASE_DURATION_BYTES: [u8; 4] = u32_bytes!(LEASE_DURATION_SECS);
+/// The client hardware address of the smoke test, see [`probe`]. Locally administered, not assigned to any client.
+const PROBE_HW: [u8; 6] = [0x02, 0, 0, 0, 0, 0];
 
 pub struct DHCPServer {
-    leases: HashMap<u32, ([u8; 6], Instant)>,
+    leases: leases::Leases,
+    /// Leases are persisted to this file, if set
+    lease_file: Option<StateFile>,
+    /// Acknowledged clients are reported to the session list, if set
+    sessions: Option<SessionsSync>,
+    /// The maximum number of clients with an unexpired lease, if set
+    max_clients: Option<usize>,
+    /// Clients that are rejected by the filter get no lease
+    mac_filter: MacFilterSync,
+    /// The socket is bound to this network interface, if set
+    interface: Option<String>,
     last_lease: u8,
     lease_duration: Duration,
     exit_receiver: tokio::sync::oneshot::Receiver<()>,
@@ -69,6 +87,11 @@ impl DHCPServer {
                 server_ip_octets: server_addr.ip().octets(),
                 exit_receiver,
                 leases: HashMap::new(),
+                lease_file: None,
+                sessions: None,
+                max_clients: None,
+                mac_filter: MacFilterSync::default(),
+                interface: None,
                 last_lease: 0,
                 lease_duration: Duration::new(LEASE_DURATION_SECS as u64, 0),
                 dns_ips,
@@ -78,6 +101,70 @@ impl DHCPServer {
         )
     }
 
+    /// Persist the lease table to the given file. Leases that have not yet expired are restored
+    /// from that file if it exists.
+    ///
+    /// Lease changes are written at most once per flush interval and when the server stops.
+    pub fn set_lease_file(&mut self, path: PathBuf, flush_interval: Duration) -> Result<(), super::CaptivePortalError> {
+        self.leases = leases::load(&path)?;
+        info!("Restored {} dhcp leases from {}", self.leases.len(), path.display());
+        self.lease_file = Some(StateFile::new(path, flush_interval));
+        Ok(())
+    }
+
+    /// Report the host name and dhcp fingerprint of acknowledged clients to the given session list.
+    pub fn set_sessions(&mut self, sessions: SessionsSync) {
+        self.sessions = Some(sessions);
+    }
+
+    /// Offer no lease to further clients if the given number of clients has an unexpired lease.
+    pub fn set_max_clients(&mut self, max_clients: usize) {
+        self.max_clients = Some(max_clients);
+    }
+
+    /// Offer no lease to clients that are rejected by the given filter, see [`crate::mac_filter`]
+    pub fn set_mac_filter(&mut self, mac_filter: MacFilterSync) {
+        self.mac_filter = mac_filter;
+    }
+
+    /// Only serve clients of the given network interface. Necessary if another interface of the device has a
+    /// default route, like the station interface of the concurrent access point and station mode.
+    pub fn set_interface(&mut self, interface_name: String) {
+        self.interface = Some(interface_name);
+    }
+
+    /// True if the client may use the hotspot
+    fn permitted(&self, chaddr: &[u8; 6]) -> bool {
+        self.mac_filter.read().expect("mac filter lock").permits(chaddr)
+    }
+
+    /// True for the discover of the smoke test, see [`probe`]. It is sent from the server address. Clients that
+    /// use the same hardware address are not exempted from the mac filter and the client limit.
+    fn self_test(&self, chaddr: &[u8; 6], src: &SocketAddr) -> bool {
+        *chaddr == PROBE_HW && src.ip() == IpAddr::V4(*self.server_addr.ip())
+    }
+
+    /// True if the client limit is reached and the given client has no unexpired lease
+    fn client_limit_reached(&self, chaddr: &[u8; 6]) -> bool {
+        let max_clients = match self.max_clients {
+            Some(max_clients) => max_clients,
+            _ => return false,
+        };
+        let now = Instant::now();
+        if self.leases.values().any(|(hw, expiry)| hw == chaddr && *expiry > now) {
+            return false;
+        }
+        self.leases.values().filter(|(_, expiry)| *expiry > now).count() >= max_clients
+    }
+
+    /// Stores the lease table, see [`StateFile::store`].
+    fn store_leases(&mut self, force: bool) {
+        stats::record(stats::Collection::DhcpLeases, self.leases.len());
+        if let Some(lease_file) = self.lease_file.as_mut() {
+            lease_file.store(&leases::entries(&self.leases), force);
+        }
+    }
+
     pub async fn run(&mut self) -> Result<(), super::CaptivePortalError> {
         let socket = self.bind().await?;
         Ok(self.receive_loop(socket).await?)
@@ -88,6 +175,10 @@ impl DHCPServer {
         socket
             .set_broadcast(true)
             .expect("Broadcast flag on udpsocket for dhcp server");
+        if let Some(interface_name) = self.interface.as_ref() {
+            super::utils::bind_to_device(&socket, interface_name)
+                .map_err(|e| super::CaptivePortalError::IO(e, "Failed to bind the dhcp server to the interface"))?;
+        }
         self.server_addr
             .set_port(socket.local_addr().expect("Local addr").port());
 
@@ -135,6 +226,7 @@ impl DHCPServer {
             }
         }
 
+        self.store_leases(true);
         info!("Stopped dhcp server on {}", &self.server_addr);
         Ok(())
     }
@@ -184,6 +276,15 @@ impl DHCPServer {
         sender: &mut Sender,
         socket: &mut tokio::net::UdpSocket,
     ) -> Result<usize, std::io::Error> {
+        let self_test = self.self_test(&in_packet.chaddr, &sender.src);
+        if !self_test && !self.permitted(&in_packet.chaddr) {
+            info!("No dhcp offer for {}: Rejected by the mac filter", hw_string(&in_packet.chaddr));
+            return Ok(0);
+        }
+        if !self_test && self.client_limit_reached(&in_packet.chaddr) {
+            info!("Client limit reached. No dhcp offer for {}", hw_string(&in_packet.chaddr));
+            return Ok(0);
+        }
         // Prefer client's choice if available
         let ip = in_packet.option(options::REQUESTED_IP_ADDRESS).and_then(|r| {
             if r.len() == 4 {
@@ -246,8 +347,9 @@ impl DHCPServer {
         sender: &mut Sender,
         socket: &mut tokio::net::UdpSocket,
     ) -> Result<usize, std::io::Error> {
-        // Ignore requests to alternative DHCP server
-        if !self.for_this_server(&in_packet) {
+        // Ignore requests to alternative DHCP server. The smoke test only awaits an offer, its hardware address
+        // never gets a lease.
+        if !self.for_this_server(&in_packet) || in_packet.chaddr == PROBE_HW {
             return Ok(0);
         }
         let req_ip = match in_packet.option(options::REQUESTED_IP_ADDRESS) {
@@ -260,6 +362,28 @@ impl DHCPServer {
                 }
             },
         };
+        if !self.permitted(&in_packet.chaddr) {
+            return reply(
+                options::MessageType::Nak,
+                nak_options(b"Rejected by the mac filter"),
+                in_packet,
+                [0, 0, 0, 0],
+                sender,
+                socket,
+            )
+            .await;
+        }
+        if self.client_limit_reached(&in_packet.chaddr) {
+            return reply(
+                options::MessageType::Nak,
+                nak_options(b"Client limit reached"),
+                in_packet,
+                [0, 0, 0, 0],
+                sender,
+                socket,
+            )
+            .await;
+        }
         if !self.available(&in_packet.chaddr, &req_ip) {
             return reply(
                 options::MessageType::Nak,
@@ -277,7 +401,17 @@ impl DHCPServer {
                 (in_packet.chaddr, Instant::now().add(self.lease_duration)),
             );
         }
+        self.store_leases(false);
         let request_options = in_packet.option(options::PARAMETER_REQUEST_LIST).unwrap_or(&[]);
+        if let Some(sessions) = self.sessions.as_ref() {
+            sessions.lock().expect("sessions mutex lock").dhcp_seen(
+                Ipv4Addr::from(req_ip),
+                &in_packet.chaddr,
+                in_packet.option(options::HOST_NAME),
+                request_options,
+                in_packet.option(options::VENDOR_CLASS_IDENTIFIER),
+            );
+        }
         reply(
             options::MessageType::Ack,
             lease_options(&self.server_ip_octets, &self.dns_ips, request_options),
@@ -296,10 +430,42 @@ impl DHCPServer {
         }
         if let Some(ip) = self.current_lease(&in_packet.chaddr) {
             self.leases.remove(&ip);
+            self.store_leases(false);
         }
     }
 }
 
+/// Sends a DHCP discover from the server address to the server and waits for the offer. Used by the smoke test
+/// after the hotspot is up. A discover does not reserve a lease.
+pub async fn probe(server_addr: SocketAddrV4) -> Result<(), super::CaptivePortalError> {
+    let mut socket = tokio::net::UdpSocket::bind((*server_addr.ip(), 0)).await?;
+    let message_type = [MessageType::Discover as u8];
+    let p = Packet {
+        reply: false,
+        hops: 0,
+        xid: [0x57, 0x43, 0x53, 0x54],
+        secs: 0,
+        broadcast: false,
+        ciaddr: [0, 0, 0, 0],
+        yiaddr: [0, 0, 0, 0],
+        siaddr: [0, 0, 0, 0],
+        giaddr: [0, 0, 0, 0],
+        chaddr: PROBE_HW,
+        options: vec![DhcpOption {
+            code: options::DHCP_MESSAGE_TYPE,
+            data: &message_type,
+        }],
+    };
+    let mut buffer = [0u8; 1500];
+    let len = p.encode(&mut buffer).len();
+    socket.send_to(&buffer[..len], SocketAddr::V4(server_addr)).await?;
+    let (size, _) = socket.recv_from(&mut buffer).await?;
+    match decode(&buffer[..size])?.message_type() {
+        Ok(MessageType::Offer) => Ok(()),
+        _ => Err(super::CaptivePortalError::DhcpError("Expected a dhcp offer")),
+    }
+}
+
 fn lease_options<'a>(router_ip: &'a [u8; 4], dns_ips: &'a [u8; 8], options: &[u8]) -> Vec<DhcpOption<'a>> {
     let mut vec = Vec::new();
 
@@ -391,13 +557,15 @@ async fn reply(
 #[cfg(test)]
 mod tests {
     use super::super::CaptivePortalError;
-    use super::{options::*, packet::decode, DHCPServer, DhcpOption, Packet};
+    use super::{options::*, packet::decode, DHCPServer, DhcpOption, Packet, PROBE_HW};
     use futures_util::future::select;
     use futures_util::future::Either;
     use futures_util::future::try_join;
     use pin_utils::pin_mut;
+    use crate::mac_filter::MacFilter;
     use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
-    use std::time::Duration;
+    use std::sync::{Arc, RwLock};
+    use std::time::{Duration, Instant};
     use tokio::time::delay_for;
     use tokio::net::UdpSocket;
 
@@ -550,4 +718,38 @@ mod tests {
             _ => {},
         };
     }
+
+    #[test]
+    fn self_test_exemption() {
+        let server_addr = SocketAddrV4::new(Ipv4Addr::new(192, 168, 42, 1), 67);
+        let (mut dhcp_server, _exit_handler) = DHCPServer::new(server_addr);
+        let mut filter = MacFilter::default();
+        filter.allow.push("AA:BB:CC:00:11:22".to_owned());
+        dhcp_server.set_mac_filter(Arc::new(RwLock::new(filter)));
+        assert!(!dhcp_server.permitted(&PROBE_HW));
+
+        let gateway = SocketAddr::V4(SocketAddrV4::new(*server_addr.ip(), 4242));
+        let client = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 168, 42, 2), 68));
+        assert!(dhcp_server.self_test(&PROBE_HW, &gateway));
+        assert!(!dhcp_server.self_test(&PROBE_HW, &client));
+        assert!(!dhcp_server.self_test(&[1; 6], &gateway));
+    }
+
+    #[test]
+    fn client_limit() {
+        let (mut dhcp_server, _exit_handler) = DHCPServer::new(SocketAddrV4::new(Ipv4Addr::new(192, 168, 42, 1), 67));
+        assert!(!dhcp_server.client_limit_reached(&[1; 6]));
+        dhcp_server.set_max_clients(1);
+        assert!(!dhcp_server.client_limit_reached(&[1; 6]));
+
+        let expiry = Instant::now() + Duration::from_secs(60);
+        dhcp_server.leases.insert(bytes_u32!([192, 168, 42, 2]), ([1; 6], expiry));
+        assert!(!dhcp_server.client_limit_reached(&[1; 6]));
+        assert!(dhcp_server.client_limit_reached(&[2; 6]));
+        assert!(dhcp_server.client_limit_reached(&PROBE_HW));
+
+        // Expired leases do not count
+        dhcp_server.leases.insert(bytes_u32!([192, 168, 42, 2]), ([1; 6], Instant::now()));
+        assert!(!dhcp_server.client_limit_reached(&[2; 6]));
+    }
 }
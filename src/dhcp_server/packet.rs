@@ -31,6 +31,10 @@ pub struct Packet<'a> {
 
 /// Parses Packet from byte array
 pub fn decode(p: &[u8]) -> Result<Packet, CaptivePortalError> {
+    // Fixed header fields and the magic cookie occupy the first 240 bytes
+    if p.len() < 240 {
+        return Err(CaptivePortalError::DhcpError("Packet too short"));
+    }
     if p[236..240] != COOKIE {
         return Err(CaptivePortalError::DhcpError("Invalid Cookie"));
     }
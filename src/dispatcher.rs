@@ -0,0 +1,81 @@
+//! # NetworkManager dispatcher events
+//! NetworkManager runs the scripts of "/etc/NetworkManager/dispatcher.d/" when a connection goes up or down.
+//! The script `data/90-wifi-captive` forwards those events to the D-Bus control interface
+//! (`NetworkEvent`, see [`crate::dbus_service`]). It is installed with `--install-dispatcher`.
+//!
+//! Other dispatcher scripts are not affected and keep reacting to connectivity changes.
+//! The setup hotspot is a NetworkManager connection as well, scripts can recognise it by its
+//! uuid (`CONNECTION_UUID`), like the forwarding script does.
+//!
+//! A connection that goes up on the wifi interface while the portal is running has been established
+//! outside of the control of this service, for example with nmcli. The portal is closed then.
+
+use crate::CaptivePortalError;
+use std::fmt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+pub const DISPATCHER_DIR: &str = "/etc/NetworkManager/dispatcher.d";
+const SCRIPT_NAME: &str = "90-wifi-captive";
+const SCRIPT: &str = include_str!("../data/90-wifi-captive");
+
+/// A connection event of the NetworkManager dispatcher
+#[derive(Clone, Debug, PartialEq)]
+pub struct DispatcherEvent {
+    pub interface: String,
+    /// The dispatcher action, like "up" or "down"
+    pub action: String,
+    /// The connection id. None for events without a connection.
+    pub connection: Option<String>,
+}
+
+impl DispatcherEvent {
+    pub fn new(interface: &str, action: &str, connection: &str) -> Self {
+        DispatcherEvent {
+            interface: interface.to_owned(),
+            action: action.to_owned(),
+            connection: Some(connection.to_owned()).filter(|c| !c.is_empty()),
+        }
+    }
+
+    /// A connection went up on the given interface
+    pub fn is_connection_up(&self, interface: &str) -> bool {
+        self.action == "up" && self.interface == interface && self.connection.is_some()
+    }
+}
+
+impl fmt::Display for DispatcherEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} on {}", self.action, self.interface)?;
+        match &self.connection {
+            Some(connection) => write!(f, " ({})", connection),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes the forwarding script to the given dispatcher directory and returns its path
+pub fn install(dir: &Path) -> Result<PathBuf, CaptivePortalError> {
+    let path = dir.join(SCRIPT_NAME);
+    crate::state_dir::write_atomic(&path, SCRIPT.as_bytes())?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to make the dispatcher script executable"))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_up() {
+        let event = DispatcherEvent::new("wlan0", "up", "Home");
+        assert!(event.is_connection_up("wlan0"));
+        assert!(!event.is_connection_up("eth0"));
+        assert_eq!(event.to_string(), "up on wlan0 (Home)");
+
+        let event = DispatcherEvent::new("wlan0", "down", "Home");
+        assert!(!event.is_connection_up("wlan0"));
+        assert!(DispatcherEvent::new("wlan0", "up", "").connection.is_none());
+    }
+}
@@ -7,6 +7,10 @@
 use std::io::Result;
 use std::io::{Error, ErrorKind};
 
+/// A qname may contain compression pointers. Limit the amount of followed pointers,
+/// otherwise a crafted packet with a pointer cycle would keep the reader busy forever.
+const MAX_QNAME_JUMPS: usize = 5;
+
 pub struct BytePacketBuffer {
     pub buf: [u8; 512],
     pub pos: usize,
@@ -93,6 +97,7 @@ impl BytePacketBuffer {
     pub fn read_qname(&mut self, outstr: &mut String) -> Result<()> {
         let mut pos = self.pos();
         let mut jumped = false;
+        let mut jumps = 0;
 
         let mut delim = "";
         loop {
@@ -109,6 +114,11 @@ impl BytePacketBuffer {
                     self.seek(pos + 2)?;
                 }
 
+                jumps += 1;
+                if jumps > MAX_QNAME_JUMPS {
+                    return Err(Error::new(ErrorKind::InvalidData, "Too many qname compression jumps"));
+                }
+
                 let b2 = self.get(pos + 1)? as u16;
                 let offset = (((len as u16) ^ 0xC0) << 8) | b2;
                 pos = offset as usize;
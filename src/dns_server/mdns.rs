@@ -0,0 +1,168 @@
+//! # mDNS responder
+//! Answers multicast dns queries (RFC 6762) for one host name in the ".local" domain with one address.
+//! Clients with a link-local address have no dns server, they resolve ".local" names via multicast instead.
+//! See [`crate::link_local`].
+
+use super::byte_buffer::BytePacketBuffer;
+use super::dns_header::ResultCode;
+use super::dns_query::QueryType;
+use super::dns_record::DnsRecord;
+use super::DnsPacket;
+use crate::CaptivePortalError;
+
+use nix::sys::socket::{self, sockopt, AddressFamily, InetAddr, SockAddr, SockFlag, SockType};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::unix::io::FromRawFd;
+use tokio::net::UdpSocket;
+
+pub const MDNS_PORT: u16 = 5353;
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+/// Recommended TTL of host name records
+const HOST_TTL: u32 = 120;
+
+/// Answers A queries for `<name>.local` with the given address
+pub struct MdnsResponder {
+    exit_receiver: tokio::sync::oneshot::Receiver<()>,
+    /// The fully qualified host name in lower case, like "wifi-captive.local"
+    host: String,
+    address: Ipv4Addr,
+}
+
+impl MdnsResponder {
+    /// The name is given without the ".local" domain
+    pub fn new(name: &str, address: Ipv4Addr) -> (Self, tokio::sync::oneshot::Sender<()>) {
+        let (exit_handler, exit_receiver) = tokio::sync::oneshot::channel::<()>();
+        (
+            MdnsResponder {
+                exit_receiver,
+                host: format!("{}.local", name.to_lowercase()),
+                address,
+            },
+            exit_handler,
+        )
+    }
+
+    /// The host name that is answered
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub async fn run(&mut self) -> Result<(), CaptivePortalError> {
+        let mut socket = bind(self.address).map_err(|e| CaptivePortalError::IO(e, "Failed to bind the mDNS responder"))?;
+        info!("Started mDNS responder for {} on {}", self.host, self.address);
+
+        let mut req_buffer = BytePacketBuffer::new();
+        loop {
+            let future =
+                crate::utils::receive_or_exit(&mut socket, &mut self.exit_receiver, &mut req_buffer.buf).await?;
+            match future {
+                Some((size, src)) => {
+                    req_buffer.set_size(size)?;
+                    if let Ok(query) = DnsPacket::from_buffer(&mut req_buffer) {
+                        if let Some(answer) = self.answer(&query, src.port() != MDNS_PORT) {
+                            // Legacy unicast queries (not from port 5353) are answered directly
+                            let target = match src.port() {
+                                MDNS_PORT => SocketAddr::V4(SocketAddrV4::new(MDNS_GROUP, MDNS_PORT)),
+                                _ => src,
+                            };
+                            let mut res_buffer = BytePacketBuffer::new();
+                            res_buffer.reset_for_write();
+                            answer.write(&mut res_buffer)?;
+                            let len = res_buffer.pos();
+                            socket.send_to(res_buffer.get_range(0, len)?, target).await?;
+                        }
+                    }
+                },
+                // Exit signal received
+                None => break,
+            }
+        }
+
+        info!("Stopped mDNS responder for {}", self.host);
+        Ok(())
+    }
+
+    /// The response to the given query, if it asks for the host name. A legacy unicast response repeats
+    /// the query id and question.
+    fn answer(&self, query: &DnsPacket, legacy_unicast: bool) -> Option<DnsPacket> {
+        if query.header.response {
+            return None;
+        }
+        let question = query.questions.iter().find(|q| {
+            q.name.to_lowercase() == self.host && (q.qtype == QueryType::A || q.qtype == QueryType::UNKNOWN(255))
+        })?;
+        let mut packet = DnsPacket::new();
+        packet.header.response = true;
+        packet.header.authoritative_answer = true;
+        packet.header.rescode = ResultCode::NOERROR;
+        if legacy_unicast {
+            packet.header.id = query.header.id;
+            packet.questions.push(question.clone());
+        }
+        packet.answers.push(DnsRecord::A {
+            domain: question.name.clone(),
+            addr: self.address,
+            ttl: HOST_TTL,
+        });
+        Some(packet)
+    }
+}
+
+/// Binds to the mDNS port, shared with other responders like avahi, and joins the mDNS group on the interface
+/// of the given address
+fn bind(address: Ipv4Addr) -> io::Result<UdpSocket> {
+    fn to_io(e: nix::Error) -> io::Error {
+        match e.as_errno() {
+            Some(errno) => io::Error::from(errno),
+            None => io::Error::new(io::ErrorKind::Other, e.to_string()),
+        }
+    }
+    let fd = socket::socket(AddressFamily::Inet, SockType::Datagram, SockFlag::SOCK_CLOEXEC, None).map_err(to_io)?;
+    // Closes the socket if one of the following steps fails
+    let std_socket = unsafe { std::net::UdpSocket::from_raw_fd(fd) };
+    socket::setsockopt(fd, sockopt::ReuseAddr, &true).map_err(to_io)?;
+    socket::setsockopt(fd, sockopt::ReusePort, &true).map_err(to_io)?;
+    let any = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, MDNS_PORT));
+    socket::bind(fd, &SockAddr::new_inet(InetAddr::from_std(&any))).map_err(to_io)?;
+    std_socket.join_multicast_v4(&MDNS_GROUP, &address)?;
+    std_socket.set_multicast_ttl_v4(255)?;
+    std_socket.set_nonblocking(true)?;
+    UdpSocket::from_std(std_socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns_server::dns_query::DnsQuery;
+
+    fn query(name: &str, qtype: QueryType) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 42;
+        packet.header.questions = 1;
+        packet.questions.push(DnsQuery::new(name.to_owned(), qtype));
+        packet
+    }
+
+    #[test]
+    fn answers_host_name() {
+        let (responder, _exit) = MdnsResponder::new("Wifi-Captive", Ipv4Addr::new(169, 254, 10, 20));
+        assert_eq!(responder.host(), "wifi-captive.local");
+
+        let answer = responder.answer(&query("wifi-captive.local", QueryType::A), false).unwrap();
+        assert!(answer.header.response);
+        assert_eq!(answer.header.id, 0);
+        assert!(answer.questions.is_empty());
+        match &answer.answers[0] {
+            DnsRecord::A { addr, .. } => assert_eq!(*addr, Ipv4Addr::new(169, 254, 10, 20)),
+            record => panic!("Unexpected record {:?}", record),
+        }
+
+        let answer = responder.answer(&query("WIFI-CAPTIVE.local", QueryType::A), true).unwrap();
+        assert_eq!(answer.header.id, 42);
+        assert_eq!(answer.questions.len(), 1);
+
+        assert!(responder.answer(&query("printer.local", QueryType::A), false).is_none());
+        assert!(responder.answer(&query("wifi-captive.local", QueryType::AAAA), false).is_none());
+    }
+}
@@ -7,12 +7,14 @@ mod dns_header;
 mod dns_packet;
 mod dns_query;
 mod dns_record;
+pub mod mdns;
 
 use byte_buffer::BytePacketBuffer;
 use dns_header::ResultCode;
-use dns_packet::DnsPacket;
 use dns_record::DnsRecord;
 
+pub use dns_packet::DnsPacket;
+
 use super::CaptivePortalError;
 
 use std::clone::Clone;
@@ -23,6 +25,10 @@ use tokio::net::UdpSocket;
 pub struct CaptiveDnsServer {
     exit_receiver: tokio::sync::oneshot::Receiver<()>,
     server_addr: SocketAddrV4,
+    /// The socket is bound to this network interface, if set
+    interface: Option<String>,
+    /// The friendly host name of the portal, in lower case without a trailing dot
+    hostname: Option<String>,
     /// For testing: Quits the receive loop after one received packet
     #[allow(unused)]
     only_once: bool,
@@ -37,15 +43,40 @@ impl CaptiveDnsServer {
             CaptiveDnsServer {
                 server_addr,
                 exit_receiver,
+                interface: None,
+                hostname: None,
                 only_once: false,
             },
             exit_handler,
         )
     }
 
+    /// Only answer queries of the given network interface, see [`crate::dhcp_server::DHCPServer::set_interface`]
+    pub fn set_interface(&mut self, interface_name: String) {
+        self.interface = Some(interface_name);
+    }
+
+    /// Answer the portal host name, like "setup.device", authoritatively. All other names are answered with the
+    /// server address as well, but not authoritatively.
+    pub fn set_hostname(&mut self, hostname: &str) {
+        self.hostname = Some(hostname.trim_end_matches('.').to_lowercase());
+    }
+
+    /// True if the queried name is the portal host name
+    fn is_hostname(&self, name: &str) -> bool {
+        match self.hostname.as_ref() {
+            Some(hostname) => name.trim_end_matches('.').eq_ignore_ascii_case(hostname),
+            None => false,
+        }
+    }
+
     pub async fn run(&mut self) -> Result<(), CaptivePortalError> {
         let mut socket = tokio::net::UdpSocket::bind(SocketAddr::V4(self.server_addr.clone())).await?;
         socket.set_broadcast(true).expect("Set broadcast flag on udp socket");
+        if let Some(interface_name) = self.interface.as_ref() {
+            super::utils::bind_to_device(&socket, interface_name)
+                .map_err(|e| CaptivePortalError::IO(e, "Failed to bind the dns server to the interface"))?;
+        }
 
         info!("Started dns server on {}", &self.server_addr);
 
@@ -78,6 +109,43 @@ impl CaptiveDnsServer {
     }
 }
 
+/// Queries the server from the server address and checks that the answer points to the server. Used by the
+/// smoke test after the hotspot is up.
+pub async fn probe(server_addr: SocketAddrV4) -> Result<(), CaptivePortalError> {
+    let mut socket = UdpSocket::bind((*server_addr.ip(), 0)).await?;
+
+    let mut packet = DnsPacket::new();
+    packet.header.id = 4242;
+    packet.header.questions = 1;
+    packet.header.recursion_desired = true;
+    packet.questions.push(dns_query::DnsQuery::new("smoke-test.invalid".to_owned(), dns_query::QueryType::A));
+
+    let mut buffer = BytePacketBuffer::new();
+    buffer.reset_for_write();
+    packet.write(&mut buffer)?;
+    socket.send_to(&buffer.buf[0..buffer.pos()], SocketAddr::V4(server_addr)).await?;
+
+    let mut buffer = BytePacketBuffer::new();
+    let (size, _) = socket.recv_from(&mut buffer.buf).await?;
+    buffer.set_size(size)?;
+    match DnsPacket::from_buffer(&mut buffer)?.answers.first() {
+        Some(DnsRecord::A { addr, .. }) if addr == server_addr.ip() => Ok(()),
+        _ => Err(CaptivePortalError::Generic("Unexpected dns answer".to_owned())),
+    }
+}
+
+/// Decodes a raw dns request datagram.
+/// The receive loop decodes in-place, this is the entry point for the fuzz targets.
+pub fn decode_request(data: &[u8]) -> Result<DnsPacket, std::io::Error> {
+    let mut buffer = BytePacketBuffer::new();
+    if data.len() > buffer.buf.len() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Datagram too large"));
+    }
+    buffer.buf[..data.len()].copy_from_slice(data);
+    buffer.set_size(data.len())?;
+    DnsPacket::from_buffer(&mut buffer)
+}
+
 async fn handle_request(
     server: &CaptiveDnsServer,
     request: DnsPacket,
@@ -101,6 +169,7 @@ async fn handle_request(
 
         packet.questions.push(question.clone());
         packet.header.rescode = ResultCode::NOERROR;
+        packet.header.authoritative_answer = server.is_hostname(&question.name);
 
         let answer = DnsRecord::A {
             domain: question.name.clone(),
@@ -178,6 +247,16 @@ mod tests {
             .expect("Failed to execute server or lookup");
     }
 
+    #[test]
+    fn hostname() {
+        let (mut dns_server, _exit_handler) = CaptiveDnsServer::new(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 53));
+        assert!(!dns_server.is_hostname("setup.device"));
+        dns_server.set_hostname("Setup.Device");
+        assert!(dns_server.is_hostname("setup.device"));
+        assert!(dns_server.is_hostname("SETUP.device."));
+        assert!(!dns_server.is_hostname("www.setup.device"));
+    }
+
     #[tokio::test]
     async fn test_domain() {
         let timeout = delay_for(Duration::from_secs(2));
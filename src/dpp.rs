@@ -0,0 +1,312 @@
+//! # Wi-Fi Easy Connect (DPP)
+//! Routers with Easy Connect show a QR code with a DPP bootstrapping URI, like
+//! `DPP:C:81/6;M:001122334455;I:Router;K:MDkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDIgAC...;;`.
+//! The device enrolls with the router as configurator and receives the network credentials, no passphrase is typed.
+//!
+//! Enrollment is done by wpa_supplicant. NetworkManager has no DPP API, so its control socket is used
+//! ("DPP_QR_CODE", "DPP_AUTH_INIT"). wpa_supplicant must be started with a control interface directory,
+//! like `-O /run/wpa_supplicant`. The received SSID and passphrase are stored in a regular connection of
+//! the network backend. Configurations with a DPP connector only (no passphrase) are not supported.
+
+use crate::network_interface::{AccessPointCredentials, SSID};
+use crate::runtime;
+use crate::CaptivePortalError;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::net::UnixDatagram;
+
+/// The default control interface directory of wpa_supplicant
+pub const DEFAULT_CTRL_DIR: &str = "/run/wpa_supplicant";
+
+/// How long the configurator has to hand out the configuration after the enrollment started
+pub const ENROLL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A DPP bootstrapping URI, as encoded in the QR code of the configurator
+#[derive(Clone, Debug, PartialEq)]
+pub struct DppUri {
+    /// The URI as entered. Handed to wpa_supplicant unchanged.
+    uri: String,
+    /// Operating class and channel pairs the configurator listens on ("C:81/1,115/36")
+    pub channels: Vec<(u8, u16)>,
+    /// The hardware address of the configurator ("M:")
+    pub mac: Option<String>,
+    /// Free text like a model name ("I:")
+    pub info: Option<String>,
+    /// The DPP protocol version ("V:")
+    pub version: Option<u8>,
+    /// The base64 encoded public bootstrapping key ("K:")
+    pub public_key: String,
+}
+
+impl FromStr for DppUri {
+    type Err = CaptivePortalError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let uri = value.trim();
+        let invalid = |m: &str| CaptivePortalError::InvalidDppUri(m.to_owned());
+        if !uri.starts_with("DPP:") || !uri.ends_with(";;") {
+            return Err(invalid("Expected DPP:...;;"));
+        }
+        let mut parsed = DppUri {
+            uri: uri.to_owned(),
+            channels: Vec::new(),
+            mac: None,
+            info: None,
+            version: None,
+            public_key: String::new(),
+        };
+        for field in uri[4..].split(';').filter(|f| !f.is_empty()) {
+            let mut parts = field.splitn(2, ':');
+            let (name, value) = match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => (name, value),
+                _ => return Err(invalid(&format!("Malformed field {}", field))),
+            };
+            match name {
+                "C" => {
+                    for pair in value.split(',') {
+                        let mut pair = pair.splitn(2, '/');
+                        let class = pair.next().and_then(|c| c.parse().ok());
+                        let channel = pair.next().and_then(|c| c.parse().ok());
+                        match (class, channel) {
+                            (Some(class), Some(channel)) => parsed.channels.push((class, channel)),
+                            _ => return Err(invalid("Malformed channel list")),
+                        }
+                    }
+                },
+                "M" => {
+                    if value.len() != 12 || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return Err(invalid("Malformed hardware address"));
+                    }
+                    parsed.mac = Some(value.to_lowercase());
+                },
+                "I" => parsed.info = Some(value.to_owned()),
+                "V" => parsed.version = Some(value.parse().map_err(|_| invalid("Malformed version"))?),
+                "K" => {
+                    let base64 = |c: char| c.is_ascii_alphanumeric() || c == '+' || c == '/' || c == '=';
+                    if value.is_empty() || !value.chars().all(base64) {
+                        return Err(invalid("Malformed public key"));
+                    }
+                    parsed.public_key = value.to_owned();
+                },
+                // Host and supported curves are not needed by the enrollee. Unknown fields are reserved.
+                _ => {},
+            }
+        }
+        if parsed.public_key.is_empty() {
+            return Err(invalid("The public key is missing"));
+        }
+        Ok(parsed)
+    }
+}
+
+impl fmt::Display for DppUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.uri.fmt(f)
+    }
+}
+
+/// The network configuration handed out by the configurator
+#[derive(Clone, Debug, PartialEq)]
+pub struct DppConfiguration {
+    pub ssid: SSID,
+    pub credentials: AccessPointCredentials,
+}
+
+/// The configuration objects reported by wpa_supplicant so far
+#[derive(Default)]
+struct Received {
+    akm: Option<String>,
+    ssid: Option<SSID>,
+    secret: Option<String>,
+}
+
+/// Decodes the hex encoded values of the "DPP-CONFOBJ-" events
+pub(crate) fn hex_decode(value: &str) -> Option<Vec<u8>> {
+    if value.len() % 2 != 0 {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| value.get(i..i + 2).and_then(|h| u8::from_str_radix(h, 16).ok()))
+        .collect()
+}
+
+impl Received {
+    /// Applies a wpa_supplicant event (without the "<3>" level prefix). Returns the configuration if it is
+    /// complete or an error if the enrollment failed. Other events are ignored.
+    fn apply(&mut self, event: &str) -> Option<Result<DppConfiguration, CaptivePortalError>> {
+        let mut parts = event.splitn(2, ' ');
+        let (name, value) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default().trim());
+        match name {
+            "DPP-AUTH-INIT-FAILED" | "DPP-NOT-COMPATIBLE" | "DPP-CONF-FAILED" | "DPP-FAIL" => {
+                return Some(Err(CaptivePortalError::Generic(format!("DPP enrollment failed: {}", event))));
+            },
+            "DPP-CONFOBJ-AKM" => {
+                if !value.contains("psk") && !value.contains("sae") {
+                    let msg = format!("DPP configuration without passphrase ({}) is not supported", value);
+                    return Some(Err(CaptivePortalError::Generic(msg)));
+                }
+                self.akm = Some(value.to_owned());
+            },
+            "DPP-CONFOBJ-SSID" => self.ssid = hex_decode(value).map(SSID::from),
+            "DPP-CONFOBJ-PASS" => {
+                self.secret = hex_decode(value).map(|s| String::from_utf8_lossy(&s).into_owned());
+            },
+            // A raw PSK, 64 hex characters. Backends accept it as passphrase.
+            "DPP-CONFOBJ-PSK" => self.secret = Some(value.to_owned()),
+            _ => {},
+        }
+        match (&self.ssid, &self.secret) {
+            (Some(ssid), Some(passphrase)) => {
+                let passphrase = passphrase.clone();
+                let credentials = match self.akm.as_deref() {
+                    Some(akm) if akm.contains("sae") && !akm.contains("psk") => {
+                        AccessPointCredentials::Sae { passphrase }
+                    },
+                    _ => AccessPointCredentials::Wpa { passphrase },
+                };
+                Some(Ok(DppConfiguration {
+                    ssid: ssid.clone(),
+                    credentials,
+                }))
+            },
+            _ => None,
+        }
+    }
+}
+
+/// A client of the wpa_supplicant control socket of one interface. The client socket is removed on drop.
+struct ControlSocket {
+    socket: UnixDatagram,
+    local: PathBuf,
+}
+
+impl ControlSocket {
+    fn open(ctrl_dir: &Path, interface_name: &str) -> Result<Self, CaptivePortalError> {
+        let local = std::env::temp_dir().join(format!("wifi-captive-dpp-{}", std::process::id()));
+        let _ = std::fs::remove_file(&local);
+        let socket = UnixDatagram::bind(&local).map_err(|e| CaptivePortalError::IO(e, "Failed to bind"))?;
+        let control = ControlSocket { socket, local };
+        control
+            .socket
+            .connect(ctrl_dir.join(interface_name))
+            .map_err(|e| CaptivePortalError::IO(e, "Failed to open the wpa_supplicant control socket"))?;
+        Ok(control)
+    }
+
+    async fn receive(&mut self) -> Result<String, CaptivePortalError> {
+        let mut buffer = [0u8; 4096];
+        let size = self.socket.recv(&mut buffer).await?;
+        Ok(String::from_utf8_lossy(&buffer[..size]).trim_end().to_owned())
+    }
+
+    /// Sends the command and returns the reply. Events that arrive meanwhile are dropped.
+    async fn request(&mut self, command: &str) -> Result<String, CaptivePortalError> {
+        self.socket.send(command.as_bytes()).await?;
+        loop {
+            let reply = self.receive().await?;
+            if !reply.starts_with('<') {
+                return match &reply[..] {
+                    "FAIL" | "UNKNOWN COMMAND" => Err(CaptivePortalError::Generic(format!("{}: {}", command, reply))),
+                    _ => Ok(reply),
+                };
+            }
+        }
+    }
+
+    /// The next event, without the level prefix
+    async fn event(&mut self) -> Result<String, CaptivePortalError> {
+        loop {
+            let message = self.receive().await?;
+            if message.starts_with('<') {
+                return Ok(message.splitn(2, '>').nth(1).unwrap_or_default().to_owned());
+            }
+        }
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.local);
+    }
+}
+
+/// Enrolls the given interface with the configurator of the URI and returns the received configuration.
+/// The interface must be in station mode. Fails if no configuration is received within the timeout.
+pub async fn enroll(
+    ctrl_dir: &Path,
+    interface_name: &str,
+    uri: &DppUri,
+    timeout: Duration,
+) -> Result<DppConfiguration, CaptivePortalError> {
+    let mut control = ControlSocket::open(ctrl_dir, interface_name)?;
+    control.request("ATTACH").await?;
+    let peer = control.request(&format!("DPP_QR_CODE {}", uri)).await?;
+    info!("DPP enrollment with {} (bootstrap {})", uri.info.as_deref().unwrap_or("configurator"), peer);
+    let configuration = async {
+        control.request(&format!("DPP_AUTH_INIT peer={} role=enrollee", peer)).await?;
+        let mut received = Received::default();
+        loop {
+            let event = control.event().await?;
+            debug!("DPP: {}", event);
+            if let Some(result) = received.apply(&event) {
+                return result;
+            }
+        }
+    };
+    let result = runtime::timeout(timeout, configuration).await;
+    let _ = control.request(&format!("DPP_BOOTSTRAP_REMOVE {}", peer)).await;
+    let _ = control.request("DETACH").await;
+    match result {
+        Some(result) => result,
+        None => Err(CaptivePortalError::Generic(format!(
+            "No DPP configuration received within {:?}",
+            timeout
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_uri() {
+        let uri: DppUri = "DPP:C:81/1,115/36;M:5254005828E5;I:Router;V:2;K:MDkwEwYHKoZIzj0CAQYIKoZIzj0DAQcD;;"
+            .parse()
+            .unwrap();
+        assert_eq!(uri.channels, vec![(81, 1), (115, 36)]);
+        assert_eq!(uri.mac.as_deref(), Some("5254005828e5"));
+        assert_eq!(uri.info.as_deref(), Some("Router"));
+        assert_eq!(uri.version, Some(2));
+        assert_eq!(uri.public_key, "MDkwEwYHKoZIzj0CAQYIKoZIzj0DAQcD");
+        assert!(uri.to_string().starts_with("DPP:C:81/1"));
+
+        assert!("DPP:K:MDkw;;".parse::<DppUri>().is_ok());
+        assert!("DPP:I:Router;;".parse::<DppUri>().is_err());
+        assert!("DPP:M:0011;K:MDkw;;".parse::<DppUri>().is_err());
+        assert!("WIFI:S:home;T:WPA;P:secret;;".parse::<DppUri>().is_err());
+    }
+
+    #[test]
+    fn configuration_events() {
+        let mut received = Received::default();
+        assert!(received.apply("DPP-CONF-RECEIVED").is_none());
+        assert!(received.apply("DPP-CONFOBJ-AKM psk+sae").is_none());
+        assert!(received.apply("DPP-CONFOBJ-SSID 686f6d65").is_none());
+        let configuration = received.apply("DPP-CONFOBJ-PASS 736563726574313233").unwrap().unwrap();
+        assert_eq!(configuration.ssid, "home");
+        assert_eq!(
+            configuration.credentials,
+            AccessPointCredentials::Wpa {
+                passphrase: "secret123".to_owned()
+            }
+        );
+
+        let mut received = Received::default();
+        assert!(received.apply("DPP-CONFOBJ-AKM dpp").unwrap().is_err());
+        assert!(Received::default().apply("DPP-AUTH-INIT-FAILED").unwrap().is_err());
+    }
+}
@@ -3,7 +3,9 @@
 //! This crate uses one wrapping error type.
 //! Sub-modules and methods should return a specific error type whenever possible though.
 
+use crate::credential_policy::Violation;
 use crate::NetworkManagerState;
+#[cfg(feature = "hyper")]
 use hyper::http;
 use std::error;
 use std::fmt;
@@ -22,9 +24,11 @@ pub enum CaptivePortalError {
     DBus(String, String),
     /// IO Error with context
     IO(std::io::Error, &'static str),
+    #[cfg(feature = "hyper")]
     Hyper(hyper::error::Error),
     RecvError(std::sync::mpsc::RecvError),
     IwdError(&'static str),
+    UbusError(String),
 
     DhcpError(&'static str),
     HttpRoutingFailed,
@@ -32,8 +36,24 @@ pub enum CaptivePortalError {
     NotRequiredConnectivity(NetworkManagerState),
     HotspotFailed,
     NoWifiDeviceFound,
+    /// The wifi device of the given interface cannot run an access point
+    ApModeUnsupported(String),
+    /// The compiled-in network backend does not offer the named feature, see [`crate::network_backend::WifiBackend`]
+    Unsupported(&'static str),
     InvalidSharedKey(String),
     NoSharedKeyProvided,
+    /// The static IPv4 configuration of a connect request is incomplete or malformed
+    InvalidIpConfiguration(String),
+    /// A control surface is not allowed to submit a command, see [`crate::policy`]
+    NotAuthorized(String),
+    /// A request body exceeds the given size in bytes
+    PayloadTooLarge(usize),
+    /// The entered credentials are rejected by the credential policy, see [`crate::credential_policy`]
+    CredentialPolicy(Violation),
+    /// A malformed Easy Connect bootstrapping URI, see [`crate::dpp`]
+    InvalidDppUri(String),
+    /// A log filter directive or duration that is rejected, see [`crate::log_filter`]
+    InvalidLogFilter(String),
 }
 
 impl Unpin for CaptivePortalError {}
@@ -44,6 +64,7 @@ impl std::convert::From<std::convert::Infallible> for CaptivePortalError {
     }
 }
 
+#[cfg(feature = "hyper")]
 impl std::convert::From<hyper::error::Error> for CaptivePortalError {
     fn from(error: hyper::error::Error) -> Self {
         CaptivePortalError::Hyper(error)
@@ -56,6 +77,7 @@ impl std::convert::From<std::string::FromUtf8Error> for CaptivePortalError {
     }
 }
 
+#[cfg(feature = "hyper")]
 impl std::convert::From<hyper::header::ToStrError> for CaptivePortalError {
     fn from(error: http::header::ToStrError) -> Self {
         CaptivePortalError::Generic(error.to_string())
@@ -108,6 +130,7 @@ impl fmt::Display for CaptivePortalError {
         match *self {
             CaptivePortalError::Generic(ref m) => m.fmt(f),
             CaptivePortalError::IO(ref e, str) => write!(f, "{} - {}", str, e),
+            #[cfg(feature = "hyper")]
             CaptivePortalError::Hyper(ref e) => e.fmt(f),
             CaptivePortalError::Utf8(ref e) => e.fmt(f),
             CaptivePortalError::DBus(ref name, ref msg) => write!(f, "Dbus Error: {} - {}", name, msg),
@@ -117,11 +140,69 @@ impl fmt::Display for CaptivePortalError {
             CaptivePortalError::NotRequiredConnectivity(_) => write!(f, "Connectivity is limited"),
             CaptivePortalError::HotspotFailed => write!(f, "Failed to initiate a hotspot"),
             CaptivePortalError::NoWifiDeviceFound => write!(f, "No wifi device found on this system"),
+            CaptivePortalError::ApModeUnsupported(ref m) => {
+                write!(f, "The wifi device {} does not support access point mode", m)
+            },
+            CaptivePortalError::Unsupported(feature) => {
+                write!(f, "{} is not supported by this network backend", feature)
+            },
             CaptivePortalError::InvalidSharedKey(ref m) => write!(f, "Invalid Passphrase: {}", m),
             CaptivePortalError::NoSharedKeyProvided => write!(f, "Passphrase required!"),
+            CaptivePortalError::InvalidIpConfiguration(ref m) => write!(f, "Invalid IP configuration: {}", m),
+            CaptivePortalError::NotAuthorized(ref m) => write!(f, "Not authorized: {}", m),
+            CaptivePortalError::PayloadTooLarge(size) => write!(f, "The request body exceeds {} bytes", size),
+            CaptivePortalError::CredentialPolicy(ref v) => v.fmt(f),
+            CaptivePortalError::InvalidDppUri(ref m) => write!(f, "Invalid DPP URI: {}", m),
+            CaptivePortalError::InvalidLogFilter(ref m) => write!(f, "Invalid log filter: {}", m),
             CaptivePortalError::HttpRoutingFailed => write!(f, "Failed to internally route http data"),
             CaptivePortalError::DhcpError(str) => str.fmt(f),
             CaptivePortalError::IwdError(str) => str.fmt(f),
+            CaptivePortalError::UbusError(ref m) => write!(f, "ubus: {}", m),
+        }
+    }
+}
+
+impl CaptivePortalError {
+    /// A stable error code, a short title and the http status code. The code is part of the
+    /// "application/problem+json" error responses of the http server, see doc/errors.md.
+    pub fn problem(&self) -> (&'static str, &'static str, u16) {
+        match *self {
+            CaptivePortalError::Ser(_) => ("invalid-json", "The request is not valid json", 400),
+            CaptivePortalError::Utf8(_) => ("invalid-encoding", "The request is not utf8 encoded", 400),
+            #[cfg(feature = "hyper")]
+            CaptivePortalError::Hyper(_) => ("http-error", "The request could not be read", 400),
+            CaptivePortalError::InvalidSharedKey(_) => ("invalid-passphrase", "Invalid passphrase", 400),
+            CaptivePortalError::NoSharedKeyProvided => ("passphrase-required", "Passphrase required", 400),
+            CaptivePortalError::InvalidIpConfiguration(_) => {
+                ("invalid-ip-configuration", "Invalid static IP configuration", 400)
+            },
+            CaptivePortalError::CredentialPolicy(_) => {
+                ("credential-policy", "The credentials are not allowed by the policy", 400)
+            },
+            CaptivePortalError::InvalidDppUri(_) => ("invalid-dpp-uri", "Invalid DPP URI", 400),
+            CaptivePortalError::InvalidLogFilter(_) => ("invalid-log-filter", "Invalid log filter", 400),
+            CaptivePortalError::NotAuthorized(_) => ("not-authorized", "Not authorized", 403),
+            CaptivePortalError::PayloadTooLarge(_) => ("payload-too-large", "The request body is too large", 413),
+            CaptivePortalError::HttpRoutingFailed => (
+                "connection-already-requested",
+                "A connection has already been requested",
+                409,
+            ),
+            CaptivePortalError::NotInStationMode => ("not-in-station-mode", "Not in station mode", 409),
+            CaptivePortalError::Unsupported(_) => ("unsupported", "Not supported by the network backend", 501),
+            CaptivePortalError::DBus(_, _) | CaptivePortalError::IwdError(_) | CaptivePortalError::UbusError(_) => {
+                ("backend-error", "The network backend reported an error", 502)
+            },
+            CaptivePortalError::NotRequiredConnectivity(_) => ("no-connectivity", "Connectivity is limited", 503),
+            CaptivePortalError::NoWifiDeviceFound => ("no-wifi-device", "No wifi device found", 503),
+            CaptivePortalError::ApModeUnsupported(_) => {
+                ("ap-mode-unsupported", "The wifi device does not support access point mode", 503)
+            },
+            CaptivePortalError::HotspotFailed => ("hotspot-failed", "Failed to initiate a hotspot", 500),
+            CaptivePortalError::IO(_, _) => ("io-error", "Input / output error", 500),
+            CaptivePortalError::Generic(_) | CaptivePortalError::RecvError(_) | CaptivePortalError::DhcpError(_) => {
+                ("internal", "Internal error", 500)
+            },
         }
     }
 }
@@ -130,6 +211,7 @@ impl error::Error for CaptivePortalError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
             CaptivePortalError::IO(ref e, _str) => Some(e),
+            #[cfg(feature = "hyper")]
             CaptivePortalError::Hyper(ref e) => Some(e),
             CaptivePortalError::Utf8(ref e) => Some(e),
             CaptivePortalError::Ser(ref e) => Some(e),
@@ -0,0 +1,180 @@
+//! # Event history
+//! Every change of the status ([`crate::status`]) is recorded as an event with a cursor and a timestamp.
+//! The last events are kept in memory and served at "/api/events?after=cursor". A companion app that reconnects
+//! after sleep catches up on what happened instead of relying on the live stream ("/events"), which only
+//! delivers changes while the app is connected.
+//!
+//! With a state directory, the history is persisted and the cursors continue after a restart.
+
+use crate::state_dir::StateFile;
+use crate::status::Status;
+use crate::CaptivePortalError;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// File name of the persisted event history within the state directory
+pub const EVENTS_FILE: &str = "events.json";
+
+/// The number of kept events if not configured
+pub const DEFAULT_CAPACITY: usize = 100;
+
+/// The maximum number of events of one page
+const MAX_PAGE: usize = 100;
+
+/// A status change
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Event {
+    /// Increments with every event. Continues after a restart if the history is persisted.
+    pub cursor: u64,
+    /// Seconds since the unix epoch
+    pub time: u64,
+    /// The status after the change
+    pub status: serde_json::Value,
+}
+
+/// The events after a cursor
+#[derive(Serialize, Debug, PartialEq)]
+pub struct EventPage {
+    pub events: Vec<Event>,
+    /// The cursor for the next request: The cursor of the last returned event,
+    /// or the requested cursor if there are no new events
+    pub cursor: u64,
+    /// Events after the requested cursor have already been discarded, or the history has been reset.
+    /// The client should reload its state.
+    pub truncated: bool,
+    /// There are more events than returned. Request again with the returned cursor.
+    pub more: bool,
+}
+
+/// The last events, oldest first
+pub struct EventLog {
+    events: VecDeque<Event>,
+    capacity: usize,
+    next_cursor: u64,
+    file: Option<StateFile>,
+}
+
+pub type EventLogSync = Arc<Mutex<EventLog>>;
+
+impl Default for EventLog {
+    fn default() -> Self {
+        EventLog::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl EventLog {
+    /// Keeps the given number of events. 0 disables the history.
+    pub fn new(capacity: usize) -> Self {
+        EventLog {
+            events: VecDeque::new(),
+            capacity,
+            next_cursor: 1,
+            file: None,
+        }
+    }
+
+    /// Restores the events from the given file, if it exists. Changes are written at most once per
+    /// flush interval and by [`EventLog::store`].
+    pub fn with_file(path: PathBuf, capacity: usize, flush_interval: Duration) -> Result<Self, CaptivePortalError> {
+        let events: VecDeque<Event> = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(_) => VecDeque::new(),
+        };
+        let mut log = EventLog::new(capacity);
+        log.next_cursor = events.back().map_or(1, |e| e.cursor + 1);
+        log.events = events;
+        log.truncate();
+        log.file = Some(StateFile::new(path, flush_interval));
+        Ok(log)
+    }
+
+    fn truncate(&mut self) {
+        while self.events.len() > self.capacity {
+            self.events.pop_front();
+        }
+    }
+
+    /// Records a status change
+    pub fn record(&mut self, status: &Status) {
+        if self.capacity == 0 {
+            return;
+        }
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        self.events.push_back(Event {
+            cursor: self.next_cursor,
+            time,
+            status: serde_json::to_value(status).unwrap_or_default(),
+        });
+        self.next_cursor += 1;
+        self.truncate();
+        self.store(false);
+    }
+
+    /// The events after the given cursor. All kept events if no cursor is given.
+    pub fn after(&self, after: Option<u64>) -> EventPage {
+        let newest = self.next_cursor - 1;
+        let oldest = self.events.front().map_or(self.next_cursor, |e| e.cursor);
+        let (after, truncated) = match after {
+            // A cursor from before a reset of the history
+            Some(after) if after > newest => (0, true),
+            Some(after) => (after, after + 1 < oldest),
+            None => (0, false),
+        };
+        let mut events: Vec<Event> = self.events.iter().filter(|e| e.cursor > after).cloned().collect();
+        let more = events.len() > MAX_PAGE;
+        events.truncate(MAX_PAGE);
+        EventPage {
+            cursor: events.last().map_or(after, |e| e.cursor),
+            events,
+            truncated,
+            more,
+        }
+    }
+
+    /// Stores the events, see [`StateFile::store`].
+    pub fn store(&mut self, force: bool) {
+        if let Some(file) = self.file.as_mut() {
+            file.store(&self.events, force);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::{Phase, StatusSender};
+
+    #[test]
+    fn event_pages() {
+        let status = StatusSender::new().subscribe().borrow().clone();
+        let mut log = EventLog::new(3);
+        for _ in 0..5 {
+            log.record(&status);
+        }
+        let cursors = |page: &EventPage| page.events.iter().map(|e| e.cursor).collect::<Vec<_>>();
+
+        let page = log.after(None);
+        assert_eq!(cursors(&page), vec![3, 4, 5]);
+        assert_eq!((page.cursor, page.truncated, page.more), (5, false, false));
+        assert_eq!(page.events[0].status["phase"], serde_json::json!(Phase::StartUp));
+
+        let page = log.after(Some(3));
+        assert_eq!(cursors(&page), vec![4, 5]);
+        assert!(!page.truncated);
+
+        // Events 2 and 3 have been discarded
+        assert!(log.after(Some(1)).truncated);
+
+        let page = log.after(Some(5));
+        assert!(page.events.is_empty());
+        assert_eq!((page.cursor, page.truncated), (5, false));
+
+        // The history has been reset
+        let page = log.after(Some(9));
+        assert_eq!(cursors(&page), vec![3, 4, 5]);
+        assert!(page.truncated);
+    }
+}
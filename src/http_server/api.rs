@@ -0,0 +1,593 @@
+//! # Shared request handlers
+//! The REST routes and the GraphQL resolvers are thin wrappers around the methods in here.
+
+use super::{HttpServerStateSync, WifiConnectionRequest, DPP_MODE};
+use crate::attempts::{AttemptRecord, Submitted};
+use crate::channels::{self, ChannelUtilization};
+use crate::commands::{Command, Origin, Surface};
+use crate::credential_policy::Credentials;
+use crate::device::DeviceIdentity;
+use crate::dpp::DppUri;
+use crate::policy::Action;
+use crate::i18n;
+use crate::log_filter::{self, LogFilterStatus};
+use crate::mac_filter::MacFilter;
+use crate::network_backend::SharedBackend;
+use crate::network_interface::{ipv4_from_data, ipv6_from_data, WifiConnection, WifiDeviceInfo, SSID};
+use crate::sessions::Session;
+use crate::status;
+use crate::CaptivePortalError;
+use futures_util::future::select;
+use pin_utils::pin_mut;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Status {
+    /// The state machine phase, the SSID of a connection attempt and the status revision
+    #[serde(flatten)]
+    pub status: status::Status,
+    /// The network backend state, for example "Connected" or "Disconnected"
+    pub state: String,
+    /// The translated failure reason of the last connection attempt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<&'static str>,
+    /// The id of the pending or running connection attempt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attempt: Option<u64>,
+    /// Driver, firmware and capabilities of the wifi device. Only reported by the NetworkManager backend.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<WifiDeviceInfo>,
+}
+
+/// The list of wifi networks, aggregated by SSID
+pub fn networks(state: &HttpServerStateSync) -> Vec<WifiConnection> {
+    state.lock().expect("http state mutex lock").connections.0.clone()
+}
+
+/// Search, filter and paging parameters of the network list, see [`networks_page`]
+#[derive(Default, Clone, Debug)]
+pub struct NetworkQuery {
+    /// Only networks with this case insensitive part in the SSID
+    pub search: Option<String>,
+    /// Only networks with this security, like "open" or "wpa"
+    pub security: Option<String>,
+    /// Only networks with at least this signal strength in percent
+    pub min_strength: Option<u8>,
+    /// The number of matching networks to skip
+    pub offset: usize,
+    /// The maximum number of networks of the page
+    pub limit: Option<usize>,
+}
+
+impl NetworkQuery {
+    /// No parameter given. The whole list is returned in the order of the scan.
+    pub fn is_empty(&self) -> bool {
+        self.search.is_none()
+            && self.security.is_none()
+            && self.min_strength.is_none()
+            && self.offset == 0
+            && self.limit.is_none()
+    }
+}
+
+/// A page of the network list and the number of all matching networks. The networks are sorted by signal strength
+/// and SSID, so that consecutive pages do not overlap as long as the list does not change.
+pub fn networks_page(state: &HttpServerStateSync, query: &NetworkQuery) -> (usize, Vec<WifiConnection>) {
+    let state = state.lock().expect("http state mutex lock");
+    filter_networks(&state.connections.0, query)
+}
+
+fn filter_networks(networks: &[WifiConnection], query: &NetworkQuery) -> (usize, Vec<WifiConnection>) {
+    let search = query.search.as_ref().map(|s| s.to_lowercase());
+    let mut matching: Vec<&WifiConnection> = networks
+        .iter()
+        .filter(|n| search.as_ref().map(|s| n.ssid.to_string_lossy().to_lowercase().contains(s)).unwrap_or(true))
+        .filter(|n| query.security.as_ref().map(|s| n.security == s.as_str()).unwrap_or(true))
+        .filter(|n| n.strength >= query.min_strength.unwrap_or(0))
+        .collect();
+    matching.sort_by(|a, b| b.strength.cmp(&a.strength).then_with(|| a.ssid.cmp(&b.ssid)));
+    let total = matching.len();
+    let page = matching
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect();
+    (total, page)
+}
+
+/// The SSIDs of the saved wifi connections
+pub fn saved_networks(state: &HttpServerStateSync) -> Vec<SSID> {
+    state.lock().expect("http state mutex lock").saved_networks.clone()
+}
+
+/// The channel utilization report of the scanned networks
+pub fn channels(state: &HttpServerStateSync) -> Vec<ChannelUtilization> {
+    channels::report(&state.lock().expect("http state mutex lock").connections.0)
+}
+
+/// The utilization of the channel of the requested network. None if the network has not been scanned.
+pub fn channel_of(state: &HttpServerStateSync, request: &WifiConnectionRequest) -> Option<ChannelUtilization> {
+    let state = state.lock().expect("http state mutex lock");
+    let networks = &state.connections.0;
+    let frequency = networks
+        .iter()
+        .find(|n| match &request.hw {
+            Some(hw) => n.hw.eq_ignore_ascii_case(hw),
+            None => n.ssid == request.ssid,
+        })?
+        .frequency;
+    channels::report(networks).into_iter().find(|c| c.frequency == frequency)
+}
+
+/// Returns the ETag of the network list and the json encoded list.
+/// If `since` is a known revision, only the changes since that revision are encoded.
+pub fn network_list(state: &HttpServerStateSync, since: Option<u64>) -> Result<(String, String), CaptivePortalError> {
+    let state = state.lock().expect("http state mutex lock");
+    let data = serde_json::to_string(&state.network_revisions.list(&state.connections, since))?;
+    Ok((state.network_revisions.etag(), data))
+}
+
+/// The list of hotspot clients. The origin must be allowed to read it, the list names the MAC addresses,
+/// host names and user agents of all clients.
+pub fn sessions(state: &HttpServerStateSync, origin: &Origin) -> Result<Vec<Session>, CaptivePortalError> {
+    let sessions = {
+        let state = state.lock().expect("http state mutex lock");
+        state.commands.authorize(origin, Action::Sessions)?;
+        state.sessions.clone()
+    };
+    let sessions = sessions.lock().expect("sessions mutex lock");
+    Ok(sessions.list())
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Pairing {
+    /// The client opened the deep link of the device QR code
+    pub paired: bool,
+    /// The device the client pairs with. Only for paired clients.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<DeviceIdentity>,
+}
+
+/// Returns the pairing context of the given client
+pub fn pairing(state: &HttpServerStateSync, client: IpAddr) -> Pairing {
+    let (sessions, device) = {
+        let state = state.lock().expect("http state mutex lock");
+        (state.sessions.clone(), state.device.clone())
+    };
+    let paired = match client {
+        IpAddr::V4(ip) => sessions.lock().expect("sessions mutex lock").is_paired(ip),
+        IpAddr::V6(_) => false,
+    };
+    Pairing {
+        paired,
+        device: Some(device).filter(|_| paired),
+    }
+}
+
+/// The status. The failure reason is translated into the given language, see [`i18n::negotiate`].
+pub async fn status(state: &HttpServerStateSync, lang: &str) -> Result<Status, CaptivePortalError> {
+    let (nm, status, attempt) = {
+        let state = state.lock().expect("http state mutex lock");
+        let attempt = state.attempts.lock().expect("attempts mutex lock").current();
+        (state.network_manager.clone(), state.status.borrow().clone(), attempt)
+    };
+    let message = status.error.and_then(|key| i18n::translate(key, lang));
+    Ok(Status {
+        status,
+        state: format!("{:?}", nm.state().await?),
+        message,
+        attempt,
+        device: device_info(&nm).await,
+    })
+}
+
+/// None if the backend cannot read the device info
+async fn device_info(nm: &SharedBackend) -> Option<WifiDeviceInfo> {
+    match nm.device_info().await {
+        Ok(info) => Some(info),
+        Err(CaptivePortalError::Unsupported(_)) => None,
+        Err(e) => {
+            debug!("Failed to read the wifi device info: {}", e);
+            None
+        },
+    }
+}
+
+/// Returns the status as soon as its revision differs from `since`, after the timeout
+/// or when the http server shuts down. If `since` is not given, the current revision is used,
+/// so this waits for the next change.
+pub async fn wait_for_status(
+    state: &HttpServerStateSync,
+    since: Option<u64>,
+    timeout: Duration,
+    lang: &str,
+) -> Result<Status, CaptivePortalError> {
+    if timeout > Duration::from_secs(0) {
+        let (mut status_receiver, mut closing) = {
+            let state = state.lock().expect("http state mutex lock");
+            (state.status.clone(), state.closing.clone())
+        };
+        let since = since.unwrap_or_else(|| status_receiver.borrow().revision);
+        let changed = status::wait_for_change(&mut status_receiver, since, timeout);
+        let closed = async {
+            while let Some(closing) = closing.recv().await {
+                if closing {
+                    break;
+                }
+            }
+        };
+        pin_mut!(changed);
+        pin_mut!(closed);
+        select(changed, closed).await;
+    }
+    status(state, lang).await
+}
+
+/// Queues the connection request for the state machine and returns the attempt id. The portal closes afterwards.
+/// A request while another attempt is pending or running is attached to that attempt or replaces it,
+/// see [`crate::config::DuplicateConnectPolicy`].
+pub fn connect(
+    state: &HttpServerStateSync,
+    mut request: WifiConnectionRequest,
+    origin: Origin,
+) -> Result<u64, CaptivePortalError> {
+    let state = state.lock().expect("http state mutex lock");
+    state.commands.authorize(&origin, Action::Connect)?;
+    let ssid = request.ssid.to_string();
+    let credentials = Credentials {
+        ssid: &ssid,
+        mode: &request.mode,
+        identity: request.identity.as_ref().map(|i| &i[..]),
+        passphrase: request.passphrase.as_ref().map(|p| &p[..]),
+    };
+    if let Err(violation) = state.credential_policy.check(&credentials) {
+        state.commands.audit(&origin, format_args!("Connect request rejected: {}", violation.rule));
+        return Err(CaptivePortalError::CredentialPolicy(violation));
+    }
+    if request.mode == DPP_MODE {
+        request.dpp_uri.as_deref().unwrap_or_default().parse::<DppUri>()?;
+    }
+    // Reject a malformed IP configuration here, the state machine can only fail the attempt
+    ipv4_from_data(
+        request.ipv4_address.clone(),
+        request.ipv4_prefix,
+        request.ipv4_gateway.clone(),
+        request.ipv4_dns.clone(),
+    )?;
+    ipv6_from_data(
+        request.ipv6_mode.clone(),
+        request.ipv6_address.clone(),
+        request.ipv6_prefix,
+        request.ipv6_gateway.clone(),
+        request.ipv6_dns.clone(),
+    )?;
+    let (paired, client) = match (origin.surface, origin.caller.as_ref().and_then(|c| c.parse::<Ipv4Addr>().ok())) {
+        (Surface::Portal, Some(ip)) => {
+            let sessions = state.sessions.lock().expect("sessions mutex lock");
+            (sessions.is_paired(ip), Some(sessions.client_id(ip)))
+        },
+        _ => (false, None),
+    };
+    request.paired = paired;
+    let (ssid, hw) = (request.ssid.clone(), request.hw.clone());
+    let mut attempts = state.attempts.lock().expect("attempts mutex lock");
+    let attempt = match attempts.submit(request) {
+        Submitted::New(attempt, request) => {
+            if let Err(e) = state.commands.submit(Command::Connect { attempt, request }, origin) {
+                attempts.finish();
+                return Err(e);
+            }
+            attempt
+        },
+        Submitted::Attached(attempt) => {
+            state.commands.audit(&origin, format_args!("Connect request attached to attempt {}", attempt));
+            return Ok(attempt);
+        },
+        Submitted::Replaced(attempt) => {
+            state.commands.audit(&origin, format_args!("Connect request replaced the current attempt ({})", attempt));
+            attempt
+        },
+    };
+    if let Some(client) = client {
+        attempts.record(client, attempt, ssid, hw);
+    }
+    Ok(attempt)
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LastAttempt {
+    #[serde(flatten)]
+    pub record: AttemptRecord,
+    /// The translated failure reason
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<&'static str>,
+}
+
+/// The last connection attempt of the given client, with the failure reason translated into the given language.
+/// The portal page selects the network of a failed attempt again.
+pub fn last_attempt(state: &HttpServerStateSync, client: IpAddr, lang: &str) -> Option<LastAttempt> {
+    let ip = match client {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(_) => return None,
+    };
+    let (sessions, attempts) = {
+        let state = state.lock().expect("http state mutex lock");
+        (state.sessions.clone(), state.attempts.clone())
+    };
+    let client = sessions.lock().expect("sessions mutex lock").client_id(ip);
+    let record = attempts.lock().expect("attempts mutex lock").last_of(&client)?.clone();
+    let message = record.error.as_ref().and_then(|key| i18n::translate(key, lang));
+    Some(LastAttempt { record, message })
+}
+
+/// Submits the last request whose connection failed the quality gate again, this time accepting the weak
+/// connection. Returns the attempt id, or None if there is no such request.
+pub fn accept_weak(state: &HttpServerStateSync, origin: Origin) -> Result<Option<u64>, CaptivePortalError> {
+    let request = {
+        let state = state.lock().expect("http state mutex lock");
+        state.commands.authorize(&origin, Action::Connect)?;
+        let mut attempts = state.attempts.lock().expect("attempts mutex lock");
+        attempts.take_weak()
+    };
+    match request {
+        Some(request) => connect(
+            state,
+            WifiConnectionRequest {
+                accept_weak: true,
+                ..request
+            },
+            origin,
+        )
+        .map(Some),
+        None => Ok(None),
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Device {
+    #[serde(flatten)]
+    pub identity: DeviceIdentity,
+    /// The signed data, the identity and the nonce of the request as json. Only with a nonce and a sign command.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signed: Option<String>,
+    /// The signature of the signed data, as printed by the sign command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Returns the device identity. The identity is signed if a nonce is given and a sign command is configured.
+/// Returns None if the signature is neither cached nor may another sign command be started, see
+/// [`crate::device::Signatures`].
+pub async fn device(state: &HttpServerStateSync, nonce: Option<&str>) -> Result<Option<Device>, CaptivePortalError> {
+    let (identity, sign_command) = {
+        let state = state.lock().expect("http state mutex lock");
+        (state.device.clone(), state.device_sign_command.clone())
+    };
+    let (signed, signature) = match (nonce, sign_command) {
+        (Some(nonce), Some(command)) => {
+            let signed = identity.signed_data(nonce)?;
+            let cached = {
+                let mut state = state.lock().expect("http state mutex lock");
+                match state.device_signatures.cached(&signed) {
+                    Some(signature) => Some(signature),
+                    None if state.device_signatures.start(Instant::now()) => None,
+                    None => return Ok(None),
+                }
+            };
+            let signature = match cached {
+                Some(signature) => signature,
+                None => {
+                    let signature = crate::device::sign(&command, &signed).await?;
+                    let mut state = state.lock().expect("http state mutex lock");
+                    state.device_signatures.insert(signed.clone(), signature.clone());
+                    signature
+                },
+            };
+            (Some(signed), Some(signature))
+        },
+        _ => (None, None),
+    };
+    Ok(Some(Device {
+        identity,
+        signed,
+        signature,
+    }))
+}
+
+/// Cancels the given connection attempt. Returns false if that attempt is not pending or running.
+pub fn cancel_connect(state: &HttpServerStateSync, attempt: u64, origin: Origin) -> Result<bool, CaptivePortalError> {
+    let (attempts, commands) = {
+        let state = state.lock().expect("http state mutex lock");
+        (state.attempts.clone(), state.commands.clone())
+    };
+    commands.authorize(&origin, Action::Connect)?;
+    let cancelled = attempts.lock().expect("attempts mutex lock").cancel(attempt);
+    commands.audit(&origin, format_args!("Cancel attempt {}: {}", attempt, cancelled));
+    Ok(cancelled)
+}
+
+/// The stored passphrase of a saved network, for users who forgot their own network password after provisioning.
+/// None if there is no saved connection or no stored secret. Not queued, the secret is read right away.
+/// Requires the "show-secret" action of the policy. Every read is audited, the secret is not logged.
+pub async fn saved_secret(
+    state: &HttpServerStateSync,
+    ssid: SSID,
+    origin: &Origin,
+) -> Result<Option<String>, CaptivePortalError> {
+    let (nm, commands) = {
+        let state = state.lock().expect("http state mutex lock");
+        (state.network_manager.clone(), state.commands.clone())
+    };
+    commands.authorize(origin, Action::ShowSecret)?;
+    commands.audit(origin, format_args!("Show the secret of {}", ssid));
+    nm.saved_secret(&ssid).await
+}
+
+/// Queues the removal of the stored connection for the given SSID and returns the command id.
+pub fn forget(state: &HttpServerStateSync, ssid: SSID, origin: Origin) -> Result<u64, CaptivePortalError> {
+    let commands = state.lock().expect("http state mutex lock").commands.clone();
+    commands.submit(Command::Forget(ssid), origin)
+}
+
+/// Queues setting (or with None removing) the update banner of the portal and returns the command id.
+pub fn update_notice(
+    state: &HttpServerStateSync,
+    notice: Option<status::UpdateNotice>,
+    origin: Origin,
+) -> Result<u64, CaptivePortalError> {
+    let commands = state.lock().expect("http state mutex lock").commands.clone();
+    commands.submit(Command::SetUpdateNotice(notice), origin)
+}
+
+/// Queues replacing the hotspot MAC filter and returns the command id.
+pub fn set_mac_filter(
+    state: &HttpServerStateSync,
+    filter: MacFilter,
+    origin: Origin,
+) -> Result<u64, CaptivePortalError> {
+    let commands = state.lock().expect("http state mutex lock").commands.clone();
+    commands.submit(Command::SetMacFilter(filter), origin)
+}
+
+/// The hotspot MAC filter. The origin must be allowed to replace it.
+pub fn mac_filter(state: &HttpServerStateSync, origin: &Origin) -> Result<MacFilter, CaptivePortalError> {
+    let state = state.lock().expect("http state mutex lock");
+    state.commands.authorize(origin, Action::MacFilter)?;
+    let filter = state.mac_filter.read().expect("mac filter lock").clone();
+    Ok(filter)
+}
+
+/// Queues a restart of the hotspot and returns the command id. The portal closes and opens again.
+pub fn restart_hotspot(state: &HttpServerStateSync, origin: Origin) -> Result<u64, CaptivePortalError> {
+    let commands = state.lock().expect("http state mutex lock").commands.clone();
+    commands.submit(Command::RestartHotspot, origin)
+}
+
+/// A replacing log filter, like "info,wifi_captive::network_backend::nm=trace", see [`crate::log_filter`]
+#[derive(Deserialize, Clone, Debug)]
+pub struct LogFilterRequest {
+    pub filter: String,
+    /// The startup filter applies again after this many minutes. Never if not set.
+    pub minutes: Option<u64>,
+}
+
+/// The log filter. The origin must be allowed to replace it.
+pub fn log_filter(state: &HttpServerStateSync, origin: &Origin) -> Result<LogFilterStatus, CaptivePortalError> {
+    let state = state.lock().expect("http state mutex lock");
+    state.commands.authorize(origin, Action::LogFilter)?;
+    Ok(state.log_filter.status())
+}
+
+/// Replaces the log filter, or with None applies the startup filter again. Not queued, the change applies
+/// right away. Returns the new log filter.
+pub fn set_log_filter(
+    state: &HttpServerStateSync,
+    request: Option<LogFilterRequest>,
+    origin: Origin,
+) -> Result<LogFilterStatus, CaptivePortalError> {
+    let state = state.lock().expect("http state mutex lock");
+    state.commands.authorize(&origin, Action::LogFilter)?;
+    match request {
+        Some(request) => {
+            let duration = request.minutes.map(log_filter::duration).transpose()?;
+            state.log_filter.set(&request.filter, duration)?;
+            let minutes = request.minutes.map(|m| format!(" for {} minutes", m)).unwrap_or_default();
+            state.commands.audit(&origin, format_args!("Log filter {}{}", request.filter, minutes));
+        },
+        None => {
+            state.log_filter.reset();
+            state.commands.audit(&origin, format_args!("Log filter reset"));
+        },
+    }
+    Ok(state.log_filter.status())
+}
+
+/// Queues restarting wifi or rebooting the device and returns the command id. Both interrupt the portal, so
+/// the caller must confirm the command by repeating its action name, like "reboot". Returns None without
+/// a matching confirmation. Unconfirmed requests of authorized callers are audited as well.
+pub fn device_action(
+    state: &HttpServerStateSync,
+    command: Command,
+    confirmation: &str,
+    origin: Origin,
+) -> Result<Option<u64>, CaptivePortalError> {
+    let commands = state.lock().expect("http state mutex lock").commands.clone();
+    let action = command.action();
+    commands.authorize(&origin, action)?;
+    if confirmation != action.name() {
+        commands.audit(&origin, format_args!("Unconfirmed {}", action.name()));
+        return Ok(None);
+    }
+    commands.submit(command, origin).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(ssid: &str, security: &'static str, strength: u8) -> WifiConnection {
+        WifiConnection {
+            ssid: ssid.into(),
+            hw: "00:11:22:33:44:55".to_owned(),
+            security,
+            strength,
+            frequency: 2412,
+            is_own: false,
+            vendor: None,
+        }
+    }
+
+    fn ssids(networks: &[WifiConnection]) -> Vec<&str> {
+        networks.iter().filter_map(|n| n.ssid.as_str()).collect()
+    }
+
+    #[test]
+    fn network_pages() {
+        let networks = vec![
+            network("Apartment 3", "wpa", 40),
+            network("Cafe", "open", 70),
+            network("apartment 12", "wpa3", 90),
+            network("Apartment 7", "wpa", 40),
+        ];
+
+        let (total, page) = filter_networks(&networks, &NetworkQuery::default());
+        assert_eq!(total, 4);
+        assert_eq!(ssids(&page), vec!["apartment 12", "Cafe", "Apartment 3", "Apartment 7"]);
+
+        let query = NetworkQuery {
+            offset: 1,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let (total, page) = filter_networks(&networks, &query);
+        assert_eq!(total, 4);
+        assert_eq!(ssids(&page), vec!["Cafe", "Apartment 3"]);
+
+        let query = NetworkQuery {
+            search: Some("APARTMENT".to_owned()),
+            limit: Some(1),
+            ..Default::default()
+        };
+        let (total, page) = filter_networks(&networks, &query);
+        assert_eq!(total, 3);
+        assert_eq!(ssids(&page), vec!["apartment 12"]);
+
+        let query = NetworkQuery {
+            security: Some("wpa".to_owned()),
+            min_strength: Some(30),
+            ..Default::default()
+        };
+        assert_eq!(filter_networks(&networks, &query).0, 2);
+
+        let query = NetworkQuery {
+            offset: 10,
+            ..Default::default()
+        };
+        let (total, page) = filter_networks(&networks, &query);
+        assert_eq!(total, 4);
+        assert!(page.is_empty());
+    }
+}
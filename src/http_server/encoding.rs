@@ -0,0 +1,113 @@
+//! # Content encoding negotiation
+//! The setup link is often weak and the network list can be large with 100+ access points.
+//! Dynamic json responses are gzip compressed if the client accepts it (requires the "compression" feature).
+//! Static ui files are served from precompressed variants ("index.js.br", "index.js.gz") next to the
+//! original file, if there are any. Nothing is compressed at runtime for them.
+
+use hyper::header::HeaderValue;
+use hyper::{Body, Response};
+
+/// Smaller responses are not worth the compression overhead
+#[cfg(feature = "compression")]
+const MIN_COMPRESS_SIZE: usize = 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// The file extension of precompressed variants
+    pub fn extension(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+        }
+    }
+}
+
+/// The encodings of an Accept-Encoding header, the preferred first. Brotli is preferred over gzip
+/// for equal quality values. Encodings with "q=0" are not accepted.
+pub fn accepted(accept_encoding: Option<&str>) -> Vec<Encoding> {
+    let mut encodings: Vec<(u32, Encoding)> = accept_encoding
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(|p| p.trim());
+            let encoding = match parts.next()? {
+                "br" => Encoding::Brotli,
+                "gzip" | "x-gzip" => Encoding::Gzip,
+                _ => return None,
+            };
+            // Quality in thousandths
+            let quality = parts
+                .filter(|p| p.starts_with("q="))
+                .filter_map(|p| p[2..].parse::<f32>().ok())
+                .next()
+                .map(|q| (q.max(0.0).min(1.0) * 1000.0) as u32)
+                .unwrap_or(1000);
+            Some((quality, encoding)).filter(|(q, _)| *q > 0)
+        })
+        .collect();
+    encodings.sort_by_key(|(q, e)| (std::cmp::Reverse(*q), *e != Encoding::Brotli));
+    encodings.into_iter().map(|(_, e)| e).collect()
+}
+
+/// Sets the json body of a dynamic response, gzip compressed if the client accepts it.
+pub fn json_body(response: &mut Response<Body>, accept_encoding: Option<&str>, data: String) {
+    response
+        .headers_mut()
+        .append("content-type", HeaderValue::from_static("application/json"));
+    response
+        .headers_mut()
+        .append("vary", HeaderValue::from_static("Accept-Encoding"));
+    #[cfg(feature = "compression")]
+    {
+        if data.len() >= MIN_COMPRESS_SIZE && accepted(accept_encoding).contains(&Encoding::Gzip) {
+            match gzip(data.as_bytes()) {
+                Ok(compressed) => {
+                    response
+                        .headers_mut()
+                        .append("content-encoding", HeaderValue::from_static("gzip"));
+                    *response.body_mut() = Body::from(compressed);
+                    return;
+                },
+                Err(e) => warn!("Failed to compress the response: {}", e),
+            }
+        }
+    }
+    #[cfg(not(feature = "compression"))]
+    let _ = accept_encoding;
+    *response.body_mut() = Body::from(data);
+}
+
+#[cfg(feature = "compression")]
+fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    let mut encoder = GzEncoder::new(Vec::with_capacity(data.len() / 4), Compression::fast());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(accepted(Some("gzip, deflate, br")), vec![Encoding::Brotli, Encoding::Gzip]);
+        assert_eq!(accepted(Some("br;q=0.5, gzip")), vec![Encoding::Gzip, Encoding::Brotli]);
+        assert_eq!(accepted(Some("gzip;q=0, identity")), vec![]);
+        assert_eq!(accepted(None), vec![]);
+    }
+}
@@ -1,10 +1,16 @@
 //! Serves the static ui files. If the "includeui" feature is set, the ui files are compiled in
 //! and no system file access is required.
 
+use super::encoding;
+use super::probes::ProbeRule;
 use super::CaptivePortalError;
 use crate::http_server::HttpServerStateSync;
+use crate::i18n;
+use crate::redirect::RedirectStrategy;
+use crate::sessions::DeviceType;
 use hyper::header::HeaderValue;
 use hyper::{Body, Request, Response, StatusCode};
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 
 #[cfg(any(feature = "includeui", not(debug_assertions)))]
@@ -54,6 +60,10 @@ impl<'a> FileWrapper {
     pub fn contents(self) -> Body {
         Body::from(self.contents)
     }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.contents
+    }
 }
 
 #[cfg(all(not(feature = "includeui"), debug_assertions))]
@@ -78,6 +88,10 @@ impl<'a> FileWrapper {
     pub fn contents(self) -> Body {
         Body::from(self.contents)
     }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.contents
+    }
 }
 
 fn mime_type_from_ext(ext: &str) -> &str {
@@ -90,15 +104,114 @@ fn mime_type_from_ext(ext: &str) -> &str {
     }
 }
 
-pub fn serve_file(
-    root: &Path,
+/// Answers a captive portal probe with the given strategy, see [`crate::redirect`].
+/// Apple's captive network assistant opens the portal for every response that is not its "Success" page.
+/// A page with a refresh is more reliable than a redirect there.
+fn captive_response(mut response: Response<Body>, redirect_loc: &str, strategy: RedirectStrategy) -> Response<Body> {
+    if strategy == RedirectStrategy::Inline {
+        response
+            .headers_mut()
+            .append("Content-Type", HeaderValue::from_static("text/html"));
+        *response.body_mut() = Body::from(format!(
+            "<HTML><HEAD><TITLE>Captive Portal</TITLE><meta http-equiv=\"refresh\" content=\"0; url={0}\"></HEAD>\
+             <BODY><a href=\"{0}\">Captive Portal</a></BODY></HTML>",
+            redirect_loc
+        ));
+        return response;
+    }
+    *response.status_mut() = StatusCode::FOUND;
+    response.headers_mut().append(
+        "Location",
+        HeaderValue::from_str(redirect_loc).expect("Headervalue from generated string"),
+    );
+    response
+}
+
+/// The url of the portal page
+fn portal_location(state: &HttpServerStateSync) -> String {
+    let state = state.lock().expect("Lock http_state mutex");
+    let host = match state.hostname.as_ref() {
+        Some(hostname) => hostname.clone(),
+        None => state.server_addr.ip().to_string(),
+    };
+    format!("http://{}:{}/index.html", host, state.server_addr.port())
+}
+
+/// Answers a probe of the configured probe table, see [`super::probes`]
+fn probe_rule_response(
     mut response: Response<Body>,
-    req: &Request<Body>,
+    rule: ProbeRule,
     state: &HttpServerStateSync,
-) -> Result<Response<Body>, CaptivePortalError> {
-    let path = &req.uri().path()[1..];
+    client: IpAddr,
+    device: DeviceType,
+) -> Response<Body> {
+    let status = match rule.status.and_then(|s| StatusCode::from_u16(s).ok()) {
+        Some(status) => status,
+        None => return captive_response(response, &portal_location(state), probe(state, client, device)),
+    };
+    let content_type = rule.content_type.as_ref().map(|c| &c[..]).unwrap_or("text/plain");
+    *response.status_mut() = status;
+    if let Ok(content_type) = HeaderValue::from_str(content_type) {
+        response.headers_mut().append("Content-Type", content_type);
+    }
+    response
+        .headers_mut()
+        .append("Cache-Control", HeaderValue::from_static("no-store"));
+    *response.body_mut() = Body::from(rule.body);
+    response
+}
+
+/// The redirect strategy for a probe of the given client. Without a session, the default of the device type.
+fn probe(state: &HttpServerStateSync, client: IpAddr, device: DeviceType) -> RedirectStrategy {
+    let ip = match client {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(_) => return RedirectStrategy::default_for(device),
+    };
+    let mut state = state.lock().expect("Lock http_state mutex");
+    let sessions = state.sessions.clone();
+    let mut sessions = sessions.lock().expect("sessions mutex lock");
+    match sessions.get_mut(ip) {
+        Some(session) => {
+            let fingerprint = session.fingerprint();
+            state.redirects.probe(&mut session.redirect, &fingerprint, device)
+        },
+        None => RedirectStrategy::default_for(device),
+    }
+}
+
+/// Records that the redirect strategy of the given client worked
+fn portal_loaded(state: &HttpServerStateSync, client: IpAddr) {
+    if let IpAddr::V4(ip) = client {
+        let mut state = state.lock().expect("Lock http_state mutex");
+        let sessions = state.sessions.clone();
+        let mut sessions = sessions.lock().expect("sessions mutex lock");
+        if let Some(session) = sessions.get_mut(ip) {
+            let fingerprint = session.fingerprint();
+            state.redirects.portal_loaded(&mut session.redirect, &fingerprint);
+        }
+    }
+}
 
-    let file = match () {
+/// Sets the language and the writing direction of an html page, for right-to-left languages like Arabic
+fn localized_page(html: &[u8], lang: &str) -> Vec<u8> {
+    let tag = b"<html";
+    match html.windows(tag.len()).position(|w| w.eq_ignore_ascii_case(tag)) {
+        Some(position) => {
+            let end = position + tag.len();
+            let attributes = format!(" lang=\"{}\" dir=\"{}\"", lang, i18n::direction(lang));
+            let mut page = Vec::with_capacity(html.len() + attributes.len());
+            page.extend_from_slice(&html[..end]);
+            page.extend_from_slice(attributes.as_bytes());
+            page.extend_from_slice(&html[end..]);
+            page
+        },
+        None => html.to_vec(),
+    }
+}
+
+/// The embedded or filesystem ui file of the given path
+fn lookup(root: &Path, path: &str) -> Option<FileWrapper> {
+    match () {
         #[cfg(all(not(feature = "includeui"), debug_assertions))]
         () => FileWrapper::from_filesystem(root, path),
         #[cfg(any(feature = "includeui", not(debug_assertions)))]
@@ -108,44 +221,110 @@ pub fn serve_file(
                 .get_file(path)
                 .and_then(|f| Some(FileWrapper::from_included(&f)))
         },
+    }
+}
+
+pub fn serve_file(
+    root: &Path,
+    mut response: Response<Body>,
+    req: &Request<Body>,
+    state: &HttpServerStateSync,
+    client: IpAddr,
+    device: DeviceType,
+    lang: &'static str,
+) -> Result<Response<Body>, CaptivePortalError> {
+    let path = &req.uri().path()[1..];
+
+    // Configured probe urls take precedence
+    let rule = {
+        let mut state = state.lock().expect("Lock http_state mutex");
+        state.probes.reload_if_changed();
+        let host = req.headers().get("Host").and_then(|v| v.to_str().ok());
+        state.probes.lookup(host, req.uri().path()).cloned()
     };
+    if let Some(rule) = rule {
+        debug!("Configured probe response for {}", req.uri().path());
+        return Ok(probe_rule_response(response, rule, state, client, device));
+    }
+
+    let file = lookup(root, path);
     // A captive portal catches all GET requests (that accept */* or text) and redirects to the main page.
+    // The connectivity checks of known devices are caught independently of the accept header.
     if file.is_none() {
-        if let Some(v) = req.headers().get("Accept") {
-            let accept = v.to_str()?;
-            if accept.contains("text") || accept.contains("*/*") {
-                let state = state.lock().expect("Lock http_state mutex");
-                let redirect_loc = format!(
-                    "http://{}:{}/index.html",
-                    state.server_addr.ip().to_string(),
-                    state.server_addr.port()
-                );
-                drop(state); // release mutex
-                *response.status_mut() = StatusCode::FOUND;
-                response.headers_mut().append(
-                    "Location",
-                    HeaderValue::from_str(&redirect_loc).expect("Headervalue from generated string"),
-                );
-                return Ok(response);
-            }
+        let accepts_text = match req.headers().get("Accept") {
+            Some(v) => {
+                let accept = v.to_str()?;
+                accept.contains("text") || accept.contains("*/*")
+            },
+            None => false,
+        };
+        if accepts_text || device != DeviceType::Unknown {
+            return Ok(captive_response(response, &portal_location(state), probe(state, client, device)));
         }
     }
 
     // Serve UI
     if let Some(file) = file {
+        if path == "index.html" {
+            portal_loaded(state, client);
+        }
         let mime = match file.path().extension() {
             Some(ext) => mime_type_from_ext(ext.to_str().expect("file path extension OsStr->str")),
             None => "application/octet-stream",
         };
+        // A precompressed variant next to the file ("index.js.br", "index.js.gz") in the preferred encoding
+        let accept_encoding = req.headers().get("Accept-Encoding").and_then(|v| v.to_str().ok());
+        let variant = encoding::accepted(accept_encoding)
+            .into_iter()
+            .filter_map(|e| lookup(root, &format!("{}.{}", path, e.extension())).map(|f| (e, f)))
+            .next();
         info!("Serve {} for {}", mime, path);
         response.headers_mut().append(
             "Content-Type",
             HeaderValue::from_str(mime).expect("mime to header value"),
         );
-        *response.body_mut() = file.contents();
+        // The portal page is served in the language of the request. It cannot be precompressed.
+        if path == "index.html" {
+            response
+                .headers_mut()
+                .append("Content-Language", HeaderValue::from_static(lang));
+            *response.body_mut() = Body::from(localized_page(file.bytes(), lang));
+            return Ok(response);
+        }
+        response
+            .headers_mut()
+            .append("Vary", HeaderValue::from_static("Accept-Encoding"));
+        let body = match variant {
+            Some((encoding, variant)) => {
+                response
+                    .headers_mut()
+                    .append("Content-Encoding", HeaderValue::from_static(encoding.name()));
+                variant.contents()
+            },
+            None => file.contents(),
+        };
+        *response.body_mut() = body;
         return Ok(response);
     }
 
     *response.status_mut() = StatusCode::NOT_FOUND;
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn localized_pages() {
+        let html = b"<!DOCTYPE html>\n<html>\n<head></head></html>";
+        let page = localized_page(html, "he");
+        assert_eq!(
+            String::from_utf8(page).unwrap(),
+            "<!DOCTYPE html>\n<html lang=\"he\" dir=\"rtl\">\n<head></head></html>"
+        );
+        let page = localized_page(b"<HTML><body></body></HTML>", "de");
+        assert_eq!(String::from_utf8(page).unwrap(), "<HTML lang=\"de\" dir=\"ltr\"><body></body></HTML>");
+        assert_eq!(localized_page(b"plain", "ar"), b"plain".to_vec());
+    }
+}
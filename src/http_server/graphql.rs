@@ -0,0 +1,345 @@
+//! # GraphQL endpoint
+//! An alternative to the REST routes at "/graphql" for apps that already use GraphQL.
+//! Exposes the networks, the channel utilization, the status and the hotspot sessions and the connect and
+//! forget mutations.
+//! The resolvers use the same handlers as the REST routes, see [`super::api`].
+//!
+//! Resolvers are executed synchronously. Asynchronous data (the status) is therefore fetched before
+//! the query is executed. Mutations are queued for the state machine, see [`crate::commands`].
+
+use super::{api, HttpServerStateSync, WifiConnectionRequest};
+use crate::channels::ChannelUtilization;
+use crate::commands::Origin;
+use crate::network_interface::WifiConnection;
+use crate::sessions::Session;
+use crate::CaptivePortalError;
+use juniper::{FieldResult, GraphQLInputObject, GraphQLObject, RootNode};
+use std::convert::TryFrom;
+
+#[derive(GraphQLObject)]
+#[graphql(description = "A wifi network. Access points are aggregated by SSID.")]
+struct Network {
+    ssid: String,
+    /// The unique hw address of the access point
+    hw: String,
+    security: String,
+    /// Signal strength in percent
+    strength: i32,
+    /// Frequency in Mhz
+    frequency: i32,
+    vendor: Option<String>,
+}
+
+impl From<WifiConnection> for Network {
+    fn from(connection: WifiConnection) -> Self {
+        Network {
+            ssid: connection.ssid.to_string(),
+            hw: connection.hw,
+            security: connection.security.to_owned(),
+            strength: connection.strength as i32,
+            frequency: connection.frequency as i32,
+            vendor: connection.vendor.map(|v| v.to_owned()),
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "The congestion of a wifi channel, estimated from the scanned networks")]
+struct Channel {
+    channel: i32,
+    /// Frequency in Mhz
+    frequency: i32,
+    /// The number of access points on this channel and overlapping channels
+    access_points: i32,
+    /// The summed up signal strengths of these access points in percent
+    load: i32,
+    congested: bool,
+}
+
+impl From<ChannelUtilization> for Channel {
+    fn from(c: ChannelUtilization) -> Self {
+        Channel {
+            channel: c.channel as i32,
+            frequency: c.frequency as i32,
+            access_points: c.access_points as i32,
+            load: c.load as i32,
+            congested: c.congested,
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "The state machine phase and network backend status")]
+struct Status {
+    /// One of "start_up", "try_reconnect", "connected", "portal", "connecting", "exit"
+    phase: String,
+    /// The SSID of the current connection attempt or of the established connection
+    ssid: Option<String>,
+    /// The IPv4 address of the established connection
+    ip: Option<String>,
+    /// The global IPv6 addresses of the established connection
+    ipv6: Vec<String>,
+    /// The network backend state
+    state: String,
+    /// The message key of the reason why the last connection attempt failed, like "wrong_password"
+    error: Option<String>,
+    /// The stage of the current or last connection attempt, like "associating" or "failed"
+    progress: Option<String>,
+    /// The translated failure reason, see the "lang" query parameter and the Accept-Language header
+    message: Option<String>,
+    /// The id of the pending or running connection attempt
+    attempt: Option<i32>,
+    /// The measured quality of the last established connection, if the quality gate is enabled
+    quality: Option<Quality>,
+    /// The hardware address of a hotspot client that claims the gateway address (ARP spoofing)
+    arp_spoof: Option<String>,
+    /// The hardware addresses of other access points that broadcast the SSID of this hotspot (evil twins)
+    twin_aps: Vec<String>,
+    /// Driver, firmware and capabilities of the wifi device
+    device: Option<WifiDevice>,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "Driver and firmware of the wifi device")]
+struct WifiDevice {
+    driver: String,
+    driver_version: String,
+    firmware_version: String,
+    /// The wireless capabilities, like "ap", "5ghz" or "rsn"
+    capabilities: Vec<String>,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "The measured quality of a connection")]
+struct Quality {
+    /// Signal strength in percent
+    signal: Option<i32>,
+    /// Bitrate in kbit/s
+    bitrate: Option<i32>,
+    /// Round trip time to the default gateway in milliseconds
+    gateway_rtt: Option<i32>,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "A hotspot client")]
+struct ClientSession {
+    ip: String,
+    mac: Option<String>,
+    hostname: Option<String>,
+    device: String,
+    user_agent: Option<String>,
+    /// Unix timestamp in seconds
+    last_seen: f64,
+}
+
+impl From<Session> for ClientSession {
+    fn from(session: Session) -> Self {
+        ClientSession {
+            ip: session.ip.to_string(),
+            mac: session.mac,
+            hostname: session.hostname,
+            device: format!("{:?}", session.device).to_lowercase(),
+            user_agent: session.user_agent,
+            last_seen: session.last_seen as f64,
+        }
+    }
+}
+
+pub struct Context {
+    state: HttpServerStateSync,
+    status: api::Status,
+    origin: Origin,
+}
+
+impl juniper::Context for Context {}
+
+pub struct Query;
+
+#[juniper::object(Context = Context)]
+impl Query {
+    fn networks(context: &Context) -> Vec<Network> {
+        api::networks(&context.state).into_iter().map(Network::from).collect()
+    }
+
+    /// The channel utilization report of the scanned networks
+    fn channels(context: &Context) -> Vec<Channel> {
+        api::channels(&context.state).into_iter().map(Channel::from).collect()
+    }
+
+    fn status(context: &Context) -> Status {
+        let status = &context.status;
+        Status {
+            phase: serde_json::to_value(&status.status.phase)
+                .ok()
+                .and_then(|v| v.as_str().map(|v| v.to_owned()))
+                .unwrap_or_default(),
+            ssid: status.status.ssid.clone(),
+            ip: status.status.ip.map(|ip| ip.to_string()),
+            ipv6: status.status.ipv6.iter().map(|ip| ip.to_string()).collect(),
+            state: status.state.clone(),
+            error: status.status.error.map(|e| e.to_owned()),
+            progress: status
+                .status
+                .progress
+                .and_then(|p| serde_json::to_value(&p).ok())
+                .and_then(|v| v.as_str().map(|v| v.to_owned())),
+            message: status.message.map(|m| m.to_owned()),
+            attempt: status.attempt.map(|a| a as i32),
+            quality: status.status.quality.as_ref().map(|q| Quality {
+                signal: q.signal.map(|s| s as i32),
+                bitrate: q.bitrate.map(|b| b as i32),
+                gateway_rtt: q.gateway_rtt.map(|r| r as i32),
+            }),
+            arp_spoof: status.status.arp_spoof.clone(),
+            twin_aps: status.status.twin_aps.clone(),
+            device: status.device.as_ref().map(|d| WifiDevice {
+                driver: d.driver.clone(),
+                driver_version: d.driver_version.clone(),
+                firmware_version: d.firmware_version.clone(),
+                capabilities: d.capabilities.iter().map(|c| (*c).to_owned()).collect(),
+            }),
+        }
+    }
+
+    /// The hotspot clients. Requires the admin token.
+    fn sessions(context: &Context) -> FieldResult<Vec<ClientSession>> {
+        Ok(api::sessions(&context.state, &context.origin)?
+            .into_iter()
+            .map(ClientSession::from)
+            .collect())
+    }
+}
+
+/// A connect request, like the json body of the "/connect" route.
+/// Mode is one of "wpa", "wpa3", "wep", "open", "enterprise", "wps" (push-button, no passphrase)
+/// or "dpp" (Easy Connect with the QR code content of the router in "dppUri", the SSID may be empty).
+/// "acceptWeak" skips the connection quality gate.
+/// Enterprise networks use "eap" ("peap" or "ttls") and an optional "anonymousIdentity".
+/// "hidden" networks do not broadcast their SSID.
+/// "ipv4Address" and "ipv4Prefix" configure a static address instead of DHCP,
+/// with an optional "ipv4Gateway" and "ipv4Dns" servers.
+/// "ipv6Mode" is "auto", "dhcp" or "static", the latter with the "ipv6Address", "ipv6Prefix",
+/// "ipv6Gateway" and "ipv6Dns" fields.
+/// "priority" is the autoconnect priority of the saved connection, higher is preferred.
+#[derive(GraphQLInputObject)]
+struct ConnectInput {
+    ssid: String,
+    mode: String,
+    passphrase: Option<String>,
+    identity: Option<String>,
+    anonymous_identity: Option<String>,
+    eap: Option<String>,
+    hw: Option<String>,
+    ipv4_address: Option<String>,
+    ipv4_prefix: Option<i32>,
+    ipv4_gateway: Option<String>,
+    ipv4_dns: Option<Vec<String>>,
+    ipv6_mode: Option<String>,
+    ipv6_address: Option<String>,
+    ipv6_prefix: Option<i32>,
+    ipv6_gateway: Option<String>,
+    ipv6_dns: Option<Vec<String>>,
+    hidden: Option<bool>,
+    accept_weak: Option<bool>,
+    dpp_uri: Option<String>,
+    priority: Option<i32>,
+}
+
+pub struct Mutation;
+
+#[juniper::object(Context = Context)]
+impl Mutation {
+    /// Connect to the given network and return the attempt id. The portal closes.
+    fn connect(context: &Context, input: ConnectInput) -> FieldResult<i32> {
+        let ConnectInput {
+            ssid,
+            mode,
+            passphrase,
+            identity,
+            anonymous_identity,
+            eap,
+            hw,
+            ipv4_address,
+            ipv4_prefix,
+            ipv4_gateway,
+            ipv4_dns,
+            ipv6_mode,
+            ipv6_address,
+            ipv6_prefix,
+            ipv6_gateway,
+            ipv6_dns,
+            hidden,
+            accept_weak,
+            dpp_uri,
+            priority,
+        } = input;
+        let request = WifiConnectionRequest {
+            mode,
+            ssid: ssid.into(),
+            identity,
+            passphrase,
+            anonymous_identity,
+            eap,
+            hw,
+            ipv4_address,
+            // Out of range values are rejected by the validation
+            ipv4_prefix: ipv4_prefix.map(|p| u8::try_from(p).unwrap_or(u8::max_value())),
+            ipv4_gateway,
+            ipv4_dns: ipv4_dns.unwrap_or_default(),
+            ipv6_mode,
+            ipv6_address,
+            ipv6_prefix: ipv6_prefix.map(|p| u8::try_from(p).unwrap_or(u8::max_value())),
+            ipv6_gateway,
+            ipv6_dns: ipv6_dns.unwrap_or_default(),
+            hidden: hidden.unwrap_or(false),
+            accept_weak: accept_weak.unwrap_or(false),
+            dpp_uri,
+            priority,
+            paired: false,
+        };
+        Ok(api::connect(&context.state, request, context.origin.clone())? as i32)
+    }
+
+    /// Connects again to the network whose connection failed the quality gate, accepting the weak connection.
+    /// Returns the attempt id, or null if there is no such connection.
+    fn accept_weak(context: &Context) -> FieldResult<Option<i32>> {
+        Ok(api::accept_weak(&context.state, context.origin.clone())?.map(|attempt| attempt as i32))
+    }
+
+    /// Removes the stored connection for the given SSID. Returns the command id.
+    fn forget(context: &Context, ssid: String) -> FieldResult<i32> {
+        Ok(api::forget(&context.state, ssid.into(), context.origin.clone())? as i32)
+    }
+
+    /// Closes the portal and starts the hotspot again. Returns the command id.
+    fn restart_hotspot(context: &Context) -> FieldResult<i32> {
+        Ok(api::restart_hotspot(&context.state, context.origin.clone())? as i32)
+    }
+}
+
+pub type Schema = RootNode<'static, Query, Mutation>;
+
+/// Executes a GraphQL request (json encoded) and returns the json encoded response and
+/// true if the execution succeeded.
+/// Failure messages are translated into the given language. Mutations are attributed to the given origin.
+pub async fn execute(
+    state: &HttpServerStateSync,
+    body: &[u8],
+    lang: &str,
+    origin: Origin,
+) -> Result<(String, bool), CaptivePortalError> {
+    let request: juniper::http::GraphQLRequest = serde_json::from_slice(body)?;
+    let context = Context {
+        state: state.clone(),
+        status: api::status(state, lang).await?,
+        origin,
+    };
+
+    let (data, ok) = {
+        let schema = Schema::new(Query, Mutation);
+        let response = request.execute(&schema, &context);
+        (serde_json::to_string(&response)?, response.is_ok())
+    };
+
+    Ok((data, ok))
+}
@@ -0,0 +1,238 @@
+//! # Slow client protection
+//! The portal runs on an open access point. A single misbehaving phone or a slowloris-style client must not
+//! exhaust the daemon. Every accepted connection is therefore wrapped in a [`LimitedStream`]:
+//!
+//! * The number of concurrent connections is limited, in total and per client ip address.
+//!   Connections above a limit are closed right away.
+//! * A connection that waits for a request or a request body is closed if the client sent nothing for the idle
+//!   timeout. While a long-poll request waits for a status change, the read timeout is suspended.
+//!   Any progress, also writes of event streams, resets the timeout.
+//! * A write that does not make progress within the idle timeout fails, for clients that stop reading.
+//!
+//! Request headers are limited by hyper (see [`MAX_HEADER_SIZE`]), request bodies by the http server.
+
+use futures_util::FutureExt;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::{delay_until, Delay, Instant};
+
+/// The read buffer size of hyper and therefore the maximum size of the request line and headers.
+/// Hyper does not accept less than 8 KiB.
+pub const MAX_HEADER_SIZE: usize = 16 * 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpLimits {
+    /// Read and write timeout of a connection
+    pub idle_timeout: Duration,
+    pub max_connections: usize,
+    pub max_connections_per_client: usize,
+    /// Maximum size of a request body in bytes
+    pub max_body_size: usize,
+}
+
+impl Default for HttpLimits {
+    fn default() -> Self {
+        HttpLimits {
+            idle_timeout: Duration::from_secs(20),
+            max_connections: 64,
+            max_connections_per_client: 8,
+            max_body_size: 64 * 1024,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    total: usize,
+    per_client: HashMap<IpAddr, usize>,
+}
+
+/// Counts the open connections of the http server
+#[derive(Clone)]
+pub struct ConnectionLimiter {
+    limits: HttpLimits,
+    counts: Arc<Mutex<Counts>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(limits: HttpLimits) -> Self {
+        ConnectionLimiter {
+            limits,
+            counts: Arc::new(Mutex::new(Counts::default())),
+        }
+    }
+
+    /// Returns true and counts the connection if it is within the limits
+    fn acquire(&self, client: IpAddr) -> bool {
+        let mut counts = self.counts.lock().expect("connection counts mutex lock");
+        let per_client = counts.per_client.get(&client).cloned().unwrap_or(0);
+        if counts.total >= self.limits.max_connections || per_client >= self.limits.max_connections_per_client {
+            return false;
+        }
+        counts.total += 1;
+        counts.per_client.insert(client, per_client + 1);
+        true
+    }
+
+    fn release(&self, client: IpAddr) {
+        let mut counts = self.counts.lock().expect("connection counts mutex lock");
+        counts.total = counts.total.saturating_sub(1);
+        if let Some(count) = counts.per_client.get_mut(&client) {
+            *count -= 1;
+            if *count == 0 {
+                counts.per_client.remove(&client);
+            }
+        }
+    }
+
+    /// Wraps an accepted connection. Returns None if a limit is exceeded. The connection is closed then.
+    pub fn admit(&self, stream: TcpStream, remote_addr: SocketAddr) -> Option<LimitedStream> {
+        if !self.acquire(remote_addr.ip()) {
+            warn!("Connection limit reached. Closing the connection of {}", remote_addr);
+            return None;
+        }
+        Some(LimitedStream {
+            stream,
+            remote_addr,
+            limiter: self.clone(),
+            busy: Arc::new(AtomicUsize::new(0)),
+            last_progress: Instant::now(),
+            deadline: None,
+        })
+    }
+}
+
+/// Marks a connection as busy while a long-poll request waits. The read timeout is suspended meanwhile.
+pub struct Busy(Arc<AtomicUsize>);
+
+impl Busy {
+    pub fn new(requests: &Arc<AtomicUsize>) -> Self {
+        requests.fetch_add(1, Ordering::Relaxed);
+        Busy(requests.clone())
+    }
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// An accepted connection with idle timeouts. Releases its connection slot when dropped.
+pub struct LimitedStream {
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+    limiter: ConnectionLimiter,
+    /// Requests in progress, see [`Busy`]
+    busy: Arc<AtomicUsize>,
+    last_progress: Instant,
+    deadline: Option<Delay>,
+}
+
+impl LimitedStream {
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    /// The number of requests in progress. Shared with the request handlers of this connection.
+    pub fn busy(&self) -> Arc<AtomicUsize> {
+        self.busy.clone()
+    }
+
+    fn progress<T>(&mut self, result: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+        if let Poll::Ready(Ok(_)) = result {
+            self.last_progress = Instant::now();
+            self.deadline = None;
+        }
+        result
+    }
+
+    /// Polls the idle deadline. Returns a timeout error if the connection made no progress in time.
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<io::Error> {
+        let deadline = self.last_progress + self.limiter.limits.idle_timeout;
+        let delay = self.deadline.get_or_insert_with(|| delay_until(deadline));
+        if delay.deadline() != deadline {
+            delay.reset(deadline);
+        }
+        match delay.poll_unpin(cx) {
+            Poll::Ready(()) => {
+                info!("Closing the idle connection of {}", self.remote_addr);
+                Poll::Ready(io::Error::new(io::ErrorKind::TimedOut, "Idle connection"))
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for LimitedStream {
+    fn drop(&mut self) {
+        self.limiter.release(self.remote_addr.ip());
+    }
+}
+
+impl AsyncRead for LimitedStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let me = self.get_mut();
+        let result = Pin::new(&mut me.stream).poll_read(cx, buf);
+        let result = me.progress(result);
+        if result.is_pending() && me.busy.load(Ordering::Relaxed) == 0 {
+            if let Poll::Ready(e) = me.poll_idle(cx) {
+                return Poll::Ready(Err(e));
+            }
+        }
+        result
+    }
+}
+
+impl AsyncWrite for LimitedStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let me = self.get_mut();
+        let result = Pin::new(&mut me.stream).poll_write(cx, buf);
+        let result = me.progress(result);
+        if result.is_pending() {
+            if let Poll::Ready(e) = me.poll_idle(cx) {
+                return Poll::Ready(Err(e));
+            }
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_limits() {
+        let limiter = ConnectionLimiter::new(HttpLimits {
+            max_connections: 3,
+            max_connections_per_client: 2,
+            ..HttpLimits::default()
+        });
+        let phone: IpAddr = "192.168.42.10".parse().unwrap();
+        let laptop: IpAddr = "192.168.42.11".parse().unwrap();
+        assert!(limiter.acquire(phone));
+        assert!(limiter.acquire(phone));
+        assert!(!limiter.acquire(phone));
+        assert!(limiter.acquire(laptop));
+        assert!(!limiter.acquire(laptop));
+        limiter.release(phone);
+        assert!(limiter.acquire(laptop));
+    }
+}
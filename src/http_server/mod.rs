@@ -1,5 +1,12 @@
 //! A hyper based http server that serves the "ui" directory. It also provides a server-send-event
-//! endpoint at /events for live updates on discovered access points.
+//! endpoint at /events for live updates on discovered access points and
+//! the list of hotspot clients at /sessions.
+//! The state machine status is available at /api/status, optionally as long-poll request, its history
+//! at /api/events,
+//! the sizes of long-lived internal collections at /api/stats, the state and counters of the wifi interface
+//! at /api/link and both in the Prometheus text format at /metrics.
+//! With the "graphql" feature, the same data and the connect / forget mutations are available at /graphql.
+//! With the "tls" feature, the portal is also served via https with HTTP/2, see [`tls`].
 //!
 //! ## Crossmodule usage
 //! This module uses the crates error type and uses the
@@ -7,40 +14,188 @@
 //! of the network manager module.
 
 use hyper::header::HeaderValue;
-use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, body::HttpBody, Method, Request, Response, Server, StatusCode};
-use std::net::{SocketAddr, SocketAddrV4};
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::net::{IpAddr, SocketAddr, SocketAddrV4};
+use std::sync::{Arc, Mutex};
 use std::path::PathBuf;
 use std::time::Duration;
 use serde::Deserialize;
 
 use futures_util::future::Either;
-use futures_util::future::try_select;
+use futures_util::StreamExt;
 use tokio::time::delay_for;
 
+use super::attempts::ConnectAttemptsSync;
+use super::commands::{Command, CommandSender, Origin, Surface};
+use super::credential_policy::CredentialPolicy;
+use super::device::{self, DeviceIdentity};
 use super::errors::CaptivePortalError;
-use super::network_backend::NetworkBackend;
-use super::network_interface::{WifiConnectionEvent, WifiConnectionEventType, WifiConnections};
+use super::i18n;
+use super::runtime;
+use super::metrics;
+use super::stats;
+use super::network_backend::SharedBackend;
+use super::network_interface::{WifiConnectionEvent, WifiConnections, SSID};
+use super::redirect::RedirectRecords;
+use super::sessions::{DeviceType, Sessions, SessionsSync};
+use super::events::EventLogSync;
+use super::log_filter::LogFilter;
+use super::mac_filter::{MacFilter, MacFilterSync};
+use super::status::StatusReceiver;
+use limits::{Busy, ConnectionLimiter, HttpLimits};
+use probes::ProbeTable;
+use problem::Problem;
+use std::sync::atomic::AtomicUsize;
 
+pub mod api;
+mod encoding;
 mod file_serve;
+#[cfg(feature = "graphql")]
+mod graphql;
+pub mod limits;
+pub mod network_delta;
+pub mod probes;
+mod problem;
 pub(crate) mod sse;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "websocket")]
+mod websocket;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct WifiConnectionRequest {
-    /// wpa, wep, open, enterprise
+    /// wpa, wpa3, wep, open, enterprise, wps (push-button, no passphrase) or dpp (Easy Connect)
     pub mode: String,
-    pub ssid: String,
+    /// Empty in "dpp" mode. A string or the array of octets of a SSID that is not valid UTF8,
+    /// see [`crate::network_interface::WifiConnection`].
+    #[serde(default)]
+    pub ssid: SSID,
     pub identity: Option<String>,
     pub passphrase: Option<String>,
+    /// Enterprise only: The outer identity
+    pub anonymous_identity: Option<String>,
+    /// Enterprise only: peap (default) or ttls
+    pub eap: Option<String>,
     pub hw: Option<String>,
+    /// Static IPv4 address instead of DHCP, see [`crate::ipv4_from_data`]
+    pub ipv4_address: Option<String>,
+    /// The network prefix length of the static address, like 24
+    pub ipv4_prefix: Option<u8>,
+    pub ipv4_gateway: Option<String>,
+    #[serde(default)]
+    pub ipv4_dns: Vec<String>,
+    /// auto, dhcp or static, see [`crate::ipv6_from_data`]
+    pub ipv6_mode: Option<String>,
+    pub ipv6_address: Option<String>,
+    pub ipv6_prefix: Option<u8>,
+    pub ipv6_gateway: Option<String>,
+    #[serde(default)]
+    pub ipv6_dns: Vec<String>,
+    /// The network does not broadcast its SSID, see [`crate::WifiBackend::connect_to`]
+    #[serde(default)]
+    pub hidden: bool,
+    /// Skip the connection quality gate, see [`crate::quality`]
+    #[serde(default)]
+    pub accept_weak: bool,
+    /// The QR code content of the router in "dpp" mode. The SSID is handed out by the router, see [`crate::dpp`].
+    pub dpp_uri: Option<String>,
+    /// The autoconnect priority of the saved connection. Of several saved networks in range, the one with
+    /// the highest priority is used. Requires the NetworkManager backend, see [`crate::WifiBackend::set_priority`].
+    pub priority: Option<i32>,
+    /// Requested by a client that presented the pairing token. Set by the server, see [`api::connect`].
+    #[serde(skip)]
+    pub paired: bool,
+}
+
+/// The connect mode of WPS push-button requests, see [`crate::WifiBackend::connect_wps`]
+pub const WPS_MODE: &str = "wps";
+
+/// The connect mode of Easy Connect requests, see [`crate::WifiBackend::connect_dpp`]
+pub const DPP_MODE: &str = "dpp";
+
+impl WifiConnectionRequest {
+    /// A request to connect via WPS push-button. No passphrase, the router hands it out.
+    pub fn wps(ssid: SSID, hw: Option<String>) -> Self {
+        WifiConnectionRequest::without_credentials(WPS_MODE, ssid, hw)
+    }
+
+    /// A request to enroll via Easy Connect with the given bootstrapping URI. The router hands out SSID and
+    /// passphrase.
+    pub fn dpp(uri: String) -> Self {
+        WifiConnectionRequest {
+            dpp_uri: Some(uri),
+            ..WifiConnectionRequest::without_credentials(DPP_MODE, SSID::default(), None)
+        }
+    }
+
+    fn without_credentials(mode: &str, ssid: SSID, hw: Option<String>) -> Self {
+        WifiConnectionRequest {
+            mode: mode.to_owned(),
+            ssid,
+            identity: None,
+            passphrase: None,
+            anonymous_identity: None,
+            eap: None,
+            hw,
+            ipv4_address: None,
+            ipv4_prefix: None,
+            ipv4_gateway: None,
+            ipv4_dns: Vec::new(),
+            ipv6_mode: None,
+            ipv6_address: None,
+            ipv6_prefix: None,
+            ipv6_gateway: None,
+            ipv6_dns: Vec::new(),
+            hidden: false,
+            accept_weak: false,
+            dpp_uri: None,
+            priority: None,
+            paired: false,
+        }
+    }
+}
+
+/// Parses the body of a "/connect" or "/api/connect" request.
+/// This is the first code path that touches user provided data and is therefore also a fuzz target.
+pub fn parse_connection_request(body: &[u8]) -> Result<WifiConnectionRequest, CaptivePortalError> {
+    Ok(serde_json::from_slice(body)?)
+}
+
+/// The body of "/api/admin/restart-wifi" and "/api/admin/reboot", see [`api::device_action`]
+#[derive(Deserialize, Default)]
+struct Confirmation {
+    #[serde(default)]
+    confirm: String,
+}
+
+/// Upper limit for the "wait" parameter of a status long-poll request
+const MAX_STATUS_WAIT: Duration = Duration::from_secs(60);
+
+/// An accepted connection of the portal, see [`limits`] and, with the "tls" feature, [`tls`]
+#[cfg(feature = "tls")]
+type PortalStream = tls::PortalStream;
+#[cfg(not(feature = "tls"))]
+type PortalStream = limits::LimitedStream;
+
+/// Spawns the connection tasks of hyper onto the current [`tokio::task::LocalSet`].
+/// The http state contains the network backend, which is not `Send` with the "single-threaded" feature.
+#[cfg(feature = "single-threaded")]
+#[derive(Clone, Copy)]
+struct LocalExec;
+
+#[cfg(feature = "single-threaded")]
+impl<F: std::future::Future + 'static> hyper::rt::Executor<F> for LocalExec {
+    fn execute(&self, future: F) {
+        tokio::task::spawn_local(future);
+    }
 }
 
 /// The http server.
 pub struct HttpServer {
     exit_handler: tokio::sync::oneshot::Receiver<()>,
-    connection_receiver: tokio::sync::oneshot::Receiver<Option<WifiConnectionRequest>>,
+    /// Set to true when the server shuts down. Pending long-poll requests return early.
+    closing: tokio::sync::watch::Sender<bool>,
     /// The server state.
     pub state: HttpServerStateSync,
     pub server_addr: SocketAddrV4,
@@ -49,12 +204,56 @@ pub struct HttpServer {
 
 /// The http server state including the wifi connection list.
 pub struct HttpServerState {
-    /// If the user selected a connection in the UI, this sender will be called
-    connection_sender: Option<tokio::sync::oneshot::Sender<Option<WifiConnectionRequest>>>,
+    /// Connect and forget requests are queued for the state machine
+    pub commands: CommandSender,
     pub connections: WifiConnections,
+    /// Revisions of the connections list
+    pub network_revisions: network_delta::NetworkRevisions,
     pub server_addr: SocketAddrV4,
+    /// Captive portal redirects point to this host name instead of the gateway address, if set
+    pub hostname: Option<String>,
     pub sse: sse::Clients,
-    pub network_manager: NetworkBackend,
+    /// Clients of "/ws", see [`websocket`]
+    #[cfg(feature = "websocket")]
+    pub websockets: websocket::Clients,
+    pub network_manager: SharedBackend,
+    /// Hotspot clients. Also shared with the dhcp server.
+    pub sessions: SessionsSync,
+    /// The state machine status bus
+    pub status: StatusReceiver,
+    /// The history of the status changes, served at "/api/events". Shared with the status bus.
+    pub events: EventLogSync,
+    /// The hotspot MAC filter, served at "/api/hotspot/mac-filter". Replaced by the state machine.
+    pub mac_filter: MacFilterSync,
+    /// The replaceable log filter, served at "/api/log-filter"
+    pub log_filter: LogFilter,
+    /// Connection attempts. Also shared with the state machine.
+    pub attempts: ConnectAttemptsSync,
+    /// Requests with this bearer token are authorized as "portal-admin", see [`crate::policy`]
+    pub admin_token: Option<String>,
+    /// Served at "/api/device", see [`crate::device`]
+    pub device: DeviceIdentity,
+    /// Signs the device identity, see [`crate::device::sign`]
+    pub device_sign_command: Option<String>,
+    /// Cached signatures and started sign commands, see [`crate::device::Signatures`]
+    pub device_signatures: device::Signatures,
+    /// The token of the deep link in the device QR code, see [`crate::sessions`]
+    pub pairing_token: Option<String>,
+    /// Outcomes of the captive portal redirect strategies by client fingerprint
+    pub redirects: RedirectRecords,
+    /// Configured probe urls and their responses, see [`probes`]
+    pub probes: ProbeTable,
+    /// Connection limits and timeouts, see [`limits`]. Applied when the server starts.
+    pub limits: HttpLimits,
+    /// The https listener, see [`tls`]. Applied when the server starts.
+    #[cfg(feature = "tls")]
+    pub tls: Option<tls::TlsSettings>,
+    /// Checks the credentials of connect requests, see [`crate::credential_policy`]
+    pub credential_policy: CredentialPolicy,
+    /// The SSIDs of the saved wifi connections, served at "/api/saved".
+    /// Only kept up to date by the NetworkManager backend, see [`set_saved_networks`].
+    pub saved_networks: Vec<SSID>,
+    closing: tokio::sync::watch::Receiver<bool>,
 }
 
 /// The thread safe wrapper around the http server state.
@@ -77,64 +276,581 @@ pub async fn user_requests_wifi_list_refresh(state: HttpServerStateSync) -> Stat
     }
 }
 
-/// Routes to one of the dynamic routes "/networks" (list of wifi networks),
-/// "/events" (server send events), "/refresh" (requests a wifi scan) and "/connect".
-/// "/connect" will exit the http server and make the future of the outer state
-/// machine to resolve.
+/// Returns the percent-decoded value of the given query parameter
+fn query_param(uri: &hyper::Uri, name: &str) -> Option<String> {
+    raw_query_param(uri, name).map(percent_decode)
+}
+
+/// Returns the value of the given query parameter as it is. For values that are not utf8 once decoded,
+/// see [`percent_decode_bytes`].
+fn raw_query_param<'a>(uri: &'a hyper::Uri, name: &str) -> Option<&'a str> {
+    uri.query()?
+        .split('&')
+        .map(|pair| {
+            let mut key_value = pair.splitn(2, '=');
+            (key_value.next().unwrap_or_default(), key_value.next().unwrap_or_default())
+        })
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Decodes a percent-encoded query parameter value. A "+" is a space. Invalid escapes are kept as they are.
+fn percent_decode(value: &str) -> String {
+    String::from_utf8_lossy(&percent_decode_bytes(value)).into_owned()
+}
+
+/// Like [`percent_decode`], but keeps bytes that are not valid utf8, like those of an SSID
+fn percent_decode_bytes(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        // Exactly two hex digits. from_str_radix alone would also accept a sign, like in "%+5".
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|h| h.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            },
+        }
+    }
+    decoded
+}
+
+/// The search, filter and paging parameters of the network list:
+/// "?search=text&security=wpa&min_strength=50&offset=20&limit=20"
+fn network_query(uri: &hyper::Uri) -> api::NetworkQuery {
+    let number = |name| query_param(uri, name).and_then(|v| v.parse::<usize>().ok());
+    api::NetworkQuery {
+        search: query_param(uri, "search").filter(|v| !v.is_empty()),
+        security: query_param(uri, "security").filter(|v| !v.is_empty()),
+        min_strength: query_param(uri, "min_strength").and_then(|v| v.parse::<u8>().ok()),
+        offset: number("offset").unwrap_or(0),
+        limit: number("limit"),
+    }
+}
+
+/// The origin of commands of the given client, see [`crate::commands`].
+/// The request is an admin request if it carries the configured admin token.
+fn origin(state: &HttpServerStateSync, req: &Request<Body>, src: SocketAddr) -> Origin {
+    let mut origin = Origin::new(Surface::Portal, Some(src.ip().to_string()));
+    let token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| v.starts_with("Bearer "))
+        .map(|v| &v[7..]);
+    if let Some(token) = token {
+        let state = state.lock().expect("http state mutex lock");
+        origin.admin = state
+            .admin_token
+            .as_ref()
+            .map(|t| crate::utils::constant_time_eq(t.as_bytes(), token.as_bytes()))
+            .unwrap_or(false);
+    }
+    origin
+}
+
+/// Returns the attempt id of a "/api/connect/{attempt}/cancel" path
+fn cancel_connect_path(path: &str) -> Option<u64> {
+    let mut segments = path.trim_start_matches("/api/connect/").splitn(2, '/');
+    match (path.starts_with("/api/connect/"), segments.next(), segments.next()) {
+        (true, Some(attempt), Some("cancel")) => attempt.parse().ok(),
+        _ => None,
+    }
+}
+
+/// The hand-written request parsers for the fuzz targets in "fuzz/fuzz_targets".
+/// Public with the "fuzz" feature only.
+#[cfg(feature = "fuzz")]
+#[doc(hidden)]
+pub mod fuzz {
+    pub fn query_param(uri: &hyper::Uri, name: &str) -> Option<String> {
+        super::query_param(uri, name)
+    }
+
+    pub fn percent_decode_bytes(value: &str) -> Vec<u8> {
+        super::percent_decode_bytes(value)
+    }
+
+    pub fn network_query(uri: &hyper::Uri) -> super::api::NetworkQuery {
+        super::network_query(uri)
+    }
+
+    pub fn cancel_connect_path(path: &str) -> Option<u64> {
+        super::cancel_connect_path(path)
+    }
+}
+
+/// The language for translated messages and the portal page: The "lang" query parameter, the language the client
+/// picked before or the Accept-Language header. A language of the query parameter is remembered in the session.
+fn request_language(req: &Request<Body>, sessions: &mut Sessions, client: IpAddr) -> &'static str {
+    let requested = query_param(req.uri(), "lang").and_then(|lang| i18n::supported(&lang));
+    if let IpAddr::V4(ip) = client {
+        match requested {
+            Some(lang) => sessions.set_language(ip, lang),
+            None => {
+                if let Some(lang) = sessions.language(ip) {
+                    return lang;
+                }
+            },
+        }
+    }
+    requested.unwrap_or_else(|| {
+        let accept_language = req.headers().get("Accept-Language").and_then(|v| v.to_str().ok());
+        i18n::negotiate(accept_language.unwrap_or_default())
+    })
+}
+
+/// Reads the request body, a stream of chunks of bytes. Fails if the body exceeds the given size.
+async fn read_body(req: Request<Body>, max_size: usize) -> Result<Vec<u8>, CaptivePortalError> {
+    let mut body = req.into_body();
+    let mut output = Vec::new();
+
+    while let Some(data_result) = body.data().await {
+        let bytes = data_result?;
+        if output.len() + bytes.len() > max_size {
+            return Err(CaptivePortalError::PayloadTooLarge(max_size));
+        }
+        output.extend(&bytes[..]);
+    }
+    Ok(output)
+}
+
+/// Routes to one of the dynamic routes "/networks" (list of wifi networks, see [`network_query`] for search
+/// and paging, the number of matching networks is in the "x-total-count" header),
+/// "/events" (server send events), "/ws" (websocket, with the "websocket" feature), "/refresh" (requests a wifi scan),
+/// "/sessions" (hotspot clients, admin only),
+/// "/api/networks" (list of wifi networks with ETag, "?since=revision" for changes only),
+/// "/api/status" (state machine status, "?wait=sec&since=revision" for long-polling),
+/// "/api/events" (history of the status changes, "?after=cursor" for the changes after the last seen one),
+/// "/api/stats" (sizes of long-lived collections), "/api/link" (wifi interface state and counters, null if unknown),
+/// "/metrics" (both in the Prometheus text format),
+/// "/api/channels" (channel utilization report),
+/// "/api/saved" (SSIDs of the saved wifi connections, DELETE with "?ssid=" forgets one),
+/// "/api/saved/secret?ssid=" (the stored passphrase of a saved network, see [`api::saved_secret`]),
+/// "/api/device" (device identity, "?nonce=" for a signed identity), "/api/pairing" (pairing context of the client),
+/// "/api/last-attempt" (the last connection attempt of the client and why it failed, null if none),
+/// "/connect" (also "/api/connect"), "/api/connect/{attempt}/cancel", "/api/connect/accept-weak" (continue with a connection
+/// that failed the quality gate), "/api/hotspot/restart", "/api/hotspot/mac-filter" (GET reads, POST replaces
+/// the MAC filter), "/api/admin/restart-wifi", "/api/admin/reboot" (both confirmed with a body like
+/// `{"confirm":"reboot"}`, see [`api::device_action`]), "/api/log-filter" (GET reads, POST replaces, DELETE
+/// resets the log filter, see [`api::set_log_filter`]) and "/api/update-notice" (POST sets, DELETE removes
+/// the update banner of the embedding firmware).
+/// "/connect" answers with the attempt id and the utilization of the channel of the network.
+/// It queues a connect command for the state machine, which closes the portal.
+///
+/// The connection is marked as busy while a long-poll request waits, see [`limits`].
 async fn http_router(
     state: HttpServerStateSync,
     ui_path: PathBuf,
     req: Request<Body>,
     src: SocketAddr,
+    busy: Arc<AtomicUsize>,
 ) -> Result<Response<Body>, CaptivePortalError> {
     let mut response = Response::new(Body::empty());
 
+    let device = match src.ip() {
+        IpAddr::V4(ip) => {
+            let user_agent = req.headers().get("User-Agent").and_then(|v| v.to_str().ok());
+            let (sessions, pairing_token) = {
+                let state = state.lock().expect("http state mutex lock");
+                (state.sessions.clone(), state.pairing_token.clone())
+            };
+            let mut sessions = sessions.lock().expect("sessions mutex lock");
+            if let (Some(token), Some(expected)) = (query_param(req.uri(), "pair"), pairing_token) {
+                if token == expected {
+                    info!("Client {} paired via the device QR code", ip);
+                    sessions.pair(ip);
+                } else {
+                    warn!("Client {} presented an invalid pairing token", ip);
+                }
+            }
+            sessions.http_seen(ip, user_agent)
+        },
+        IpAddr::V6(_) => DeviceType::Unknown,
+    };
+    let lang = {
+        let sessions = state.lock().expect("http state mutex lock").sessions.clone();
+        let mut sessions = sessions.lock().expect("sessions mutex lock");
+        request_language(&req, &mut sessions, src.ip())
+    };
+
+    #[cfg(feature = "websocket")]
+    {
+        if req.method() == Method::GET && req.uri().path() == "/ws" {
+            return Ok(websocket::upgrade(&state, req, src.ip()));
+        }
+    }
+
     if req.method() == Method::GET {
+        let accept_encoding = req.headers().get("Accept-Encoding").and_then(|v| v.to_str().ok());
         if req.uri().path() == "/networks" {
-            let state = state.lock().expect("http state mutex lock");
-            let data = serde_json::to_string(&state.connections)?;
-            drop(state); // release mutex
-            response
-                .headers_mut()
-                .append("content-type", HeaderValue::from_static("application/json"));
-            *response.body_mut() = Body::from(data);
+            let query = network_query(req.uri());
+            if query.is_empty() {
+                let data = serde_json::to_string(&api::networks(&state))?;
+                encoding::json_body(&mut response, accept_encoding, data);
+                return Ok(response);
+            }
+            let (total, page) = api::networks_page(&state, &query);
+            let data = serde_json::to_string(&page)?;
+            encoding::json_body(&mut response, accept_encoding, data);
+            response.headers_mut().append("x-total-count", HeaderValue::from(total));
             return Ok(response);
         } else if req.uri().path() == "/events" {
             let mut state = state.lock().expect("http state mutex lock");
             let result = sse::create_stream(&mut state.sse, src.ip());
             return Ok(result);
         } else if req.uri().path() == "/refresh" {
-            *response.status_mut() = user_requests_wifi_list_refresh(state.clone()).await;
+            let status = user_requests_wifi_list_refresh(state.clone()).await;
+            if status != StatusCode::OK {
+                let problem = Problem::new("scan-failed", "Scanning is not possible right now", status.as_u16(), None);
+                return Ok(problem.into_response());
+            }
+            return Ok(response);
+        } else if req.uri().path() == "/api/networks" {
+            let since = query_param(req.uri(), "since").and_then(|v| v.parse::<u64>().ok());
+            let (etag, data) = api::network_list(&state, since)?;
+            let if_none_match = req.headers().get("If-None-Match").and_then(|v| v.to_str().ok());
+            if if_none_match == Some(&etag[..]) {
+                *response.status_mut() = StatusCode::NOT_MODIFIED;
+            } else {
+                encoding::json_body(&mut response, accept_encoding, data);
+            }
+            response
+                .headers_mut()
+                .append("etag", HeaderValue::from_str(&etag).expect("Headervalue from generated string"));
+            return Ok(response);
+        } else if req.uri().path() == "/api/status" {
+            let param = |name| query_param(req.uri(), name).and_then(|v| v.parse::<u64>().ok());
+            let wait = Duration::from_secs(param("wait").unwrap_or(0)).min(MAX_STATUS_WAIT);
+            let status = {
+                let _busy = Busy::new(&busy);
+                api::wait_for_status(&state, param("since"), wait, lang).await?
+            };
+            let data = serde_json::to_string(&status)?;
+            response
+                .headers_mut()
+                .append("content-type", HeaderValue::from_static("application/json"));
+            response
+                .headers_mut()
+                .append("cache-control", HeaderValue::from_static("no-cache"));
+            *response.body_mut() = Body::from(data);
+            return Ok(response);
+        } else if req.uri().path() == "/api/events" {
+            let after = query_param(req.uri(), "after").and_then(|v| v.parse::<u64>().ok());
+            let events = state.lock().expect("http state mutex lock").events.clone();
+            let page = events.lock().expect("event log mutex lock").after(after);
+            let data = serde_json::to_string(&page)?;
+            response
+                .headers_mut()
+                .append("content-type", HeaderValue::from_static("application/json"));
+            response
+                .headers_mut()
+                .append("cache-control", HeaderValue::from_static("no-cache"));
+            *response.body_mut() = Body::from(data);
+            return Ok(response);
+        } else if req.uri().path() == "/api/device" {
+            let nonce = query_param(req.uri(), "nonce");
+            if let Some(nonce) = nonce.as_deref().filter(|n| !device::valid_nonce(n)) {
+                let detail = format!(
+                    "Expected up to {} characters of a-z, A-Z, 0-9, - and _, got {}",
+                    device::MAX_NONCE_LEN,
+                    nonce
+                );
+                let problem = Problem::new("invalid-nonce", "Invalid nonce", 400, Some(detail));
+                return Ok(problem.into_response());
+            }
+            let data = match api::device(&state, nonce.as_deref()).await {
+                Ok(Some(device)) => serde_json::to_string(&device)?,
+                Ok(None) => {
+                    let detail = format!("At most {} signatures per minute", device::MAX_SIGNATURES_PER_MINUTE);
+                    let problem = Problem::new("too-many-signatures", "Too many signature requests", 429, Some(detail));
+                    return Ok(problem.into_response());
+                },
+                Err(e) => {
+                    warn!("Failed to sign the device identity: {}", e);
+                    let problem = Problem::new("signing-failed", "The device identity cannot be signed", 500, None);
+                    return Ok(problem.into_response());
+                },
+            };
+            response
+                .headers_mut()
+                .append("content-type", HeaderValue::from_static("application/json"));
+            response
+                .headers_mut()
+                .append("cache-control", HeaderValue::from_static("no-cache"));
+            *response.body_mut() = Body::from(data);
+            return Ok(response);
+        } else if req.uri().path() == "/api/last-attempt" {
+            let data = serde_json::to_string(&api::last_attempt(&state, src.ip(), lang))?;
+            response
+                .headers_mut()
+                .append("content-type", HeaderValue::from_static("application/json"));
+            response
+                .headers_mut()
+                .append("cache-control", HeaderValue::from_static("no-cache"));
+            *response.body_mut() = Body::from(data);
+            return Ok(response);
+        } else if req.uri().path() == "/api/pairing" {
+            let data = serde_json::to_string(&api::pairing(&state, src.ip()))?;
+            response
+                .headers_mut()
+                .append("content-type", HeaderValue::from_static("application/json"));
+            response
+                .headers_mut()
+                .append("cache-control", HeaderValue::from_static("no-cache"));
+            *response.body_mut() = Body::from(data);
+            return Ok(response);
+        } else if req.uri().path() == "/api/channels" {
+            let data = serde_json::to_string(&api::channels(&state))?;
+            encoding::json_body(&mut response, accept_encoding, data);
+            return Ok(response);
+        } else if req.uri().path() == "/api/saved" {
+            let data = serde_json::to_string(&api::saved_networks(&state))?;
+            response
+                .headers_mut()
+                .append("content-type", HeaderValue::from_static("application/json"));
+            response
+                .headers_mut()
+                .append("cache-control", HeaderValue::from_static("no-cache"));
+            *response.body_mut() = Body::from(data);
+            return Ok(response);
+        } else if req.uri().path() == "/api/saved/secret" {
+            let ssid = match raw_query_param(req.uri(), "ssid").map(percent_decode_bytes) {
+                Some(ssid) if !ssid.is_empty() => SSID::from(ssid),
+                _ => {
+                    let detail = "Name the network with \"?ssid=\"".to_owned();
+                    let problem = Problem::new("missing-ssid", "Missing SSID", 400, Some(detail));
+                    return Ok(problem.into_response());
+                },
+            };
+            let secret = api::saved_secret(&state, ssid.clone(), &origin(&state, &req, src)).await?;
+            let data = serde_json::to_string(&serde_json::json!({ "ssid": ssid, "secret": secret }))?;
+            response
+                .headers_mut()
+                .append("content-type", HeaderValue::from_static("application/json"));
+            response
+                .headers_mut()
+                .append("cache-control", HeaderValue::from_static("no-store"));
+            *response.body_mut() = Body::from(data);
+            return Ok(response);
+        } else if req.uri().path() == "/api/stats" {
+            let data = serde_json::to_string(&stats::snapshot())?;
+            response
+                .headers_mut()
+                .append("content-type", HeaderValue::from_static("application/json"));
+            *response.body_mut() = Body::from(data);
+            return Ok(response);
+        } else if req.uri().path() == "/api/link" {
+            let data = serde_json::to_string(&stats::link())?;
+            response
+                .headers_mut()
+                .append("content-type", HeaderValue::from_static("application/json"));
+            response
+                .headers_mut()
+                .append("cache-control", HeaderValue::from_static("no-cache"));
+            *response.body_mut() = Body::from(data);
+            return Ok(response);
+        } else if req.uri().path() == "/metrics" {
+            response.headers_mut().append(
+                "content-type",
+                HeaderValue::from_static("text/plain; version=0.0.4"),
+            );
+            *response.body_mut() = Body::from(metrics::render_text(&metrics::collect()));
+            return Ok(response);
+        } else if req.uri().path() == "/sessions" {
+            let data = serde_json::to_string(&api::sessions(&state, &origin(&state, &req, src))?)?;
+            encoding::json_body(&mut response, accept_encoding, data);
+            return Ok(response);
+        } else if req.uri().path() == "/api/hotspot/mac-filter" {
+            let data = serde_json::to_string(&api::mac_filter(&state, &origin(&state, &req, src))?)?;
+            encoding::json_body(&mut response, accept_encoding, data);
+            return Ok(response);
+        } else if req.uri().path() == "/api/log-filter" {
+            let data = serde_json::to_string(&api::log_filter(&state, &origin(&state, &req, src))?)?;
+            encoding::json_body(&mut response, accept_encoding, data);
             return Ok(response);
         }
 
-        return file_serve::serve_file(&ui_path, response, &req, &state);
+        return file_serve::serve_file(&ui_path, response, &req, &state, src.ip(), device, lang);
     }
-    if req.method() == Method::POST && req.uri().path() == "/connect" {
-        // Body is a stream of chunks of bytes.
-        let mut body = req.into_body();
-        let mut output = Vec::new();
-
-        while let Some(data_result) = body.data().await {
-            let bytes = data_result?;
-            output.extend(&bytes[..]);
+    let origin = origin(&state, &req, src);
+    let max_body_size = state.lock().expect("http state mutex lock").limits.max_body_size;
+    if req.method() == Method::POST && (req.uri().path() == "/connect" || req.uri().path() == "/api/connect") {
+        let output = read_body(req, max_body_size).await?;
+        let parsed = parse_connection_request(&output[..])?;
+        let channel = api::channel_of(&state, &parsed);
+        let attempt = api::connect(&state, parsed, origin)?;
+        let data = serde_json::to_string(&serde_json::json!({ "attempt": attempt, "channel": channel }))?;
+        response
+            .headers_mut()
+            .append("content-type", HeaderValue::from_static("application/json"));
+        *response.body_mut() = Body::from(data);
+        return Ok(response);
+    }
+    if req.method() == Method::POST {
+        if let Some(attempt) = cancel_connect_path(req.uri().path()) {
+            if !api::cancel_connect(&state, attempt, origin)? {
+                let detail = format!("Attempt {} is not pending or running", attempt);
+                let problem = Problem::new("unknown-attempt", "Unknown connection attempt", 404, Some(detail));
+                return Ok(problem.into_response());
+            }
+            return Ok(response);
         }
-
-        let parsed: WifiConnectionRequest = serde_json::from_slice(&output[..])?;
-        let mut state = state.lock().expect("http state mutex lock");
-        let sender = state.connection_sender.take().expect("http state mutex lock");
-        // release mutex as soon as possible
-        drop(state);
-
-        sender
-            .send(Some(parsed))
-            .map_err(|_| CaptivePortalError::HttpRoutingFailed)?;
-        *response.status_mut() = StatusCode::OK;
+        if req.uri().path() == "/api/connect/accept-weak" {
+            let attempt = match api::accept_weak(&state, origin)? {
+                Some(attempt) => attempt,
+                None => {
+                    let problem = Problem::new("no-weak-connection", "No weak connection to accept", 404, None);
+                    return Ok(problem.into_response());
+                },
+            };
+            let data = serde_json::to_string(&serde_json::json!({ "attempt": attempt }))?;
+            response
+                .headers_mut()
+                .append("content-type", HeaderValue::from_static("application/json"));
+            *response.body_mut() = Body::from(data);
+            return Ok(response);
+        }
+        if req.uri().path() == "/api/hotspot/mac-filter" {
+            let filter: MacFilter = serde_json::from_slice(&read_body(req, max_body_size).await?)?;
+            let filter = match filter.normalized() {
+                Ok(filter) => filter,
+                Err(e) => {
+                    let problem = Problem::new("invalid-mac-filter", "Invalid MAC filter", 400, Some(e));
+                    return Ok(problem.into_response());
+                },
+            };
+            let command = api::set_mac_filter(&state, filter, origin)?;
+            let data = serde_json::to_string(&serde_json::json!({ "command": command }))?;
+            response
+                .headers_mut()
+                .append("content-type", HeaderValue::from_static("application/json"));
+            *response.body_mut() = Body::from(data);
+            return Ok(response);
+        }
+        let device_command = match req.uri().path() {
+            "/api/admin/restart-wifi" => Some(Command::RestartWifi),
+            "/api/admin/reboot" => Some(Command::Reboot),
+            _ => None,
+        };
+        if let Some(command) = device_command {
+            let body = read_body(req, max_body_size).await?;
+            let confirmation: Confirmation = serde_json::from_slice(&body).unwrap_or_default();
+            let command = match api::device_action(&state, command, &confirmation.confirm, origin)? {
+                Some(command) => command,
+                None => {
+                    let detail = "Repeat the action name in the \"confirm\" field".to_owned();
+                    let problem = Problem::new("confirmation-required", "Confirmation required", 428, Some(detail));
+                    return Ok(problem.into_response());
+                },
+            };
+            let data = serde_json::to_string(&serde_json::json!({ "command": command }))?;
+            response
+                .headers_mut()
+                .append("content-type", HeaderValue::from_static("application/json"));
+            *response.body_mut() = Body::from(data);
+            return Ok(response);
+        }
+        if req.uri().path() == "/api/hotspot/restart" {
+            let command = api::restart_hotspot(&state, origin)?;
+            let data = serde_json::to_string(&serde_json::json!({ "command": command }))?;
+            response
+                .headers_mut()
+                .append("content-type", HeaderValue::from_static("application/json"));
+            *response.body_mut() = Body::from(data);
+            return Ok(response);
+        }
+    }
+    if req.method() == Method::DELETE && req.uri().path() == "/api/saved" {
+        let ssid = match raw_query_param(req.uri(), "ssid").map(percent_decode_bytes) {
+            Some(ssid) if !ssid.is_empty() => SSID::from(ssid),
+            _ => {
+                let detail = "Name the network with \"?ssid=\"".to_owned();
+                let problem = Problem::new("missing-ssid", "Missing SSID", 400, Some(detail));
+                return Ok(problem.into_response());
+            },
+        };
+        let command = api::forget(&state, ssid, origin)?;
+        let data = serde_json::to_string(&serde_json::json!({ "command": command }))?;
+        response
+            .headers_mut()
+            .append("content-type", HeaderValue::from_static("application/json"));
+        *response.body_mut() = Body::from(data);
+        return Ok(response);
+    }
+    if req.uri().path() == "/api/log-filter" && (req.method() == Method::POST || req.method() == Method::DELETE) {
+        let request = match req.method() == Method::POST {
+            true => Some(serde_json::from_slice(&read_body(req, max_body_size).await?)?),
+            false => None,
+        };
+        let data = serde_json::to_string(&api::set_log_filter(&state, request, origin)?)?;
+        response
+            .headers_mut()
+            .append("content-type", HeaderValue::from_static("application/json"));
+        *response.body_mut() = Body::from(data);
         return Ok(response);
     }
+    if req.uri().path() == "/api/update-notice" && (req.method() == Method::POST || req.method() == Method::DELETE) {
+        let notice = match req.method() == Method::POST {
+            true => Some(serde_json::from_slice(&read_body(req, max_body_size).await?)?),
+            false => None,
+        };
+        let command = api::update_notice(&state, notice, origin)?;
+        let data = serde_json::to_string(&serde_json::json!({ "command": command }))?;
+        response
+            .headers_mut()
+            .append("content-type", HeaderValue::from_static("application/json"));
+        *response.body_mut() = Body::from(data);
+        return Ok(response);
+    }
+    #[cfg(feature = "graphql")]
+    {
+        if req.method() == Method::POST && req.uri().path() == "/graphql" {
+            let output = read_body(req, max_body_size).await?;
+            let (data, ok) = graphql::execute(&state, &output[..], lang, origin).await?;
+            if !ok {
+                *response.status_mut() = StatusCode::BAD_REQUEST;
+            }
+            response
+                .headers_mut()
+                .append("content-type", HeaderValue::from_static("application/json"));
+            *response.body_mut() = Body::from(data);
+            return Ok(response);
+        }
+    }
+
+    Ok(Problem::not_found().into_response())
+}
 
-    *response.status_mut() = StatusCode::NOT_FOUND;
-    Ok(response)
+/// Answers failed requests with an "application/problem+json" response.
+async fn http_service(
+    state: HttpServerStateSync,
+    ui_path: PathBuf,
+    req: Request<Body>,
+    src: SocketAddr,
+    busy: Arc<AtomicUsize>,
+) -> Result<Response<Body>, CaptivePortalError> {
+    let route = format!("{} {}", req.method(), req.uri().path());
+    match http_router(state, ui_path, req, src, busy).await {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            warn!("{}: {}", route, e);
+            Ok(Problem::from(&e).into_response())
+        },
+    }
 }
 
 impl HttpServer {
@@ -142,43 +858,70 @@ impl HttpServer {
         self,
     ) -> (
         tokio::sync::oneshot::Receiver<()>,
-        tokio::sync::oneshot::Receiver<Option<WifiConnectionRequest>>,
+        tokio::sync::watch::Sender<bool>,
         HttpServerStateSync,
         SocketAddrV4,
         PathBuf,
     ) {
         (
             self.exit_handler,
-            self.connection_receiver,
+            self.closing,
             self.state,
             self.server_addr,
             self.ui_path,
         )
     }
 
-    /// Create a new http server. The gateway address and a clone of the network manager is required.
+    /// Create a new http server. The gateway address, a clone of the network manager,
+    /// a status bus subscription, the connection attempts and the command queue of the state machine are required.
     /// If the ui is not compiled in, a valid ui_path must be given as well.
     ///
     /// A tuple (http_server, exit handler) is returned. Call the exit handler for a graceful shutdown.
     pub fn new(
         server_addr: SocketAddrV4,
-        nm: NetworkBackend,
+        nm: SharedBackend,
         ui_path: PathBuf,
+        status: StatusReceiver,
+        attempts: ConnectAttemptsSync,
+        commands: CommandSender,
     ) -> (HttpServer, tokio::sync::oneshot::Sender<()>) {
         let (tx, exit_handler) = tokio::sync::oneshot::channel::<()>();
-        let (connection_sender, connection_receiver) = tokio::sync::oneshot::channel::<Option<WifiConnectionRequest>>();
+        let (closing, closing_receiver) = tokio::sync::watch::channel(false);
 
         (
             HttpServer {
                 exit_handler,
-                connection_receiver,
+                closing,
                 server_addr: server_addr.clone(),
                 state: Arc::new(Mutex::new(HttpServerState {
-                    connection_sender: Some(connection_sender),
+                    commands,
                     network_manager: nm,
                     connections: WifiConnections(Vec::new()),
+                    network_revisions: network_delta::NetworkRevisions::new(),
                     server_addr,
+                    hostname: None,
                     sse: sse::new(),
+                    #[cfg(feature = "websocket")]
+                    websockets: websocket::new(),
+                    sessions: Sessions::new_sync(),
+                    status,
+                    events: EventLogSync::default(),
+                    mac_filter: MacFilterSync::default(),
+                    log_filter: LogFilter::default(),
+                    attempts,
+                    admin_token: None,
+                    device: DeviceIdentity::default(),
+                    device_sign_command: None,
+                    device_signatures: device::Signatures::default(),
+                    pairing_token: None,
+                    redirects: RedirectRecords::default(),
+                    probes: ProbeTable::default(),
+                    limits: HttpLimits::default(),
+                    #[cfg(feature = "tls")]
+                    tls: None,
+                    credential_policy: CredentialPolicy::default(),
+                    saved_networks: Vec::new(),
+                    closing: closing_receiver,
                 })),
                 ui_path,
             },
@@ -187,105 +930,135 @@ impl HttpServer {
     }
 
     /// Consumes the server object and runs it until it receives an exit signal via
-    /// the [`tokio::sync::oneshot::Sender`] returned by [`new`]. Also quits the server
-    /// when
-    pub async fn run(self: HttpServer) -> Result<Option<WifiConnectionRequest>, super::CaptivePortalError> {
+    /// the [`tokio::sync::oneshot::Sender`] returned by [`new`].
+    pub async fn run(self: HttpServer) -> Result<(), super::CaptivePortalError> {
         // Consume the HttpServer by destructuring into its parts
-        let (exit_handler, connection_receiver, state, server_addr, ui_path) = self.into();
+        let (exit_handler, closing, state, server_addr, ui_path) = self.into();
 
         // We need a cloned state for each future in this method
         let state_for_ping = state.clone();
 
-        let make_service = make_service_fn(move |socket: &AddrStream| {
+        let http_limits = state.lock().expect("http state mutex lock").limits.clone();
+        #[cfg(feature = "tls")]
+        let tls_settings = state.lock().expect("http state mutex lock").tls.clone();
+
+        let make_service = make_service_fn(move |socket: &PortalStream| {
             let remote_addr = socket.remote_addr();
+            let busy = socket.busy();
             // There is a future constructed in this future. Time to clone again.
             let state = state.clone();
             let ui_path = ui_path.clone();
             async move {
-                let fun = service_fn(move |req| http_router(state.clone(), ui_path.clone(), req, remote_addr));
+                let fun = service_fn(move |req| {
+                    http_service(state.clone(), ui_path.clone(), req, remote_addr, busy.clone())
+                });
                 Ok::<_, hyper::Error>(fun)
             }
         });
 
-        // Construct server and bind it
-        let server = Server::bind(&SocketAddr::V4(server_addr.clone())).serve(make_service);
-
-        // A graceful shutdown state: This only contains the wifi connection request, if any.
-        type GracefulShutdownRequestState = Option<WifiConnectionRequest>;
-        let graceful_shutdown_state = Arc::new(Mutex::new(GracefulShutdownRequestState::None));
-
-        // The clone will be consumed by the graceful shutdown future
-        let graceful_shutdown_state_clone = graceful_shutdown_state.clone();
+        // Bind and accept connections within the limits. Accept errors (like too many open files) are
+        // logged only, they must not stop the server.
+        let listener = tokio::net::TcpListener::bind(&SocketAddr::V4(server_addr.clone()))
+            .await
+            .map_err(|e| CaptivePortalError::IO(e, "Failed to bind the http server"))?;
+        let limiter = ConnectionLimiter::new(http_limits.clone());
+        #[cfg(feature = "tls")]
+        let tls_limiter = limiter.clone();
+        let incoming = futures_util::stream::unfold(listener, |mut listener| async move {
+            let accepted = listener.accept().await;
+            Some((accepted, listener))
+        })
+        .filter_map(move |accepted| {
+            let stream = match accepted {
+                Ok((stream, remote_addr)) => limiter.admit(stream, remote_addr).map(Ok::<_, std::io::Error>),
+                Err(e) => {
+                    warn!("Failed to accept an http connection: {}", e);
+                    None
+                },
+            };
+            futures_util::future::ready(stream)
+        });
+        // The https listener shares the connection limits with the plain listener
+        #[cfg(feature = "tls")]
+        let incoming = {
+            let incoming = incoming.map(|stream| stream.map(tls::PortalStream::Plain));
+            match tls_settings {
+                Some(settings) => {
+                    let acceptor = tls::acceptor(&settings)?;
+                    let listener = tokio::net::TcpListener::bind(&SocketAddr::V4(settings.server_addr.clone()))
+                        .await
+                        .map_err(|e| CaptivePortalError::IO(e, "Failed to bind the https server"))?;
+                    info!("Started https server on {}", &settings.server_addr);
+                    let tls_incoming = tls::incoming(listener, tls_limiter, acceptor, http_limits.max_connections);
+                    Either::Left(futures_util::stream::select(incoming, tls_incoming))
+                },
+                None => Either::Right(incoming),
+            }
+        };
+        let server = Server::builder(hyper::server::accept::from_stream(incoming))
+            .http1_max_buf_size(limits::MAX_HEADER_SIZE);
+        #[cfg(feature = "single-threaded")]
+        let server = server.executor(LocalExec);
+        let server = server.serve(make_service);
 
-        // Keep alive ping for the server send events stream.
+        // Keep alive ping and status changes for the server send events stream.
         // As usual, also establish a quit channel. Will be called by the graceful shutdown future
         let (keep_alive_exit, keep_alive_exit_handler) = tokio::sync::oneshot::channel::<()>();
-        tokio::spawn(async move {
+        let mut status = state_for_ping.lock().expect("http state mutex lock").status.clone();
+        let mut progress = status.borrow().progress;
+        runtime::spawn_tokio(async move {
+            use futures_util::future::select;
             use pin_utils::pin_mut;
             let mut keep_alive_exit_handler = keep_alive_exit_handler;
             // Endless loop to send ping events ...
             loop {
-                // ... every 2 seconds
+                // ... every 2 seconds, or the status if it changed
                 let sleep = delay_for(Duration::from_secs(2));
                 pin_mut!(sleep);
+                let status_changed = status.recv();
+                pin_mut!(status_changed);
                 // If the exit handler is called or dropped however, quit the loop
-                let r = futures_util::future::select(sleep, &mut keep_alive_exit_handler).await;
-                if let Either::Right(_) = r {
+                let r = select(select(sleep, status_changed), &mut keep_alive_exit_handler).await;
+                let mut state = state_for_ping.lock().expect("http state mutex lock");
+                match r {
                     // Exit handler called
-                    break;
+                    Either::Right(_) => break,
+                    // The status bus is gone, the program exits
+                    Either::Left((Either::Right((None, _)), _)) => break,
+                    Either::Left((Either::Right((Some(status), _)), _)) => {
+                        sse::send_status(&mut state.sse, &status).expect("json encoding failed");
+                        if status.progress != progress {
+                            progress = status.progress;
+                            sse::send_progress(&mut state.sse, &status).expect("json encoding failed");
+                        }
+                    },
+                    Either::Left(_) => sse::ping(&mut state.sse),
                 }
-                let mut state = state_for_ping.lock().expect("http state mutex lock");
-                sse::ping(&mut state.sse);
             }
             // After the not-so-endless loop finished: Close all server-send-event connections.
             // Without closing them, the graceful shutdown future would never resolve.
             let mut state = state_for_ping.lock().expect("http state mutex lock");
             sse::close_all(&mut state.sse);
+            #[cfg(feature = "websocket")]
+            websocket::close_all(&mut state.websockets);
+            state.redirects.store(true);
+            state.sessions.lock().expect("sessions mutex lock").store(true);
         });
 
         let graceful = server.with_graceful_shutdown(async move {
-            // We either shutdown when the exit_handler got called OR when we received a connection
-            // request by the user.
-            let r = try_select(exit_handler, connection_receiver).await;
-
-            match r {
-                Err(_) => {
-                    // The http exit handler has been dropped. Time to leave this future.
-                    return;
-                },
-                Ok(r) => {
-                    // select/try_select return an Either. If it's the right side of the Either (received connection),
-                    // we extract that connection and assign it to the GracefulShutdownState.
-                    // That object is a thread safe requested-connection wrapper and our way of communicating
-                    // a state out of this future.
-                    match r {
-                        Either::Right((f, _receiver)) => {
-                            let mut shutdown_state = graceful_shutdown_state_clone
-                                .lock()
-                                .expect("Mutex lock for http server state on graceful shutdown");
-                            *shutdown_state = f;
-                            info!("Received connect state {:?}", *shutdown_state);
-                        },
-                        // The http exit handler has been been activated. Time to leave this future.
-                        _ => (),
-                    };
-                },
-            }
+            // Shutdown when the exit_handler got called or dropped
+            let _ = exit_handler.await;
 
-            // Stop server-send-events keep alive and refresh request future
+            // Stop server-send-events keep alive and refresh request future. Return pending long-poll requests.
             let _ = keep_alive_exit.send(());
+            let _ = closing.broadcast(true);
             ()
         });
 
         info!("Started http server on {}", &server_addr);
         graceful.await?;
         info!("Stopped http server on {}", &server_addr);
-
-        // Extract the graceful shutdown state
-        let mut state: MutexGuard<GracefulShutdownRequestState> = graceful_shutdown_state
-            .lock()
-            .expect("http server mutex lock for return value");
-        Ok(state.take())
+        Ok(())
     }
 }
 
@@ -293,25 +1066,87 @@ impl HttpServer {
 pub async fn update_network(http_state: HttpServerStateSync, event: WifiConnectionEvent) {
     let mut state = http_state.lock().expect("Mutex lock for http state on update_network");
     info!("Add network {}", &event.access_point.ssid);
-    let ref mut connections = state.connections.0;
-    match connections.iter().position(|n| n.ssid == event.access_point.ssid) {
-        Some(pos) => {
-            match event.event {
-                WifiConnectionEventType::Added => {
-                    use std::mem;
-                    let dest = connections
-                        .get_mut(pos)
-                        .expect("update_network: Vector access on connections");
-                    mem::replace(dest, event.access_point.clone());
-                },
-                WifiConnectionEventType::Removed => {
-                    connections.remove(pos);
-                },
-            };
-        },
-        None => {
-            state.connections.0.push(event.access_point.clone());
-        },
-    };
+    #[cfg(feature = "websocket")]
+    let since = state.network_revisions.revision();
+    if let Some(change) = state.connections.update(&event) {
+        state.network_revisions.record(&event.access_point.ssid, change);
+        #[cfg(feature = "websocket")]
+        {
+            let HttpServerState {
+                websockets,
+                network_revisions,
+                connections,
+                ..
+            } = &mut *state;
+            let delta = network_revisions.list(connections, Some(since));
+            websocket::send_networks(websockets, &delta).expect("json encoding failed");
+        }
+    }
     sse::send_wifi_connection(&mut state.sse, &event).expect("json encoding failed");
 }
+
+/// Replaces the saved networks, for example after an administrator added a connection with nmcli.
+/// Clients are informed with the server sent event "saved".
+pub fn set_saved_networks(http_state: &HttpServerStateSync, saved: Vec<SSID>) {
+    let mut state = http_state.lock().expect("Mutex lock for http state on set_saved_networks");
+    if state.saved_networks == saved {
+        return;
+    }
+    state.saved_networks = saved;
+    let HttpServerState { sse, saved_networks, .. } = &mut *state;
+    sse::send_saved_networks(sse, saved_networks).expect("json encoding failed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_query_parameters() {
+        let uri: hyper::Uri = "/networks?search=Caf%C3%A9+Wifi&security=wpa&offset=20&limit=x".parse().unwrap();
+        let query = network_query(&uri);
+        assert_eq!(query.search.as_deref(), Some("Café Wifi"));
+        assert_eq!(query.security.as_deref(), Some("wpa"));
+        assert_eq!(query.offset, 20);
+        assert_eq!(query.limit, None);
+        assert!(network_query(&"/networks?search=".parse().unwrap()).is_empty());
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%41"), "%zzA");
+        assert_eq!(percent_decode("%+5%-1"), "% 5%-1");
+        assert_eq!(percent_decode_bytes("Caf%E9"), b"Caf\xe9".to_vec());
+    }
+
+    /// A client that sends the headers of a request and then stalls the body is closed after the idle timeout
+    #[tokio::test]
+    async fn stalled_body() {
+        use tokio::io::AsyncWriteExt;
+        let limiter = ConnectionLimiter::new(HttpLimits {
+            idle_timeout: Duration::from_millis(200),
+            ..HttpLimits::default()
+        });
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, remote_addr) = listener.accept().await.unwrap();
+        let stream = limiter.admit(stream, remote_addr).unwrap();
+        let service = service_fn(|req| async move {
+            let body = read_body(req, 1024).await?;
+            Ok::<_, CaptivePortalError>(Response::new(Body::from(body)))
+        });
+        let connection = hyper::server::conn::Http::new().serve_connection(stream, service);
+
+        let request = b"POST /connect HTTP/1.1\r\nHost: portal\r\nContent-Length: 10\r\n\r\n{";
+        client.write_all(request).await.unwrap();
+        let closed = tokio::time::timeout(Duration::from_secs(5), connection).await;
+        assert!(closed.expect("The stalled connection must be closed").is_err());
+    }
+
+    #[test]
+    fn decoded_query_parameters() {
+        let uri: hyper::Uri = "/api/status?wait=%33%30&since=%31%32&lang=he".parse().unwrap();
+        assert_eq!(query_param(&uri, "wait").and_then(|v| v.parse::<u64>().ok()), Some(30));
+        assert_eq!(query_param(&uri, "since").as_deref(), Some("12"));
+        assert_eq!(query_param(&uri, "lang").as_deref(), Some("he"));
+        assert_eq!(query_param(&uri, "after"), None);
+        assert_eq!(raw_query_param(&uri, "wait"), Some("%33%30"));
+    }
+}
@@ -0,0 +1,175 @@
+//! # Network list revisions
+//! Every change of the aggregated network list increments a revision. The revision is used as ETag
+//! for the network list and clients can request only the entries that changed since a known revision.
+//!
+//! Revisions start at the creation time of the list in milliseconds, so that revisions of a previous
+//! portal session are never mistaken for current ones.
+
+use crate::network_interface::{WifiConnection, WifiConnectionChange, WifiConnections, SSID};
+use crate::stats;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of remembered changes. Clients with an older revision receive the full list.
+const MAX_CHANGES: usize = 512;
+
+pub struct NetworkRevisions {
+    revision: u64,
+    changes: VecDeque<(u64, SSID, WifiConnectionChange)>,
+}
+
+/// The network list, either complete or the changes since the requested revision.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum NetworkList<'a> {
+    Full {
+        revision: u64,
+        networks: &'a [WifiConnection],
+    },
+    Delta {
+        revision: u64,
+        added: Vec<&'a WifiConnection>,
+        changed: Vec<&'a WifiConnection>,
+        /// The SSIDs for display, like the "ssid" field of the networks
+        removed: Vec<Cow<'a, str>>,
+    },
+}
+
+impl NetworkRevisions {
+    pub fn new() -> Self {
+        let revision = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        NetworkRevisions {
+            revision,
+            changes: VecDeque::new(),
+        }
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// The ETag header value for the current revision
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.revision)
+    }
+
+    pub fn record(&mut self, ssid: &SSID, change: WifiConnectionChange) {
+        self.revision += 1;
+        if self.changes.len() == MAX_CHANGES {
+            self.changes.pop_front();
+        }
+        self.changes.push_back((self.revision, ssid.clone(), change));
+        stats::record(stats::Collection::NetworkHistory, self.changes.len());
+    }
+
+    /// Returns the changes since the given revision or the full list if that revision is unknown.
+    pub fn list<'a>(&'a self, connections: &'a WifiConnections, since: Option<u64>) -> NetworkList<'a> {
+        let oldest_known = self.changes.front().map(|c| c.0 - 1).unwrap_or(self.revision);
+        let since = match since {
+            Some(since) if since >= oldest_known && since <= self.revision => since,
+            _ => {
+                return NetworkList::Full {
+                    revision: self.revision,
+                    networks: &connections.0,
+                };
+            },
+        };
+
+        let mut added = Vec::new();
+        let mut changed = Vec::new();
+        let mut removed = Vec::new();
+        // Per SSID: The first change tells if it existed at "since", the current list tells if it exists now.
+        let mut seen: Vec<&SSID> = Vec::new();
+        for (_, ssid, first_change) in self.changes.iter().filter(|c| c.0 > since) {
+            if seen.contains(&ssid) {
+                continue;
+            }
+            seen.push(ssid);
+            let existed = *first_change != WifiConnectionChange::Added;
+            match (existed, connections.0.iter().find(|c| &c.ssid == ssid)) {
+                (false, Some(connection)) => added.push(connection),
+                (true, Some(connection)) => changed.push(connection),
+                (true, None) => removed.push(ssid.to_string_lossy()),
+                (false, None) => {},
+            }
+        }
+        NetworkList::Delta {
+            revision: self.revision,
+            added,
+            changed,
+            removed,
+        }
+    }
+}
+
+impl Default for NetworkRevisions {
+    fn default() -> Self {
+        NetworkRevisions::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_interface::{WifiConnectionEvent, WifiConnectionEventType};
+
+    fn event(ssid: &str, event: WifiConnectionEventType) -> WifiConnectionEvent {
+        WifiConnectionEvent {
+            access_point: WifiConnection {
+                ssid: ssid.into(),
+                hw: "00:11:22:33:44:55".to_owned(),
+                security: "wpa",
+                strength: 50,
+                frequency: 2412,
+                is_own: false,
+                vendor: None,
+            },
+            event,
+        }
+    }
+
+    fn apply(connections: &mut WifiConnections, revisions: &mut NetworkRevisions, event: WifiConnectionEvent) {
+        if let Some(change) = connections.update(&event) {
+            revisions.record(&event.access_point.ssid, change);
+        }
+    }
+
+    #[test]
+    fn delta() {
+        let mut connections = WifiConnections(Vec::new());
+        let mut revisions = NetworkRevisions::new();
+        apply(&mut connections, &mut revisions, event("a", WifiConnectionEventType::Added));
+        apply(&mut connections, &mut revisions, event("b", WifiConnectionEventType::Added));
+        let since = revisions.revision();
+        apply(&mut connections, &mut revisions, event("c", WifiConnectionEventType::Added));
+        apply(&mut connections, &mut revisions, event("a", WifiConnectionEventType::Added));
+        apply(&mut connections, &mut revisions, event("b", WifiConnectionEventType::Removed));
+        apply(&mut connections, &mut revisions, event("d", WifiConnectionEventType::Added));
+        apply(&mut connections, &mut revisions, event("d", WifiConnectionEventType::Removed));
+
+        match revisions.list(&connections, Some(since)) {
+            NetworkList::Delta {
+                revision,
+                added,
+                changed,
+                removed,
+            } => {
+                assert_eq!(revision, since + 5);
+                assert_eq!(added.iter().map(|c| c.ssid.to_string()).collect::<Vec<_>>(), vec!["c"]);
+                assert_eq!(changed.iter().map(|c| c.ssid.to_string()).collect::<Vec<_>>(), vec!["a"]);
+                assert_eq!(removed, vec!["b"]);
+            },
+            NetworkList::Full { .. } => panic!("Expected a delta"),
+        }
+
+        match revisions.list(&connections, Some(since - 100)) {
+            NetworkList::Full { networks, .. } => assert_eq!(networks.len(), 2),
+            NetworkList::Delta { .. } => panic!("Expected the full list"),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! # Configured probe responses
+//! Connectivity checks of known operating systems are caught by the portal anyway (see [`crate::redirect`]).
+//! Some embedded webviews and kiosk clients probe their own urls and expect a specific answer, for example
+//! a "204 No Content" of their vendor host, before they show the portal.
+//!
+//! Operators extend the probe table without recompiling via `--probe-file`: A JSON list of rules like
+//! `{"host": "check.example.com", "path": "/ping", "status": 204}`. A rule without a host matches every host.
+//! A rule without a status answers with the portal, like the connectivity checks of known devices.
+//! The file is read again if it changed, at most every [`RELOAD_INTERVAL`].
+
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the modification time of the probe file is checked
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A probe url and its response
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct ProbeRule {
+    /// The host without port. Matches every host if not set.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// The path, like "/generate_204"
+    pub path: String,
+    /// The status code of the response. The portal response if not set.
+    #[serde(default)]
+    pub status: Option<u16>,
+    #[serde(default)]
+    pub body: String,
+    /// The content type of the body. Defaults to "text/plain".
+    #[serde(default)]
+    pub content_type: Option<String>,
+}
+
+impl ProbeRule {
+    fn matches(&self, host: Option<&str>, path: &str) -> bool {
+        let host_matches = match (self.host.as_ref(), host) {
+            (None, _) => true,
+            (Some(expected), Some(host)) => expected.eq_ignore_ascii_case(host),
+            (Some(_), None) => false,
+        };
+        host_matches && self.path == path
+    }
+}
+
+/// The configured probe rules and the file they are read from
+#[derive(Default)]
+pub struct ProbeTable {
+    rules: Vec<ProbeRule>,
+    file: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    checked: Option<Instant>,
+}
+
+impl ProbeTable {
+    /// Reads the rules of the given file. If the file cannot be read, the table is empty
+    /// until the file is fixed.
+    pub fn with_file(path: PathBuf) -> Self {
+        let mut table = ProbeTable {
+            file: Some(path),
+            ..Default::default()
+        };
+        table.reload_if_changed();
+        table
+    }
+
+    /// Reads the file again if its modification time changed. Errors are logged and the previous rules are kept.
+    pub fn reload_if_changed(&mut self) {
+        let path = match self.file.as_ref() {
+            Some(path) => path,
+            None => return,
+        };
+        if self.checked.map_or(false, |checked| checked.elapsed() < RELOAD_INTERVAL) {
+            return;
+        }
+        self.checked = Some(Instant::now());
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        if modified.is_none() || modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        let rules = std::fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| parse(&data));
+        match rules {
+            Ok(rules) => {
+                info!("Loaded {} probe rules from {}", rules.len(), path.display());
+                self.rules = rules;
+            },
+            Err(e) => warn!("{}: {}", path.display(), e),
+        }
+    }
+
+    /// The rule of the given request. The host may contain a port.
+    pub fn lookup(&self, host: Option<&str>, path: &str) -> Option<&ProbeRule> {
+        let host = host.map(|h| h.rsplitn(2, ':').last().unwrap_or(h));
+        self.rules.iter().find(|rule| rule.matches(host, path))
+    }
+}
+
+fn parse(data: &[u8]) -> Result<Vec<ProbeRule>, String> {
+    let rules: Vec<ProbeRule> = serde_json::from_slice(data).map_err(|e| e.to_string())?;
+    match rules.iter().find(|r| !r.path.starts_with('/')) {
+        Some(rule) => Err(format!("The path {} does not start with /", rule.path)),
+        None => Ok(rules),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_rules() {
+        let data = br#"[
+            {"host": "check.example.com", "path": "/ping", "status": 204},
+            {"path": "/kiosk/online"}
+        ]"#;
+        let table = ProbeTable {
+            rules: parse(data).unwrap(),
+            ..Default::default()
+        };
+        let rule = table.lookup(Some("CHECK.example.com:80"), "/ping").unwrap();
+        assert_eq!(rule.status, Some(204));
+        assert!(table.lookup(Some("other.example.com"), "/ping").is_none());
+        assert!(table.lookup(None, "/ping").is_none());
+        assert_eq!(table.lookup(Some("any.host"), "/kiosk/online").unwrap().status, None);
+
+        assert!(parse(br#"[{"path": "ping"}]"#).is_err());
+    }
+}
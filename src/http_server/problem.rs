@@ -0,0 +1,75 @@
+//! # Error responses
+//! Errors of the REST routes are answered with an RFC 7807 "application/problem+json" body.
+//! The "type" is a link to the error code in doc/errors.md, see [`CaptivePortalError::problem`].
+
+use crate::credential_policy::Violation;
+use crate::CaptivePortalError;
+use hyper::{Body, Response, StatusCode};
+use serde::Serialize;
+
+const TYPE_BASE_URI: &str = "https://github.com/openhab-nodes/wifi-captive-rs/blob/master/doc/errors.md#";
+
+#[derive(Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub type_uri: String,
+    pub title: &'static str,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// The rejected form field of a "credential-policy" problem
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub violation: Option<Violation>,
+}
+
+impl Problem {
+    pub fn new(code: &str, title: &'static str, status: u16, detail: Option<String>) -> Self {
+        Problem {
+            type_uri: format!("{}{}", TYPE_BASE_URI, code),
+            title,
+            status,
+            detail,
+            violation: None,
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Problem::new("not-found", "Not found", 404, None)
+    }
+
+    pub fn into_response(self) -> Response<Body> {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let body = serde_json::to_string(&self).unwrap_or_default();
+        Response::builder()
+            .status(status)
+            .header("content-type", "application/problem+json")
+            .body(Body::from(body))
+            .expect("Could not create response")
+    }
+}
+
+impl From<&CaptivePortalError> for Problem {
+    fn from(error: &CaptivePortalError) -> Self {
+        let (code, title, status) = error.problem();
+        let mut problem = Problem::new(code, title, status, Some(error.to_string()));
+        if let CaptivePortalError::CredentialPolicy(ref violation) = *error {
+            problem.violation = Some(violation.clone());
+        }
+        problem
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn problem_json() {
+        let problem = Problem::from(&CaptivePortalError::NoSharedKeyProvided);
+        let json: serde_json::Value = serde_json::to_value(&problem).unwrap();
+        assert_eq!(json["type"], format!("{}passphrase-required", TYPE_BASE_URI));
+        assert_eq!(json["status"], 400);
+        assert_eq!(json["title"], "Passphrase required");
+        assert_eq!(json["detail"], "Passphrase required!");
+    }
+}
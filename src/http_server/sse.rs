@@ -8,7 +8,10 @@ use hyper::{Body, Response};
 use bytes::Bytes;
 use std::net::IpAddr;
 
-use crate::network_interface::WifiConnectionEvent;
+use crate::network_interface::{WifiConnectionEvent, SSID};
+use crate::stats;
+use crate::status::{Progress, Status};
+use serde::Serialize;
 use std::collections::LinkedList;
 
 pub type Clients = LinkedList<Client>;
@@ -28,6 +31,7 @@ pub fn ping(clients: &mut Clients) {
 }
 
 pub fn close_all(clients: &mut Clients) {
+    stats::record(stats::Collection::SseClients, 0);
     for client in clients.drain_filter(|_| true) {
         client.tx.abort();
     }
@@ -46,6 +50,48 @@ pub fn send_wifi_connection(
     Ok(())
 }
 
+pub fn send_saved_networks(clients: &mut Clients, saved: &[SSID]) -> Result<(), serde_json::error::Error> {
+    let message = format!("retry: 3000\nevent: saved\ndata: {}\n\n", serde_json::to_string(saved)?);
+    push_to_all_clients(clients, message);
+    Ok(())
+}
+
+pub fn send_status(clients: &mut Clients, status: &Status) -> Result<(), serde_json::error::Error> {
+    let message = format!(
+        "retry: 3000\nevent: status\ndata: {}\n\n",
+        serde_json::to_string(status)?
+    );
+    push_to_all_clients(clients, message);
+    Ok(())
+}
+
+/// The data of the "progress" event
+#[derive(Serialize)]
+struct ProgressEvent<'a> {
+    stage: Progress,
+    ssid: &'a Option<String>,
+    /// The message key of the failure reason, if the stage is "failed"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+}
+
+/// Sends the stage of the current connection attempt, if there is one. The portal page shows it
+/// while it waits for the outcome of the attempt.
+pub fn send_progress(clients: &mut Clients, status: &Status) -> Result<(), serde_json::error::Error> {
+    let stage = match status.progress {
+        Some(stage) => stage,
+        None => return Ok(()),
+    };
+    let event = ProgressEvent {
+        stage,
+        ssid: &status.ssid,
+        reason: status.error.filter(|_| stage == Progress::Failed),
+    };
+    let message = format!("retry: 3000\nevent: progress\ndata: {}\n\n", serde_json::to_string(&event)?);
+    push_to_all_clients(clients, message);
+    Ok(())
+}
+
 /// Push a message for the event to all clients registered on the channel.
 ///
 /// The message is first serialized and then send to all registered
@@ -65,6 +111,7 @@ fn push_to_all_clients(clients: &mut Clients, chunk: String) {
         info!("SSE Client drop: {:?}", &client.dest);
         client.tx.abort();
     }
+    stats::record(stats::Collection::SseClients, clients.len());
 }
 
 /// Initiate a new SSE stream for the given request and request IP.
@@ -78,6 +125,7 @@ pub fn create_stream(clients: &mut Clients, src: IpAddr) -> Response<Body> {
         client.tx.abort();
     }
     clients.push_back(Client { tx: sender, dest: src });
+    stats::record(stats::Collection::SseClients, clients.len());
 
     info!("SSE Client added: {:?}. Clients: {}", src, clients.len());
 
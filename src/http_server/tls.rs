@@ -0,0 +1,178 @@
+//! # TLS listener
+//! With the "tls" feature and a configured certificate and key, the portal is also served via https on its
+//! own port. The TLS handshake offers "h2" and "http/1.1" via ALPN. Captive webviews that load the ui with
+//! many parallel asset requests multiplex them over one HTTP/2 connection instead of opening a connection
+//! per asset, and stay within the per client connection limit (see [`super::limits`]).
+//!
+//! Hyper does not implement the extended CONNECT method (RFC 8441), so "/ws" is not offered over HTTP/2.
+//! Browsers fall back to a separate HTTP/1.1 connection for the websocket, which is served by the same listener.
+//!
+//! Captive portal probes are plain http requests, the plain http listener is always running.
+
+use super::limits::{ConnectionLimiter, LimitedStream};
+use crate::CaptivePortalError;
+use futures_util::{Stream, StreamExt};
+use std::io;
+use std::net::{SocketAddr, SocketAddrV4};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// The protocols offered via ALPN, in the order of preference
+const ALPN_PROTOCOLS: [&[u8]; 2] = [b"h2", b"http/1.1"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsSettings {
+    pub server_addr: SocketAddrV4,
+    /// PEM encoded certificate chain
+    pub certificate: PathBuf,
+    /// PEM encoded PKCS#8 or RSA private key
+    pub key: PathBuf,
+}
+
+/// A connection of the plain http listener or of the TLS listener
+pub enum PortalStream {
+    Plain(LimitedStream),
+    Tls(TlsStream<LimitedStream>),
+}
+
+impl PortalStream {
+    fn limited(&self) -> &LimitedStream {
+        match self {
+            PortalStream::Plain(stream) => stream,
+            PortalStream::Tls(stream) => stream.get_ref().0,
+        }
+    }
+
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.limited().remote_addr()
+    }
+
+    /// See [`LimitedStream::busy`]
+    pub fn busy(&self) -> Arc<AtomicUsize> {
+        self.limited().busy()
+    }
+}
+
+impl AsyncRead for PortalStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            PortalStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            PortalStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for PortalStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            PortalStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            PortalStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            PortalStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            PortalStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            PortalStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            PortalStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+fn open(path: &Path) -> Result<io::BufReader<std::fs::File>, CaptivePortalError> {
+    let file = std::fs::File::open(path).map_err(|e| CaptivePortalError::IO(e, "Failed to open a TLS file"))?;
+    Ok(io::BufReader::new(file))
+}
+
+/// Loads the certificate chain and the private key and offers h2 and http/1.1 via ALPN
+pub fn acceptor(settings: &TlsSettings) -> Result<TlsAcceptor, CaptivePortalError> {
+    let invalid = |what: &str, path: &Path| CaptivePortalError::Generic(format!("No {} in {}", what, path.display()));
+
+    let certificates = pemfile::certs(&mut open(&settings.certificate)?)
+        .ok()
+        .filter(|certificates| !certificates.is_empty())
+        .ok_or_else(|| invalid("PEM certificate", &settings.certificate))?;
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(&settings.key)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(&settings.key)?).unwrap_or_default();
+    }
+    let key = keys.pop().ok_or_else(|| invalid("PEM private key", &settings.key))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(certificates, key)
+        .map_err(|e| CaptivePortalError::Generic(format!("Invalid TLS certificate or key: {}", e)))?;
+    config.set_protocols(&ALPN_PROTOCOLS.iter().map(|p| p.to_vec()).collect::<Vec<_>>());
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Accepts connections within the limits and performs the TLS handshakes.
+/// Handshakes run concurrently, a stalled client is closed by the idle timeout of its [`LimitedStream`].
+/// Failed handshakes are logged only.
+pub fn incoming(
+    listener: TcpListener,
+    limiter: ConnectionLimiter,
+    acceptor: TlsAcceptor,
+    max_handshakes: usize,
+) -> impl Stream<Item = Result<PortalStream, io::Error>> {
+    futures_util::stream::unfold(listener, |mut listener| async move {
+        let accepted = listener.accept().await;
+        Some((accepted, listener))
+    })
+    .filter_map(move |accepted| {
+        let stream = match accepted {
+            Ok((stream, remote_addr)) => limiter.admit(stream, remote_addr),
+            Err(e) => {
+                warn!("Failed to accept a https connection: {}", e);
+                None
+            },
+        };
+        futures_util::future::ready(stream)
+    })
+    .map(move |stream| {
+        let remote_addr = stream.remote_addr();
+        let handshake = acceptor.accept(stream);
+        async move { (handshake.await, remote_addr) }
+    })
+    .buffer_unordered(max_handshakes.max(1))
+    .filter_map(|(handshake, remote_addr)| {
+        let stream = match handshake {
+            Ok(stream) => Some(Ok(PortalStream::Tls(stream))),
+            Err(e) => {
+                info!("TLS handshake with {} failed: {}", remote_addr, e);
+                None
+            },
+        };
+        futures_util::future::ready(stream)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_files() {
+        let settings = TlsSettings {
+            server_addr: "192.168.42.1:443".parse().unwrap(),
+            certificate: PathBuf::from("/nonexistent/portal.crt"),
+            key: PathBuf::from("/nonexistent/portal.key"),
+        };
+        assert!(acceptor(&settings).is_err());
+    }
+}
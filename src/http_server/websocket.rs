@@ -0,0 +1,209 @@
+//! # WebSocket endpoint
+//! Richer front-ends, like kiosk UIs, embed the provisioning flow via "/ws" instead of combining the REST routes
+//! with the server send events. Every message is a json object with a "type":
+//!
+//! * "networks": The complete network list after the connection has been established, then the changes,
+//!   like "/api/networks?since=revision", see [`NetworkList`].
+//! * "status": The state machine status after the connection has been established and on every change,
+//!   like "/api/status".
+//!
+//! Messages of the client are ignored, pings are answered by the websocket implementation. The server pings the
+//! client every 10 seconds, so that a listening client stays within the idle timeout of the connection limits
+//! (see [`super::limits`]) while the networks do not change.
+//! Available with the "websocket" feature.
+
+use super::network_delta::NetworkList;
+use super::problem::Problem;
+use super::HttpServerStateSync;
+use crate::runtime;
+use crate::status::{Status, StatusReceiver};
+use futures_util::future::{select, Either};
+use futures_util::{SinkExt, StreamExt};
+use hyper::header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
+use hyper::{Body, Request, Response, StatusCode};
+use pin_utils::pin_mut;
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio::time::{interval_at, Instant};
+use tokio_tungstenite::tungstenite::protocol::{Message, Role, WebSocketConfig};
+use tokio_tungstenite::WebSocketStream;
+
+/// Appended to the key of the client for the accept header, see RFC 6455
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Below the idle timeout of the connection limits. The pong of the client counts as activity.
+const PING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Client messages are ignored. Larger messages and frames close the connection.
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+pub type Clients = Vec<Client>;
+
+#[derive(Debug)]
+pub struct Client {
+    tx: mpsc::UnboundedSender<String>,
+    dest: IpAddr,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WsMessage<'a> {
+    Networks(&'a NetworkList<'a>),
+    Status(&'a Status),
+}
+
+pub fn new() -> Clients {
+    Vec::new()
+}
+
+/// Sends the changes of the network list to all clients. Clients that are gone are removed.
+pub fn send_networks(clients: &mut Clients, list: &NetworkList) -> Result<(), serde_json::error::Error> {
+    let message = serde_json::to_string(&WsMessage::Networks(list))?;
+    clients.retain(|client| match client.tx.send(message.clone()) {
+        Ok(()) => true,
+        Err(_) => {
+            info!("WebSocket client drop: {:?}", &client.dest);
+            false
+        },
+    });
+    Ok(())
+}
+
+/// Closes the connections of all clients
+pub fn close_all(clients: &mut Clients) {
+    clients.clear();
+}
+
+fn accept_key(key: &[u8]) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.input(key);
+    sha1.input(ACCEPT_GUID.as_bytes());
+    base64::encode(&sha1.result())
+}
+
+/// Answers the upgrade request of a websocket client and serves the connection after the upgrade.
+pub fn upgrade(state: &HttpServerStateSync, req: Request<Body>, src: IpAddr) -> Response<Body> {
+    let is_websocket = req
+        .headers()
+        .get(UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| v.eq_ignore_ascii_case("websocket"));
+    let accept = match req.headers().get(SEC_WEBSOCKET_KEY) {
+        Some(key) if is_websocket => accept_key(key.as_bytes()),
+        _ => {
+            let detail = "Connect with a websocket client".to_owned();
+            return Problem::new("websocket-required", "WebSocket upgrade required", 400, Some(detail))
+                .into_response();
+        },
+    };
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let (status, networks) = {
+        let mut state = state.lock().expect("http state mutex lock");
+        let list = state.network_revisions.list(&state.connections, None);
+        let networks = serde_json::to_string(&WsMessage::Networks(&list)).expect("json encoding failed");
+        state.websockets.push(Client { tx, dest: src });
+        info!("WebSocket client added: {:?}. Clients: {}", src, state.websockets.len());
+        (state.status.clone(), networks)
+    };
+
+    runtime::spawn_tokio(async move {
+        match req.into_body().on_upgrade().await {
+            Ok(upgraded) => {
+                let config = WebSocketConfig {
+                    max_message_size: Some(MAX_MESSAGE_SIZE),
+                    max_frame_size: Some(MAX_MESSAGE_SIZE),
+                    ..WebSocketConfig::default()
+                };
+                let ws = WebSocketStream::from_raw_socket(upgraded, Role::Server, Some(config)).await;
+                serve(ws, rx, status, networks).await;
+            },
+            Err(e) => warn!("WebSocket upgrade of {:?} failed: {}", src, e),
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(UPGRADE, "websocket")
+        .header(CONNECTION, "upgrade")
+        .header(SEC_WEBSOCKET_ACCEPT, accept)
+        .body(Body::empty())
+        .expect("Could not create response")
+}
+
+enum Event {
+    Send(String),
+    Status(Status),
+    Ping,
+    /// A client message, which is ignored
+    Received,
+    Closed,
+}
+
+/// Sends the network list and the status, then the network changes of [`send_networks`] and the status changes,
+/// until the client or the server closes the connection.
+async fn serve<S>(
+    mut ws: WebSocketStream<S>,
+    mut rx: mpsc::UnboundedReceiver<String>,
+    mut status: StatusReceiver,
+    networks: String,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let current = status.borrow().clone();
+    let mut revision = current.revision;
+    let mut ping = interval_at(Instant::now() + PING_INTERVAL, PING_INTERVAL);
+    let status_message = serde_json::to_string(&WsMessage::Status(&current)).expect("json encoding failed");
+    if ws.send(Message::Text(networks)).await.is_err() || ws.send(Message::Text(status_message)).await.is_err() {
+        return;
+    }
+
+    loop {
+        let event = {
+            let outgoing = rx.recv();
+            pin_mut!(outgoing);
+            let status_changed = status.recv();
+            pin_mut!(status_changed);
+            let tick = ping.tick();
+            pin_mut!(tick);
+            let incoming = ws.next();
+            match select(select(select(outgoing, status_changed), tick), incoming).await {
+                Either::Left((Either::Left((Either::Left((Some(message), _)), _)), _)) => Event::Send(message),
+                Either::Left((Either::Left((Either::Right((Some(status), _)), _)), _)) => Event::Status(status),
+                Either::Left((Either::Right(_), _)) => Event::Ping,
+                Either::Right((Some(Ok(message)), _)) if !message.is_close() => Event::Received,
+                // The server stops, the status bus is gone or the client closed the connection
+                _ => Event::Closed,
+            }
+        };
+        let message = match event {
+            Event::Send(message) => Message::Text(message),
+            Event::Ping => Message::Ping(Vec::new()),
+            Event::Status(status) if status.revision != revision => {
+                revision = status.revision;
+                Message::Text(serde_json::to_string(&WsMessage::Status(&status)).expect("json encoding failed"))
+            },
+            Event::Status(_) | Event::Received => continue,
+            Event::Closed => break,
+        };
+        if ws.send(message).await.is_err() {
+            break;
+        }
+    }
+    let _ = ws.close(None).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept() {
+        // The example of RFC 6455
+        assert_eq!(accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+}
@@ -0,0 +1,210 @@
+//! # Translations of user facing messages
+//! Messages are identified by a key, like "wrong_password". Network backends map their failure reasons
+//! to these keys. The http server picks the language from the "lang" query parameter or the
+//! Accept-Language header of the request. English is the fallback.
+//! A language picked with the "lang" query parameter is remembered in the client session, see [`crate::sessions`].
+//! The portal page is served with the language and writing direction ([`direction`]) of the request.
+
+/// Supported languages, in the column order of [`MESSAGES`]
+const LANGUAGES: [&str; 4] = ["en", "de", "ar", "he"];
+
+/// Languages that are written from right to left
+const RTL_LANGUAGES: [&str; 2] = ["ar", "he"];
+
+/// Message key, English, German, Arabic, Hebrew
+const MESSAGES: &[(&str, [&str; 4])] = &[
+    (
+        "wrong_password",
+        [
+            "The Wi-Fi password appears to be incorrect.",
+            "Das WLAN-Passwort scheint falsch zu sein.",
+            "يبدو أن كلمة مرور شبكة Wi-Fi غير صحيحة.",
+            "נראה שסיסמת ה-Wi-Fi שגויה.",
+        ],
+    ),
+    (
+        "network_not_found",
+        [
+            "The Wi-Fi network could not be found. It may be out of range.",
+            "Das WLAN wurde nicht gefunden. Möglicherweise ist es außer Reichweite.",
+            "تعذر العثور على شبكة Wi-Fi. قد تكون خارج النطاق.",
+            "רשת ה-Wi-Fi לא נמצאה. ייתכן שהיא מחוץ לטווח.",
+        ],
+    ),
+    (
+        "auth_timeout",
+        [
+            "The Wi-Fi network did not respond in time.",
+            "Das WLAN hat nicht rechtzeitig geantwortet.",
+            "لم تستجب شبكة Wi-Fi في الوقت المناسب.",
+            "רשת ה-Wi-Fi לא הגיבה בזמן.",
+        ],
+    ),
+    (
+        "invalid_settings",
+        [
+            "The connection settings were rejected. Please check the security type and the password.",
+            "Die Verbindungseinstellungen wurden abgelehnt. Bitte Sicherheitstyp und Passwort prüfen.",
+            "تم رفض إعدادات الاتصال. يرجى التحقق من نوع الأمان وكلمة المرور.",
+            "הגדרות החיבור נדחו. נא לבדוק את סוג האבטחה ואת הסיסמה.",
+        ],
+    ),
+    (
+        "no_ip_address",
+        [
+            "Connected to the Wi-Fi network, but no IP address has been assigned.",
+            "Mit dem WLAN verbunden, aber es wurde keine IP-Adresse zugewiesen.",
+            "تم الاتصال بشبكة Wi-Fi، لكن لم يتم تعيين عنوان IP.",
+            "מחובר לרשת ה-Wi-Fi, אך לא הוקצתה כתובת IP.",
+        ],
+    ),
+    (
+        "ip_address_conflict",
+        [
+            "The assigned IP address is already in use in this network.",
+            "Die zugewiesene IP-Adresse wird in diesem Netzwerk bereits verwendet.",
+            "عنوان IP المعيّن مستخدم بالفعل في هذه الشبكة.",
+            "כתובת ה-IP שהוקצתה כבר בשימוש ברשת זו.",
+        ],
+    ),
+    (
+        "device_unavailable",
+        [
+            "The Wi-Fi adapter is not available.",
+            "Der WLAN-Adapter ist nicht verfügbar.",
+            "محول Wi-Fi غير متاح.",
+            "מתאם ה-Wi-Fi אינו זמין.",
+        ],
+    ),
+    (
+        "cancelled",
+        [
+            "The connection attempt has been cancelled.",
+            "Der Verbindungsversuch wurde abgebrochen.",
+            "تم إلغاء محاولة الاتصال.",
+            "ניסיון החיבור בוטל.",
+        ],
+    ),
+    (
+        "weak_connection",
+        [
+            "Connected, but the connection is weak (low signal or slow network). Continue anyway?",
+            "Verbunden, aber die Verbindung ist schwach (schwaches Signal oder langsames Netz). Trotzdem fortfahren?",
+            "تم الاتصال، لكن الاتصال ضعيف (إشارة منخفضة أو شبكة بطيئة). هل تريد المتابعة على أي حال؟",
+            "מחובר, אך החיבור חלש (אות נמוך או רשת איטית). להמשיך בכל זאת?",
+        ],
+    ),
+    (
+        "setup_not_allowed",
+        [
+            "The setup hotspot is currently not allowed. Press the setup button on the device.",
+            "Der Einrichtungs-Hotspot ist derzeit nicht erlaubt. Drücken Sie die Einrichtungstaste am Gerät.",
+            "نقطة اتصال الإعداد غير مسموح بها حاليًا. اضغط على زر الإعداد في الجهاز.",
+            "נקודת הגישה להגדרה אינה מותרת כרגע. יש ללחוץ על לחצן ההגדרה במכשיר.",
+        ],
+    ),
+    (
+        "no_internet",
+        [
+            "Connected to the Wi-Fi network, but the internet is not reachable.",
+            "Mit dem WLAN verbunden, aber das Internet ist nicht erreichbar.",
+            "تم الاتصال بشبكة Wi-Fi، لكن لا يمكن الوصول إلى الإنترنت.",
+            "מחובר לרשת ה-Wi-Fi, אך האינטרנט אינו זמין.",
+        ],
+    ),
+    (
+        "connection_failed",
+        [
+            "The connection could not be established.",
+            "Die Verbindung konnte nicht hergestellt werden.",
+            "تعذر إنشاء الاتصال.",
+            "לא ניתן היה ליצור את החיבור.",
+        ],
+    ),
+];
+
+/// Returns the supported language with the highest weight of an Accept-Language header value,
+/// like "de-DE,de;q=0.9,en;q=0.8". Returns "en" if no language is supported.
+pub fn negotiate(accept_language: &str) -> &'static str {
+    let mut best: Option<(&'static str, f32)> = None;
+    for entry in accept_language.split(',') {
+        let mut parts = entry.split(';');
+        let tag = parts.next().unwrap_or_default().trim();
+        let weight = parts
+            .map(|p| p.trim())
+            .filter(|p| p.starts_with("q="))
+            .filter_map(|p| p[2..].parse::<f32>().ok())
+            .next()
+            .unwrap_or(1.0);
+        if let Some(lang) = supported(tag) {
+            if best.map(|(_, w)| weight > w).unwrap_or(weight > 0.0) {
+                best = Some((lang, weight));
+            }
+        }
+    }
+    best.map(|(lang, _)| lang).unwrap_or(LANGUAGES[0])
+}
+
+/// Returns the supported language of a language tag, like "de" for "de-AT"
+pub fn supported(tag: &str) -> Option<&'static str> {
+    let primary = tag.trim().split('-').next().unwrap_or_default().to_lowercase();
+    LANGUAGES.iter().find(|l| **l == primary).copied()
+}
+
+/// The writing direction of the given language: "rtl" or "ltr", like the html "dir" attribute
+pub fn direction(lang: &str) -> &'static str {
+    match RTL_LANGUAGES.contains(&lang) {
+        true => "rtl",
+        false => "ltr",
+    }
+}
+
+/// Returns the message for the given key in the given language, or in English if there is no translation.
+pub fn translate(key: &str, lang: &str) -> Option<&'static str> {
+    let column = LANGUAGES.iter().position(|l| *l == lang).unwrap_or(0);
+    MESSAGES
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, translations)| translations[column])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_language() {
+        assert_eq!(negotiate("de-DE,de;q=0.9,en;q=0.8"), "de");
+        assert_eq!(negotiate("fr-FR,en;q=0.5,de;q=0.7"), "de");
+        assert_eq!(negotiate("fr-FR"), "en");
+        assert_eq!(negotiate("de;q=0"), "en");
+        assert_eq!(negotiate(""), "en");
+        assert_eq!(negotiate("he-IL,en;q=0.5"), "he");
+    }
+
+    #[test]
+    fn languages() {
+        assert_eq!(supported("de-AT"), Some("de"));
+        assert_eq!(supported("AR"), Some("ar"));
+        assert_eq!(supported("fr"), None);
+        assert_eq!(direction("ar"), "rtl");
+        assert_eq!(direction("he"), "rtl");
+        assert_eq!(direction("de"), "ltr");
+        for (key, translations) in MESSAGES {
+            assert!(translations.iter().all(|t| !t.is_empty()), "{}", key);
+        }
+    }
+
+    #[test]
+    fn messages() {
+        assert_eq!(
+            translate("wrong_password", "de"),
+            Some("Das WLAN-Passwort scheint falsch zu sein.")
+        );
+        assert_eq!(
+            translate("wrong_password", "fr"),
+            Some("The Wi-Fi password appears to be incorrect.")
+        );
+        assert_eq!(translate("unknown_key", "en"), None);
+    }
+}
@@ -6,22 +6,71 @@
 extern crate log;
 
 mod errors;
+mod netlink;
 mod network_interface;
 mod utils;
 
+pub mod arp_guard;
+pub mod boot_loop;
+pub mod channels;
 pub mod config;
+pub mod credential_policy;
+pub mod dbus_record;
+pub mod device;
+pub mod dispatcher;
+pub mod dpp;
+pub mod events;
+pub mod i18n;
+pub mod log_filter;
+pub mod mac_filter;
+pub mod metrics;
+pub mod oui;
+pub mod policy;
+pub mod quality;
+pub mod redirect;
+pub mod regulatory;
+pub mod rogue_ap;
+pub mod runtime;
+pub mod sessions;
+pub mod setup_window;
+pub mod stats;
+pub mod state_dir;
+pub mod status;
+pub mod status_file;
+pub mod subnet;
+pub mod trace;
+pub mod watchdog;
+#[cfg(feature = "ui")]
+pub mod attempts;
+#[cfg(feature = "ui")]
+pub mod commands;
+#[cfg(feature = "ui")]
 pub mod portal;
+#[cfg(feature = "ui")]
+pub mod smoke_test;
+#[cfg(feature = "ui")]
 pub mod state_machine;
 
+#[cfg(feature = "dbus-service")]
+pub mod dbus_service;
+#[cfg(feature = "dhcp")]
 pub mod dhcp_server;
+#[cfg(feature = "dhcp")]
+pub mod link_local;
+#[cfg(feature = "dns")]
 pub mod dns_server;
+#[cfg(feature = "ui")]
 pub mod http_server;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
 
 pub mod network_backend;
-pub use network_backend::NetworkBackend;
+pub use network_backend::{NetworkBackend, WifiBackend};
 
 pub use network_interface::*;
 pub use utils::*;
 
+pub use netlink::LinkStats;
+
 /// Re-export error type
 pub use errors::CaptivePortalError;
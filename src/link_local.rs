@@ -0,0 +1,158 @@
+//! # IPv4 link-local fallback
+//! If the dhcp server of the portal cannot be started, clients of the hotspot do not get an address. Most of them
+//! assign themselves a link-local address (RFC 3927) after a while. The portal then adds a link-local address
+//! to the hotspot interface as well, relays http connections on it to the portal web server and announces
+//! the address via mDNS (see [`crate::dns_server::mdns`]), so that clients reach the portal at `http://<name>.local`.
+//!
+//! Relayed requests arrive from the gateway address at the web server. Link-local clients have no dhcp session.
+
+use crate::netlink::{self, address};
+use crate::CaptivePortalError;
+
+use futures_util::future::{select, try_join, Either};
+use pin_utils::pin_mut;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+
+/// The link-local network is 169.254.0.0/16
+pub const LINK_LOCAL_PREFIX: u8 = 16;
+
+/// The link-local address of an interface with the given hardware address. Always the same address for one
+/// interface, within 169.254.1.0 - 169.254.254.255. The first and last 256 addresses are reserved.
+pub fn address_for(hw: &[u8; 6]) -> Ipv4Addr {
+    Ipv4Addr::new(169, 254, 1 + hw[4] % 254, hw[5])
+}
+
+/// Parses the hardware address of the interface, like "aa:bb:cc:00:11:22"
+fn hardware_address(interface_name: &str) -> Result<[u8; 6], CaptivePortalError> {
+    let hw = std::fs::read_to_string(format!("/sys/class/net/{}/address", interface_name))
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to read the hardware address"))?;
+    let octets: Vec<u8> = hw.trim().split(':').filter_map(|o| u8::from_str_radix(o, 16).ok()).collect();
+    match octets.len() {
+        6 => Ok([octets[0], octets[1], octets[2], octets[3], octets[4], octets[5]]),
+        _ => Err(CaptivePortalError::Generic(format!("Invalid hardware address of {}", interface_name))),
+    }
+}
+
+/// The link-local fallback of a running portal. Dropping it stops the relay and the mDNS responder and removes
+/// the address from the interface.
+pub struct LinkLocal {
+    interface_index: u32,
+    pub address: Ipv4Addr,
+    /// The announced host name, like "wifi-captive.local"
+    pub host: String,
+    #[allow(dead_code)]
+    relay_exit: tokio::sync::oneshot::Sender<()>,
+    #[cfg(feature = "dns")]
+    #[allow(dead_code)]
+    mdns_exit: tokio::sync::oneshot::Sender<()>,
+}
+
+impl LinkLocal {
+    /// Adds the link-local address to the interface and relays connections to the http port of that address
+    /// to the portal web server. The address is announced via mDNS with the given name, if the dns feature
+    /// is enabled.
+    pub async fn start(interface_name: &str, name: &str, portal: SocketAddrV4) -> Result<Self, CaptivePortalError> {
+        let interface_index = netlink::interface_index(interface_name)?;
+        let address = address_for(&hardware_address(interface_name)?);
+        address::add_address(interface_index, address, LINK_LOCAL_PREFIX)?;
+
+        let listener = match TcpListener::bind(SocketAddr::V4(SocketAddrV4::new(address, portal.port()))).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                let _ = address::remove_address(interface_index, address, LINK_LOCAL_PREFIX);
+                return Err(CaptivePortalError::IO(e, "Failed to bind the link-local relay"));
+            },
+        };
+        let (relay_exit, exit_receiver) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(run_relay(listener, portal, exit_receiver));
+
+        #[cfg(feature = "dns")]
+        let (host, mdns_exit) = {
+            let (mut responder, mdns_exit) = crate::dns_server::mdns::MdnsResponder::new(name, address);
+            let host = responder.host().to_owned();
+            tokio::spawn(async move {
+                if let Err(e) = responder.run().await {
+                    error!("{}", e);
+                }
+            });
+            (host, mdns_exit)
+        };
+        #[cfg(not(feature = "dns"))]
+        let host = format!("{}.local", name.to_lowercase());
+
+        info!("Link-local fallback on {} with {}", interface_name, address);
+        Ok(LinkLocal {
+            interface_index,
+            address,
+            host,
+            relay_exit,
+            #[cfg(feature = "dns")]
+            mdns_exit,
+        })
+    }
+}
+
+impl Drop for LinkLocal {
+    fn drop(&mut self) {
+        if let Err(e) = address::remove_address(self.interface_index, self.address, LINK_LOCAL_PREFIX) {
+            warn!("{}", e);
+        }
+    }
+}
+
+/// Accepts connections until the exit handler is called or dropped
+async fn run_relay(
+    mut listener: TcpListener,
+    portal: SocketAddrV4,
+    mut exit_receiver: tokio::sync::oneshot::Receiver<()>,
+) {
+    loop {
+        let accept = listener.accept();
+        pin_mut!(accept);
+        match select(accept, &mut exit_receiver).await {
+            Either::Left((Ok((stream, _)), _)) => {
+                tokio::spawn(async move {
+                    if let Err(e) = relay(stream, portal).await {
+                        debug!("Link-local relay: {}", e);
+                    }
+                });
+            },
+            Either::Left((Err(e), _)) => warn!("Failed to accept a link-local connection: {}", e),
+            Either::Right(_) => break,
+        }
+    }
+}
+
+/// Copies data in both directions. A side that closes its connection is forwarded as a shutdown of the other side.
+async fn relay(mut inbound: TcpStream, portal: SocketAddrV4) -> io::Result<()> {
+    let mut outbound = TcpStream::connect(SocketAddr::V4(portal)).await?;
+    let (mut inbound_read, mut inbound_write) = inbound.split();
+    let (mut outbound_read, mut outbound_write) = outbound.split();
+    let to_portal = async {
+        tokio::io::copy(&mut inbound_read, &mut outbound_write).await?;
+        outbound_write.shutdown().await
+    };
+    let to_client = async {
+        tokio::io::copy(&mut outbound_read, &mut inbound_write).await?;
+        inbound_write.shutdown().await
+    };
+    try_join(to_portal, to_client).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses() {
+        assert_eq!(address_for(&[0xb8, 0x27, 0xeb, 0x00, 0x11, 0x22]), Ipv4Addr::new(169, 254, 18, 0x22));
+        // Never in the reserved first and last 256 addresses
+        assert_eq!(address_for(&[0, 0, 0, 0, 0, 0]), Ipv4Addr::new(169, 254, 1, 0));
+        assert_eq!(address_for(&[0, 0, 0, 0, 0xfd, 0xff]), Ipv4Addr::new(169, 254, 254, 0xff));
+        assert_eq!(address_for(&[0, 0, 0, 0, 0xfe, 0xff]), Ipv4Addr::new(169, 254, 1, 0xff));
+    }
+}
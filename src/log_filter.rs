@@ -0,0 +1,220 @@
+//! # Runtime log filter
+//! The log filter of `RUST_LOG` can be replaced while the service runs, for example with
+//! "info,wifi_captive::network_backend::nm=trace" for ten minutes. Debugging a device in the field then
+//! needs no restart, which would lose the failing state. A temporary filter falls back to the startup
+//! filter when it expires.
+//!
+//! The filter is changed via "/api/log-filter" and the D-Bus method `SetLogFilter`, if the policy allows
+//! the "log-filter" action, see [`crate::policy`]. Changes are audited.
+
+use crate::runtime;
+use crate::CaptivePortalError;
+use env_logger::filter::{Builder, Filter};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// The longest time a replacing filter may be requested for
+pub const MAX_MINUTES: u64 = 7 * 24 * 60;
+
+/// The duration of a replacing filter that is requested for the given minutes. Rejects more than
+/// [`MAX_MINUTES`] instead of overflowing.
+pub fn duration(minutes: u64) -> Result<Duration, CaptivePortalError> {
+    match minutes <= MAX_MINUTES {
+        true => Ok(Duration::from_secs(minutes * 60)),
+        false => Err(CaptivePortalError::InvalidLogFilter(format!(
+            "{} minutes exceed the maximum of {}",
+            minutes, MAX_MINUTES
+        ))),
+    }
+}
+
+/// A parsed filter, like "info,wifi_captive::dhcp_server=debug"
+struct Spec {
+    spec: String,
+    filter: Filter,
+}
+
+impl Spec {
+    fn parse(spec: &str) -> Result<Self, CaptivePortalError> {
+        let spec = spec.trim();
+        // The env_logger parser skips invalid directives with a message on stderr. Reject them instead.
+        let directives = spec.splitn(2, '/').next().unwrap_or_default();
+        for directive in directives.split(',').map(|d| d.trim()).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            let (name, level) = (parts.next().unwrap_or_default(), parts.next());
+            let valid = match level {
+                Some(level) => !name.is_empty() && level.parse::<LevelFilter>().is_ok(),
+                None => true,
+            };
+            if !valid {
+                return Err(CaptivePortalError::InvalidLogFilter(format!("Invalid directive {}", directive)));
+            }
+        }
+        Ok(Spec {
+            spec: spec.to_owned(),
+            filter: Builder::new().parse(spec).build(),
+        })
+    }
+}
+
+struct State {
+    /// The filter of `RUST_LOG` at startup
+    base: Spec,
+    /// The replacing filter and when it expires, if ever
+    replaced: Option<(Spec, Option<Instant>)>,
+}
+
+impl State {
+    fn current(&self) -> &Spec {
+        match &self.replaced {
+            Some((spec, None)) => spec,
+            Some((spec, Some(until))) if Instant::now() < *until => spec,
+            _ => &self.base,
+        }
+    }
+}
+
+/// The active filter and when it falls back to the startup filter
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct LogFilterStatus {
+    pub filter: String,
+    /// The filter of `RUST_LOG` at startup
+    pub startup_filter: String,
+    /// Seconds until the startup filter applies again. None if the filter does not expire.
+    pub expires_in: Option<u64>,
+}
+
+/// The replaceable log filter. Shared by all clones.
+#[derive(Clone)]
+pub struct LogFilter(Arc<RwLock<State>>);
+
+impl LogFilter {
+    /// Starts with the given filter. An invalid filter logs errors only.
+    pub fn new(spec: &str) -> Self {
+        let base = Spec::parse(spec).unwrap_or_else(|_| Spec::parse("error").expect("Parse the error filter"));
+        LogFilter(Arc::new(RwLock::new(State { base, replaced: None })))
+    }
+
+    /// Replaces the filter, for the given duration or until [`LogFilter::reset`]
+    pub fn set(&self, spec: &str, duration: Option<Duration>) -> Result<(), CaptivePortalError> {
+        let parsed = Spec::parse(spec)?;
+        let until = duration.map(|d| Instant::now() + d);
+        self.0.write().expect("log filter lock").replaced = Some((parsed, until));
+        self.apply_max_level();
+        if let Some(duration) = duration {
+            let filter = self.clone();
+            runtime::spawn(async move {
+                runtime::delay_for(duration).await;
+                filter.apply_max_level();
+            });
+        }
+        Ok(())
+    }
+
+    /// Applies the filter of `RUST_LOG` at startup again
+    pub fn reset(&self) {
+        self.0.write().expect("log filter lock").replaced = None;
+        self.apply_max_level();
+    }
+
+    pub fn status(&self) -> LogFilterStatus {
+        let state = self.0.read().expect("log filter lock");
+        let now = Instant::now();
+        LogFilterStatus {
+            filter: state.current().spec.clone(),
+            startup_filter: state.base.spec.clone(),
+            expires_in: match &state.replaced {
+                Some((_, Some(until))) if *until > now => Some((*until - now).as_secs()),
+                _ => None,
+            },
+        }
+    }
+
+    /// Log statements above the maximum level of the current filter are skipped by the log macros
+    fn apply_max_level(&self) {
+        log::set_max_level(self.0.read().expect("log filter lock").current().filter.filter());
+    }
+
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.read().expect("log filter lock").current().filter.enabled(metadata)
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        self.0.read().expect("log filter lock").current().filter.matches(record)
+    }
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        LogFilter::new("info")
+    }
+}
+
+impl fmt::Debug for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("LogFilter").field(&self.status().filter).finish()
+    }
+}
+
+/// Writes the records that pass the log filter with the given logger. The logger itself should not filter.
+struct FilteredLogger {
+    logger: env_logger::Logger,
+    filter: LogFilter,
+}
+
+impl Log for FilteredLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.filter.matches(record) {
+            self.logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.logger.flush()
+    }
+}
+
+/// Installs the global logger. Fails if a logger has been installed before.
+pub fn init(logger: env_logger::Logger, filter: LogFilter) -> Result<(), log::SetLoggerError> {
+    log::set_boxed_logger(Box::new(FilteredLogger {
+        logger,
+        filter: filter.clone(),
+    }))?;
+    filter.apply_max_level();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replace_and_expire() {
+        let filter = LogFilter::new("info");
+        assert!(filter.set("info,wifi_captive::dhcp_server=verbose", None).is_err());
+        assert!(filter.set("=debug", None).is_err());
+
+        filter.set("warn,wifi_captive::dhcp_server=trace", None).unwrap();
+        let status = filter.status();
+        assert_eq!(status.filter, "warn,wifi_captive::dhcp_server=trace");
+        assert_eq!(status.startup_filter, "info");
+        assert_eq!(status.expires_in, None);
+
+        filter.0.write().unwrap().replaced.as_mut().unwrap().1 = Some(Instant::now());
+        assert_eq!(filter.status().filter, "info");
+
+        assert_eq!(duration(10).unwrap(), Duration::from_secs(600));
+        assert!(duration(u64::MAX).is_err());
+
+        filter.set("debug", None).unwrap();
+        filter.reset();
+        assert_eq!(filter.status().filter, "info");
+    }
+}
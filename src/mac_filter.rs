@@ -0,0 +1,95 @@
+//! # Hotspot MAC filter
+//! Restricts which clients may use the provisioning hotspot, for example only the tester tablet of a factory line.
+//! Denied hardware addresses are always rejected. If the allow list is not empty, only its addresses are admitted.
+//!
+//! The filter is read from the file of `--mac-filter-file` at start up and can be replaced at runtime
+//! ("/api/hotspot/mac-filter", policy action "mac-filter"). The dhcp server offers no lease to rejected clients.
+//! The OpenWrt backend also rejects their association, with the filter at the time the hotspot starts.
+//!
+//! The NetworkManager, iwd, wpa_supplicant, networkd and ConnMan backends filter at dhcp only. Their access
+//! points admit every client, a rejected client that configures a static address can still reach the portal.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+
+/// Allowed and denied hardware addresses, like "AA:BB:CC:00:11:22"
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct MacFilter {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// The filter of the hotspot. Shared by the configuration, the dhcp server and the http server.
+pub type MacFilterSync = Arc<RwLock<MacFilter>>;
+
+/// The hardware address in upper case hex with colons
+pub fn hw_string(hw: &[u8; 6]) -> String {
+    hw.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":")
+}
+
+/// Normalizes a hardware address like "aa-bb-cc-00-11-22" to "AA:BB:CC:00:11:22"
+fn normalize(hw: &str) -> Result<String, String> {
+    let octets: Vec<&str> = hw.trim().split(|c| c == ':' || c == '-').collect();
+    if octets.len() != 6 || octets.iter().any(|o| o.len() != 2 || u8::from_str_radix(o, 16).is_err()) {
+        return Err(format!("Invalid hardware address {}", hw));
+    }
+    Ok(octets.join(":").to_uppercase())
+}
+
+impl MacFilter {
+    /// Parses a filter file: One "allow <address>" or "deny <address>" per line. Empty lines and lines
+    /// starting with # are skipped.
+    pub fn parse(content: &str) -> Result<MacFilter, String> {
+        let mut filter = MacFilter::default();
+        for line in content.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some("allow"), Some(hw)) => filter.allow.push(hw.to_owned()),
+                (Some("deny"), Some(hw)) => filter.deny.push(hw.to_owned()),
+                _ => return Err(format!("Expected allow <address> or deny <address>, got {}", line)),
+            }
+        }
+        filter.normalized()
+    }
+
+    /// The filter with normalized addresses. An error if an address is invalid.
+    pub fn normalized(self) -> Result<MacFilter, String> {
+        Ok(MacFilter {
+            allow: self.allow.iter().map(|hw| normalize(hw)).collect::<Result<_, _>>()?,
+            deny: self.deny.iter().map(|hw| normalize(hw)).collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Whether the client with the given hardware address may use the hotspot
+    pub fn permits(&self, hw: &[u8; 6]) -> bool {
+        let hw = hw_string(hw);
+        !self.deny.contains(&hw) && (self.allow.is_empty() || self.allow.contains(&hw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mac_filter() {
+        let filter = MacFilter::parse("# Tester\nallow aa:bb:cc:00:11:22\n\ndeny 02-00-00-00-00-01\n").unwrap();
+        assert_eq!(filter.allow, vec!["AA:BB:CC:00:11:22".to_owned()]);
+        assert_eq!(filter.deny, vec!["02:00:00:00:00:01".to_owned()]);
+        assert!(filter.permits(&[0xaa, 0xbb, 0xcc, 0, 0x11, 0x22]));
+        assert!(!filter.permits(&[2, 0, 0, 0, 0, 2]));
+
+        let filter = MacFilter::parse("deny 02:00:00:00:00:01").unwrap();
+        assert!(filter.permits(&[2, 0, 0, 0, 0, 2]));
+        assert!(!filter.permits(&[2, 0, 0, 0, 0, 1]));
+
+        assert!(MacFilter::parse("allow 02:00:00:00:00").is_err());
+        assert!(MacFilter::parse("permit 02:00:00:00:00:01").is_err());
+    }
+}
@@ -7,9 +7,10 @@ extern crate log;
 
 use wifi_captive::*;
 
-use env_logger::{Env, TimestampPrecision, DEFAULT_FILTER_ENV};
+use env_logger::{Env, TimestampPrecision, DEFAULT_FILTER_ENV, DEFAULT_WRITE_STYLE_ENV};
 use std::io::ErrorKind;
 use std::net::{SocketAddr, SocketAddrV4};
+use std::time::Duration;
 use structopt::StructOpt;
 
 fn map_to_err(err_kind: ErrorKind, server_addr: SocketAddrV4, service_name: &'static str) -> CaptivePortalError {
@@ -54,40 +55,189 @@ pub async fn test_tcp(server_addr: SocketAddrV4) -> Result<(), CaptivePortalErro
     Ok(())
 }
 
+#[cfg(not(feature = "single-threaded"))]
 #[tokio::main]
 async fn main() {
-    let mut builder = env_logger::Builder::from_env(Env::new().filter_or(DEFAULT_FILTER_ENV, "info"));
+    run().await
+}
+
+/// Everything runs on the current thread: The network backend uses a D-Bus `LocalConnection`
+/// and tasks are spawned onto a `LocalSet`.
+#[cfg(feature = "single-threaded")]
+fn main() {
+    let mut runtime = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .expect("Create tokio runtime");
+    let local = tokio::task::LocalSet::new();
+    local.block_on(&mut runtime, run());
+}
+
+async fn run() {
+    // The filter of RUST_LOG is applied by the replaceable log filter, the logger itself writes everything
+    let log_filter = log_filter::LogFilter::new(&std::env::var(DEFAULT_FILTER_ENV).unwrap_or_else(|_| "info".into()));
+    let mut builder = env_logger::Builder::from_env(Env::new().write_style(DEFAULT_WRITE_STYLE_ENV));
     builder
+        .filter_level(log::LevelFilter::Trace)
         .format_timestamp(Some(TimestampPrecision::Seconds))
-        .format_module_path(false)
-        .init();
+        .format_module_path(false);
+    log_filter::init(builder.build(), log_filter.clone()).expect("Install the logger");
 
-    if let Err(e) = main_inner().await {
+    if let Err(e) = main_inner(log_filter).await {
         error!("{}", e.to_string());
     }
 }
 
-async fn main_inner() -> Result<(), Box<dyn std::error::Error>> {
-    let config: config::Config = config::Config::from_args();
+async fn main_inner(log_filter: log_filter::LogFilter) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config: config::Config = config::Config::from_args();
+    config.log_filter = log_filter;
+    // SIGTERM of systemd takes the same clean exit path as ctrl+c, see the boot loop detection
+    runtime::spawn_tokio(watch_exit_signals());
 
     if config.passphrase.len() > 0 {
         verify_password(&config.passphrase)?;
     }
 
+    config.hotspot_radio()?;
+    if let Some(path) = config.mac_filter_file.as_ref() {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| CaptivePortalError::IO(e, "Failed to read the mac filter file"))?;
+        let filter = mac_filter::MacFilter::parse(&content)?;
+        info!("Hotspot mac filter: {} allowed, {} denied", filter.allow.len(), filter.deny.len());
+        *config.mac_filter.write().expect("mac filter lock") = filter;
+    }
+
+    if config.in_memory && config.state_dir.is_some() {
+        warn!("In-memory mode: The state directory is not used");
+    }
+    if let Some(state_dir) = config.persistence_dir() {
+        state_dir::prepare_state_dir(state_dir)?;
+    }
+    let mut safe_mode = None;
+    let boot_loop_guard = match (config.persistence_dir(), config.safe_mode_crashes) {
+        (Some(state_dir), crashes) if crashes > 0 => {
+            let window = Duration::from_secs(config.safe_mode_window * 60);
+            let (guard, reason) = boot_loop::BootLoopGuard::start(state_dir, crashes, window)?;
+            safe_mode = reason;
+            Some(guard)
+        },
+        _ => None,
+    };
+    if let Some(reason) = safe_mode.as_ref() {
+        warn!(
+            "Safe mode: Crashed {} times within {} minutes. Only the hotspot is offered.",
+            reason.crashes, reason.window_minutes
+        );
+        config.safe_mode = true;
+    }
+    if let Some(path) = config.record_dbus.as_ref() {
+        dbus_record::record_to(path)?;
+        warn!("Recording the D-Bus traffic to {}", path.display());
+    }
+    for budget in &config.memory_budget {
+        stats::set_budget(*budget);
+    }
+
     test_udp(SocketAddrV4::new(config.gateway, config.dns_port), "DNS Server").await?;
     test_udp(SocketAddrV4::new(config.gateway, config.dhcp_port), "DHCP Server").await?;
     test_tcp(SocketAddrV4::new(config.gateway, config.listening_port)).await?;
 
-    let mut sm = state_machine::StateMachine::StartUp(config.clone());
-
-    loop {
-        sm = if let Some(sm) = sm.progress().await? {
-            sm
-        } else {
-            break;
+    let mut sm: state_machine::StateMachine = state_machine::StateMachine::StartUp(config.clone());
+    let event_log = match config.persistence_dir() {
+        Some(state_dir) => {
+            let path = state_dir.join(events::EVENTS_FILE);
+            let flush_interval = Duration::from_secs(config.flush_interval);
+            events::EventLog::with_file(path, config.event_history, flush_interval).unwrap_or_else(|e| {
+                warn!("Could not restore the event history: {}", e);
+                events::EventLog::new(config.event_history)
+            })
+        },
+        None => events::EventLog::new(config.event_history),
+    };
+    let status = status::StatusSender::with_event_log(event_log);
+    status.set_safe_mode(safe_mode);
+    if let Some(path) = config.status_file.clone() {
+        runtime::spawn(status_file::run(path, status.subscribe()));
+    }
+    let attempts = attempts::ConnectAttempts::new_sync(config.duplicate_connect);
+    if let Some(state_dir) = config.persistence_dir() {
+        let path = state_dir.join(attempts::ATTEMPT_HISTORY_FILE);
+        let flush_interval = Duration::from_secs(config.flush_interval);
+        if let Err(e) = attempts.lock().expect("attempts mutex lock").set_history_file(path, flush_interval) {
+            warn!("Could not restore the attempt history: {}", e);
+        }
+    }
+    let mut commands = commands::CommandQueue::new(policy::Policy::new(&config.policy));
+    if let Some(state_dir) = config.persistence_dir() {
+        let path = state_dir.join(commands::AUDIT_LOG_FILE);
+        let flush_interval = Duration::from_secs(config.flush_interval);
+        match commands::AuditLog::with_file(path, flush_interval) {
+            Ok(audit_log) => *commands.audit_log().lock().expect("audit log mutex lock") = audit_log,
+            Err(e) => warn!("Could not restore the audit log: {}", e),
         }
     }
+    #[cfg(feature = "dbus-service")]
+    {
+        if let Err(e) = dbus_service::start(attempts.clone(), commands.sender(), config.log_filter.clone()).await {
+            warn!("D-Bus control interface not available: {}", e);
+        }
+    }
+    #[cfg(not(feature = "dbus-service"))]
+    {
+        if config.install_dispatcher {
+            warn!("The dispatcher script requires the D-Bus control interface of the dbus-service feature");
+        }
+    }
+    if config.install_dispatcher {
+        match dispatcher::install(std::path::Path::new(dispatcher::DISPATCHER_DIR)) {
+            Ok(path) => info!("Installed the NetworkManager dispatcher script {}", path.display()),
+            Err(e) => warn!("Failed to install the NetworkManager dispatcher script: {}", e),
+        }
+    }
+    #[cfg(feature = "metrics-push")]
+    {
+        if let Some(target) = config.metrics_push_target() {
+            let interval = Duration::from_secs(config.metrics_push_interval.max(1));
+            runtime::spawn_tokio(metrics::run(target, interval, config.metrics_buffer));
+        }
+    }
+    #[cfg(not(feature = "metrics-push"))]
+    {
+        if config.metrics_push_url.is_some() || config.metrics_remote_write_url.is_some() {
+            warn!("Pushing metrics requires the metrics-push feature");
+        }
+    }
+    #[cfg(feature = "mqtt")]
+    {
+        if let Some(broker) = config.mqtt_broker.clone() {
+            let topic = config.mqtt_topic.clone();
+            runtime::spawn_tokio(mqtt::run(broker, topic, commands.sender(), status.subscribe()));
+        }
+    }
+    #[cfg(not(feature = "mqtt"))]
+    {
+        if config.mqtt_broker.is_some() {
+            warn!("The MQTT bridge requires the mqtt feature");
+        }
+    }
+
+    let result = loop {
+        sm = match sm.progress(&status, &attempts, &mut commands).await {
+            Ok(Some(sm)) => sm,
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
 
     info!("State machine left");
+    // Pending debounced writes are flushed on every exit path, see [`state_dir::StateFile`]
+    status.events().lock().expect("event log mutex lock").store(true);
+    attempts.lock().expect("attempts mutex lock").store_history(true);
+    commands.audit_log().lock().expect("audit log mutex lock").store(true);
+    result?;
+    if let Some(guard) = boot_loop_guard {
+        guard.clean_shutdown()?;
+    }
     Ok(())
 }
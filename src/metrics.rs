@@ -0,0 +1,286 @@
+//! # Metrics
+//! The sizes and budgets of the long-lived collections (see [`crate::stats`]), the state and counters of
+//! the wifi interface and the spoofed ARP packets (see [`crate::arp_guard`]) in the Prometheus text format.
+//! The portal serves them at "/metrics".
+//!
+//! Devices behind NAT cannot be scraped. With the "metrics-push" feature the same samples are pushed instead:
+//! * To a Prometheus push gateway with `--metrics-push-url`, like
+//!   "http://gateway:9091/metrics/job/wifi-captive/instance/device-1". The push gateway only keeps the latest
+//!   values, so only the latest snapshot is pushed.
+//! * Via Prometheus remote write with `--metrics-remote-write-url`. Snapshots are taken every interval and
+//!   sent in batches. While the receiver is not reachable, up to `--metrics-buffer` snapshots are kept,
+//!   the oldest are dropped first.
+
+use crate::arp_guard;
+use crate::netlink::LinkStats;
+use crate::stats;
+use std::fmt::Write;
+
+/// A gauge value
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub name: &'static str,
+    pub labels: Vec<(&'static str, String)>,
+    pub value: f64,
+}
+
+/// The current samples of all metrics
+pub fn collect() -> Vec<Sample> {
+    let collections = stats::snapshot();
+    let sizes = collections.iter().map(|c| Sample {
+        name: "wifi_captive_collection_size",
+        labels: vec![("collection", c.name.to_owned())],
+        value: c.size as f64,
+    });
+    let budgets = collections.iter().map(|c| Sample {
+        name: "wifi_captive_collection_budget",
+        labels: vec![("collection", c.name.to_owned())],
+        value: c.budget as f64,
+    });
+    let mut samples: Vec<Sample> = sizes.chain(budgets).collect();
+    if let Some(link) = stats::link() {
+        samples.extend(link_samples(&link));
+    }
+    samples.push(Sample {
+        name: "wifi_captive_arp_spoof_packets",
+        labels: Vec::new(),
+        value: arp_guard::spoofed_packets() as f64,
+    });
+    samples
+}
+
+fn link_samples(link: &LinkStats) -> Vec<Sample> {
+    let flag = |name, value: bool| Sample {
+        name,
+        labels: Vec::new(),
+        value: if value { 1.0 } else { 0.0 },
+    };
+    let counter = |name, direction: &str, value: u64| Sample {
+        name,
+        labels: vec![("direction", direction.to_owned())],
+        value: value as f64,
+    };
+    vec![
+        flag("wifi_captive_link_up", link.up),
+        flag("wifi_captive_link_carrier", link.carrier),
+        counter("wifi_captive_link_bytes", "rx", link.rx_bytes),
+        counter("wifi_captive_link_bytes", "tx", link.tx_bytes),
+        counter("wifi_captive_link_packets", "rx", link.rx_packets),
+        counter("wifi_captive_link_packets", "tx", link.tx_packets),
+        counter("wifi_captive_link_errors", "rx", link.rx_errors),
+        counter("wifi_captive_link_errors", "tx", link.tx_errors),
+        counter("wifi_captive_link_dropped", "rx", link.rx_dropped),
+        counter("wifi_captive_link_dropped", "tx", link.tx_dropped),
+    ]
+}
+
+/// The samples in the Prometheus text exposition format. Samples of a metric must be consecutive.
+pub fn render_text(samples: &[Sample]) -> String {
+    let mut text = String::new();
+    let mut last_name = "";
+    for sample in samples {
+        if sample.name != last_name {
+            let _ = writeln!(text, "# TYPE {} gauge", sample.name);
+            last_name = sample.name;
+        }
+        let labels: Vec<String> = sample
+            .labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+            .collect();
+        if labels.is_empty() {
+            let _ = writeln!(text, "{} {}", sample.name, sample.value);
+        } else {
+            let _ = writeln!(text, "{}{{{}}} {}", sample.name, labels.join(","), sample.value);
+        }
+    }
+    text
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Appends a protobuf varint
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Appends a length delimited protobuf field
+fn length_delimited(out: &mut Vec<u8>, field: u64, data: &[u8]) {
+    varint(out, field << 3 | 2);
+    varint(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+/// Encodes a remote write `WriteRequest` protobuf message: One time series per sample,
+/// with the samples of all snapshots of that series. Snapshots are (timestamp in ms, samples).
+pub fn encode_write_request(snapshots: &[(i64, Vec<Sample>)]) -> Vec<u8> {
+    let mut series: Vec<(&Sample, Vec<(i64, f64)>)> = Vec::new();
+    for (timestamp, samples) in snapshots {
+        for sample in samples {
+            match series
+                .iter_mut()
+                .find(|(s, _)| s.name == sample.name && s.labels == sample.labels)
+            {
+                Some((_, values)) => values.push((*timestamp, sample.value)),
+                None => series.push((sample, vec![(*timestamp, sample.value)])),
+            }
+        }
+    }
+
+    let mut request = Vec::new();
+    for (sample, values) in series {
+        let mut time_series = Vec::new();
+        // Labels are sorted by name, "__name__" first
+        let mut labels = vec![("__name__", sample.name.to_owned())];
+        labels.extend(sample.labels.iter().cloned());
+        labels.sort();
+        for (name, value) in labels {
+            let mut label = Vec::new();
+            length_delimited(&mut label, 1, name.as_bytes());
+            length_delimited(&mut label, 2, value.as_bytes());
+            length_delimited(&mut time_series, 1, &label);
+        }
+        for (timestamp, value) in values {
+            let mut s = Vec::new();
+            varint(&mut s, 1 << 3 | 1);
+            s.extend_from_slice(&value.to_le_bytes());
+            varint(&mut s, 2 << 3);
+            varint(&mut s, timestamp as u64);
+            length_delimited(&mut time_series, 2, &s);
+        }
+        length_delimited(&mut request, 1, &time_series);
+    }
+    request
+}
+
+/// Snappy block format without compression: The uncompressed length and literal chunks.
+/// Remote write requires snappy, and the messages are small.
+pub fn snappy_uncompressed(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 65536 * 3 + 8);
+    varint(&mut out, data.len() as u64);
+    for chunk in data.chunks(65536) {
+        let len = chunk.len() - 1;
+        if len < 60 {
+            out.push((len as u8) << 2);
+        } else if len < 256 {
+            out.extend_from_slice(&[60 << 2, len as u8]);
+        } else {
+            out.extend_from_slice(&[61 << 2, len as u8, (len >> 8) as u8]);
+        }
+        out.extend_from_slice(chunk);
+    }
+    out
+}
+
+#[cfg(feature = "metrics-push")]
+pub use push::*;
+
+#[cfg(feature = "metrics-push")]
+mod push {
+    use super::*;
+    use crate::runtime::delay_for;
+    use crate::CaptivePortalError;
+    use hyper::{Body, Client, Method, Request};
+    use std::collections::VecDeque;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    /// Where metrics are pushed to
+    #[derive(Clone, Debug)]
+    pub enum PushTarget {
+        PushGateway(String),
+        RemoteWrite(String),
+    }
+
+    /// Takes a snapshot every interval and pushes it. Runs forever.
+    pub async fn run(target: PushTarget, interval: Duration, buffer: usize) {
+        let client = Client::new();
+        let mut snapshots: VecDeque<(i64, Vec<Sample>)> = VecDeque::new();
+        loop {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or_default();
+            snapshots.push_back((timestamp, collect()));
+            while snapshots.len() > buffer.max(1) {
+                snapshots.pop_front();
+            }
+
+            let request = match &target {
+                PushTarget::PushGateway(url) => {
+                    let latest = snapshots.back().map(|(_, s)| render_text(s)).unwrap_or_default();
+                    Request::builder().method(Method::PUT).uri(url.as_str()).body(Body::from(latest))
+                },
+                PushTarget::RemoteWrite(url) => {
+                    let batch: Vec<(i64, Vec<Sample>)> = snapshots.iter().cloned().collect();
+                    let body = snappy_uncompressed(&encode_write_request(&batch));
+                    Request::builder()
+                        .method(Method::POST)
+                        .uri(url.as_str())
+                        .header("Content-Type", "application/x-protobuf")
+                        .header("Content-Encoding", "snappy")
+                        .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+                        .body(Body::from(body))
+                },
+            };
+            let result = match request {
+                Ok(request) => client.request(request).await.map_err(CaptivePortalError::from),
+                Err(e) => Err(CaptivePortalError::Generic(e.to_string())),
+            };
+            match result {
+                Ok(response) if response.status().is_success() => snapshots.clear(),
+                Ok(response) => warn!("Pushing metrics failed: {}", response.status()),
+                Err(e) => debug!("Pushing metrics failed, {} snapshots buffered: {}", snapshots.len(), e),
+            }
+            delay_for(interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_format() {
+        let samples = vec![
+            Sample {
+                name: "a",
+                labels: vec![("collection", "x\"y".to_owned())],
+                value: 1.0,
+            },
+            Sample {
+                name: "a",
+                labels: vec![("collection", "z".to_owned())],
+                value: 2.5,
+            },
+        ];
+        assert_eq!(
+            render_text(&samples),
+            "# TYPE a gauge\na{collection=\"x\\\"y\"} 1\na{collection=\"z\"} 2.5\n"
+        );
+    }
+
+    #[test]
+    fn remote_write_encoding() {
+        let sample = Sample {
+            name: "m",
+            labels: vec![],
+            value: 1.0,
+        };
+        let request = encode_write_request(&[(1, vec![sample.clone()]), (2, vec![sample])]);
+        // One time series with the label __name__="m" and two samples
+        assert_eq!(&request[..4], &[0x0a, 41, 0x0a, 13]);
+        assert_eq!(request.len(), 43);
+
+        let data = vec![7u8; 300];
+        let snappy = snappy_uncompressed(&data);
+        assert_eq!(&snappy[..5], &[0xac, 0x02, 61 << 2, 43, 1]);
+        assert_eq!(snappy.len(), 5 + 300);
+    }
+}
@@ -0,0 +1,272 @@
+//! # MQTT bridge
+//! Devices of a fleet that is managed via MQTT instead of D-Bus connect to a broker (MQTT 3.1.1 without TLS
+//! and authentication) with `--mqtt-broker host:port`. Below the device specific `--mqtt-topic`:
+//!
+//! * "<topic>/status": The status as in the status file (see [`crate::status_file`]). Published retained
+//!   on every change.
+//! * "<topic>/command": Commands as json objects, like `{"command":"forget","ssid":"Home"}`.
+//!   The commands are "forget", "restart-hotspot", "restart-wifi" and "reboot".
+//!
+//! Commands are submitted to the command queue with the "mqtt" surface, see [`crate::commands`].
+//! The default policy has no rule for "mqtt": Configure one, like "mqtt=forget+restart-hotspot".
+//! All packets are sent and received with QoS 0. A lost connection is established again after some seconds.
+//! Available with the "mqtt" feature.
+
+use crate::commands::{Command, CommandSender, Origin, Surface};
+use crate::network_interface::SSID;
+use crate::runtime;
+use crate::status::StatusReceiver;
+use crate::status_file;
+use crate::CaptivePortalError;
+use futures_util::future::{select, Either};
+use pin_utils::pin_mut;
+use serde::Deserialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{interval_at, Instant};
+
+/// The broker disconnects clients that are silent for 1.5 times the keep alive interval
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Larger packets close the connection. Commands are small json objects.
+const MAX_PACKET_SIZE: usize = 4096;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const PINGREQ: u8 = 12;
+const DISCONNECT: u8 = 14;
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum MqttCommand {
+    Forget { ssid: String },
+    RestartHotspot,
+    RestartWifi,
+    Reboot,
+}
+
+fn encode_string(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+/// Prepends the fixed header: The packet type and flags and the variable length encoded body length
+fn encode_packet(packet_type: u8, flags: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![packet_type << 4 | flags];
+    let mut remaining = body.len();
+    loop {
+        let byte = (remaining % 128) as u8;
+        remaining /= 128;
+        match remaining {
+            0 => {
+                packet.push(byte);
+                break;
+            },
+            _ => packet.push(byte | 0x80),
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// A clean session with the given client id
+fn connect_packet(client_id: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    encode_string(&mut body, "MQTT");
+    body.push(4); // Protocol level 3.1.1
+    body.push(0x02); // Clean session
+    body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    encode_string(&mut body, client_id);
+    encode_packet(CONNECT, 0, &body)
+}
+
+fn subscribe_packet(topic: &str) -> Vec<u8> {
+    let mut body = vec![0, 1]; // Packet id
+    encode_string(&mut body, topic);
+    body.push(0); // QoS 0
+    encode_packet(SUBSCRIBE, 0x02, &body)
+}
+
+fn publish_packet(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    encode_string(&mut body, topic);
+    body.extend_from_slice(payload);
+    encode_packet(PUBLISH, retain as u8, &body)
+}
+
+/// Reads the next packet. Returns the first byte (packet type and flags) and the body.
+async fn read_packet<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(u8, Vec<u8>), CaptivePortalError> {
+    let first = reader.read_u8().await?;
+    let mut length = 0usize;
+    for i in 0..4 {
+        let byte = reader.read_u8().await?;
+        length |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    if length > MAX_PACKET_SIZE {
+        return Err(CaptivePortalError::Generic(format!("MQTT packet of {} bytes is too large", length)));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    Ok((first, body))
+}
+
+/// Returns the topic and payload of a publish packet
+fn parse_publish(first: u8, body: &[u8]) -> Option<(&str, &[u8])> {
+    let topic_len = u16::from_be_bytes([*body.get(0)?, *body.get(1)?]) as usize;
+    let topic = std::str::from_utf8(body.get(2..2 + topic_len)?).ok()?;
+    // QoS 1 and 2 packets carry a packet id
+    let offset = match (first >> 1) & 0x03 {
+        0 => 2 + topic_len,
+        _ => 4 + topic_len,
+    };
+    Some((topic, body.get(offset..)?))
+}
+
+fn submit(commands: &CommandSender, payload: &[u8]) {
+    let command = match serde_json::from_slice(payload) {
+        Ok(MqttCommand::Forget { ssid }) => Command::Forget(SSID::from(ssid)),
+        Ok(MqttCommand::RestartHotspot) => Command::RestartHotspot,
+        Ok(MqttCommand::RestartWifi) => Command::RestartWifi,
+        Ok(MqttCommand::Reboot) => Command::Reboot,
+        Err(e) => {
+            warn!("Invalid MQTT command: {}", e);
+            return;
+        },
+    };
+    if let Err(e) = commands.submit(command, Origin::new(Surface::Mqtt, None)) {
+        warn!("MQTT command not queued: {}", e);
+    }
+}
+
+/// Submits the commands of received publish packets. Other packets, like ping responses, are ignored.
+async fn receive<R: AsyncRead + Unpin>(mut reader: R, commands: &CommandSender) -> Result<(), CaptivePortalError> {
+    loop {
+        let (first, body) = read_packet(&mut reader).await?;
+        if first >> 4 == PUBLISH {
+            if let Some((_, payload)) = parse_publish(first, &body) {
+                submit(commands, payload);
+            }
+        }
+    }
+}
+
+/// Publishes the current status and every change. Pings the broker within the keep alive interval.
+/// Disconnects when the status bus is closed.
+async fn publish<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    topic: &str,
+    mut status: StatusReceiver,
+) -> Result<(), CaptivePortalError> {
+    let mut ping = interval_at(Instant::now() + KEEP_ALIVE / 2, KEEP_ALIVE / 2);
+    loop {
+        let status_changed = status.recv();
+        pin_mut!(status_changed);
+        let tick = ping.tick();
+        pin_mut!(tick);
+        let packet = match select(status_changed, tick).await {
+            Either::Left((Some(current), _)) => {
+                let updated = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default();
+                publish_packet(topic, &status_file::encode(&current, updated)?, true)
+            },
+            Either::Right(_) => encode_packet(PINGREQ, 0, &[]),
+            Either::Left((None, _)) => {
+                writer.write_all(&encode_packet(DISCONNECT, 0, &[])).await?;
+                return Ok(());
+            },
+        };
+        writer.write_all(&packet).await?;
+    }
+}
+
+async fn session(
+    broker: &str,
+    topic: &str,
+    commands: &CommandSender,
+    status: StatusReceiver,
+) -> Result<(), CaptivePortalError> {
+    let stream = TcpStream::connect(broker)
+        .await
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to connect to the MQTT broker"))?;
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    writer.write_all(&connect_packet(topic)).await?;
+    match read_packet(&mut reader).await? {
+        (first, ref body) if first >> 4 == CONNACK && body.get(1) == Some(&0) => {},
+        (_, body) => {
+            return Err(CaptivePortalError::Generic(format!(
+                "The MQTT broker refused the connection: {:?}",
+                body
+            )))
+        },
+    }
+    writer.write_all(&subscribe_packet(&format!("{}/command", topic))).await?;
+    info!("Connected to the MQTT broker {}", broker);
+
+    let status_topic = format!("{}/status", topic);
+    let incoming = receive(reader, commands);
+    pin_mut!(incoming);
+    let outgoing = publish(writer, &status_topic, status);
+    pin_mut!(outgoing);
+    match select(incoming, outgoing).await {
+        Either::Left((result, _)) | Either::Right((result, _)) => result,
+    }
+}
+
+/// Keeps a connection to the broker. The topic is also used as client id. Resolves when the status bus is closed.
+pub async fn run(broker: String, topic: String, commands: CommandSender, status: StatusReceiver) {
+    loop {
+        match session(&broker, &topic, &commands, status.clone()).await {
+            Ok(()) => return,
+            Err(e) => warn!("MQTT broker {}: {}", broker, e),
+        }
+        runtime::delay_for(RECONNECT_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets() {
+        assert_eq!(
+            connect_packet("dev"),
+            vec![0x10, 15, 0, 4, b'M', b'Q', b'T', b'T', 4, 2, 0, 60, 0, 3, b'd', b'e', b'v']
+        );
+        assert_eq!(subscribe_packet("a/b"), vec![0x82, 8, 0, 1, 0, 3, b'a', b'/', b'b', 0]);
+        assert_eq!(publish_packet("a", b"x", true), vec![0x31, 4, 0, 1, b'a', b'x']);
+        // Two length bytes: 321 = 65 + 2 * 128
+        let packet = encode_packet(PUBLISH, 0, &[0; 321]);
+        assert_eq!(&packet[..3], &[0x30, 0xc1, 0x02]);
+    }
+
+    #[tokio::test]
+    async fn receive_publish() {
+        let packet = publish_packet("dev/command", br#"{"command":"forget","ssid":"Home"}"#, false);
+        let (first, body) = read_packet(&mut &packet[..]).await.unwrap();
+        let (topic, payload) = parse_publish(first, &body).unwrap();
+        assert_eq!(topic, "dev/command");
+        assert_eq!(
+            serde_json::from_slice::<MqttCommand>(payload).unwrap(),
+            MqttCommand::Forget { ssid: "Home".to_owned() }
+        );
+        assert_eq!(
+            serde_json::from_slice::<MqttCommand>(br#"{"command":"restart-hotspot"}"#).unwrap(),
+            MqttCommand::RestartHotspot
+        );
+
+        let oversized = encode_packet(PUBLISH, 0, &[0; MAX_PACKET_SIZE + 1]);
+        assert!(read_packet(&mut &oversized[..]).await.is_err());
+        assert!(parse_publish(0x30, &[0, 9, b'a']).is_none());
+    }
+}
@@ -0,0 +1,298 @@
+//! # Route and generic netlink
+//! Requests are sent on a blocking netlink socket. The kernel answers them right away.
+//!
+//! Link state and counters ([`link_stats`]) are read from the kernel directly, which works the same
+//! for every network backend and does not poll network manager properties.
+//! Interface addresses are assigned with [`address`], by the wpa_supplicant backend and for the link-local fallback.
+//! Generic netlink families, like nl80211 for the regulatory domain (see [`crate::regulatory`]),
+//! are resolved with [`family_id`]. The network manager backend also adds and removes virtual wifi interfaces
+//! with nl80211 for the concurrent access point and station mode.
+
+use crate::CaptivePortalError;
+use nix::libc;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::FromRawFd;
+
+pub(crate) mod address;
+
+pub(crate) const RTM_NEWLINK: u16 = 16;
+const RTM_GETLINK: u16 = 18;
+pub(crate) const NLMSG_ERROR: u16 = 2;
+
+pub(crate) const NLM_F_REQUEST: u16 = 0x1;
+pub(crate) const NLM_F_ACK: u16 = 0x4;
+
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+const IFF_UP: u32 = 0x1;
+const IFF_LOWER_UP: u32 = 0x10000;
+
+const IFLA_STATS64: u16 = 23;
+const IFLA_CARRIER: u16 = 33;
+/// The upper bits of the attribute type are the nested and byte order flags
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+/// The netlink message header
+pub(crate) fn header(len: usize, kind: u16, flags: u16) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(len);
+    msg.extend_from_slice(&(len as u32).to_ne_bytes());
+    msg.extend_from_slice(&kind.to_ne_bytes());
+    msg.extend_from_slice(&flags.to_ne_bytes());
+    // Sequence number and port id. The kernel answers on the same socket.
+    msg.extend_from_slice(&1u32.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg
+}
+
+/// Returns the error of an acknowledgement. Errors are negative errno values.
+pub(crate) fn parse_ack(reply: &[u8]) -> io::Result<()> {
+    if reply.len() < 20 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Short netlink reply"));
+    }
+    let kind = u16::from_ne_bytes([reply[4], reply[5]]);
+    let error = i32::from_ne_bytes([reply[16], reply[17], reply[18], reply[19]]);
+    match (kind, error) {
+        (NLMSG_ERROR, 0) => Ok(()),
+        (NLMSG_ERROR, e) => Err(io::Error::from_raw_os_error(-e)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected netlink reply")),
+    }
+}
+
+/// The attributes of a message payload: Type and payload. The type is without the nested and byte order flags.
+pub(crate) fn attributes(data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attributes = Vec::new();
+    // Length (2), type (2), payload, aligned to 4 bytes
+    let mut offset = 0;
+    while offset + 4 <= data.len() {
+        let len = u16::from_ne_bytes([data[offset], data[offset + 1]]) as usize;
+        let kind = u16::from_ne_bytes([data[offset + 2], data[offset + 3]]) & NLA_TYPE_MASK;
+        if len < 4 || offset + len > data.len() {
+            break;
+        }
+        attributes.push((kind, &data[offset + 4..offset + len]));
+        offset += (len + 3) & !3;
+    }
+    attributes
+}
+
+/// Appends an attribute, padded to 4 bytes
+pub(crate) fn push_attribute(msg: &mut Vec<u8>, kind: u16, payload: &[u8]) {
+    msg.extend_from_slice(&(4 + payload.len() as u16).to_ne_bytes());
+    msg.extend_from_slice(&kind.to_ne_bytes());
+    msg.extend_from_slice(payload);
+    msg.resize((msg.len() + 3) & !3, 0);
+}
+
+/// Sends the message and returns the reply
+fn exchange(message: &[u8]) -> io::Result<Vec<u8>> {
+    exchange_on(libc::NETLINK_ROUTE, message)
+}
+
+/// Sends the message on a socket of the given netlink protocol and returns the reply
+fn exchange_on(protocol: libc::c_int, message: &[u8]) -> io::Result<Vec<u8>> {
+    let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, protocol) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Closes the socket when dropped
+    let mut socket = unsafe { File::from_raw_fd(fd) };
+    socket.write_all(message)?;
+    // Link replies carry all attributes of the interface
+    let mut reply = vec![0u8; 32 * 1024];
+    let len = socket.read(&mut reply)?;
+    reply.truncate(len);
+    Ok(reply)
+}
+
+/// Sends the request and waits for the acknowledgement
+pub(crate) fn request(message: &[u8]) -> io::Result<()> {
+    parse_ack(&exchange(message)?)
+}
+
+/// The index of the given network interface
+pub(crate) fn interface_index(interface_name: &str) -> Result<u32, CaptivePortalError> {
+    std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", interface_name))
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to read the wifi interface index"))?
+        .trim()
+        .parse()
+        .map_err(|_| CaptivePortalError::Generic(format!("Invalid interface index of {}", interface_name)))
+}
+
+/// Link state and counters of a network interface. The counters start with the interface.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct LinkStats {
+    /// The interface is administratively up
+    pub up: bool,
+    /// The link layer is operational. For wifi this means associated with an access point.
+    pub carrier: bool,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
+}
+
+/// A RTM_GETLINK request for the given interface index
+fn link_request(index: u32) -> Vec<u8> {
+    // Header (16), ifinfomsg (16)
+    let mut msg = header(16 + 16, RTM_GETLINK, NLM_F_REQUEST);
+    msg.extend_from_slice(&[libc::AF_UNSPEC as u8, 0, 0, 0]);
+    msg.extend_from_slice(&(index as i32).to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg
+}
+
+fn read_u64(data: &[u8], field: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    if let Some(value) = data.get(field * 8..field * 8 + 8) {
+        bytes.copy_from_slice(value);
+    }
+    u64::from_ne_bytes(bytes)
+}
+
+/// Parses the RTM_NEWLINK reply of a [`link_request`]
+fn parse_link(reply: &[u8]) -> io::Result<LinkStats> {
+    if reply.len() >= 6 && u16::from_ne_bytes([reply[4], reply[5]]) == NLMSG_ERROR {
+        parse_ack(reply)?;
+    }
+    if reply.len() < 32 || u16::from_ne_bytes([reply[4], reply[5]]) != RTM_NEWLINK {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected netlink reply"));
+    }
+    let len = (u32::from_ne_bytes([reply[0], reply[1], reply[2], reply[3]]) as usize).min(reply.len());
+    let flags = u32::from_ne_bytes([reply[24], reply[25], reply[26], reply[27]]);
+    let mut stats = LinkStats {
+        up: flags & IFF_UP != 0,
+        carrier: flags & IFF_LOWER_UP != 0,
+        ..LinkStats::default()
+    };
+
+    for (kind, payload) in attributes(&reply[32..len.max(32)]) {
+        match kind {
+            IFLA_CARRIER if !payload.is_empty() => stats.carrier = payload[0] != 0,
+            // struct rtnl_link_stats64
+            IFLA_STATS64 => {
+                stats.rx_packets = read_u64(payload, 0);
+                stats.tx_packets = read_u64(payload, 1);
+                stats.rx_bytes = read_u64(payload, 2);
+                stats.tx_bytes = read_u64(payload, 3);
+                stats.rx_errors = read_u64(payload, 4);
+                stats.tx_errors = read_u64(payload, 5);
+                stats.rx_dropped = read_u64(payload, 6);
+                stats.tx_dropped = read_u64(payload, 7);
+            },
+            _ => {},
+        }
+    }
+    Ok(stats)
+}
+
+/// A generic netlink request: Header, generic netlink header (command, version) and attributes
+pub(crate) fn generic_request(family: u16, command: u8, attributes: &[u8]) -> Vec<u8> {
+    let len = 16 + 4 + attributes.len();
+    let mut msg = header(len, family, NLM_F_REQUEST);
+    msg.extend_from_slice(&[command, 1, 0, 0]);
+    msg.extend_from_slice(attributes);
+    msg
+}
+
+/// Requests an acknowledgement. For commands that are not answered otherwise, like removing an interface.
+pub(crate) fn with_ack(mut message: Vec<u8>) -> Vec<u8> {
+    let flags = u16::from_ne_bytes([message[6], message[7]]) | NLM_F_ACK;
+    message[6..8].copy_from_slice(&flags.to_ne_bytes());
+    message
+}
+
+/// Sends a generic netlink request and returns the attributes of the reply, after the generic netlink header
+pub(crate) fn generic_exchange(message: &[u8]) -> io::Result<Vec<u8>> {
+    let reply = exchange_on(libc::NETLINK_GENERIC, message)?;
+    if reply.len() >= 6 && u16::from_ne_bytes([reply[4], reply[5]]) == NLMSG_ERROR {
+        parse_ack(&reply)?;
+    }
+    if reply.len() < 20 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Short netlink reply"));
+    }
+    let len = (u32::from_ne_bytes([reply[0], reply[1], reply[2], reply[3]]) as usize).min(reply.len());
+    Ok(reply[20..len].to_vec())
+}
+
+/// The id of the generic netlink family with the given name, like "nl80211"
+pub(crate) fn family_id(name: &str) -> io::Result<u16> {
+    let mut name = name.as_bytes().to_vec();
+    name.push(0);
+    let mut attributes = Vec::new();
+    push_attribute(&mut attributes, CTRL_ATTR_FAMILY_NAME, &name);
+    let reply = generic_exchange(&generic_request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, &attributes))?;
+    self::attributes(&reply)
+        .into_iter()
+        .find(|(kind, payload)| *kind == CTRL_ATTR_FAMILY_ID && payload.len() >= 2)
+        .map(|(_, payload)| u16::from_ne_bytes([payload[0], payload[1]]))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Unknown generic netlink family"))
+}
+
+/// Reads the link state and counters of the interface with the given index
+pub fn link_stats(index: u32) -> Result<LinkStats, CaptivePortalError> {
+    exchange(&link_request(index))
+        .and_then(|reply| parse_link(&reply))
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to read the link statistics"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_reply() {
+        assert_eq!(link_request(3).len(), 32);
+
+        let mut reply = header(0, RTM_NEWLINK, 0);
+        reply.extend_from_slice(&[libc::AF_UNSPEC as u8, 0, 1, 0]);
+        reply.extend_from_slice(&3i32.to_ne_bytes());
+        reply.extend_from_slice(&(IFF_UP | IFF_LOWER_UP).to_ne_bytes());
+        reply.extend_from_slice(&0u32.to_ne_bytes());
+        // IFLA_CARRIER: 1 byte payload, padded to 4
+        reply.extend_from_slice(&5u16.to_ne_bytes());
+        reply.extend_from_slice(&IFLA_CARRIER.to_ne_bytes());
+        reply.extend_from_slice(&[0, 0, 0, 0]);
+        reply.extend_from_slice(&(4u16 + 8 * 8).to_ne_bytes());
+        reply.extend_from_slice(&IFLA_STATS64.to_ne_bytes());
+        for counter in 1..=8u64 {
+            reply.extend_from_slice(&counter.to_ne_bytes());
+        }
+        let len = reply.len() as u32;
+        reply[..4].copy_from_slice(&len.to_ne_bytes());
+
+        let stats = parse_link(&reply).unwrap();
+        assert!(stats.up);
+        // The carrier attribute takes precedence over the flag
+        assert!(!stats.carrier);
+        assert_eq!(stats.rx_packets, 1);
+        assert_eq!(stats.rx_bytes, 3);
+        assert_eq!(stats.tx_dropped, 8);
+
+        let mut nack = header(36, NLMSG_ERROR, 0);
+        nack.extend_from_slice(&(-libc::ENODEV).to_ne_bytes());
+        assert_eq!(parse_link(&nack).unwrap_err().raw_os_error(), Some(libc::ENODEV));
+    }
+
+    #[test]
+    fn generic_attributes() {
+        let mut data = Vec::new();
+        push_attribute(&mut data, CTRL_ATTR_FAMILY_NAME, b"nl80211\0");
+        push_attribute(&mut data, CTRL_ATTR_FAMILY_ID, &[1, 2, 3]);
+        assert_eq!(data.len(), 12 + 8);
+        assert_eq!(
+            attributes(&data),
+            vec![(CTRL_ATTR_FAMILY_NAME, &b"nl80211\0"[..]), (CTRL_ATTR_FAMILY_ID, &[1, 2, 3][..])]
+        );
+        assert_eq!(generic_request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, &data).len(), 20 + 20);
+    }
+}
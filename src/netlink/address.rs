@@ -0,0 +1,89 @@
+//! # Interface addressing via rtnetlink
+//! Adds and removes IPv4 addresses of an interface and sets it up, like `ip addr add` and `ip link set up`.
+//! Used for the hotspot address of the wpa_supplicant backend and the link-local fallback address
+//! ([`crate::link_local`]).
+
+use super::{header, request, NLM_F_ACK, NLM_F_REQUEST, RTM_NEWLINK};
+use crate::CaptivePortalError;
+use nix::libc;
+use std::net::Ipv4Addr;
+
+const RTM_NEWADDR: u16 = 20;
+const RTM_DELADDR: u16 = 21;
+
+const NLM_F_REPLACE: u16 = 0x100;
+const NLM_F_CREATE: u16 = 0x400;
+
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+const IFF_UP: u32 = 0x1;
+
+/// A RTM_NEWADDR or RTM_DELADDR request for the given interface index
+fn address_request(kind: u16, index: u32, address: Ipv4Addr, prefix: u8) -> Vec<u8> {
+    // Header (16), ifaddrmsg (8), IFA_LOCAL and IFA_ADDRESS attributes (8 each)
+    let len = 16 + 8 + 8 + 8;
+    let flags = match kind {
+        RTM_NEWADDR => NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE,
+        _ => NLM_F_REQUEST | NLM_F_ACK,
+    };
+    let mut msg = header(len, kind, flags);
+    msg.extend_from_slice(&[libc::AF_INET as u8, prefix, 0, 0]);
+    msg.extend_from_slice(&index.to_ne_bytes());
+    for attribute in &[IFA_LOCAL, IFA_ADDRESS] {
+        msg.extend_from_slice(&8u16.to_ne_bytes());
+        msg.extend_from_slice(&attribute.to_ne_bytes());
+        msg.extend_from_slice(&address.octets());
+    }
+    msg
+}
+
+/// A RTM_NEWLINK request that sets the given interface up
+fn link_up_request(index: u32) -> Vec<u8> {
+    // Header (16), ifinfomsg (16)
+    let mut msg = header(16 + 16, RTM_NEWLINK, NLM_F_REQUEST | NLM_F_ACK);
+    msg.extend_from_slice(&[libc::AF_UNSPEC as u8, 0, 0, 0]);
+    msg.extend_from_slice(&(index as i32).to_ne_bytes());
+    msg.extend_from_slice(&IFF_UP.to_ne_bytes());
+    msg.extend_from_slice(&IFF_UP.to_ne_bytes());
+    msg
+}
+
+/// Assigns the address to the interface and sets the interface up
+pub(crate) fn add_address(index: u32, address: Ipv4Addr, prefix: u8) -> Result<(), CaptivePortalError> {
+    request(&address_request(RTM_NEWADDR, index, address, prefix))
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to assign the interface address"))?;
+    request(&link_up_request(index)).map_err(|e| CaptivePortalError::IO(e, "Failed to set the interface up"))
+}
+
+/// Removes the address from the interface. An address that is not assigned is not an error.
+pub(crate) fn remove_address(index: u32, address: Ipv4Addr, prefix: u8) -> Result<(), CaptivePortalError> {
+    match request(&address_request(RTM_DELADDR, index, address, prefix)) {
+        Err(e) if e.raw_os_error() != Some(libc::EADDRNOTAVAIL) => {
+            Err(CaptivePortalError::IO(e, "Failed to remove the interface address"))
+        },
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netlink::{parse_ack, NLMSG_ERROR};
+
+    #[test]
+    fn messages() {
+        let msg = address_request(RTM_NEWADDR, 3, Ipv4Addr::new(192, 168, 42, 1), 24);
+        assert_eq!(msg.len(), 40);
+        assert_eq!(u32::from_ne_bytes([msg[0], msg[1], msg[2], msg[3]]), 40);
+        assert_eq!(&msg[16..20], &[libc::AF_INET as u8, 24, 0, 0]);
+        assert_eq!(&msg[28..32], &[192, 168, 42, 1]);
+        assert_eq!(link_up_request(3).len(), 32);
+
+        let mut ack = header(36, NLMSG_ERROR, 0);
+        ack.extend_from_slice(&0i32.to_ne_bytes());
+        assert!(parse_ack(&ack).is_ok());
+        let mut nack = header(36, NLMSG_ERROR, 0);
+        nack.extend_from_slice(&(-libc::EPERM).to_ne_bytes());
+        assert_eq!(parse_ack(&nack).unwrap_err().raw_os_error(), Some(libc::EPERM));
+    }
+}
@@ -0,0 +1,70 @@
+//! # Access points change stream
+//! Provides a stream of added and removed ConnMan wifi service paths.
+//! ConnMan announces new, changed and removed services with the ServicesChanged signal of its manager.
+
+use dbus::arg::{self, RefArg, Variant};
+use dbus::message::SignalArgs;
+use futures_util::stream::{self, StreamExt};
+use std::collections::HashMap;
+
+use crate::dbus_tokio::SignalStream;
+use crate::network_backend::{NetworkBackend, CONNMAN_BUSNAME};
+use crate::network_interface::WifiConnectionEventType;
+use crate::runtime::BoxStream;
+use crate::CaptivePortalError;
+
+pub struct AccessPointChanged {
+    pub path: String,
+    pub event: WifiConnectionEventType,
+}
+
+/// The changed services also carry their properties as dictionary. Those are not thread safe (RefArg)
+/// and not required, so only the paths are kept. This is why the signal type is not generated.
+#[derive(Debug)]
+struct ServicesChanged {
+    changed: Vec<dbus::Path<'static>>,
+    removed: Vec<dbus::Path<'static>>,
+}
+
+impl arg::ReadAll for ServicesChanged {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        let changed: Vec<(dbus::Path<'static>, HashMap<String, Variant<Box<dyn RefArg + 'static>>>)> = i.read()?;
+        Ok(ServicesChanged {
+            changed: changed.into_iter().map(|(path, _)| path).collect(),
+            removed: i.read()?,
+        })
+    }
+}
+
+impl SignalArgs for ServicesChanged {
+    const NAME: &'static str = "ServicesChanged";
+    const INTERFACE: &'static str = "net.connman.Manager";
+}
+
+/// New and changed services are both reported as added
+fn helper(v: (ServicesChanged, String), prefix: &str) -> Vec<AccessPointChanged> {
+    let changed = v.0.changed.into_iter().map(|path| (path, WifiConnectionEventType::Added));
+    let removed = v.0.removed.into_iter().map(|path| (path, WifiConnectionEventType::Removed));
+    changed
+        .chain(removed)
+        .filter(|(path, _)| path.starts_with(prefix))
+        .map(|(path, event)| AccessPointChanged {
+            path: path.to_string(),
+            event,
+        })
+        .collect()
+}
+
+pub async fn ap_changed_stream(
+    network_manager: &NetworkBackend,
+) -> Result<BoxStream<'static, AccessPointChanged>, CaptivePortalError> {
+    let rule = ServicesChanged::match_rule(Some(&CONNMAN_BUSNAME.to_owned().into()), Some(&"/".into())).static_clone();
+
+    // Only the wifi services of the own interface
+    let prefix = network_manager.service_prefix.clone();
+    let inner_stream = SignalStream::<ServicesChanged>::new(network_manager.conn.clone(), rule)
+        .await?
+        .flat_map(move |v| stream::iter(helper(v, &prefix)));
+
+    Ok(Box::pin(inner_stream))
+}
@@ -0,0 +1,163 @@
+//! # Credentials agent
+//! ConnMan asks a registered agent object for the passphrase of a service that is not yet configured.
+//! [`NetworkBackend::connect_to`](super::NetworkBackend::connect_to) stores the credentials of the
+//! service it is about to connect to, and the agent answers the input request of ConnMan with them.
+//!
+//! Enterprise networks require the EAP method, which cannot be provided by an agent.
+//! For those a provisioning file is written to the ConnMan storage directory instead, see [`provisioning_file`].
+
+use super::generated::connman::NetConnmanManager;
+use super::CONNMAN_BUSNAME;
+use crate::dbus_tokio::DbusConnection;
+use crate::network_interface::{AccessPointCredentials, EapMethod, SSID};
+use crate::CaptivePortalError;
+use dbus::arg::{RefArg, Variant};
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::{MatchRule, MessageType};
+use dbus::{nonblock, Message};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+pub(crate) const AGENT_PATH: &str = "/org/openhab/WifiCaptive/ConnmanAgent";
+const AGENT_INTERFACE: &str = "net.connman.Agent";
+/// The directory of the provisioning files of ConnMan
+const CONNMAN_STORAGE_DIR: &str = "/var/lib/connman";
+
+/// The service path and the credentials that the agent hands out
+pub(crate) type PendingCredentials = Arc<Mutex<Option<(dbus::Path<'static>, AccessPointCredentials)>>>;
+
+/// Answers the credential requests of ConnMan and registers the agent.
+pub(crate) async fn register(conn: Arc<DbusConnection>, pending: PendingCredentials) -> Result<(), CaptivePortalError> {
+    let mut rule = MatchRule::new();
+    rule.msg_type = Some(MessageType::MethodCall);
+    rule.path = Some(AGENT_PATH.into());
+    rule.interface = Some(AGENT_INTERFACE.into());
+    conn.start_receive(
+        rule,
+        Box::new(move |msg, conn| {
+            let _ = conn.send(method_call(&pending, &msg));
+            true
+        }),
+    );
+
+    let p = nonblock::Proxy::new(CONNMAN_BUSNAME, "/", conn.clone());
+    p.register_agent(AGENT_PATH.into()).await?;
+    Ok(())
+}
+
+fn error(msg: &Message, name: &'static str, text: &str) -> Message {
+    msg.error(&name.into(), &CString::new(text).unwrap_or_default())
+}
+
+/// Returns the reply for the given agent method call
+fn method_call(pending: &PendingCredentials, msg: &Message) -> Message {
+    let member = msg.member();
+    let member = member.as_ref().map(|m| &**m).unwrap_or_default();
+    match member {
+        "RequestInput" => {},
+        "ReportError" => {
+            if let Ok((service, reason)) = msg.read2::<dbus::Path, &str>() {
+                info!("ConnMan reported an error for {}: {}", service, reason);
+            }
+            return msg.method_return();
+        },
+        "Release" | "Cancel" => return msg.method_return(),
+        _ => return error(msg, "net.connman.Agent.Error.Canceled", "Not supported"),
+    }
+
+    // The requested fields are dictionaries of their own. Only the names are required.
+    let request = msg.read2::<dbus::Path, HashMap<String, Variant<Box<dyn RefArg>>>>();
+    let (service, fields): (dbus::Path<'static>, Vec<String>) = match request {
+        Ok((service, fields)) => (service.into_static(), fields.into_iter().map(|(name, _)| name).collect()),
+        Err(_) => return error(msg, "net.connman.Agent.Error.Canceled", "Invalid input request"),
+    };
+    let credentials = match pending.lock().expect("Lock pending credentials").clone() {
+        Some((path, credentials)) if path == service => credentials,
+        _ => {
+            warn!("ConnMan requested credentials for an unexpected service: {}", service);
+            return error(msg, "net.connman.Agent.Error.Canceled", "No credentials for this service");
+        },
+    };
+    match input(&fields, credentials) {
+        Some(values) => msg.method_return().append1(values),
+        None => {
+            warn!("ConnMan requested unsupported credentials: {:?}", fields);
+            error(msg, "net.connman.Agent.Error.Canceled", "Credentials not available")
+        },
+    }
+}
+
+/// The values for the requested input fields. None if a field cannot be answered.
+fn input(fields: &[String], credentials: AccessPointCredentials) -> Option<HashMap<String, Variant<String>>> {
+    let (identity, passphrase) = match credentials {
+        AccessPointCredentials::None => (None, None),
+        AccessPointCredentials::Wep { passphrase }
+        | AccessPointCredentials::Wpa { passphrase }
+        | AccessPointCredentials::Sae { passphrase } => (None, Some(passphrase)),
+        AccessPointCredentials::Enterprise { identity, passphrase, .. } => (Some(identity), Some(passphrase)),
+    };
+    fields
+        .iter()
+        .map(|field| {
+            let value = match &field[..] {
+                "Passphrase" | "Password" => passphrase.clone(),
+                "Identity" | "Username" => identity.clone(),
+                _ => None,
+            };
+            value.map(|value| (field.clone(), Variant(value)))
+        })
+        .collect()
+}
+
+/// Writes a provisioning file for an enterprise network (PEAP with MSCHAPv2 or TTLS with PAP) and returns its path.
+/// ConnMan picks it up as configured service.
+pub(crate) fn provisioning_file(
+    ssid: &SSID,
+    identity: &str,
+    passphrase: &str,
+    anonymous_identity: Option<&str>,
+    method: EapMethod,
+) -> Result<PathBuf, CaptivePortalError> {
+    let hex = ssid.to_hex();
+    let mut content = format!(
+        "[service_{0}]\nType=wifi\nSSID={0}\nEAP={1}\nPhase2={2}\nIdentity={3}\nPassphrase={4}\n",
+        hex,
+        method.as_str(),
+        method.phase2().to_uppercase(),
+        identity,
+        passphrase
+    );
+    if let Some(anonymous_identity) = anonymous_identity {
+        content += &format!("AnonymousIdentity={}\n", anonymous_identity);
+    }
+    let path = PathBuf::from(CONNMAN_STORAGE_DIR).join(format!("wifi-captive-{}.config", hex));
+    crate::state_dir::write_atomic(&path, content.as_bytes())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_fields() {
+        let fields = vec!["Identity".to_owned(), "Passphrase".to_owned()];
+        let credentials = AccessPointCredentials::Enterprise {
+            identity: "user".to_owned(),
+            passphrase: "secret".to_owned(),
+            anonymous_identity: None,
+            method: EapMethod::Peap,
+        };
+        let values = input(&fields, credentials).unwrap();
+        assert_eq!(values["Identity"].0, "user");
+        assert_eq!(values["Passphrase"].0, "secret");
+
+        let credentials = AccessPointCredentials::Wpa {
+            passphrase: "secret".to_owned(),
+        };
+        assert!(input(&fields, credentials.clone()).is_none());
+        assert_eq!(input(&fields[1..], credentials).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,120 @@
+// This code was autogenerated with `dbus-codegen-rust -c nonblock -m None --dbuscrate ::dbus -o connman.rs`, see https://github.com/diwic/dbus-rs
+use ::dbus;
+use ::dbus::arg;
+use ::dbus::nonblock;
+
+pub trait NetConnmanManager {
+    fn get_properties(
+        &self,
+    ) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>>;
+    fn get_services(
+        &self,
+    ) -> nonblock::MethodReply<
+        Vec<(
+            dbus::Path<'static>,
+            ::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>,
+        )>,
+    >;
+    fn register_agent(&self, path: dbus::Path) -> nonblock::MethodReply<()>;
+    fn unregister_agent(&self, path: dbus::Path) -> nonblock::MethodReply<()>;
+}
+
+impl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target = T>> NetConnmanManager for nonblock::Proxy<'a, C> {
+    fn get_properties(
+        &self,
+    ) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>> {
+        self.method_call("net.connman.Manager", "GetProperties", ()).and_then(
+            |r: (::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>,)| Ok(r.0),
+        )
+    }
+
+    fn get_services(
+        &self,
+    ) -> nonblock::MethodReply<
+        Vec<(
+            dbus::Path<'static>,
+            ::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>,
+        )>,
+    > {
+        self.method_call("net.connman.Manager", "GetServices", ()).and_then(
+            |r: (
+                Vec<(
+                    dbus::Path<'static>,
+                    ::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>,
+                )>,
+            )| Ok(r.0),
+        )
+    }
+
+    fn register_agent(&self, path: dbus::Path) -> nonblock::MethodReply<()> {
+        self.method_call("net.connman.Manager", "RegisterAgent", (path,))
+    }
+
+    fn unregister_agent(&self, path: dbus::Path) -> nonblock::MethodReply<()> {
+        self.method_call("net.connman.Manager", "UnregisterAgent", (path,))
+    }
+}
+
+pub trait NetConnmanTechnology {
+    fn get_properties(
+        &self,
+    ) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>>;
+    fn set_property(&self, name: &str, value: arg::Variant<Box<dyn arg::RefArg>>) -> nonblock::MethodReply<()>;
+    fn scan(&self) -> nonblock::MethodReply<()>;
+}
+
+impl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target = T>> NetConnmanTechnology
+    for nonblock::Proxy<'a, C>
+{
+    fn get_properties(
+        &self,
+    ) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>> {
+        self.method_call("net.connman.Technology", "GetProperties", ()).and_then(
+            |r: (::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>,)| Ok(r.0),
+        )
+    }
+
+    fn set_property(&self, name: &str, value: arg::Variant<Box<dyn arg::RefArg>>) -> nonblock::MethodReply<()> {
+        self.method_call("net.connman.Technology", "SetProperty", (name, value))
+    }
+
+    fn scan(&self) -> nonblock::MethodReply<()> {
+        self.method_call("net.connman.Technology", "Scan", ())
+    }
+}
+
+pub trait NetConnmanService {
+    fn get_properties(
+        &self,
+    ) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>>;
+    fn set_property(&self, name: &str, value: arg::Variant<Box<dyn arg::RefArg>>) -> nonblock::MethodReply<()>;
+    fn connect(&self) -> nonblock::MethodReply<()>;
+    fn disconnect(&self) -> nonblock::MethodReply<()>;
+    fn remove(&self) -> nonblock::MethodReply<()>;
+}
+
+impl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target = T>> NetConnmanService for nonblock::Proxy<'a, C> {
+    fn get_properties(
+        &self,
+    ) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>> {
+        self.method_call("net.connman.Service", "GetProperties", ()).and_then(
+            |r: (::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>,)| Ok(r.0),
+        )
+    }
+
+    fn set_property(&self, name: &str, value: arg::Variant<Box<dyn arg::RefArg>>) -> nonblock::MethodReply<()> {
+        self.method_call("net.connman.Service", "SetProperty", (name, value))
+    }
+
+    fn connect(&self) -> nonblock::MethodReply<()> {
+        self.method_call("net.connman.Service", "Connect", ())
+    }
+
+    fn disconnect(&self) -> nonblock::MethodReply<()> {
+        self.method_call("net.connman.Service", "Disconnect", ())
+    }
+
+    fn remove(&self) -> nonblock::MethodReply<()> {
+        self.method_call("net.connman.Service", "Remove", ())
+    }
+}
@@ -0,0 +1,5 @@
+#!/bin/sh -e
+# cargo install --git https://github.com/diwic/dbus-rs --force  dbus-codegen
+
+# The xml file only contains the subset of the ConnMan API that is used by this backend
+cat connman.xml | dbus-codegen-rust -c nonblock -m None --dbuscrate ::dbus -o connman.rs
@@ -0,0 +1,680 @@
+//! # The ConnMan backend
+//! See https://git.kernel.org/pub/scm/network/connman/connman.git/tree/doc for API documentation.
+//!
+//! All files in generated/* are auto-generated.
+//! Use the `generate.sh` script to update them to newer dbus crate or ConnMan API versions.
+//!
+//! ConnMan represents every wifi network as "service" and does not report access points, their mac
+//! addresses or frequencies. The service path is therefore reported as "hw" address of a [`WifiConnection`]
+//! and the frequency is 0. Like iwd, ConnMan asks a registered agent for the credentials of a service,
+//! see [`credentials_agent`].
+//!
+//! The hotspot is ConnMan's wifi tethering. ConnMan chooses the tethering address itself (usually 192.168.0.1)
+//! and serves dhcp and dns on the tethering bridge. Configure the gateway address accordingly and build
+//! without the "dhcp" feature.
+//! ConnMan always stores configured services on disk. In-memory mode is not supported.
+mod generated;
+
+mod access_points_changed;
+mod credentials_agent;
+
+use crate::dbus_tokio::{self, DbusConnection};
+use crate::mac_filter::MacFilterSync;
+use crate::network_interface::{
+    AccessPointCredentials, ActiveConnection, ConnectOptions, ConnectionQuality, ConnectionState, HotspotRadio,
+    HotspotSecurity, NetworkManagerState, Pmf, Security, WifiConnection, SSID,
+};
+use crate::runtime::{self, delay_for};
+use crate::network_backend::WifiBackend;
+use crate::CaptivePortalError;
+use credentials_agent::PendingCredentials;
+use generated::connman::{NetConnmanManager, NetConnmanService, NetConnmanTechnology};
+
+use dbus::arg::{RefArg, Variant};
+use dbus::nonblock;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Public API: AccessPointsChangedStream
+pub use access_points_changed::{ap_changed_stream, AccessPointChanged};
+
+/// The handle of a connection of this backend, see [`crate::ActiveConnection`]
+pub type ConnectionPath = dbus::Path<'static>;
+
+pub const CONNMAN_BUSNAME: &str = "net.connman";
+pub(crate) const WIFI_TECHNOLOGY_PATH: &str = "/net/connman/technology/wifi";
+/// Polling interval for state changes
+const POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+type VariantMap = HashMap<String, Variant<Box<dyn RefArg + 'static>>>;
+
+/// The properties of a wifi service that are used by this backend
+#[derive(Clone, Debug)]
+struct Service {
+    path: dbus::Path<'static>,
+    name: String,
+    security: Vec<String>,
+    strength: u8,
+    state: String,
+    favorite: bool,
+}
+
+impl Service {
+    /// The service properties are not thread safe (RefArg). Only the used ones are kept.
+    fn new(path: dbus::Path<'static>, m: &VariantMap) -> Self {
+        Service {
+            path,
+            name: m.get("Name").and_then(|v| v.0.as_str()).unwrap_or_default().to_owned(),
+            security: m
+                .get("Security")
+                .and_then(|v| v.0.as_iter())
+                .map(|i| i.filter_map(|s| s.as_str().map(|s| s.to_owned())).collect())
+                .unwrap_or_default(),
+            strength: m.get("Strength").and_then(|v| v.0.as_u64()).unwrap_or_default() as u8,
+            state: m.get("State").and_then(|v| v.0.as_str()).unwrap_or("idle").to_owned(),
+            favorite: m.get("Favorite").and_then(|v| v.0.as_i64()).unwrap_or_default() != 0,
+        }
+    }
+
+    fn connection_state(&self) -> NetworkManagerState {
+        NetworkManagerState::from(&self.state[..])
+    }
+}
+
+impl From<&str> for NetworkManagerState {
+    /// ConnMan checks the internet connectivity itself and reports "online" if it is given
+    fn from(state: &str) -> Self {
+        match state {
+            "online" => NetworkManagerState::Connected,
+            "ready" => NetworkManagerState::ConnectedLimited,
+            "association" | "configuration" => NetworkManagerState::Connecting,
+            "disconnect" => NetworkManagerState::Disconnecting,
+            "idle" | "failure" => NetworkManagerState::Disconnected,
+            _ => {
+                warn!("Undefined ConnMan service state: {}", state);
+                NetworkManagerState::Unknown
+            },
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NetworkBackend {
+    exit_handler: Arc<Mutex<Option<tokio::sync::oneshot::Sender<()>>>>,
+    pub(crate) conn: Arc<DbusConnection>,
+    /// The path prefix of the wifi services of the own interface, "/net/connman/service/wifi_<mac>_"
+    pub(crate) service_prefix: String,
+    /// Network interface name
+    interface_name: String,
+    /// The credentials of the service that is being connected to, handed out by the agent
+    pending_credentials: PendingCredentials,
+}
+
+impl NetworkBackend {
+    /// Create a new connection to ConnMan and register the credentials agent.
+    /// Returns an error if no wifi device can be found.
+    pub async fn new(interface_name: &Option<String>) -> Result<NetworkBackend, CaptivePortalError> {
+        // Prepare an exit handler
+        let (exit_handler, exit_receiver) = tokio::sync::oneshot::channel::<()>();
+
+        // Connect to the D-Bus session bus (this is blocking, unfortunately).
+        let (resource, conn) = dbus_tokio::new_system_default()?;
+
+        // The resource is a task that should be spawned onto the async
+        // runtime ASAP. If the resource ever finishes, you lost connection to D-Bus.
+        runtime::spawn(async move {
+            use futures_util::future::select;
+            use futures_util::future::Either;
+            use pin_utils::pin_mut;
+
+            pin_mut!(resource);
+            pin_mut!(exit_receiver);
+            let result = select(resource, exit_receiver).await;
+            if let Either::Left((err, _)) = result {
+                panic!("Lost connection to D-Bus: {}", err);
+            }
+        });
+
+        // ConnMan does not expose devices. The wifi interface is found via sysfs.
+        let interface_name = find_wifi_interface(interface_name)?;
+        let hw = std::fs::read_to_string(format!("/sys/class/net/{}/address", interface_name))
+            .map_err(|e| CaptivePortalError::IO(e, "Failed to read the wifi interface address"))?
+            .trim()
+            .to_lowercase();
+
+        info!("Using wifi interface {} ({})", interface_name, hw);
+
+        let pending_credentials = PendingCredentials::default();
+        credentials_agent::register(conn.clone(), pending_credentials.clone()).await?;
+
+        Ok(NetworkBackend {
+            exit_handler: Arc::new(Mutex::new(Some(exit_handler))),
+            conn,
+            service_prefix: format!("/net/connman/service/wifi_{}_", hw.replace(':', "")),
+            interface_name,
+            pending_credentials,
+        })
+    }
+
+    /// ConnMan always stores configured services in its own state directory. In-memory mode is not supported.
+    pub fn set_in_memory(&mut self, in_memory: bool) {
+        if in_memory {
+            warn!("The ConnMan backend cannot keep connections in memory only. ConnMan will store them on disk.");
+        }
+    }
+
+    /// ConnMan negotiates protected management frames itself.
+    pub fn set_pmf(&mut self, pmf: Pmf) {
+        if pmf != Pmf::Auto {
+            warn!("The ConnMan backend cannot configure protected management frames. ConnMan negotiates them.");
+        }
+    }
+
+    /// ConnMan tethering does not support OWE. Requesting an OWE transition mode pair fails.
+    pub fn set_owe_transition(&mut self, owe_transition: bool) -> Result<(), CaptivePortalError> {
+        match owe_transition {
+            true => Err(CaptivePortalError::Generic(
+                "The ConnMan backend cannot offer an OWE transition mode pair (--hotspot-owe)".to_owned(),
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// The ConnMan access point has no client limit. The dhcp server limits the clients instead.
+    pub fn set_max_clients(&mut self, max_clients: Option<u32>) {
+        if max_clients.is_some() {
+            info!("The ConnMan backend cannot limit the hotspot clients. Only the dhcp leases are limited.");
+        }
+    }
+
+    /// The ConnMan access point has no MAC filter. The dhcp server rejects the clients instead.
+    pub fn set_mac_filter(&mut self, mac_filter: MacFilterSync) {
+        if !mac_filter.read().expect("mac filter lock").is_empty() {
+            info!("The ConnMan backend cannot filter the hotspot clients. Only the dhcp leases are filtered.");
+        }
+    }
+
+    /// ConnMan tethering uses WPA2.
+    pub fn set_hotspot_security(&mut self, security: HotspotSecurity) {
+        if security != HotspotSecurity::Wpa2 {
+            warn!("The ConnMan backend cannot offer a WPA3 hotspot. The hotspot uses WPA2.");
+        }
+    }
+
+    /// Whether the backend assigns the address given to [`NetworkBackend::hotspot_start`].
+    /// ConnMan chooses the tethering address.
+    pub fn assigns_hotspot_address(&self) -> bool {
+        false
+    }
+
+    /// The message key of the reason why the last connection attempt failed, see [`crate::i18n`].
+    /// Failure reasons are not reported by this backend.
+    pub fn last_failure(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// The name of the wifi network interface
+    pub fn interface_name(&self) -> &str {
+        &self.interface_name
+    }
+
+    fn technology(&self) -> nonblock::Proxy<'static, Arc<DbusConnection>> {
+        nonblock::Proxy::new(CONNMAN_BUSNAME, WIFI_TECHNOLOGY_PATH, self.conn.clone())
+    }
+
+    /// The "Powered" and "Tethering" flags of the wifi technology
+    async fn technology_flags(&self) -> Result<(bool, bool), CaptivePortalError> {
+        let flags = NetConnmanTechnology::get_properties(&self.technology()).and_then(|m| {
+            let flag = |key: &str| m.get(key).and_then(|v| v.0.as_i64()).unwrap_or_default() != 0;
+            Ok((flag("Powered"), flag("Tethering")))
+        });
+        Ok(flags.await?)
+    }
+
+    /// The wifi services of the own interface, ordered by ConnMan: Connected services first,
+    /// then by signal strength.
+    async fn services(&self) -> Result<Vec<Service>, CaptivePortalError> {
+        let prefix = self.service_prefix.clone();
+        let p = nonblock::Proxy::new(CONNMAN_BUSNAME, "/", self.conn.clone());
+        let services = p.get_services().and_then(move |services| {
+            Ok(services
+                .iter()
+                .filter(|(path, _)| path.starts_with(&prefix))
+                .map(|(path, m)| Service::new(path.clone(), m))
+                .collect::<Vec<Service>>())
+        });
+        Ok(services.await?)
+    }
+
+    /// The connected service. None if the wifi device is not connected.
+    async fn connected_service(&self) -> Result<Option<Service>, CaptivePortalError> {
+        Ok(self.services().await?.into_iter().find(|s| {
+            let state = s.connection_state();
+            state == NetworkManagerState::Connected || state == NetworkManagerState::ConnectedLimited
+        }))
+    }
+
+    /// The SSID of the connected service. None if the wifi device is not connected.
+    pub async fn active_ssid(&self) -> Result<Option<SSID>, CaptivePortalError> {
+        Ok(self.connected_service().await?.map(|s| SSID::from(s.name)))
+    }
+
+    /// The signal strength of the current connection. ConnMan does not report the bitrate.
+    /// The gateway round trip time is not measured by the backend, see [`crate::quality`].
+    pub async fn connection_quality(&self) -> Result<ConnectionQuality, CaptivePortalError> {
+        Ok(ConnectionQuality {
+            signal: self.connected_service().await?.map(|s| s.strength),
+            bitrate: None,
+            gateway_rtt: None,
+        })
+    }
+
+    /// Wifi might be disabled. This method powers the wifi technology.
+    pub async fn enable_networking_and_wifi(&self) -> Result<(), CaptivePortalError> {
+        set_property(&self.technology(), "Powered", true).await?;
+        Ok(())
+    }
+
+    /// Request a scan. Not possible while the hotspot is active.
+    pub async fn scan_networks(&self) -> Result<(), CaptivePortalError> {
+        if self.technology_flags().await?.1 {
+            return Err(CaptivePortalError::NotInStationMode);
+        }
+        self.technology().scan().await?;
+        Ok(())
+    }
+
+    /// Terminates this dbus connection
+    pub fn quit(self) {
+        let mut exit_handler = self
+            .exit_handler
+            .lock()
+            .expect("Lock network manager exit handler mutex");
+        if let Some(exit_handler) = exit_handler.take() {
+            let _ = exit_handler.send(());
+        }
+    }
+
+    /// The state of the most connected wifi service. An active hotspot counts as disconnected.
+    pub async fn state(&self) -> Result<NetworkManagerState, CaptivePortalError> {
+        let (powered, tethering) = self.technology_flags().await?;
+        if !powered {
+            return Ok(NetworkManagerState::Asleep);
+        }
+        if tethering {
+            return Ok(NetworkManagerState::Disconnected);
+        }
+        let states: Vec<NetworkManagerState> = self.services().await?.iter().map(|s| s.connection_state()).collect();
+        Ok([
+            NetworkManagerState::Connected,
+            NetworkManagerState::ConnectedLimited,
+            NetworkManagerState::Connecting,
+            NetworkManagerState::Disconnecting,
+        ]
+        .iter()
+        .find(|state| states.contains(*state))
+        .cloned()
+        .unwrap_or(NetworkManagerState::Disconnected))
+    }
+
+    /// Stops tethering and waits for ConnMan to connect to one of the configured services.
+    pub async fn try_auto_connect(&self, timeout: std::time::Duration) -> Result<bool, CaptivePortalError> {
+        self.deactivate_hotspots().await?;
+
+        match self.wait_for_connectivity(false, timeout).await {
+            Ok(state) => Ok(state == NetworkManagerState::Connected || state == NetworkManagerState::ConnectedLimited),
+            Err(CaptivePortalError::NotRequiredConnectivity(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The returned future resolves when either the timeout expired or the wifi service
+    /// reports (internet) connectivity.
+    pub async fn wait_for_connectivity(
+        &self,
+        internet_connectivity: bool,
+        timeout: std::time::Duration,
+    ) -> Result<NetworkManagerState, CaptivePortalError> {
+        self.connectivity_changed(timeout, |state| {
+            state == NetworkManagerState::Connected
+                || (state == NetworkManagerState::ConnectedLimited && !internet_connectivity)
+        })
+        .await
+    }
+
+    /// The returned future resolves when either the timeout expired or (internet) connectivity is lost
+    pub async fn wait_for_connectivity_lost(
+        &self,
+        internet_connectivity: bool,
+        timeout: std::time::Duration,
+    ) -> Result<NetworkManagerState, CaptivePortalError> {
+        self.connectivity_changed(timeout, |state| {
+            state != NetworkManagerState::Connected
+                && (state != NetworkManagerState::ConnectedLimited || internet_connectivity)
+        })
+        .await
+    }
+
+    /// Polls the connection state until "condition" is met or "timeout" expired.
+    async fn connectivity_changed<F>(
+        &self,
+        timeout: std::time::Duration,
+        condition: F,
+    ) -> Result<NetworkManagerState, CaptivePortalError>
+    where
+        F: Fn(NetworkManagerState) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let state = self.state().await?;
+            if condition(state) {
+                return Ok(state);
+            }
+            if Instant::now() >= deadline {
+                return Err(CaptivePortalError::NotRequiredConnectivity(state));
+            }
+            delay_for(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Connect to the given SSID with the given credentials.
+    /// The credentials are handed out by the agent when ConnMan asks for them. ConnMan stores the service
+    /// if the connection succeeds.
+    ///
+    /// # Arguments:
+    /// * ssid: The ssid
+    /// * credentials: The connection credentials
+    /// * hw: The ConnMan service path as reported by [`NetworkBackend::access_point`]. If this is not set,
+    ///   the service is looked up by its SSID.
+    /// * options.ipv4: Not supported, ConnMan requests the address via DHCP.
+    /// * options.ipv6: Not supported, the ConnMan default applies.
+    /// * options.hidden: Not supported. ConnMan lists hidden networks as services without a name,
+    ///   which cannot be told apart. Hidden networks must have been found by a scan.
+    /// * options.overwrite_same_ssid_connection: If this is true, a configured service with the same SSID is removed
+    ///   first, so that ConnMan asks for the new credentials.
+    pub async fn connect_to(
+        &self,
+        ssid: SSID,
+        credentials: AccessPointCredentials,
+        hw: Option<String>,
+        options: ConnectOptions,
+    ) -> Result<Option<ActiveConnection>, CaptivePortalError> {
+        let ConnectOptions { ipv4, ipv6, hidden, overwrite_same_ssid_connection } = options;
+        if ipv4.is_some() {
+            warn!("Static IPv4 configuration is not supported by ConnMan, the address is requested via DHCP");
+        }
+        if ipv6.is_some() {
+            warn!("IPv6 configuration is not supported by ConnMan, the service default applies");
+        }
+        if overwrite_same_ssid_connection {
+            self.forget(&ssid).await?;
+        }
+
+        let service_path = match hw.filter(|hw| hw.starts_with(&self.service_prefix)) {
+            Some(path) => dbus::Path::new(path).map_err(CaptivePortalError::Generic)?,
+            None => match self.services().await?.into_iter().find(|s| ssid == s.name.as_str()) {
+                Some(service) => service.path,
+                None if hidden => {
+                    warn!("Hidden network {} not found. ConnMan cannot probe for hidden networks", ssid);
+                    return Ok(None);
+                },
+                None => {
+                    warn!("Network {} not found", ssid);
+                    return Ok(None);
+                },
+            },
+        };
+
+        // Enterprise networks need a provisioning file, the agent cannot provide the EAP method
+        let provisioning_file = match &credentials {
+            AccessPointCredentials::Enterprise {
+                identity,
+                passphrase,
+                anonymous_identity,
+                method,
+            } => Some(credentials_agent::provisioning_file(
+                &ssid,
+                identity,
+                passphrase,
+                anonymous_identity.as_deref(),
+                *method,
+            )?),
+            _ => None,
+        };
+
+        self.pending_credentials
+            .lock()
+            .expect("Lock pending credentials")
+            .replace((service_path.clone(), credentials));
+
+        // Resolves when ConnMan has established the connection or it failed
+        let p = nonblock::Proxy::new(CONNMAN_BUSNAME, service_path.clone(), self.conn.clone());
+        let connected = p.connect().await;
+        self.pending_credentials.lock().expect("Lock pending credentials").take();
+
+        let connected = match connected {
+            Ok(()) => self.wait_for_connectivity(false, Duration::from_secs(30)).await,
+            Err(e) => {
+                info!("Connecting to {} failed: {}", ssid, e);
+                Err(CaptivePortalError::NotRequiredConnectivity(NetworkManagerState::Disconnected))
+            },
+        };
+        match connected {
+            Ok(_) => Ok(Some(ActiveConnection {
+                connection_path: service_path.clone(),
+                active_connection_path: service_path,
+                state: ConnectionState::Activated,
+            })),
+            Err(CaptivePortalError::NotRequiredConnectivity(_)) => {
+                // Removing the service also disconnects it and drops the wrong credentials
+                let _ = p.remove().await;
+                if let Some(file) = provisioning_file {
+                    let _ = std::fs::remove_file(file);
+                }
+                Ok(None)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Aborts a connection attempt that has been cancelled while [`connect_to`] was in progress.
+    pub async fn abort_connect(&self) -> Result<(), CaptivePortalError> {
+        self.pending_credentials.lock().expect("Lock pending credentials").take();
+        for service in self.services().await? {
+            if service.connection_state() == NetworkManagerState::Connecting {
+                let p = nonblock::Proxy::new(CONNMAN_BUSNAME, service.path, self.conn.clone());
+                p.disconnect().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the configured services with the given SSID. Returns false if there are none.
+    pub async fn forget(&self, ssid: &SSID) -> Result<bool, CaptivePortalError> {
+        let mut removed = false;
+        for service in self.services().await? {
+            if service.favorite && *ssid == service.name.as_str() {
+                let p = nonblock::Proxy::new(CONNMAN_BUSNAME, service.path, self.conn.clone());
+                p.remove().await?;
+                removed = true;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Get access point data for the given ConnMan service dbus path.
+    pub async fn access_point<'b, P: Into<dbus::Path<'b>>>(
+        &self,
+        ap_path: P,
+    ) -> Result<WifiConnection, CaptivePortalError> {
+        let ap_path: dbus::Path<'static> = ap_path.into().into_static();
+        let p = nonblock::Proxy::new(CONNMAN_BUSNAME, ap_path.clone(), self.conn.clone());
+        let service = NetConnmanService::get_properties(&p).and_then(move |m| Ok(Service::new(ap_path, &m)));
+        let service = service.await?;
+        if service.name.is_empty() {
+            return Err(CaptivePortalError::Generic(format!("Hidden network {}", service.path)));
+        }
+        let wifi_connection = WifiConnection {
+            ssid: SSID::from(service.name),
+            hw: service.path.to_string(),
+            security: security(&service.security).as_str(),
+            strength: service.strength,
+            frequency: 0,
+            // The own access point is not listed, scanning is not possible while tethering
+            is_own: false,
+            vendor: None,
+        };
+        info!("Found AP {:?}", &wifi_connection.ssid);
+        Ok(wifi_connection)
+    }
+
+    /// Return all known access points of the wifi device.
+    /// The list might not be up to date and can be refreshed with a call to [`scan_networks`].
+    ///
+    /// ## Arguments
+    /// * timeout: If timeout is != 0, performs a full scan. Waits up to timeout for at least one result.
+    pub async fn list_access_points(
+        &self,
+        mut timeout: std::time::Duration,
+    ) -> Result<Vec<WifiConnection>, CaptivePortalError> {
+        if timeout.as_secs() > 0 {
+            self.scan_networks().await?;
+        }
+        let interval = Duration::from_millis(500);
+        loop {
+            let mut access_points = Vec::new();
+            for service in self.services().await? {
+                if let Ok(ap) = self.access_point(service.path).await {
+                    access_points.push(ap);
+                }
+            }
+            if !access_points.is_empty() || timeout < interval {
+                return Ok(access_points);
+            }
+            delay_for(interval).await;
+            timeout -= interval;
+        }
+    }
+
+    /// Stops wifi tethering, if it is active
+    pub async fn deactivate_hotspots(&self) -> Result<(), CaptivePortalError> {
+        if self.technology_flags().await?.1 {
+            set_property(&self.technology(), "Tethering", false).await?;
+        }
+        Ok(())
+    }
+
+    /// Starts wifi tethering. ConnMan assigns the tethering address itself, see the module documentation.
+    pub async fn hotspot_start(
+        &self,
+        ssid: SSID,
+        password: String,
+        address: Option<Ipv4Addr>,
+        radio: Option<HotspotRadio>,
+    ) -> Result<ActiveConnection, CaptivePortalError> {
+        self.deactivate_hotspots().await?;
+        if let Some(address) = address {
+            debug!("The hotspot address {} is not assigned by the ConnMan backend", address);
+        }
+
+        debug!("Configuring hotspot ...");
+        let p = self.technology();
+        set_property(&p, "TetheringIdentifier", ssid.to_string()).await?;
+        set_property(&p, "TetheringPassphrase", password).await?;
+        if let Some(radio) = radio {
+            // Since ConnMan 1.38
+            set_property(&p, "TetheringFreq", radio.frequency() as i32).await?;
+        }
+
+        info!("Starting hotspot ...");
+        set_property(&p, "Tethering", true).await?;
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !self.technology_flags().await?.1 {
+            if Instant::now() >= deadline {
+                warn!("Hotspot starting failed");
+                return Err(CaptivePortalError::HotspotFailed);
+            }
+            delay_for(POLL_INTERVAL).await;
+        }
+
+        Ok(ActiveConnection {
+            connection_path: WIFI_TECHNOLOGY_PATH.into(),
+            active_connection_path: WIFI_TECHNOLOGY_PATH.into(),
+            state: ConnectionState::Activated,
+        })
+    }
+
+    /// Resolves when wifi tethering stopped
+    pub async fn on_hotspot_stopped(&self, _path: ConnectionPath) -> Result<(), CaptivePortalError> {
+        loop {
+            if !self.technology_flags().await?.1 {
+                return Ok(());
+            }
+            delay_for(POLL_INTERVAL * 2).await;
+        }
+    }
+}
+
+impl WifiBackend for NetworkBackend {
+    wifi_backend_methods!();
+}
+
+/// The property values are not thread safe (RefArg). This helper is not async, so that the value is not
+/// kept in the state of an async function.
+fn set_property<V: RefArg + 'static>(
+    p: &nonblock::Proxy<Arc<DbusConnection>>,
+    name: &str,
+    value: V,
+) -> nonblock::MethodReply<()> {
+    NetConnmanTechnology::set_property(p, name, Variant(Box::new(value)))
+}
+
+/// Maps the security entries of a service. "wps" is offered in addition to "psk".
+fn security(entries: &[String]) -> Security {
+    let has = |s: &str| entries.iter().any(|e| e == s);
+    if has("ieee8021x") {
+        Security::ENTERPRISE
+    } else if has("psk") {
+        Security::WPA2
+    } else if has("wep") {
+        Security::WEP
+    } else {
+        Security::NONE
+    }
+}
+
+/// The given interface or the first wireless interface of the system
+fn find_wifi_interface(interface_name: &Option<String>) -> Result<String, CaptivePortalError> {
+    if let Some(interface_name) = interface_name {
+        return match Path::new("/sys/class/net").join(interface_name).join("wireless").exists() {
+            true => Ok(interface_name.clone()),
+            false => Err(CaptivePortalError::NoWifiDeviceFound),
+        };
+    }
+    let interfaces = std::fs::read_dir("/sys/class/net")
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to list the network interfaces"))?;
+    let mut names: Vec<String> = interfaces
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().join("wireless").exists())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    names.into_iter().next().ok_or(CaptivePortalError::NoWifiDeviceFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_security() {
+        let entries = |e: &[&str]| e.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        assert_eq!(security(&entries(&["psk", "wps"])).as_str(), Security::WPA2.as_str());
+        assert_eq!(security(&entries(&["ieee8021x"])).as_str(), Security::ENTERPRISE.as_str());
+        assert_eq!(security(&entries(&["none"])).as_str(), Security::NONE.as_str());
+    }
+}
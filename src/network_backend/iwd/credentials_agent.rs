@@ -1 +1,139 @@
+//! # Credentials agent
+//! iwd asks a registered agent object for the passphrase of a network that is not yet known.
+//! [`NetworkBackend::connect_to`](super::NetworkBackend::connect_to) stores the credentials of the
+//! network it is about to connect to, and the agent answers the request of iwd with them.
+//!
+//! Enterprise networks require the EAP method, which cannot be provided by an agent.
+//! For those a provisioning file is written to the iwd storage directory instead, see [`provisioning_file`].
 
+use super::generated::iwd::NetConnmanIwdAgentManager;
+use super::NM_BUSNAME;
+use crate::dbus_tokio::DbusConnection;
+use crate::network_interface::{AccessPointCredentials, EapMethod};
+use crate::CaptivePortalError;
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::{MatchRule, MessageType};
+use dbus::{nonblock, Message};
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+pub(crate) const AGENT_PATH: &str = "/org/openhab/WifiCaptive/IwdAgent";
+const AGENT_INTERFACE: &str = "net.connman.iwd.Agent";
+const AGENT_MANAGER_PATH: &str = "/net/connman/iwd";
+/// The directory of known networks and provisioning files of iwd
+const IWD_STORAGE_DIR: &str = "/var/lib/iwd";
+
+/// The network path and the credentials that the agent hands out
+pub(crate) type PendingCredentials = Arc<Mutex<Option<(dbus::Path<'static>, AccessPointCredentials)>>>;
+
+/// Answers the credential requests of iwd and registers the agent.
+pub(crate) async fn register(conn: Arc<DbusConnection>, pending: PendingCredentials) -> Result<(), CaptivePortalError> {
+    let mut rule = MatchRule::new();
+    rule.msg_type = Some(MessageType::MethodCall);
+    rule.path = Some(AGENT_PATH.into());
+    rule.interface = Some(AGENT_INTERFACE.into());
+    conn.start_receive(
+        rule,
+        Box::new(move |msg, conn| {
+            let _ = conn.send(method_call(&pending, &msg));
+            true
+        }),
+    );
+
+    let p = nonblock::Proxy::new(NM_BUSNAME, AGENT_MANAGER_PATH, conn.clone());
+    p.register_agent(AGENT_PATH.into()).await?;
+    Ok(())
+}
+
+fn error(msg: &Message, name: &'static str, text: &str) -> Message {
+    msg.error(&name.into(), &CString::new(text).unwrap_or_default())
+}
+
+/// Returns the reply for the given agent method call
+fn method_call(pending: &PendingCredentials, msg: &Message) -> Message {
+    let member = msg.member();
+    let member = member.as_ref().map(|m| &**m).unwrap_or_default();
+    if member == "Release" || member == "Cancel" {
+        if member == "Cancel" {
+            info!("iwd cancelled the credentials request: {}", msg.read1::<&str>().unwrap_or_default());
+        }
+        return msg.method_return();
+    }
+
+    let network = msg.read1::<dbus::Path>().ok().map(|p| p.into_static());
+    let credentials = match (pending.lock().expect("Lock pending credentials").clone(), network) {
+        (Some((path, credentials)), Some(network)) if path == network => credentials,
+        (_, network) => {
+            warn!("iwd requested credentials for an unexpected network: {:?}", network);
+            return error(msg, "net.connman.iwd.Agent.Error.Canceled", "No credentials for this network");
+        },
+    };
+    match (member, credentials) {
+        ("RequestPassphrase", AccessPointCredentials::Wpa { passphrase })
+        | ("RequestPassphrase", AccessPointCredentials::Sae { passphrase })
+        | ("RequestUserPassword", AccessPointCredentials::Enterprise { passphrase, .. }) => {
+            msg.method_return().append1(passphrase)
+        },
+        ("RequestUserNameAndPassword", AccessPointCredentials::Enterprise { identity, passphrase, .. }) => {
+            msg.method_return().append2(identity, passphrase)
+        },
+        _ => {
+            warn!("iwd requested unsupported credentials: {}", member);
+            error(msg, "net.connman.iwd.Agent.Error.Canceled", "Credentials not available")
+        },
+    }
+}
+
+/// The file name of a known network in the iwd storage directory. SSIDs with other characters than
+/// a-z, A-Z, 0-9, "-", "_" and " " are hex encoded with a "=" prefix.
+pub fn provisioning_file_name(ssid: &str, extension: &str) -> String {
+    let plain = ssid
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == ' ');
+    match plain {
+        true => format!("{}.{}", ssid, extension),
+        false => {
+            let hex: String = ssid.bytes().map(|b| format!("{:02x}", b)).collect();
+            format!("={}.{}", hex, extension)
+        },
+    }
+}
+
+/// Writes a provisioning file for an enterprise network (PEAP with MSCHAPv2 or TTLS with PAP) and returns its path.
+/// iwd picks it up as known network.
+pub(crate) fn provisioning_file(
+    ssid: &str,
+    identity: &str,
+    passphrase: &str,
+    anonymous_identity: Option<&str>,
+    method: EapMethod,
+) -> Result<PathBuf, CaptivePortalError> {
+    let (eap, phase2) = match method {
+        EapMethod::Peap => ("PEAP", "MSCHAPV2"),
+        EapMethod::Ttls => ("TTLS", "Tunneled-PAP"),
+    };
+    let content = format!(
+        "[Security]\nEAP-Method={0}\nEAP-Identity={2}\nEAP-{0}-Phase2-Method={1}\n\
+         EAP-{0}-Phase2-Identity={3}\nEAP-{0}-Phase2-Password={4}\n",
+        eap,
+        phase2,
+        anonymous_identity.unwrap_or(identity),
+        identity,
+        passphrase
+    );
+    let path = PathBuf::from(IWD_STORAGE_DIR).join(provisioning_file_name(ssid, "8021x"));
+    crate::state_dir::write_atomic(&path, content.as_bytes())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        assert_eq!(provisioning_file_name("My AP_2-g", "psk"), "My AP_2-g.psk");
+        assert_eq!(provisioning_file_name("Café", "8021x"), "=436166c3a9.8021x");
+    }
+}
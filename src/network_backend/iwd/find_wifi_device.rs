@@ -18,7 +18,7 @@ pub(crate) struct FindWifiDeviceResult {
 /// Finds the first wifi device or the wifi device on the given device interface.
 /// Returns (wifi_device_path, interface_name) on success and an error otherwise.
 pub(crate) async fn find_wifi_device(
-    connection: Arc<dbus::nonblock::SyncConnection>,
+    connection: Arc<crate::dbus_tokio::DbusConnection>,
     preferred_interface: &Option<String>,
 ) -> Result<FindWifiDeviceResult, CaptivePortalError> {
     let p = nonblock::Proxy::new(NM_BUSNAME, "/", connection.clone());
@@ -30,22 +30,22 @@ pub(crate) async fn find_wifi_device(
         if let Some(entry) = entry.get("net.connman.iwd.Device") {
             let device_hw = entry
                 .get("Address")
-                .ok_or(CaptivePortalError::Generic(
+                .ok_or(CaptivePortalError::IwdError(
                     "net.connman.iwd.Device: Must have an 'Address'",
                 ))?
                 .0
                 .as_str()
-                .ok_or(CaptivePortalError::Generic(
+                .ok_or(CaptivePortalError::IwdError(
                     "net.connman.iwd.Device/Address: Expects a string!",
                 ))?;
             let device_interface = entry
                 .get("Name")
-                .ok_or(CaptivePortalError::Generic(
+                .ok_or(CaptivePortalError::IwdError(
                     "net.connman.iwd.Device: Must have a 'Name'",
                 ))?
                 .0
                 .as_str()
-                .ok_or(CaptivePortalError::Generic(
+                .ok_or(CaptivePortalError::IwdError(
                     "net.connman.iwd.Device/Name: Expects a string!",
                 ))?;
 
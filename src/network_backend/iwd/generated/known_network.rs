@@ -8,8 +8,8 @@ pub trait NetConnmanIwdKnownNetwork {
     fn name(&self) -> nonblock::MethodReply<String>;
     fn type_(&self) -> nonblock::MethodReply<String>;
     fn hidden(&self) -> nonblock::MethodReply<bool>;
-    fn autoconnect(&self) -> nonblock::MethodReply<bool>;
-    fn set_autoconnect(&self, value: bool) -> nonblock::MethodReply<()>;
+    fn auto_connect(&self) -> nonblock::MethodReply<bool>;
+    fn set_auto_connect(&self, value: bool) -> nonblock::MethodReply<()>;
     fn last_connected_time(&self) -> nonblock::MethodReply<String>;
 }
 
@@ -44,11 +44,11 @@ impl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target = T>> NetConnma
         )
     }
 
-    fn autoconnect(&self) -> nonblock::MethodReply<bool> {
+    fn auto_connect(&self) -> nonblock::MethodReply<bool> {
         <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(
             &self,
             "net.connman.iwd.KnownNetwork",
-            "Autoconnect",
+            "AutoConnect",
         )
     }
 
@@ -60,11 +60,11 @@ impl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target = T>> NetConnma
         )
     }
 
-    fn set_autoconnect(&self, value: bool) -> nonblock::MethodReply<()> {
+    fn set_auto_connect(&self, value: bool) -> nonblock::MethodReply<()> {
         <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::set(
             &self,
             "net.connman.iwd.KnownNetwork",
-            "Autoconnect",
+            "AutoConnect",
             value,
         )
     }
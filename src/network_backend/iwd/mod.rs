@@ -6,61 +6,76 @@
 //!
 //! iwd handles connection secrets different than network manager. The network manager API
 //! just takes a SSID and a password. iwd requires an "agent" dbus service to be registered.
-//! That agent will be asked for credentials for a to-be-established connection.
+//! That agent will be asked for credentials for a to-be-established connection, see [`credentials_agent`].
+//!
+//! iwd merges the access points of one network and does not report their mac addresses or frequencies.
+//! The iwd network path is therefore reported as "hw" address of a [`WifiConnection`] and the frequency is 0.
+//! A connection request with such a "hw" address connects to exactly that network.
 //!
 //! In contrast to NetworkManager you need to assign the hotspot IP to the wifi interface yourself
-//! before starting this service. Eg: `ip addr add 192.168.41/24 dev wlan0`
+//! before starting this service. Eg: `ip addr add 192.168.41/24 dev wlan0`.
+//! iwd only offers WPA2 protected access points. A hotspot passphrase must be configured.
 mod generated;
 
-//mod access_points_changed;
+mod access_points_changed;
 mod connectivity;
 mod credentials_agent;
 mod find_wifi_device;
 
-use crate::{
-    dbus_tokio, AccessPointCredentials, ActiveConnection, CaptivePortalError, ConnectionState, Connectivity,
-    NetworkManagerState, WifiConnection, SSID,
+use crate::dbus_tokio::{self, DbusConnection};
+use crate::mac_filter::MacFilterSync;
+use crate::network_interface::{
+    AccessPointCredentials, ActiveConnection, ConnectOptions, ConnectionQuality, ConnectionState, HotspotRadio,
+    HotspotSecurity, NetworkManagerState, Pmf, Security, WifiConnection, SSID,
 };
-pub use access_points_changed::AccessPointsChangedStream;
-
-use crate::dbus_tokio::SignalStream;
-use crate::network_backend::NM_PATH;
-use dbus::arg::RefArg;
-use dbus::nonblock::SyncConnection;
-use dbus::{nonblock, Path};
-use futures_util::StreamExt;
+use crate::runtime::{self, delay_for};
+use crate::network_backend::WifiBackend;
+use crate::CaptivePortalError;
+use connectivity::POLL_INTERVAL;
+use credentials_agent::PendingCredentials;
+use generated::device::{NetConnmanIwdAccessPoint, NetConnmanIwdDevice, NetConnmanIwdStation};
+use generated::iwd::OrgFreedesktopDBusObjectManager;
+use generated::known_network::NetConnmanIwdKnownNetwork;
+use generated::network::NetConnmanIwdNetwork;
+
+use dbus::nonblock;
 use std::net::Ipv4Addr;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use tokio::stream::StreamExt as TokioStreamExt;
+use std::time::{Duration, Instant};
+
+// Public API: AccessPointsChangedStream
+pub use access_points_changed::{ap_changed_stream, AccessPointChanged};
+
+/// The handle of a connection of this backend, see [`crate::ActiveConnection`]
+pub type ConnectionPath = dbus::Path<'static>;
 
 pub const NM_BUSNAME: &str = "net.connman.iwd";
 
 #[derive(Clone)]
 pub struct NetworkBackend {
     exit_handler: Arc<Mutex<Option<tokio::sync::oneshot::Sender<()>>>>,
-    pub(crate) conn: Arc<SyncConnection>,
+    pub(crate) conn: Arc<DbusConnection>,
     /// The wifi device. Will always be set, because the service quits if it didn't find a wifi device.
     pub(crate) wifi_device_path: dbus::Path<'static>,
-    /// Mac address of the own network interface
-    hw: String,
     /// Network interface name
     interface_name: String,
+    /// The credentials of the network that is being connected to, handed out by the agent
+    pending_credentials: PendingCredentials,
 }
 
 impl NetworkBackend {
-    /// Create a new connection to the network manager. This will also try to enable networking
-    /// and wifi. Returns a network manager instance or an error if no wifi device can be found.
+    /// Create a new connection to iwd and register the credentials agent.
+    /// Returns an error if no wifi device can be found.
     pub async fn new(interface_name: &Option<String>) -> Result<NetworkBackend, CaptivePortalError> {
         // Prepare an exit handler
         let (exit_handler, exit_receiver) = tokio::sync::oneshot::channel::<()>();
 
         // Connect to the D-Bus session bus (this is blocking, unfortunately).
-        let (resource, conn) = signal_stream::new_system_sync()?;
+        let (resource, conn) = dbus_tokio::new_system_default()?;
 
-        // The resource is a task that should be spawned onto a tokio compatible
-        // reactor ASAP. If the resource ever finishes, you lost connection to D-Bus.
-        tokio::spawn(async move {
+        // The resource is a task that should be spawned onto the async
+        // runtime ASAP. If the resource ever finishes, you lost connection to D-Bus.
+        runtime::spawn(async move {
             use futures_util::future::select;
             use futures_util::future::Either;
             use pin_utils::pin_mut;
@@ -74,28 +89,144 @@ impl NetworkBackend {
         });
 
         let wifi_device = find_wifi_device::find_wifi_device(conn.clone(), interface_name).await?;
+
+        info!("Using wifi interface {} ({})", wifi_device.interface_name, wifi_device.hw);
+
+        let pending_credentials = PendingCredentials::default();
+        credentials_agent::register(conn.clone(), pending_credentials.clone()).await?;
+
         Ok(NetworkBackend {
             exit_handler: Arc::new(Mutex::new(Some(exit_handler))),
             conn,
             interface_name: wifi_device.interface_name,
-            hw: wifi_device.hw,
             wifi_device_path: wifi_device.device_path,
+            pending_credentials,
+        })
+    }
+
+    /// iwd always stores known networks in its own state directory. In-memory mode is not supported.
+    pub fn set_in_memory(&mut self, in_memory: bool) {
+        if in_memory {
+            warn!("The iwd backend cannot keep connections in memory only. iwd will store them on disk.");
+        }
+    }
+
+    /// iwd negotiates protected management frames itself.
+    pub fn set_pmf(&mut self, pmf: Pmf) {
+        if pmf != Pmf::Auto {
+            warn!("The iwd backend cannot configure protected management frames. iwd negotiates them.");
+        }
+    }
+
+    /// iwd does not support OWE for access points. Requesting an OWE transition mode pair fails.
+    pub fn set_owe_transition(&mut self, owe_transition: bool) -> Result<(), CaptivePortalError> {
+        match owe_transition {
+            true => Err(CaptivePortalError::Generic(
+                "The iwd backend cannot offer an OWE transition mode pair (--hotspot-owe)".to_owned(),
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// The iwd access point has no client limit. The dhcp server limits the clients instead.
+    pub fn set_max_clients(&mut self, max_clients: Option<u32>) {
+        if max_clients.is_some() {
+            info!("The iwd backend cannot limit the hotspot clients. Only the dhcp leases are limited.");
+        }
+    }
+
+    /// The iwd access point has no MAC filter. The dhcp server rejects the clients instead.
+    pub fn set_mac_filter(&mut self, mac_filter: MacFilterSync) {
+        if !mac_filter.read().expect("mac filter lock").is_empty() {
+            info!("The iwd backend cannot filter the hotspot clients. Only the dhcp leases are filtered.");
+        }
+    }
+
+    /// iwd does not support SAE for access points.
+    pub fn set_hotspot_security(&mut self, security: HotspotSecurity) {
+        if security != HotspotSecurity::Wpa2 {
+            warn!("The iwd backend cannot offer a WPA3 hotspot. The hotspot uses WPA2.");
+        }
+    }
+
+    /// Whether the backend assigns the address given to [`NetworkBackend::hotspot_start`].
+    /// The hotspot address must be assigned beforehand.
+    pub fn assigns_hotspot_address(&self) -> bool {
+        false
+    }
+
+    /// The message key of the reason why the last connection attempt failed, see [`crate::i18n`].
+    /// Failure reasons are not reported by this backend.
+    pub fn last_failure(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// The name of the wifi network interface
+    pub fn interface_name(&self) -> &str {
+        &self.interface_name
+    }
+
+    fn device(&self) -> nonblock::Proxy<'static, Arc<DbusConnection>> {
+        nonblock::Proxy::new(NM_BUSNAME, self.wifi_device_path.clone(), self.conn.clone())
+    }
+
+    /// The network the station is connected to. None if the device is not connected or not in station mode.
+    async fn connected_network(&self) -> Result<Option<dbus::Path<'static>>, CaptivePortalError> {
+        let p = self.device();
+        if p.mode().await? != "station" {
+            return Ok(None);
+        }
+        if NetworkManagerState::from(&p.state().await?[..]) != NetworkManagerState::ConnectedLimited {
+            return Ok(None);
+        }
+        // The property only exists while the station is connected
+        Ok(p.connected_network().await.ok())
+    }
+
+    /// The signal strength of the given network in percent. 0 if the network is not in the scan results.
+    async fn signal(&self, network_path: &dbus::Path<'_>) -> Result<u8, CaptivePortalError> {
+        let networks = self.device().get_ordered_networks().await?;
+        Ok(networks
+            .into_iter()
+            .find(|(path, _)| path == network_path)
+            .map(|(_, signal)| signal_percent(signal))
+            .unwrap_or(0))
+    }
+
+    /// The SSID of the connected network. None if the wifi device is not connected.
+    pub async fn active_ssid(&self) -> Result<Option<SSID>, CaptivePortalError> {
+        match self.connected_network().await? {
+            Some(network_path) => {
+                let p = nonblock::Proxy::new(NM_BUSNAME, network_path, self.conn.clone());
+                Ok(Some(SSID::from(NetConnmanIwdNetwork::name(&p).await?)))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// The signal strength of the current connection. iwd does not report the bitrate.
+    /// The gateway round trip time is not measured by the backend, see [`crate::quality`].
+    pub async fn connection_quality(&self) -> Result<ConnectionQuality, CaptivePortalError> {
+        let signal = match self.connected_network().await? {
+            Some(network_path) => Some(self.signal(&network_path).await?),
+            None => None,
+        };
+        Ok(ConnectionQuality {
+            signal,
+            bitrate: None,
+            gateway_rtt: None,
         })
     }
 
     /// Network might be disabled or "unmanaged". This method tries to enable networking and wifi.
     pub async fn enable_networking_and_wifi(&self) -> Result<(), CaptivePortalError> {
-        use generated::device::NetConnmanIwdDevice;
-        let p = nonblock::Proxy::new(NM_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
-        p.set_powered(true).await?;
+        self.device().set_powered(true).await?;
         Ok(())
     }
 
-    /// Scan for access points if the last scan is older than 10 seconds
+    /// Request a scan. Not possible while the hotspot is active.
     pub async fn scan_networks(&self) -> Result<(), CaptivePortalError> {
-        use generated::device::NetConnmanIwdDevice;
-        use generated::device::NetConnmanIwdStation;
-        let p = nonblock::Proxy::new(NM_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        let p = self.device();
         if p.mode().await? != "station" {
             return Err(CaptivePortalError::NotInStationMode);
         }
@@ -103,7 +234,7 @@ impl NetworkBackend {
         Ok(())
     }
 
-    /// Terminates this network manager dbus connection
+    /// Terminates this dbus connection
     pub fn quit(self) {
         let mut exit_handler = self
             .exit_handler
@@ -114,43 +245,26 @@ impl NetworkBackend {
         }
     }
 
-    /// The iwd network state
+    /// The iwd station state. An active hotspot counts as disconnected.
     pub async fn state(&self) -> Result<NetworkManagerState, CaptivePortalError> {
-        use generated::device::NetConnmanIwdDevice;
-        use generated::device::NetConnmanIwdStation;
-        let p = nonblock::Proxy::new(NM_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        let p = self.device();
+        if !p.powered().await? {
+            return Ok(NetworkManagerState::Asleep);
+        }
         if p.mode().await? != "station" {
-            return Err(CaptivePortalError::NotInStationMode);
+            return Ok(NetworkManagerState::Disconnected);
         }
-        let state = NetworkManagerState::from(&p.state().await?[..]);
-        Ok(state)
+        Ok(NetworkManagerState::from(&p.state().await?[..]))
     }
 
-    /// Enables auto connect. This enumerates all known connections and sets auto connect to true.
+    /// Enables auto connect of all known networks and waits for iwd to connect to one of them.
     pub async fn try_auto_connect(&self, timeout: std::time::Duration) -> Result<bool, CaptivePortalError> {
-        let p = nonblock::Proxy::new(NM_BUSNAME, "/", self.conn.clone());
-        use generated::iwd::OrgFreedesktopDBusObjectManager;
+        self.deactivate_hotspots().await?;
 
-        // Get all devices (if possible: by interface)
-        let objects = p.get_managed_objects().await?;
-        for (device_path, entry) in objects {
-            if let Some(entry) = entry.get("net.connman.iwd.KnownNetwork") {
-                let auto_connect = entry
-                    .get("Autoconnect")
-                    .ok_or(CaptivePortalError::IwdError(
-                        "net.connman.iwd.KnownNetwork: Autoconnect expected'",
-                    ))?
-                    .0
-                    .as_any()
-                    .downcast_ref::<bool>()
-                    .ok_or(CaptivePortalError::IwdError(
-                        "net.connman.iwd.KnownNetwork/Autoconnect: Expects a bool!",
-                    ))?;
-
-                if !auto_connect {
-                    use generated::known_network::NetConnmanIwdKnownNetwork;
-                    p.set_autoconnect(true);
-                }
+        for path in self.known_networks().await? {
+            let p = nonblock::Proxy::new(NM_BUSNAME, path, self.conn.clone());
+            if !p.auto_connect().await? {
+                p.set_auto_connect(true).await?;
             }
         }
 
@@ -158,121 +272,307 @@ impl NetworkBackend {
             Ok(state) => Ok(state == NetworkManagerState::Connected || state == NetworkManagerState::ConnectedLimited),
             Err(CaptivePortalError::NotRequiredConnectivity(_)) => Ok(false),
             Err(e) => Err(e),
-            _ => Ok(false),
         }
     }
 
+    /// The paths of all known networks
+    async fn known_networks(&self) -> Result<Vec<dbus::Path<'static>>, CaptivePortalError> {
+        let p = nonblock::Proxy::new(NM_BUSNAME, "/", self.conn.clone());
+        let objects = p.get_managed_objects().await?;
+        Ok(objects
+            .into_iter()
+            .filter(|(_, interfaces)| interfaces.contains_key("net.connman.iwd.KnownNetwork"))
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    /// The path of the scanned network with the given SSID
+    async fn find_network(&self, ssid: &str) -> Result<Option<dbus::Path<'static>>, CaptivePortalError> {
+        for (path, _) in self.device().get_ordered_networks().await? {
+            let p = nonblock::Proxy::new(NM_BUSNAME, path.clone(), self.conn.clone());
+            if NetConnmanIwdNetwork::name(&p).await? == ssid {
+                return Ok(Some(path));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The object path iwd assigns to the hidden network with the given SSID: The device path,
+    /// the hex encoded SSID and the security type. The agent is asked for credentials with this path.
+    fn hidden_network_path(
+        &self,
+        ssid: &SSID,
+        credentials: &AccessPointCredentials,
+    ) -> Result<dbus::Path<'static>, CaptivePortalError> {
+        let security = match credentials {
+            AccessPointCredentials::None => "open",
+            AccessPointCredentials::Enterprise { .. } => "8021x",
+            _ => "psk",
+        };
+        let path = format!("{}/{}_{}", self.wifi_device_path, ssid.to_hex(), security);
+        dbus::Path::new(path).map_err(CaptivePortalError::Generic)
+    }
+
     /// Connect to the given SSID with the given credentials.
-    /// First tries to find a wifi connection if "hw" is set or "overwrite_same_ssid_connection" is true.
-    /// If it finds one, the connection will be altered to use the given credentials and SSID, otherwise a new connection is created.
-    ///
-    /// If "overwrite_same_ssid_connection" is false and "hw" is not set, but network manager already knows about a connection
-    /// to a SSID with the same name, it will add a number suffix to the connection name. If the SSID is
-    /// for example "My AP" and there is already a connection with the name "My AP", the newly
-    /// created connection will be named "My AP 1".
+    /// The credentials are handed out by the agent when iwd asks for them. iwd stores the network as
+    /// known network if the connection succeeds.
     ///
     /// # Arguments:
     /// * ssid: The ssid
     /// * credentials: The connection credentials
-    /// * hw: The target access point mac address. If this is set, this method will first try to find
-    ///   a connection that was connected to that access point in the past and update that connection.
-    /// * overwrite_same_ssid_connection: If this is true and a connection can be found that matches the
-    ///   given SSID, that connection will be updated.
+    /// * hw: The iwd network path as reported by [`NetworkBackend::access_point`]. If this is not set,
+    ///   the network is looked up by its SSID.
+    /// * options.ipv4: Not supported, the address is configured by the dhcp client of the system.
+    /// * options.ipv6: Not supported, the system default applies.
+    /// * options.hidden: The network does not broadcast its SSID. If it is not in the scan list,
+    ///   iwd probes for it (ConnectHiddenNetwork).
+    /// * options.overwrite_same_ssid_connection: If this is true, a known network with the same SSID is forgotten
+    ///   first, so that iwd asks for the new credentials.
     pub async fn connect_to(
         &self,
         ssid: SSID,
         credentials: AccessPointCredentials,
         hw: Option<String>,
-        overwrite_same_ssid_connection: bool,
+        options: ConnectOptions,
     ) -> Result<Option<ActiveConnection>, CaptivePortalError> {
-        unimplemented!()
+        let ConnectOptions { ipv4, ipv6, hidden, overwrite_same_ssid_connection } = options;
+        if ipv4.is_some() {
+            warn!("Static IPv4 configuration is not supported by iwd, the address is requested via DHCP");
+        }
+        if ipv6.is_some() {
+            warn!("IPv6 configuration is not supported by iwd, the system default applies");
+        }
+        if let AccessPointCredentials::Wep { .. } = credentials {
+            warn!("iwd does not support WEP networks");
+            return Ok(None);
+        }
+        let name = match ssid.as_str() {
+            Some(name) => name.to_owned(),
+            None => {
+                warn!("iwd does not support SSIDs that are not valid UTF8: {:?}", ssid);
+                return Ok(None);
+            },
+        };
+        if overwrite_same_ssid_connection {
+            self.forget(&ssid).await?;
+        }
+
+        let network_path = match hw.filter(|hw| hw.starts_with('/')) {
+            Some(path) => Some(dbus::Path::new(path).map_err(CaptivePortalError::Generic)?),
+            None => self.find_network(&name).await?,
+        };
+        let probe_hidden = network_path.is_none() && hidden;
+        let network_path = match network_path {
+            Some(path) => path,
+            None if hidden => self.hidden_network_path(&ssid, &credentials)?,
+            None => {
+                warn!("Network {} not found", ssid);
+                return Ok(None);
+            },
+        };
+
+        // Enterprise networks need a provisioning file, the agent cannot provide the EAP method
+        let provisioning_file = match &credentials {
+            AccessPointCredentials::Enterprise {
+                identity,
+                passphrase,
+                anonymous_identity,
+                method,
+            } => Some(credentials_agent::provisioning_file(
+                &name,
+                identity,
+                passphrase,
+                anonymous_identity.as_deref(),
+                *method,
+            )?),
+            _ => None,
+        };
+
+        self.pending_credentials
+            .lock()
+            .expect("Lock pending credentials")
+            .replace((network_path.clone(), credentials));
+
+        // Resolves when iwd has established the connection or it failed
+        let connected = match probe_hidden {
+            true => self.device().connect_hidden_network(&name).await,
+            false => {
+                let p = nonblock::Proxy::new(NM_BUSNAME, network_path.clone(), self.conn.clone());
+                p.connect().await
+            },
+        };
+        self.pending_credentials.lock().expect("Lock pending credentials").take();
+
+        let connected = match connected {
+            Ok(()) => self.wait_for_connectivity(false, Duration::from_secs(30)).await,
+            Err(e) => {
+                info!("Connecting to {} failed: {}", ssid, e);
+                Err(CaptivePortalError::NotRequiredConnectivity(NetworkManagerState::Disconnected))
+            },
+        };
+        match connected {
+            Ok(_) => Ok(Some(ActiveConnection {
+                connection_path: network_path,
+                active_connection_path: self.wifi_device_path.clone(),
+                state: ConnectionState::Activated,
+            })),
+            Err(CaptivePortalError::NotRequiredConnectivity(_)) => {
+                let _ = self.device().disconnect().await;
+                self.forget(&ssid).await?;
+                if let Some(file) = provisioning_file {
+                    let _ = std::fs::remove_file(file);
+                }
+                Ok(None)
+            },
+            Err(e) => Err(e),
+        }
     }
 
-    /// Get access point data for the given access point network manager dbus path.
+    /// Aborts a connection attempt that has been cancelled while [`connect_to`] was in progress.
+    pub async fn abort_connect(&self) -> Result<(), CaptivePortalError> {
+        self.pending_credentials.lock().expect("Lock pending credentials").take();
+        self.device().disconnect().await?;
+        Ok(())
+    }
+
+    /// Forgets the known network for the given SSID. Returns false if there is none.
+    pub async fn forget(&self, ssid: &SSID) -> Result<bool, CaptivePortalError> {
+        let mut removed = false;
+        for path in self.known_networks().await? {
+            let p = nonblock::Proxy::new(NM_BUSNAME, path, self.conn.clone());
+            if SSID::from(NetConnmanIwdKnownNetwork::name(&p).await?) == *ssid {
+                p.forget().await?;
+                removed = true;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Get access point data for the given iwd network dbus path.
     pub async fn access_point<'b, P: Into<dbus::Path<'b>>>(
         &self,
         ap_path: P,
     ) -> Result<WifiConnection, CaptivePortalError> {
-        let ap_path: Path = ap_path.into();
-
-        unimplemented!()
+        let ap_path: dbus::Path<'static> = ap_path.into().into_static();
+        let p = nonblock::Proxy::new(NM_BUSNAME, ap_path.clone(), self.conn.clone());
+        let security = match &NetConnmanIwdNetwork::type_(&p).await?[..] {
+            "open" => Security::NONE,
+            "wep" => Security::WEP,
+            "8021x" => Security::ENTERPRISE,
+            _ => Security::WPA2,
+        };
+        let wifi_connection = WifiConnection {
+            ssid: SSID::from(NetConnmanIwdNetwork::name(&p).await?),
+            hw: ap_path.to_string(),
+            security: security.as_str(),
+            strength: self.signal(&ap_path).await?,
+            frequency: 0,
+            // The own access point is not listed, scanning is not possible in access point mode
+            is_own: false,
+            vendor: None,
+        };
+        info!("Found AP {:?}", &wifi_connection.ssid);
+        Ok(wifi_connection)
     }
 
     /// Return all known access points of the associated wifi device.
     /// The list might not be up to date and can be refreshed with a call to [`scan_networks`].
     ///
     /// ## Arguments
-    /// * find_all: Perform a full scan. This may take up to a minute.
+    /// * timeout: If timeout is != 0, performs a full scan. Waits up to timeout for at least one result.
     pub async fn list_access_points(
         &self,
-        timeout: std::time::Duration,
+        mut timeout: std::time::Duration,
     ) -> Result<Vec<WifiConnection>, CaptivePortalError> {
-        if find_all {
+        if timeout.as_secs() > 0 {
             self.scan_networks().await?;
         }
-        unimplemented!()
+        let interval = Duration::from_millis(500);
+        loop {
+            let mut access_points = Vec::new();
+            for (path, _) in self.device().get_ordered_networks().await? {
+                if let Ok(ap) = self.access_point(path).await {
+                    access_points.push(ap);
+                }
+            }
+            if !access_points.is_empty() || timeout < interval {
+                return Ok(access_points);
+            }
+            delay_for(interval).await;
+            timeout -= interval;
+        }
     }
 
     /// iwd does not store hotspot/APs as "known network"s, so there is nothing to deactivate.
     /// This method will however change from hotspot/AP mode into station mode if necessary.
     pub async fn deactivate_hotspots(&self) -> Result<(), CaptivePortalError> {
-        use generated::device::NetConnmanIwdDevice;
-        use generated::device::NetConnmanIwdStation;
-        let p = nonblock::Proxy::new(NM_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        let p = self.device();
         if p.mode().await? != "station" {
             p.set_mode("station".into()).await?;
         }
         Ok(())
     }
 
-    /// Starts a hotspot
+    /// Starts a hotspot. The given address must already be assigned to the wifi interface.
     pub async fn hotspot_start(
         &self,
         ssid: SSID,
         password: String,
         address: Option<Ipv4Addr>,
+        radio: Option<HotspotRadio>,
     ) -> Result<ActiveConnection, CaptivePortalError> {
-        use generated::device::NetConnmanIwdAccessPoint;
-        use generated::device::NetConnmanIwdDevice;
-        let p = nonblock::Proxy::new(NM_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        if password.is_empty() {
+            warn!("iwd cannot start an open hotspot. Configure a passphrase.");
+            return Err(CaptivePortalError::HotspotFailed);
+        }
+        if let Some(address) = address {
+            debug!("The hotspot address {} is not assigned by the iwd backend", address);
+        }
+        if let Some(radio) = radio {
+            debug!("The hotspot channel {:?} is not selected by the iwd backend", radio.channel);
+        }
+
+        let p = self.device();
         if p.mode().await? != "ap" {
             p.set_mode("ap".into()).await?;
         }
 
-        info!("Configuring hotspot ...");
-        p.start(&ssid, &password).await?;
+        info!("Starting hotspot ...");
+        p.start(&ssid.to_string(), &password).await?;
 
-        // Wait for connected state
-        let stream = SignalStream::prop_new(NM_PATH.to_owned().into(), self.conn.clone()).await?;
-
-        let mut stream = stream.timeout(Duration::from_secs(1));
-        loop {
-            let v = stream.next().await;
-            match v {
-                Some(Ok((value, _path))) => {
-                    if &value.interface_name[..] == "net.connman.iwd.AccessPoint" {
-                        if value.changed_properties.contains_key("Started") {
-                            break;
-                        }
-                    }
-                },
-                _ => break,
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !p.started().await? {
+            if Instant::now() >= deadline {
+                warn!("Hotspot starting failed");
+                return Err(CaptivePortalError::HotspotFailed);
             }
+            delay_for(POLL_INTERVAL).await;
         }
 
-        let state = p.started().await?;
-
         Ok(ActiveConnection {
             connection_path: self.wifi_device_path.clone(),
             active_connection_path: self.wifi_device_path.clone(),
-            state: match state {
-                true => ConnectionState::Activated,
-                false => ConnectionState::Deactivated,
-            },
+            state: ConnectionState::Activated,
         })
     }
 
-    pub async fn on_hotspot_stopped(&self, path: dbus::Path<'_>) -> Result<(), CaptivePortalError> {
-        unimplemented!()
+    /// Resolves when the access point of the wifi device stopped or the device left access point mode
+    pub async fn on_hotspot_stopped(&self, _path: ConnectionPath) -> Result<(), CaptivePortalError> {
+        let p = self.device();
+        loop {
+            if p.mode().await? != "ap" || !p.started().await? {
+                return Ok(());
+            }
+            delay_for(POLL_INTERVAL * 2).await;
+        }
     }
 }
+
+impl WifiBackend for NetworkBackend {
+    wifi_backend_methods!();
+}
+
+/// Converts the iwd signal strength (100 * dBm) into a percentage: -100 dBm is 0%, -50 dBm and better is 100%
+fn signal_percent(signal: i16) -> u8 {
+    ((signal as i32 / 100 + 100) * 2).max(0).min(100) as u8
+}
@@ -1,5 +1,191 @@
-//! # A network backend implementation. Either network manager or iwd.
-//! This depends on the cargo feature flag. Either "networkmanager" or "iwd".
+//! # A network backend implementation. Either network manager, iwd, ConnMan, OpenWrt (ubus), systemd-networkd
+//! with wpa_supplicant or wpa_supplicant alone. This depends on the cargo feature flag.
+//! Either "networkmanager", "iwd", "connman", "openwrt", "networkd" or "wpa-supplicant". Exactly one backend
+//! is compiled in.
+//!
+//! The compiled-in backend is exported as [`NetworkBackend`], together with the [`ConnectionPath`] handle of its
+//! connections. Every backend implements the [`WifiBackend`] trait. The portal, the http server and the state
+//! machine only use that trait, so a backend is added with a module, a feature flag and a trait implementation,
+//! without touching them. Features that only some backends offer, like WPS or a station interface,
+//! are provided methods of the trait that report [`CaptivePortalError::Unsupported`].
+
+use crate::dpp::DppUri;
+use crate::mac_filter::MacFilterSync;
+use crate::network_interface::{
+    AccessPointCredentials, ActiveConnection, ConnectOptions, ConnectionQuality, HotspotRadio, HotspotSecurity,
+    Ipv6Config, NetworkManagerState, Pmf, StaticIpv4, WifiConnection, WifiConnectionEventType, WifiDeviceInfo, SSID,
+};
+use crate::runtime::{BoxFuture, BoxStream, MaybeSend};
+use crate::CaptivePortalError;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(any(
+    all(feature = "networkmanager", feature = "iwd"),
+    all(feature = "networkmanager", feature = "connman"),
+    all(feature = "networkmanager", feature = "openwrt"),
+    all(feature = "networkmanager", any(feature = "networkd", feature = "wpa-supplicant")),
+    all(feature = "iwd", feature = "connman"),
+    all(feature = "iwd", feature = "openwrt"),
+    all(feature = "iwd", any(feature = "networkd", feature = "wpa-supplicant")),
+    all(feature = "connman", feature = "openwrt"),
+    all(feature = "connman", any(feature = "networkd", feature = "wpa-supplicant")),
+    all(feature = "openwrt", any(feature = "networkd", feature = "wpa-supplicant")),
+))]
+compile_error!("Only one network backend feature can be enabled. Use default-features = false to change it.");
+
+/// Implements the required methods of [`WifiBackend`] with the inherent methods of the same name,
+/// which every backend module provides. Used inside the trait implementation of each backend.
+macro_rules! wifi_backend_methods {
+    () => {
+        fn new(interface_name: &Option<String>) -> $crate::network_backend::BackendFuture<'_, Self> {
+            Box::pin(NetworkBackend::new(interface_name))
+        }
+
+        fn quit(self) {
+            NetworkBackend::quit(self)
+        }
+
+        fn set_in_memory(&mut self, in_memory: bool) {
+            NetworkBackend::set_in_memory(self, in_memory)
+        }
+
+        fn set_pmf(&mut self, pmf: $crate::network_interface::Pmf) {
+            NetworkBackend::set_pmf(self, pmf)
+        }
+
+        fn set_hotspot_security(&mut self, security: $crate::network_interface::HotspotSecurity) {
+            NetworkBackend::set_hotspot_security(self, security)
+        }
+
+        fn set_owe_transition(&mut self, owe_transition: bool) -> Result<(), $crate::CaptivePortalError> {
+            NetworkBackend::set_owe_transition(self, owe_transition)
+        }
+
+        fn set_max_clients(&mut self, max_clients: Option<u32>) {
+            NetworkBackend::set_max_clients(self, max_clients)
+        }
+
+        fn set_mac_filter(&mut self, mac_filter: $crate::mac_filter::MacFilterSync) {
+            NetworkBackend::set_mac_filter(self, mac_filter)
+        }
+
+        fn interface_name(&self) -> &str {
+            NetworkBackend::interface_name(self)
+        }
+
+        fn assigns_hotspot_address(&self) -> bool {
+            NetworkBackend::assigns_hotspot_address(self)
+        }
+
+        fn last_failure(&self) -> Option<&'static str> {
+            NetworkBackend::last_failure(self)
+        }
+
+        fn enable_networking_and_wifi(&self) -> $crate::network_backend::BackendFuture<'_, ()> {
+            Box::pin(NetworkBackend::enable_networking_and_wifi(self))
+        }
+
+        fn scan_networks(&self) -> $crate::network_backend::BackendFuture<'_, ()> {
+            Box::pin(NetworkBackend::scan_networks(self))
+        }
+
+        fn state(&self) -> $crate::network_backend::BackendFuture<'_, $crate::network_interface::NetworkManagerState> {
+            Box::pin(NetworkBackend::state(self))
+        }
+
+        fn try_auto_connect(&self, timeout: std::time::Duration) -> $crate::network_backend::BackendFuture<'_, bool> {
+            Box::pin(NetworkBackend::try_auto_connect(self, timeout))
+        }
+
+        fn wait_for_connectivity(
+            &self,
+            internet_connectivity: bool,
+            timeout: std::time::Duration,
+        ) -> $crate::network_backend::BackendFuture<'_, $crate::network_interface::NetworkManagerState> {
+            Box::pin(NetworkBackend::wait_for_connectivity(self, internet_connectivity, timeout))
+        }
+
+        fn wait_for_connectivity_lost(
+            &self,
+            internet_connectivity: bool,
+            timeout: std::time::Duration,
+        ) -> $crate::network_backend::BackendFuture<'_, $crate::network_interface::NetworkManagerState> {
+            Box::pin(NetworkBackend::wait_for_connectivity_lost(self, internet_connectivity, timeout))
+        }
+
+        fn active_ssid(&self) -> $crate::network_backend::BackendFuture<'_, Option<$crate::network_interface::SSID>> {
+            Box::pin(NetworkBackend::active_ssid(self))
+        }
+
+        fn connection_quality(
+            &self,
+        ) -> $crate::network_backend::BackendFuture<'_, $crate::network_interface::ConnectionQuality> {
+            Box::pin(NetworkBackend::connection_quality(self))
+        }
+
+        fn connect_to(
+            &self,
+            ssid: $crate::network_interface::SSID,
+            credentials: $crate::network_interface::AccessPointCredentials,
+            hw: Option<String>,
+            options: $crate::network_interface::ConnectOptions,
+        ) -> $crate::network_backend::BackendFuture<'_, Option<$crate::network_interface::ActiveConnection>> {
+            Box::pin(NetworkBackend::connect_to(self, ssid, credentials, hw, options))
+        }
+
+        fn abort_connect(&self) -> $crate::network_backend::BackendFuture<'_, ()> {
+            Box::pin(NetworkBackend::abort_connect(self))
+        }
+
+        fn forget<'a>(
+            &'a self,
+            ssid: &'a $crate::network_interface::SSID,
+        ) -> $crate::network_backend::BackendFuture<'a, bool> {
+            Box::pin(NetworkBackend::forget(self, ssid))
+        }
+
+        fn access_point(
+            &self,
+            path: String,
+        ) -> $crate::network_backend::BackendFuture<'_, $crate::network_interface::WifiConnection> {
+            Box::pin(NetworkBackend::access_point(self, path))
+        }
+
+        fn list_access_points(
+            &self,
+            timeout: std::time::Duration,
+        ) -> $crate::network_backend::BackendFuture<'_, Vec<$crate::network_interface::WifiConnection>> {
+            Box::pin(NetworkBackend::list_access_points(self, timeout))
+        }
+
+        fn ap_changed_stream(
+            &self,
+        ) -> $crate::network_backend::BackendFuture<'_, $crate::runtime::BoxStream<'static, AccessPointChanged>> {
+            Box::pin(ap_changed_stream(self))
+        }
+
+        fn deactivate_hotspots(&self) -> $crate::network_backend::BackendFuture<'_, ()> {
+            Box::pin(NetworkBackend::deactivate_hotspots(self))
+        }
+
+        fn hotspot_start(
+            &self,
+            ssid: $crate::network_interface::SSID,
+            password: String,
+            address: Option<std::net::Ipv4Addr>,
+            radio: Option<$crate::network_interface::HotspotRadio>,
+        ) -> $crate::network_backend::BackendFuture<'_, $crate::network_interface::ActiveConnection> {
+            Box::pin(NetworkBackend::hotspot_start(self, ssid, password, address, radio))
+        }
+
+        fn on_hotspot_stopped(&self, path: ConnectionPath) -> $crate::network_backend::BackendFuture<'_, ()> {
+            Box::pin(NetworkBackend::on_hotspot_stopped(self, path))
+        }
+    };
+}
 
 #[cfg(feature = "iwd")]
 mod iwd;
@@ -7,7 +193,252 @@ mod iwd;
 #[cfg(feature = "networkmanager")]
 mod nm;
 
+#[cfg(feature = "connman")]
+mod connman;
+
+#[cfg(feature = "openwrt")]
+mod openwrt;
+
+// The networkd backend is the wpa_supplicant backend with networkd addressing
+#[cfg(any(feature = "networkd", feature = "wpa-supplicant"))]
+mod wpa_supplicant;
+
 #[cfg(feature = "iwd")]
 pub use iwd::*;
 #[cfg(feature = "networkmanager")]
 pub use nm::*;
+#[cfg(feature = "connman")]
+pub use connman::*;
+#[cfg(feature = "openwrt")]
+pub use openwrt::*;
+#[cfg(any(feature = "networkd", feature = "wpa-supplicant"))]
+pub use wpa_supplicant::*;
+
+/// The future of a backend operation
+pub type BackendFuture<'a, T> = BoxFuture<'a, Result<T, CaptivePortalError>>;
+
+/// The network backend as shared with the tasks of the http server and the portal
+pub type SharedBackend = Arc<dyn WifiBackend>;
+
+/// An added or removed saved connection, see [`WifiBackend::settings_changed_stream`]
+pub struct SettingsChanged {
+    /// The backend path of the connection profile
+    pub path: String,
+    pub event: WifiConnectionEventType,
+}
+
+impl fmt::Display for SettingsChanged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.event, self.path)
+    }
+}
+
+/// The operations of a network backend. The state machine and the portal are generic over it,
+/// the http server holds a [`SharedBackend`].
+///
+/// The required methods are offered by every backend. The provided methods report
+/// [`CaptivePortalError::Unsupported`], or do nothing where that is a valid answer, unless a backend
+/// overrides them. Currently NetworkManager overrides all of them.
+pub trait WifiBackend: MaybeSend {
+    /// Connects to the backend and finds the wifi device of the given interface, or the first one.
+    /// Fails with [`CaptivePortalError::ApModeUnsupported`] if the device cannot run an access point.
+    fn new(interface_name: &Option<String>) -> BackendFuture<'_, Self>
+    where
+        Self: Sized;
+
+    /// Like [`WifiBackend::new`], but the hotspot is an open ad-hoc network on a device without access point mode
+    fn new_adhoc(_interface_name: &Option<String>) -> BackendFuture<'_, Self>
+    where
+        Self: Sized,
+    {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("The ad-hoc fallback")) })
+    }
+
+    /// Closes the connection to the backend
+    fn quit(self)
+    where
+        Self: Sized;
+
+    /// Store new and updated connections in memory only, where the backend supports it
+    fn set_in_memory(&mut self, in_memory: bool);
+    /// Protected management frames (802.11w) setting of new connections and the hotspot
+    fn set_pmf(&mut self, pmf: Pmf);
+    /// The key management of the hotspot
+    fn set_hotspot_security(&mut self, security: HotspotSecurity);
+    /// Offer an open hotspot as OWE transition mode pair. Fails if the backend cannot.
+    fn set_owe_transition(&mut self, owe_transition: bool) -> Result<(), CaptivePortalError>;
+    /// The client limit of the hotspot
+    fn set_max_clients(&mut self, max_clients: Option<u32>);
+    /// The MAC filter of the hotspot
+    fn set_mac_filter(&mut self, mac_filter: MacFilterSync);
+
+    /// The name of the wifi network interface
+    fn interface_name(&self) -> &str;
+    /// True if the backend assigns the gateway address of the hotspot itself
+    fn assigns_hotspot_address(&self) -> bool;
+    /// The message key of the reason why the last connection attempt failed, see [`crate::i18n`]
+    fn last_failure(&self) -> Option<&'static str>;
+
+    /// Networking or wifi might be disabled. This tries to enable both.
+    fn enable_networking_and_wifi(&self) -> BackendFuture<'_, ()>;
+    /// Requests a wifi scan
+    fn scan_networks(&self) -> BackendFuture<'_, ()>;
+    /// The connectivity state
+    fn state(&self) -> BackendFuture<'_, NetworkManagerState>;
+    /// Waits up to `timeout` for the backend to connect to a known network on its own
+    fn try_auto_connect(&self, timeout: Duration) -> BackendFuture<'_, bool>;
+    /// Resolves with the state when connected, or fails with [`CaptivePortalError::NotRequiredConnectivity`]
+    /// after the timeout
+    fn wait_for_connectivity(
+        &self,
+        internet_connectivity: bool,
+        timeout: Duration,
+    ) -> BackendFuture<'_, NetworkManagerState>;
+    /// Resolves when the (internet) connectivity is lost or after the timeout
+    fn wait_for_connectivity_lost(
+        &self,
+        internet_connectivity: bool,
+        timeout: Duration,
+    ) -> BackendFuture<'_, NetworkManagerState>;
+    /// The SSID of the active connection. None if the wifi device is not connected.
+    fn active_ssid(&self) -> BackendFuture<'_, Option<SSID>>;
+    /// Signal and link speed of the active connection, see [`crate::quality`]
+    fn connection_quality(&self) -> BackendFuture<'_, ConnectionQuality>;
+
+    /// Connects to the given network. None if the connection could not be established.
+    fn connect_to(
+        &self,
+        ssid: SSID,
+        credentials: AccessPointCredentials,
+        hw: Option<String>,
+        options: ConnectOptions,
+    ) -> BackendFuture<'_, Option<ActiveConnection>>;
+    /// Aborts a pending connection attempt
+    fn abort_connect(&self) -> BackendFuture<'_, ()>;
+    /// Removes the saved connections of the given SSID. Returns false if there was none.
+    fn forget<'a>(&'a self, ssid: &'a SSID) -> BackendFuture<'a, bool>;
+    /// The access point of the given backend path, as reported by [`WifiBackend::ap_changed_stream`]
+    fn access_point(&self, path: String) -> BackendFuture<'_, WifiConnection>;
+    /// The access points in range. A timeout above zero performs a scan first.
+    fn list_access_points(&self, timeout: Duration) -> BackendFuture<'_, Vec<WifiConnection>>;
+    /// A stream of added and removed access points
+    fn ap_changed_stream(&self) -> BackendFuture<'_, BoxStream<'static, AccessPointChanged>>;
+
+    /// Stops all hotspots of the wifi device
+    fn deactivate_hotspots(&self) -> BackendFuture<'_, ()>;
+    /// Starts the hotspot. Without a `radio`, the hotspot uses the 2.4 Ghz band.
+    fn hotspot_start(
+        &self,
+        ssid: SSID,
+        password: String,
+        address: Option<Ipv4Addr>,
+        radio: Option<HotspotRadio>,
+    ) -> BackendFuture<'_, ActiveConnection>;
+    /// Resolves when the given hotspot connection stopped
+    fn on_hotspot_stopped(&self, path: ConnectionPath) -> BackendFuture<'_, ()>;
+
+    /// Tries the saved networks in range, the highest priority first. Returns true if one of them connected.
+    /// Without saved network priorities, none is tried.
+    fn connect_saved(&self, _timeout: Duration) -> BackendFuture<'_, bool> {
+        Box::pin(async { Ok(false) })
+    }
+
+    /// Connects to the given network via WPS push-button
+    fn connect_wps(
+        &self,
+        _ssid: SSID,
+        _hw: Option<String>,
+        _ipv4: Option<StaticIpv4>,
+        _ipv6: Option<Ipv6Config>,
+    ) -> BackendFuture<'_, Option<ActiveConnection>> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("WPS")) })
+    }
+
+    /// Enrolls via Wi-Fi Easy Connect with the configurator of the given URI, see [`crate::dpp`]
+    fn connect_dpp<'a>(
+        &'a self,
+        _uri: &'a DppUri,
+        _ipv4: Option<StaticIpv4>,
+        _ipv6: Option<Ipv6Config>,
+    ) -> BackendFuture<'a, Option<ActiveConnection>> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("Easy Connect")) })
+    }
+
+    /// The access points that advertise WPS push-button, strongest first
+    fn wps_access_points(&self) -> BackendFuture<'_, Vec<WifiConnection>> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("WPS")) })
+    }
+
+    /// Sets the priority of the saved connection of the given SSID. Returns false if there is none.
+    fn set_priority<'a>(&'a self, _ssid: &'a SSID, _priority: i32) -> BackendFuture<'a, bool> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("Network priorities")) })
+    }
+
+    /// The SSIDs of the saved connections
+    fn saved_ssids(&self) -> BackendFuture<'_, Vec<SSID>> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("Listing the saved networks")) })
+    }
+
+    /// The stored passphrase of the saved connection of the given SSID
+    fn saved_secret<'a>(&'a self, _ssid: &'a SSID) -> BackendFuture<'a, Option<String>> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("Reading secrets")) })
+    }
+
+    /// A stream of saved connections that are added or removed outside of the portal
+    fn settings_changed_stream(
+        &self,
+    ) -> BackendFuture<'_, BoxStream<'static, SettingsChanged>> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("Watching the saved networks")) })
+    }
+
+    /// Driver, firmware and wireless capabilities of the wifi device
+    fn device_info(&self) -> BackendFuture<'_, WifiDeviceInfo> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("Reading the wifi device info")) })
+    }
+
+    /// Fails if the backend does not answer within the timeout, see [`crate::watchdog`]
+    fn ping(&self, _timeout: Duration) -> BackendFuture<'_, ()> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("The watchdog")) })
+    }
+
+    /// Restarts the backend service and waits up to `timeout` until it is back. False if it did not come back.
+    fn restart_service(&self, _timeout: Duration) -> BackendFuture<'_, bool> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("Restarting the backend service")) })
+    }
+
+    /// Switches wifi off and on again
+    fn restart_wifi(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("Restarting wifi")) })
+    }
+
+    /// Asks the system to reboot
+    fn reboot(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("Rebooting")) })
+    }
+
+    /// Dedicates a second wifi adapter to connection attempts
+    fn set_station_adapter<'a>(&'a mut self, _interface_name: &'a str) -> BackendFuture<'a, ()> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("A dedicated station adapter")) })
+    }
+
+    /// Adds a station interface for the concurrent access point and station mode.
+    /// None if the wifi device does not support it.
+    fn station_interface_add(&self) -> BackendFuture<'_, Option<String>> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("The concurrent access point and station mode")) })
+    }
+
+    /// The station interface or adapter, if there is one
+    fn station_interface(&self) -> Option<String> {
+        None
+    }
+
+    /// Removes the station interface, if there is one
+    fn station_interface_remove(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Moves the connection of the station interface to the wifi interface. False if it did not come up in time.
+    fn station_handover(&self, _timeout: Duration) -> BackendFuture<'_, bool> {
+        Box::pin(async { Err(CaptivePortalError::Unsupported("The concurrent access point and station mode")) })
+    }
+}
@@ -0,0 +1,148 @@
+//! # All active connections
+//! A device with ethernet and wifi is online via either of them. The states of all active connections
+//! are followed concurrently, with one typed signal stream per connection, and joined into an aggregate
+//! "online" verdict. The state machine uses the verdict to notice a lost connection,
+//! see [`NetworkBackend::wait_for_connectivity_lost`].
+
+use super::connection_active::ConnectionActiveStateChanged;
+use super::properties::{get_all, ActiveConnectionProperties};
+use super::signals::ActiveConnectionsChanged;
+use super::{NetworkBackend, NM_BUSNAME};
+use crate::dbus_tokio::SignalStream;
+use crate::network_backend::NM_PATH;
+use crate::network_interface::ConnectionState;
+use crate::runtime::BoxStream;
+use crate::CaptivePortalError;
+use dbus::nonblock;
+use futures_util::future;
+use futures_util::stream::{select_all, StreamExt};
+
+/// Connection types that make the device online. VPNs, bridges and loopback connections do not.
+const ONLINE_TYPES: [&str; 2] = ["802-11-wireless", "802-3-ethernet"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ActiveConnectionState {
+    /// The dbus path of the active connection
+    pub path: String,
+    pub id: String,
+    /// The connection type, like "802-11-wireless" or "802-3-ethernet"
+    pub connection_type: String,
+    pub state: ConnectionState,
+}
+
+/// The aggregate verdict: Online if an ethernet or wifi connection is activated
+pub(crate) fn is_online(connections: &[ActiveConnectionState]) -> bool {
+    connections
+        .iter()
+        .any(|c| c.state == ConnectionState::Activated && ONLINE_TYPES.contains(&c.connection_type.as_str()))
+}
+
+fn describe(connections: &[ActiveConnectionState]) -> String {
+    let connections: Vec<String> = connections
+        .iter()
+        .map(|c| format!("{} ({}) {:?}", c.id, c.connection_type, c.state))
+        .collect();
+    connections.join(", ")
+}
+
+enum Event {
+    State(String, ConnectionState),
+    /// Connections have been activated or removed
+    Changed,
+}
+
+impl NetworkBackend {
+    /// The states of all active connections, of all devices
+    pub async fn active_connections(&self) -> Result<Vec<ActiveConnectionState>, CaptivePortalError> {
+        use super::networkmanager::NetworkManager;
+        let p = nonblock::Proxy::new(NM_BUSNAME, NM_PATH, self.conn.clone());
+        let paths = p.active_connections().await?;
+        Ok(self.active_connection_states(&paths).await)
+    }
+
+    async fn active_connection_states(&self, paths: &[dbus::Path<'static>]) -> Vec<ActiveConnectionState> {
+        let mut connections = Vec::new();
+        for path in paths {
+            // The connection may be gone already
+            match get_all::<ActiveConnectionProperties>(self.conn.clone(), path.clone()).await {
+                Ok(properties) => connections.push(ActiveConnectionState {
+                    path: path.to_string(),
+                    id: properties.id,
+                    connection_type: properties.connection_type,
+                    state: properties.state.into(),
+                }),
+                Err(e) => debug!("Skip the active connection {}: {}", path, e),
+            }
+        }
+        connections
+    }
+
+    /// Resolves when no ethernet or wifi connection is activated anymore, on any device.
+    /// Connections that are activated meanwhile, like a plugged in ethernet cable, are followed as well.
+    pub async fn wait_for_offline(&self) -> Result<(), CaptivePortalError> {
+        use super::networkmanager::NetworkManager;
+        let p = nonblock::Proxy::new(NM_BUSNAME, NM_PATH, self.conn.clone());
+        let nm_path: dbus::Path = NM_PATH.into();
+
+        loop {
+            // Subscribe before reading the states, so that no change gets lost in between
+            let changed = SignalStream::<ActiveConnectionsChanged>::prop_new(&nm_path, self.conn.clone())
+                .await?
+                .filter_map(|(value, _path)| future::ready(value.active_connections.map(|_| Event::Changed)));
+            let mut streams: Vec<BoxStream<'static, Event>> = vec![Box::pin(changed)];
+            let paths = p.active_connections().await?;
+            for path in &paths {
+                let states = SignalStream::<ConnectionActiveStateChanged>::prop_new(path, self.conn.clone())
+                    .await?
+                    .map(|(value, path)| Event::State(path, value.state.into()));
+                streams.push(Box::pin(states));
+            }
+
+            let mut connections = self.active_connection_states(&paths).await;
+            info!("Active connections: {}", describe(&connections));
+            if !is_online(&connections) {
+                return Ok(());
+            }
+
+            let mut events = select_all(streams);
+            while let Some(event) = events.next().await {
+                match event {
+                    Event::State(path, state) => {
+                        if let Some(connection) = connections.iter_mut().find(|c| c.path == path) {
+                            connection.state = state;
+                        }
+                        if !is_online(&connections) {
+                            info!("No connection is activated anymore: {}", describe(&connections));
+                            return Ok(());
+                        }
+                    },
+                    Event::Changed => break,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(connection_type: &str, state: ConnectionState) -> ActiveConnectionState {
+        ActiveConnectionState {
+            path: "/org/freedesktop/NetworkManager/ActiveConnection/1".to_owned(),
+            id: "a".to_owned(),
+            connection_type: connection_type.to_owned(),
+            state,
+        }
+    }
+
+    #[test]
+    fn online() {
+        assert!(!is_online(&[]));
+        let wifi = connection("802-11-wireless", ConnectionState::Deactivated);
+        let ethernet = connection("802-3-ethernet", ConnectionState::Activated);
+        assert!(!is_online(&[wifi.clone()]));
+        assert!(is_online(&[wifi, ethernet]));
+        assert!(!is_online(&[connection("vpn", ConnectionState::Activated)]));
+    }
+}
@@ -1,16 +1,18 @@
 //! This module contains connectivity and state related types. This includes
 //! network manager state as well as connection and device state.
 
-use futures_util::stream::StreamExt;
-use tokio::time::timeout;
+use futures_util::future;
+use futures_util::stream::{select, StreamExt};
 
+use super::device_state_type::DeviceState;
+use super::signals::{ConnectivityChanged, DeviceStateChanged, ACTIVE_CONNECTION_INTERFACE, NM_INTERFACE};
 use super::NetworkBackend;
 use super::NM_BUSNAME;
-use crate::dbus_tokio::SignalStream;
+use crate::dbus_tokio::{await_property, SignalStream};
 use crate::network_backend::NM_PATH;
 use crate::network_interface::{ConnectionState, NetworkManagerState};
+use crate::runtime;
 use crate::CaptivePortalError;
-use dbus::message::SignalArgs;
 use dbus::nonblock;
 
 impl From<u32> for NetworkManagerState {
@@ -45,9 +47,10 @@ impl NetworkBackend {
             SignalStream::<ConnectionActiveChanged>::prop_new(&self.wifi_device_path, self.conn.clone()).await?;
         while let Some((value, path)) = stream.next().await {
             info!(
-                "Connection state changed: {:?} {} on {}",
+                "Connection state changed: {:?} {} ({}) on {}",
                 ConnectionState::from(value.state),
                 value.reason,
+                super::reasons::active_connection_reason(value.reason).unwrap_or("none"),
                 path
             );
         }
@@ -55,7 +58,26 @@ impl NetworkBackend {
         Ok(())
     }
 
-    /// Continuously print connection state changes
+    /// Continuously print state changes of the wifi device
+    #[allow(dead_code)]
+    pub async fn print_device_changes(&self) -> Result<(), CaptivePortalError> {
+        let mut stream =
+            SignalStream::<DeviceStateChanged>::prop_new(&self.wifi_device_path, self.conn.clone()).await?;
+        while let Some((value, path)) = stream.next().await {
+            info!(
+                "Device state changed: {:?} -> {:?} {} ({}) on {}",
+                DeviceState::from(value.old_state),
+                DeviceState::from(value.new_state),
+                value.reason,
+                super::reasons::device_reason(value.reason).unwrap_or("none"),
+                path
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Continuously print network manager state and connectivity check changes
     #[allow(dead_code)]
     pub async fn print_connectivity_changes(&self) -> Result<(), CaptivePortalError> {
         use super::networkmanager::NetworkManagerStateChanged as StateChanged;
@@ -63,10 +85,18 @@ impl NetworkBackend {
         let state = self.state().await?;
         info!("Connectivity state: {:?}", state);
 
-        let mut stream = SignalStream::<StateChanged>::prop_new(&NM_PATH.to_owned().into(), self.conn.clone()).await?;
-        while let Some((value, _path)) = stream.next().await {
-            let state = NetworkManagerState::from(value.state);
-            info!("Connectivity state changed: {:?}", state);
+        let nm_path: dbus::Path = NM_PATH.into();
+        let states = SignalStream::<StateChanged>::prop_new(&nm_path, self.conn.clone())
+            .await?
+            .map(|(value, _path)| format!("Connectivity state changed: {:?}", NetworkManagerState::from(value.state)));
+        let checks = SignalStream::<ConnectivityChanged>::prop_new(&nm_path, self.conn.clone())
+            .await?
+            .filter_map(|(value, _path)| future::ready(value.connectivity))
+            .map(|connectivity| format!("Connectivity check changed: {:?}", connectivity));
+
+        let mut stream = select(states, checks);
+        while let Some(message) = stream.next().await {
+            info!("{}", message);
         }
 
         Ok(())
@@ -87,17 +117,26 @@ impl NetworkBackend {
             .await
     }
 
-    /// The returned future resolves when either the timeout expired or (internet) connectivity is lost
+    /// The returned future resolves when either the timeout expired or (internet) connectivity is lost.
+    ///
+    /// Without required internet connectivity, the connection is lost when no ethernet or wifi connection
+    /// is activated anymore, see [`NetworkBackend::wait_for_offline`]. A device with ethernet stays online
+    /// if the wifi connection drops.
     pub async fn wait_for_connectivity_lost(
         &self,
         internet_connectivity: bool,
         timeout: std::time::Duration,
     ) -> Result<NetworkManagerState, CaptivePortalError> {
-        self.connectivity_changed(timeout, |state| {
-            state != NetworkManagerState::Connected
-                && (state != NetworkManagerState::ConnectedLimited || internet_connectivity)
-        })
-            .await
+        if internet_connectivity {
+            return self
+                .connectivity_changed(timeout, |state| state != NetworkManagerState::Connected)
+                .await;
+        }
+        match runtime::timeout(timeout, self.wait_for_offline()).await {
+            Some(Ok(())) => Ok(NetworkManagerState::Disconnected),
+            Some(Err(e)) => Err(e),
+            None => Err(CaptivePortalError::NotRequiredConnectivity(self.state().await?)),
+        }
     }
 
     /// Waits up to "timeout" for the network backend to report the condition given in "condition".
@@ -109,22 +148,17 @@ impl NetworkBackend {
         where
             F: Fn(NetworkManagerState) -> bool,
     {
-        use super::networkmanager::NetworkManagerStateChanged as StateChanged;
-
-        let mut state = self.state().await?;
-        if condition(state) {
-            return Ok(state);
-        }
-
-        let mut stream = SignalStream::<StateChanged>::prop_new(&NM_PATH.to_owned().into(), self.conn.clone())
-            .await?;
-        while let Ok(Some((value, _path))) = timeout(timeout_value, stream.next()).await {
-            state = NetworkManagerState::from(value.state);
-            if condition(state) {
-                return Ok(state);
-            }
-        }
-
+        let p = nonblock::Proxy::new(NM_BUSNAME, NM_PATH, self.conn.clone());
+        let state: u32 = await_property(
+            &p,
+            NM_INTERFACE,
+            "State",
+            |state| condition(NetworkManagerState::from(*state)),
+            timeout_value,
+        )
+        .await?;
+
+        let state = NetworkManagerState::from(state);
         if condition(state) {
             Ok(state)
         } else {
@@ -143,29 +177,15 @@ impl NetworkBackend {
         negate: bool,
     ) -> Result<ConnectionState, CaptivePortalError> {
         let p = nonblock::Proxy::new(NM_BUSNAME, path, self.conn.clone());
-
-        use super::connection_active::ConnectionActive;
-        let state: ConnectionState = p.state().await?.into();
-        if (state == expected_state) ^ negate {
-            return Ok(state);
-        }
-
-        use super::connection_active::ConnectionActiveStateChanged as StateChanged;
-
-        let rule = StateChanged::match_rule(None, None).static_clone();
-        let stream: SignalStream<StateChanged> = SignalStream::new(self.conn.clone(), rule).await?;
-        pin_utils::pin_mut!(stream);
-        let mut stream = stream; // Idea IDE Workaround
-
-        while let Ok(Some((state, _path))) = timeout(timeout_value, stream.next()).await {
-            let state = ConnectionState::from(state.state);
-            if (state == expected_state) ^ negate {
-                return Ok(state);
-            }
-        }
-
-        let state: ConnectionState = p.state().await?.into();
-        Ok(state)
+        let state: u32 = await_property(
+            &p,
+            ACTIVE_CONNECTION_INTERFACE,
+            "State",
+            |state| (ConnectionState::from(*state) == expected_state) ^ negate,
+            timeout_value,
+        )
+        .await?;
+        Ok(state.into())
     }
 
     pub async fn enable_auto_connect(&self) {
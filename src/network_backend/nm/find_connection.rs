@@ -1,11 +1,15 @@
 //! # Find a connection on some criteria; Update connection
 //! Implementation detail of the network manager implementation
 
+use dbus::arg::{RefArg, Variant};
 use dbus::nonblock;
+use std::collections::HashMap;
 
-use super::wifi_settings::{self, VariantMap, WiFiConnectionSettings};
-use crate::network_backend::{NetworkBackend, IN_MEMORY_ONLY, NM_BUSNAME, NM_PATH, NM_SETTINGS_PATH};
-use crate::network_interface::{AccessPointCredentials, SSID};
+use super::wifi_settings::{self, VariantMap, WifiConnectionMode, WiFiConnectionSettings};
+use crate::network_backend::{
+    NetworkBackend, IN_MEMORY_FLAG, IN_MEMORY_ONLY, NM_BUSNAME, NM_PATH, NM_SETTINGS_PATH, SAVE_TO_DISK_FLAG,
+};
+use crate::network_interface::{AccessPointCredentials, ConnectOptions, SavedNetwork, SSID};
 use crate::CaptivePortalError;
 
 impl NetworkBackend {
@@ -53,6 +57,109 @@ impl NetworkBackend {
         return Ok(None);
     }
 
+    /// The saved wifi connections in station mode, the highest autoconnect priority first.
+    /// The hotspot connection is not included.
+    pub(crate) async fn saved_connections(
+        &self,
+    ) -> Result<Vec<(dbus::Path<'static>, WiFiConnectionSettings)>, CaptivePortalError> {
+        let connections = {
+            use super::generated::connections::Settings;
+            let p = nonblock::Proxy::new(NM_BUSNAME, NM_SETTINGS_PATH, self.conn.clone());
+            p.connections().await?
+        };
+        let mut saved = Vec::new();
+        for connection_path in connections {
+            let settings = wifi_settings::get_connection_settings(self.conn.clone(), connection_path.clone()).await;
+            if let Ok(Some(settings)) = settings {
+                if settings.mode == WifiConnectionMode::Infrastructure {
+                    saved.push((connection_path, settings));
+                }
+            }
+        }
+        saved.sort_by(|(_, a), (_, b)| b.priority.cmp(&a.priority).then_with(|| a.ssid.cmp(&b.ssid)));
+        Ok(saved)
+    }
+
+    /// Returns the sorted SSIDs of the saved wifi connections. The hotspot connection is not included.
+    pub async fn saved_ssids(&self) -> Result<Vec<SSID>, CaptivePortalError> {
+        let mut ssids: Vec<SSID> = self.saved_connections().await?.into_iter().map(|(_, s)| s.ssid).collect();
+        ssids.sort();
+        ssids.dedup();
+        Ok(ssids)
+    }
+
+    /// The saved wifi connections, the highest priority first
+    pub async fn saved_networks(&self) -> Result<Vec<SavedNetwork>, CaptivePortalError> {
+        Ok(self
+            .saved_connections()
+            .await?
+            .into_iter()
+            .map(|(_, settings)| SavedNetwork {
+                ssid: settings.ssid,
+                priority: settings.priority,
+            })
+            .collect())
+    }
+
+    /// Sets the autoconnect priority of the saved connection for the given SSID. If several saved networks
+    /// are in range, network manager connects to the one with the highest priority.
+    /// Returns false if there is no saved connection.
+    pub async fn set_priority(&self, ssid: &SSID, priority: i32) -> Result<bool, CaptivePortalError> {
+        let connection_path = match self.find_connection_by_ssid(ssid).await? {
+            Some((connection_path, _)) => connection_path,
+            None => return Ok(false),
+        };
+        use super::generated::connection_nm::Connection;
+        let p = nonblock::Proxy::new(NM_BUSNAME, connection_path, self.conn.clone());
+        // The settings do not contain the secrets. Network manager keeps the stored ones.
+        let current = p.get_settings().await?;
+        let mut settings: HashMap<&str, HashMap<&str, Variant<Box<dyn RefArg>>>> = current
+            .iter()
+            .map(|(name, values)| {
+                let values = values.iter().map(|(k, v)| (k.as_str(), Variant(v.0.box_clone()))).collect();
+                (name.as_str(), values)
+            })
+            .collect();
+        if let Some(connection) = settings.get_mut("connection") {
+            connection.insert("autoconnect-priority", Variant(Box::new(priority)));
+        }
+        let flags = match self.in_memory {
+            true => IN_MEMORY_FLAG,
+            false => SAVE_TO_DISK_FLAG,
+        };
+        p.update2(settings, flags, VariantMap::new()).await?;
+        Ok(true)
+    }
+
+    /// The stored secret of the saved connection for the given SSID: The 802.1x password, the PSK or the WEP key.
+    /// None if there is no saved connection or no stored secret, for example for an open network.
+    /// Network manager only returns system-wide stored secrets, not the ones of a user session secret agent.
+    pub async fn saved_secret(&self, ssid: &SSID) -> Result<Option<String>, CaptivePortalError> {
+        let connection_path = match self.find_connection_by_ssid(ssid).await? {
+            Some((connection_path, _)) => connection_path,
+            None => return Ok(None),
+        };
+        use super::generated::connection_nm::Connection;
+        let p = nonblock::Proxy::new(NM_BUSNAME, connection_path, self.conn.clone());
+        let settings = p.get_settings().await?;
+        let (setting_name, keys): (&str, &[&str]) = if settings.contains_key("802-1x") {
+            ("802-1x", &["password"])
+        } else if settings.contains_key("802-11-wireless-security") {
+            ("802-11-wireless-security", &["psk", "wep-key0"])
+        } else {
+            return Ok(None);
+        };
+        let secrets = p.get_secrets(setting_name).await?;
+        let secret = secrets.get(setting_name).and_then(|values| {
+            keys.iter()
+                .filter_map(|key| values.get(*key))
+                .filter_map(|value| value.0.as_str())
+                .find(|value| !value.is_empty())
+                .map(|value| value.to_owned())
+        });
+        Ok(secret)
+    }
+
     /// Returns a tuple with network manager dbus paths on success: (connection, active_connection)
     pub(crate) async fn update_connection<'a>(
         &self,
@@ -60,16 +167,25 @@ impl NetworkBackend {
         ssid: &SSID,
         old_connection: WiFiConnectionSettings,
         credentials: AccessPointCredentials,
+        options: &ConnectOptions,
     ) -> Result<(dbus::Path<'a>, dbus::Path<'_>), CaptivePortalError> {
         use super::generated::connection_nm::Connection;
         let p = nonblock::Proxy::new(NM_BUSNAME, connection_path.clone(), self.conn.clone());
-        let settings = wifi_settings::make_arguments_for_ap::<&'static str>(ssid, credentials, Some(old_connection))?;
+        let settings = wifi_settings::make_arguments_for_ap::<&'static str>(
+            ssid,
+            credentials,
+            self.pmf,
+            options.ipv4.as_ref(),
+            options.ipv6.as_ref(),
+            options.hidden,
+            Some(old_connection),
+        )?;
         p.update2(settings, IN_MEMORY_ONLY, VariantMap::new()).await?;
         // Activate connection
         let p = nonblock::Proxy::new(NM_BUSNAME, NM_PATH, self.conn.clone());
         use super::generated::networkmanager::NetworkManager;
         let active_path = p
-            .activate_connection(connection_path.clone(), self.wifi_device_path.clone(), "/".into())
+            .activate_connection(connection_path.clone(), self.station_device_path(), "/".into())
             .await?;
         Ok((connection_path, active_path))
     }
@@ -1,6 +1,8 @@
 //! This crate will immediately quit if no wifi device can be found. This module encapsulates the
-//! method to find a wifi device via the network manager dbus API.
+//! methods to find the wifi devices via the network manager dbus API.
 
+use super::properties::{get_all, DeviceProperties};
+use super::virtual_interface::is_station_interface;
 use super::{NM_BUSNAME, NM_PATH};
 use crate::CaptivePortalError;
 use dbus::nonblock;
@@ -33,13 +35,52 @@ pub(crate) struct FindWifiDeviceResult {
     pub interface_name: String,
     /// The mac address
     pub hw: String,
+    /// The device can run an access point, see [`supports_ap`]
+    pub ap: bool,
 }
 
-/// Finds the first wifi device or the wifi device on the given device interface.
-/// Returns (wifi_device_path, interface_name) on success and an error otherwise.
+/// NM_WIFI_DEVICE_CAP_AP: The device supports access point mode
+const WIFI_DEVICE_CAP_AP: u32 = 0x40;
+
+/// True if the "WirelessCapabilities" bitmask of a wifi device includes access point mode
+pub(crate) fn supports_ap(wireless_capabilities: u32) -> bool {
+    wireless_capabilities & WIFI_DEVICE_CAP_AP != 0
+}
+
+/// NM_WIFI_DEVICE_CAP_*: The flags of the "WirelessCapabilities" bitmask and their names.
+/// NM_WIFI_DEVICE_CAP_FREQ_VALID only tells that the frequency flags are valid.
+const WIFI_DEVICE_CAPS: [(u32, &str); 12] = [
+    (0x1, "wep40"),
+    (0x2, "wep104"),
+    (0x4, "tkip"),
+    (0x8, "ccmp"),
+    (0x10, "wpa"),
+    (0x20, "rsn"),
+    (WIFI_DEVICE_CAP_AP, "ap"),
+    (0x80, "adhoc"),
+    (0x200, "2ghz"),
+    (0x400, "5ghz"),
+    (0x800, "mesh"),
+    (0x1000, "ibss_rsn"),
+];
+
+/// The names of the flags of a "WirelessCapabilities" bitmask
+pub(crate) fn wireless_capabilities(wireless_capabilities: u32) -> Vec<&'static str> {
+    WIFI_DEVICE_CAPS
+        .iter()
+        .filter(|(flag, _)| wireless_capabilities & flag != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// Finds the first wifi device that supports access point mode or the wifi device on the given device interface.
+/// Returns (wifi_device_path, interface_name) on success and an error otherwise, also if the device on the given
+/// interface cannot run the hotspot. Without `require_ap` the first wifi device is taken if none supports
+/// access point mode.
 pub(crate) async fn find_wifi_device(
-    connection: Arc<dbus::nonblock::SyncConnection>,
+    connection: Arc<crate::dbus_tokio::DbusConnection>,
     preferred_interface: &Option<String>,
+    require_ap: bool,
 ) -> Result<FindWifiDeviceResult, CaptivePortalError> {
     let p = nonblock::Proxy::new(NM_BUSNAME, NM_PATH, connection.clone());
 
@@ -52,34 +93,100 @@ pub(crate) async fn find_wifi_device(
         let device_type = device_data.device_type().await?;
         if device_type == DeviceType::WiFi as u32 {
             use super::generated::device::DeviceWireless;
+            let ap = supports_ap(device_data.wireless_capabilities().await?);
+            if !ap && require_ap {
+                return Err(CaptivePortalError::ApModeUnsupported(interface_name.clone()));
+            }
             let hw = device_data.hw_address().await?;
             info!("Wireless device found: {}", interface_name);
             return Ok(FindWifiDeviceResult {
                 device_path,
                 interface_name: interface_name.clone(),
                 hw,
+                ap,
             });
         }
     };
 
-    // Filter by type; only wifi devices; take first
+    // Take the first wifi device that can run the hotspot
+    let wifi_devices = find_wifi_devices(connection).await?;
+    for wifi_device in wifi_devices.iter().filter(|d| !d.ap) {
+        info!("Wireless device on '{}' does not support access point mode", &wifi_device.interface_name);
+    }
+    match wifi_devices.iter().position(|d| d.ap) {
+        Some(index) => {
+            let wifi_device = wifi_devices.into_iter().nth(index).expect("Wifi device index");
+            info!("Wireless device on '{}'", &wifi_device.interface_name);
+            Ok(wifi_device)
+        },
+        None => match wifi_devices.into_iter().next() {
+            Some(wifi_device) if !require_ap => {
+                info!("Wireless device without access point mode on '{}'", &wifi_device.interface_name);
+                Ok(wifi_device)
+            },
+            Some(wifi_device) => Err(CaptivePortalError::ApModeUnsupported(wifi_device.interface_name)),
+            None => Err(CaptivePortalError::NoWifiDeviceFound),
+        },
+    }
+}
+
+/// Finds all wifi devices, for example the built-in wifi and a USB dongle. One can serve the hotspot and another
+/// one can be dedicated to connection attempts, see [`super::NetworkBackend::set_station_adapter`].
+pub(crate) async fn find_wifi_devices(
+    connection: Arc<crate::dbus_tokio::DbusConnection>,
+) -> Result<Vec<FindWifiDeviceResult>, CaptivePortalError> {
+    let p = nonblock::Proxy::new(NM_BUSNAME, NM_PATH, connection.clone());
+    use super::generated::networkmanager::NetworkManager;
+
+    // Filter by type; only wifi devices. A left over station interface is not a wifi device of its own.
+    let mut wifi_devices = Vec::new();
     let device_paths = p.get_all_devices().await?;
     for device_path in device_paths {
-        let device_data = nonblock::Proxy::new(NM_BUSNAME, &device_path, connection.clone());
-        use super::generated::device::Device;
-        let dtype = device_data.device_type().await?;
-        if dtype == DeviceType::WiFi as u32 {
+        let device = get_all::<DeviceProperties>(connection.clone(), device_path.clone()).await?;
+        if device.device_type == DeviceType::WiFi as u32 && !is_station_interface(&device.interface) {
+            let device_data = nonblock::Proxy::new(NM_BUSNAME, &device_path, connection.clone());
             use super::generated::device::DeviceWireless;
             let hw = device_data.hw_address().await?;
-            let interface_name = device_data.interface().await?;
-            info!("Wireless device on '{}'", &interface_name);
-            return Ok(FindWifiDeviceResult {
+            let ap = supports_ap(device_data.wireless_capabilities().await?);
+            wifi_devices.push(FindWifiDeviceResult {
                 device_path,
-                interface_name,
+                interface_name: device.interface,
                 hw,
+                ap,
             });
         }
     }
+    Ok(wifi_devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbus_record::{self, PrivateBus, Replay};
+
+    /// Replays a recorded trace of network manager on a bus daemon of its own
+    #[tokio::test]
+    async fn find_wifi_device_replayed() {
+        let bus = match PrivateBus::start() {
+            Some(bus) => bus,
+            None => {
+                eprintln!("Skipped: dbus-daemon is not installed");
+                return;
+            },
+        };
+        let replay = Replay::parse(include_str!("traces/find_wifi_device.jsonl")).unwrap();
+        dbus_record::serve(replay, bus.connect().unwrap()).await.unwrap();
+
+        let conn = bus.connect().unwrap();
+        let device = find_wifi_device(conn, &Some("wlan0".to_owned()), true).await.unwrap();
+        assert_eq!(device.device_path, dbus::Path::from("/org/freedesktop/NetworkManager/Devices/3"));
+        assert_eq!(device.hw, "DC:A6:32:01:02:03");
+        assert!(device.ap);
+    }
 
-    Err(CaptivePortalError::NoWifiDeviceFound)
+    #[test]
+    fn capabilities() {
+        assert_eq!(wireless_capabilities(0x7f8), vec!["ccmp", "wpa", "rsn", "ap", "adhoc", "2ghz", "5ghz"]);
+        assert!(wireless_capabilities(0).is_empty());
+    }
 }
@@ -13,6 +13,18 @@ pub trait Settings {
             ::std::collections::HashMap<&str, arg::Variant<Box<dyn arg::RefArg>>>,
         >,
     ) -> nonblock::MethodReply<dbus::Path<'static>>;
+    fn add_connection2(
+        &self,
+        settings: ::std::collections::HashMap<
+            &str,
+            ::std::collections::HashMap<&str, arg::Variant<Box<dyn arg::RefArg>>>,
+        >,
+        flags: u32,
+        args: ::std::collections::HashMap<&str, arg::Variant<Box<dyn arg::RefArg>>>,
+    ) -> nonblock::MethodReply<(
+        dbus::Path<'static>,
+        ::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>,
+    )>;
     fn add_connection_unsaved(
         &self,
         connection: ::std::collections::HashMap<
@@ -59,6 +71,25 @@ impl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target = T>> Settings
         .and_then(|r: (dbus::Path<'static>,)| Ok(r.0))
     }
 
+    fn add_connection2(
+        &self,
+        settings: ::std::collections::HashMap<
+            &str,
+            ::std::collections::HashMap<&str, arg::Variant<Box<dyn arg::RefArg>>>,
+        >,
+        flags: u32,
+        args: ::std::collections::HashMap<&str, arg::Variant<Box<dyn arg::RefArg>>>,
+    ) -> nonblock::MethodReply<(
+        dbus::Path<'static>,
+        ::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>,
+    )> {
+        self.method_call(
+            "org.freedesktop.NetworkManager.Settings",
+            "AddConnection2",
+            (settings, flags, args),
+        )
+    }
+
     fn add_connection_unsaved(
         &self,
         connection: ::std::collections::HashMap<
@@ -1,7 +1,16 @@
+//! Generated bindings, one module per D-Bus interface (see generate.sh).
+//! Every module is behind a cargo feature. The "networkmanager" feature enables the ones used by the backend.
+#[cfg(feature = "nm-access-point")]
 pub mod access_point;
+#[cfg(feature = "nm-active-connection")]
 pub mod connection_active;
+#[cfg(feature = "nm-settings")]
 pub mod connection_nm;
+#[cfg(feature = "nm-settings")]
 pub mod connections;
+#[cfg(feature = "nm-device")]
 pub mod device;
+#[cfg(feature = "nm-manager")]
 pub mod networkmanager;
+#[cfg(feature = "nm-systemd-unit")]
 pub mod systemd_service_unit;
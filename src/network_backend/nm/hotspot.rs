@@ -3,13 +3,21 @@ use dbus::nonblock;
 use std::net::Ipv4Addr;
 
 use super::wifi_settings::{self, VariantMap, VariantMapNested, WifiConnectionMode};
-use super::{NetworkBackend, HOTSPOT_UUID, IN_MEMORY_ONLY, NM_BUSNAME, NM_PATH, NM_SETTINGS_PATH, VOLATILE_FLAG};
+use super::{
+    ConnectionPath, NetworkBackend, BLOCK_AUTOCONNECT_FLAG, HOTSPOT_UUID, IN_MEMORY_FLAG, NM_BUSNAME, NM_PATH,
+    NM_SETTINGS_PATH, VOLATILE_FLAG,
+};
 use crate::dbus_tokio::SignalStream;
-use crate::network_interface::{ActiveConnection, ConnectionState, SSID};
+use crate::network_interface::{ActiveConnection, ConnectionState, HotspotRadio, HotspotSecurity, SSID};
 use crate::CaptivePortalError;
+use dbus::arg::{RefArg, Variant};
 use dbus::message::SignalArgs;
 use futures_util::StreamExt;
 
+/// NetworkManager runs the access point with wpa_supplicant, which reports the SAE support of the device
+const WPA_SUPPLICANT_BUSNAME: &str = "fi.w1.wpa_supplicant1";
+const WPA_SUPPLICANT_PATH: &str = "/fi/w1/wpa_supplicant1";
+
 impl NetworkBackend {
     /// The hotspot that is created by this service has a unique id.
     /// This method will search connections for this id and delete the respective connection.
@@ -45,7 +53,7 @@ impl NetworkBackend {
             let settings = wifi_settings::get_connection_settings(self.conn.clone(), connection_path.clone()).await;
             match settings {
                 Ok(Some(settings)) => {
-                    if settings.mode == WifiConnectionMode::AP {
+                    if settings.mode == WifiConnectionMode::AP || settings.mode == WifiConnectionMode::AdHoc {
                         info!("disable hotspot connection {} {}", settings.uuid, settings.ssid);
                         p.deactivate_connection(connection_path).await?;
                     }
@@ -60,31 +68,86 @@ impl NetworkBackend {
         Ok(())
     }
 
-    /// Starts a hotspot
+    /// The configured hotspot security if the wifi device supports SAE, WPA2 otherwise
+    async fn supported_hotspot_security(&self) -> HotspotSecurity {
+        if self.hotspot_security == HotspotSecurity::Wpa2 {
+            return HotspotSecurity::Wpa2;
+        }
+        match self.key_mgmt_capabilities().await {
+            Ok(key_mgmt) if key_mgmt.iter().any(|k| k == "sae") => self.hotspot_security,
+            Ok(key_mgmt) => {
+                warn!("The wifi device does not support SAE ({:?}). The hotspot uses WPA2.", key_mgmt);
+                HotspotSecurity::Wpa2
+            },
+            Err(e) => {
+                warn!("Failed to read the SAE support of the wifi device: {}. The hotspot uses WPA2.", e);
+                HotspotSecurity::Wpa2
+            },
+        }
+    }
+
+    /// The key management capabilities of the wifi interface, as reported by wpa_supplicant
+    async fn key_mgmt_capabilities(&self) -> Result<Vec<String>, CaptivePortalError> {
+        let p = nonblock::Proxy::new(WPA_SUPPLICANT_BUSNAME, WPA_SUPPLICANT_PATH, self.conn.clone());
+        let (interface,): (dbus::Path<'static>,) = p
+            .method_call(WPA_SUPPLICANT_BUSNAME, "GetInterface", (&self.interface_name[..],))
+            .await?;
+        let p = nonblock::Proxy::new(WPA_SUPPLICANT_BUSNAME, interface, self.conn.clone());
+        // The capabilities dictionary is not thread safe (RefArg). Only keep the key management.
+        let key_mgmt = p
+            .method_call(
+                "org.freedesktop.DBus.Properties",
+                "Get",
+                ("fi.w1.wpa_supplicant1.Interface", "Capabilities"),
+            )
+            .and_then(|r: (Variant<Box<dyn RefArg>>,)| Ok(dict_strings(&*(r.0).0, "KeyMgmt")))
+            .await?;
+        Ok(key_mgmt)
+    }
+
+    /// Starts a hotspot. With a passphrase, the hotspot uses WPA3 or WPA2/WPA3 transition mode if configured
+    /// (see [`NetworkBackend::set_hotspot_security`]) and supported by the wifi device.
+    /// Without a `radio`, the hotspot uses the 2.4 Ghz band.
+    /// On a wifi device without access point mode the hotspot is an open ad-hoc network instead,
+    /// see [`NetworkBackend::new_adhoc`].
     pub async fn hotspot_start(
         &self,
         ssid: SSID,
         password: String,
         address: Option<Ipv4Addr>,
+        radio: Option<HotspotRadio>,
     ) -> Result<ActiveConnection, CaptivePortalError> {
         self.hotspot_remove_existing().await?;
+        let hotspot_security = match password.is_empty() {
+            true => HotspotSecurity::Wpa2,
+            false => self.supported_hotspot_security().await,
+        };
 
         debug!("Configuring hotspot ...");
         let connection_path = {
             // add connection
-            let settings =
-                wifi_settings::make_arguments_for_sta(ssid, password, address, &self.interface_name, HOTSPOT_UUID)?;
+            let mut settings =
+                wifi_settings::make_arguments_for_sta(
+                ssid,
+                password.clone(),
+                address,
+                radio,
+                &self.interface_name,
+                self.pmf,
+                hotspot_security,
+            )?;
+            if self.adhoc {
+                if !password.is_empty() {
+                    warn!("The ad-hoc network is open. The portal passphrase is not used.");
+                }
+                wifi_settings::make_adhoc(&mut settings);
+            }
             let p = nonblock::Proxy::new(NM_BUSNAME, NM_SETTINGS_PATH, self.conn.clone());
             use super::generated::connections::Settings;
-            // We want the dbus nm api AddConnection2 here, but that's not yet available everywhere as of Oct 2019.
-            // Instead we first add the connection and then use Update2.
-            let connection_path = p.add_connection(settings).await?;
-
-            use super::generated::connection_nm::Connection;
-            let p = nonblock::Proxy::new(NM_BUSNAME, connection_path.clone(), self.conn.clone());
+            // The profile is never written to disk.
             // Do not set volatile here! volatile would immediately delete the connection.
-            // Settings: Provide an empty array, to use the current settings.
-            p.update2(VariantMapNested::new(), IN_MEMORY_ONLY, VariantMap::new())
+            let (connection_path, _) = p
+                .add_connection2(settings, IN_MEMORY_FLAG | BLOCK_AUTOCONNECT_FLAG, VariantMap::new())
                 .await?;
             connection_path
         };
@@ -143,7 +206,7 @@ impl NetworkBackend {
         })
     }
 
-    pub async fn on_hotspot_stopped(&self, path: dbus::Path<'_>) -> Result<(), CaptivePortalError> {
+    pub async fn on_hotspot_stopped(&self, path: ConnectionPath) -> Result<(), CaptivePortalError> {
         use super::connection_active::ConnectionActiveStateChanged as StateChanged;
 
         let rule = StateChanged::match_rule(None, Some(&path)).static_clone();
@@ -156,3 +219,39 @@ impl NetworkBackend {
         Ok(())
     }
 }
+
+/// The strings of an array entry of a dictionary ("a{sv}"), like the "KeyMgmt" capabilities of wpa_supplicant
+fn dict_strings(dict: &dyn RefArg, key: &str) -> Vec<String> {
+    let mut entries = match dict.as_iter() {
+        Some(entries) => entries,
+        None => return Vec::new(),
+    };
+    while let (Some(k), Some(v)) = (entries.next(), entries.next()) {
+        if k.as_str() == Some(key) {
+            // The value is a variant that contains the array
+            return v
+                .as_iter()
+                .and_then(|mut variant| variant.next())
+                .and_then(|array| array.as_iter())
+                .map(|items| items.filter_map(|i| i.as_str().map(|s| s.to_owned())).collect())
+                .unwrap_or_default();
+        }
+    }
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn key_mgmt_capabilities() {
+        let mut capabilities: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        let key_mgmt = vec!["wpa-psk".to_owned(), "sae".to_owned()];
+        capabilities.insert("KeyMgmt".to_owned(), Variant(Box::new(key_mgmt)));
+        capabilities.insert("Modes".to_owned(), Variant(Box::new(vec!["ap".to_owned()])));
+        assert_eq!(dict_strings(&capabilities, "KeyMgmt"), vec!["wpa-psk", "sae"]);
+        assert!(dict_strings(&capabilities, "Pairwise").is_empty());
+    }
+}
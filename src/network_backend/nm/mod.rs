@@ -4,34 +4,55 @@
 //! Use the `generate.sh` script to update them to newer dbus crate or network dbus API versions.
 
 mod access_points_changed;
+mod active_connections;
 mod connectivity;
 mod device_state_type;
 mod find_connection;
 mod find_wifi_device;
 mod generated;
 mod hotspot;
+mod properties;
+mod reasons;
 mod security;
+mod settings_changed;
+mod signals;
+mod systemd;
+mod virtual_interface;
 mod wifi_settings;
+mod wps_agent;
 
-use dbus::{nonblock, nonblock::SyncConnection};
+use dbus::arg::{RefArg, Variant};
+use dbus::nonblock;
 
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use futures_util::StreamExt;
 use std::time::Duration;
-use tokio::time::delay_for;
+use crate::runtime::{self, delay_for, BoxStream};
+use crate::trace::{self, Phase};
 
 // Re-export for easier use in sub-modules
-use crate::dbus_tokio;
+use crate::dbus_tokio::{self, DbusConnection};
+use crate::dpp::{self, DppUri};
+use crate::mac_filter::MacFilterSync;
 use crate::network_interface::{
-    AccessPointCredentials, ActiveConnection, ConnectionState, NetworkManagerState, WifiConnection, SSID,
+    AccessPointCredentials, ActiveConnection, ConnectOptions, ConnectionQuality, ConnectionState, HotspotSecurity,
+    Ipv6Config, NetworkManagerState, Pmf, StaticIpv4, WifiConnection, WifiDeviceInfo, SSID,
 };
+use crate::network_backend::{BackendFuture, SettingsChanged, WifiBackend};
 use crate::CaptivePortalError;
 use generated::*;
+use reasons::StateReasons;
+use virtual_interface::StationSync;
 use wifi_settings::{VariantMap, VariantMapNested};
 
 // Public API: AccessPointsChangedStream
 pub use access_points_changed::{ap_changed_stream, AccessPointChanged};
+pub use settings_changed::settings_changed_stream;
+pub use active_connections::ActiveConnectionState;
+
+/// The handle of a connection of this backend, see [`crate::ActiveConnection`]
+pub type ConnectionPath = dbus::Path<'static>;
 
 pub const NM_BUSNAME: &str = "org.freedesktop.NetworkManager";
 pub(crate) const NM_PATH: &str = "/org/freedesktop/NetworkManager";
@@ -44,32 +65,63 @@ pub(crate) const HOTSPOT_UUID: &str = "2b0d0f1d-b79d-43af-bde1-71744625642e";
 pub const SAVE_TO_DISK_FLAG: u32 = 0x01;
 pub const VOLATILE_FLAG: u32 = 0x8 | 0x10;
 pub const IN_MEMORY_ONLY: u32 = 0x8 | 0x20;
+pub const IN_MEMORY_FLAG: u32 = 0x02;
+pub const BLOCK_AUTOCONNECT_FLAG: u32 = 0x20;
+
+/// How long a router accepts WPS push-button enrollments after its button has been pushed
+pub const WPS_WALK_TIME: Duration = Duration::from_secs(120);
+/// How long wifi stays switched off while restarting the wifi stack, see [`NetworkBackend::restart_wifi`]
+const WIFI_RESTART_DELAY: Duration = Duration::from_secs(2);
 
 #[derive(Clone)]
 pub struct NetworkBackend {
     exit_handler: Arc<Mutex<Option<tokio::sync::oneshot::Sender<()>>>>,
-    pub(crate) conn: Arc<SyncConnection>,
+    pub(crate) conn: Arc<DbusConnection>,
     /// The wifi device. Will always be set, because the service quits if it didn't find a wifi device.
     pub(crate) wifi_device_path: dbus::Path<'static>,
     /// Mac address of the own network interface
     hw: String,
     /// Network interface name
     interface_name: String,
+    /// Do not persist new connections to disk
+    in_memory: bool,
+    /// Protected management frames of new connections and the hotspot
+    pmf: Pmf,
+    /// The key management of the hotspot
+    hotspot_security: HotspotSecurity,
+    /// Message key of the reason why the last connection attempt failed, see [`crate::i18n`]
+    last_failure: Arc<Mutex<Option<&'static str>>>,
+    /// The virtual station interface for connection attempts while the hotspot is up, see [`virtual_interface`]
+    station: StationSync,
+    /// The wifi device does not support access point mode. The hotspot is an ad-hoc network.
+    adhoc: bool,
+    /// The WPS secret agent has been registered, see [`wps_agent`]
+    wps_agent: Arc<Mutex<bool>>,
 }
 
 impl NetworkBackend {
     /// Create a new connection to the network manager. This will also try to enable networking
     /// and wifi. Returns a network manager instance or an error if no wifi device can be found.
     pub async fn new(interface_name: &Option<String>) -> Result<NetworkBackend, CaptivePortalError> {
+        Self::connect(interface_name, true).await
+    }
+
+    /// Like [`NetworkBackend::new`], but also accepts a wifi device without access point mode.
+    /// The hotspot is an open ad-hoc (IBSS) network on such a device, see [`NetworkBackend::hotspot_start`].
+    pub async fn new_adhoc(interface_name: &Option<String>) -> Result<NetworkBackend, CaptivePortalError> {
+        Self::connect(interface_name, false).await
+    }
+
+    async fn connect(interface_name: &Option<String>, require_ap: bool) -> Result<NetworkBackend, CaptivePortalError> {
         // Prepare an exit handler
         let (exit_handler, exit_receiver) = tokio::sync::oneshot::channel::<()>();
 
         // Connect to the D-Bus session bus (this is blocking, unfortunately).
-        let (resource, conn) = dbus_tokio::new_system_sync()?;
+        let (resource, conn) = dbus_tokio::new_system_recorded()?;
 
-        // The resource is a task that should be spawned onto a tokio compatible
-        // reactor ASAP. If the resource ever finishes, you lost connection to D-Bus.
-        tokio::spawn(async move {
+        // The resource is a task that should be spawned onto the async
+        // runtime ASAP. If the resource ever finishes, you lost connection to D-Bus.
+        runtime::spawn(async move {
             use futures_util::future::select;
             use futures_util::future::Either;
             use pin_utils::pin_mut;
@@ -82,27 +134,75 @@ impl NetworkBackend {
             }
         });
 
-        let wifi_device = find_wifi_device::find_wifi_device(conn.clone(), interface_name).await?;
+        let wifi_device = find_wifi_device::find_wifi_device(conn.clone(), interface_name, require_ap).await?;
         Ok(NetworkBackend {
             exit_handler: Arc::new(Mutex::new(Some(exit_handler))),
             conn,
             interface_name: wifi_device.interface_name,
             hw: wifi_device.hw,
             wifi_device_path: wifi_device.device_path,
+            in_memory: false,
+            pmf: Pmf::Auto,
+            hotspot_security: HotspotSecurity::Wpa2,
+            last_failure: Arc::new(Mutex::new(None)),
+            station: Arc::new(Mutex::new(None)),
+            adhoc: !wifi_device.ap,
+            wps_agent: Arc::new(Mutex::new(false)),
         })
     }
 
+    /// Store new and updated connections in memory only instead of saving them to disk.
+    pub fn set_in_memory(&mut self, in_memory: bool) {
+        self.in_memory = in_memory;
+    }
+
+    /// Protected management frames (802.11w) setting of new connections and the hotspot
+    pub fn set_pmf(&mut self, pmf: Pmf) {
+        self.pmf = pmf;
+    }
+
+    /// The key management of the hotspot. WPA3 requires SAE support of the wifi device,
+    /// see [`NetworkBackend::hotspot_start`].
+    pub fn set_hotspot_security(&mut self, security: HotspotSecurity) {
+        self.hotspot_security = security;
+    }
+
+    /// NetworkManager runs one access point per device. Requesting an OWE transition mode pair fails.
+    pub fn set_owe_transition(&mut self, owe_transition: bool) -> Result<(), CaptivePortalError> {
+        match owe_transition {
+            true => Err(CaptivePortalError::Generic(
+                "The NetworkManager backend cannot offer an OWE transition mode pair (--hotspot-owe)".to_owned(),
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// The NetworkManager access point has no client limit. The dhcp server limits the clients instead.
+    pub fn set_max_clients(&mut self, max_clients: Option<u32>) {
+        if max_clients.is_some() {
+            info!("The NetworkManager backend cannot limit the hotspot clients. Only the dhcp leases are limited.");
+        }
+    }
+
+    /// The NetworkManager access point has no MAC filter. The dhcp server rejects the clients instead.
+    pub fn set_mac_filter(&mut self, mac_filter: MacFilterSync) {
+        if !mac_filter.read().expect("mac filter lock").is_empty() {
+            info!("The NetworkManager backend cannot filter the hotspot clients. Only the dhcp leases are filtered.");
+        }
+    }
+
     /// Network might be disabled or "unmanaged". This method tries to enable networking and wifi.
     pub async fn enable_networking_and_wifi(&self) -> Result<(), CaptivePortalError> {
         let p = nonblock::Proxy::new(NM_BUSNAME, NM_PATH, self.conn.clone());
         use networkmanager::NetworkManager;
-        if !p.networking_enabled().await? {
+        let properties = properties::get_all::<properties::ManagerProperties>(self.conn.clone(), NM_PATH).await?;
+        if !properties.networking_enabled {
             p.enable(true).await?;
         }
-        if !p.wireless_enabled().await? {
+        if !properties.wireless_enabled {
             p.set_wireless_enabled(true).await?;
         }
-        if p.connectivity_check_available().await? {
+        if properties.connectivity_check_available {
             p.set_connectivity_check_enabled(true).await?;
         }
         Ok(())
@@ -110,19 +210,31 @@ impl NetworkBackend {
 
     /// Scan for access points if the last scan is older than 10 seconds
     pub async fn scan_networks(&self) -> Result<(), CaptivePortalError> {
+        self.request_scan(Vec::new()).await
+    }
+
+    /// Requests a scan. The given SSIDs are probed for, which finds access points of hidden networks.
+    async fn request_scan(&self, ssids: Vec<Vec<u8>>) -> Result<(), CaptivePortalError> {
         use generated::device::DeviceWireless;
-        let p = nonblock::Proxy::new(NM_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        let p = nonblock::Proxy::new(NM_BUSNAME, self.station_device_path(), self.conn.clone());
 
         // request_scan requires a hashmap of dbus::arg::RefArg parameters as argument.
         // Those are not thread safe, eg implement Send, so cannot be wrapped as intermediate state in the
         // async state machine. A function scope helps out here.
-        fn scan_networks(p: dbus::nonblock::Proxy<Arc<SyncConnection>>) -> dbus::nonblock::MethodReply<()> {
-            p.request_scan(HashMap::new())
+        fn scan_networks(
+            p: dbus::nonblock::Proxy<Arc<DbusConnection>>,
+            ssids: Vec<Vec<u8>>,
+        ) -> dbus::nonblock::MethodReply<()> {
+            let mut options: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+            if !ssids.is_empty() {
+                options.insert("ssids", Variant(Box::new(ssids)));
+            }
+            p.request_scan(options)
         }
 
         // There is one error that we can expect by calling this method:
         // org.freedesktop.NetworkManager.Device.NotAllowed - Scanning not allowed while already scanning
-        if let Err(e) = scan_networks(p).await {
+        if let Err(e) = scan_networks(p, ssids).await {
             if let Some(name) = e.name() {
                 // All good
                 if name == "org.freedesktop.NetworkManager.Device.NotAllowed" {
@@ -172,6 +284,35 @@ impl NetworkBackend {
         }
     }
 
+    /// Activates the saved connections of the networks in range one after another, the highest priority first,
+    /// see [`NetworkBackend::set_priority`]. Waits up to the timeout for each of them.
+    /// Network manager only retries a failed connection a few times on its own.
+    ///
+    /// Returns true if one of them connected.
+    pub async fn connect_saved(&self, timeout: Duration) -> Result<bool, CaptivePortalError> {
+        let in_range = self.list_access_points(Duration::from_secs(0)).await?;
+        for (connection_path, settings) in self.saved_connections().await? {
+            if !in_range.iter().any(|ap| ap.ssid == settings.ssid) {
+                continue;
+            }
+            info!("Trying the saved network {} (priority {})", settings.ssid, settings.priority);
+            use networkmanager::NetworkManager;
+            let p = nonblock::Proxy::new(NM_BUSNAME, NM_PATH, self.conn.clone());
+            if let Err(e) = p.activate_connection(connection_path, self.station_device_path(), "/".into()).await {
+                warn!("Failed to activate the saved network {}: {}", settings.ssid, e);
+                continue;
+            }
+            match self.wait_for_connectivity(false, timeout).await {
+                Ok(NetworkManagerState::Connected) | Ok(NetworkManagerState::ConnectedLimited) => return Ok(true),
+                Ok(_) | Err(CaptivePortalError::NotRequiredConnectivity(_)) => {
+                    info!("The saved network {} did not connect", settings.ssid)
+                },
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(false)
+    }
+
     /// Connect to the given SSID with the given credentials.
     /// First tries to find a wifi connection if "hw" is set or "overwrite_same_ssid_connection" is true.
     /// If it finds one, the connection will be altered to use the given credentials and SSID, otherwise a new connection is created.
@@ -186,34 +327,126 @@ impl NetworkBackend {
     /// * credentials: The connection credentials
     /// * hw: The target access point mac address. If this is set, this method will first try to find
     ///   a connection that was connected to that access point in the past and update that connection.
-    /// * overwrite_same_ssid_connection: If this is true and a connection can be found that matches the
+    /// * options.ipv4: A static address configuration. The address is requested via DHCP if this is not set.
+    /// * options.ipv6: The IPv6 method and static configuration. Network manager uses "auto" if this is not set.
+    /// * options.hidden: The network does not broadcast its SSID. The connection is marked as hidden and
+    ///   a directed scan for the SSID is requested, so that network manager finds the access point.
+    /// * options.overwrite_same_ssid_connection: If this is true and a connection can be found that matches the
     ///   given SSID, that connection will be updated.
     pub async fn connect_to(
         &self,
         ssid: SSID,
         credentials: AccessPointCredentials,
         hw: Option<String>,
-        overwrite_same_ssid_connection: bool,
+        options: ConnectOptions,
     ) -> Result<Option<ActiveConnection>, CaptivePortalError> {
-        // try to find connection, update it, activate it and return the connection path
-        let active_connection = if let Some(hw) = hw {
-            if let Some((connection_path, old_connection)) = self.find_connection_by_mac(&hw).await? {
-                Some(
-                    self.update_connection(connection_path, &ssid, old_connection, credentials.clone())
-                        .await?,
-                )
-            } else {
-                None
+        self.activate(ssid, Some(credentials), hw, options).await
+    }
+
+    /// Connects to the given network via WPS push-button, for users who do not know the passphrase.
+    /// The user has to push the WPS button of the router within [`WPS_WALK_TIME`]. The passphrase handed out
+    /// by the router is stored in a new connection. See [`wps_agent`] and [`NetworkBackend::connect_to`].
+    pub async fn connect_wps(
+        &self,
+        ssid: SSID,
+        hw: Option<String>,
+        ipv4: Option<StaticIpv4>,
+        ipv6: Option<Ipv6Config>,
+    ) -> Result<Option<ActiveConnection>, CaptivePortalError> {
+        {
+            let registered = *self.wps_agent.lock().expect("WPS agent mutex lock");
+            if !registered {
+                wps_agent::register(self.conn.clone()).await?;
+                *self.wps_agent.lock().expect("WPS agent mutex lock") = true;
             }
-        } else if overwrite_same_ssid_connection {
-            if let Some((connection_path, old_connection)) = self.find_connection_by_ssid(&ssid).await? {
-                Some(
-                    self.update_connection(connection_path, &ssid, old_connection, credentials.clone())
-                        .await?,
-                )
-            } else {
-                None
+        }
+        info!("Connecting to {} via WPS. Push the WPS button of the router now.", ssid);
+        let options = ConnectOptions { ipv4, ipv6, ..ConnectOptions::default() };
+        self.activate(ssid, None, hw, options).await
+    }
+
+    /// Enrolls via Wi-Fi Easy Connect with the configurator of the given URI, usually the router, and connects
+    /// to the handed out network. SSID and passphrase are stored in a new connection. See [`crate::dpp`].
+    pub async fn connect_dpp(
+        &self,
+        uri: &DppUri,
+        ipv4: Option<StaticIpv4>,
+        ipv6: Option<Ipv6Config>,
+    ) -> Result<Option<ActiveConnection>, CaptivePortalError> {
+        let interface_name = self.station_interface().unwrap_or_else(|| self.interface_name.clone());
+        let ctrl_dir = std::path::Path::new(dpp::DEFAULT_CTRL_DIR);
+        let configuration = dpp::enroll(ctrl_dir, &interface_name, uri, dpp::ENROLL_TIMEOUT).await?;
+        info!("Received the configuration of {} via Easy Connect", configuration.ssid);
+        let credentials = Some(configuration.credentials);
+        let options = ConnectOptions { ipv4, ipv6, hidden: false, overwrite_same_ssid_connection: true };
+        self.activate(configuration.ssid, credentials, None, options).await
+    }
+
+    /// The access points that advertise WPS push-button, strongest first
+    pub async fn wps_access_points(&self) -> Result<Vec<WifiConnection>, CaptivePortalError> {
+        use device::DeviceWireless;
+        let p = nonblock::Proxy::new(NM_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        let mut access_points = Vec::new();
+        for ap_path in p.get_access_points().await? {
+            let flags = properties::get_all::<properties::AccessPointProperties>(self.conn.clone(), ap_path.clone())
+                .await?
+                .flags;
+            if security::supports_wps_pbc(flags) {
+                access_points.push(self.access_point(ap_path).await?);
             }
+        }
+        access_points.retain(|ap| !ap.is_own);
+        access_points.sort_by(|a, b| b.strength.cmp(&a.strength));
+        Ok(access_points)
+    }
+
+    /// Adds or updates the connection and activates it, see [`NetworkBackend::connect_to`].
+    /// Without credentials, the connection enrolls via WPS push-button.
+    async fn activate(
+        &self,
+        ssid: SSID,
+        credentials: Option<AccessPointCredentials>,
+        hw: Option<String>,
+        options: ConnectOptions,
+    ) -> Result<Option<ActiveConnection>, CaptivePortalError> {
+        let wps = credentials.is_none();
+        let hidden = options.hidden;
+        if hidden {
+            self.request_scan(vec![ssid.as_bytes().to_vec()]).await?;
+        }
+        let ssid = match hw.as_ref() {
+            Some(hw) if !hidden => self.scanned_ssid(ssid, hw).await,
+            _ => ssid,
+        };
+
+        // try to find connection, update it, activate it and return the connection path
+        trace::begin(Phase::FindConnection);
+        // A WPS connection never updates an existing connection, the passphrase is not known yet
+        let existing_connection = if wps {
+            None
+        } else if let Some(hw) = hw {
+            self.find_connection_by_mac(&hw).await?
+        } else if options.overwrite_same_ssid_connection {
+            self.find_connection_by_ssid(&ssid).await?
+        } else {
+            None
+        };
+        trace::end(Phase::FindConnection);
+
+        trace::begin(Phase::Activate);
+        let active_connection = if let (Some((connection_path, old_connection)), Some(credentials)) =
+            (existing_connection, credentials.as_ref())
+        {
+            Some(
+                self.update_connection(
+                    connection_path,
+                    &ssid,
+                    old_connection,
+                    credentials.clone(),
+                    &options,
+                )
+                .await?,
+            )
         } else {
             None
         };
@@ -222,19 +455,36 @@ impl NetworkBackend {
         let (connection_path, active_connection) = if let Some(active_connection) = active_connection {
             active_connection
         } else {
-            let settings = wifi_settings::make_arguments_for_ap(&ssid, credentials, None)?;
+            let mut settings = wifi_settings::make_arguments_for_ap(
+                &ssid,
+                credentials.unwrap_or(AccessPointCredentials::None),
+                self.pmf,
+                options.ipv4.as_ref(),
+                options.ipv6.as_ref(),
+                hidden,
+                None,
+            )?;
+            if wps {
+                wifi_settings::make_wps(&mut settings);
+            }
             let options = wifi_settings::make_options_for_ap();
 
             // Create connection
             use networkmanager::NetworkManager;
             let p = nonblock::Proxy::new(NM_BUSNAME, NM_PATH, self.conn.clone());
             let (conn_path, active_connection, _) = p
-                .add_and_activate_connection2(settings, self.wifi_device_path.clone(), "/".into(), options)
+                .add_and_activate_connection2(settings, self.station_device_path(), "/".into(), options)
                 .await?;
             (conn_path, active_connection)
         };
+        trace::end(Phase::Activate);
+
+        // A rejected password is reported with the state changes, before the attempt is given up
+        let mut reasons =
+            StateReasons::subscribe(self.conn.clone(), &self.station_device_path(), &active_connection).await?;
 
         // Wait up to 5 seconds while in Deactivated
+        trace::begin(Phase::Associate);
         let state = self
             .wait_for_active_connection_state(
                 ConnectionState::Deactivated,
@@ -245,36 +495,48 @@ impl NetworkBackend {
             .await?;
         // Not successful
         if state == ConnectionState::Deactivated {
+            trace::fail(Phase::Associate);
+            self.record_failure(reasons.failure()).await;
             use connection_nm::Connection;
             let p = nonblock::Proxy::new(NM_BUSNAME, connection_path, self.conn.clone());
             p.delete().await?;
             return Ok(None);
         }
 
-        // Wait up to 30 seconds while in Activating
+        trace::end(Phase::Associate);
+
+        // Wait up to 30 seconds while in Activating, with WPS until the button has been pushed
+        trace::begin(Phase::Dhcp);
+        let timeout = match wps {
+            true => WPS_WALK_TIME + Duration::from_secs(30),
+            false => Duration::from_secs(30),
+        };
         let state = self
-            .wait_for_active_connection_state(
-                ConnectionState::Activated,
-                active_connection.clone(),
-                Duration::from_secs(30),
-                false,
-            )
+            .wait_for_active_connection_state(ConnectionState::Activated, active_connection.clone(), timeout, false)
             .await?;
 
-        // Remove connection if not successful. Store it permanently if successful
+        // Remove connection if not successful. Store it permanently (or in memory) if successful
         if state == ConnectionState::Activated {
+            trace::end(Phase::Dhcp);
             use connection_nm::Connection;
             let p = nonblock::Proxy::new(NM_BUSNAME, connection_path.clone(), self.conn.clone());
 
+            let flags = match self.in_memory {
+                true => IN_MEMORY_FLAG,
+                false => SAVE_TO_DISK_FLAG,
+            };
             // Settings: Provide an empty array, to use the current settings.
-            p.update2(VariantMapNested::new(), SAVE_TO_DISK_FLAG, VariantMap::new())
-                .await?;
+            trace::begin(Phase::Save);
+            p.update2(VariantMapNested::new(), flags, VariantMap::new()).await?;
+            trace::end(Phase::Save);
             return Ok(Some(ActiveConnection {
                 connection_path: connection_path.into_static(),
                 active_connection_path: active_connection.into_static(),
                 state,
             }));
         } else {
+            trace::fail(Phase::Dhcp);
+            self.record_failure(reasons.failure()).await;
             use connection_nm::Connection;
             let p = nonblock::Proxy::new(NM_BUSNAME, connection_path, self.conn.clone());
             p.delete().await?;
@@ -282,27 +544,225 @@ impl NetworkBackend {
         }
     }
 
+    /// Aborts a connection attempt that has been cancelled while [`connect_to`] was in progress:
+    /// Deactivates the active connection of the wifi device and deletes its profile,
+    /// if the profile has not been stored yet.
+    pub async fn abort_connect(&self) -> Result<(), CaptivePortalError> {
+        use device::Device;
+        let p = nonblock::Proxy::new(NM_BUSNAME, self.station_device_path(), self.conn.clone());
+        let active_connection = p.active_connection().await?;
+        if &*active_connection != "/" {
+            use connection_active::ConnectionActive;
+            let p = nonblock::Proxy::new(NM_BUSNAME, active_connection.clone(), self.conn.clone());
+            let connection_path = p.connection().await?;
+
+            use networkmanager::NetworkManager;
+            let p = nonblock::Proxy::new(NM_BUSNAME, NM_PATH, self.conn.clone());
+            p.deactivate_connection(active_connection).await?;
+
+            use connection_nm::Connection;
+            let p = nonblock::Proxy::new(NM_BUSNAME, connection_path, self.conn.clone());
+            if p.unsaved().await? {
+                p.delete().await?;
+            }
+        }
+        *self.last_failure.lock().expect("last failure mutex") = Some("cancelled");
+        Ok(())
+    }
+
+    /// Stores the reason for a failed connection attempt. Without a reason reported by the state changes
+    /// of the attempt, the state reason of the wifi device is used.
+    /// Must be called before the connection is deleted, which changes the reason.
+    async fn record_failure(&self, reported: Option<&'static str>) {
+        if let Some(reason) = reported {
+            info!("Connection failed: {}", reason);
+            *self.last_failure.lock().expect("last failure mutex") = Some(reason);
+            return;
+        }
+        use device::Device;
+        let p = nonblock::Proxy::new(NM_BUSNAME, self.station_device_path(), self.conn.clone());
+        let reason = match p.state_reason().await {
+            Ok((_state, reason)) => {
+                info!("Connection failed, device state reason {}", reason);
+                reasons::device_reason(reason)
+            },
+            Err(e) => {
+                warn!("Failed to read the device state reason: {}", e);
+                None
+            },
+        };
+        *self.last_failure.lock().expect("last failure mutex") = Some(reason.unwrap_or("connection_failed"));
+    }
+
+    /// Whether the backend assigns the address given to [`NetworkBackend::hotspot_start`].
+    /// Network manager assigns the hotspot address.
+    pub fn assigns_hotspot_address(&self) -> bool {
+        true
+    }
+
+    /// The message key of the reason why the last connection attempt failed, see [`crate::i18n`].
+    pub fn last_failure(&self) -> Option<&'static str> {
+        *self.last_failure.lock().expect("last failure mutex")
+    }
+
+    /// Reads the network manager version. Fails if network manager does not answer within the timeout.
+    /// See [`crate::watchdog`].
+    pub async fn ping(&self, timeout: Duration) -> Result<(), CaptivePortalError> {
+        use networkmanager::NetworkManager;
+        let p = nonblock::Proxy::new(NM_BUSNAME, NM_PATH, self.conn.clone());
+        match runtime::timeout(timeout, p.version()).await {
+            Some(version) => version.map(|_| ()).map_err(CaptivePortalError::from),
+            None => Err(CaptivePortalError::Generic("Network manager did not answer".to_owned())),
+        }
+    }
+
+    /// Asks systemd to restart the network manager service and waits up to `timeout` until network manager
+    /// is back on the bus. Returns false if it did not come back in time.
+    pub async fn restart_service(&self, timeout: Duration) -> Result<bool, CaptivePortalError> {
+        let old_owner = systemd::name_owner(self.conn.clone(), NM_BUSNAME).await;
+        let job = systemd::restart_unit(self.conn.clone(), systemd::NM_UNIT).await?;
+        debug!("Restarting {}: Job {}", systemd::NM_UNIT, job);
+        Ok(systemd::wait_for_new_owner(self.conn.clone(), NM_BUSNAME, old_owner.as_deref(), timeout).await)
+    }
+
+    /// Restarts the wifi stack: Disconnects the wifi device and switches wifi off and on again.
+    /// Network manager activates the best saved connection afterwards, if auto-connect is enabled.
+    pub async fn restart_wifi(&self) -> Result<(), CaptivePortalError> {
+        use device::Device;
+        let p = nonblock::Proxy::new(NM_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        // Fails if the device is not connected
+        if let Err(e) = p.disconnect().await {
+            debug!("Disconnect {}: {}", self.interface_name, e);
+        }
+        use networkmanager::NetworkManager;
+        let p = nonblock::Proxy::new(NM_BUSNAME, NM_PATH, self.conn.clone());
+        p.set_wireless_enabled(false).await?;
+        delay_for(WIFI_RESTART_DELAY).await;
+        p.set_wireless_enabled(true).await?;
+        info!("Wifi restarted on {}", self.interface_name);
+        Ok(())
+    }
+
+    /// Asks logind to reboot the device. Returns as soon as the reboot has been scheduled.
+    pub async fn reboot(&self) -> Result<(), CaptivePortalError> {
+        systemd::reboot(self.conn.clone()).await
+    }
+
+    /// The name of the wifi network interface
+    pub fn interface_name(&self) -> &str {
+        &self.interface_name
+    }
+
+    /// Driver, firmware and wireless capabilities of the wifi device
+    pub async fn device_info(&self) -> Result<WifiDeviceInfo, CaptivePortalError> {
+        use properties::{get_all, DeviceProperties};
+        let device = get_all::<DeviceProperties>(self.conn.clone(), self.wifi_device_path.clone()).await?;
+        use device::DeviceWireless;
+        let p = nonblock::Proxy::new(NM_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        let capabilities = find_wifi_device::wireless_capabilities(p.wireless_capabilities().await?);
+        Ok(WifiDeviceInfo {
+            driver: device.driver,
+            driver_version: device.driver_version,
+            firmware_version: device.firmware_version,
+            capabilities,
+        })
+    }
+
+    /// The SSID of the active access point. None if the wifi device is not connected.
+    pub async fn active_ssid(&self) -> Result<Option<SSID>, CaptivePortalError> {
+        use device::DeviceWireless;
+        let p = nonblock::Proxy::new(NM_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        let ap_path = p.active_access_point().await?;
+        if &*ap_path == "/" {
+            return Ok(None);
+        }
+        let ap = properties::get_all::<properties::AccessPointProperties>(self.conn.clone(), ap_path).await?;
+        Ok(Some(SSID::from(ap.ssid)))
+    }
+
+    /// The signal strength of the active access point and the bitrate of the wifi device.
+    /// The gateway round trip time is not measured by the backend, see [`crate::quality`].
+    pub async fn connection_quality(&self) -> Result<ConnectionQuality, CaptivePortalError> {
+        use device::DeviceWireless;
+        let p = nonblock::Proxy::new(NM_BUSNAME, self.station_device_path(), self.conn.clone());
+        let bitrate = p.bitrate().await?;
+        let ap_path = p.active_access_point().await?;
+        let signal = match &*ap_path {
+            "/" => None,
+            _ => Some(
+                properties::get_all::<properties::AccessPointProperties>(self.conn.clone(), ap_path)
+                    .await?
+                    .strength,
+            ),
+        };
+        Ok(ConnectionQuality {
+            signal,
+            bitrate: Some(bitrate),
+            gateway_rtt: None,
+        })
+    }
+
+    /// Deletes the stored connection for the given SSID. Returns false if there is none.
+    pub async fn forget(&self, ssid: &SSID) -> Result<bool, CaptivePortalError> {
+        match self.find_connection_by_ssid(ssid).await? {
+            Some((connection_path, _)) => {
+                use connection_nm::Connection;
+                let p = nonblock::Proxy::new(NM_BUSNAME, connection_path, self.conn.clone());
+                p.delete().await?;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// The exact SSID of the scanned access point with the given hardware address, if it reads like the
+    /// requested one. The requested SSID went through the portal form and may have been re-encoded or trimmed.
+    async fn scanned_ssid(&self, ssid: SSID, hw: &str) -> SSID {
+        use device::DeviceWireless;
+        let p = nonblock::Proxy::new(NM_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        let paths = match p.get_access_points().await {
+            Ok(paths) => paths,
+            Err(e) => {
+                warn!("Failed to list the access points: {}", e);
+                return ssid;
+            },
+        };
+        for path in paths {
+            let ap = match properties::get_all::<properties::AccessPointProperties>(self.conn.clone(), path).await {
+                Ok(ap) => ap,
+                Err(_) => continue,
+            };
+            if !ap.hw_address.eq_ignore_ascii_case(hw) {
+                continue;
+            }
+            let scanned = SSID::from(ap.ssid);
+            if scanned != ssid && scanned.same_text(&ssid) {
+                info!("Using the scanned SSID {:?} of {} instead of {:?}", scanned, hw, ssid);
+                return scanned;
+            }
+            break;
+        }
+        ssid
+    }
+
     /// Get access point data for the given access point network manager dbus path.
     pub async fn access_point<'b, P: Into<dbus::Path<'b>>>(
         &self,
         ap_path: P,
     ) -> Result<WifiConnection, CaptivePortalError> {
-        let ap_path = ap_path.into();
-        let security = security::get_access_point_security(self.conn.clone(), &ap_path)
-            .await?
-            .as_str();
-        let access_point_data = nonblock::Proxy::new(NM_BUSNAME, ap_path, self.conn.clone());
-        use access_point::AccessPoint;
-        let hw = access_point_data.hw_address().await?;
-        let ssid = String::from_utf8(access_point_data.ssid().await?)?;
+        let ap = properties::get_all::<properties::AccessPointProperties>(self.conn.clone(), ap_path).await?;
+        let security = security::access_point_security(ap.flags, ap.wpa_flags, ap.rsn_flags).as_str();
+        let hw = ap.hw_address;
+        let ssid = SSID::from(ap.ssid);
 
         let wifi_connection = WifiConnection {
             is_own: hw == self.hw,
+            vendor: crate::oui::vendor(&hw),
             ssid,
             hw,
             security,
-            strength: access_point_data.strength().await?,
-            frequency: access_point_data.frequency().await?,
+            strength: ap.strength,
+            frequency: ap.frequency,
         };
         if !wifi_connection.is_own {
             info!("Found AP {:?}", &wifi_connection.ssid);
@@ -355,3 +815,95 @@ impl NetworkBackend {
         Ok(connections)
     }
 }
+
+/// NetworkManager offers all optional features of the trait
+impl WifiBackend for NetworkBackend {
+    wifi_backend_methods!();
+
+    fn new_adhoc(interface_name: &Option<String>) -> BackendFuture<'_, Self> {
+        Box::pin(NetworkBackend::new_adhoc(interface_name))
+    }
+
+    fn connect_saved(&self, timeout: Duration) -> BackendFuture<'_, bool> {
+        Box::pin(NetworkBackend::connect_saved(self, timeout))
+    }
+
+    fn connect_wps(
+        &self,
+        ssid: SSID,
+        hw: Option<String>,
+        ipv4: Option<StaticIpv4>,
+        ipv6: Option<Ipv6Config>,
+    ) -> BackendFuture<'_, Option<ActiveConnection>> {
+        Box::pin(NetworkBackend::connect_wps(self, ssid, hw, ipv4, ipv6))
+    }
+
+    fn connect_dpp<'a>(
+        &'a self,
+        uri: &'a DppUri,
+        ipv4: Option<StaticIpv4>,
+        ipv6: Option<Ipv6Config>,
+    ) -> BackendFuture<'a, Option<ActiveConnection>> {
+        Box::pin(NetworkBackend::connect_dpp(self, uri, ipv4, ipv6))
+    }
+
+    fn wps_access_points(&self) -> BackendFuture<'_, Vec<WifiConnection>> {
+        Box::pin(NetworkBackend::wps_access_points(self))
+    }
+
+    fn set_priority<'a>(&'a self, ssid: &'a SSID, priority: i32) -> BackendFuture<'a, bool> {
+        Box::pin(NetworkBackend::set_priority(self, ssid, priority))
+    }
+
+    fn saved_ssids(&self) -> BackendFuture<'_, Vec<SSID>> {
+        Box::pin(NetworkBackend::saved_ssids(self))
+    }
+
+    fn saved_secret<'a>(&'a self, ssid: &'a SSID) -> BackendFuture<'a, Option<String>> {
+        Box::pin(NetworkBackend::saved_secret(self, ssid))
+    }
+
+    fn settings_changed_stream(&self) -> BackendFuture<'_, BoxStream<'static, SettingsChanged>> {
+        Box::pin(settings_changed_stream(self))
+    }
+
+    fn device_info(&self) -> BackendFuture<'_, WifiDeviceInfo> {
+        Box::pin(NetworkBackend::device_info(self))
+    }
+
+    fn ping(&self, timeout: Duration) -> BackendFuture<'_, ()> {
+        Box::pin(NetworkBackend::ping(self, timeout))
+    }
+
+    fn restart_service(&self, timeout: Duration) -> BackendFuture<'_, bool> {
+        Box::pin(NetworkBackend::restart_service(self, timeout))
+    }
+
+    fn restart_wifi(&self) -> BackendFuture<'_, ()> {
+        Box::pin(NetworkBackend::restart_wifi(self))
+    }
+
+    fn reboot(&self) -> BackendFuture<'_, ()> {
+        Box::pin(NetworkBackend::reboot(self))
+    }
+
+    fn set_station_adapter<'a>(&'a mut self, interface_name: &'a str) -> BackendFuture<'a, ()> {
+        Box::pin(NetworkBackend::set_station_adapter(self, interface_name))
+    }
+
+    fn station_interface_add(&self) -> BackendFuture<'_, Option<String>> {
+        Box::pin(NetworkBackend::station_interface_add(self))
+    }
+
+    fn station_interface(&self) -> Option<String> {
+        NetworkBackend::station_interface(self)
+    }
+
+    fn station_interface_remove(&self) -> BackendFuture<'_, ()> {
+        Box::pin(NetworkBackend::station_interface_remove(self))
+    }
+
+    fn station_handover(&self, timeout: Duration) -> BackendFuture<'_, bool> {
+        Box::pin(NetworkBackend::station_handover(self, timeout))
+    }
+}
@@ -0,0 +1,144 @@
+//! # Batched property reads
+//! `org.freedesktop.DBus.Properties.GetAll` returns all properties of an interface with one round trip,
+//! instead of one round trip per property getter of the generated bindings.
+//!
+//! The returned dictionary is not thread safe (RefArg). It is converted into a typed struct
+//! before the reply future resolves, so that no RefArg lives across an await point.
+
+use super::NM_BUSNAME;
+use crate::dbus_tokio::DbusConnection;
+use dbus::arg::{RefArg, Variant};
+use dbus::nonblock;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+type PropertyMap = HashMap<String, Variant<Box<dyn RefArg + 'static>>>;
+
+/// A typed view on the properties of a dbus interface
+pub(crate) trait FromProperties: Sized {
+    const INTERFACE: &'static str;
+    fn from_properties(map: &PropertyMap) -> Result<Self, dbus::Error>;
+}
+
+/// Reads all properties of the interface `T::INTERFACE` of the given object.
+pub(crate) fn get_all<'a, T: FromProperties + Send + Sync + 'static>(
+    conn: Arc<DbusConnection>,
+    path: impl Into<dbus::Path<'a>>,
+) -> nonblock::MethodReply<T> {
+    let p = nonblock::Proxy::new(NM_BUSNAME, path, conn);
+    p.method_call("org.freedesktop.DBus.Properties", "GetAll", (T::INTERFACE,))
+        .and_then(|r: (PropertyMap,)| T::from_properties(&r.0))
+}
+
+fn value<'a>(map: &'a PropertyMap, key: &str) -> Result<&'a (dyn RefArg + 'static), dbus::Error> {
+    map.get(key)
+        .map(|v| &*v.0)
+        .ok_or_else(|| dbus::Error::new_failed(&format!("Property {} missing", key)))
+}
+
+fn number(map: &PropertyMap, key: &str) -> Result<u64, dbus::Error> {
+    value(map, key)?
+        .as_u64()
+        .ok_or_else(|| dbus::Error::new_failed(&format!("Property {} is not a number", key)))
+}
+
+fn string(map: &PropertyMap, key: &str) -> Result<String, dbus::Error> {
+    value(map, key)?
+        .as_str()
+        .map(|v| v.to_owned())
+        .ok_or_else(|| dbus::Error::new_failed(&format!("Property {} is not a string", key)))
+}
+
+fn bytes(map: &PropertyMap, key: &str) -> Result<Vec<u8>, dbus::Error> {
+    value(map, key)?
+        .as_iter()
+        .map(|i| i.filter_map(|b| b.as_u64()).map(|b| b as u8).collect())
+        .ok_or_else(|| dbus::Error::new_failed(&format!("Property {} is not an array", key)))
+}
+
+pub(crate) struct AccessPointProperties {
+    pub flags: u32,
+    pub wpa_flags: u32,
+    pub rsn_flags: u32,
+    pub ssid: Vec<u8>,
+    pub frequency: u32,
+    pub hw_address: String,
+    pub strength: u8,
+}
+
+impl FromProperties for AccessPointProperties {
+    const INTERFACE: &'static str = "org.freedesktop.NetworkManager.AccessPoint";
+
+    fn from_properties(map: &PropertyMap) -> Result<Self, dbus::Error> {
+        Ok(AccessPointProperties {
+            flags: number(map, "Flags")? as u32,
+            wpa_flags: number(map, "WpaFlags")? as u32,
+            rsn_flags: number(map, "RsnFlags")? as u32,
+            ssid: bytes(map, "Ssid")?,
+            frequency: number(map, "Frequency")? as u32,
+            hw_address: string(map, "HwAddress")?,
+            strength: number(map, "Strength")? as u8,
+        })
+    }
+}
+
+pub(crate) struct DeviceProperties {
+    pub device_type: u32,
+    pub interface: String,
+    /// Empty if unknown, like the versions
+    pub driver: String,
+    pub driver_version: String,
+    pub firmware_version: String,
+}
+
+impl FromProperties for DeviceProperties {
+    const INTERFACE: &'static str = "org.freedesktop.NetworkManager.Device";
+
+    fn from_properties(map: &PropertyMap) -> Result<Self, dbus::Error> {
+        Ok(DeviceProperties {
+            device_type: number(map, "DeviceType")? as u32,
+            interface: string(map, "Interface")?,
+            driver: string(map, "Driver").unwrap_or_default(),
+            driver_version: string(map, "DriverVersion").unwrap_or_default(),
+            firmware_version: string(map, "FirmwareVersion").unwrap_or_default(),
+        })
+    }
+}
+
+pub(crate) struct ManagerProperties {
+    pub networking_enabled: bool,
+    pub wireless_enabled: bool,
+    pub connectivity_check_available: bool,
+}
+
+impl FromProperties for ManagerProperties {
+    const INTERFACE: &'static str = "org.freedesktop.NetworkManager";
+
+    fn from_properties(map: &PropertyMap) -> Result<Self, dbus::Error> {
+        Ok(ManagerProperties {
+            networking_enabled: number(map, "NetworkingEnabled")? != 0,
+            wireless_enabled: number(map, "WirelessEnabled")? != 0,
+            // Since network manager 1.10
+            connectivity_check_available: number(map, "ConnectivityCheckAvailable").unwrap_or(0) != 0,
+        })
+    }
+}
+
+pub(crate) struct ActiveConnectionProperties {
+    pub id: String,
+    /// The connection type, like "802-11-wireless" or "802-3-ethernet"
+    pub connection_type: String,
+    pub state: u32,
+}
+
+impl FromProperties for ActiveConnectionProperties {
+    const INTERFACE: &'static str = "org.freedesktop.NetworkManager.Connection.Active";
+
+    fn from_properties(map: &PropertyMap) -> Result<Self, dbus::Error> {
+        Ok(ActiveConnectionProperties {
+            id: string(map, "Id")?,
+            connection_type: string(map, "Type")?,
+            state: number(map, "State")? as u32,
+        })
+    }
+}
@@ -0,0 +1,161 @@
+//! # Failure reasons
+//! Translates network manager device and active connection state reasons into message keys of [`crate::i18n`].
+//! Reasons of other device types (modems, bluetooth, bridges) are reported as "connection_failed".
+//!
+//! The reasons of a connection attempt are collected from the state change signals while the attempt runs,
+//! see [`StateReasons`]. The device state reason that can be read after a failure has often been reset
+//! already, for example after network manager gave up asking for the secrets of a rejected password.
+
+use super::connection_active::ConnectionActiveStateChanged;
+use super::signals::DeviceStateChanged;
+use crate::dbus_tokio::{DbusConnection, SignalStream};
+use futures_util::{FutureExt, StreamExt};
+use std::sync::Arc;
+
+/// NMDeviceStateReason
+pub fn device_reason(reason: u32) -> Option<&'static str> {
+    Some(match reason {
+        // NONE, UNKNOWN
+        0 | 1 => return None,
+        // NOW_MANAGED, NOW_UNMANAGED, FIRMWARE_MISSING, REMOVED, SLEEPING, SUPPLICANT_AVAILABLE
+        2 | 3 | 35 | 36 | 37 | 42 => "device_unavailable",
+        // CONFIG_FAILED, SUPPLICANT_CONFIG_FAILED, IP_METHOD_UNSUPPORTED
+        4 | 9 | 65 => "invalid_settings",
+        // IP_CONFIG_UNAVAILABLE, IP_CONFIG_EXPIRED, DHCP_START_FAILED, DHCP_ERROR, DHCP_FAILED,
+        // AUTOIP_START_FAILED, AUTOIP_ERROR, AUTOIP_FAILED
+        5 | 6 | 15 | 16 | 17 | 20 | 21 | 22 => "no_ip_address",
+        // NO_SECRETS, SUPPLICANT_DISCONNECT: The 4-way handshake failed
+        7 | 8 => "wrong_password",
+        // SUPPLICANT_FAILED
+        10 => "connection_failed",
+        // SUPPLICANT_TIMEOUT
+        11 => "auth_timeout",
+        // CONNECTION_REMOVED, USER_REQUESTED, CONNECTION_ASSUMED, NEW_ACTIVATION
+        38 | 39 | 41 | 60 => "cancelled",
+        // SSID_NOT_FOUND, PEER_NOT_FOUND
+        53 | 67 => "network_not_found",
+        // IP_ADDRESS_DUPLICATE
+        64 => "ip_address_conflict",
+        // PPP, modem, GSM, bluetooth, infiniband, bridge, team, OVS, SR-IOV and dependency failures
+        12..=14 | 18 | 19 | 23..=34 | 40 | 43..=52 | 54..=59 | 61..=63 | 66 => "connection_failed",
+        _ => "connection_failed",
+    })
+}
+
+/// NMActiveConnectionStateReason
+pub fn active_connection_reason(reason: u32) -> Option<&'static str> {
+    Some(match reason {
+        // UNKNOWN, NONE
+        0 | 1 => return None,
+        // USER_DISCONNECTED, CONNECTION_REMOVED
+        2 | 11 => "cancelled",
+        // DEVICE_DISCONNECTED, DEVICE_REALIZE_FAILED, DEVICE_REMOVED
+        3 | 13 | 14 => "device_unavailable",
+        // IP_CONFIG_INVALID
+        5 => "no_ip_address",
+        // CONNECT_TIMEOUT
+        6 => "auth_timeout",
+        // NO_SECRETS, LOGIN_FAILED
+        9 | 10 => "wrong_password",
+        // SERVICE_STOPPED, SERVICE_START_TIMEOUT, SERVICE_START_FAILED, DEPENDENCY_FAILED
+        4 | 7 | 8 | 12 => "connection_failed",
+        _ => "connection_failed",
+    })
+}
+
+/// The message key of the most specific reason of the reported state changes.
+/// A rejected password wins, then the last device reason, then the last active connection reason.
+fn most_specific(device: &[u32], active_connection: &[u32]) -> Option<&'static str> {
+    let device = device.iter().filter_map(|r| device_reason(*r));
+    let active_connection = active_connection.iter().filter_map(|r| active_connection_reason(*r));
+    let reasons: Vec<&'static str> = device.rev().chain(active_connection.rev()).collect();
+    reasons
+        .iter()
+        .find(|r| **r == "wrong_password")
+        .or_else(|| reasons.first())
+        .copied()
+}
+
+/// Correlates an unspecific reason with the last device state that an attempt reached:
+/// NM_DEVICE_STATE_NEED_AUTH (60) is the handshake, NM_DEVICE_STATE_IP_CONFIG (70) and later the ip configuration.
+fn with_device_state(reason: Option<&'static str>, reached: Option<u32>) -> Option<&'static str> {
+    match (reason, reached) {
+        (None, Some(60)) | (Some("connection_failed"), Some(60)) => Some("auth_timeout"),
+        (None, Some(70..=90)) | (Some("connection_failed"), Some(70..=90)) => Some("no_ip_address"),
+        _ => reason,
+    }
+}
+
+/// Subscribes to the state changes of a connection attempt. The signals are queued until the attempt
+/// failed and [`StateReasons::failure`] is asked for the reason.
+pub(crate) struct StateReasons {
+    device: SignalStream<DeviceStateChanged>,
+    active_connection: SignalStream<ConnectionActiveStateChanged>,
+}
+
+impl StateReasons {
+    pub async fn subscribe(
+        conn: Arc<DbusConnection>,
+        device: &dbus::Path<'_>,
+        active_connection: &dbus::Path<'_>,
+    ) -> Result<Self, dbus::Error> {
+        Ok(StateReasons {
+            device: SignalStream::prop_new(device, conn.clone()).await?,
+            active_connection: SignalStream::prop_new(active_connection, conn).await?,
+        })
+    }
+
+    /// The message key of the most specific failure reason reported since the subscription
+    pub fn failure(&mut self) -> Option<&'static str> {
+        let mut device = Vec::new();
+        let mut reached = None;
+        while let Some(Some((value, _path))) = self.device.next().now_or_never() {
+            device.push(value.reason);
+            // Up to NM_DEVICE_STATE_ACTIVATED. Later states are failures and deactivations.
+            if value.new_state <= 100 {
+                reached = reached.max(Some(value.new_state));
+            }
+        }
+        let mut active_connection = Vec::new();
+        while let Some(Some((value, _path))) = self.active_connection.next().now_or_never() {
+            active_connection.push(value.reason);
+        }
+        debug!(
+            "State reasons: device {:?} (reached {:?}), active connection {:?}",
+            device, reached, active_connection
+        );
+        with_device_state(most_specific(&device, &active_connection), reached)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_translated() {
+        for reason in 0..70 {
+            for key in device_reason(reason).iter().chain(active_connection_reason(reason).iter()) {
+                assert!(crate::i18n::translate(key, "en").is_some(), "{}", key);
+            }
+        }
+        assert_eq!(device_reason(8), Some("wrong_password"));
+    }
+
+    #[test]
+    fn most_specific_reason() {
+        assert_eq!(most_specific(&[], &[]), None);
+        // SUPPLICANT_DISCONNECT and then NO_SECRETS, the device falls back to NONE
+        assert_eq!(most_specific(&[8, 7, 0], &[6]), Some("wrong_password"));
+        // SSID_NOT_FOUND before CONNECT_TIMEOUT
+        assert_eq!(most_specific(&[53], &[6]), Some("network_not_found"));
+        assert_eq!(most_specific(&[0], &[6]), Some("auth_timeout"));
+        // The active connection reports the rejected secrets only
+        assert_eq!(most_specific(&[11], &[9]), Some("wrong_password"));
+
+        assert_eq!(with_device_state(Some("connection_failed"), Some(60)), Some("auth_timeout"));
+        assert_eq!(with_device_state(None, Some(70)), Some("no_ip_address"));
+        assert_eq!(with_device_state(Some("wrong_password"), Some(70)), Some("wrong_password"));
+        assert_eq!(with_device_state(None, Some(50)), None);
+    }
+}
@@ -2,13 +2,9 @@
 //!
 //! This contains implementation specific bits only.
 
-use super::NM_BUSNAME;
-use dbus::nonblock;
-use dbus::nonblock::SyncConnection;
 use enumflags2::BitFlags;
 //use serde::{Deserialize, Serialize};
 use crate::Security;
-use std::sync::Arc;
 
 #[allow(non_camel_case_types)]
 #[derive(BitFlags, Copy, Clone)]
@@ -52,40 +48,74 @@ pub(crate) enum NM80211ApSecurityFlags {
     AP_SEC_KEY_MGMT_PSK = 0x0000_0100,
     // 802.1x authentication and key management is supported
     AP_SEC_KEY_MGMT_802_1X = 0x0000_0200,
+    // WPA/RSN Simultaneous Authentication of Equals is supported
+    AP_SEC_KEY_MGMT_SAE = 0x0000_0400,
 }
 
-// Returns the strongest supported encryption mode of an dbus access point path. The encryption mode depends on
-// quite a few flags and that's why it is encapsulated into its own method.
-pub(crate) async fn get_access_point_security(
-    conn: Arc<SyncConnection>,
-    ap_path: &dbus::Path<'_>,
-) -> Result<Security, super::CaptivePortalError> {
-    let access_point_data = nonblock::Proxy::new(NM_BUSNAME, ap_path, conn.clone());
-    use super::access_point::AccessPoint;
-    let flags: BitFlags<NM80211ApFlags> =
-        BitFlags::from_bits(access_point_data.flags().await?).unwrap_or(BitFlags::empty());
-    let wpa_flags: BitFlags<NM80211ApSecurityFlags> =
-        BitFlags::from_bits(access_point_data.wpa_flags().await?).unwrap_or(BitFlags::empty());
-    let rsn_flags: BitFlags<NM80211ApSecurityFlags> =
-        BitFlags::from_bits(access_point_data.rsn_flags().await?).unwrap_or(BitFlags::empty());
+// Returns the strongest supported encryption mode of an access point, given its "Flags", "WpaFlags" and "RsnFlags"
+// properties. The encryption mode depends on quite a few flags and that's why it is encapsulated into its own method.
+pub(crate) fn access_point_security(flags: u32, wpa_flags: u32, rsn_flags: u32) -> Security {
+    let flags: BitFlags<NM80211ApFlags> = BitFlags::from_bits(flags).unwrap_or(BitFlags::empty());
+    let wpa_flags: BitFlags<NM80211ApSecurityFlags> = BitFlags::from_bits(wpa_flags).unwrap_or(BitFlags::empty());
+    let rsn_flags: BitFlags<NM80211ApSecurityFlags> = BitFlags::from_bits(rsn_flags).unwrap_or(BitFlags::empty());
 
     if wpa_flags.contains(NM80211ApSecurityFlags::AP_SEC_KEY_MGMT_802_1X)
         || rsn_flags.contains(NM80211ApSecurityFlags::AP_SEC_KEY_MGMT_802_1X)
     {
-        return Ok(Security::ENTERPRISE);
+        return Security::ENTERPRISE;
+    }
+
+    // WPA3 only. Access points in WPA2/WPA3 transition mode also support PSK and are treated as WPA2.
+    if rsn_flags.contains(NM80211ApSecurityFlags::AP_SEC_KEY_MGMT_SAE)
+        && !rsn_flags.contains(NM80211ApSecurityFlags::AP_SEC_KEY_MGMT_PSK)
+    {
+        return Security::WPA3;
     }
 
     if !rsn_flags.is_empty() {
-        return Ok(Security::WPA2);
+        return Security::WPA2;
     }
 
     if !wpa_flags.is_empty() {
-        return Ok(Security::WPA);
+        return Security::WPA;
     }
 
     if flags.contains(NM80211ApFlags::AP_FLAGS_PRIVACY) && wpa_flags.is_empty() && rsn_flags.is_empty() {
-        return Ok(Security::WEP);
+        return Security::WEP;
     }
 
-    Ok(Security::NONE)
+    Security::NONE
+}
+
+/// The access point advertises WPS push-button configuration, given its "Flags" property
+pub(crate) fn supports_wps_pbc(flags: u32) -> bool {
+    let flags: BitFlags<NM80211ApFlags> = BitFlags::from_bits(flags).unwrap_or(BitFlags::empty());
+    flags.contains(NM80211ApFlags::AP_FLAGS_WPS_PBC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wpa3_access_points() {
+        let privacy = NM80211ApFlags::AP_FLAGS_PRIVACY as u32;
+        let ccmp = NM80211ApSecurityFlags::AP_SEC_PAIR_CCMP as u32 | NM80211ApSecurityFlags::AP_SEC_GROUP_CCMP as u32;
+        let sae = NM80211ApSecurityFlags::AP_SEC_KEY_MGMT_SAE as u32;
+        let psk = NM80211ApSecurityFlags::AP_SEC_KEY_MGMT_PSK as u32;
+        assert_eq!(access_point_security(privacy, 0, ccmp | sae).as_str(), "wpa3");
+        // Transition mode
+        assert_eq!(access_point_security(privacy, 0, ccmp | sae | psk).as_str(), "wpa");
+        assert_eq!(access_point_security(privacy, 0, 0).as_str(), "wep");
+    }
+
+    #[test]
+    fn wps_access_points() {
+        let wps = NM80211ApFlags::AP_FLAGS_WPS as u32;
+        let pbc = NM80211ApFlags::AP_FLAGS_WPS_PBC as u32;
+        let pin = NM80211ApFlags::AP_FLAGS_WPS_PIN as u32;
+        assert!(supports_wps_pbc(NM80211ApFlags::AP_FLAGS_PRIVACY as u32 | wps | pbc));
+        assert!(!supports_wps_pbc(wps | pin));
+        assert!(!supports_wps_pbc(0));
+    }
 }
@@ -0,0 +1,51 @@
+//! # Saved connections change stream
+//! Provides a stream of added and removed network manager connection profiles,
+//! for example profiles that an administrator adds or removes with nmcli.
+
+use dbus::message::SignalArgs;
+use futures_util::stream::select;
+use futures_util::stream::StreamExt;
+
+use super::connections::{SettingsConnectionRemoved, SettingsNewConnection};
+use crate::dbus_tokio::SignalStream;
+use crate::network_backend::{NetworkBackend, SettingsChanged, NM_BUSNAME, NM_SETTINGS_PATH};
+use crate::network_interface::WifiConnectionEventType;
+use crate::runtime::BoxStream;
+use crate::CaptivePortalError;
+
+fn helper_1(v: (SettingsNewConnection, String)) -> SettingsChanged {
+    SettingsChanged {
+        event: WifiConnectionEventType::Added,
+        path: v.0.connection.to_string(),
+    }
+}
+
+fn helper_2(v: (SettingsConnectionRemoved, String)) -> SettingsChanged {
+    SettingsChanged {
+        event: WifiConnectionEventType::Removed,
+        path: v.0.connection.to_string(),
+    }
+}
+
+pub async fn settings_changed_stream(
+    network_manager: &NetworkBackend,
+) -> Result<BoxStream<'static, SettingsChanged>, CaptivePortalError> {
+    let rule_added =
+        SettingsNewConnection::match_rule(Some(&NM_BUSNAME.to_owned().into()), Some(&NM_SETTINGS_PATH.into()))
+            .static_clone();
+
+    let rule_removed =
+        SettingsConnectionRemoved::match_rule(Some(&NM_BUSNAME.to_owned().into()), Some(&NM_SETTINGS_PATH.into()))
+            .static_clone();
+
+    let inner_stream_added = SignalStream::<SettingsNewConnection>::new(network_manager.conn.clone(), rule_added)
+        .await?
+        .map(helper_1);
+
+    let inner_stream_removed =
+        SignalStream::<SettingsConnectionRemoved>::new(network_manager.conn.clone(), rule_removed)
+            .await?
+            .map(helper_2);
+
+    Ok(Box::pin(select(inner_stream_added, inner_stream_removed)))
+}
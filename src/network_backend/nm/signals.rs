@@ -0,0 +1,119 @@
+//! # Typed network manager signals
+//! The generated bindings contain the signals of the network manager interfaces, for example
+//! `DeviceStateChanged` with the new state, old state and reason, and `DeviceWirelessAccessPointAdded`.
+//!
+//! Network manager reports property changes with the standard `org.freedesktop.DBus.Properties.PropertiesChanged`
+//! signal, which is not part of the introspection data that the bindings are generated from.
+//! The typed variants of that signal are written by hand here, see also [`crate::dbus_tokio::await_property`].
+
+use crate::dbus_tokio::PropertiesChanged;
+use dbus::arg;
+use dbus::message::SignalArgs;
+
+pub(crate) use super::device::{DeviceStateChanged, DeviceWirelessAccessPointAdded, DeviceWirelessAccessPointRemoved};
+
+pub(crate) const NM_INTERFACE: &str = "org.freedesktop.NetworkManager";
+pub(crate) const ACTIVE_CONNECTION_INTERFACE: &str = "org.freedesktop.NetworkManager.Connection.Active";
+
+/// NMConnectivityState: The result of the last connectivity check of network manager
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Connectivity {
+    Unknown,
+    /// Not connected to any network
+    None,
+    /// Connected, but a captive portal intercepts the connectivity check
+    Portal,
+    /// Connected, but the internet is not reachable
+    Limited,
+    Full,
+}
+
+impl From<u32> for Connectivity {
+    fn from(state: u32) -> Self {
+        match state {
+            1 => Connectivity::None,
+            2 => Connectivity::Portal,
+            3 => Connectivity::Limited,
+            4 => Connectivity::Full,
+            _ => Connectivity::Unknown,
+        }
+    }
+}
+
+/// The "Connectivity" property of network manager changed.
+///
+/// Reads a `PropertiesChanged` signal. The connectivity is None if the signal reports other properties only.
+/// Unlike [`PropertiesChanged`] this type is thread safe and can be used with a signal stream.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct ConnectivityChanged {
+    pub connectivity: Option<Connectivity>,
+}
+
+impl arg::ReadAll for ConnectivityChanged {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        let changed = <PropertiesChanged as arg::ReadAll>::read(i)?;
+        Ok(ConnectivityChanged {
+            connectivity: changed
+                .get(NM_INTERFACE, "Connectivity")
+                .and_then(|v| v.as_u64())
+                .map(|v| Connectivity::from(v as u32)),
+        })
+    }
+}
+
+impl SignalArgs for ConnectivityChanged {
+    const NAME: &'static str = PropertiesChanged::NAME;
+    const INTERFACE: &'static str = PropertiesChanged::INTERFACE;
+}
+
+/// The "ActiveConnections" property of network manager changed, for example because an ethernet cable
+/// has been plugged in. Reads a `PropertiesChanged` signal like [`ConnectivityChanged`].
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ActiveConnectionsChanged {
+    /// The paths of the active connections. None if the signal reports other properties only.
+    pub active_connections: Option<Vec<String>>,
+}
+
+impl arg::ReadAll for ActiveConnectionsChanged {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        let changed = <PropertiesChanged as arg::ReadAll>::read(i)?;
+        Ok(ActiveConnectionsChanged {
+            active_connections: changed
+                .get(NM_INTERFACE, "ActiveConnections")
+                .and_then(|v| v.as_iter())
+                .map(|paths| paths.filter_map(|p| p.as_str().map(|p| p.to_owned())).collect()),
+        })
+    }
+}
+
+impl SignalArgs for ActiveConnectionsChanged {
+    const NAME: &'static str = PropertiesChanged::NAME;
+    const INTERFACE: &'static str = PropertiesChanged::INTERFACE;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_backend::NM_PATH;
+    use dbus::arg::{ReadAll, RefArg, Variant};
+    use std::collections::HashMap;
+
+    fn signal(changed: HashMap<String, Variant<Box<dyn RefArg>>>, invalidated: Vec<String>) -> dbus::Message {
+        dbus::Message::new_signal(NM_PATH, PropertiesChanged::INTERFACE, PropertiesChanged::NAME)
+            .unwrap()
+            .append3(NM_INTERFACE, changed, invalidated)
+    }
+
+    #[test]
+    fn connectivity_changed() {
+        let mut changed: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        changed.insert("Connectivity".to_owned(), Variant(Box::new(2u32)));
+        let msg = signal(changed, Vec::new());
+        let value = ConnectivityChanged::read(&mut msg.iter_init()).unwrap();
+        assert_eq!(value.connectivity, Some(Connectivity::Portal));
+
+        let msg = signal(HashMap::new(), vec!["Connectivity".to_owned()]);
+        let value = ConnectivityChanged::read(&mut msg.iter_init()).unwrap();
+        assert_eq!(value.connectivity, None);
+    }
+}
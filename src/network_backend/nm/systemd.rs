@@ -0,0 +1,74 @@
+//! Restarts network manager via the systemd D-Bus API (org.freedesktop.systemd1), see [`crate::watchdog`],
+//! and reboots the device via logind (org.freedesktop.login1).
+//!
+//! "RestartUnit" only queues a job. Network manager is back as soon as its bus name is owned by
+//! another connection than before the restart.
+
+use crate::dbus_tokio::DbusConnection;
+use crate::runtime::delay_for;
+use crate::CaptivePortalError;
+use dbus::nonblock;
+use dbus::nonblock::stdintf::org_freedesktop_dbus::DBus;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const SYSTEMD_BUSNAME: &str = "org.freedesktop.systemd1";
+const SYSTEMD_PATH: &str = "/org/freedesktop/systemd1";
+const LOGIND_BUSNAME: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+
+/// The systemd unit of network manager
+pub(crate) const NM_UNIT: &str = "NetworkManager.service";
+
+/// How often the owner of the bus name is checked while waiting
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Queues a restart job for the given unit and returns the job path.
+/// A running job for the unit is replaced.
+pub(crate) async fn restart_unit(
+    conn: Arc<DbusConnection>,
+    unit: &str,
+) -> Result<dbus::Path<'static>, CaptivePortalError> {
+    let p = nonblock::Proxy::new(SYSTEMD_BUSNAME, SYSTEMD_PATH, conn);
+    let (job,): (dbus::Path<'static>,) = p
+        .method_call("org.freedesktop.systemd1.Manager", "RestartUnit", (unit, "replace"))
+        .await?;
+    Ok(job)
+}
+
+/// Asks logind to reboot. Not interactive: logind asks no polkit agent and fails if the service
+/// is not allowed to reboot.
+pub(crate) async fn reboot(conn: Arc<DbusConnection>) -> Result<(), CaptivePortalError> {
+    let p = nonblock::Proxy::new(LOGIND_BUSNAME, LOGIND_PATH, conn);
+    p.method_call::<(), _, _, _>("org.freedesktop.login1.Manager", "Reboot", (false,))
+        .await?;
+    Ok(())
+}
+
+/// The unique connection name that owns the given bus name. None if the name has no owner.
+pub(crate) async fn name_owner(conn: Arc<DbusConnection>, name: &str) -> Option<String> {
+    let p = nonblock::Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus", conn);
+    p.get_name_owner(name).await.ok()
+}
+
+/// Waits until the given bus name is owned by another connection than `old_owner`.
+/// Returns false if that does not happen within the timeout.
+pub(crate) async fn wait_for_new_owner(
+    conn: Arc<DbusConnection>,
+    name: &str,
+    old_owner: Option<&str>,
+    timeout: Duration,
+) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(owner) = name_owner(conn.clone(), name).await {
+            if Some(&owner[..]) != old_owner {
+                return true;
+            }
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        delay_for(POLL_INTERVAL).await;
+    }
+}
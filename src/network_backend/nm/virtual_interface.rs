@@ -0,0 +1,437 @@
+//! # Concurrent access point and station mode
+//! Some wifi adapters can run an access point and a station at the same time ("interface combinations").
+//! A second, virtual station interface is then added to the wifi device via nl80211, while the hotspot keeps
+//! running on the wifi interface. Network manager picks the new interface up as another wifi device and
+//! connection attempts use that device, so that the portal stays online during the attempt.
+//!
+//! After a successful attempt the connection is handed over to the wifi interface, see
+//! [`NetworkBackend::station_handover`].
+//!
+//! With a second wifi adapter, like a USB dongle, no virtual interface is needed: The adapter is dedicated to
+//! connection attempts and keeps its connection, see [`NetworkBackend::set_station_adapter`].
+
+use super::find_wifi_device::supports_ap;
+use super::{NetworkBackend, NM_BUSNAME, NM_PATH};
+use crate::netlink::{
+    attributes, family_id, generic_exchange, generic_request, interface_index, push_attribute, with_ack,
+};
+use crate::network_interface::ConnectionState;
+use crate::runtime::delay_for;
+use crate::CaptivePortalError;
+use dbus::nonblock;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const NL80211_CMD_GET_WIPHY: u8 = 1;
+const NL80211_CMD_NEW_INTERFACE: u8 = 7;
+const NL80211_CMD_DEL_INTERFACE: u8 = 8;
+
+const NL80211_ATTR_IFINDEX: u16 = 3;
+const NL80211_ATTR_IFNAME: u16 = 4;
+const NL80211_ATTR_IFTYPE: u16 = 5;
+const NL80211_ATTR_INTERFACE_COMBINATIONS: u16 = 120;
+
+const NL80211_IFACE_COMB_LIMITS: u16 = 1;
+const NL80211_IFACE_COMB_MAXNUM: u16 = 2;
+const NL80211_IFACE_COMB_NUM_CHANNELS: u16 = 4;
+const NL80211_IFACE_LIMIT_MAX: u16 = 1;
+const NL80211_IFACE_LIMIT_TYPES: u16 = 2;
+
+const NL80211_IFTYPE_STATION: u16 = 2;
+const NL80211_IFTYPE_AP: u16 = 3;
+
+/// The suffix of the station interface name. Interface names have at most 15 characters.
+const STATION_SUFFIX: &str = "sta";
+const IFNAME_MAX: usize = 15;
+
+/// How long to wait for network manager to report the added station interface
+const STATION_DEVICE_TIMEOUT: Duration = Duration::from_secs(5);
+const STATION_DEVICE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Interfaces of the given types, at most `max` of them
+#[derive(Debug, Clone, PartialEq, Default)]
+struct IfaceLimit {
+    max: u32,
+    types: Vec<u16>,
+}
+
+/// A combination of interfaces that the wifi device can run at the same time
+#[derive(Debug, Clone, PartialEq, Default)]
+struct IfaceCombination {
+    limits: Vec<IfaceLimit>,
+    max_interfaces: u32,
+    /// The number of different channels. With one channel the station must use the channel of the access point.
+    channels: u32,
+}
+
+impl IfaceCombination {
+    /// True if a station and an access point may run at the same time
+    fn ap_sta(&self) -> bool {
+        if self.max_interfaces < 2 {
+            return false;
+        }
+        let has = |limit: &IfaceLimit, kind: u16| limit.max > 0 && limit.types.contains(&kind);
+        self.limits.iter().enumerate().any(|(i, sta)| {
+            has(sta, NL80211_IFTYPE_STATION)
+                && self.limits.iter().enumerate().any(|(j, ap)| {
+                    has(ap, NL80211_IFTYPE_AP) && (i != j || ap.max >= 2)
+                })
+        })
+    }
+}
+
+fn read_u32(payload: &[u8]) -> u32 {
+    if payload.len() < 4 {
+        return 0;
+    }
+    u32::from_ne_bytes([payload[0], payload[1], payload[2], payload[3]])
+}
+
+/// Parses the interface combinations of a NL80211_CMD_GET_WIPHY reply
+fn parse_combinations(data: &[u8]) -> Vec<IfaceCombination> {
+    let mut combinations = Vec::new();
+    for (kind, payload) in attributes(data) {
+        if kind != NL80211_ATTR_INTERFACE_COMBINATIONS {
+            continue;
+        }
+        for (_, combination) in attributes(payload) {
+            let mut iface_combination = IfaceCombination::default();
+            for (kind, payload) in attributes(combination) {
+                match kind {
+                    NL80211_IFACE_COMB_LIMITS => {
+                        for (_, limit) in attributes(payload) {
+                            let mut iface_limit = IfaceLimit::default();
+                            for (kind, payload) in attributes(limit) {
+                                match kind {
+                                    NL80211_IFACE_LIMIT_MAX => iface_limit.max = read_u32(payload),
+                                    // Flag attributes, the attribute type is the interface type
+                                    NL80211_IFACE_LIMIT_TYPES => {
+                                        iface_limit.types = attributes(payload).into_iter().map(|(t, _)| t).collect()
+                                    },
+                                    _ => {},
+                                }
+                            }
+                            iface_combination.limits.push(iface_limit);
+                        }
+                    },
+                    NL80211_IFACE_COMB_MAXNUM => iface_combination.max_interfaces = read_u32(payload),
+                    NL80211_IFACE_COMB_NUM_CHANNELS => iface_combination.channels = read_u32(payload),
+                    _ => {},
+                }
+            }
+            combinations.push(iface_combination);
+        }
+    }
+    combinations
+}
+
+/// The name of the station interface of the given wifi interface, like "wlan0sta"
+fn station_name(interface_name: &str) -> String {
+    let prefix: String = interface_name.chars().take(IFNAME_MAX - STATION_SUFFIX.len()).collect();
+    format!("{}{}", prefix, STATION_SUFFIX)
+}
+
+/// True if the given interface is the station interface of another interface, see [`station_name`]
+pub(crate) fn is_station_interface(interface_name: &str) -> bool {
+    let interfaces = match std::fs::read_dir("/sys/class/net") {
+        Ok(interfaces) => interfaces,
+        Err(_) => return false,
+    };
+    interfaces
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .any(|name| name != interface_name && station_name(&name) == interface_name)
+}
+
+fn nl80211() -> Result<u16, CaptivePortalError> {
+    family_id("nl80211").map_err(|e| CaptivePortalError::IO(e, "nl80211 is not available"))
+}
+
+/// The interface combinations of the wifi device of the given interface
+fn interface_combinations(index: u32) -> Result<Vec<IfaceCombination>, CaptivePortalError> {
+    let mut attributes = Vec::new();
+    push_attribute(&mut attributes, NL80211_ATTR_IFINDEX, &index.to_ne_bytes());
+    let reply = generic_exchange(&generic_request(nl80211()?, NL80211_CMD_GET_WIPHY, &attributes))
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to read the wifi device capabilities"))?;
+    Ok(parse_combinations(&reply))
+}
+
+/// Adds a station interface with the given name to the wifi device of the interface with the given index
+fn add_station(index: u32, name: &str) -> Result<(), CaptivePortalError> {
+    let mut name_attribute = name.as_bytes().to_vec();
+    name_attribute.push(0);
+    let mut attributes = Vec::new();
+    push_attribute(&mut attributes, NL80211_ATTR_IFINDEX, &index.to_ne_bytes());
+    push_attribute(&mut attributes, NL80211_ATTR_IFNAME, &name_attribute);
+    push_attribute(&mut attributes, NL80211_ATTR_IFTYPE, &(NL80211_IFTYPE_STATION as u32).to_ne_bytes());
+    generic_exchange(&generic_request(nl80211()?, NL80211_CMD_NEW_INTERFACE, &attributes))
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to add the station interface"))?;
+    Ok(())
+}
+
+/// Removes the virtual interface with the given index
+fn delete_interface(index: u32) -> Result<(), CaptivePortalError> {
+    let mut attributes = Vec::new();
+    push_attribute(&mut attributes, NL80211_ATTR_IFINDEX, &index.to_ne_bytes());
+    // The kernel only answers with an acknowledgement
+    generic_exchange(&with_ack(generic_request(nl80211()?, NL80211_CMD_DEL_INTERFACE, &attributes)))
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to remove the station interface"))?;
+    Ok(())
+}
+
+/// The station interface and its network manager device
+#[derive(Clone)]
+pub(crate) struct StationInterface {
+    pub interface_name: String,
+    pub device_path: dbus::Path<'static>,
+    /// A wifi adapter of its own instead of a virtual interface. It is never removed.
+    pub adapter: bool,
+}
+
+/// The station interface of a backend, shared between its clones
+pub(crate) type StationSync = Arc<Mutex<Option<StationInterface>>>;
+
+impl NetworkBackend {
+    /// Adds a virtual station interface for connection attempts while the hotspot is up.
+    /// Returns the interface name, or None if the wifi device cannot run an access point and a station at
+    /// the same time.
+    pub async fn station_interface_add(&self) -> Result<Option<String>, CaptivePortalError> {
+        if let Some(station) = self.station.lock().expect("station mutex").as_ref() {
+            return Ok(Some(station.interface_name.clone()));
+        }
+
+        use super::device::DeviceWireless;
+        let p = nonblock::Proxy::new(NM_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        if !supports_ap(p.wireless_capabilities().await?) {
+            info!("The wifi device does not support access point mode");
+            return Ok(None);
+        }
+        let index = interface_index(&self.interface_name)?;
+        let combinations = interface_combinations(index)?;
+        let combination = match combinations.iter().find(|c| c.ap_sta()) {
+            Some(combination) => combination,
+            None => {
+                info!("The wifi device cannot run an access point and a station at the same time");
+                return Ok(None);
+            },
+        };
+        if combination.channels < 2 {
+            info!("The station interface must use the channel of the hotspot");
+        }
+
+        let name = station_name(&self.interface_name);
+        // Left over by a previous run
+        if let Ok(index) = interface_index(&name) {
+            delete_interface(index)?;
+        }
+        add_station(index, &name)?;
+
+        let device_path = match self.wait_for_device(&name).await {
+            Some(device_path) => device_path,
+            None => {
+                delete_interface(interface_index(&name)?)?;
+                return Err(CaptivePortalError::Generic(format!(
+                    "Network manager did not report the station interface {}",
+                    name
+                )));
+            },
+        };
+        // Saved connections are not activated on the station interface, only the requested one
+        use super::device::Device;
+        let p = nonblock::Proxy::new(NM_BUSNAME, device_path.clone(), self.conn.clone());
+        if let Err(e) = p.set_autoconnect(false).await {
+            warn!("Failed to disable autoconnect for {}: {}", name, e);
+        }
+
+        info!("Added the station interface {}", name);
+        *self.station.lock().expect("station mutex") = Some(StationInterface {
+            interface_name: name.clone(),
+            device_path,
+            adapter: false,
+        });
+        Ok(Some(name))
+    }
+
+    /// Dedicates the wifi adapter of the given interface to connection attempts. The hotspot moves to another
+    /// wifi adapter if it has been found on this one.
+    pub async fn set_station_adapter(&mut self, interface_name: &str) -> Result<(), CaptivePortalError> {
+        let wifi_devices = super::find_wifi_device::find_wifi_devices(self.conn.clone()).await?;
+        let station = match wifi_devices.iter().find(|d| d.interface_name == interface_name) {
+            Some(station) => station,
+            None => return Err(CaptivePortalError::Generic(format!("No wifi device on {}", interface_name))),
+        };
+        if self.interface_name == interface_name {
+            let hotspot = match wifi_devices.iter().find(|d| d.interface_name != interface_name && d.ap) {
+                Some(hotspot) => hotspot,
+                None => {
+                    return Err(CaptivePortalError::Generic(format!(
+                        "No other wifi device than the station adapter {} supports access point mode",
+                        interface_name
+                    )))
+                },
+            };
+            info!("Wireless device for the hotspot on '{}'", hotspot.interface_name);
+            self.interface_name = hotspot.interface_name.clone();
+            self.hw = hotspot.hw.clone();
+            self.wifi_device_path = hotspot.device_path.clone();
+        }
+
+        info!("Connection attempts use the wifi adapter {}", interface_name);
+        *self.station.lock().expect("station mutex") = Some(StationInterface {
+            interface_name: station.interface_name.clone(),
+            device_path: station.device_path.clone(),
+            adapter: true,
+        });
+        Ok(())
+    }
+
+    /// Waits for network manager to report a device for the given interface
+    async fn wait_for_device(&self, interface_name: &str) -> Option<dbus::Path<'static>> {
+        use super::generated::networkmanager::NetworkManager;
+        let p = nonblock::Proxy::new(NM_BUSNAME, NM_PATH, self.conn.clone());
+        let mut waited = Duration::from_secs(0);
+        while waited < STATION_DEVICE_TIMEOUT {
+            if let Ok(device_path) = p.get_device_by_ip_iface(interface_name).await {
+                return Some(device_path);
+            }
+            delay_for(STATION_DEVICE_INTERVAL).await;
+            waited += STATION_DEVICE_INTERVAL;
+        }
+        None
+    }
+
+    /// The name of the station interface, if one has been added, see [`NetworkBackend::station_interface_add`].
+    pub fn station_interface(&self) -> Option<String> {
+        self.station
+            .lock()
+            .expect("station mutex")
+            .as_ref()
+            .map(|station| station.interface_name.clone())
+    }
+
+    /// Removes the station interface, if one has been added. A dedicated station adapter is kept.
+    pub async fn station_interface_remove(&self) -> Result<(), CaptivePortalError> {
+        let station = {
+            let mut station = self.station.lock().expect("station mutex");
+            match station.as_ref() {
+                Some(s) if s.adapter => None,
+                _ => station.take(),
+            }
+        };
+        if let Some(station) = station {
+            info!("Removing the station interface {}", station.interface_name);
+            delete_interface(interface_index(&station.interface_name)?)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the connection of the station interface to the wifi interface and removes the station interface.
+    /// Activating the connection on the wifi interface stops the hotspot.
+    /// Returns false if the connection could not be activated on the wifi interface within the timeout.
+    pub async fn station_handover(&self, timeout: Duration) -> Result<bool, CaptivePortalError> {
+        let device_path = match self.station.lock().expect("station mutex").as_ref() {
+            Some(station) => station.device_path.clone(),
+            None => return Ok(false),
+        };
+        use super::device::Device;
+        let p = nonblock::Proxy::new(NM_BUSNAME, device_path, self.conn.clone());
+        let active_connection = p.active_connection().await?;
+        self.station_interface_remove().await?;
+        if &*active_connection == "/" {
+            return Ok(false);
+        }
+        use super::connection_active::ConnectionActive;
+        let p = nonblock::Proxy::new(NM_BUSNAME, active_connection, self.conn.clone());
+        let connection_path = p.connection().await?;
+
+        use super::generated::networkmanager::NetworkManager;
+        let p = nonblock::Proxy::new(NM_BUSNAME, NM_PATH, self.conn.clone());
+        let active_connection = p
+            .activate_connection(connection_path, self.wifi_device_path.clone(), "/".into())
+            .await?;
+        let state = self
+            .wait_for_active_connection_state(ConnectionState::Activated, active_connection, timeout, false)
+            .await?;
+        Ok(state == ConnectionState::Activated)
+    }
+
+    /// The device of connection attempts: The station interface if one has been added, the wifi device otherwise
+    pub(crate) fn station_device_path(&self) -> dbus::Path<'static> {
+        match self.station.lock().expect("station mutex").as_ref() {
+            Some(station) => station.device_path.clone(),
+            None => self.wifi_device_path.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(max: u32, types: &[u16]) -> Vec<u8> {
+        let mut flags = Vec::new();
+        for kind in types {
+            push_attribute(&mut flags, *kind, &[]);
+        }
+        let mut limit = Vec::new();
+        push_attribute(&mut limit, NL80211_IFACE_LIMIT_MAX, &max.to_ne_bytes());
+        push_attribute(&mut limit, NL80211_IFACE_LIMIT_TYPES, &flags);
+        limit
+    }
+
+    fn combination(limits: &[Vec<u8>], max_interfaces: u32, channels: u32) -> Vec<u8> {
+        let mut nested = Vec::new();
+        for (i, limit) in limits.iter().enumerate() {
+            push_attribute(&mut nested, i as u16 + 1, limit);
+        }
+        let mut combination = Vec::new();
+        push_attribute(&mut combination, NL80211_IFACE_COMB_LIMITS, &nested);
+        push_attribute(&mut combination, NL80211_IFACE_COMB_MAXNUM, &max_interfaces.to_ne_bytes());
+        push_attribute(&mut combination, NL80211_IFACE_COMB_NUM_CHANNELS, &channels.to_ne_bytes());
+        combination
+    }
+
+    fn wiphy(combinations: &[Vec<u8>]) -> Vec<u8> {
+        let mut nested = Vec::new();
+        for (i, combination) in combinations.iter().enumerate() {
+            push_attribute(&mut nested, i as u16 + 1, combination);
+        }
+        let mut data = Vec::new();
+        push_attribute(&mut data, NL80211_ATTR_IFINDEX, &3u32.to_ne_bytes());
+        push_attribute(&mut data, NL80211_ATTR_INTERFACE_COMBINATIONS, &nested);
+        data
+    }
+
+    #[test]
+    fn combinations() {
+        // Like brcmfmac: one station plus one access point, on one channel
+        let data = wiphy(&[combination(
+            &[limit(1, &[NL80211_IFTYPE_STATION]), limit(1, &[NL80211_IFTYPE_AP])],
+            2,
+            1,
+        )]);
+        let combinations = parse_combinations(&data);
+        assert_eq!(combinations.len(), 1);
+        assert_eq!(combinations[0].limits[1], IfaceLimit { max: 1, types: vec![NL80211_IFTYPE_AP] });
+        assert_eq!(combinations[0].channels, 1);
+        assert!(combinations[0].ap_sta());
+
+        // One interface of either type
+        let data = wiphy(&[combination(&[limit(1, &[NL80211_IFTYPE_STATION, NL80211_IFTYPE_AP])], 1, 1)]);
+        assert!(!parse_combinations(&data)[0].ap_sta());
+
+        // Two interfaces of either type
+        let data = wiphy(&[combination(&[limit(2, &[NL80211_IFTYPE_STATION, NL80211_IFTYPE_AP])], 2, 2)]);
+        assert!(parse_combinations(&data)[0].ap_sta());
+
+        // Two stations only
+        let data = wiphy(&[combination(&[limit(2, &[NL80211_IFTYPE_STATION])], 2, 1)]);
+        assert!(!parse_combinations(&data)[0].ap_sta());
+    }
+
+    #[test]
+    fn station_names() {
+        assert_eq!(station_name("wlan0"), "wlan0sta");
+        assert_eq!(station_name("wlx00c0ca123456"), "wlx00c0ca123sta");
+    }
+}
@@ -5,8 +5,11 @@
 //! This module creates and encodes those data containers.
 //! This is an internal implementation detail of the network manager implementation.
 
-use super::NM_BUSNAME;
-use crate::network_interface::{AccessPointCredentials, SSID};
+use super::{HOTSPOT_UUID, NM_BUSNAME};
+use crate::network_interface::{
+    AccessPointCredentials, EapMethod, HotspotBand, HotspotRadio, HotspotSecurity, Ipv6Config, Pmf, StaticIpv4,
+    StaticIpv6, SSID,
+};
 use crate::utils::verify_password;
 use crate::CaptivePortalError;
 
@@ -15,9 +18,22 @@ use std::net::Ipv4Addr;
 use std::sync::Arc;
 
 use dbus::arg::{RefArg, Variant};
-use dbus::{nonblock, nonblock::SyncConnection};
+use dbus::nonblock;
+use crate::dbus_tokio::DbusConnection;
 
 const NM_WEP_KEY_TYPE_PASSPHRASE: u8 = 2;
+/// "802-11-wireless-security.wps-method": Push-button only
+const NM_WPS_METHOD_PBC: u32 = 0x4;
+
+/// The "802-11-wireless-security.pmf" value. None for the global default.
+fn nm_pmf(pmf: Pmf) -> Option<i32> {
+    match pmf {
+        Pmf::Auto => None,
+        Pmf::Disabled => Some(1),
+        Pmf::Optional => Some(2),
+        Pmf::Required => Some(3),
+    }
+}
 
 #[derive(Debug, Eq, PartialEq)]
 pub(crate) enum WifiConnectionMode {
@@ -33,6 +49,8 @@ pub(crate) struct WiFiConnectionSettings {
     pub ssid: SSID,
     pub mode: WifiConnectionMode,
     pub seen_bssids: Vec<String>,
+    /// The "autoconnect-priority", 0 by default
+    pub priority: i32,
 }
 
 /**
@@ -83,23 +101,43 @@ pub(crate) fn make_arguments_for_sta(
     ssid: SSID,
     password: String,
     address: Option<Ipv4Addr>,
+    radio: Option<HotspotRadio>,
     interface: &str,
-    uuid: &str,
+    pmf: Pmf,
+    hotspot_security: HotspotSecurity,
 ) -> Result<HashMap<&'static str, VariantMap>, CaptivePortalError> {
     let mut settings: HashMap<&'static str, VariantMap> = HashMap::new();
 
     let mut wireless: VariantMap = HashMap::new();
     add_val(&mut wireless, "ssid", ssid.as_bytes().to_owned());
-    add_str(&mut wireless, "band", "bg");
+    match radio {
+        Some(radio) => {
+            add_str(&mut wireless, "band", if radio.band == HotspotBand::A { "a" } else { "bg" });
+            if let Some(channel) = radio.channel {
+                add_val(&mut wireless, "channel", channel);
+            }
+        },
+        None => add_str(&mut wireless, "band", "bg"),
+    }
     add_val(&mut wireless, "hidden", false);
     add_str(&mut wireless, "mode", "ap");
     if password.len() > 0 {
         verify_password(&password)?;
         add_str(&mut wireless, "security", "802-11-wireless-security");
 
+        // NetworkManager offers SAE in addition to WPA-PSK on access points with optional protected
+        // management frames, if wpa_supplicant supports it.
+        let (key_mgmt, pmf) = match hotspot_security {
+            HotspotSecurity::Wpa2 => ("wpa-psk", pmf),
+            HotspotSecurity::Transition => ("wpa-psk", Pmf::Optional),
+            HotspotSecurity::Wpa3 => ("sae", Pmf::Required),
+        };
         let mut security: VariantMap = HashMap::new();
-        add_str(&mut security, "key-mgmt", "wpa-psk");
+        add_str(&mut security, "key-mgmt", key_mgmt);
         add_str(&mut security, "psk", &password);
+        if let Some(pmf) = nm_pmf(pmf) {
+            add_val(&mut security, "pmf", pmf);
+        }
 
         settings.insert("802-11-wireless-security", security);
     }
@@ -109,7 +147,7 @@ pub(crate) fn make_arguments_for_sta(
     let mut connection: VariantMap = HashMap::new();
     add_str(&mut connection, "id", "Hotspot");
     add_str(&mut connection, "interface-name", interface);
-    add_str(&mut connection, "uuid", uuid);
+    add_str(&mut connection, "uuid", HOTSPOT_UUID);
     add_str(&mut connection, "type", "802-11-wireless");
     add_val(&mut connection, "autoconnect", false);
     settings.insert("connection", connection);
@@ -130,6 +168,16 @@ pub(crate) fn make_arguments_for_sta(
     Ok(settings)
 }
 
+/// Turns the hotspot settings into an open ad-hoc (IBSS) network, for wifi devices without access point mode.
+/// Network manager offers no WPA for ad-hoc networks.
+pub(crate) fn make_adhoc(settings: &mut HashMap<&'static str, VariantMap>) {
+    settings.remove("802-11-wireless-security");
+    if let Some(wireless) = settings.get_mut("802-11-wireless") {
+        wireless.remove("security");
+        add_str(wireless, "mode", "adhoc");
+    }
+}
+
 /// The connection should be temporary only, until explicitly saved.
 pub(crate) fn make_options_for_ap() -> HashMap<&'static str, Variant<Box<dyn RefArg>>> {
     let mut options = HashMap::new();
@@ -143,12 +191,19 @@ pub(crate) fn make_options_for_ap() -> HashMap<&'static str, Variant<Box<dyn Ref
 pub(crate) fn make_arguments_for_ap<T: Eq + std::hash::Hash + std::convert::From<&'static str>>(
     ssid: &SSID,
     credentials: AccessPointCredentials,
+    pmf: Pmf,
+    ipv4: Option<&StaticIpv4>,
+    ipv6: Option<&Ipv6Config>,
+    hidden: bool,
     old_connection: Option<WiFiConnectionSettings>,
 ) -> Result<HashMap<T, VariantMap>, CaptivePortalError> {
     let mut settings: HashMap<T, VariantMap> = HashMap::new();
 
     let mut wireless: VariantMap = HashMap::new();
     add_val(&mut wireless, "ssid", ssid.as_bytes().to_owned());
+    if hidden {
+        add_val(&mut wireless, "hidden", true);
+    }
     settings.insert("802-11-wireless".into(), wireless);
 
     let mut connection: VariantMap = HashMap::new();
@@ -157,20 +212,85 @@ pub(crate) fn make_arguments_for_ap<T: Eq + std::hash::Hash + std::convert::From
     if let Some(old_connection) = old_connection {
         add_val(&mut connection, "id", old_connection.id);
         add_val(&mut connection, "uuid", old_connection.uuid);
+        add_val(&mut connection, "autoconnect-priority", old_connection.priority);
     }
     settings.insert("connection".into(), connection);
 
-    prepare_wifi_security_settings(&credentials, &mut settings)?;
+    // Without an ipv4 dict network manager uses DHCP
+    if let Some(ipv4) = ipv4 {
+        settings.insert("ipv4".into(), make_arguments_for_static_ipv4(ipv4));
+    }
+    if let Some(ipv6) = ipv6 {
+        settings.insert("ipv6".into(), make_arguments_for_ipv6(ipv6));
+    }
+
+    prepare_wifi_security_settings(&credentials, pmf, &mut settings)?;
 
     Ok(settings)
 }
 
+/// The "ipv4" settings of a static configuration.
+/// The legacy "dns" property is used, "dns-data" requires network manager 1.42.
+pub(crate) fn make_arguments_for_static_ipv4(ipv4: &StaticIpv4) -> VariantMap {
+    let mut settings: VariantMap = HashMap::new();
+    add_str(&mut settings, "method", "manual");
+
+    let mut addr_map: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+    addr_map.insert("address".to_owned(), Variant(Box::new(format!("{}", ipv4.address))));
+    addr_map.insert("prefix".to_owned(), Variant(Box::new(u32::from(ipv4.prefix))));
+    add_val(&mut settings, "address-data", vec![addr_map]);
+
+    if let Some(gateway) = ipv4.gateway {
+        add_str(&mut settings, "gateway", format!("{}", gateway));
+    }
+    if !ipv4.dns.is_empty() {
+        // Network byte order
+        let dns: Vec<u32> = ipv4.dns.iter().map(|d| u32::from_ne_bytes(d.octets())).collect();
+        add_val(&mut settings, "dns", dns);
+    }
+    settings
+}
+
+/// The "ipv6" settings. Method "auto" uses router advertisements and DHCPv6 if the router asks for it.
+pub(crate) fn make_arguments_for_ipv6(ipv6: &Ipv6Config) -> VariantMap {
+    let mut settings: VariantMap = HashMap::new();
+    match ipv6 {
+        Ipv6Config::Auto => add_str(&mut settings, "method", "auto"),
+        Ipv6Config::Dhcp => add_str(&mut settings, "method", "dhcp"),
+        Ipv6Config::Static(StaticIpv6 {
+            address,
+            prefix,
+            gateway,
+            dns,
+        }) => {
+            add_str(&mut settings, "method", "manual");
+
+            let mut addr_map: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+            addr_map.insert("address".to_owned(), Variant(Box::new(format!("{}", address))));
+            addr_map.insert("prefix".to_owned(), Variant(Box::new(u32::from(*prefix))));
+            add_val(&mut settings, "address-data", vec![addr_map]);
+
+            if let Some(gateway) = gateway {
+                add_str(&mut settings, "gateway", format!("{}", gateway));
+            }
+            if !dns.is_empty() {
+                let dns: Vec<Vec<u8>> = dns.iter().map(|d| d.octets().to_vec()).collect();
+                add_val(&mut settings, "dns", dns);
+            }
+        },
+    }
+    settings
+}
+
 /// Adds necessary entries to the given settings map.
 /// To be used by wifi device connect and [`add_wifi_connection`].
+/// Protected management frames are required for WPA3 if `pmf` is [`Pmf::Auto`].
 pub(crate) fn prepare_wifi_security_settings<T: Eq + std::hash::Hash + std::convert::From<&'static str>>(
     credentials: &AccessPointCredentials,
+    pmf: Pmf,
     settings: &mut HashMap<T, VariantMap>,
 ) -> Result<(), CaptivePortalError> {
+    let pmf = nm_pmf(pmf.for_credentials(credentials));
     match *credentials {
         AccessPointCredentials::Wep { ref passphrase } => {
             verify_password(&passphrase)?;
@@ -187,32 +307,80 @@ pub(crate) fn prepare_wifi_security_settings<T: Eq + std::hash::Hash + std::conv
 
             add_str(&mut security_settings, "key-mgmt", "wpa-psk");
             add_val(&mut security_settings, "psk", passphrase.clone());
+            if let Some(pmf) = pmf {
+                add_val(&mut security_settings, "pmf", pmf);
+            }
+
+            settings.insert("802-11-wireless-security".into(), security_settings);
+        },
+        AccessPointCredentials::Sae { ref passphrase } => {
+            verify_password(&passphrase)?;
+            let mut security_settings: VariantMap = HashMap::new();
+
+            add_str(&mut security_settings, "key-mgmt", "sae");
+            add_val(&mut security_settings, "psk", passphrase.clone());
+            if let Some(pmf) = pmf {
+                add_val(&mut security_settings, "pmf", pmf);
+            }
 
             settings.insert("802-11-wireless-security".into(), security_settings);
         },
         AccessPointCredentials::Enterprise {
             ref identity,
             ref passphrase,
+            ref anonymous_identity,
+            method,
         } => {
             verify_password(&passphrase)?;
             let mut security_settings: VariantMap = HashMap::new();
 
             add_str(&mut security_settings, "key-mgmt", "wpa-eap");
-
-            let mut eap: VariantMap = HashMap::new();
-            add_val(&mut eap, "eap", vec!["peap".to_string()]);
-            add_str(&mut eap, "identity", identity as &str);
-            add_str(&mut eap, "password", passphrase as &str);
-            add_str(&mut eap, "phase2-auth", "mschapv2");
+            if let Some(pmf) = pmf {
+                add_val(&mut security_settings, "pmf", pmf);
+            }
 
             settings.insert("802-11-wireless-security".into(), security_settings);
-            settings.insert("802-1x".into(), eap);
+            settings.insert(
+                "802-1x".into(),
+                make_arguments_for_enterprise(identity, passphrase, anonymous_identity.as_deref(), method),
+            );
         },
         AccessPointCredentials::None => {},
     };
     Ok(())
 }
 
+/// The security settings of a WPS push-button connection: WPA without a passphrase. Network manager asks the
+/// secret agents for the passphrase and enrolls via WPS meanwhile, see [`super::wps_agent`].
+/// The passphrase handed out by the router is stored in the connection.
+pub(crate) fn make_wps<T: Eq + std::hash::Hash + std::convert::From<&'static str>>(
+    settings: &mut HashMap<T, VariantMap>,
+) {
+    let mut security_settings: VariantMap = HashMap::new();
+    add_str(&mut security_settings, "key-mgmt", "wpa-psk");
+    add_val(&mut security_settings, "wps-method", NM_WPS_METHOD_PBC);
+    settings.insert("802-11-wireless-security".into(), security_settings);
+}
+
+/// The "802-1x" settings of an enterprise network: PEAP with MSCHAPv2 or TTLS with PAP.
+/// The server certificate is not validated, there is no way to provide a CA certificate via the portal.
+pub(crate) fn make_arguments_for_enterprise(
+    identity: &str,
+    passphrase: &str,
+    anonymous_identity: Option<&str>,
+    method: EapMethod,
+) -> VariantMap {
+    let mut eap: VariantMap = HashMap::new();
+    add_val(&mut eap, "eap", vec![method.as_str().to_string()]);
+    add_str(&mut eap, "identity", identity);
+    if let Some(anonymous_identity) = anonymous_identity {
+        add_str(&mut eap, "anonymous-identity", anonymous_identity);
+    }
+    add_str(&mut eap, "password", passphrase);
+    add_str(&mut eap, "phase2-auth", method.phase2());
+    eap
+}
+
 pub(crate) fn extract(key: &str, map: &HashMap<String, Variant<Box<dyn RefArg>>>) -> String {
     map.get(key)
         .and_then(|v| v.0.as_str().and_then(|v| Some(v.to_owned())))
@@ -251,7 +419,7 @@ pub(crate) fn extract_vector(key: &str, map: &HashMap<String, Variant<Box<dyn Re
 
 /// Return a wifi connection settings object if the given connection (or active connection) is a wifi connection and None otherwise.
 pub(crate) async fn get_connection_settings(
-    conn: Arc<SyncConnection>,
+    conn: Arc<DbusConnection>,
     connection_path: dbus::Path<'_>,
 ) -> Result<Option<WiFiConnectionSettings>, CaptivePortalError> {
     // The api consumer might hand us an active connection instead of a regular one. If so, determine the connection path
@@ -266,16 +434,23 @@ pub(crate) async fn get_connection_settings(
     use super::generated::connection_nm::Connection;
 
     let dict = p.get_settings().await?;
+    Ok(parse_connection_settings(&dict))
+}
 
+/// The wifi settings of a connection profile, None if it is not a wifi connection.
+/// The SSID keeps the exact octets of the profile.
+pub(crate) fn parse_connection_settings(
+    dict: &HashMap<String, HashMap<String, Variant<Box<dyn RefArg>>>>,
+) -> Option<WiFiConnectionSettings> {
     let wireless_settings = if let Some(v) = dict.get("802-11-wireless") {
         v
     } else {
-        return Ok(None);
+        return None;
     };
     let connection_settings = if let Some(v) = dict.get("connection") {
         v
     } else {
-        return Ok(None);
+        return None;
     };
 
     let mode = match &extract("mode", &wireless_settings)[..] {
@@ -293,13 +468,17 @@ pub(crate) async fn get_connection_settings(
 
     let d = extract_bytes("ssid", &wireless_settings);
 
-    Ok(Some(WiFiConnectionSettings {
+    Some(WiFiConnectionSettings {
         id: extract("id", &connection_settings),
         uuid: extract("uuid", &connection_settings),
-        ssid: String::from_utf8(d)?,
+        ssid: SSID::from(d),
         mode,
         seen_bssids: extract_vector("seen-bssids", &wireless_settings),
-    }))
+        priority: connection_settings
+            .get("autoconnect-priority")
+            .and_then(|v| v.0.as_i64())
+            .unwrap_or(0) as i32,
+    })
 }
 
 /// Dbus library helper type
@@ -319,3 +498,167 @@ where
 {
     map.insert(key, Variant(Box::new(value.into())));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sae_security_settings() {
+        let credentials = AccessPointCredentials::Sae {
+            passphrase: "secret123".to_owned(),
+        };
+        let settings: HashMap<&'static str, VariantMap> =
+            make_arguments_for_ap(&"wpa3 network".into(), credentials, Pmf::Auto, None, None, false, None).unwrap();
+        let security = &settings["802-11-wireless-security"];
+        assert_eq!(security["key-mgmt"].0.as_str(), Some("sae"));
+        assert_eq!(security["psk"].0.as_str(), Some("secret123"));
+        // Required, WPA3 does not work without protected management frames
+        assert_eq!(security["pmf"].0.as_i64(), Some(3));
+    }
+
+    #[test]
+    fn enterprise_settings() {
+        let credentials = AccessPointCredentials::Enterprise {
+            identity: "student@university.edu".to_owned(),
+            passphrase: "secret123".to_owned(),
+            anonymous_identity: Some("anonymous@university.edu".to_owned()),
+            method: EapMethod::Ttls,
+        };
+        let settings: HashMap<&'static str, VariantMap> =
+            make_arguments_for_ap(&"eduroam".into(), credentials, Pmf::Auto, None, None, true, None).unwrap();
+        assert_eq!(settings["802-11-wireless-security"]["key-mgmt"].0.as_str(), Some("wpa-eap"));
+        assert_eq!(settings["802-11-wireless"]["hidden"].0.as_i64(), Some(1));
+        let eap = &settings["802-1x"];
+        let methods: Vec<&str> = eap["eap"].0.as_iter().unwrap().filter_map(|v| v.as_str()).collect();
+        assert_eq!(methods, vec!["ttls"]);
+        assert_eq!(eap["identity"].0.as_str(), Some("student@university.edu"));
+        assert_eq!(eap["anonymous-identity"].0.as_str(), Some("anonymous@university.edu"));
+        assert_eq!(eap["phase2-auth"].0.as_str(), Some("pap"));
+
+        let eap = make_arguments_for_enterprise("user", "secret123", None, EapMethod::Peap);
+        assert_eq!(eap["phase2-auth"].0.as_str(), Some("mschapv2"));
+        assert!(eap.get("anonymous-identity").is_none());
+    }
+
+    #[test]
+    fn static_ipv4_settings() {
+        let ipv4 = StaticIpv4 {
+            address: Ipv4Addr::new(10, 0, 3, 20),
+            prefix: 16,
+            gateway: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            dns: vec![Ipv4Addr::new(10, 0, 0, 2)],
+        };
+        let credentials = AccessPointCredentials::None;
+        let settings: HashMap<&'static str, VariantMap> =
+            make_arguments_for_ap(&"plant".into(), credentials, Pmf::Auto, Some(&ipv4), None, false, None).unwrap();
+        let ipv4 = &settings["ipv4"];
+        assert_eq!(ipv4["method"].0.as_str(), Some("manual"));
+        assert_eq!(ipv4["gateway"].0.as_str(), Some("10.0.0.1"));
+        let dns: Vec<i64> = ipv4["dns"].0.as_iter().unwrap().filter_map(|v| v.as_i64()).collect();
+        assert_eq!(dns, vec![i64::from(u32::from_ne_bytes([10, 0, 0, 2]))]);
+        let address = ipv4["address-data"].0.as_iter().unwrap().next().unwrap();
+        let address: Vec<String> = address.as_iter().unwrap().filter_map(|v| v.as_str().map(str::to_owned)).collect();
+        assert!(address.contains(&"10.0.3.20".to_owned()));
+
+        let ipv6 = Ipv6Config::Dhcp;
+        let credentials = AccessPointCredentials::None;
+        let settings: HashMap<&'static str, VariantMap> =
+            make_arguments_for_ap(&"plant".into(), credentials, Pmf::Auto, None, Some(&ipv6), false, None).unwrap();
+        assert!(settings.get("ipv4").is_none());
+        assert_eq!(settings["ipv6"]["method"].0.as_str(), Some("dhcp"));
+    }
+
+    #[test]
+    fn static_ipv6_settings() {
+        let ipv6 = Ipv6Config::Static(StaticIpv6 {
+            address: "2001:db8::20".parse().unwrap(),
+            prefix: 64,
+            gateway: None,
+            dns: vec!["2001:db8::1".parse().unwrap()],
+        });
+        let ipv6 = make_arguments_for_ipv6(&ipv6);
+        assert_eq!(ipv6["method"].0.as_str(), Some("manual"));
+        assert!(ipv6.get("gateway").is_none());
+        let dns = ipv6["dns"].0.as_iter().unwrap().next().unwrap();
+        let dns: Vec<i64> = dns.as_iter().unwrap().filter_map(|v| v.as_i64()).collect();
+        assert_eq!(dns, vec![0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn wpa3_hotspot_settings() {
+        let hotspot = |security| {
+            let address = Some(Ipv4Addr::new(192, 168, 42, 1));
+            let password = "secret123".to_owned();
+            make_arguments_for_sta("hotspot".into(), password, address, None, "wlan0", Pmf::Auto, security)
+                .unwrap()
+                .remove("802-11-wireless-security")
+                .unwrap()
+        };
+        let security = hotspot(HotspotSecurity::Wpa3);
+        assert_eq!(security["key-mgmt"].0.as_str(), Some("sae"));
+        assert_eq!(security["pmf"].0.as_i64(), Some(3));
+        let security = hotspot(HotspotSecurity::Transition);
+        assert_eq!(security["key-mgmt"].0.as_str(), Some("wpa-psk"));
+        assert_eq!(security["pmf"].0.as_i64(), Some(2));
+        assert!(hotspot(HotspotSecurity::Wpa2).get("pmf").is_none());
+    }
+    #[test]
+    fn hotspot_band_settings() {
+        let radio = HotspotRadio::new(None, Some(36)).unwrap();
+        let ssid = SSID::from("hotspot");
+        let security = HotspotSecurity::Wpa2;
+        let mut settings =
+            make_arguments_for_sta(ssid, String::new(), None, radio, "wlan0", Pmf::Auto, security).unwrap();
+        let wireless = settings.remove("802-11-wireless").unwrap();
+        assert_eq!(wireless["band"].0.as_str(), Some("a"));
+        assert_eq!(wireless["channel"].0.as_u64(), Some(36));
+    }
+
+    #[test]
+    fn adhoc_hotspot_settings() {
+        let address = Some(Ipv4Addr::new(192, 168, 42, 1));
+        let password = "secret123".to_owned();
+        let security = HotspotSecurity::Wpa2;
+        let mut settings =
+            make_arguments_for_sta("hotspot".into(), password, address, None, "wlan0", Pmf::Auto, security)
+                .unwrap();
+        make_adhoc(&mut settings);
+        assert!(settings.get("802-11-wireless-security").is_none());
+        let wireless = settings.remove("802-11-wireless").unwrap();
+        assert_eq!(wireless["mode"].0.as_str(), Some("adhoc"));
+        assert!(wireless.get("security").is_none());
+        assert_eq!(settings["ipv4"]["method"].0.as_str(), Some("manual"));
+    }
+
+    #[test]
+    fn ssid_round_trip() {
+        for bytes in [&b"Home  "[..], &b"Caf\xe9"[..], &b"\x00 \xff"[..]].iter() {
+            let ssid = SSID::from_bytes(*bytes);
+            let credentials = AccessPointCredentials::None;
+            let settings = make_arguments_for_ap(&ssid, credentials, Pmf::Auto, None, None, false, None).unwrap();
+            // As returned by GetSettings
+            let dict: HashMap<String, HashMap<String, Variant<Box<dyn RefArg>>>> = settings
+                .iter()
+                .map(|(group, values)| {
+                    let values = values.iter().map(|(k, v)| (k.to_string(), Variant(v.0.box_clone()))).collect();
+                    (group.to_string(), values)
+                })
+                .collect();
+            let parsed = parse_connection_settings(&dict).unwrap();
+            assert_eq!(parsed.ssid.as_bytes(), *bytes);
+        }
+    }
+
+    #[test]
+    fn wps_settings() {
+        let credentials = AccessPointCredentials::None;
+        let mut settings: HashMap<&'static str, VariantMap> =
+            make_arguments_for_ap(&"router".into(), credentials, Pmf::Auto, None, None, false, None).unwrap();
+        make_wps(&mut settings);
+        let security = &settings["802-11-wireless-security"];
+        assert_eq!(security["key-mgmt"].0.as_str(), Some("wpa-psk"));
+        assert_eq!(security["wps-method"].0.as_u64(), Some(4));
+        assert!(security.get("psk").is_none());
+    }
+}
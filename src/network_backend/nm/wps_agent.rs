@@ -0,0 +1,92 @@
+//! # WPS push-button agent
+//! Network manager enrolls via WPS push-button if a connection has no passphrase and its "wps-method" allows it
+//! (see [`super::wifi_settings::make_wps`]). Meanwhile it asks the registered secret agents for the passphrase
+//! and cancels that request as soon as the router handed out the credentials. Without any agent, the request
+//! fails right away and so does the connection attempt.
+//!
+//! This agent keeps such requests open, so that the user has time to push the button on the router.
+//! All other requests are answered with "no secrets" immediately, network manager asks the next agent then.
+
+use super::NM_BUSNAME;
+use crate::dbus_tokio::DbusConnection;
+use crate::CaptivePortalError;
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::{MatchRule, MessageType};
+use dbus::{nonblock, Message};
+use std::ffi::CString;
+use std::sync::{Arc, Mutex};
+
+const AGENT_PATH: &str = "/org/freedesktop/NetworkManager/SecretAgent";
+const AGENT_INTERFACE: &str = "org.freedesktop.NetworkManager.SecretAgent";
+const AGENT_MANAGER_PATH: &str = "/org/freedesktop/NetworkManager/AgentManager";
+const AGENT_MANAGER_INTERFACE: &str = "org.freedesktop.NetworkManager.AgentManager";
+const AGENT_IDENTIFIER: &str = "org.openhab.WifiCaptive";
+/// NM_SECRET_AGENT_GET_SECRETS_FLAG_WPS_PBC_ACTIVE: Network manager waits for the WPS button to be pushed
+const GET_SECRETS_FLAG_WPS_PBC_ACTIVE: u32 = 0x8;
+const ERROR_NO_SECRETS: &str = "org.freedesktop.NetworkManager.SecretAgent.NoSecrets";
+const ERROR_USER_CANCELED: &str = "org.freedesktop.NetworkManager.SecretAgent.UserCanceled";
+const ERROR_AGENT_CANCELED: &str = "org.freedesktop.NetworkManager.SecretAgent.AgentCanceled";
+
+/// The "GetSecrets" call that is kept open while waiting for the WPS button
+type PendingRequest = Arc<Mutex<Option<Message>>>;
+
+/// Answers the secret requests of network manager and registers the agent.
+/// The agent stays registered until the service quits.
+pub(crate) async fn register(conn: Arc<DbusConnection>) -> Result<(), CaptivePortalError> {
+    let pending: PendingRequest = Arc::new(Mutex::new(None));
+    let mut rule = MatchRule::new();
+    rule.msg_type = Some(MessageType::MethodCall);
+    rule.path = Some(AGENT_PATH.into());
+    rule.interface = Some(AGENT_INTERFACE.into());
+    conn.start_receive(
+        rule,
+        Box::new(move |msg, conn| {
+            for reply in method_call(&pending, msg) {
+                let _ = conn.send(reply);
+            }
+            true
+        }),
+    );
+
+    let p = nonblock::Proxy::new(NM_BUSNAME, AGENT_MANAGER_PATH, conn.clone());
+    p.method_call::<(), _, _, _>(AGENT_MANAGER_INTERFACE, "Register", (AGENT_IDENTIFIER,)).await?;
+    info!("Registered the WPS secret agent");
+    Ok(())
+}
+
+fn error(msg: &Message, name: &'static str, text: &str) -> Message {
+    msg.error(&name.into(), &CString::new(text).unwrap_or_default())
+}
+
+/// Returns the replies for the given agent method call. A held "GetSecrets" call is answered
+/// when network manager cancels it or when another WPS request replaces it.
+fn method_call(pending: &PendingRequest, msg: Message) -> Vec<Message> {
+    let member = msg.member().map(|m| m.to_string()).unwrap_or_default();
+    let mut pending = pending.lock().expect("Lock pending WPS request");
+    match &member[..] {
+        "GetSecrets" => {
+            // Arguments: connection, connection path, setting name, hints, flags
+            let mut args = msg.iter_init();
+            for _ in 0..4 {
+                args.next();
+            }
+            let flags: u32 = args.get().unwrap_or(0);
+            if flags & GET_SECRETS_FLAG_WPS_PBC_ACTIVE == 0 {
+                return vec![error(&msg, ERROR_NO_SECRETS, "No secrets available")];
+            }
+            info!("Waiting for the WPS button to be pushed on the router");
+            let replaced = pending.replace(msg);
+            replaced.map(|r| error(&r, ERROR_USER_CANCELED, "Replaced")).into_iter().collect()
+        },
+        "CancelGetSecrets" => {
+            let mut replies = vec![msg.method_return()];
+            if let Some(request) = pending.take() {
+                debug!("WPS secret request cancelled");
+                replies.push(error(&request, ERROR_AGENT_CANCELED, "Cancelled"));
+            }
+            replies
+        },
+        // SaveSecrets, DeleteSecrets: The agent does not store secrets
+        _ => vec![msg.method_return()],
+    }
+}
@@ -0,0 +1,59 @@
+//! # Access points change stream
+//! ubus does not notify about scan results. Scans are performed periodically instead and compared
+//! to the previous result.
+
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+use crate::runtime::{delay_for, BoxStream};
+
+use super::{access_point_path, NetworkBackend};
+use crate::network_interface::WifiConnectionEventType;
+use crate::CaptivePortalError;
+
+/// Time between two scans
+const SCAN_INTERVAL: Duration = Duration::from_secs(15);
+
+pub struct AccessPointChanged {
+    pub path: String,
+    pub event: WifiConnectionEventType,
+}
+
+pub async fn ap_changed_stream(
+    network_manager: &NetworkBackend,
+) -> Result<BoxStream<'static, AccessPointChanged>, CaptivePortalError> {
+    let nm = network_manager.clone();
+    let known: HashSet<String> = nm.cached_access_point_paths();
+
+    let stream = futures_util::stream::unfold(
+        (nm, known, VecDeque::new()),
+        |(nm, mut known, mut pending)| async move {
+            loop {
+                if let Some(event) = pending.pop_front() {
+                    return Some((event, (nm, known, pending)));
+                }
+                delay_for(SCAN_INTERVAL).await;
+                let current: HashSet<String> = match nm.list_access_points(Duration::from_secs(0)).await {
+                    Ok(access_points) => access_points.iter().map(|ap| access_point_path(&ap.hw)).collect(),
+                    Err(e) => {
+                        warn!("Periodic scan failed: {}", e);
+                        continue;
+                    },
+                };
+                for path in current.difference(&known) {
+                    pending.push_back(AccessPointChanged {
+                        path: path.clone(),
+                        event: WifiConnectionEventType::Added,
+                    });
+                }
+                for path in known.difference(&current) {
+                    pending.push_back(AccessPointChanged {
+                        path: path.clone(),
+                        event: WifiConnectionEventType::Removed,
+                    });
+                }
+                known = current;
+            }
+        },
+    );
+    Ok(Box::pin(stream))
+}
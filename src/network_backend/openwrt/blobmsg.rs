@@ -0,0 +1,239 @@
+//! # blob and blobmsg encoding
+//! ubus messages are lists of blob attributes (libubox "blob.h"): A big endian header with the attribute id
+//! and the length, followed by the data, padded to 4 bytes. Method arguments and responses are blobmsg
+//! attributes, which carry a name and a type. They are translated from and to json, like the `ubus` command
+//! line tool does: Integers fit into the smallest of int32 and int64, booleans are int8.
+
+use crate::CaptivePortalError;
+use serde_json::{Map, Number, Value};
+
+const ID_SHIFT: u32 = 24;
+const ID_MASK: u32 = 0x7f;
+const LEN_MASK: u32 = 0x00ff_ffff;
+/// Set for blobmsg attributes
+const EXTENDED: u32 = 0x8000_0000;
+const HEADER_LEN: usize = 4;
+
+// blobmsg types
+const TYPE_UNSPEC: u8 = 0;
+const TYPE_ARRAY: u8 = 1;
+const TYPE_TABLE: u8 = 2;
+const TYPE_STRING: u8 = 3;
+const TYPE_INT64: u8 = 4;
+const TYPE_INT32: u8 = 5;
+const TYPE_INT16: u8 = 6;
+const TYPE_INT8: u8 = 7;
+const TYPE_DOUBLE: u8 = 8;
+
+/// A parsed blob attribute
+pub(crate) struct Attr<'a> {
+    pub id: u8,
+    pub extended: bool,
+    pub data: &'a [u8],
+}
+
+fn padded(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn invalid(what: &str) -> CaptivePortalError {
+    CaptivePortalError::UbusError(format!("Invalid message: {}", what))
+}
+
+/// The header of an attribute with the given id and data length
+pub(crate) fn header(id: u8, extended: bool, data_len: usize) -> [u8; HEADER_LEN] {
+    let extended = if extended { EXTENDED } else { 0 };
+    let id_len = extended | ((u32::from(id) & ID_MASK) << ID_SHIFT) | ((HEADER_LEN + data_len) as u32 & LEN_MASK);
+    id_len.to_be_bytes()
+}
+
+/// The data length of the attribute with the given header
+pub(crate) fn data_len(header: [u8; HEADER_LEN]) -> Result<usize, CaptivePortalError> {
+    let len = (u32::from_be_bytes(header) & LEN_MASK) as usize;
+    len.checked_sub(HEADER_LEN).ok_or_else(|| invalid("attribute length"))
+}
+
+/// Appends a blob attribute with the given data
+pub(crate) fn put(buf: &mut Vec<u8>, id: u8, data: &[u8]) {
+    put_attr(buf, id, false, data);
+}
+
+/// Appends a blob attribute with a nul terminated string
+pub(crate) fn put_string(buf: &mut Vec<u8>, id: u8, value: &str) {
+    let mut data = value.as_bytes().to_vec();
+    data.push(0);
+    put(buf, id, &data);
+}
+
+fn put_attr(buf: &mut Vec<u8>, id: u8, extended: bool, data: &[u8]) {
+    buf.extend_from_slice(&header(id, extended, data.len()));
+    buf.extend_from_slice(data);
+    buf.resize(padded(buf.len()), 0);
+}
+
+/// Splits the given data into its attributes
+pub(crate) fn parse(mut data: &[u8]) -> Result<Vec<Attr>, CaptivePortalError> {
+    let mut attrs = Vec::new();
+    while data.len() >= HEADER_LEN {
+        let id_len = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let len = (id_len & LEN_MASK) as usize;
+        if len < HEADER_LEN || len > data.len() {
+            return Err(invalid("attribute length"));
+        }
+        attrs.push(Attr {
+            id: ((id_len >> ID_SHIFT) & ID_MASK) as u8,
+            extended: id_len & EXTENDED != 0,
+            data: &data[HEADER_LEN..len],
+        });
+        data = &data[padded(len).min(data.len())..];
+    }
+    Ok(attrs)
+}
+
+/// The value of a big endian integer attribute
+pub(crate) fn to_u32(data: &[u8]) -> Option<u32> {
+    match data {
+        [a, b, c, d] => Some(u32::from_be_bytes([*a, *b, *c, *d])),
+        _ => None,
+    }
+}
+
+/// Appends a json value as blobmsg attribute with the given name. Array elements have an empty name.
+fn put_value(buf: &mut Vec<u8>, name: &str, value: &Value) {
+    // The name is nul terminated and padded, the header length is included in the padding
+    let mut data = (name.len() as u16).to_be_bytes().to_vec();
+    data.extend_from_slice(name.as_bytes());
+    data.push(0);
+    data.resize(padded(data.len()), 0);
+
+    let id = match value {
+        Value::Null => TYPE_UNSPEC,
+        Value::Bool(value) => {
+            data.push(*value as u8);
+            TYPE_INT8
+        },
+        Value::Number(number) => match (number.as_i64(), number.as_f64()) {
+            (Some(value), _) if value >= i64::from(i32::MIN) && value <= i64::from(i32::MAX) => {
+                data.extend_from_slice(&(value as i32).to_be_bytes());
+                TYPE_INT32
+            },
+            (Some(value), _) => {
+                data.extend_from_slice(&value.to_be_bytes());
+                TYPE_INT64
+            },
+            (None, value) => {
+                data.extend_from_slice(&value.unwrap_or_default().to_bits().to_be_bytes());
+                TYPE_DOUBLE
+            },
+        },
+        Value::String(value) => {
+            data.extend_from_slice(value.as_bytes());
+            data.push(0);
+            TYPE_STRING
+        },
+        Value::Array(values) => {
+            for value in values {
+                put_value(&mut data, "", value);
+            }
+            TYPE_ARRAY
+        },
+        Value::Object(values) => {
+            put_table(&mut data, values);
+            TYPE_TABLE
+        },
+    };
+    put_attr(buf, id, true, &data);
+}
+
+/// Appends the fields of a json object as blobmsg attributes
+pub(crate) fn put_table(buf: &mut Vec<u8>, values: &Map<String, Value>) {
+    for (name, value) in values {
+        put_value(buf, name, value);
+    }
+}
+
+/// Returns the name and the json value of a blobmsg attribute
+fn to_value(attr: &Attr) -> Result<(String, Value), CaptivePortalError> {
+    if !attr.extended || attr.data.len() < 2 {
+        return Err(invalid("not a blobmsg attribute"));
+    }
+    let name_len = u16::from_be_bytes([attr.data[0], attr.data[1]]) as usize;
+    let header_len = padded(2 + name_len + 1);
+    if header_len > attr.data.len() {
+        return Err(invalid("blobmsg name length"));
+    }
+    let name = String::from_utf8_lossy(&attr.data[2..2 + name_len]).into_owned();
+    let data = &attr.data[header_len..];
+
+    let value = match (attr.id, data) {
+        (TYPE_ARRAY, data) => Value::Array(
+            parse(data)?
+                .iter()
+                .map(|attr| to_value(attr).map(|(_, value)| value))
+                .collect::<Result<_, _>>()?,
+        ),
+        (TYPE_TABLE, data) => to_json(data)?,
+        (TYPE_STRING, data) => {
+            let data = data.split(|b| *b == 0).next().unwrap_or_default();
+            Value::String(String::from_utf8_lossy(data).into_owned())
+        },
+        (TYPE_INT64, [a, b, c, d, e, f, g, h]) => Value::from(i64::from_be_bytes([*a, *b, *c, *d, *e, *f, *g, *h])),
+        (TYPE_INT32, [a, b, c, d]) => Value::from(i32::from_be_bytes([*a, *b, *c, *d])),
+        (TYPE_INT16, [a, b]) => Value::from(i16::from_be_bytes([*a, *b])),
+        (TYPE_INT8, [a]) => Value::Bool(*a != 0),
+        (TYPE_DOUBLE, [a, b, c, d, e, f, g, h]) => {
+            let value = f64::from_bits(u64::from_be_bytes([*a, *b, *c, *d, *e, *f, *g, *h]));
+            Number::from_f64(value).map(Value::Number).unwrap_or(Value::Null)
+        },
+        (TYPE_UNSPEC, _) => Value::Null,
+        (id, _) => return Err(invalid(&format!("blobmsg type {} of {}", id, name))),
+    };
+    Ok((name, value))
+}
+
+/// Translates a blobmsg table, given by the data of its attribute, into a json object
+pub(crate) fn to_json(data: &[u8]) -> Result<Value, CaptivePortalError> {
+    let mut values = Map::new();
+    for attr in parse(data)? {
+        let (name, value) = to_value(&attr)?;
+        values.insert(name, value);
+    }
+    Ok(Value::Object(values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn json_round_trip() {
+        let value = json!({
+            "config": "wireless",
+            "values": { "disabled": false, "maxassoc": 10, "tx": 5_000_000_000i64, "ratio": 0.5 },
+            "maclist": ["00:11:22:33:44:55", "66:77:88:99:AA:BB"],
+            "section": "wifi_captive_ap",
+            "none": null,
+        });
+        let mut buf = Vec::new();
+        put_table(&mut buf, value.as_object().unwrap());
+        assert_eq!(buf.len() % 4, 0);
+        assert_eq!(to_json(&buf).unwrap(), value);
+    }
+
+    #[test]
+    fn encoding() {
+        let mut buf = Vec::new();
+        put_table(&mut buf, json!({ "a": 1 }).as_object().unwrap());
+        // Extended attribute of type int32, 4 bytes header, 4 bytes name header ("a" and nul), 4 bytes value
+        assert_eq!(buf, vec![0x85, 0, 0, 12, 0, 1, b'a', 0, 0, 0, 0, 1]);
+
+        let mut buf = Vec::new();
+        put_string(&mut buf, 2, "uci");
+        assert_eq!(buf, vec![0x02, 0, 0, 8, b'u', b'c', b'i', 0]);
+        let attrs = parse(&buf).unwrap();
+        assert_eq!((attrs[0].id, attrs[0].extended, attrs[0].data), (2, false, &b"uci\0"[..]));
+
+        assert!(parse(&[0, 0, 0, 12, 0]).is_err());
+    }
+}
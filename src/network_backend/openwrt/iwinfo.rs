@@ -0,0 +1,117 @@
+//! # Scan result parsing
+//! Converts the json output of `ubus call iwinfo scan` into [`WifiConnection`]s
+//! and the output of `ubus call iwinfo info` into a [`ConnectionQuality`].
+
+use crate::channels::channel_to_frequency;
+use crate::network_interface::{ConnectionQuality, Security, WifiConnection};
+use serde_json::Value;
+
+/// Determines the security type of a scan result "encryption" object
+fn security(encryption: &Value) -> Security {
+    if !encryption["enabled"].as_bool().unwrap_or(false) {
+        return Security::NONE;
+    }
+    let authentication = |name| {
+        encryption["authentication"]
+            .as_array()
+            .map(|a| a.iter().any(|v| v.as_str() == Some(name)))
+            .unwrap_or(false)
+    };
+    if authentication("802.1x") {
+        return Security::ENTERPRISE;
+    }
+    // WPA3 only. Access points in WPA2/WPA3 transition mode are treated as WPA2.
+    if authentication("sae") && !authentication("psk") {
+        return Security::WPA3;
+    }
+    let has_wpa = encryption["wpa"].as_array().map(|a| !a.is_empty()).unwrap_or(false);
+    match has_wpa {
+        true => Security::WPA2,
+        false => Security::WEP,
+    }
+}
+
+/// Parses the `results` array of a scan response. Hidden networks (empty SSID) are skipped.
+pub(crate) fn parse_scan_results(response: &Value, own_hw: &str) -> Vec<WifiConnection> {
+    let results = match response["results"].as_array() {
+        Some(results) => results,
+        None => return Vec::new(),
+    };
+    results
+        .iter()
+        .filter_map(|result| {
+            let ssid = result["ssid"].as_str().filter(|ssid| !ssid.is_empty())?;
+            let hw = result["bssid"].as_str()?.to_uppercase();
+            let quality = result["quality"].as_u64().unwrap_or(0);
+            let quality_max = result["quality_max"].as_u64().unwrap_or(0).max(1);
+            Some(WifiConnection {
+                ssid: ssid.into(),
+                is_own: hw.eq_ignore_ascii_case(own_hw),
+                vendor: crate::oui::vendor(&hw),
+                hw,
+                security: security(&result["encryption"]).as_str(),
+                strength: (quality * 100 / quality_max).min(100) as u8,
+                frequency: channel_to_frequency(result["channel"].as_u64().unwrap_or(0) as u32),
+            })
+        })
+        .collect()
+}
+
+/// Parses the signal quality and bitrate (kbit/s) of an info response. Both are missing if not associated.
+pub(crate) fn parse_quality(info: &Value) -> ConnectionQuality {
+    let quality_max = info["quality_max"].as_u64().unwrap_or(0).max(1);
+    ConnectionQuality {
+        signal: info["quality"]
+            .as_u64()
+            .map(|quality| (quality * 100 / quality_max).min(100) as u8),
+        bitrate: info["bitrate"].as_u64().map(|bitrate| bitrate as u32),
+        gateway_rtt: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_quality, parse_scan_results};
+    use serde_json::json;
+
+    #[test]
+    fn scan_results() {
+        let response = json!({ "results": [
+            { "ssid": "Home", "bssid": "aa:bb:cc:dd:ee:ff", "channel": 6, "quality": 35, "quality_max": 70,
+              "encryption": { "enabled": true, "wpa": [2], "authentication": ["psk"] } },
+            { "ssid": "Office", "bssid": "11:22:33:44:55:66", "channel": 36, "quality": 70, "quality_max": 70,
+              "encryption": { "enabled": true, "wpa": [2], "authentication": ["802.1x"] } },
+            { "ssid": "", "bssid": "11:22:33:44:55:67", "channel": 1, "quality": 10, "quality_max": 70,
+              "encryption": { "enabled": false } },
+            { "ssid": "Cafe", "bssid": "11:22:33:44:55:68", "channel": 1, "quality": 10, "quality_max": 70,
+              "encryption": { "enabled": false } },
+            { "ssid": "Lab", "bssid": "11:22:33:44:55:69", "channel": 1, "quality": 10, "quality_max": 70,
+              "encryption": { "enabled": true, "wpa": [3], "authentication": ["sae"] } },
+        ]});
+        let aps = parse_scan_results(&response, "11:22:33:44:55:66");
+        assert_eq!(aps.len(), 4);
+
+        assert_eq!(aps[0].hw, "AA:BB:CC:DD:EE:FF");
+        assert_eq!(aps[0].security, "wpa");
+        assert_eq!(aps[0].strength, 50);
+        assert_eq!(aps[0].frequency, 2437);
+        assert!(!aps[0].is_own);
+
+        assert_eq!(aps[1].security, "enterprise");
+        assert_eq!(aps[1].frequency, 5180);
+        assert!(aps[1].is_own);
+
+        assert_eq!(aps[2].ssid, "Cafe");
+        assert_eq!(aps[2].security, "none");
+
+        assert_eq!(aps[3].security, "wpa3");
+    }
+
+    #[test]
+    fn quality() {
+        let quality = parse_quality(&json!({ "quality": 42, "quality_max": 70, "bitrate": 65000 }));
+        assert_eq!(quality.signal, Some(60));
+        assert_eq!(quality.bitrate, Some(65000));
+        assert_eq!(parse_quality(&json!({})).signal, None);
+    }
+}
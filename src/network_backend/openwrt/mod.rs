@@ -0,0 +1,675 @@
+//! # The OpenWrt backend
+//! Drives netifd and the wireless configuration of OpenWrt via ubus and uci.
+//! See https://openwrt.org/docs/techref/ubus for API documentation.
+//!
+//! The station connection is configured as uci wifi-iface section "wifi_captive_sta" attached
+//! to the network interface "wwan" (dhcp), which is created if it does not exist yet.
+//! A static IPv4 configuration replaces the "wwan" interface with a static one.
+//! The hotspot is configured as wifi-iface section "wifi_captive_ap" attached to the static network
+//! interface "wifi_captive" with the portal gateway address. Both sections are committed,
+//! because netifd only applies committed configuration.
+//!
+//! There are no scan result notifications. The access point change stream scans periodically.
+//! The firewall configuration is not touched: Allow dns, dhcp and http input on the hotspot network.
+
+mod access_points_changed;
+mod blobmsg;
+mod iwinfo;
+mod ubus;
+
+use crate::mac_filter::{MacFilter, MacFilterSync};
+use crate::network_interface::{
+    AccessPointCredentials, ActiveConnection, ConnectOptions, ConnectionQuality, ConnectionState, HotspotBand,
+    HotspotRadio, HotspotSecurity, NetworkManagerState, Pmf, WifiConnection, SSID,
+};
+use crate::network_backend::WifiBackend;
+use crate::CaptivePortalError;
+
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::runtime::delay_for;
+
+// Public API: AccessPointsChangedStream
+pub use access_points_changed::{ap_changed_stream, AccessPointChanged};
+
+/// The handle of a connection of this backend, see [`crate::ActiveConnection`]:
+/// The uci wifi-iface section or the netifd network interface. There is no D-Bus object behind it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionPath(String);
+
+/// The uci wifi-iface section of the station connection
+const STA_SECTION: &str = "wifi_captive_sta";
+/// The uci wifi-iface section of the hotspot
+const AP_SECTION: &str = "wifi_captive_ap";
+/// The uci wifi-iface section of the hidden OWE hotspot of an OWE transition mode pair
+const AP_OWE_SECTION: &str = "wifi_captive_ap_owe";
+/// Interface names of an OWE transition mode pair. Each access point announces the other one.
+const AP_IFNAME: &str = "wcap";
+const AP_OWE_IFNAME: &str = "wcap-owe";
+/// The network interface of the station connection. This is the OpenWrt convention for wifi uplinks.
+const STA_NETWORK: &str = "wwan";
+/// The network interface of the hotspot
+const AP_NETWORK: &str = "wifi_captive";
+/// Polling interval for netifd state changes
+const POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Returns the (dbus compatible) path that identifies an access point by its hw address
+pub(crate) fn access_point_path(hw: &str) -> String {
+    format!("/ap/{}", hw.replace(':', ""))
+}
+
+#[derive(Clone)]
+pub struct NetworkBackend {
+    /// The radio (uci wifi-device) of the wifi interface
+    radio: String,
+    /// Mac address of the own network interface
+    hw: String,
+    /// Network interface name
+    interface_name: String,
+    /// Last scan results, by access point path. Removed access points are kept.
+    access_points: Arc<Mutex<HashMap<String, WifiConnection>>>,
+    /// Offer an open hotspot as OWE transition mode pair
+    owe_transition: bool,
+    /// Protected management frames of the station connection and the hotspot
+    pmf: Pmf,
+    /// The key management of the hotspot
+    hotspot_security: HotspotSecurity,
+    /// The maximum number of associated hotspot clients
+    max_clients: Option<u32>,
+    /// The clients that may associate with the hotspot
+    mac_filter: MacFilterSync,
+}
+
+impl NetworkBackend {
+    /// Finds the wifi interface and its radio. Returns an error if no wifi device can be found.
+    pub async fn new(interface_name: &Option<String>) -> Result<NetworkBackend, CaptivePortalError> {
+        let devices = ubus::call("iwinfo", "devices", json!({})).await?;
+        let devices: Vec<&str> = devices["devices"]
+            .as_array()
+            .map(|d| d.iter().filter_map(|d| d.as_str()).collect())
+            .unwrap_or_default();
+        let interface_name = match interface_name {
+            Some(interface_name) => devices.into_iter().find(|d| d == interface_name),
+            None => devices.into_iter().next(),
+        }
+        .ok_or(CaptivePortalError::NoWifiDeviceFound)?
+        .to_owned();
+
+        let info = ubus::call("iwinfo", "info", json!({ "device": &interface_name })).await?;
+        let hw = info["bssid"].as_str().unwrap_or_default().to_uppercase();
+
+        let status = ubus::call("network.wireless", "status", json!({})).await?;
+        let radio = find_radio(&status, &interface_name).ok_or(CaptivePortalError::NoWifiDeviceFound)?;
+
+        info!("Using wifi interface {} on {}", interface_name, radio);
+        Ok(NetworkBackend {
+            radio,
+            hw,
+            interface_name,
+            access_points: Arc::new(Mutex::new(HashMap::new())),
+            owe_transition: false,
+            pmf: Pmf::Auto,
+            hotspot_security: HotspotSecurity::Wpa2,
+            max_clients: None,
+            mac_filter: MacFilterSync::default(),
+        })
+    }
+
+    /// uci configuration is always committed to flash. In-memory mode is not supported.
+    pub fn set_in_memory(&mut self, in_memory: bool) {
+        if in_memory {
+            warn!("The OpenWrt backend cannot keep connections in memory only. uci commits to flash.");
+        }
+    }
+
+    /// Offer an open hotspot as OWE transition mode pair: The open access point and a hidden OWE access point.
+    /// hostapd must be built with OWE support ("wpad-openssl" or "wpad-wolfssl").
+    pub fn set_owe_transition(&mut self, owe_transition: bool) -> Result<(), CaptivePortalError> {
+        self.owe_transition = owe_transition;
+        Ok(())
+    }
+
+    /// Protected management frames (802.11w) setting of the station connection and the hotspot
+    pub fn set_pmf(&mut self, pmf: Pmf) {
+        self.pmf = pmf;
+    }
+
+    /// The maximum number of clients of the hotspot ("maxassoc" of the access point)
+    pub fn set_max_clients(&mut self, max_clients: Option<u32>) {
+        self.max_clients = max_clients;
+    }
+
+    /// The clients that may associate with the hotspot ("macfilter" and "maclist" of the access point).
+    /// The filter is applied when the hotspot starts.
+    pub fn set_mac_filter(&mut self, mac_filter: MacFilterSync) {
+        self.mac_filter = mac_filter;
+    }
+
+    /// The key management of the hotspot. WPA3 requires a hostapd with SAE support ("wpad-openssl" or
+    /// "wpad-wolfssl"), otherwise the hotspot uses WPA2.
+    pub fn set_hotspot_security(&mut self, security: HotspotSecurity) {
+        self.hotspot_security = security;
+    }
+
+    /// Whether the backend assigns the address given to [`NetworkBackend::hotspot_start`].
+    /// The hotspot address is configured in the uci network section.
+    pub fn assigns_hotspot_address(&self) -> bool {
+        true
+    }
+
+    /// The message key of the reason why the last connection attempt failed, see [`crate::i18n`].
+    /// Failure reasons are not reported by this backend.
+    pub fn last_failure(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// The name of the wifi network interface
+    pub fn interface_name(&self) -> &str {
+        &self.interface_name
+    }
+
+    /// The SSID of the wifi interface as reported by iwinfo. None if the interface is not associated.
+    pub async fn active_ssid(&self) -> Result<Option<SSID>, CaptivePortalError> {
+        let info = ubus::call("iwinfo", "info", json!({ "device": &self.interface_name })).await?;
+        Ok(info["ssid"].as_str().filter(|ssid| !ssid.is_empty()).map(SSID::from))
+    }
+
+    /// The signal quality and bitrate of the wifi interface as reported by iwinfo.
+    /// The gateway round trip time is not measured by the backend, see [`crate::quality`].
+    pub async fn connection_quality(&self) -> Result<ConnectionQuality, CaptivePortalError> {
+        let info = ubus::call("iwinfo", "info", json!({ "device": &self.interface_name })).await?;
+        Ok(iwinfo::parse_quality(&info))
+    }
+
+    /// Brings all wifi radios up.
+    pub async fn enable_networking_and_wifi(&self) -> Result<(), CaptivePortalError> {
+        ubus::call("network.wireless", "up", json!({})).await?;
+        Ok(())
+    }
+
+    /// iwinfo performs a scan on each request. Nothing to do here.
+    pub async fn scan_networks(&self) -> Result<(), CaptivePortalError> {
+        Ok(())
+    }
+
+    /// There is no persistent connection to terminate
+    pub fn quit(self) {}
+
+    /// The state of the station network interface
+    pub async fn state(&self) -> Result<NetworkManagerState, CaptivePortalError> {
+        let status = match ubus::call(&format!("network.interface.{}", STA_NETWORK), "status", json!({})).await {
+            Ok(status) => status,
+            // The interface does not exist (yet)
+            Err(CaptivePortalError::UbusError(_)) => return Ok(NetworkManagerState::Disconnected),
+            Err(e) => return Err(e),
+        };
+        let has_default_route = status["route"]
+            .as_array()
+            .map(|r| r.iter().any(|r| r["target"].as_str() == Some("0.0.0.0") && r["mask"] == 0))
+            .unwrap_or(false);
+        Ok(
+            match (status["up"].as_bool(), status["pending"].as_bool(), has_default_route) {
+                (Some(true), _, true) => NetworkManagerState::Connected,
+                (Some(true), _, false) => NetworkManagerState::ConnectedLimited,
+                (_, Some(true), _) => NetworkManagerState::Connecting,
+                _ => NetworkManagerState::Disconnected,
+            },
+        )
+    }
+
+    /// Brings the wifi up, which lets netifd connect to the configured station network.
+    pub async fn try_auto_connect(&self, timeout: std::time::Duration) -> Result<bool, CaptivePortalError> {
+        self.enable_networking_and_wifi().await?;
+
+        match self.wait_for_connectivity(false, timeout).await {
+            Ok(state) => Ok(state == NetworkManagerState::Connected || state == NetworkManagerState::ConnectedLimited),
+            Err(CaptivePortalError::NotRequiredConnectivity(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The returned future resolves when either the timeout expired or the station network
+    /// interface reports (internet) connectivity.
+    pub async fn wait_for_connectivity(
+        &self,
+        internet_connectivity: bool,
+        timeout: std::time::Duration,
+    ) -> Result<NetworkManagerState, CaptivePortalError> {
+        self.connectivity_changed(timeout, |state| {
+            state == NetworkManagerState::Connected
+                || (state == NetworkManagerState::ConnectedLimited && !internet_connectivity)
+        })
+        .await
+    }
+
+    /// The returned future resolves when either the timeout expired or (internet) connectivity is lost
+    pub async fn wait_for_connectivity_lost(
+        &self,
+        internet_connectivity: bool,
+        timeout: std::time::Duration,
+    ) -> Result<NetworkManagerState, CaptivePortalError> {
+        self.connectivity_changed(timeout, |state| {
+            state != NetworkManagerState::Connected
+                && (state != NetworkManagerState::ConnectedLimited || internet_connectivity)
+        })
+        .await
+    }
+
+    /// Polls the station network interface state until "condition" is met or "timeout" expired.
+    async fn connectivity_changed<F>(
+        &self,
+        timeout: std::time::Duration,
+        condition: F,
+    ) -> Result<NetworkManagerState, CaptivePortalError>
+    where
+        F: Fn(NetworkManagerState) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let state = self.state().await?;
+            if condition(state) {
+                return Ok(state);
+            }
+            if Instant::now() >= deadline {
+                return Err(CaptivePortalError::NotRequiredConnectivity(state));
+            }
+            delay_for(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Connect to the given SSID with the given credentials.
+    /// The station configuration of this service is replaced, so "overwrite_same_ssid_connection" has no effect.
+    /// If "hw" is set, the connection is restricted to that access point.
+    /// Hidden networks need no special configuration, netifd lets wpa_supplicant probe for the ssid of stations.
+    /// If "ipv4" is set, the "wwan" interface is configured statically. It is not reverted to dhcp afterwards.
+    /// An IPv6 configuration is not supported, the "wwan6" interface of OpenWrt is left as it is.
+    ///
+    /// The configuration is removed again if no connection can be established within 30 seconds.
+    pub async fn connect_to(
+        &self,
+        ssid: SSID,
+        credentials: AccessPointCredentials,
+        hw: Option<String>,
+        options: ConnectOptions,
+    ) -> Result<Option<ActiveConnection>, CaptivePortalError> {
+        let ConnectOptions { ipv4, ipv6, .. } = options;
+        if ipv6.is_some() {
+            warn!("IPv6 configuration is not supported by the OpenWrt backend");
+        }
+        // UCI options are strings
+        let name = match ssid.as_str() {
+            Some(name) => name.to_owned(),
+            None => {
+                warn!("The OpenWrt backend does not support SSIDs that are not valid UTF8: {:?}", ssid);
+                return Ok(None);
+            },
+        };
+        if let Some(ipv4) = ipv4 {
+            let mut network = json!({
+                "proto": "static",
+                "ipaddr": ipv4.address.to_string(),
+                "netmask": ipv4.netmask().to_string(),
+            });
+            if let Some(gateway) = ipv4.gateway {
+                network["gateway"] = gateway.to_string().into();
+            }
+            if !ipv4.dns.is_empty() {
+                network["dns"] = ipv4.dns.iter().map(|d| d.to_string()).collect::<Vec<_>>().into();
+            }
+            ubus::uci_replace_section("network", "interface", STA_NETWORK, network).await?;
+        } else if !ubus::uci_section_exists("network", STA_NETWORK).await {
+            ubus::uci_replace_section("network", "interface", STA_NETWORK, json!({ "proto": "dhcp" })).await?;
+        }
+
+        let mut values = json!({
+            "device": &self.radio,
+            "network": STA_NETWORK,
+            "mode": "sta",
+            "ssid": name,
+        });
+        if let Some(ieee80211w) = ieee80211w(self.pmf.for_credentials(&credentials)) {
+            values["ieee80211w"] = ieee80211w.into();
+        }
+        match credentials {
+            AccessPointCredentials::None => {
+                values["encryption"] = "none".into();
+            },
+            AccessPointCredentials::Wep { passphrase } => {
+                values["encryption"] = "wep".into();
+                values["key"] = passphrase.into();
+            },
+            AccessPointCredentials::Wpa { passphrase } => {
+                values["encryption"] = "psk2".into();
+                values["key"] = passphrase.into();
+            },
+            AccessPointCredentials::Sae { passphrase } => {
+                values["encryption"] = "sae".into();
+                values["key"] = passphrase.into();
+            },
+            AccessPointCredentials::Enterprise {
+                identity,
+                passphrase,
+                anonymous_identity,
+                method,
+            } => {
+                values["encryption"] = "wpa2".into();
+                values["eap_type"] = method.as_str().into();
+                values["auth"] = method.phase2().to_uppercase().into();
+                values["identity"] = identity.into();
+                if let Some(anonymous_identity) = anonymous_identity {
+                    values["anonymous_identity"] = anonymous_identity.into();
+                }
+                values["password"] = passphrase.into();
+            },
+        }
+        if let Some(hw) = hw {
+            values["bssid"] = hw.into();
+        }
+
+        ubus::uci_replace_section("wireless", "wifi-iface", STA_SECTION, values).await?;
+        ubus::uci_commit_and_reload(&["network", "wireless"]).await?;
+
+        match self.wait_for_connectivity(false, Duration::from_secs(30)).await {
+            Ok(_) => Ok(Some(ActiveConnection {
+                connection_path: ConnectionPath(STA_SECTION.to_owned()),
+                active_connection_path: ConnectionPath(STA_NETWORK.to_owned()),
+                state: ConnectionState::Activated,
+            })),
+            Err(CaptivePortalError::NotRequiredConnectivity(_)) => {
+                ubus::uci_delete_section("wireless", STA_SECTION).await?;
+                ubus::uci_commit_and_reload(&["wireless"]).await?;
+                Ok(None)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Aborts a connection attempt that has been cancelled while [`connect_to`] was in progress.
+    /// Removes the station configuration, like a failed attempt does.
+    pub async fn abort_connect(&self) -> Result<(), CaptivePortalError> {
+        if ubus::uci_section_exists("wireless", STA_SECTION).await {
+            ubus::uci_delete_section("wireless", STA_SECTION).await?;
+            ubus::uci_commit_and_reload(&["wireless"]).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes the station configuration of this service if it is for the given SSID.
+    /// Returns false if there is none.
+    pub async fn forget(&self, ssid: &SSID) -> Result<bool, CaptivePortalError> {
+        let configured = match ubus::call("uci", "get", json!({ "config": "wireless", "section": STA_SECTION })).await {
+            Ok(section) => section["values"]["ssid"].as_str().map_or(false, |s| *ssid == s),
+            Err(_) => false,
+        };
+        if configured {
+            ubus::uci_delete_section("wireless", STA_SECTION).await?;
+            ubus::uci_commit_and_reload(&["wireless"]).await?;
+        }
+        Ok(configured)
+    }
+
+    /// Get access point data for the given access point path. The access point must have
+    /// been part of a scan result before.
+    pub async fn access_point<P: AsRef<str>>(&self, ap_path: P) -> Result<WifiConnection, CaptivePortalError> {
+        let ap_path = ap_path.as_ref();
+        let access_points = self.access_points.lock().expect("Lock access point cache");
+        access_points
+            .get(ap_path)
+            .cloned()
+            .ok_or_else(|| CaptivePortalError::UbusError(format!("Unknown access point {}", ap_path)))
+    }
+
+    /// Paths of all access points that have been seen so far
+    pub(crate) fn cached_access_point_paths(&self) -> HashSet<String> {
+        let access_points = self.access_points.lock().expect("Lock access point cache");
+        access_points.keys().cloned().collect()
+    }
+
+    /// Scans and returns all access points.
+    ///
+    /// ## Arguments
+    /// * timeout: If timeout is != 0, scans are repeated up to timeout until there is at least one result.
+    pub async fn list_access_points(
+        &self,
+        mut timeout: std::time::Duration,
+    ) -> Result<Vec<WifiConnection>, CaptivePortalError> {
+        let interval = Duration::from_millis(500);
+        let connections = loop {
+            let response = ubus::call("iwinfo", "scan", json!({ "device": &self.interface_name })).await?;
+            let connections: Vec<WifiConnection> = iwinfo::parse_scan_results(&response, &self.hw)
+                .into_iter()
+                .filter(|ap| !ap.is_own)
+                .collect();
+            if !connections.is_empty() || timeout < interval {
+                break connections;
+            }
+            delay_for(interval).await;
+            timeout -= interval;
+        };
+
+        let mut access_points = self.access_points.lock().expect("Lock access point cache");
+        for connection in connections.iter() {
+            access_points.insert(access_point_path(&connection.hw), connection.clone());
+        }
+        Ok(connections)
+    }
+
+    /// Removes the hotspot configuration of this service, if any.
+    pub async fn deactivate_hotspots(&self) -> Result<(), CaptivePortalError> {
+        let mut changed = false;
+        for section in &[AP_SECTION, AP_OWE_SECTION] {
+            if ubus::uci_section_exists("wireless", section).await {
+                ubus::uci_delete_section("wireless", section).await?;
+                changed = true;
+            }
+        }
+        if changed {
+            ubus::uci_commit_and_reload(&["wireless"]).await?;
+        }
+        Ok(())
+    }
+
+    /// The configured hotspot security if hostapd supports SAE, WPA2 otherwise
+    async fn supported_hotspot_security(&self) -> HotspotSecurity {
+        if self.hotspot_security == HotspotSecurity::Wpa2 {
+            return HotspotSecurity::Wpa2;
+        }
+        // hostapd exits with success if it has been built with the given feature
+        let sae = tokio::process::Command::new("hostapd")
+            .arg("-vsae")
+            .status()
+            .await
+            .map(|status| status.success())
+            .unwrap_or(false);
+        if !sae {
+            warn!("hostapd does not support SAE. The hotspot uses WPA2.");
+            return HotspotSecurity::Wpa2;
+        }
+        self.hotspot_security
+    }
+
+    /// Starts a hotspot. The hotspot network interface gets the given address (/24).
+    /// An open hotspot is started as OWE transition mode pair if enabled, see [`NetworkBackend::set_owe_transition`].
+    pub async fn hotspot_start(
+        &self,
+        ssid: SSID,
+        password: String,
+        address: Option<Ipv4Addr>,
+        radio: Option<HotspotRadio>,
+    ) -> Result<ActiveConnection, CaptivePortalError> {
+        let address = address
+            .ok_or_else(|| CaptivePortalError::Generic("The OpenWrt backend requires a hotspot address".to_owned()))?;
+
+        let owe_transition = self.owe_transition && password.is_empty();
+
+        debug!("Configuring hotspot ...");
+        let mut network = json!({ "proto": "static", "ipaddr": address.to_string(), "netmask": "255.255.255.0" });
+        if owe_transition {
+            // Both access points share the network
+            network["type"] = "bridge".into();
+        }
+        ubus::uci_replace_section("network", "interface", AP_NETWORK, network).await?;
+
+        let mut values = json!({
+            "device": &self.radio,
+            "network": AP_NETWORK,
+            "mode": "ap",
+            "ssid": ssid.to_string(),
+            "encryption": "none",
+        });
+        if !password.is_empty() {
+            values["encryption"] = hotspot_encryption(self.supported_hotspot_security().await).into();
+            values["key"] = password.into();
+            if let Some(ieee80211w) = ieee80211w(self.pmf) {
+                values["ieee80211w"] = ieee80211w.into();
+            }
+        }
+        if let Some(max_clients) = self.max_clients {
+            values["maxassoc"] = max_clients.to_string().into();
+        }
+        let mac_filter = hostapd_mac_filter(&self.mac_filter.read().expect("mac filter lock"));
+        if let Some((policy, list)) = mac_filter.clone() {
+            values["macfilter"] = policy.into();
+            values["maclist"] = list.into();
+        }
+        if owe_transition {
+            values["ifname"] = AP_IFNAME.into();
+            values["owe_transition_ifname"] = AP_OWE_IFNAME.into();
+            let mut owe = json!({
+                "device": &self.radio,
+                "network": AP_NETWORK,
+                "mode": "ap",
+                "ssid": owe_ssid(&ssid.to_string()),
+                "encryption": "owe",
+                "hidden": "1",
+                "ifname": AP_OWE_IFNAME,
+                "owe_transition_ifname": AP_IFNAME,
+            });
+            if let Some(max_clients) = self.max_clients {
+                owe["maxassoc"] = max_clients.to_string().into();
+            }
+            if let Some((policy, list)) = mac_filter {
+                owe["macfilter"] = policy.into();
+                owe["maclist"] = list.into();
+            }
+            ubus::uci_replace_section("wireless", "wifi-iface", AP_OWE_SECTION, owe).await?;
+        } else if ubus::uci_section_exists("wireless", AP_OWE_SECTION).await {
+            ubus::uci_delete_section("wireless", AP_OWE_SECTION).await?;
+        }
+        ubus::uci_replace_section("wireless", "wifi-iface", AP_SECTION, values).await?;
+        if let Some(radio) = radio {
+            // The band and channel are options of the radio and stay configured for the station
+            let band = match radio.band {
+                HotspotBand::Bg => "2g",
+                HotspotBand::A => "5g",
+            };
+            let channel = radio.channel.map_or("auto".to_owned(), |c| c.to_string());
+            ubus::uci_set("wireless", &self.radio, json!({ "band": band, "channel": channel })).await?;
+        }
+
+        info!("Starting hotspot ...");
+        ubus::uci_commit_and_reload(&["network", "wireless"]).await?;
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !self.hotspot_is_up().await? {
+            if Instant::now() >= deadline {
+                warn!("Hotspot starting failed");
+                return Err(CaptivePortalError::HotspotFailed);
+            }
+            delay_for(POLL_INTERVAL).await;
+        }
+
+        Ok(ActiveConnection {
+            connection_path: ConnectionPath(AP_SECTION.to_owned()),
+            active_connection_path: ConnectionPath(AP_SECTION.to_owned()),
+            state: ConnectionState::Activated,
+        })
+    }
+
+    /// Resolves when the hotspot interface is no longer up.
+    pub async fn on_hotspot_stopped(&self, _path: ConnectionPath) -> Result<(), CaptivePortalError> {
+        while self.hotspot_is_up().await? {
+            delay_for(POLL_INTERVAL * 2).await;
+        }
+        Ok(())
+    }
+
+    /// Returns true if netifd reports the hotspot wifi-iface with an interface name on an active radio
+    async fn hotspot_is_up(&self) -> Result<bool, CaptivePortalError> {
+        let status = ubus::call("network.wireless", "status", json!({})).await?;
+        let radio = &status[&self.radio];
+        let up = radio["up"].as_bool().unwrap_or(false)
+            && radio["interfaces"]
+                .as_array()
+                .map(|i| {
+                    i.iter()
+                        .any(|i| i["section"].as_str() == Some(AP_SECTION) && i["ifname"].is_string())
+                })
+                .unwrap_or(false);
+        Ok(up)
+    }
+}
+
+impl WifiBackend for NetworkBackend {
+    wifi_backend_methods!();
+}
+
+/// The uci "encryption" wifi-iface option of a hotspot with a passphrase
+fn hotspot_encryption(security: HotspotSecurity) -> &'static str {
+    match security {
+        HotspotSecurity::Wpa2 => "psk2",
+        HotspotSecurity::Wpa3 => "sae",
+        HotspotSecurity::Transition => "sae-mixed",
+    }
+}
+
+/// The uci "ieee80211w" wifi-iface option. None for the default of the encryption.
+fn ieee80211w(pmf: Pmf) -> Option<&'static str> {
+    match pmf {
+        Pmf::Auto => None,
+        Pmf::Disabled => Some("0"),
+        Pmf::Optional => Some("1"),
+        Pmf::Required => Some("2"),
+    }
+}
+
+/// The SSID of the hidden OWE access point of a transition mode pair. SSIDs are limited to 32 bytes.
+fn owe_ssid(ssid: &str) -> String {
+    let mut end = ssid.len().min(32 - 4);
+    while !ssid.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}-OWE", &ssid[..end])
+}
+
+/// The uci "macfilter" policy and "maclist" of the hotspot. hostapd filters either by an allow or by a deny list:
+/// With allowed addresses, the denied ones are removed from the allow list. None without a filter.
+fn hostapd_mac_filter(filter: &MacFilter) -> Option<(&'static str, Vec<String>)> {
+    if !filter.allow.is_empty() {
+        let allow = filter.allow.iter().filter(|hw| !filter.deny.contains(hw)).cloned().collect();
+        Some(("allow", allow))
+    } else if !filter.deny.is_empty() {
+        Some(("deny", filter.deny.clone()))
+    } else {
+        None
+    }
+}
+
+/// Finds the radio with the given interface in a `network.wireless status` response.
+/// Falls back to the first radio.
+fn find_radio(status: &Value, interface_name: &str) -> Option<String> {
+    let radios = status.as_object()?;
+    radios
+        .iter()
+        .find(|(_, radio)| {
+            radio["interfaces"]
+                .as_array()
+                .map(|i| i.iter().any(|i| i["ifname"].as_str() == Some(interface_name)))
+                .unwrap_or(false)
+        })
+        .or_else(|| radios.iter().next())
+        .map(|(name, _)| name.clone())
+}
@@ -0,0 +1,233 @@
+//! # ubus and uci access
+//!
+//! Methods are invoked via the ubusd unix socket. The socket protocol is not json: Messages are blob attributes
+//! and method arguments and responses are blobmsg tables, see [`super::blobmsg`]. Each call uses its own
+//! connection: It looks up the object id by its path and invokes the method on it.
+
+use super::blobmsg;
+use crate::runtime;
+use crate::CaptivePortalError;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+/// The socket of current OpenWrt releases and the one of releases before 19.07
+const SOCKET_PATHS: [&str; 2] = ["/var/run/ubus/ubus.sock", "/var/run/ubus.sock"];
+/// The default timeout of the ubus command line tool
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// ubusd does not accept larger messages either
+const MAX_MESSAGE_LEN: usize = 1024 * 1024;
+
+// Message types
+const MSG_HELLO: u8 = 0;
+const MSG_STATUS: u8 = 1;
+const MSG_DATA: u8 = 2;
+const MSG_LOOKUP: u8 = 4;
+const MSG_INVOKE: u8 = 5;
+
+// Message attributes
+const ATTR_STATUS: u8 = 1;
+const ATTR_OBJPATH: u8 = 2;
+const ATTR_OBJID: u8 = 3;
+const ATTR_METHOD: u8 = 4;
+const ATTR_DATA: u8 = 7;
+
+/// The messages of ubus_strerror, like the ubus command line tool prints them
+fn status_message(status: u32) -> &'static str {
+    match status {
+        1 => "Invalid command",
+        2 => "Invalid argument",
+        3 => "Method not found",
+        4 => "Not found",
+        5 => "No response",
+        6 => "Permission denied",
+        7 => "Request timed out",
+        8 => "Operation not supported",
+        10 => "Connection failed",
+        _ => "Unknown error",
+    }
+}
+
+/// A message: The type, the sequence number of the request it belongs to and its attributes
+struct Message {
+    message_type: u8,
+    seq: u16,
+    data: Vec<u8>,
+}
+
+impl Message {
+    fn attr(&self, id: u8) -> Result<Option<&[u8]>, CaptivePortalError> {
+        Ok(blobmsg::parse(&self.data)?.into_iter().find(|a| a.id == id).map(|a| a.data))
+    }
+}
+
+struct Connection {
+    stream: UnixStream,
+    seq: u16,
+}
+
+impl Connection {
+    /// Connects and waits for the hello message of ubusd
+    async fn connect() -> Result<Connection, CaptivePortalError> {
+        let stream = match UnixStream::connect(SOCKET_PATHS[0]).await {
+            Ok(stream) => stream,
+            Err(_) => UnixStream::connect(SOCKET_PATHS[1])
+                .await
+                .map_err(|e| CaptivePortalError::IO(e, "Failed to connect to ubusd"))?,
+        };
+        let mut connection = Connection { stream, seq: 0 };
+        match connection.receive().await?.message_type {
+            MSG_HELLO => Ok(connection),
+            _ => Err(CaptivePortalError::UbusError("No hello message".to_owned())),
+        }
+    }
+
+    async fn receive(&mut self) -> Result<Message, CaptivePortalError> {
+        let read_error = |e| CaptivePortalError::IO(e, "Failed to read from ubusd");
+        // Version, type, sequence number, peer and the header of the attribute that contains the message
+        let mut header = [0u8; 12];
+        self.stream.read_exact(&mut header).await.map_err(read_error)?;
+        let len = blobmsg::data_len([header[8], header[9], header[10], header[11]])?;
+        if len > MAX_MESSAGE_LEN {
+            return Err(CaptivePortalError::UbusError("Message too large".to_owned()));
+        }
+        let mut data = vec![0u8; len];
+        self.stream.read_exact(&mut data).await.map_err(read_error)?;
+        Ok(Message {
+            message_type: header[1],
+            seq: u16::from_be_bytes([header[2], header[3]]),
+            data,
+        })
+    }
+
+    /// Sends a request and returns the data messages of the response and its status. 0 means success.
+    async fn request(
+        &mut self,
+        message_type: u8,
+        peer: u32,
+        attrs: Vec<u8>,
+    ) -> Result<(Vec<Message>, u32), CaptivePortalError> {
+        self.seq = self.seq.wrapping_add(1);
+        let mut message = vec![0, message_type];
+        message.extend_from_slice(&self.seq.to_be_bytes());
+        message.extend_from_slice(&peer.to_be_bytes());
+        message.extend_from_slice(&blobmsg::header(0, false, attrs.len()));
+        message.extend_from_slice(&attrs);
+        self.stream
+            .write_all(&message)
+            .await
+            .map_err(|e| CaptivePortalError::IO(e, "Failed to write to ubusd"))?;
+
+        let mut data = Vec::new();
+        loop {
+            let message = self.receive().await?;
+            if message.seq != self.seq {
+                continue;
+            }
+            match message.message_type {
+                MSG_DATA => data.push(message),
+                MSG_STATUS => {
+                    let status = message.attr(ATTR_STATUS)?.and_then(blobmsg::to_u32).unwrap_or(0);
+                    return Ok((data, status));
+                },
+                _ => {},
+            }
+        }
+    }
+
+    async fn call(&mut self, object: &str, method: &str, args: Value) -> Result<Value, CaptivePortalError> {
+        let failed = |status: u32| {
+            CaptivePortalError::UbusError(format!("{} {}: {}", object, method, status_message(status)))
+        };
+
+        let mut attrs = Vec::new();
+        blobmsg::put_string(&mut attrs, ATTR_OBJPATH, object);
+        let (data, status) = self.request(MSG_LOOKUP, 0, attrs).await?;
+        let mut object_id = None;
+        for message in &data {
+            object_id = object_id.or(message.attr(ATTR_OBJID)?.and_then(blobmsg::to_u32));
+        }
+        let object_id = match (status, object_id) {
+            (0, Some(object_id)) => object_id,
+            (0, None) => return Err(failed(4)),
+            (status, _) => return Err(failed(status)),
+        };
+
+        let mut attrs = Vec::new();
+        blobmsg::put(&mut attrs, ATTR_OBJID, &object_id.to_be_bytes());
+        blobmsg::put_string(&mut attrs, ATTR_METHOD, method);
+        let mut table = Vec::new();
+        if let Some(args) = args.as_object() {
+            blobmsg::put_table(&mut table, args);
+        }
+        blobmsg::put(&mut attrs, ATTR_DATA, &table);
+        let (data, status) = self.request(MSG_INVOKE, object_id, attrs).await?;
+        if status != 0 {
+            return Err(failed(status));
+        }
+        match data.last() {
+            Some(message) => match message.attr(ATTR_DATA)? {
+                Some(table) => blobmsg::to_json(table),
+                None => Ok(Value::Null),
+            },
+            None => Ok(Value::Null),
+        }
+    }
+}
+
+/// Calls the given ubus object method with the given json arguments and returns the json response.
+/// Methods without a response return [`Value::Null`].
+pub(crate) async fn call(object: &str, method: &str, args: Value) -> Result<Value, CaptivePortalError> {
+    let call = async move { Connection::connect().await?.call(object, method, args).await };
+    runtime::timeout(TIMEOUT, call)
+        .await
+        .unwrap_or_else(|| Err(CaptivePortalError::UbusError(format!("{} {}: Request timed out", object, method))))
+}
+
+/// Returns true if the given uci section exists
+pub(crate) async fn uci_section_exists(config: &str, section: &str) -> bool {
+    call("uci", "get", json!({ "config": config, "section": section }))
+        .await
+        .is_ok()
+}
+
+/// Adds a named uci section with the given values. An existing section with the same name is replaced.
+pub(crate) async fn uci_replace_section(
+    config: &str,
+    section_type: &str,
+    section: &str,
+    values: Value,
+) -> Result<(), CaptivePortalError> {
+    uci_delete_section(config, section).await?;
+    call(
+        "uci",
+        "add",
+        json!({ "config": config, "type": section_type, "name": section, "values": values }),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Sets the given options of an existing uci section
+pub(crate) async fn uci_set(config: &str, section: &str, values: Value) -> Result<(), CaptivePortalError> {
+    call("uci", "set", json!({ "config": config, "section": section, "values": values })).await?;
+    Ok(())
+}
+
+/// Deletes the given uci section if it exists
+pub(crate) async fn uci_delete_section(config: &str, section: &str) -> Result<(), CaptivePortalError> {
+    if uci_section_exists(config, section).await {
+        call("uci", "delete", json!({ "config": config, "section": section })).await?;
+    }
+    Ok(())
+}
+
+/// Commits the given uci configurations and lets netifd apply the new configuration
+pub(crate) async fn uci_commit_and_reload(configs: &[&str]) -> Result<(), CaptivePortalError> {
+    for config in configs {
+        call("uci", "commit", json!({ "config": config })).await?;
+    }
+    call("network", "reload", json!({})).await?;
+    Ok(())
+}
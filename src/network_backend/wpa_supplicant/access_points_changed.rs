@@ -0,0 +1,94 @@
+//! # Access points change stream
+//! Provides a stream of added and removed wpa_supplicant BSS paths.
+
+use dbus::arg;
+use dbus::message::SignalArgs;
+use crate::runtime::BoxStream;
+use futures_util::stream::select;
+use futures_util::stream::StreamExt;
+
+use crate::dbus_tokio::SignalStream;
+use crate::network_backend::{NetworkBackend, WPA_BUSNAME};
+use crate::network_interface::WifiConnectionEventType;
+use crate::CaptivePortalError;
+
+pub struct AccessPointChanged {
+    pub path: String,
+    pub event: WifiConnectionEventType,
+}
+
+/// The BSSAdded signal also carries the BSS properties as dictionary. Those are not thread safe (RefArg)
+/// and not required, so only the path is read. This is why the signal type is not generated.
+#[derive(Debug)]
+struct BSSAdded {
+    path: dbus::Path<'static>,
+}
+
+impl arg::ReadAll for BSSAdded {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(BSSAdded { path: i.read()? })
+    }
+}
+
+impl SignalArgs for BSSAdded {
+    const NAME: &'static str = "BSSAdded";
+    const INTERFACE: &'static str = "fi.w1.wpa_supplicant1.Interface";
+}
+
+#[derive(Debug)]
+struct BSSRemoved {
+    path: dbus::Path<'static>,
+}
+
+impl arg::ReadAll for BSSRemoved {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(BSSRemoved { path: i.read()? })
+    }
+}
+
+impl SignalArgs for BSSRemoved {
+    const NAME: &'static str = "BSSRemoved";
+    const INTERFACE: &'static str = "fi.w1.wpa_supplicant1.Interface";
+}
+
+fn helper_1(v: (BSSAdded, String)) -> AccessPointChanged {
+    AccessPointChanged {
+        event: WifiConnectionEventType::Added,
+        path: v.0.path.to_string(),
+    }
+}
+
+fn helper_2(v: (BSSRemoved, String)) -> AccessPointChanged {
+    AccessPointChanged {
+        event: WifiConnectionEventType::Removed,
+        path: v.0.path.to_string(),
+    }
+}
+
+pub async fn ap_changed_stream(
+    network_manager: &NetworkBackend,
+) -> Result<BoxStream<'static, AccessPointChanged>, CaptivePortalError> {
+    // This is implemented via stream merging, because each subscription is encapsulated in its own stream.
+
+    let rule_added = BSSAdded::match_rule(
+        Some(&WPA_BUSNAME.to_owned().into()),
+        Some(&network_manager.wifi_device_path.clone().into()),
+    )
+    .static_clone();
+
+    let rule_removed = BSSRemoved::match_rule(
+        Some(&WPA_BUSNAME.to_owned().into()),
+        Some(&network_manager.wifi_device_path.clone().into()),
+    )
+    .static_clone();
+
+    let inner_stream_added = SignalStream::<BSSAdded>::new(network_manager.conn.clone(), rule_added)
+        .await?
+        .map(helper_1);
+
+    let inner_stream_removed = SignalStream::<BSSRemoved>::new(network_manager.conn.clone(), rule_removed)
+        .await?
+        .map(helper_2);
+
+    Ok(Box::pin(select(inner_stream_added, inner_stream_removed)))
+}
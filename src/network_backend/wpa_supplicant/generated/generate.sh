@@ -0,0 +1,6 @@
+#!/bin/sh -e
+# cargo install --git https://github.com/diwic/dbus-rs --force  dbus-codegen
+
+# The xml files only contain the subset of the APIs that is used by this backend
+cat wpa_supplicant.xml | dbus-codegen-rust -c nonblock -m None --dbuscrate ::dbus -o wpa_supplicant.rs
+cat networkd.xml | dbus-codegen-rust -c nonblock -m None --dbuscrate ::dbus -o networkd.rs
@@ -0,0 +1,3 @@
+#[cfg(feature = "networkd")]
+pub mod networkd;
+pub mod wpa_supplicant;
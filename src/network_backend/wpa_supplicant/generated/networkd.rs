@@ -0,0 +1,36 @@
+// This code was autogenerated with `dbus-codegen-rust -c nonblock -m None --dbuscrate ::dbus -o networkd.rs`, see https://github.com/diwic/dbus-rs
+use ::dbus;
+use ::dbus::nonblock;
+
+pub trait OrgFreedesktopNetwork1Manager {
+    fn get_link_by_name(&self, name: &str) -> nonblock::MethodReply<(i32, dbus::Path<'static>)>;
+}
+
+impl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target = T>> OrgFreedesktopNetwork1Manager
+    for nonblock::Proxy<'a, C>
+{
+    fn get_link_by_name(&self, name: &str) -> nonblock::MethodReply<(i32, dbus::Path<'static>)> {
+        self.method_call("org.freedesktop.network1.Manager", "GetLinkByName", (name,))
+    }
+}
+
+pub trait OrgFreedesktopNetwork1Link {
+    fn reconfigure(&self) -> nonblock::MethodReply<()>;
+    fn operational_state(&self) -> nonblock::MethodReply<String>;
+}
+
+impl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target = T>> OrgFreedesktopNetwork1Link
+    for nonblock::Proxy<'a, C>
+{
+    fn reconfigure(&self) -> nonblock::MethodReply<()> {
+        self.method_call("org.freedesktop.network1.Link", "Reconfigure", ())
+    }
+
+    fn operational_state(&self) -> nonblock::MethodReply<String> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(
+            &self,
+            "org.freedesktop.network1.Link",
+            "OperationalState",
+        )
+    }
+}
@@ -0,0 +1,190 @@
+// This code was autogenerated with `dbus-codegen-rust -c nonblock -m None --dbuscrate ::dbus -o wpa_supplicant.rs`, see https://github.com/diwic/dbus-rs
+use ::dbus;
+use ::dbus::arg;
+use ::dbus::nonblock;
+
+pub trait FiW1WpaSupplicant1 {
+    fn interfaces(&self) -> nonblock::MethodReply<Vec<dbus::Path<'static>>>;
+}
+
+impl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target = T>> FiW1WpaSupplicant1 for nonblock::Proxy<'a, C> {
+    fn interfaces(&self) -> nonblock::MethodReply<Vec<dbus::Path<'static>>> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(&self, "fi.w1.wpa_supplicant1", "Interfaces")
+    }
+}
+
+pub trait FiW1WpaSupplicant1Interface {
+    fn scan(
+        &self,
+        args: ::std::collections::HashMap<&str, arg::Variant<Box<dyn arg::RefArg>>>,
+    ) -> nonblock::MethodReply<()>;
+    fn reassociate(&self) -> nonblock::MethodReply<()>;
+    fn add_network(
+        &self,
+        args: ::std::collections::HashMap<&str, arg::Variant<Box<dyn arg::RefArg>>>,
+    ) -> nonblock::MethodReply<dbus::Path<'static>>;
+    fn remove_network(&self, path: dbus::Path) -> nonblock::MethodReply<()>;
+    fn select_network(&self, path: dbus::Path) -> nonblock::MethodReply<()>;
+    fn save_config(&self) -> nonblock::MethodReply<()>;
+    fn state(&self) -> nonblock::MethodReply<String>;
+    fn ifname(&self) -> nonblock::MethodReply<String>;
+    fn bsss(&self) -> nonblock::MethodReply<Vec<dbus::Path<'static>>>;
+    fn networks(&self) -> nonblock::MethodReply<Vec<dbus::Path<'static>>>;
+    fn current_network(&self) -> nonblock::MethodReply<dbus::Path<'static>>;
+    fn current_bss(&self) -> nonblock::MethodReply<dbus::Path<'static>>;
+}
+
+impl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target = T>> FiW1WpaSupplicant1Interface
+    for nonblock::Proxy<'a, C>
+{
+    fn scan(
+        &self,
+        args: ::std::collections::HashMap<&str, arg::Variant<Box<dyn arg::RefArg>>>,
+    ) -> nonblock::MethodReply<()> {
+        self.method_call("fi.w1.wpa_supplicant1.Interface", "Scan", (args,))
+    }
+
+    fn reassociate(&self) -> nonblock::MethodReply<()> {
+        self.method_call("fi.w1.wpa_supplicant1.Interface", "Reassociate", ())
+    }
+
+    fn add_network(
+        &self,
+        args: ::std::collections::HashMap<&str, arg::Variant<Box<dyn arg::RefArg>>>,
+    ) -> nonblock::MethodReply<dbus::Path<'static>> {
+        self.method_call("fi.w1.wpa_supplicant1.Interface", "AddNetwork", (args,))
+            .and_then(|r: (dbus::Path<'static>,)| Ok(r.0))
+    }
+
+    fn remove_network(&self, path: dbus::Path) -> nonblock::MethodReply<()> {
+        self.method_call("fi.w1.wpa_supplicant1.Interface", "RemoveNetwork", (path,))
+    }
+
+    fn select_network(&self, path: dbus::Path) -> nonblock::MethodReply<()> {
+        self.method_call("fi.w1.wpa_supplicant1.Interface", "SelectNetwork", (path,))
+    }
+
+    fn save_config(&self) -> nonblock::MethodReply<()> {
+        self.method_call("fi.w1.wpa_supplicant1.Interface", "SaveConfig", ())
+    }
+
+    fn state(&self) -> nonblock::MethodReply<String> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(
+            &self,
+            "fi.w1.wpa_supplicant1.Interface",
+            "State",
+        )
+    }
+
+    fn ifname(&self) -> nonblock::MethodReply<String> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(
+            &self,
+            "fi.w1.wpa_supplicant1.Interface",
+            "Ifname",
+        )
+    }
+
+    fn bsss(&self) -> nonblock::MethodReply<Vec<dbus::Path<'static>>> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(
+            &self,
+            "fi.w1.wpa_supplicant1.Interface",
+            "BSSs",
+        )
+    }
+
+    fn networks(&self) -> nonblock::MethodReply<Vec<dbus::Path<'static>>> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(
+            &self,
+            "fi.w1.wpa_supplicant1.Interface",
+            "Networks",
+        )
+    }
+
+    fn current_network(&self) -> nonblock::MethodReply<dbus::Path<'static>> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(
+            &self,
+            "fi.w1.wpa_supplicant1.Interface",
+            "CurrentNetwork",
+        )
+    }
+
+    fn current_bss(&self) -> nonblock::MethodReply<dbus::Path<'static>> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(
+            &self,
+            "fi.w1.wpa_supplicant1.Interface",
+            "CurrentBSS",
+        )
+    }
+}
+
+pub trait FiW1WpaSupplicant1BSS {
+    fn ssid(&self) -> nonblock::MethodReply<Vec<u8>>;
+    fn bssid(&self) -> nonblock::MethodReply<Vec<u8>>;
+    fn privacy(&self) -> nonblock::MethodReply<bool>;
+    fn frequency(&self) -> nonblock::MethodReply<u16>;
+    fn signal(&self) -> nonblock::MethodReply<i16>;
+    fn wpa(
+        &self,
+    ) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>>;
+    fn rsn(
+        &self,
+    ) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>>;
+}
+
+impl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target = T>> FiW1WpaSupplicant1BSS for nonblock::Proxy<'a, C> {
+    fn ssid(&self) -> nonblock::MethodReply<Vec<u8>> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(&self, "fi.w1.wpa_supplicant1.BSS", "SSID")
+    }
+
+    fn bssid(&self) -> nonblock::MethodReply<Vec<u8>> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(&self, "fi.w1.wpa_supplicant1.BSS", "BSSID")
+    }
+
+    fn privacy(&self) -> nonblock::MethodReply<bool> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(&self, "fi.w1.wpa_supplicant1.BSS", "Privacy")
+    }
+
+    fn frequency(&self) -> nonblock::MethodReply<u16> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(
+            &self,
+            "fi.w1.wpa_supplicant1.BSS",
+            "Frequency",
+        )
+    }
+
+    fn signal(&self) -> nonblock::MethodReply<i16> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(&self, "fi.w1.wpa_supplicant1.BSS", "Signal")
+    }
+
+    fn wpa(
+        &self,
+    ) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(&self, "fi.w1.wpa_supplicant1.BSS", "WPA")
+    }
+
+    fn rsn(
+        &self,
+    ) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(&self, "fi.w1.wpa_supplicant1.BSS", "RSN")
+    }
+}
+
+pub trait FiW1WpaSupplicant1Network {
+    fn properties(
+        &self,
+    ) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>>;
+}
+
+impl<'a, T: nonblock::NonblockReply, C: ::std::ops::Deref<Target = T>> FiW1WpaSupplicant1Network
+    for nonblock::Proxy<'a, C>
+{
+    fn properties(
+        &self,
+    ) -> nonblock::MethodReply<::std::collections::HashMap<String, arg::Variant<Box<dyn arg::RefArg + 'static>>>> {
+        <Self as nonblock::stdintf::org_freedesktop_dbus::Properties>::get(
+            &self,
+            "fi.w1.wpa_supplicant1.Network",
+            "Properties",
+        )
+    }
+}
@@ -0,0 +1,801 @@
+//! # The wpa_supplicant and the systemd-networkd + wpa_supplicant backends
+//! For systems without NetworkManager or iwd. wpa_supplicant manages the wifi link
+//! (station and access point mode) via its dbus API, the hotspot address is assigned via rtnetlink.
+//! See https://w1.fi/wpa_supplicant/devel/dbus.html.
+//!
+//! With the "networkd" feature, systemd-networkd is responsible for the station addressing,
+//! see [`networkd`]. Both features share this module.
+//!
+//! All files in generated/* are auto-generated.
+//! Use the `generate.sh` script to update them to newer dbus crate or dbus API versions.
+//!
+//! Requirements:
+//! * wpa_supplicant must run with the dbus control interface enabled (`-u`) and manage the wifi interface.
+//!   Set `update_config=1` in its configuration, otherwise established connections are not saved.
+//! * Without networkd, a dhcp client (udhcpc, dhcpcd) must run on the wifi interface for station mode addressing.
+//!   With networkd, it must manage the wifi interface with a `.network` file that enables DHCP.
+//!   `KeepConfiguration=static` keeps the hotspot address if networkd reconfigures the link meanwhile.
+//! * The process requires `CAP_NET_ADMIN` to assign the hotspot address.
+//!
+//! Hotspot clients get their addresses from the dhcp server of this crate (cargo feature "dhcp"),
+//! which listens on the gateway address.
+
+mod access_points_changed;
+mod generated;
+#[cfg(feature = "networkd")]
+mod networkd;
+
+use crate::dbus_tokio::{self, DbusConnection};
+use crate::mac_filter::MacFilterSync;
+use crate::netlink::address as netlink;
+use crate::network_interface::{
+    AccessPointCredentials, ActiveConnection, ConnectOptions, ConnectionQuality, ConnectionState, HotspotRadio,
+    HotspotSecurity, NetworkManagerState, Pmf, Security, WifiConnection, SSID,
+};
+use crate::network_backend::WifiBackend;
+use crate::CaptivePortalError;
+use generated::wpa_supplicant::*;
+
+use dbus::arg::{RefArg, Variant};
+use dbus::nonblock;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::runtime::{self, delay_for};
+
+// Public API: AccessPointsChangedStream
+pub use access_points_changed::{ap_changed_stream, AccessPointChanged};
+
+/// The handle of a connection of this backend, see [`crate::ActiveConnection`]
+pub type ConnectionPath = dbus::Path<'static>;
+
+#[cfg(feature = "networkd")]
+pub use networkd::NETWORKD_BUSNAME;
+
+pub const WPA_BUSNAME: &str = "fi.w1.wpa_supplicant1";
+pub(crate) const WPA_PATH: &str = "/fi/w1/wpa_supplicant1";
+/// The name of this backend in log messages
+#[cfg(not(feature = "networkd"))]
+const BACKEND: &str = "wpa_supplicant";
+#[cfg(feature = "networkd")]
+const BACKEND: &str = "networkd";
+/// The prefix length of the hotspot address
+const HOTSPOT_PREFIX: u8 = 24;
+/// Polling interval for state changes
+const POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+type VariantMap = HashMap<String, Variant<Box<dyn RefArg + 'static>>>;
+
+#[derive(Clone)]
+pub struct NetworkBackend {
+    exit_handler: Arc<Mutex<Option<tokio::sync::oneshot::Sender<()>>>>,
+    pub(crate) conn: Arc<DbusConnection>,
+    /// The wpa_supplicant interface of the wifi device
+    pub(crate) wifi_device_path: dbus::Path<'static>,
+    /// The kernel index of the wifi interface, for netlink requests
+    if_index: u32,
+    /// The networkd link of the wifi device
+    #[cfg(feature = "networkd")]
+    link_path: dbus::Path<'static>,
+    /// Mac address of the own network interface
+    hw: String,
+    /// Network interface name
+    interface_name: String,
+    /// Do not save new connections to the wpa_supplicant configuration
+    in_memory: bool,
+    /// Protected management frames of new networks and the hotspot
+    pmf: Pmf,
+    /// The wpa_supplicant network of the hotspot, if one is active
+    hotspot_network: Arc<Mutex<Option<dbus::Path<'static>>>>,
+    /// The address that has been assigned to the wifi interface for the hotspot
+    hotspot_address: Arc<Mutex<Option<Ipv4Addr>>>,
+}
+
+impl NetworkBackend {
+    /// Create a new connection to the system bus and find the wifi interface that is managed by
+    /// wpa_supplicant. Returns an error if no wifi device can be found.
+    pub async fn new(interface_name: &Option<String>) -> Result<NetworkBackend, CaptivePortalError> {
+        // Prepare an exit handler
+        let (exit_handler, exit_receiver) = tokio::sync::oneshot::channel::<()>();
+
+        // Connect to the D-Bus session bus (this is blocking, unfortunately).
+        let (resource, conn) = dbus_tokio::new_system_default()?;
+
+        // The resource is a task that should be spawned onto the async
+        // runtime ASAP. If the resource ever finishes, you lost connection to D-Bus.
+        runtime::spawn(async move {
+            use futures_util::future::select;
+            use futures_util::future::Either;
+            use pin_utils::pin_mut;
+
+            pin_mut!(resource);
+            pin_mut!(exit_receiver);
+            let result = select(resource, exit_receiver).await;
+            if let Either::Left((err, _)) = result {
+                panic!("Lost connection to D-Bus: {}", err);
+            }
+        });
+
+        let p = nonblock::Proxy::new(WPA_BUSNAME, WPA_PATH, conn.clone());
+        let mut wifi_device = None;
+        for interface_path in p.interfaces().await? {
+            let p = nonblock::Proxy::new(WPA_BUSNAME, interface_path.clone(), conn.clone());
+            let device_interface = p.ifname().await?;
+            if let Some(interface_name) = interface_name {
+                if &interface_name[..] != device_interface {
+                    info!(
+                        "Wireless device found: {}. Skipping because user requested: {}",
+                        device_interface, &interface_name
+                    );
+                    continue;
+                }
+            }
+            wifi_device = Some((interface_path, device_interface));
+            break;
+        }
+        let (wifi_device_path, interface_name) = wifi_device.ok_or(CaptivePortalError::NoWifiDeviceFound)?;
+
+        let hw = std::fs::read_to_string(format!("/sys/class/net/{}/address", interface_name))
+            .map_err(|e| CaptivePortalError::IO(e, "Failed to read the wifi interface address"))?
+            .trim()
+            .to_uppercase();
+
+        let if_index = crate::netlink::interface_index(&interface_name)?;
+        #[cfg(feature = "networkd")]
+        let link_path = networkd::link_path(&conn, &interface_name).await?;
+
+        info!("Using wifi interface {}", interface_name);
+        Ok(NetworkBackend {
+            exit_handler: Arc::new(Mutex::new(Some(exit_handler))),
+            conn,
+            wifi_device_path,
+            if_index,
+            #[cfg(feature = "networkd")]
+            link_path,
+            hw,
+            interface_name,
+            in_memory: false,
+            pmf: Pmf::Auto,
+            hotspot_network: Arc::new(Mutex::new(None)),
+            hotspot_address: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Do not save established connections to the wpa_supplicant configuration file.
+    pub fn set_in_memory(&mut self, in_memory: bool) {
+        self.in_memory = in_memory;
+    }
+
+    /// Protected management frames (802.11w) setting of new networks and the hotspot
+    pub fn set_pmf(&mut self, pmf: Pmf) {
+        self.pmf = pmf;
+    }
+
+    /// wpa_supplicant runs one access point per interface. Requesting an OWE transition mode pair fails.
+    pub fn set_owe_transition(&mut self, owe_transition: bool) -> Result<(), CaptivePortalError> {
+        match owe_transition {
+            true => Err(CaptivePortalError::Generic(format!(
+                "The {} backend cannot offer an OWE transition mode pair (--hotspot-owe)",
+                BACKEND
+            ))),
+            false => Ok(()),
+        }
+    }
+
+    /// The wpa_supplicant access point has no client limit. The dhcp server limits the clients instead.
+    pub fn set_max_clients(&mut self, max_clients: Option<u32>) {
+        if max_clients.is_some() {
+            info!("The {} backend cannot limit the hotspot clients. Only the dhcp leases are limited.", BACKEND);
+        }
+    }
+
+    /// The wpa_supplicant access point has no MAC filter. The dhcp server rejects the clients instead.
+    pub fn set_mac_filter(&mut self, mac_filter: MacFilterSync) {
+        if !mac_filter.read().expect("mac filter lock").is_empty() {
+            info!("The {} backend cannot filter the hotspot clients. Only the dhcp leases are filtered.", BACKEND);
+        }
+    }
+
+    /// The access point of wpa_supplicant is configured with WPA2.
+    pub fn set_hotspot_security(&mut self, security: HotspotSecurity) {
+        if security != HotspotSecurity::Wpa2 {
+            warn!("The {} backend cannot offer a WPA3 hotspot. The hotspot uses WPA2.", BACKEND);
+        }
+    }
+
+    /// Whether the backend assigns the address given to [`NetworkBackend::hotspot_start`].
+    /// The hotspot address is assigned via netlink.
+    pub fn assigns_hotspot_address(&self) -> bool {
+        true
+    }
+
+    /// The message key of the reason why the last connection attempt failed, see [`crate::i18n`].
+    /// Failure reasons are not reported by this backend.
+    pub fn last_failure(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// The name of the wifi network interface
+    pub fn interface_name(&self) -> &str {
+        &self.interface_name
+    }
+
+    /// The SSID of the current BSS. None if the wifi device is not connected.
+    pub async fn active_ssid(&self) -> Result<Option<SSID>, CaptivePortalError> {
+        let p = nonblock::Proxy::new(WPA_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        let bss_path = p.current_bss().await?;
+        if &*bss_path == "/" {
+            return Ok(None);
+        }
+        let p = nonblock::Proxy::new(WPA_BUSNAME, bss_path, self.conn.clone());
+        Ok(Some(SSID::from(p.ssid().await?)))
+    }
+
+    /// The signal strength of the current BSS. wpa_supplicant does not report the bitrate.
+    /// The gateway round trip time is not measured by the backend, see [`crate::quality`].
+    pub async fn connection_quality(&self) -> Result<ConnectionQuality, CaptivePortalError> {
+        let p = nonblock::Proxy::new(WPA_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        let bss_path = p.current_bss().await?;
+        let signal = match &*bss_path {
+            "/" => None,
+            _ => {
+                let p = nonblock::Proxy::new(WPA_BUSNAME, bss_path, self.conn.clone());
+                Some(signal_percent(p.signal().await?))
+            },
+        };
+        Ok(ConnectionQuality {
+            signal,
+            bitrate: None,
+            gateway_rtt: None,
+        })
+    }
+
+    /// wpa_supplicant does not have a global switch. Nothing to do here.
+    pub async fn enable_networking_and_wifi(&self) -> Result<(), CaptivePortalError> {
+        Ok(())
+    }
+
+    /// Request an active scan. Not possible while the hotspot is active.
+    pub async fn scan_networks(&self) -> Result<(), CaptivePortalError> {
+        if self.hotspot_network.lock().expect("Lock hotspot network").is_some() {
+            return Err(CaptivePortalError::NotInStationMode);
+        }
+        let p = nonblock::Proxy::new(WPA_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+
+        // The scan arguments are not thread safe (RefArg), see the network manager backend.
+        fn scan(p: nonblock::Proxy<Arc<DbusConnection>>) -> nonblock::MethodReply<()> {
+            let mut args: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+            args.insert("Type", Variant(Box::new("active".to_owned())));
+            p.scan(args)
+        }
+        scan(p).await?;
+        Ok(())
+    }
+
+    /// Terminates this dbus connection
+    pub fn quit(self) {
+        let mut exit_handler = self
+            .exit_handler
+            .lock()
+            .expect("Lock network manager exit handler mutex");
+        if let Some(exit_handler) = exit_handler.take() {
+            let _ = exit_handler.send(());
+        }
+    }
+
+    /// The connection state is determined by the wpa_supplicant state and the addressing of the wifi interface,
+    /// see [`NetworkBackend::link_state`]. An active hotspot counts as disconnected.
+    pub async fn state(&self) -> Result<NetworkManagerState, CaptivePortalError> {
+        let p = nonblock::Proxy::new(WPA_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        let wpa_state = p.state().await?;
+        let hotspot_network = self.hotspot_network.lock().expect("Lock hotspot network").clone();
+        if hotspot_network.is_some() {
+            return Ok(NetworkManagerState::Disconnected);
+        }
+
+        Ok(match &wpa_state[..] {
+            "completed" => self.link_state().await?,
+            "authenticating" | "associating" | "associated" | "4way_handshake" | "group_handshake" => {
+                NetworkManagerState::Connecting
+            },
+            "interface_disabled" => NetworkManagerState::Asleep,
+            _ => NetworkManagerState::Disconnected,
+        })
+    }
+
+    /// Let wpa_supplicant reconnect to one of the configured networks.
+    pub async fn try_auto_connect(&self, timeout: std::time::Duration) -> Result<bool, CaptivePortalError> {
+        let p = nonblock::Proxy::new(WPA_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        p.reassociate().await?;
+
+        match self.wait_for_connectivity(false, timeout).await {
+            Ok(state) => Ok(state == NetworkManagerState::Connected || state == NetworkManagerState::ConnectedLimited),
+            Err(CaptivePortalError::NotRequiredConnectivity(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The returned future resolves when either the timeout expired or the wifi link
+    /// reports (internet) connectivity.
+    pub async fn wait_for_connectivity(
+        &self,
+        internet_connectivity: bool,
+        timeout: std::time::Duration,
+    ) -> Result<NetworkManagerState, CaptivePortalError> {
+        self.connectivity_changed(timeout, |state| {
+            state == NetworkManagerState::Connected
+                || (state == NetworkManagerState::ConnectedLimited && !internet_connectivity)
+        })
+        .await
+    }
+
+    /// The returned future resolves when either the timeout expired or (internet) connectivity is lost
+    pub async fn wait_for_connectivity_lost(
+        &self,
+        internet_connectivity: bool,
+        timeout: std::time::Duration,
+    ) -> Result<NetworkManagerState, CaptivePortalError> {
+        self.connectivity_changed(timeout, |state| {
+            state != NetworkManagerState::Connected
+                && (state != NetworkManagerState::ConnectedLimited || internet_connectivity)
+        })
+        .await
+    }
+
+    /// Polls the connection state until "condition" is met or "timeout" expired.
+    async fn connectivity_changed<F>(
+        &self,
+        timeout: std::time::Duration,
+        condition: F,
+    ) -> Result<NetworkManagerState, CaptivePortalError>
+    where
+        F: Fn(NetworkManagerState) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let state = self.state().await?;
+            if condition(state) {
+                return Ok(state);
+            }
+            if Instant::now() >= deadline {
+                return Err(CaptivePortalError::NotRequiredConnectivity(state));
+            }
+            delay_for(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Connect to the given SSID with the given credentials.
+    /// If "overwrite_same_ssid_connection" is true, all configured networks with the same SSID are removed first.
+    /// If "hw" is set, the connection is restricted to that access point.
+    /// If "hidden" is set, wpa_supplicant probes for the ssid (scan_ssid).
+    /// IP configuration is not supported, addresses are configured outside of this service.
+    ///
+    /// The network is saved to the wpa_supplicant configuration if the connection succeeds
+    /// and removed otherwise.
+    pub async fn connect_to(
+        &self,
+        ssid: SSID,
+        credentials: AccessPointCredentials,
+        hw: Option<String>,
+        options: ConnectOptions,
+    ) -> Result<Option<ActiveConnection>, CaptivePortalError> {
+        let ConnectOptions { ipv4, ipv6, hidden, overwrite_same_ssid_connection } = options;
+        if ipv4.is_some() {
+            warn!("Static IPv4 configuration is not supported. Configure the dhcp client or networkd instead");
+        }
+        if ipv6.is_some() {
+            warn!("IPv6 configuration is not supported. Configure the dhcp client or networkd instead");
+        }
+        let p = nonblock::Proxy::new(WPA_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+
+        if overwrite_same_ssid_connection {
+            let quoted_ssid = config_ssid(&ssid);
+            for network_path in p.networks().await? {
+                let network = nonblock::Proxy::new(WPA_BUSNAME, network_path.clone(), self.conn.clone());
+                let network_ssid = network_property(&network, "ssid").await?;
+                if network_ssid.as_ref() == Some(&quoted_ssid) {
+                    info!("Replacing configured network {}", &ssid);
+                    p.remove_network(network_path).await?;
+                }
+            }
+        }
+
+        fn add_network(
+            p: &nonblock::Proxy<Arc<DbusConnection>>,
+            ssid: SSID,
+            credentials: AccessPointCredentials,
+            hw: Option<String>,
+            hidden: bool,
+            pmf: Pmf,
+        ) -> nonblock::MethodReply<dbus::Path<'static>> {
+            let mut args: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+            args.insert("ssid", Variant(Box::new(ssid.into_bytes())));
+            // Probe requests with the ssid find access points of hidden networks
+            if hidden {
+                args.insert("scan_ssid", Variant(Box::new(1i32)));
+            }
+            if let Some(ieee80211w) = ieee80211w(pmf.for_credentials(&credentials)) {
+                args.insert("ieee80211w", Variant(Box::new(ieee80211w)));
+            }
+            match credentials {
+                AccessPointCredentials::None => {
+                    args.insert("key_mgmt", Variant(Box::new("NONE".to_owned())));
+                },
+                AccessPointCredentials::Wep { passphrase } => {
+                    args.insert("key_mgmt", Variant(Box::new("NONE".to_owned())));
+                    args.insert("wep_key0", Variant(Box::new(passphrase)));
+                },
+                AccessPointCredentials::Wpa { passphrase } => {
+                    args.insert("key_mgmt", Variant(Box::new("WPA-PSK".to_owned())));
+                    args.insert("psk", Variant(Box::new(passphrase)));
+                },
+                AccessPointCredentials::Sae { passphrase } => {
+                    args.insert("key_mgmt", Variant(Box::new("SAE".to_owned())));
+                    args.insert("sae_password", Variant(Box::new(passphrase)));
+                },
+                AccessPointCredentials::Enterprise {
+                    identity,
+                    passphrase,
+                    anonymous_identity,
+                    method,
+                } => {
+                    args.insert("key_mgmt", Variant(Box::new("WPA-EAP".to_owned())));
+                    args.insert("eap", Variant(Box::new(method.as_str().to_uppercase())));
+                    let phase2 = format!("auth={}", method.phase2().to_uppercase());
+                    args.insert("phase2", Variant(Box::new(phase2)));
+                    args.insert("identity", Variant(Box::new(identity)));
+                    if let Some(anonymous_identity) = anonymous_identity {
+                        args.insert("anonymous_identity", Variant(Box::new(anonymous_identity)));
+                    }
+                    args.insert("password", Variant(Box::new(passphrase)));
+                },
+            }
+            if let Some(hw) = hw {
+                args.insert("bssid", Variant(Box::new(hw)));
+            }
+            p.add_network(args)
+        }
+
+        let network_path = add_network(&p, ssid, credentials, hw, hidden, self.pmf).await?;
+        p.select_network(network_path.clone()).await?;
+
+        match self.wait_for_connectivity(false, Duration::from_secs(30)).await {
+            Ok(_) => {
+                if !self.in_memory {
+                    if let Err(e) = p.save_config().await {
+                        warn!("Failed to save the wpa_supplicant configuration (update_config=1?): {}", e);
+                    }
+                }
+                Ok(Some(ActiveConnection {
+                    connection_path: network_path,
+                    active_connection_path: self.wifi_device_path.clone(),
+                    state: ConnectionState::Activated,
+                }))
+            },
+            Err(CaptivePortalError::NotRequiredConnectivity(_)) => {
+                p.remove_network(network_path).await?;
+                Ok(None)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Aborts a connection attempt that has been cancelled while [`connect_to`] was in progress.
+    /// Removes the network that wpa_supplicant is currently connecting to, like a failed attempt does.
+    pub async fn abort_connect(&self) -> Result<(), CaptivePortalError> {
+        let p = nonblock::Proxy::new(WPA_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        let network_path = p.current_network().await?;
+        if &*network_path != "/" {
+            p.remove_network(network_path).await?;
+        }
+        Ok(())
+    }
+
+    /// Get access point data for the given wpa_supplicant BSS dbus path.
+    pub async fn access_point<'b, P: Into<dbus::Path<'b>>>(
+        &self,
+        ap_path: P,
+    ) -> Result<WifiConnection, CaptivePortalError> {
+        let p = nonblock::Proxy::new(WPA_BUSNAME, ap_path.into(), self.conn.clone());
+        let hw = p
+            .bssid()
+            .await?
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<String>>()
+            .join(":");
+        let ssid = SSID::from(p.ssid().await?);
+
+        // The security dictionaries are not thread safe (RefArg). Only keep the key management.
+        let rsn = p.rsn().and_then(|m| Ok(key_mgmt(&m))).await?;
+        let wpa = p.wpa().and_then(|m| Ok(key_mgmt(&m))).await?;
+        let security = if !p.privacy().await? {
+            Security::NONE
+        } else if rsn.iter().chain(wpa.iter()).any(|k| k.starts_with("wpa-eap")) {
+            Security::ENTERPRISE
+        } else if rsn.iter().any(|k| k == "sae") && !rsn.iter().any(|k| k.starts_with("wpa-psk")) {
+            // WPA3 only. Access points in WPA2/WPA3 transition mode are treated as WPA2.
+            Security::WPA3
+        } else if !rsn.is_empty() || !wpa.is_empty() {
+            Security::WPA2
+        } else {
+            Security::WEP
+        };
+
+        let strength = signal_percent(p.signal().await?);
+
+        let wifi_connection = WifiConnection {
+            is_own: hw == self.hw,
+            vendor: crate::oui::vendor(&hw),
+            ssid,
+            hw,
+            security: security.as_str(),
+            strength,
+            frequency: p.frequency().await? as u32,
+        };
+        if !wifi_connection.is_own {
+            info!("Found AP {:?}", &wifi_connection.ssid);
+        }
+        Ok(wifi_connection)
+    }
+
+    /// Return all known access points of the wifi device.
+    /// The list might not be up to date and can be refreshed with a call to [`scan_networks`].
+    ///
+    /// ## Arguments
+    /// * timeout: If timeout is != 0, performs a full scan. Waits up to timeout for at least one result.
+    pub async fn list_access_points(
+        &self,
+        mut timeout: std::time::Duration,
+    ) -> Result<Vec<WifiConnection>, CaptivePortalError> {
+        let p = nonblock::Proxy::new(WPA_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        if timeout.as_secs() > 0 {
+            self.scan_networks().await?;
+        }
+        let interval = Duration::from_millis(500);
+        loop {
+            let mut access_points = Vec::new();
+            for bss_path in p.bsss().await? {
+                if let Ok(ap) = self.access_point(bss_path).await {
+                    if !ap.is_own {
+                        access_points.push(ap);
+                    }
+                }
+            }
+            if !access_points.is_empty() || timeout < interval {
+                return Ok(access_points);
+            }
+            delay_for(interval).await;
+            timeout -= interval;
+        }
+    }
+
+    /// Removes all configured networks with the given SSID. Returns false if there are none.
+    pub async fn forget(&self, ssid: &SSID) -> Result<bool, CaptivePortalError> {
+        let p = nonblock::Proxy::new(WPA_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        let quoted_ssid = config_ssid(ssid);
+        let mut removed = false;
+        for network_path in p.networks().await? {
+            let network = nonblock::Proxy::new(WPA_BUSNAME, network_path.clone(), self.conn.clone());
+            if network_property(&network, "ssid").await?.as_ref() == Some(&quoted_ssid) {
+                p.remove_network(network_path).await?;
+                removed = true;
+            }
+        }
+        if removed && !self.in_memory {
+            p.save_config().await?;
+        }
+        Ok(removed)
+    }
+
+    /// Removes the hotspot network (also left-overs of a previous run) and the hotspot address.
+    pub async fn deactivate_hotspots(&self) -> Result<(), CaptivePortalError> {
+        let p = nonblock::Proxy::new(WPA_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        for network_path in p.networks().await? {
+            let network = nonblock::Proxy::new(WPA_BUSNAME, network_path.clone(), self.conn.clone());
+            // Access point mode
+            if network_property(&network, "mode").await?.as_ref().map(|m| &m[..]) == Some("2") {
+                p.remove_network(network_path).await?;
+            }
+        }
+        self.hotspot_network.lock().expect("Lock hotspot network").take();
+
+        let address = self.hotspot_address.lock().expect("Lock hotspot address").take();
+        if let Some(address) = address {
+            netlink::remove_address(self.if_index, address, HOTSPOT_PREFIX)?;
+            #[cfg(feature = "networkd")]
+            networkd::reconfigure(&self.conn, self.link_path.clone()).await?;
+        }
+        Ok(())
+    }
+
+    /// Starts a hotspot. The given address (/24) is assigned to the wifi interface.
+    pub async fn hotspot_start(
+        &self,
+        ssid: SSID,
+        password: String,
+        address: Option<Ipv4Addr>,
+        radio: Option<HotspotRadio>,
+    ) -> Result<ActiveConnection, CaptivePortalError> {
+        self.deactivate_hotspots().await?;
+
+        debug!("Configuring hotspot ...");
+        if let Some(address) = address {
+            netlink::add_address(self.if_index, address, HOTSPOT_PREFIX)?;
+            self.hotspot_address
+                .lock()
+                .expect("Lock hotspot address")
+                .replace(address);
+        }
+
+        fn add_network(
+            p: &nonblock::Proxy<Arc<DbusConnection>>,
+            ssid: SSID,
+            password: String,
+            pmf: Pmf,
+            frequency: u32,
+        ) -> nonblock::MethodReply<dbus::Path<'static>> {
+            let mut args: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+            args.insert("ssid", Variant(Box::new(ssid.into_bytes())));
+            args.insert("mode", Variant(Box::new(2_u32)));
+            args.insert("frequency", Variant(Box::new(frequency)));
+            if password.is_empty() {
+                args.insert("key_mgmt", Variant(Box::new("NONE".to_owned())));
+            } else {
+                args.insert("key_mgmt", Variant(Box::new("WPA-PSK".to_owned())));
+                args.insert("proto", Variant(Box::new("RSN".to_owned())));
+                args.insert("pairwise", Variant(Box::new("CCMP".to_owned())));
+                args.insert("group", Variant(Box::new("CCMP".to_owned())));
+                args.insert("psk", Variant(Box::new(password)));
+                if let Some(ieee80211w) = ieee80211w(pmf) {
+                    args.insert("ieee80211w", Variant(Box::new(ieee80211w)));
+                }
+            }
+            p.add_network(args)
+        }
+
+        info!("Starting hotspot ...");
+        let p = nonblock::Proxy::new(WPA_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        // Channel 1 without a radio configuration
+        let frequency = radio.map_or(2412, |radio| radio.frequency());
+        let network_path = add_network(&p, ssid, password, self.pmf, frequency).await?;
+        p.select_network(network_path.clone()).await?;
+        self.hotspot_network
+            .lock()
+            .expect("Lock hotspot network")
+            .replace(network_path.clone());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while p.state().await? != "completed" {
+            if Instant::now() >= deadline {
+                warn!("Hotspot starting failed");
+                return Err(CaptivePortalError::HotspotFailed);
+            }
+            delay_for(POLL_INTERVAL).await;
+        }
+
+        Ok(ActiveConnection {
+            connection_path: network_path.clone(),
+            active_connection_path: network_path,
+            state: ConnectionState::Activated,
+        })
+    }
+
+    /// Resolves when the given hotspot network is no longer the active network
+    pub async fn on_hotspot_stopped(&self, path: ConnectionPath) -> Result<(), CaptivePortalError> {
+        let p = nonblock::Proxy::new(WPA_BUSNAME, self.wifi_device_path.clone(), self.conn.clone());
+        loop {
+            if p.current_network().await? != path || p.state().await? != "completed" {
+                return Ok(());
+            }
+            delay_for(POLL_INTERVAL * 2).await;
+        }
+    }
+
+    /// The state of the configured station link: networkd's operational state of the link
+    #[cfg(feature = "networkd")]
+    async fn link_state(&self) -> Result<NetworkManagerState, CaptivePortalError> {
+        networkd::link_state(&self.conn, self.link_path.clone()).await
+    }
+
+    /// The state of the configured station link: The interface address and the default route
+    #[cfg(not(feature = "networkd"))]
+    async fn link_state(&self) -> Result<NetworkManagerState, CaptivePortalError> {
+        Ok(match crate::utils::interface_ipv4(&self.interface_name) {
+            // The dhcp client has not configured the interface yet
+            None => NetworkManagerState::Connecting,
+            Some(_) if self.default_gateway().is_some() => NetworkManagerState::Connected,
+            Some(_) => NetworkManagerState::ConnectedLimited,
+        })
+    }
+
+    /// The default gateway that is routed via the wifi interface, if any
+    #[cfg(not(feature = "networkd"))]
+    fn default_gateway(&self) -> Option<Ipv4Addr> {
+        let route_table = std::fs::read_to_string("/proc/net/route").ok()?;
+        crate::quality::parse_default_gateway(&interface_routes(&route_table, &self.interface_name))
+    }
+}
+
+impl WifiBackend for NetworkBackend {
+    wifi_backend_methods!();
+}
+
+/// The wpa_supplicant "ieee80211w" network value. None for the global default.
+fn ieee80211w(pmf: Pmf) -> Option<u32> {
+    match pmf {
+        Pmf::Auto => None,
+        Pmf::Disabled => Some(0),
+        Pmf::Optional => Some(1),
+        Pmf::Required => Some(2),
+    }
+}
+
+/// Converts dBm into a percentage: -100 dBm is 0%, -50 dBm and better is 100%
+fn signal_percent(dbm: i16) -> u8 {
+    ((dbm as i32 + 100) * 2).max(0).min(100) as u8
+}
+
+/// Returns the key management entries of a BSS "RSN" or "WPA" dictionary
+fn key_mgmt(map: &VariantMap) -> Vec<String> {
+    map.get("KeyMgmt")
+        .and_then(|v| v.0.as_iter())
+        .map(|i| i.filter_map(|k| k.as_str().map(|k| k.to_owned())).collect())
+        .unwrap_or_default()
+}
+
+/// The ssid as wpa_supplicant writes it in the network properties:
+/// Quoted if it consists of printable ASCII characters, hex encoded otherwise.
+fn config_ssid(ssid: &SSID) -> String {
+    match ssid.as_bytes().iter().all(|b| (32..127).contains(b)) {
+        true => format!("\"{}\"", ssid),
+        false => ssid.to_hex(),
+    }
+}
+
+/// Reads a single value of the "Properties" dictionary of a wpa_supplicant network.
+/// String values like the ssid are quoted.
+fn network_property(
+    p: &nonblock::Proxy<Arc<DbusConnection>>,
+    key: &'static str,
+) -> nonblock::MethodReply<Option<String>> {
+    p.properties()
+        .and_then(move |m| Ok(m.get(key).and_then(|v| v.0.as_str()).map(|v| v.to_owned())))
+}
+
+/// Keeps the header line and the routes of the given interface of a "/proc/net/route" table
+#[cfg(not(feature = "networkd"))]
+fn interface_routes(route_table: &str, interface_name: &str) -> String {
+    route_table
+        .lines()
+        .enumerate()
+        .filter(|(i, line)| *i == 0 || line.split_whitespace().next() == Some(interface_name))
+        .map(|(_, line)| line)
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(not(feature = "networkd"))]
+    fn routes_of_interface() {
+        let route_table = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\n\
+                           eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\n\
+                           wlan0\t0000A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\n";
+        let routes = interface_routes(route_table, "wlan0");
+        assert_eq!(routes.lines().count(), 2);
+        assert_eq!(crate::quality::parse_default_gateway(&routes), None);
+        let routes = interface_routes(route_table, "eth0");
+        assert!(crate::quality::parse_default_gateway(&routes).is_some());
+    }
+
+    #[test]
+    fn ssid_in_network_properties() {
+        assert_eq!(config_ssid(&"home".into()), "\"home\"");
+        assert_eq!(config_ssid(&SSID::from_bytes(&b"caf\xe9"[..])), "636166e9");
+    }
+}
@@ -0,0 +1,52 @@
+//! # systemd-networkd link state
+//! With the "networkd" feature, systemd-networkd (version 244 or newer) manages the addressing of the wifi
+//! interface. Its operational state of the link tells whether the station connection is configured.
+//! See https://www.freedesktop.org/software/systemd/man/org.freedesktop.network1.html.
+//!
+//! networkd has no D-Bus method to add an address. The hotspot address is assigned via rtnetlink like without
+//! networkd, and the link is reconfigured when the hotspot is deactivated, so that networkd applies its
+//! `.network` file again.
+
+use super::generated::networkd::{OrgFreedesktopNetwork1Link, OrgFreedesktopNetwork1Manager};
+use crate::dbus_tokio::DbusConnection;
+use crate::network_interface::NetworkManagerState;
+use crate::CaptivePortalError;
+
+use dbus::nonblock;
+use std::sync::Arc;
+
+pub const NETWORKD_BUSNAME: &str = "org.freedesktop.network1";
+pub(crate) const NETWORKD_PATH: &str = "/org/freedesktop/network1";
+
+/// The networkd link of the given network interface
+pub(crate) async fn link_path(
+    conn: &Arc<DbusConnection>,
+    interface_name: &str,
+) -> Result<dbus::Path<'static>, CaptivePortalError> {
+    let p = nonblock::Proxy::new(NETWORKD_BUSNAME, NETWORKD_PATH, conn.clone());
+    let (_, link_path) = p.get_link_by_name(interface_name).await?;
+    Ok(link_path)
+}
+
+/// The state of a station link, by its operational state
+pub(crate) async fn link_state(
+    conn: &Arc<DbusConnection>,
+    link_path: dbus::Path<'static>,
+) -> Result<NetworkManagerState, CaptivePortalError> {
+    let p = nonblock::Proxy::new(NETWORKD_BUSNAME, link_path, conn.clone());
+    Ok(match &p.operational_state().await?[..] {
+        "routable" => NetworkManagerState::Connected,
+        "degraded" => NetworkManagerState::ConnectedLimited,
+        _ => NetworkManagerState::Connecting,
+    })
+}
+
+/// Lets networkd configure the link according to its `.network` file
+pub(crate) async fn reconfigure(
+    conn: &Arc<DbusConnection>,
+    link_path: dbus::Path<'static>,
+) -> Result<(), CaptivePortalError> {
+    let p = nonblock::Proxy::new(NETWORKD_BUSNAME, link_path, conn.clone());
+    p.reconfigure().await?;
+    Ok(())
+}
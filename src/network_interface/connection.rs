@@ -6,44 +6,70 @@ use dbus::Error;
 use std::sync::Arc;
 use std::{future, pin, task};
 
-use tokio::io::Registration;
+use crate::dbus_record;
+use crate::runtime::{DefaultRuntime, FdRegistration, Runtime};
+use crate::stats;
 use crate::CaptivePortalError;
 
-/// The I/O Resource should be spawned onto a Tokio compatible reactor.
+/// The I/O Resource should be spawned onto the [`DefaultRuntime`], see [`crate::runtime::spawn`].
 ///
 /// If you need to ever cancel this resource (i e disconnect from D-Bus),
 /// you need to make this future abortable. If it finishes, you probably lost
 /// contact with the D-Bus server.
 pub struct IOResource<C> {
     connection: Arc<C>,
-    registration: Registration,
+    registration: <DefaultRuntime as Runtime>::Registration,
 }
 
-impl<C: AsRef<Channel> + Process> IOResource<C> {
+/// Sizes of the reply and filter tables of a connection, see [`crate::stats`]
+pub trait ConnectionStats {
+    fn pending_replies(&self) -> usize;
+    fn filter_count(&self) -> usize;
+}
+
+macro_rules! connection_stats {
+    ($c: ident) => {
+        impl ConnectionStats for $c {
+            fn pending_replies(&self) -> usize {
+                $c::pending_replies(self)
+            }
+            fn filter_count(&self) -> usize {
+                $c::filter_count(self)
+            }
+        }
+    };
+}
+
+connection_stats!(Connection);
+connection_stats!(LocalConnection);
+connection_stats!(SyncConnection);
+
+impl<C: AsRef<Channel> + Process + ConnectionStats> IOResource<C> {
     fn poll_internal(&self, ctx: &mut task::Context<'_>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let c: &Channel = (*self.connection).as_ref();
 
         c.read_write(Some(Default::default()))
             .map_err(|_| Error::new_failed("Read/write failed"))?;
         self.connection.process_all();
+        stats::record(stats::Collection::DbusReplies, self.connection.pending_replies());
+        stats::record(stats::Collection::DbusFilters, self.connection.filter_count());
 
         let w = c.watch();
         let r = &self.registration;
-        r.take_read_ready()?;
-        r.take_write_ready()?;
+        r.clear_readiness()?;
 
         if w.read {
-            let _ = r.poll_read_ready(ctx)?;
+            let _ = r.poll_readable(ctx)?;
         };
         if w.write {
-            let _ = r.poll_write_ready(ctx)?;
+            let _ = r.poll_writable(ctx)?;
         };
 
         Ok(())
     }
 }
 
-impl<C: AsRef<Channel> + Process> future::Future for IOResource<C> {
+impl<C: AsRef<Channel> + Process + ConnectionStats> future::Future for IOResource<C> {
     type Output = Box<dyn std::error::Error + Send + Sync>;
     fn poll(self: pin::Pin<&mut Self>, ctx: &mut task::Context<'_>) -> task::Poll<Self::Output> {
         match self.poll_internal(ctx) {
@@ -55,18 +81,47 @@ impl<C: AsRef<Channel> + Process> future::Future for IOResource<C> {
 
 /// Generic connection creator, you might want to use e g `new_session_local`, `new_system_sync` etc for convenience.
 pub fn new<C: From<Channel>>(b: BusType) -> Result<(IOResource<C>, Arc<C>), CaptivePortalError> {
-    let mut channel = Channel::get_private(b)?;
+    from_channel(Channel::get_private(b)?)
+}
+
+/// Connects to the bus daemon at the given address, like the private bus of a test
+#[cfg(test)]
+pub(crate) fn new_address<C: From<Channel>>(address: &str) -> Result<(IOResource<C>, Arc<C>), CaptivePortalError> {
+    let mut channel = Channel::open_private(address)?;
+    channel.register()?;
+    from_channel(channel)
+}
+
+fn from_channel<C: From<Channel>>(mut channel: Channel) -> Result<(IOResource<C>, Arc<C>), CaptivePortalError> {
     channel.set_watch_enabled(true);
 
     let w = channel.watch();
     let conn = Arc::new(C::from(channel));
     let res = IOResource {
         connection: conn.clone(),
-        registration: Registration::new(&mio::unix::EventedFd(&w.fd))?,
+        registration: DefaultRuntime::register(w.fd)?,
     };
     Ok((res, conn))
 }
 
+/// The D-Bus connection type of the network backends. A [`LocalConnection`] with the "single-threaded" feature.
+#[cfg(not(feature = "single-threaded"))]
+pub type DbusConnection = SyncConnection;
+#[cfg(feature = "single-threaded")]
+pub type DbusConnection = LocalConnection;
+
+/// Connects to the system bus with the connection type of the network backends, see [`DbusConnection`].
+pub fn new_system_default() -> Result<(IOResource<DbusConnection>, Arc<DbusConnection>), CaptivePortalError> {
+    new(BusType::System)
+}
+
+/// Like [`new_system_default`], but the traffic is recorded if `--record-dbus` is given, see [`crate::dbus_record`].
+pub fn new_system_recorded() -> Result<(IOResource<DbusConnection>, Arc<DbusConnection>), CaptivePortalError> {
+    let mut channel = Channel::get_private(BusType::System)?;
+    dbus_record::monitor(&mut channel);
+    from_channel(channel)
+}
+
 pub fn new_session_local() -> Result<(IOResource<LocalConnection>, Arc<LocalConnection>), CaptivePortalError> {
     new(BusType::Session)
 }
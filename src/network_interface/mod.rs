@@ -1,25 +1,167 @@
 //! # Generic types, traits and methods for network interfaces
 //! Find implementations in [`network_backend`]
 mod connection;
+mod properties_changed;
 mod signal_stream;
 
 pub mod dbus_tokio {
     pub use super::connection::*;
+    pub use super::properties_changed::{await_property, PropertiesChanged};
     pub use super::signal_stream::SignalStream;
 }
 
+use crate::network_backend::ConnectionPath;
 use crate::CaptivePortalError;
 use core::fmt;
-use serde::Serialize;
+use serde::ser::SerializeStruct;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::convert::TryFrom;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
 
 /// A wifi SSID
 /// According to last standard 802.11-2012 (Section 6.3.11.2.2),
 /// a SSID  can be 0-32 octets with an unspecified or UTF8 encoding.
-pub type SSID = String;
+///
+/// The octets are kept as they are, so that networks with for example a Latin-1 SSID can be selected.
+/// Display is lossy. SSIDs serialize to a string if they are valid UTF8 and to an array of octets otherwise,
+/// both forms deserialize.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SSID(Vec<u8>);
+
+impl SSID {
+    pub fn from_bytes(bytes: impl Into<Vec<u8>>) -> Self {
+        SSID(bytes.into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The SSID if it is valid UTF8
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.0).ok()
+    }
+
+    /// The SSID for display. Invalid UTF8 sequences are replaced by U+FFFD.
+    pub fn to_string_lossy(&self) -> std::borrow::Cow<str> {
+        String::from_utf8_lossy(&self.0)
+    }
+
+    /// Lower case hex encoded octets, as used in wpa_supplicant configurations and iwd object paths
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Whether both SSIDs read the same when displayed, ignoring trailing white space.
+    /// The octets may differ, for example if a form re-encoded a Latin-1 SSID or trimmed it.
+    pub fn same_text(&self, other: &SSID) -> bool {
+        self.to_string_lossy().trim_end() == other.to_string_lossy().trim_end()
+    }
+}
+
+impl From<Vec<u8>> for SSID {
+    fn from(bytes: Vec<u8>) -> Self {
+        SSID(bytes)
+    }
+}
 
-#[derive(Serialize, Clone, Debug)]
+impl From<String> for SSID {
+    fn from(ssid: String) -> Self {
+        SSID(ssid.into_bytes())
+    }
+}
+
+impl From<&str> for SSID {
+    fn from(ssid: &str) -> Self {
+        SSID(ssid.as_bytes().to_vec())
+    }
+}
+
+impl PartialEq<str> for SSID {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for SSID {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl fmt::Display for SSID {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_string_lossy())
+    }
+}
+
+impl fmt::Debug for SSID {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.as_str() {
+            Some(ssid) => fmt::Debug::fmt(ssid, f),
+            None => write!(f, "0x{}", self.to_hex()),
+        }
+    }
+}
+
+impl Serialize for SSID {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.as_str() {
+            Some(ssid) => serializer.serialize_str(ssid),
+            None => serializer.collect_seq(&self.0),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SSID {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SsidVisitor;
+
+        impl<'de> de::Visitor<'de> for SsidVisitor {
+            type Value = SSID;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string or an array of octets")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<SSID, E> {
+                Ok(SSID::from(v))
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<SSID, E> {
+                Ok(SSID::from_bytes(v))
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<SSID, A::Error> {
+                let mut bytes = Vec::new();
+                while let Some(b) = seq.next_element::<u8>()? {
+                    bytes.push(b);
+                }
+                Ok(SSID(bytes))
+            }
+        }
+
+        deserializer.deserialize_any(SsidVisitor)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct WifiConnection {
+    /// Serialized as the lossy display string. SSIDs that are not valid UTF8 are additionally
+    /// serialized as "ssid_bytes", the array of octets to use in a connection request.
     pub ssid: SSID,
     /// The unique hw address of the access point
     pub hw: String,
@@ -31,6 +173,40 @@ pub struct WifiConnection {
     pub frequency: u32,
     // True if this is spawned by the current device
     pub is_own: bool,
+    /// The access point vendor, derived from the hw address. Requires the "oui" feature.
+    pub vendor: Option<&'static str>,
+}
+
+impl Serialize for WifiConnection {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let raw = self.ssid.as_str().is_none();
+        let len = 6 + raw as usize + self.vendor.is_some() as usize;
+        let mut s = serializer.serialize_struct("WifiConnection", len)?;
+        s.serialize_field("ssid", &self.ssid.to_string_lossy())?;
+        if raw {
+            s.serialize_field("ssid_bytes", self.ssid.as_bytes())?;
+        } else {
+            s.skip_field("ssid_bytes")?;
+        }
+        s.serialize_field("hw", &self.hw)?;
+        s.serialize_field("security", self.security)?;
+        s.serialize_field("strength", &self.strength)?;
+        s.serialize_field("frequency", &self.frequency)?;
+        s.serialize_field("is_own", &self.is_own)?;
+        match self.vendor {
+            Some(vendor) => s.serialize_field("vendor", vendor)?,
+            None => s.skip_field("vendor")?,
+        }
+        s.end()
+    }
+}
+
+/// A saved wifi connection
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SavedNetwork {
+    pub ssid: SSID,
+    /// Of several saved networks in range, the one with the highest priority is used. 0 by default.
+    pub priority: i32,
 }
 
 #[derive(Serialize, Debug, Copy, Clone)]
@@ -54,6 +230,42 @@ pub struct WifiConnectionEvent {
 #[derive(Serialize)]
 pub struct WifiConnections(pub Vec<WifiConnection>);
 
+/// The effect of an access point event on the aggregated list
+#[derive(Serialize, Debug, Copy, Clone, PartialEq)]
+pub enum WifiConnectionChange {
+    Added,
+    Changed,
+    Removed,
+}
+
+impl WifiConnections {
+    /// Applies an access point added / removed event to the list.
+    /// Access points are aggregated by SSID: A newly seen access point replaces the entry with the same SSID.
+    ///
+    /// Returns the change of the list entry or None if the list has not changed.
+    pub fn update(&mut self, event: &WifiConnectionEvent) -> Option<WifiConnectionChange> {
+        match self.0.iter().position(|n| n.ssid == event.access_point.ssid) {
+            Some(pos) => match event.event {
+                WifiConnectionEventType::Added => {
+                    self.0[pos] = event.access_point.clone();
+                    Some(WifiConnectionChange::Changed)
+                },
+                WifiConnectionEventType::Removed => {
+                    self.0.remove(pos);
+                    Some(WifiConnectionChange::Removed)
+                },
+            },
+            None => match event.event {
+                WifiConnectionEventType::Added => {
+                    self.0.push(event.access_point.clone());
+                    Some(WifiConnectionChange::Added)
+                },
+                WifiConnectionEventType::Removed => None,
+            },
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ConnectionState {
     Unknown,
@@ -96,12 +308,35 @@ pub enum NetworkManagerState {
 /// are present.
 pub struct ActiveConnection {
     /// The dbus path to the underlying connection. In iwd this is called "network".
-    pub connection_path: dbus::Path<'static>,
+    pub connection_path: ConnectionPath,
     /// The dbus path to the active connection. In iwd this is called "known network".
-    pub active_connection_path: dbus::Path<'static>,
+    pub active_connection_path: ConnectionPath,
     pub state: ConnectionState,
 }
 
+/// Link quality of the station connection. Values a backend cannot report are None.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ConnectionQuality {
+    /// The signal strength of the connected access point in percent
+    pub signal: Option<u8>,
+    /// The current transmit bitrate in kbit/s. An estimate of the achievable downlink throughput.
+    pub bitrate: Option<u32>,
+    /// The round trip time to the default gateway in milliseconds
+    pub gateway_rtt: Option<u32>,
+}
+
+/// Driver and firmware of the wifi device, for triaging chipset specific problems.
+/// Values a backend cannot report are empty.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct WifiDeviceInfo {
+    /// The kernel driver, like "brcmfmac"
+    pub driver: String,
+    pub driver_version: String,
+    pub firmware_version: String,
+    /// The wireless capabilities, like "ap", "5ghz" or "rsn"
+    pub capabilities: Vec<&'static str>,
+}
+
 impl From<u32> for ConnectionState {
     fn from(state: u32) -> Self {
         match state {
@@ -128,6 +363,8 @@ pub enum Security {
     WEP,
     WPA,
     WPA2,
+    /// WPA3 Personal (SAE)
+    WPA3,
     ENTERPRISE,
 }
 
@@ -138,6 +375,7 @@ impl Security {
             Security::ENTERPRISE => "enterprise",
             Security::WEP => "wep",
             Security::WPA | Security::WPA2 => "wpa",
+            Security::WPA3 => "wpa3",
         }
     }
 }
@@ -150,6 +388,7 @@ impl TryFrom<String> for Security {
             "enterprise" => Ok(Security::ENTERPRISE),
             "wpa" => Ok(Security::WPA),
             "wpa2" => Ok(Security::WPA2),
+            "wpa3" => Ok(Security::WPA3),
             "wep" => Ok(Security::WEP),
             "open" | "" => Ok(Security::NONE),
             _ => Err(CaptivePortalError::Generic(format!(
@@ -161,27 +400,459 @@ impl TryFrom<String> for Security {
 }
 
 /// Different encryption mechanisms require different sets of credentials.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AccessPointCredentials {
     None,
     Wep { passphrase: String },
     Wpa { passphrase: String },
-    Enterprise { identity: String, passphrase: String },
+    /// WPA3 Personal
+    Sae { passphrase: String },
+    /// WPA2 Enterprise (802.1X) with a username and password
+    Enterprise {
+        identity: String,
+        passphrase: String,
+        /// The outer identity, sent unencrypted before the tunnel is established. The identity is used if not set.
+        anonymous_identity: Option<String>,
+        method: EapMethod,
+    },
+}
+
+/// The tunneled EAP method of an enterprise network
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EapMethod {
+    /// PEAP with MSCHAPv2
+    Peap,
+    /// TTLS with PAP
+    Ttls,
+}
+
+impl EapMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EapMethod::Peap => "peap",
+            EapMethod::Ttls => "ttls",
+        }
+    }
+
+    /// The inner authentication method
+    pub fn phase2(&self) -> &'static str {
+        match self {
+            EapMethod::Peap => "mschapv2",
+            EapMethod::Ttls => "pap",
+        }
+    }
+}
+
+impl Default for EapMethod {
+    fn default() -> Self {
+        EapMethod::Peap
+    }
+}
+
+impl FromStr for EapMethod {
+    type Err = CaptivePortalError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "peap" | "" => Ok(EapMethod::Peap),
+            "ttls" => Ok(EapMethod::Ttls),
+            _ => Err(CaptivePortalError::Generic(format!(
+                "Expected an EAP method (peap, ttls). Got: {}",
+                value
+            ))),
+        }
+    }
 }
 
 /// Converts a set of credentials into the [`AccessPointCredentials`] type.
+/// The anonymous identity and the EAP method are only used for enterprise networks.
 pub fn credentials_from_data(
     passphrase: String,
     identity: Option<String>,
+    anonymous_identity: Option<String>,
+    eap: Option<String>,
     mode: Security,
 ) -> Result<AccessPointCredentials, CaptivePortalError> {
     match mode {
         Security::ENTERPRISE => Ok(AccessPointCredentials::Enterprise {
             identity: identity.ok_or(CaptivePortalError::NoSharedKeyProvided)?,
             passphrase,
+            anonymous_identity: anonymous_identity.filter(|i| !i.is_empty()),
+            method: eap.as_deref().unwrap_or_default().parse()?,
         }),
         Security::WPA | Security::WPA2 => Ok(AccessPointCredentials::Wpa { passphrase }),
+        Security::WPA3 => Ok(AccessPointCredentials::Sae { passphrase }),
         Security::WEP => Ok(AccessPointCredentials::Wep { passphrase }),
         Security::NONE => Ok(AccessPointCredentials::None),
     }
 }
+
+/// A static IPv4 configuration of the target network, used instead of DHCP
+#[derive(Clone, Debug, PartialEq)]
+pub struct StaticIpv4 {
+    pub address: Ipv4Addr,
+    /// The network prefix length, like 24 for 255.255.255.0
+    pub prefix: u8,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns: Vec<Ipv4Addr>,
+}
+
+impl StaticIpv4 {
+    /// The prefix as dotted netmask, like 255.255.255.0 for 24
+    pub fn netmask(&self) -> Ipv4Addr {
+        match self.prefix {
+            0 => Ipv4Addr::from(0),
+            prefix => Ipv4Addr::from(u32::max_value() << (32 - u32::from(prefix))),
+        }
+    }
+}
+
+/// Converts the static IP fields of a connect request into a [`StaticIpv4`] configuration.
+/// Returns None if no address is given, the network is then configured via DHCP.
+pub fn ipv4_from_data(
+    address: Option<String>,
+    prefix: Option<u8>,
+    gateway: Option<String>,
+    dns: Vec<String>,
+) -> Result<Option<StaticIpv4>, CaptivePortalError> {
+    let gateway = gateway.filter(|g| !g.is_empty());
+    let dns: Vec<String> = dns.into_iter().filter(|d| !d.trim().is_empty()).collect();
+    let address = match address.filter(|a| !a.is_empty()) {
+        Some(address) => parse_address("address", &address)?,
+        None if prefix.is_some() || gateway.is_some() || !dns.is_empty() => {
+            return Err(CaptivePortalError::InvalidIpConfiguration(
+                "The address is required for a static configuration".to_owned(),
+            ));
+        },
+        None => return Ok(None),
+    };
+    Ok(Some(StaticIpv4 {
+        address,
+        prefix: check_prefix(prefix, 32)?,
+        gateway: gateway.map(|g| parse_address("gateway", &g)).transpose()?,
+        dns: dns.iter().map(|d| parse_address("dns", d)).collect::<Result<_, _>>()?,
+    }))
+}
+
+/// The IPv6 configuration of the target network. The backend default applies if none is given,
+/// which is [`Ipv6Config::Auto`] for network manager.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Ipv6Config {
+    /// Router advertisements (SLAAC), and DHCPv6 if the router asks for it
+    Auto,
+    /// DHCPv6 only
+    Dhcp,
+    Static(StaticIpv6),
+}
+
+/// A static IPv6 configuration of the target network
+#[derive(Clone, Debug, PartialEq)]
+pub struct StaticIpv6 {
+    pub address: Ipv6Addr,
+    /// The network prefix length, usually 64
+    pub prefix: u8,
+    pub gateway: Option<Ipv6Addr>,
+    pub dns: Vec<Ipv6Addr>,
+}
+
+/// How a new connection is configured, see `NetworkBackend::connect_to`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConnectOptions {
+    /// A static address configuration. The address is requested via DHCP if this is not set.
+    pub ipv4: Option<StaticIpv4>,
+    /// The IPv6 method and static configuration. The backend default applies if this is not set.
+    pub ipv6: Option<Ipv6Config>,
+    /// The network does not broadcast its SSID and is probed for
+    pub hidden: bool,
+    /// An existing connection with the same SSID is replaced instead of adding another one
+    pub overwrite_same_ssid_connection: bool,
+}
+
+/// Converts the IPv6 fields of a connect request into an [`Ipv6Config`].
+/// The mode is "auto", "dhcp" or "static". Static fields without a mode imply "static".
+/// Returns None if nothing is given.
+pub fn ipv6_from_data(
+    mode: Option<String>,
+    address: Option<String>,
+    prefix: Option<u8>,
+    gateway: Option<String>,
+    dns: Vec<String>,
+) -> Result<Option<Ipv6Config>, CaptivePortalError> {
+    let address = address.filter(|a| !a.is_empty());
+    let gateway = gateway.filter(|g| !g.is_empty());
+    let dns: Vec<String> = dns.into_iter().filter(|d| !d.trim().is_empty()).collect();
+    let static_fields = address.is_some() || prefix.is_some() || gateway.is_some() || !dns.is_empty();
+    match mode.as_deref().unwrap_or_default() {
+        "" if !static_fields => Ok(None),
+        "auto" | "dhcp" if static_fields => Err(CaptivePortalError::InvalidIpConfiguration(
+            "Static IPv6 fields require the static mode".to_owned(),
+        )),
+        "auto" => Ok(Some(Ipv6Config::Auto)),
+        "dhcp" => Ok(Some(Ipv6Config::Dhcp)),
+        "" | "static" => {
+            let address = match address {
+                Some(address) => parse_address("address", &address)?,
+                None => {
+                    return Err(CaptivePortalError::InvalidIpConfiguration(
+                        "The address is required for a static configuration".to_owned(),
+                    ));
+                },
+            };
+            Ok(Some(Ipv6Config::Static(StaticIpv6 {
+                address,
+                prefix: check_prefix(prefix, 128)?,
+                gateway: gateway.map(|g| parse_address("gateway", &g)).transpose()?,
+                dns: dns.iter().map(|d| parse_address("dns", d)).collect::<Result<_, _>>()?,
+            })))
+        },
+        mode => Err(CaptivePortalError::InvalidIpConfiguration(format!(
+            "Expected an IPv6 mode (auto, dhcp, static). Got: {}",
+            mode
+        ))),
+    }
+}
+
+fn parse_address<T: FromStr>(field: &str, value: &str) -> Result<T, CaptivePortalError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| CaptivePortalError::InvalidIpConfiguration(format!("{} is not a valid address: {}", field, value)))
+}
+
+/// Checks that a prefix length is given and between 1 and `max`
+fn check_prefix(prefix: Option<u8>, max: u8) -> Result<u8, CaptivePortalError> {
+    match prefix {
+        Some(prefix) if prefix >= 1 && prefix <= max => Ok(prefix),
+        Some(prefix) => Err(CaptivePortalError::InvalidIpConfiguration(format!(
+            "Expected a prefix between 1 and {}. Got: {}",
+            max, prefix
+        ))),
+        None => Err(CaptivePortalError::InvalidIpConfiguration(
+            "The prefix is required for a static configuration".to_owned(),
+        )),
+    }
+}
+
+/// Protected management frames (802.11w). Mismatched settings let connection attempts fail silently.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Pmf {
+    /// Required for WPA3, backend default otherwise
+    Auto,
+    Disabled,
+    Optional,
+    Required,
+}
+
+impl Pmf {
+    /// The setting for the given credentials: WPA3 requires protected management frames.
+    pub fn for_credentials(self, credentials: &AccessPointCredentials) -> Pmf {
+        match (self, credentials) {
+            (Pmf::Auto, AccessPointCredentials::Sae { .. }) => Pmf::Required,
+            (pmf, _) => pmf,
+        }
+    }
+}
+
+impl FromStr for Pmf {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(Pmf::Auto),
+            "disabled" => Ok(Pmf::Disabled),
+            "optional" => Ok(Pmf::Optional),
+            "required" => Ok(Pmf::Required),
+            _ => Err(format!("Expected auto, disabled, optional or required, got {}", value)),
+        }
+    }
+}
+
+/// The key management of a hotspot with a passphrase
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HotspotSecurity {
+    Wpa2,
+    /// WPA3 Personal (SAE) only. Older clients cannot connect.
+    Wpa3,
+    /// WPA2 and WPA3 Personal on the same access point
+    Transition,
+}
+
+impl FromStr for HotspotSecurity {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "wpa2" => Ok(HotspotSecurity::Wpa2),
+            "wpa3" => Ok(HotspotSecurity::Wpa3),
+            "transition" => Ok(HotspotSecurity::Transition),
+            _ => Err(format!("Expected wpa2, wpa3 or transition, got {}", value)),
+        }
+    }
+}
+
+/// The frequency band of the hotspot
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HotspotBand {
+    /// 2.4 Ghz, compatible with all clients
+    Bg,
+    /// 5 Ghz, less crowded but not supported by every client
+    A,
+}
+
+impl HotspotBand {
+    /// The band of the given channel. None for an unknown channel.
+    pub fn of_channel(channel: u32) -> Option<HotspotBand> {
+        match channel {
+            1..=14 => Some(HotspotBand::Bg),
+            32..=177 => Some(HotspotBand::A),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for HotspotBand {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "2.4" | "bg" => Ok(HotspotBand::Bg),
+            "5" | "a" => Ok(HotspotBand::A),
+            _ => Err(format!("Expected 2.4 or 5, got {}", value)),
+        }
+    }
+}
+
+/// The band and optionally the channel of the hotspot
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HotspotRadio {
+    pub band: HotspotBand,
+    /// The wifi driver picks a channel if not given
+    pub channel: Option<u32>,
+}
+
+impl HotspotRadio {
+    /// The band defaults to the band of the channel. Returns None if neither is given,
+    /// and an error if the channel is not within the band.
+    pub fn new(band: Option<HotspotBand>, channel: Option<u32>) -> Result<Option<HotspotRadio>, String> {
+        let channel_band = match channel {
+            Some(channel) => {
+                let band = HotspotBand::of_channel(channel).ok_or_else(|| format!("Unknown channel {}", channel))?;
+                Some(band)
+            },
+            None => None,
+        };
+        match (band, channel_band) {
+            (Some(band), Some(channel_band)) if band != channel_band => Err(format!(
+                "Channel {} is not within the selected band",
+                channel.unwrap_or_default()
+            )),
+            (band, channel_band) => Ok(band.or(channel_band).map(|band| HotspotRadio { band, channel })),
+        }
+    }
+
+    /// The center frequency in Mhz. Channel 1 or 36 if no channel is given.
+    pub fn frequency(&self) -> u32 {
+        let channel = match (self.channel, self.band) {
+            (Some(channel), _) => channel,
+            (None, HotspotBand::Bg) => 1,
+            (None, HotspotBand::A) => 36,
+        };
+        crate::channels::channel_to_frequency(channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hotspot_radio() {
+        assert_eq!(HotspotRadio::new(None, None), Ok(None));
+        let radio = HotspotRadio::new(None, Some(36)).unwrap().unwrap();
+        assert_eq!(radio.band, HotspotBand::A);
+        assert_eq!(radio.frequency(), 5180);
+        assert_eq!(HotspotRadio::new(Some(HotspotBand::Bg), None).unwrap().unwrap().frequency(), 2412);
+        assert!(HotspotRadio::new(Some(HotspotBand::Bg), Some(36)).is_err());
+        assert!(HotspotRadio::new(None, Some(200)).is_err());
+    }
+
+    #[test]
+    fn static_ipv4() {
+        assert_eq!(ipv4_from_data(None, None, Some(String::new()), vec![]).unwrap(), None);
+        assert!(ipv4_from_data(None, None, Some("192.168.1.1".into()), vec![]).is_err());
+        assert!(ipv4_from_data(Some("192.168.1.20".into()), Some(33), None, vec![]).is_err());
+        assert!(ipv4_from_data(Some("192.168.1.20".into()), None, None, vec![]).is_err());
+        assert!(ipv4_from_data(Some("192.168.1.20".into()), Some(24), None, vec!["dns".into()]).is_err());
+
+        let config = ipv4_from_data(
+            Some("192.168.1.20".into()),
+            Some(24),
+            Some("192.168.1.1".into()),
+            vec![" 1.1.1.1".into(), "".into()],
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(config.address, Ipv4Addr::new(192, 168, 1, 20));
+        assert_eq!(config.gateway, Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(config.dns, vec![Ipv4Addr::new(1, 1, 1, 1)]);
+        assert_eq!(config.netmask(), Ipv4Addr::new(255, 255, 255, 0));
+    }
+
+    #[test]
+    fn ipv6_modes() {
+        assert_eq!(ipv6_from_data(None, None, None, None, vec![]).unwrap(), None);
+        assert_eq!(ipv6_from_data(Some("dhcp".into()), None, None, None, vec![]).unwrap(), Some(Ipv6Config::Dhcp));
+        assert!(ipv6_from_data(Some("auto".into()), Some("2001:db8::20".into()), Some(64), None, vec![]).is_err());
+        assert!(ipv6_from_data(Some("slaac".into()), None, None, None, vec![]).is_err());
+        assert!(ipv6_from_data(Some("static".into()), Some("2001:db8::20".into()), Some(129), None, vec![]).is_err());
+
+        let config = ipv6_from_data(None, Some("2001:db8::20".into()), Some(64), Some("fe80::1".into()), vec![]);
+        match config.unwrap() {
+            Some(Ipv6Config::Static(config)) => {
+                assert_eq!(config.address, "2001:db8::20".parse::<Ipv6Addr>().unwrap());
+                assert_eq!(config.prefix, 64);
+                assert_eq!(config.gateway, Some("fe80::1".parse().unwrap()));
+            },
+            config => panic!("Expected a static configuration, got {:?}", config),
+        }
+    }
+
+    #[test]
+    fn raw_ssid() {
+        let latin1 = SSID::from_bytes(&b"Caf\xe9"[..]);
+        assert_eq!(latin1.to_string(), "Caf\u{fffd}");
+        assert_eq!(serde_json::to_string(&latin1).unwrap(), "[67,97,102,233]");
+        assert_eq!(serde_json::from_str::<SSID>("[67,97,102,233]").unwrap(), latin1);
+        let emoji = SSID::from("Caf\u{2615}");
+        assert_eq!(serde_json::to_string(&emoji).unwrap(), "\"Caf\u{2615}\"");
+        assert_eq!(serde_json::from_str::<SSID>("\"Caf\u{2615}\"").unwrap(), emoji);
+
+        let mut network = WifiConnection {
+            ssid: latin1,
+            hw: "00:11:22:33:44:55".to_owned(),
+            security: "wpa",
+            strength: 50,
+            frequency: 2412,
+            is_own: false,
+            vendor: None,
+        };
+        let json = serde_json::to_value(&network).unwrap();
+        assert_eq!(json["ssid"], "Caf\u{fffd}");
+        assert_eq!(json["ssid_bytes"], serde_json::json!([67, 97, 102, 233]));
+        network.ssid = emoji;
+        assert!(serde_json::to_value(&network).unwrap().get("ssid_bytes").is_none());
+    }
+
+    #[test]
+    fn ssid_round_trip() {
+        for bytes in [&b"Home  "[..], &b" "[..], &b"Caf\xe9 "[..], &b"\x00\xff"[..], &b""[..]].iter() {
+            let ssid = SSID::from_bytes(*bytes);
+            let json = serde_json::to_string(&ssid).unwrap();
+            assert_eq!(serde_json::from_str::<SSID>(&json).unwrap().as_bytes(), *bytes);
+        }
+        let scanned = SSID::from_bytes(&b"Caf\xe9 "[..]);
+        assert!(scanned.same_text(&SSID::from("Caf\u{fffd}")));
+        assert!(SSID::from("Home  ").same_text(&SSID::from("Home")));
+        assert!(!SSID::from("Home").same_text(&SSID::from("home")));
+    }
+}
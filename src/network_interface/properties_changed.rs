@@ -0,0 +1,126 @@
+//! # Wait for property changes
+//! The standard `org.freedesktop.DBus.Properties.PropertiesChanged` signal and a helper that waits for a
+//! property of a dbus object to satisfy a condition, for example for an active connection to be activated.
+//!
+//! The property is read once, and then followed via the signal. This replaces polling loops and
+//! subscriptions to interface specific signals.
+
+use super::connection::DbusConnection;
+use super::signal_stream::SignalStream;
+use crate::runtime::timeout;
+
+use dbus::arg::{self, RefArg, Variant};
+use dbus::message::SignalArgs;
+use dbus::nonblock::{self, stdintf::org_freedesktop_dbus::Properties};
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// org.freedesktop.DBus.Properties.PropertiesChanged
+///
+/// The changed values are not thread safe (RefArg). Convert them before an await point.
+#[derive(Debug)]
+pub struct PropertiesChanged {
+    pub interface_name: String,
+    pub changed_properties: HashMap<String, Variant<Box<dyn RefArg + 'static>>>,
+    pub invalidated_properties: Vec<String>,
+}
+
+impl PropertiesChanged {
+    /// The new value of the given property of the given interface, if it is part of this change
+    pub fn get(&self, interface: &str, name: &str) -> Option<&(dyn RefArg + 'static)> {
+        if self.interface_name != interface {
+            return None;
+        }
+        self.changed_properties.get(name).map(|v| &*v.0)
+    }
+}
+
+impl arg::AppendAll for PropertiesChanged {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.interface_name, i);
+        arg::RefArg::append(&self.changed_properties, i);
+        arg::RefArg::append(&self.invalidated_properties, i);
+    }
+}
+
+impl arg::ReadAll for PropertiesChanged {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(PropertiesChanged {
+            interface_name: i.read()?,
+            changed_properties: i.read()?,
+            invalidated_properties: i.read()?,
+        })
+    }
+}
+
+impl SignalArgs for PropertiesChanged {
+    const NAME: &'static str = "PropertiesChanged";
+    const INTERFACE: &'static str = "org.freedesktop.DBus.Properties";
+}
+
+/// Waits up to "timeout_value" for the property "name" of the given interface to satisfy the predicate.
+///
+/// Resolves to the first value that satisfies the predicate, or to the last known value after the timeout.
+/// Callers that need to know which one it is test the value again.
+/// Only property types that are sent as themselves, like numbers, strings and object paths, can be followed.
+pub async fn await_property<T, F>(
+    proxy: &nonblock::Proxy<'_, Arc<DbusConnection>>,
+    interface: &str,
+    name: &str,
+    predicate: F,
+    timeout_value: Duration,
+) -> Result<T, dbus::Error>
+where
+    T: for<'b> arg::Get<'b> + Clone + 'static,
+    F: Fn(&T) -> bool,
+{
+    let deadline = Instant::now() + timeout_value;
+
+    // Subscribe before reading the current value, so that no change gets lost in between
+    let path = proxy.path.clone().into_static();
+    let mut stream = SignalStream::<PropertiesChanged>::prop_new(&path, proxy.connection.clone()).await?;
+
+    let mut value: T = proxy.get(interface, name).await?;
+    if predicate(&value) {
+        return Ok(value);
+    }
+
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(value);
+        }
+        match timeout(deadline - now, stream.next()).await {
+            Some(Some((changed, _path))) => {
+                if let Some(new_value) = changed.get(interface, name).and_then(|v| arg::cast::<T>(v)) {
+                    value = new_value.clone();
+                    if predicate(&value) {
+                        return Ok(value);
+                    }
+                }
+            }
+            _ => return Ok(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbus::arg::ReadAll;
+
+    #[test]
+    fn changed_value() {
+        let mut changed: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        changed.insert("State".to_owned(), Variant(Box::new(2u32)));
+        let msg = dbus::Message::new_signal("/o", PropertiesChanged::INTERFACE, PropertiesChanged::NAME)
+            .unwrap()
+            .append3("org.example.Interface", changed, Vec::<String>::new());
+        let changed = PropertiesChanged::read(&mut msg.iter_init()).unwrap();
+        let value = changed.get("org.example.Interface", "State").and_then(|v| arg::cast::<u32>(v));
+        assert_eq!(value, Some(&2));
+        assert!(changed.get("org.example.Other", "State").is_none());
+    }
+}
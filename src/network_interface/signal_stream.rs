@@ -1,7 +1,7 @@
 //! # A convenience stream type that allows to listen to dbus signals as a future stream
 
 use dbus::channel::{MatchingReceiver, Token};
-use dbus::nonblock::SyncConnection;
+use super::connection::DbusConnection;
 use dbus::Error;
 
 use std::sync::{Arc, Mutex};
@@ -19,13 +19,15 @@ struct SignalStreamState<U> {
     signal_queue: VecDeque<dbus::Message>,
     waker: Option<Waker>,
     rule_handler: Token,
-    _u: PhantomData<U>,
+    /// Signals are converted into U when they are taken from the queue. The state holds no U
+    /// and is thread safe also for signal types that are not.
+    _u: PhantomData<fn() -> U>,
 }
 
 /// The signal stream type handles the signal registration process and offers a convenience interface
 /// over the connections *start_receive* and *stop_receive* method.
 pub struct SignalStream<U> {
-    connection: Arc<SyncConnection>,
+    connection: Arc<DbusConnection>,
     rule_handler: Token,
     state: Arc<Mutex<SignalStreamState<U>>>,
 }
@@ -69,12 +71,12 @@ where
     }
 }
 
-impl<U: SignalArgs + 'static + Send> SignalStream<U> {
-    /// Create a new signal stream. This works with [`SyncConnection`] only.
+impl<U: SignalArgs + 'static> SignalStream<U> {
+    /// Create a new signal stream. The connection type is [`DbusConnection`].
     ///
     /// Create a match rule like this:
     /// `let mr = MatchRule::new_signal("com.example.dbustest", "HelloHappened");`
-    pub async fn new(connection: Arc<SyncConnection>, mr: MatchRule<'static>) -> Result<Self, Error> {
+    pub async fn new(connection: Arc<DbusConnection>, mr: MatchRule<'static>) -> Result<Self, Error> {
         let match_str = mr.match_str();
 
         let p = dbus::nonblock::Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus", connection.clone());
@@ -121,12 +123,12 @@ impl<U: SignalArgs + 'static + Send> SignalStream<U> {
         })
     }
 
-    /// Create a new signal stream. This works with [`SyncConnection`] only.
+    /// Create a new signal stream. The connection type is [`DbusConnection`].
     ///
     /// This is a convenience function for streams that operate on org.freedesktop.DBus.Properties changes.
     pub async fn prop_new(
         wifi_device_path: &dbus::Path<'_>,
-        conn: Arc<SyncConnection>,
+        conn: Arc<DbusConnection>,
     ) -> Result<SignalStream<U>, Error> {
         let rule = U::match_rule(None, Some(wifi_device_path)).static_clone();
         Ok(SignalStream::new(conn, rule).await?)
@@ -0,0 +1,62 @@
+//! # Access point vendor lookup
+//! Maps the OUI prefix (first three octets) of a hardware address to the registered organization.
+//! The table in `data/oui.txt` is only embedded with the "oui" feature. It is a compact subset
+//! of the IEEE registry, use `scripts/update_oui.sh` to embed the full table instead.
+
+#[cfg(feature = "oui")]
+const OUI_TABLE: &str = include_str!("../data/oui.txt");
+
+/// Returns the uppercase 6 hex digit OUI prefix of a hardware address like "AA:BB:CC:DD:EE:FF".
+#[cfg(any(feature = "oui", test))]
+fn prefix(hw: &str) -> Option<String> {
+    let prefix: String = hw
+        .chars()
+        .filter(|c| *c != ':' && *c != '-' && *c != '.')
+        .take(6)
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if prefix.len() == 6 && prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(prefix)
+    } else {
+        None
+    }
+}
+
+/// Returns the vendor name for the given hardware address or None if the prefix is unknown
+/// or the "oui" feature is not enabled.
+#[cfg(feature = "oui")]
+pub fn vendor(hw: &str) -> Option<&'static str> {
+    let prefix = prefix(hw)?;
+    OUI_TABLE
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .find(|line| line.starts_with(&prefix[..]))
+        .and_then(|line| line.splitn(2, '\t').nth(1))
+        .map(str::trim)
+}
+
+#[cfg(not(feature = "oui"))]
+pub fn vendor(_hw: &str) -> Option<&'static str> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hw_prefix() {
+        assert_eq!(prefix("b8:27:eb:12:34:56"), Some("B827EB".to_owned()));
+        assert_eq!(prefix("B8-27-EB-12-34-56"), Some("B827EB".to_owned()));
+        assert_eq!(prefix("B8:27"), None);
+        assert_eq!(prefix("XX:27:EB:12:34:56"), None);
+    }
+
+    #[cfg(feature = "oui")]
+    #[test]
+    fn vendor_lookup() {
+        assert_eq!(vendor("B8:27:EB:12:34:56"), Some("Raspberry Pi Foundation"));
+        assert_eq!(vendor("DC:39:6F:00:00:01"), Some("AVM GmbH"));
+        assert_eq!(vendor("02:00:00:00:00:01"), None);
+    }
+}
@@ -0,0 +1,240 @@
+//! # Authorization policy of the control surfaces
+//! A table of rules "subject=action+action" decides which control surface may submit which command.
+//! Subjects are "portal" (REST and GraphQL without admin token), "portal-admin" (with the admin token),
+//! "dbus" (any D-Bus caller), "dbus:<uid>" (D-Bus callers with this unix user id), "mqtt" and "cli".
+//! Actions are "connect" (also cancelling an attempt), "forget", "restart-hotspot", "update-notice"
+//! (the update banner of the embedding firmware), "network-event" (NetworkManager dispatcher events,
+//! see [`crate::dispatcher`]), "mac-filter" (reading and replacing the hotspot MAC filter,
+//! see [`crate::mac_filter`]), "restart-wifi" (switching wifi off and on), "reboot" (rebooting the device)
+//! "log-filter" (reading and replacing the log filter, see [`crate::log_filter`]), "show-secret" (reading
+//! the stored passphrase of a saved network) and "sessions" (reading the hotspot client list, see [`crate::sessions`]).
+//!
+//! A configured rule replaces the default rule of the same subject, "mqtt=" denies everything.
+//! Subjects without a rule are denied. The policy is enforced by the command queue, see [`crate::commands`].
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    Connect,
+    Forget,
+    RestartHotspot,
+    UpdateNotice,
+    NetworkEvent,
+    MacFilter,
+    RestartWifi,
+    Reboot,
+    LogFilter,
+    /// Read the stored passphrase of a saved network
+    ShowSecret,
+    /// Read the hotspot clients with their MAC addresses, host names and user agents
+    Sessions,
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Connect => "connect",
+            Action::Forget => "forget",
+            Action::RestartHotspot => "restart-hotspot",
+            Action::UpdateNotice => "update-notice",
+            Action::NetworkEvent => "network-event",
+            Action::MacFilter => "mac-filter",
+            Action::RestartWifi => "restart-wifi",
+            Action::Reboot => "reboot",
+            Action::LogFilter => "log-filter",
+            Action::ShowSecret => "show-secret",
+            Action::Sessions => "sessions",
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "connect" => Ok(Action::Connect),
+            "forget" => Ok(Action::Forget),
+            "restart-hotspot" => Ok(Action::RestartHotspot),
+            "update-notice" => Ok(Action::UpdateNotice),
+            "network-event" => Ok(Action::NetworkEvent),
+            "mac-filter" => Ok(Action::MacFilter),
+            "restart-wifi" => Ok(Action::RestartWifi),
+            "reboot" => Ok(Action::Reboot),
+            "log-filter" => Ok(Action::LogFilter),
+            "show-secret" => Ok(Action::ShowSecret),
+            "sessions" => Ok(Action::Sessions),
+            _ => Err(format!(
+                "Expected connect, forget, restart-hotspot, update-notice, network-event, mac-filter, restart-wifi, \
+                 reboot, log-filter, show-secret or sessions, got {}",
+                value
+            )),
+        }
+    }
+}
+
+/// Who a rule applies to
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Subject {
+    Portal,
+    PortalAdmin,
+    /// D-Bus callers. None for any unix user id.
+    Dbus(Option<u32>),
+    Mqtt,
+    Cli,
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Subject::Portal => write!(f, "portal"),
+            Subject::PortalAdmin => write!(f, "portal-admin"),
+            Subject::Dbus(None) => write!(f, "dbus"),
+            Subject::Dbus(Some(uid)) => write!(f, "dbus:{}", uid),
+            Subject::Mqtt => write!(f, "mqtt"),
+            Subject::Cli => write!(f, "cli"),
+        }
+    }
+}
+
+impl FromStr for Subject {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "portal" => Ok(Subject::Portal),
+            "portal-admin" => Ok(Subject::PortalAdmin),
+            "dbus" => Ok(Subject::Dbus(None)),
+            "mqtt" => Ok(Subject::Mqtt),
+            "cli" => Ok(Subject::Cli),
+            _ if value.starts_with("dbus:") => value[5..]
+                .parse()
+                .map(|uid| Subject::Dbus(Some(uid)))
+                .map_err(|e| format!("{}: {}", value, e)),
+            _ => Err(format!("Unknown subject {}", value)),
+        }
+    }
+}
+
+/// The allowed actions of a subject. Parsed from "subject=action+action", for example "portal=connect+forget".
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub subject: Subject,
+    pub actions: Vec<Action>,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.splitn(2, '=');
+        let subject = parts.next().unwrap_or_default().trim().parse()?;
+        let actions = parts
+            .next()
+            .ok_or_else(|| format!("Expected subject=action+action, got {}", value))?
+            .split('+')
+            .map(|a| a.trim())
+            .filter(|a| !a.is_empty())
+            .map(|a| a.parse())
+            .collect::<Result<Vec<Action>, String>>()?;
+        Ok(Rule { subject, actions })
+    }
+}
+
+pub struct Policy {
+    rules: Vec<Rule>,
+}
+
+impl Policy {
+    /// Creates the default policy, overridden by the given rules
+    pub fn new(rules: &[Rule]) -> Self {
+        let mut policy = Policy::default();
+        for rule in rules {
+            policy.rules.retain(|r| r.subject != rule.subject);
+            policy.rules.push(rule.clone());
+        }
+        policy
+    }
+
+    /// The rule of the first subject that has one decides. Subjects are ordered from specific to general.
+    pub fn allows(&self, subjects: &[Subject], action: Action) -> bool {
+        subjects
+            .iter()
+            .filter_map(|s| self.rules.iter().find(|r| r.subject == *s))
+            .next()
+            .map(|r| r.actions.contains(&action))
+            .unwrap_or(false)
+    }
+}
+
+/// The portal may connect and forget, root and the local CLI may do everything
+impl Default for Policy {
+    fn default() -> Self {
+        let all = vec![
+            Action::Connect,
+            Action::Forget,
+            Action::RestartHotspot,
+            Action::UpdateNotice,
+            Action::NetworkEvent,
+            Action::MacFilter,
+            Action::RestartWifi,
+            Action::Reboot,
+            Action::LogFilter,
+            Action::ShowSecret,
+            Action::Sessions,
+        ];
+        Policy {
+            rules: vec![
+                Rule {
+                    subject: Subject::Portal,
+                    actions: vec![Action::Connect, Action::Forget],
+                },
+                Rule {
+                    subject: Subject::PortalAdmin,
+                    actions: all.clone(),
+                },
+                Rule {
+                    subject: Subject::Dbus(Some(0)),
+                    actions: all.clone(),
+                },
+                Rule {
+                    subject: Subject::Cli,
+                    actions: all,
+                },
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules() {
+        let policy = Policy::new(&[
+            "dbus=connect".parse().unwrap(),
+            "portal=connect".parse().unwrap(),
+            "mqtt=".parse().unwrap(),
+        ]);
+        assert!(policy.allows(&[Subject::Portal], Action::Connect));
+        assert!(!policy.allows(&[Subject::Portal], Action::Forget));
+        assert!(policy.allows(&[Subject::PortalAdmin], Action::RestartHotspot));
+        assert!(!policy.allows(&[Subject::Portal], Action::UpdateNotice));
+        assert!(policy.allows(&[Subject::Dbus(Some(0)), Subject::Dbus(None)], Action::Forget));
+        assert!(!policy.allows(&[Subject::Dbus(Some(1000)), Subject::Dbus(None)], Action::Forget));
+        assert!(policy.allows(&[Subject::Dbus(Some(1000)), Subject::Dbus(None)], Action::Connect));
+        assert!(!policy.allows(&[Subject::Mqtt], Action::Connect));
+        assert!("dbus:x=connect".parse::<Rule>().is_err());
+        assert!("portal=shutdown".parse::<Rule>().is_err());
+        assert!(policy.allows(&[Subject::PortalAdmin], Action::Reboot));
+        assert!(!policy.allows(&[Subject::Portal], Action::RestartWifi));
+        assert!(!policy.allows(&[Subject::Portal], Action::LogFilter));
+        assert!(!policy.allows(&[Subject::Portal], Action::ShowSecret));
+        assert!(policy.allows(&[Subject::PortalAdmin], Action::ShowSecret));
+        assert!(!policy.allows(&[Subject::Portal], Action::Sessions));
+        assert!(policy.allows(&[Subject::PortalAdmin], Action::Sessions));
+    }
+}
@@ -1,89 +1,162 @@
 //! # This module contains the portal implementation, spawned by the state machine.
 
-use super::http_server::WifiConnectionRequest;
-use super::network_backend::{ap_changed_stream, NetworkBackend};
+use super::attempts::ConnectAttemptsSync;
+use super::commands::CommandSender;
+use super::device::DeviceIdentity;
+use super::events::EventLogSync;
+use super::http_server::probes::ProbeTable;
+use super::redirect::RedirectRecords;
+use super::network_backend::{ConnectionPath, SharedBackend, WifiBackend};
 use super::network_interface::WifiConnection;
+use super::smoke_test::Subsystem;
+use super::status::StatusReceiver;
 use super::utils::take_optional;
-use super::{dhcp_server, dns_server, http_server, CaptivePortalError};
+use super::{http_server, CaptivePortalError};
 
 use crate::{NetworkManagerState, WifiConnectionEvent};
-use futures_core::future::BoxFuture;
+use crate::runtime::{self, BoxFuture};
 use futures_util::{FutureExt, StreamExt};
 use std::future::Future;
 use std::net::SocketAddrV4;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task;
 use std::task::Poll;
 use std::time::Duration;
+#[cfg(any(feature = "dns", feature = "dhcp"))]
+use tokio::time::delay_for;
+
+/// The time for a stopped dns or dhcp server to close its socket
+#[cfg(any(feature = "dns", feature = "dhcp"))]
+const RESTART_DELAY: Duration = Duration::from_millis(500);
 
 /// The portal type offers a web-ui and redirection services ("Captive Portal"). It stays online
-/// for a certain configurable time and returns when the user has selected a wifi SSID and entered
-/// credentials.
+/// for a certain configurable time or until it is closed via the exit handler.
+/// When the user has selected a wifi SSID and entered credentials, a connect command is queued
+/// for the state machine, which then closes the portal.
 ///
 /// # Implementation details
 /// The portal spawns several background tasks for dns, dhcp, access point changes.
 /// It is itself a future that polls the timeout, connection-changed and webserver inner futures.
-/// It also resolves when the user has selected a wifi connection from the UI.
 pub struct Portal<'a> {
     /// Used to quit the server by the timeout or user wifi selection
     http_exit: Option<tokio::sync::oneshot::Sender<()>>,
     /// As soon as Portal is dropped, the dns server will stop
+    #[cfg(feature = "dns")]
     #[allow(dead_code)]
     dns_exit: tokio::sync::oneshot::Sender<()>,
     /// As soon as Portal is dropped, the dhcp server will stop
+    #[cfg(feature = "dhcp")]
     #[allow(dead_code)]
     dhcp_exit: tokio::sync::oneshot::Sender<()>,
+    /// The session list of the http server, for a restarted dhcp server
+    #[cfg(feature = "dhcp")]
+    sessions: crate::sessions::SessionsSync,
+    /// Set if the dhcp server failed, see [`Portal::link_local_fallback`]. Removes its address when dropped.
+    #[cfg(feature = "dhcp")]
+    link_local: Option<crate::link_local::LinkLocal>,
+    /// The dns and dhcp servers are bound to this interface, see [`bound_interface`]
+    #[cfg(any(feature = "dns", feature = "dhcp"))]
+    interface: Option<String>,
     /// Internal: This future is polled by this wrapping future to determine if outside wants us to quit.
     exit_receiver: Option<tokio::sync::oneshot::Receiver<()>>,
     /// The timeout future. Will be polled by this wrapping future.
     timeout: Option<BoxFuture<'a, Result<NetworkManagerState, CaptivePortalError>>>,
     /// The connection changed future. Will be polled by this wrapping future.
     hotspot_stopped_fut: Option<BoxFuture<'a, Result<(), CaptivePortalError>>>,
+    /// Resolves when a network in range has been saved outside of the portal, see [`reconcile_saved_networks`]
+    saved_network_fut: Option<BoxFuture<'a, ()>>,
     /// The http server future. Will be polled by this wrapping future.
-    http_server: Pin<Box<dyn Future<Output = Result<Option<WifiConnectionRequest>, CaptivePortalError>> + Send>>,
+    http_server: BoxFuture<'static, Result<(), CaptivePortalError>>,
+}
+
+/// What the portal shares with the state machine: The status bus, the status history,
+/// the connect attempts and the command queue
+pub struct PortalLinks {
+    pub status: StatusReceiver,
+    pub events: EventLogSync,
+    pub attempts: ConnectAttemptsSync,
+    pub commands: CommandSender,
 }
 
 impl<'a> Portal<'a> {
     /// The configuration should contain a ui_directory, if the UI is not embedded. If that is not set,
     /// the environment variable CARGO_MANIFEST_DIR will be used, which is only useful during development.
-    pub fn new(
-        nm: &'a NetworkBackend,
+    /// Without a timeout, the portal stays online until it is closed via the exit handler or the hotspot stops.
+    pub fn new<B: WifiBackend + Clone + 'static>(
+        nm: &'a B,
         config: &crate::config::Config,
-        wifi_sta_active_connection: dbus::Path<'static>,
+        wifi_sta_active_connection: ConnectionPath,
         wifi_access_points: Vec<WifiConnection>,
-        timeout: Duration,
+        timeout: Option<Duration>,
+        links: PortalLinks,
     ) -> Result<(Portal<'a>, tokio::sync::oneshot::Sender<()>), CaptivePortalError> {
+        let PortalLinks { status, events, attempts, commands } = links;
+        let shared: SharedBackend = Arc::new(nm.clone());
         let (http_server, http_exit) = http_server::HttpServer::new(
             SocketAddrV4::new(config.gateway.clone(), config.listening_port),
-            nm.clone(),
+            shared.clone(),
             config.get_ui_directory(),
+            status,
+            attempts,
+            commands,
         );
 
         let mut state = http_server.state.lock().expect("Lock http_state mutex for portal");
         state.connections.0.extend(wifi_access_points);
+        state.admin_token = config.admin_token.clone();
+        state.events = events;
+        state.mac_filter = config.mac_filter.clone();
+        state.log_filter = config.log_filter.clone();
+        state.device = DeviceIdentity::detect(
+            config.device_model.clone(),
+            config.device_serial.clone(),
+            config.firmware_version.clone(),
+        );
+        state.device_sign_command = config.device_sign_command.clone();
+        state.pairing_token = config.pairing_token.clone();
+        // The host name only resolves with the dns server of the portal
+        #[cfg(feature = "dns")]
+        {
+            state.hostname = config.portal_hostname.clone();
+        }
+        state.limits = config.http_limits();
+        #[cfg(feature = "tls")]
+        {
+            state.tls = config.tls_settings();
+        }
+        state.credential_policy = config.credential_policy();
+        if let Some(path) = config.probe_file.clone() {
+            state.probes = ProbeTable::with_file(path);
+        }
+        if let Some(state_dir) = config.persistence_dir() {
+            let path = state_dir.join(crate::redirect::REDIRECT_STRATEGIES_FILE);
+            match RedirectRecords::with_file(path, Duration::from_secs(config.flush_interval)) {
+                Ok(redirects) => state.redirects = redirects,
+                Err(e) => warn!("Could not restore the redirect strategies: {}", e),
+            }
+            let path = state_dir.join(crate::sessions::SESSIONS_FILE);
+            match crate::sessions::Sessions::with_file(path, Duration::from_secs(config.flush_interval)) {
+                Ok(sessions) => *state.sessions.lock().expect("sessions mutex lock") = sessions,
+                Err(e) => warn!("Could not restore the sessions: {}", e),
+            }
+        }
+        #[cfg(feature = "dhcp")]
+        let sessions = state.sessions.clone();
         drop(state);
 
         let http_state = http_server.state.clone();
+        let saved_network_fut = Box::pin(reconcile_saved_networks(nm, http_state.clone()));
 
-        let (mut dns_server, dns_exit) =
-            dns_server::CaptiveDnsServer::new(SocketAddrV4::new(config.gateway.clone(), config.dns_port));
-        let (mut dhcp_server, dhcp_exit) =
-            dhcp_server::DHCPServer::new(SocketAddrV4::new(config.gateway.clone(), config.dhcp_port));
-
-        tokio::spawn(async move {
-            if let Err(e) = dns_server.run().await {
-                error!("{}", e);
-            }
-        });
-        tokio::spawn(async move {
-            if let Err(e) = dhcp_server.run().await {
-                error!("{}", e);
-            }
-        });
+        #[cfg(any(feature = "dns", feature = "dhcp"))]
+        let interface = bound_interface(nm);
+        #[cfg(feature = "dns")]
+        let dns_exit = spawn_dns_server(config, interface.clone());
+        #[cfg(feature = "dhcp")]
+        let dhcp_exit = spawn_dhcp_server(config, sessions.clone(), interface.clone());
 
-        let nm_clone = nm.clone();
-        tokio::spawn(async move {
-            let stream = ap_changed_stream(&nm_clone).await;
+        runtime::spawn_tokio(async move {
+            let stream = shared.ap_changed_stream().await;
             let mut stream = match stream {
                 Err(e) => {
                     error!("{}", e);
@@ -92,7 +165,7 @@ impl<'a> Portal<'a> {
                 Ok(stream) => stream,
             };
             for event in stream.next().await {
-                let access_point = nm_clone.access_point(event.path).await;
+                let access_point = shared.access_point(event.path).await;
                 if let Ok(access_point) = access_point {
                     if access_point.is_own {
                         continue;
@@ -107,29 +180,186 @@ impl<'a> Portal<'a> {
         });
 
         let (exit_handler, exit_receiver) = tokio::sync::oneshot::channel::<()>();
+        info!("Portal page at {}", config.portal_url());
 
         let portal = Portal {
             http_server: Box::pin(http_server.run()),
+            #[cfg(feature = "dns")]
             dns_exit,
+            #[cfg(feature = "dhcp")]
             dhcp_exit,
+            #[cfg(feature = "dhcp")]
+            sessions,
+            #[cfg(feature = "dhcp")]
+            link_local: None,
+            #[cfg(any(feature = "dns", feature = "dhcp"))]
+            interface,
             exit_receiver: Some(exit_receiver),
             http_exit: Some(http_exit),
-            timeout: Some(nm.wait_for_connectivity(config.internet_connectivity, timeout).boxed()),
-            hotspot_stopped_fut: Some(nm.on_hotspot_stopped(wifi_sta_active_connection).boxed()),
+            timeout: timeout.map(|timeout| -> BoxFuture<'a, _> {
+                Box::pin(nm.wait_for_connectivity(config.internet_connectivity, timeout))
+            }),
+            hotspot_stopped_fut: Some(Box::pin(nm.on_hotspot_stopped(wifi_sta_active_connection))),
+            saved_network_fut: Some(saved_network_fut),
         };
 
         Ok((portal, exit_handler))
     }
+
+    /// Stops and spawns the given service again, for example if it failed the smoke test.
+    /// The hotspot address is not a service of the portal and is left as it is.
+    pub async fn restart(&mut self, config: &crate::config::Config, subsystem: Subsystem) {
+        match subsystem {
+            #[cfg(feature = "dns")]
+            Subsystem::Dns => {
+                // Dropping the exit handler stops the server. Its socket is closed before the new server binds.
+                self.dns_exit = tokio::sync::oneshot::channel().0;
+                delay_for(RESTART_DELAY).await;
+                self.dns_exit = spawn_dns_server(config, self.interface.clone());
+            },
+            #[cfg(feature = "dhcp")]
+            Subsystem::Dhcp => {
+                self.dhcp_exit = tokio::sync::oneshot::channel().0;
+                delay_for(RESTART_DELAY).await;
+                self.dhcp_exit = spawn_dhcp_server(config, self.sessions.clone(), self.interface.clone());
+            },
+            _ => {},
+        }
+    }
+
+    /// Adds a link-local address to the hotspot interface and announces the portal on it via mDNS, for clients
+    /// that do not get an address from the failed dhcp server. Returns the url of the portal.
+    #[cfg(feature = "dhcp")]
+    pub async fn link_local_fallback(
+        &mut self,
+        config: &crate::config::Config,
+        interface_name: &str,
+    ) -> Result<String, CaptivePortalError> {
+        if self.link_local.is_none() {
+            let portal = SocketAddrV4::new(config.gateway.clone(), config.listening_port);
+            self.link_local =
+                Some(crate::link_local::LinkLocal::start(interface_name, &config.link_local_name, portal).await?);
+        }
+        let link_local = self.link_local.as_ref().expect("Link-local fallback started");
+        Ok(match config.listening_port {
+            80 => format!("http://{}", link_local.host),
+            port => format!("http://{}:{}", link_local.host, port),
+        })
+    }
+}
+
+/// Keeps the saved networks of the http server up to date while the portal is online. Administrators may
+/// add or remove connections outside of the portal, for example with nmcli.
+/// Resolves when a newly saved network is in range. The portal closes then and the state machine tries the
+/// saved connections. Never resolves if the connection changes cannot be observed.
+async fn reconcile_saved_networks<B: WifiBackend>(nm: &B, http_state: http_server::HttpServerStateSync) {
+    use crate::network_interface::WifiConnectionEventType;
+
+    let mut changes = match nm.settings_changed_stream().await {
+        Ok(changes) => changes,
+        Err(CaptivePortalError::Unsupported(_)) => return futures_util::future::pending().await,
+        Err(e) => {
+            warn!("Changes of the saved networks cannot be observed: {}", e);
+            return futures_util::future::pending().await;
+        },
+    };
+    let mut saved = nm.saved_ssids().await.unwrap_or_default();
+    http_server::set_saved_networks(&http_state, saved.clone());
+
+    while let Some(change) = changes.next().await {
+        info!("Saved connection changed outside of the portal: {}", change);
+        let previous = std::mem::replace(&mut saved, nm.saved_ssids().await.unwrap_or_default());
+        http_server::set_saved_networks(&http_state, saved.clone());
+        if let WifiConnectionEventType::Removed = change.event {
+            continue;
+        }
+        let in_range = {
+            let state = http_state.lock().expect("Lock http_state mutex for saved networks");
+            saved
+                .iter()
+                .filter(|ssid| !previous.contains(ssid))
+                .find(|ssid| state.connections.0.iter().any(|c| &&c.ssid == ssid))
+                .cloned()
+        };
+        if let Some(ssid) = in_range {
+            info!("The saved network {} is in range. Closing the portal", ssid);
+            return;
+        }
+    }
+    futures_util::future::pending().await
+}
+
+/// The interface that the dns and dhcp servers are bound to: The hotspot interface while a station interface
+/// is up (concurrent access point and station mode). Otherwise the servers are not bound to an interface,
+/// because some backends serve the hotspot on a bridge.
+#[cfg(any(feature = "dns", feature = "dhcp"))]
+fn bound_interface<B: WifiBackend>(nm: &B) -> Option<String> {
+    nm.station_interface().map(|_| nm.interface_name().to_owned())
+}
+
+/// Spawns the captive dns server. Returns the exit handler. The server stops when it is dropped.
+#[cfg(feature = "dns")]
+fn spawn_dns_server(
+    config: &crate::config::Config,
+    interface: Option<String>,
+) -> tokio::sync::oneshot::Sender<()> {
+    use super::dns_server;
+    let (mut dns_server, dns_exit) =
+        dns_server::CaptiveDnsServer::new(SocketAddrV4::new(config.gateway.clone(), config.dns_port));
+    if let Some(interface_name) = interface {
+        dns_server.set_interface(interface_name);
+    }
+    if let Some(hostname) = config.portal_hostname.as_ref() {
+        dns_server.set_hostname(hostname);
+    }
+    tokio::spawn(async move {
+        if let Err(e) = dns_server.run().await {
+            error!("{}", e);
+        }
+    });
+    dns_exit
+}
+
+/// Spawns the dhcp server. Returns the exit handler. The server stops when it is dropped.
+#[cfg(feature = "dhcp")]
+fn spawn_dhcp_server(
+    config: &crate::config::Config,
+    sessions: crate::sessions::SessionsSync,
+    interface: Option<String>,
+) -> tokio::sync::oneshot::Sender<()> {
+    use super::dhcp_server;
+    let (mut dhcp_server, dhcp_exit) =
+        dhcp_server::DHCPServer::new(SocketAddrV4::new(config.gateway.clone(), config.dhcp_port));
+    dhcp_server.set_sessions(sessions);
+    if let Some(interface_name) = interface {
+        dhcp_server.set_interface(interface_name);
+    }
+    dhcp_server.set_mac_filter(config.mac_filter.clone());
+    if let Some(max_clients) = config.max_clients {
+        dhcp_server.set_max_clients(max_clients as usize);
+    }
+    if let Some(state_dir) = config.persistence_dir() {
+        let lease_file = state_dir.join(crate::state_dir::DHCP_LEASES_FILE);
+        if let Err(e) = dhcp_server.set_lease_file(lease_file, Duration::from_secs(config.flush_interval)) {
+            warn!("Could not restore dhcp leases: {}", e);
+        }
+    }
+    tokio::spawn(async move {
+        if let Err(e) = dhcp_server.run().await {
+            error!("{}", e);
+        }
+    });
+    dhcp_exit
 }
 
 /// The portal is also a future. It polls on various exit conditions like the timeout,
-/// a user selected wifi, or when the active connection changes its state. And it
+/// the exit handler, or when the active connection changes its state. And it
 /// also polls on the webserver of course.
 ///
 /// All polled futures are wrapped in Optional in the portal structure, because we do not
 /// want to call a resolved future again.
 impl<'a> Future for Portal<'a> {
-    type Output = Result<Option<WifiConnectionRequest>, CaptivePortalError>;
+    type Output = Result<(), CaptivePortalError>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
         let mut exit_soon = false;
@@ -149,6 +379,13 @@ impl<'a> Future for Portal<'a> {
             }
         }
 
+        if let Some(saved_network_fut) = self.saved_network_fut.as_mut() {
+            if let Poll::Ready(_) = saved_network_fut.as_mut().poll(cx) {
+                exit_soon = true;
+                take_optional(self.as_mut(), |me| &mut me.saved_network_fut);
+            }
+        }
+
         if let Some(timeout) = self.timeout.as_mut() {
             if let Poll::Ready(_) = timeout.poll_unpin(cx) {
                 exit_soon = true;
@@ -0,0 +1,129 @@
+//! # Connection quality gate
+//! A freshly established connection is measured before it is declared successful: The signal strength
+//! and bitrate are reported by the network backend, the round trip time to the default gateway is measured here.
+//!
+//! If a measured value is below a configured threshold, the state machine reopens the portal and the user
+//! is asked whether to continue with the weak connection anyway. Values that cannot be measured pass the gate.
+
+use crate::network_interface::ConnectionQuality;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// The port the gateway round trip time is measured with. Most routers run a dns forwarder.
+/// A rejected connection is an answer as well.
+const GATEWAY_PORT: u16 = 53;
+/// The gateway has not answered if there is no answer within this time
+const GATEWAY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The thresholds of the gate. A threshold that is not set is not checked.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QualityGate {
+    /// Minimum signal strength in percent
+    pub min_signal: Option<u8>,
+    /// Minimum bitrate in kbit/s
+    pub min_bitrate: Option<u32>,
+    /// Maximum gateway round trip time in milliseconds
+    pub max_gateway_rtt: Option<u32>,
+}
+
+impl QualityGate {
+    /// True if no threshold is set. The connection does not need to be measured.
+    pub fn is_disabled(&self) -> bool {
+        self.min_signal.is_none() && self.min_bitrate.is_none() && self.max_gateway_rtt.is_none()
+    }
+
+    /// Returns the names of the failed criteria. The connection passes the gate if the list is empty.
+    pub fn check(&self, quality: &ConnectionQuality) -> Vec<&'static str> {
+        let mut failed = Vec::new();
+        if let (Some(min), Some(signal)) = (self.min_signal, quality.signal) {
+            if signal < min {
+                failed.push("signal");
+            }
+        }
+        if let (Some(min), Some(bitrate)) = (self.min_bitrate, quality.bitrate) {
+            if bitrate < min {
+                failed.push("bitrate");
+            }
+        }
+        if let (Some(max), Some(rtt)) = (self.max_gateway_rtt, quality.gateway_rtt) {
+            if rtt > max {
+                failed.push("gateway_rtt");
+            }
+        }
+        failed
+    }
+}
+
+/// Returns the default gateway with the lowest metric of a routing table in the format of "/proc/net/route".
+pub fn parse_default_gateway(route_table: &str) -> Option<Ipv4Addr> {
+    route_table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            // Iface Destination Gateway Flags RefCnt Use Metric Mask ...
+            if fields.len() < 8 || fields[1] != "00000000" || fields[7] != "00000000" {
+                return None;
+            }
+            // The addresses are in host byte order
+            let gateway = u32::from_str_radix(fields[2], 16).ok().filter(|g| *g != 0)?;
+            let metric: u32 = fields[6].parse().ok()?;
+            Some((metric, Ipv4Addr::from(gateway.to_ne_bytes())))
+        })
+        .min_by_key(|(metric, _)| *metric)
+        .map(|(_, gateway)| gateway)
+}
+
+/// Measures the round trip time to the default gateway in milliseconds with a tcp connection attempt.
+/// Returns None if there is no default gateway or it did not answer.
+pub async fn gateway_rtt() -> Option<u32> {
+    let route_table = std::fs::read_to_string("/proc/net/route").ok()?;
+    let gateway = parse_default_gateway(&route_table)?;
+    let started = Instant::now();
+    let connect = tokio::net::TcpStream::connect(SocketAddr::from((gateway, GATEWAY_PORT)));
+    match tokio::time::timeout(GATEWAY_TIMEOUT, connect).await {
+        Ok(Ok(_)) => {},
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => {},
+        Ok(Err(e)) => {
+            info!("Gateway {} not reachable: {}", gateway, e);
+            return None;
+        },
+        Err(_) => {
+            info!("Gateway {} did not answer within {:?}", gateway, GATEWAY_TIMEOUT);
+            return None;
+        },
+    }
+    Some(started.elapsed().as_millis() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gate() {
+        let gate = QualityGate {
+            min_signal: Some(40),
+            min_bitrate: Some(6000),
+            max_gateway_rtt: None,
+        };
+        let quality = ConnectionQuality {
+            signal: Some(30),
+            bitrate: None,
+            gateway_rtt: Some(900),
+        };
+        assert_eq!(gate.check(&quality), vec!["signal"]);
+        assert!(QualityGate::default().is_disabled());
+        assert!(QualityGate::default().check(&quality).is_empty());
+    }
+
+    #[test]
+    fn default_gateway() {
+        let route_table = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0
+wlan0\t00000000\t012AA8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+wlan0\t002AA8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0";
+        assert_eq!(parse_default_gateway(route_table), Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(parse_default_gateway(""), None);
+    }
+}
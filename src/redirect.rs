@@ -0,0 +1,207 @@
+//! # Captive portal redirect strategies
+//! The connectivity check of a client ("probe") is answered either with a redirect to the portal page
+//! or with a page that opens the portal inline (status 200 and a refresh). Some Android vendor builds only
+//! show the sign-in sheet for one of them and Apple's captive network assistant prefers the inline page.
+//!
+//! Each client starts with the strategy that worked for its dhcp fingerprint before, or the default of
+//! its device type. If the client probes again and again without loading the portal page, the sheet
+//! did not show up and the other strategy is tried. The outcomes are recorded per fingerprint and
+//! persisted in the state directory.
+
+use crate::sessions::DeviceType;
+use crate::state_dir::StateFile;
+use crate::CaptivePortalError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// File name of the recorded outcomes within the state directory
+pub const REDIRECT_STRATEGIES_FILE: &str = "redirect_strategies.json";
+
+/// Probes without loading the portal page after which the other strategy is tried
+pub const MAX_UNANSWERED_PROBES: u32 = 2;
+
+/// Fingerprints are provided by unauthenticated clients. No new fingerprints are recorded above this limit.
+const MAX_RECORDS: usize = 256;
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RedirectStrategy {
+    /// "302 Found" to the portal page
+    Redirect,
+    /// "200 OK" with a page that refreshes to the portal page
+    Inline,
+}
+
+impl RedirectStrategy {
+    /// The strategy for a client without recorded outcomes
+    pub fn default_for(device: DeviceType) -> Self {
+        match device.is_apple() {
+            true => RedirectStrategy::Inline,
+            false => RedirectStrategy::Redirect,
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            RedirectStrategy::Redirect => RedirectStrategy::Inline,
+            RedirectStrategy::Inline => RedirectStrategy::Redirect,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct Outcome {
+    pub worked: u32,
+    pub failed: u32,
+}
+
+impl Outcome {
+    fn score(&self) -> i64 {
+        self.worked as i64 - self.failed as i64
+    }
+}
+
+/// The outcomes of both strategies for one fingerprint
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct StrategyRecord {
+    pub redirect: Outcome,
+    pub inline: Outcome,
+}
+
+impl StrategyRecord {
+    fn outcome_mut(&mut self, strategy: RedirectStrategy) -> &mut Outcome {
+        match strategy {
+            RedirectStrategy::Redirect => &mut self.redirect,
+            RedirectStrategy::Inline => &mut self.inline,
+        }
+    }
+
+    /// The strategy with the better outcomes. None if both are equal.
+    fn preferred(&self) -> Option<RedirectStrategy> {
+        match self.redirect.score().cmp(&self.inline.score()) {
+            std::cmp::Ordering::Greater => Some(RedirectStrategy::Redirect),
+            std::cmp::Ordering::Less => Some(RedirectStrategy::Inline),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+}
+
+/// The strategy of one client. Part of its session.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct ClientProbe {
+    pub strategy: Option<RedirectStrategy>,
+    /// Probes answered with the current strategy without loading the portal page
+    pub unanswered: u32,
+    /// The client loaded the portal page. The strategy is not changed anymore.
+    pub worked: bool,
+}
+
+/// The recorded outcomes by fingerprint
+#[derive(Default)]
+pub struct RedirectRecords {
+    records: HashMap<String, StrategyRecord>,
+    file: Option<StateFile>,
+}
+
+impl RedirectRecords {
+    /// Restores the outcomes from the given file, if it exists. Changes are written at most once per
+    /// flush interval and by [`RedirectRecords::store`].
+    pub fn with_file(path: PathBuf, flush_interval: Duration) -> Result<Self, CaptivePortalError> {
+        let records = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(_) => HashMap::new(),
+        };
+        Ok(RedirectRecords {
+            records,
+            file: Some(StateFile::new(path, flush_interval)),
+        })
+    }
+
+    pub fn get(&self, fingerprint: &str) -> Option<&StrategyRecord> {
+        self.records.get(fingerprint)
+    }
+
+    /// Chooses the strategy to answer a probe of the given client with
+    pub fn probe(&mut self, client: &mut ClientProbe, fingerprint: &str, device: DeviceType) -> RedirectStrategy {
+        let strategy = match client.strategy {
+            Some(strategy) if client.worked => return strategy,
+            Some(strategy) if client.unanswered >= MAX_UNANSWERED_PROBES => {
+                info!("Probes of {} not answered with {:?}", fingerprint, strategy);
+                self.record(fingerprint, strategy, false);
+                client.unanswered = 0;
+                strategy.other()
+            },
+            Some(strategy) => strategy,
+            None => self
+                .records
+                .get(fingerprint)
+                .and_then(|r| r.preferred())
+                .unwrap_or_else(|| RedirectStrategy::default_for(device)),
+        };
+        client.strategy = Some(strategy);
+        client.unanswered += 1;
+        strategy
+    }
+
+    /// The client loaded the portal page: Its current strategy worked
+    pub fn portal_loaded(&mut self, client: &mut ClientProbe, fingerprint: &str) {
+        if let (Some(strategy), false) = (client.strategy, client.worked) {
+            client.worked = true;
+            self.record(fingerprint, strategy, true);
+        }
+    }
+
+    fn record(&mut self, fingerprint: &str, strategy: RedirectStrategy, worked: bool) {
+        if !self.records.contains_key(fingerprint) && self.records.len() >= MAX_RECORDS {
+            return;
+        }
+        let outcome = self
+            .records
+            .entry(fingerprint.to_owned())
+            .or_default()
+            .outcome_mut(strategy);
+        match worked {
+            true => outcome.worked += 1,
+            false => outcome.failed += 1,
+        }
+        self.store(false);
+    }
+
+    /// Stores the outcomes, see [`StateFile::store`].
+    pub fn store(&mut self, force: bool) {
+        if let Some(file) = self.file.as_mut() {
+            file.store(&self.records, force);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strategies() {
+        let mut records = RedirectRecords::default();
+        let fingerprint = "1,3,6,15,26,28,51,58,59,43";
+
+        // The sheet only shows up for the inline page
+        let mut client = ClientProbe::default();
+        assert_eq!(records.probe(&mut client, fingerprint, DeviceType::Android), RedirectStrategy::Redirect);
+        assert_eq!(records.probe(&mut client, fingerprint, DeviceType::Android), RedirectStrategy::Redirect);
+        assert_eq!(records.probe(&mut client, fingerprint, DeviceType::Android), RedirectStrategy::Inline);
+        records.portal_loaded(&mut client, fingerprint);
+        assert_eq!(records.probe(&mut client, fingerprint, DeviceType::Android), RedirectStrategy::Inline);
+
+        let record = records.get(fingerprint).unwrap();
+        assert_eq!(record.redirect, Outcome { worked: 0, failed: 1 });
+        assert_eq!(record.inline, Outcome { worked: 1, failed: 0 });
+
+        // The next client with that fingerprint starts with the inline page
+        let mut client = ClientProbe::default();
+        assert_eq!(records.probe(&mut client, fingerprint, DeviceType::Android), RedirectStrategy::Inline);
+        let mut client = ClientProbe::default();
+        assert_eq!(records.probe(&mut client, "other", DeviceType::Ios), RedirectStrategy::Inline);
+    }
+}
@@ -0,0 +1,170 @@
+//! # Regulatory domain
+//! Not every channel may be used for an access point in every country. Network manager fails the activation
+//! of such a hotspot with an error that does not name the cause.
+//!
+//! The active regulatory domain is read from the kernel via nl80211 ([`query`]). A hotspot channel that the
+//! domain does not permit is replaced by a permitted one with a warning, see [`correct_radio`].
+
+use crate::channels::{candidates, channel_to_frequency, least_congested_of};
+use crate::netlink::{attributes, family_id, generic_exchange, generic_request};
+use crate::network_interface::{HotspotBand, HotspotRadio, WifiConnection};
+use crate::CaptivePortalError;
+
+const NL80211_CMD_GET_REG: u8 = 31;
+const NL80211_ATTR_REG_ALPHA2: u16 = 33;
+const NL80211_ATTR_REG_RULES: u16 = 34;
+
+const NL80211_ATTR_REG_RULE_FLAGS: u16 = 1;
+const NL80211_ATTR_FREQ_RANGE_START: u16 = 2;
+const NL80211_ATTR_FREQ_RANGE_END: u16 = 3;
+
+const NL80211_RRF_DFS: u32 = 1 << 4;
+const NL80211_RRF_NO_IR: u32 = 1 << 7;
+
+/// Half the width of a 20 MHz channel in kHz
+const HALF_CHANNEL_WIDTH: u32 = 10_000;
+
+/// A frequency range of a regulatory domain, in kHz
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegRule {
+    pub start: u32,
+    pub end: u32,
+    pub flags: u32,
+}
+
+/// The regulatory domain: The country code, like "DE" or "00" for the world domain, and its rules
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RegDomain {
+    pub country: String,
+    pub rules: Vec<RegRule>,
+}
+
+impl RegDomain {
+    /// Whether an access point may be started on the given channel. Channels that require radar detection (DFS)
+    /// or that forbid initiating radiation (no-IR) are not permitted.
+    pub fn permits(&self, channel: u32) -> bool {
+        let center = channel_to_frequency(channel) * 1000;
+        self.rules.iter().any(|rule| {
+            rule.start <= center - HALF_CHANNEL_WIDTH
+                && center + HALF_CHANNEL_WIDTH <= rule.end
+                && rule.flags & (NL80211_RRF_DFS | NL80211_RRF_NO_IR) == 0
+        })
+    }
+}
+
+fn read_u32(payload: &[u8]) -> Option<u32> {
+    if payload.len() < 4 {
+        return None;
+    }
+    Some(u32::from_ne_bytes([payload[0], payload[1], payload[2], payload[3]]))
+}
+
+/// Parses the attributes of a NL80211_CMD_GET_REG reply
+fn parse_domain(data: &[u8]) -> RegDomain {
+    let mut domain = RegDomain::default();
+    for (kind, payload) in attributes(data) {
+        match kind {
+            NL80211_ATTR_REG_ALPHA2 => {
+                domain.country = String::from_utf8_lossy(payload).trim_end_matches('\0').to_owned();
+            },
+            NL80211_ATTR_REG_RULES => {
+                for (_, rule) in attributes(payload) {
+                    let mut reg_rule = RegRule { start: 0, end: 0, flags: 0 };
+                    for (kind, payload) in attributes(rule) {
+                        let value = read_u32(payload).unwrap_or_default();
+                        match kind {
+                            NL80211_ATTR_REG_RULE_FLAGS => reg_rule.flags = value,
+                            NL80211_ATTR_FREQ_RANGE_START => reg_rule.start = value,
+                            NL80211_ATTR_FREQ_RANGE_END => reg_rule.end = value,
+                            _ => {},
+                        }
+                    }
+                    domain.rules.push(reg_rule);
+                }
+            },
+            _ => {},
+        }
+    }
+    domain
+}
+
+/// Reads the active regulatory domain from the kernel
+pub fn query() -> Result<RegDomain, CaptivePortalError> {
+    let family = family_id("nl80211").map_err(|e| CaptivePortalError::IO(e, "nl80211 is not available"))?;
+    let reply = generic_exchange(&generic_request(family, NL80211_CMD_GET_REG, &[]))
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to read the regulatory domain"))?;
+    Ok(parse_domain(&reply))
+}
+
+/// A radio with a channel that the domain permits. The channel is kept if permitted. Otherwise the least
+/// congested permitted channel of the same band is chosen, then of the other band. If no channel is permitted,
+/// the channel is left to the network backend.
+pub fn correct_radio(domain: &RegDomain, radio: HotspotRadio, access_points: &[WifiConnection]) -> HotspotRadio {
+    let channel = match radio.channel {
+        Some(channel) if !domain.permits(channel) => channel,
+        _ => return radio,
+    };
+    let band = HotspotBand::of_channel(channel).unwrap_or(radio.band);
+    let other = match band {
+        HotspotBand::Bg => HotspotBand::A,
+        HotspotBand::A => HotspotBand::Bg,
+    };
+    for band in [band, other].iter().copied() {
+        let permitted: Vec<u32> = candidates(band).iter().copied().filter(|c| domain.permits(*c)).collect();
+        if let Some(channel) = least_congested_of(access_points, &permitted) {
+            return HotspotRadio { band, channel: Some(channel) };
+        }
+    }
+    HotspotRadio { channel: None, ..radio }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netlink::push_attribute;
+
+    fn rule(start_mhz: u32, end_mhz: u32, flags: u32) -> Vec<u8> {
+        let mut rule = Vec::new();
+        push_attribute(&mut rule, NL80211_ATTR_REG_RULE_FLAGS, &flags.to_ne_bytes());
+        push_attribute(&mut rule, NL80211_ATTR_FREQ_RANGE_START, &(start_mhz * 1000).to_ne_bytes());
+        push_attribute(&mut rule, NL80211_ATTR_FREQ_RANGE_END, &(end_mhz * 1000).to_ne_bytes());
+        rule
+    }
+
+    fn domain() -> RegDomain {
+        let mut rules = Vec::new();
+        push_attribute(&mut rules, 0, &rule(2402, 2482, 0));
+        push_attribute(&mut rules, 1, &rule(5170, 5250, NL80211_RRF_NO_IR));
+        let mut data = Vec::new();
+        push_attribute(&mut data, NL80211_ATTR_REG_ALPHA2, b"DE\0");
+        push_attribute(&mut data, NL80211_ATTR_REG_RULES, &rules);
+        parse_domain(&data)
+    }
+
+    #[test]
+    fn reg_domain() {
+        let domain = domain();
+        assert_eq!(domain.country, "DE");
+        assert_eq!(domain.rules.len(), 2);
+        assert_eq!(domain.rules[0], RegRule { start: 2_402_000, end: 2_482_000, flags: 0 });
+        assert!(domain.permits(1));
+        assert!(domain.permits(13));
+        assert!(!domain.permits(14));
+        assert!(!domain.permits(36));
+    }
+
+    #[test]
+    fn corrected_radio() {
+        let domain = domain();
+        let radio = HotspotRadio { band: HotspotBand::Bg, channel: Some(6) };
+        assert_eq!(correct_radio(&domain, radio, &[]), radio);
+
+        let radio = HotspotRadio { band: HotspotBand::A, channel: Some(40) };
+        let expected = HotspotRadio { band: HotspotBand::Bg, channel: Some(1) };
+        assert_eq!(correct_radio(&domain, radio, &[]), expected);
+
+        let radio = HotspotRadio { band: HotspotBand::A, channel: Some(40) };
+        let expected = HotspotRadio { band: HotspotBand::A, channel: None };
+        assert_eq!(correct_radio(&RegDomain::default(), radio, &[]), expected);
+    }
+}
@@ -0,0 +1,62 @@
+//! # Evil twin detection
+//! An access point that broadcasts the setup SSID of this device ("evil twin") lures the clients away from the
+//! portal and collects the wifi credentials they enter.
+//!
+//! The scan before the hotspot is started reveals such access points, see [`twins`]. A warning is logged
+//! and the portal shows their hardware addresses (`twin_aps` in the status). With `--randomize-ssid`, the hotspot
+//! uses the setup SSID with a random suffix instead, see [`randomized_ssid`].
+
+use crate::network_interface::WifiConnection;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// The maximum SSID length in bytes
+const MAX_SSID_LEN: usize = 32;
+
+/// The hardware addresses of the foreign access points that broadcast the given SSID
+pub fn twins(access_points: &[WifiConnection], ssid: &str) -> Vec<String> {
+    access_points
+        .iter()
+        .filter(|ap| !ap.is_own && ap.ssid == ssid)
+        .map(|ap| ap.hw.clone())
+        .collect()
+}
+
+/// The given SSID with a random suffix of four hex digits, like "WiFi Connect-3F0A".
+/// The SSID is shortened if necessary.
+pub fn randomized_ssid(ssid: &str) -> String {
+    let suffix = format!("-{:04X}", RandomState::new().build_hasher().finish() as u16);
+    let mut end = ssid.len().min(MAX_SSID_LEN - suffix.len());
+    while !ssid.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &ssid[..end], suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ap(ssid: &str, hw: &str, is_own: bool) -> WifiConnection {
+        WifiConnection {
+            ssid: ssid.into(),
+            hw: hw.to_owned(),
+            security: "none",
+            strength: 50,
+            frequency: 2412,
+            is_own,
+            vendor: None,
+        }
+    }
+
+    #[test]
+    fn evil_twins() {
+        let access_points = [ap("Setup", "aa", false), ap("Setup", "bb", true), ap("Home", "cc", false)];
+        assert_eq!(twins(&access_points, "Setup"), vec!["aa".to_owned()]);
+
+        let ssid = randomized_ssid("WiFi Connect");
+        assert!(ssid.starts_with("WiFi Connect-"));
+        assert_eq!(ssid.len(), 17);
+        assert_eq!(randomized_ssid(&"ä".repeat(16)).len(), 31);
+    }
+}
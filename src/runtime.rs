@@ -0,0 +1,211 @@
+//! # Async runtime abstraction
+//! The network backends need three things from an async runtime: Spawning the D-Bus connection task,
+//! timers and a reactor to watch the D-Bus socket. These are behind the [`Runtime`] trait,
+//! so that the library can be embedded into applications that do not use tokio.
+//!
+//! Tokio is used by default. With the "async-std-runtime" feature, tasks are spawned with async-std
+//! and the D-Bus socket is registered with async-io, the reactor shared by async-std and smol.
+//!
+//! The http, dns and dhcp servers of the captive portal binary always run on tokio.
+//!
+//! With the "single-threaded" feature, the network backends use a [`dbus::nonblock::LocalConnection`]
+//! and tasks are spawned onto the current [`tokio::task::LocalSet`]. Futures and streams of the backends
+//! are not `Send` in this mode. The binary then runs on tokios current-thread scheduler,
+//! which saves the memory of the worker threads on small devices.
+
+use futures_util::future::{select, Either};
+use std::future::Future;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+#[cfg(all(feature = "single-threaded", feature = "async-std-runtime"))]
+compile_error!("The \"single-threaded\" feature requires the tokio runtime");
+
+/// A boxed future of the network backends. Not `Send` with the "single-threaded" feature.
+#[cfg(not(feature = "single-threaded"))]
+pub use futures_util::future::BoxFuture;
+#[cfg(feature = "single-threaded")]
+pub use futures_util::future::LocalBoxFuture as BoxFuture;
+
+/// A boxed stream of the network backends. Not `Send` with the "single-threaded" feature.
+#[cfg(not(feature = "single-threaded"))]
+pub use futures_util::stream::BoxStream;
+#[cfg(feature = "single-threaded")]
+pub use futures_util::stream::LocalBoxStream as BoxStream;
+
+/// `Send + Sync`, except with the "single-threaded" feature. A network backend that is shared
+/// between tasks must be, see [`crate::network_backend::WifiBackend`].
+#[cfg(not(feature = "single-threaded"))]
+pub trait MaybeSend: Send + Sync {}
+#[cfg(not(feature = "single-threaded"))]
+impl<T: Send + Sync> MaybeSend for T {}
+#[cfg(feature = "single-threaded")]
+pub trait MaybeSend {}
+#[cfg(feature = "single-threaded")]
+impl<T> MaybeSend for T {}
+
+/// Readiness of a file descriptor that is registered with the runtimes reactor.
+pub trait FdRegistration: Send + Sync + Unpin {
+    /// Resets the readiness state, so that the next poll waits for new events.
+    fn clear_readiness(&self) -> io::Result<()>;
+    /// Resolves if the file descriptor is readable and registers the waker otherwise
+    fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+    /// Resolves if the file descriptor is writable and registers the waker otherwise
+    fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+}
+
+pub trait Runtime {
+    type Registration: FdRegistration;
+
+    /// Runs the given future in the background
+    fn spawn(future: BoxFuture<'static, ()>);
+    /// Resolves after the given duration
+    fn delay(duration: Duration) -> BoxFuture<'static, ()>;
+    /// Registers the file descriptor with the reactor. The descriptor is not closed on drop.
+    fn register(fd: RawFd) -> io::Result<Self::Registration>;
+}
+
+/// The tokio 0.2 runtime
+pub struct Tokio;
+
+impl FdRegistration for tokio::io::Registration {
+    fn clear_readiness(&self) -> io::Result<()> {
+        self.take_read_ready()?;
+        self.take_write_ready()?;
+        Ok(())
+    }
+
+    fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_read_ready(cx).map_ok(|_| ())
+    }
+
+    fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_write_ready(cx).map_ok(|_| ())
+    }
+}
+
+impl Runtime for Tokio {
+    type Registration = tokio::io::Registration;
+
+    #[cfg(not(feature = "single-threaded"))]
+    fn spawn(future: BoxFuture<'static, ()>) {
+        tokio::spawn(future);
+    }
+
+    #[cfg(feature = "single-threaded")]
+    fn spawn(future: BoxFuture<'static, ()>) {
+        tokio::task::spawn_local(future);
+    }
+
+    fn delay(duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::delay_for(duration))
+    }
+
+    fn register(fd: RawFd) -> io::Result<Self::Registration> {
+        tokio::io::Registration::new(&mio::unix::EventedFd(&fd))
+    }
+}
+
+#[cfg(feature = "async-std-runtime")]
+pub use self::async_std_runtime::AsyncStd;
+
+#[cfg(feature = "async-std-runtime")]
+mod async_std_runtime {
+    use super::{FdRegistration, Runtime};
+    use futures_util::future::BoxFuture;
+    use std::io;
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    /// A borrowed file descriptor. Dropping it does not close the descriptor.
+    pub struct Fd(RawFd);
+
+    impl AsRawFd for Fd {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0
+        }
+    }
+
+    /// The async-std runtime. The reactor (async-io) is also used by smol.
+    pub struct AsyncStd;
+
+    impl FdRegistration for async_io::Async<Fd> {
+        fn clear_readiness(&self) -> io::Result<()> {
+            // async-io clears the readiness itself with every poll
+            Ok(())
+        }
+
+        fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            async_io::Async::poll_readable(self, cx)
+        }
+
+        fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            async_io::Async::poll_writable(self, cx)
+        }
+    }
+
+    impl Runtime for AsyncStd {
+        type Registration = async_io::Async<Fd>;
+
+        fn spawn(future: BoxFuture<'static, ()>) {
+            async_std::task::spawn(future);
+        }
+
+        fn delay(duration: Duration) -> BoxFuture<'static, ()> {
+            Box::pin(async_std::task::sleep(duration))
+        }
+
+        fn register(fd: RawFd) -> io::Result<Self::Registration> {
+            async_io::Async::new(Fd(fd))
+        }
+    }
+}
+
+/// The runtime of the network backends: async-std if the "async-std-runtime" feature is enabled, tokio otherwise.
+#[cfg(not(feature = "async-std-runtime"))]
+pub type DefaultRuntime = Tokio;
+#[cfg(feature = "async-std-runtime")]
+pub type DefaultRuntime = AsyncStd;
+
+/// Runs the given future in the background on the [`DefaultRuntime`]
+#[cfg(not(feature = "single-threaded"))]
+pub fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+    DefaultRuntime::spawn(Box::pin(future))
+}
+
+/// Runs the given future in the background on the current [`tokio::task::LocalSet`]
+#[cfg(feature = "single-threaded")]
+pub fn spawn<F: Future<Output = ()> + 'static>(future: F) {
+    DefaultRuntime::spawn(Box::pin(future))
+}
+
+/// Spawns a task of the captive portal (http server, access point updates) onto tokio.
+/// With the "single-threaded" feature, the task is spawned onto the current [`tokio::task::LocalSet`].
+#[cfg(not(feature = "single-threaded"))]
+pub fn spawn_tokio<F: Future<Output = ()> + Send + 'static>(future: F) {
+    tokio::spawn(future);
+}
+
+/// Spawns a task of the captive portal (http server, access point updates) onto tokio.
+/// With the "single-threaded" feature, the task is spawned onto the current [`tokio::task::LocalSet`].
+#[cfg(feature = "single-threaded")]
+pub fn spawn_tokio<F: Future<Output = ()> + 'static>(future: F) {
+    tokio::task::spawn_local(future);
+}
+
+/// Resolves after the given duration
+pub async fn delay_for(duration: Duration) {
+    DefaultRuntime::delay(duration).await
+}
+
+/// Returns the output of the given future or None if the duration expired first.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    let future = Box::pin(future);
+    match select(future, DefaultRuntime::delay(duration)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
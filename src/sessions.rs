@@ -0,0 +1,408 @@
+//! # Hotspot client sessions
+//!
+//! Clients of the captive portal are tracked by their IP address. The dhcp server contributes
+//! the client hardware address, host name and dhcp fingerprint (parameter request list and vendor class),
+//! the http server contributes the browser user agent. Both are used to guess the device type,
+//! which is shown in the session list and used to answer the captive portal detection of that device.
+//!
+//! A client that opened the deep link of the device QR code with the pairing token is marked as paired.
+//! A language picked with the "lang" query parameter is kept for the following requests of the client.
+//!
+//! With a state directory, the sessions are persisted and clients keep their pairing and language after a restart.
+
+use crate::redirect::ClientProbe;
+use crate::state_dir::StateFile;
+use crate::{stats, CaptivePortalError};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// File name of the persisted sessions within the state directory
+pub const SESSIONS_FILE: &str = "sessions.json";
+
+/// User agents and dhcp strings are provided by unauthenticated clients. Longer values are truncated.
+const MAX_VALUE_LEN: usize = 256;
+
+/// Sessions are created by unauthenticated clients. Above this limit, the least recently seen one is replaced.
+const MAX_SESSIONS: usize = 256;
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceType {
+    Unknown,
+    /// An Apple device. The dhcp fingerprint does not tell iOS and macOS apart.
+    Apple,
+    Ios,
+    Macos,
+    Android,
+    Windows,
+    Chromeos,
+    Linux,
+}
+
+impl DeviceType {
+    pub fn is_apple(&self) -> bool {
+        match self {
+            DeviceType::Apple | DeviceType::Ios | DeviceType::Macos => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Session {
+    pub ip: Ipv4Addr,
+    pub mac: Option<String>,
+    pub hostname: Option<String>,
+    /// The dhcp parameter request list, comma separated. For example "1,121,3,6,15,119,252".
+    pub dhcp_fingerprint: Option<String>,
+    pub vendor_class: Option<String>,
+    pub user_agent: Option<String>,
+    pub device: DeviceType,
+    /// The client presented the pairing token of the device QR code
+    pub paired: bool,
+    /// The redirect strategy of captive portal probes, see [`crate::redirect`]
+    pub redirect: ClientProbe,
+    /// The language picked with the "lang" query parameter, see [`crate::i18n`]
+    #[serde(deserialize_with = "deserialize_lang")]
+    pub lang: Option<&'static str>,
+    /// Unix timestamps in seconds
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+/// All known clients, by IP address
+#[derive(Default)]
+pub struct Sessions {
+    sessions: HashMap<Ipv4Addr, Session>,
+    file: Option<StateFile>,
+}
+
+/// The thread safe wrapper, shared by the http and dhcp server.
+pub type SessionsSync = Arc<Mutex<Sessions>>;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn truncated(value: &str) -> String {
+    value.chars().take(MAX_VALUE_LEN).collect()
+}
+
+/// Only supported languages are restored, see [`crate::i18n::supported`]
+fn deserialize_lang<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<&'static str>, D::Error> {
+    let lang: Option<String> = Option::deserialize(deserializer)?;
+    Ok(lang.and_then(|lang| crate::i18n::supported(&lang)))
+}
+
+impl Sessions {
+    pub fn new_sync() -> SessionsSync {
+        Arc::new(Mutex::new(Sessions::default()))
+    }
+
+    /// Restores the sessions from the given file, if it exists. Changes are written at most once per
+    /// flush interval and by [`Sessions::store`].
+    pub fn with_file(path: PathBuf, flush_interval: Duration) -> Result<Self, CaptivePortalError> {
+        let mut sessions: Vec<Session> = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(_) => Vec::new(),
+        };
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.last_seen));
+        sessions.truncate(MAX_SESSIONS);
+        Ok(Sessions {
+            sessions: sessions.into_iter().map(|s| (s.ip, s)).collect(),
+            file: Some(StateFile::new(path, flush_interval)),
+        })
+    }
+
+    /// Stores the sessions, see [`StateFile::store`].
+    ///
+    /// Only changes of the client properties are stored, not each request that updates the last seen time.
+    pub fn store(&mut self, force: bool) {
+        if let Some(file) = self.file.as_mut() {
+            file.store(&self.sessions.values().collect::<Vec<_>>(), force);
+        }
+    }
+
+    fn session(&mut self, ip: Ipv4Addr) -> &mut Session {
+        let now = now();
+        if !self.sessions.contains_key(&ip) {
+            if self.sessions.len() >= MAX_SESSIONS {
+                let oldest = self.sessions.values().min_by_key(|s| s.last_seen).map(|s| s.ip);
+                if let Some(oldest) = oldest {
+                    self.sessions.remove(&oldest);
+                }
+            }
+            self.sessions.insert(
+                ip,
+                Session {
+                    ip,
+                    mac: None,
+                    hostname: None,
+                    dhcp_fingerprint: None,
+                    vendor_class: None,
+                    user_agent: None,
+                    device: DeviceType::Unknown,
+                    paired: false,
+                    redirect: ClientProbe::default(),
+                    lang: None,
+                    first_seen: now,
+                    last_seen: now,
+                },
+            );
+            stats::record(stats::Collection::Sessions, self.sessions.len());
+        }
+        let session = self.sessions.get_mut(&ip).expect("session inserted above");
+        session.last_seen = now;
+        session
+    }
+
+    /// Called by the dhcp server when an address has been acknowledged.
+    pub fn dhcp_seen(
+        &mut self,
+        ip: Ipv4Addr,
+        mac: &[u8; 6],
+        hostname: Option<&[u8]>,
+        parameter_request_list: &[u8],
+        vendor_class: Option<&[u8]>,
+    ) {
+        let session = self.session(ip);
+        session.mac = Some(
+            mac.iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(":"),
+        );
+        session.hostname = hostname.map(|h| truncated(&String::from_utf8_lossy(h)));
+        session.dhcp_fingerprint = Some(
+            parameter_request_list
+                .iter()
+                .map(|o| o.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+        session.vendor_class = vendor_class.map(|v| truncated(&String::from_utf8_lossy(v)));
+        session.device = device_type(session);
+        self.store(false);
+    }
+
+    /// Called by the http server for each request. Returns the guessed device type.
+    pub fn http_seen(&mut self, ip: Ipv4Addr, user_agent: Option<&str>) -> DeviceType {
+        let session = self.session(ip);
+        let device = session.device;
+        // Compared truncated, like it is stored. Longer user agents would never match otherwise.
+        match user_agent.map(truncated) {
+            Some(user_agent) if session.user_agent.as_ref() != Some(&user_agent) => {
+                session.user_agent = Some(user_agent);
+                session.device = device_type(session);
+                let device = session.device;
+                self.store(false);
+                device
+            },
+            _ => device,
+        }
+    }
+
+    /// Called by the http server if the client presented the pairing token
+    pub fn pair(&mut self, ip: Ipv4Addr) {
+        self.session(ip).paired = true;
+        self.store(false);
+    }
+
+    pub fn is_paired(&self, ip: Ipv4Addr) -> bool {
+        self.sessions.get(&ip).map(|s| s.paired).unwrap_or(false)
+    }
+
+    /// Remembers the language picked by the client
+    pub fn set_language(&mut self, ip: Ipv4Addr, lang: &'static str) {
+        self.session(ip).lang = Some(lang);
+        self.store(false);
+    }
+
+    /// The language picked by the client, if any
+    pub fn language(&self, ip: Ipv4Addr) -> Option<&'static str> {
+        self.sessions.get(&ip).and_then(|s| s.lang)
+    }
+
+    /// Identifies the client across portal restarts: Its hardware address if the dhcp server saw it,
+    /// its IP address otherwise. See [`crate::attempts`].
+    pub fn client_id(&self, ip: Ipv4Addr) -> String {
+        self.sessions
+            .get(&ip)
+            .and_then(|s| s.mac.clone())
+            .unwrap_or_else(|| ip.to_string())
+    }
+
+    /// The session of a client that has been seen before
+    pub fn get_mut(&mut self, ip: Ipv4Addr) -> Option<&mut Session> {
+        self.sessions.get_mut(&ip)
+    }
+
+    /// Returns all sessions, sorted by IP address
+    pub fn list(&self) -> Vec<Session> {
+        let mut sessions: Vec<Session> = self.sessions.values().cloned().collect();
+        sessions.sort_by_key(|s| s.ip);
+        sessions
+    }
+}
+
+impl Session {
+    /// Identifies clients with the same network stack: The dhcp parameter request list or,
+    /// without dhcp, the device type.
+    pub fn fingerprint(&self) -> String {
+        match self.dhcp_fingerprint.as_ref() {
+            Some(fingerprint) => fingerprint.clone(),
+            None => format!("{:?}", self.device).to_lowercase(),
+        }
+    }
+}
+
+/// The user agent is more specific than the dhcp fingerprint and takes precedence.
+fn device_type(session: &Session) -> DeviceType {
+    let from_user_agent = session
+        .user_agent
+        .as_ref()
+        .map(|u| device_from_user_agent(u))
+        .unwrap_or(DeviceType::Unknown);
+    if from_user_agent != DeviceType::Unknown {
+        return from_user_agent;
+    }
+    match session.dhcp_fingerprint.as_ref() {
+        Some(fingerprint) => device_from_dhcp(fingerprint, session.vendor_class.as_ref().map(|v| &v[..])),
+        None => DeviceType::Unknown,
+    }
+}
+
+/// Guesses the device type from a browser or captive portal detection user agent.
+pub fn device_from_user_agent(user_agent: &str) -> DeviceType {
+    let contains = |needles: &[&str]| needles.iter().any(|n| user_agent.contains(n));
+    if contains(&["iPhone", "iPad", "iPod"]) {
+        DeviceType::Ios
+    } else if contains(&["Macintosh", "Mac OS X"]) {
+        DeviceType::Macos
+    } else if contains(&["CaptiveNetworkSupport"]) {
+        DeviceType::Apple
+    } else if contains(&["Android", "Dalvik"]) {
+        DeviceType::Android
+    } else if contains(&["CrOS"]) {
+        DeviceType::Chromeos
+    } else if contains(&["Windows", "Microsoft NCSI"]) {
+        DeviceType::Windows
+    } else if contains(&["Linux", "X11"]) {
+        DeviceType::Linux
+    } else {
+        DeviceType::Unknown
+    }
+}
+
+/// Guesses the device type from the dhcp parameter request list (comma separated) and vendor class.
+pub fn device_from_dhcp(fingerprint: &str, vendor_class: Option<&str>) -> DeviceType {
+    match vendor_class {
+        Some(v) if v.starts_with("android-dhcp") => return DeviceType::Android,
+        Some(v) if v.starts_with("MSFT") => return DeviceType::Windows,
+        _ => {},
+    };
+    if fingerprint.starts_with("1,121,3,6,15,119,252") {
+        DeviceType::Apple
+    } else if fingerprint.starts_with("1,3,6,15,31,33,43,44,46,47,119,121,249,252") {
+        DeviceType::Windows
+    } else if fingerprint.starts_with("1,3,6,15,26,28,51,58,59") {
+        DeviceType::Android
+    } else if fingerprint.starts_with("1,28,2,3,15,6,119,12") {
+        // ISC dhclient
+        DeviceType::Linux
+    } else {
+        DeviceType::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_agents() {
+        let ios = "Mozilla/5.0 (iPhone; CPU iPhone OS 13_3 like Mac OS X) AppleWebKit/605.1.15 Mobile/15E148";
+        assert_eq!(device_from_user_agent(ios), DeviceType::Ios);
+        let android = "Mozilla/5.0 (Linux; Android 10; Pixel 3) AppleWebKit/537.36 Chrome/79.0.3945.93 Mobile";
+        assert_eq!(device_from_user_agent(android), DeviceType::Android);
+        assert_eq!(device_from_user_agent("CaptiveNetworkSupport-390.60.1 wispr"), DeviceType::Apple);
+        assert_eq!(device_from_user_agent("Microsoft NCSI"), DeviceType::Windows);
+        assert_eq!(device_from_user_agent("curl/7.67.0"), DeviceType::Unknown);
+    }
+
+    #[test]
+    fn user_agent_overrides_dhcp() {
+        let mut sessions = Sessions::default();
+        let ip = Ipv4Addr::new(192, 168, 42, 10);
+        sessions.dhcp_seen(ip, &[0, 1, 2, 3, 4, 5], Some(b"phone"), &[1, 121, 3, 6, 15, 119, 252], None);
+        assert_eq!(sessions.list()[0].device, DeviceType::Apple);
+        assert_eq!(sessions.list()[0].mac.as_ref().unwrap(), "00:01:02:03:04:05");
+        let device = sessions.http_seen(ip, Some("Mozilla/5.0 (iPad; CPU OS 13_3 like Mac OS X)"));
+        assert_eq!(device, DeviceType::Ios);
+        assert_eq!(sessions.http_seen(ip, None), DeviceType::Ios);
+        assert_eq!(device_from_dhcp("1,3,6", Some("android-dhcp-10")), DeviceType::Android);
+
+        let long = format!("Mozilla/5.0 (iPhone; CPU iPhone OS 13_3 like Mac OS X) {}", "x".repeat(300));
+        assert_eq!(sessions.http_seen(ip, Some(&long)), DeviceType::Ios);
+        assert_eq!(sessions.http_seen(ip, Some(&long)), DeviceType::Ios);
+        assert_eq!(sessions.list()[0].user_agent.as_ref().unwrap().chars().count(), MAX_VALUE_LEN);
+    }
+
+    #[test]
+    fn language() {
+        let mut sessions = Sessions::default();
+        let ip = Ipv4Addr::new(192, 168, 42, 10);
+        assert_eq!(sessions.language(ip), None);
+        sessions.set_language(ip, "he");
+        assert_eq!(sessions.language(ip), Some("he"));
+        assert_eq!(sessions.language(Ipv4Addr::new(192, 168, 42, 11)), None);
+    }
+
+    #[test]
+    fn client_id() {
+        let mut sessions = Sessions::default();
+        let ip = Ipv4Addr::new(192, 168, 42, 10);
+        assert_eq!(sessions.client_id(ip), "192.168.42.10");
+        sessions.dhcp_seen(ip, &[0xaa, 0xbb, 0xcc, 0, 0x11, 0x22], None, &[1, 3, 6], None);
+        assert_eq!(sessions.client_id(ip), "AA:BB:CC:00:11:22");
+    }
+
+    #[test]
+    fn evict_least_recently_seen() {
+        let mut sessions = Sessions::default();
+        for i in 0..MAX_SESSIONS {
+            let ip = Ipv4Addr::from(u32::from(Ipv4Addr::new(10, 0, 0, 0)) + i as u32);
+            sessions.http_seen(ip, None);
+            sessions.get_mut(ip).unwrap().last_seen = 1000 + i as u64;
+        }
+        let ip = Ipv4Addr::new(192, 168, 42, 10);
+        sessions.http_seen(ip, None);
+        assert_eq!(sessions.list().len(), MAX_SESSIONS);
+        assert!(sessions.get_mut(ip).is_some());
+        assert!(sessions.get_mut(Ipv4Addr::new(10, 0, 0, 0)).is_none());
+        assert!(sessions.get_mut(Ipv4Addr::new(10, 0, 0, 1)).is_some());
+    }
+
+    #[test]
+    fn persisted_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(SESSIONS_FILE);
+        let mut sessions = Sessions::with_file(path.clone(), Duration::from_secs(0)).unwrap();
+        let ip = Ipv4Addr::new(192, 168, 42, 10);
+        sessions.dhcp_seen(ip, &[0xaa, 0xbb, 0xcc, 0, 0x11, 0x22], None, &[1, 3, 6], None);
+        sessions.pair(ip);
+        sessions.set_language(ip, "he");
+
+        let restored = Sessions::with_file(path, Duration::from_secs(0)).unwrap();
+        assert!(restored.is_paired(ip));
+        assert_eq!(restored.language(ip), Some("he"));
+        assert_eq!(restored.client_id(ip), "AA:BB:CC:00:11:22");
+    }
+}
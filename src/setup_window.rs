@@ -0,0 +1,159 @@
+//! # Setup window
+//! Restricts when the setup hotspot may be raised: During configured hours of the day (local time),
+//! like "08:00-20:00", or within some minutes after a physical presence proof.
+//!
+//! The presence proof is the modification time of a file, which a button handler touches on a press
+//! (for example a gpio-keys or triggerhappy rule running `touch /run/wifi-captive/presence`).
+//! If hours and a presence file are configured, either of them allows the hotspot.
+//! Without both the hotspot is always allowed. Otherwise the state machine does not raise the hotspot and
+//! tries known connections again until the hotspot is allowed.
+
+use nix::libc;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A time of day range in local time. The end is exclusive. Ranges like "22:00-06:00" span midnight.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TimeWindow {
+    /// Minutes since midnight
+    start: u16,
+    end: u16,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, minute_of_day: u16) -> bool {
+        match self.start <= self.end {
+            true => minute_of_day >= self.start && minute_of_day < self.end,
+            false => minute_of_day >= self.start || minute_of_day < self.end,
+        }
+    }
+}
+
+fn parse_time(value: &str) -> Option<u16> {
+    let mut parts = value.trim().splitn(2, ':');
+    let hours: u16 = parts.next()?.parse().ok()?;
+    let minutes: u16 = parts.next()?.parse().ok()?;
+    match hours <= 24 && minutes < 60 && hours * 60 + minutes <= 24 * 60 {
+        true => Some(hours * 60 + minutes),
+        false => None,
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.splitn(2, '-');
+        match (parts.next().and_then(parse_time), parts.next().and_then(parse_time)) {
+            (Some(start), Some(end)) => Ok(TimeWindow { start, end }),
+            _ => Err(format!("Expected a time range like 08:00-20:00, got {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+/// When the setup hotspot may be raised
+#[derive(Clone, Debug, Default)]
+pub struct SetupWindow {
+    pub hours: Vec<TimeWindow>,
+    /// Touched on a physical button press
+    pub presence_file: Option<PathBuf>,
+    /// How long a presence proof allows the hotspot
+    pub presence_validity: Duration,
+}
+
+impl SetupWindow {
+    /// Returns Ok if the hotspot may be raised now, otherwise the reason why not
+    pub fn check(&self) -> Result<(), String> {
+        let presence_age = self
+            .presence_file
+            .as_ref()
+            .and_then(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .and_then(|modified| modified.elapsed().ok());
+        self.decide(local_minute_of_day(), presence_age)
+    }
+
+    fn decide(&self, minute_of_day: Option<u16>, presence_age: Option<Duration>) -> Result<(), String> {
+        if self.hours.is_empty() && self.presence_file.is_none() {
+            return Ok(());
+        }
+        if let Some(minute) = minute_of_day {
+            if self.hours.iter().any(|w| w.contains(minute)) {
+                return Ok(());
+            }
+        }
+        if presence_age.map(|age| age <= self.presence_validity).unwrap_or(false) {
+            return Ok(());
+        }
+
+        let mut reasons = Vec::new();
+        if !self.hours.is_empty() {
+            let hours: Vec<String> = self.hours.iter().map(|w| w.to_string()).collect();
+            reasons.push(format!("outside of the setup hours {}", hours.join(", ")));
+        }
+        if self.presence_file.is_some() {
+            reasons.push(format!(
+                "no button press within the last {} minutes",
+                self.presence_validity.as_secs() / 60
+            ));
+        }
+        Err(reasons.join(" and "))
+    }
+}
+
+/// Minutes since midnight in local time. None if the local time is not available.
+fn local_minute_of_day() -> Option<u16> {
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return None;
+        }
+        Some((tm.tm_hour * 60 + tm.tm_min) as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_windows() {
+        let day: TimeWindow = "08:00-20:00".parse().unwrap();
+        assert!(day.contains(8 * 60));
+        assert!(!day.contains(20 * 60));
+        let night: TimeWindow = "22:30-6:00".parse().unwrap();
+        assert!(night.contains(23 * 60) && night.contains(60) && !night.contains(12 * 60));
+        assert_eq!(night.to_string(), "22:30-06:00");
+        assert!("8-20".parse::<TimeWindow>().is_err());
+        assert!("08:00-25:00".parse::<TimeWindow>().is_err());
+    }
+
+    #[test]
+    fn decide() {
+        let window = SetupWindow {
+            hours: vec!["08:00-20:00".parse().unwrap()],
+            presence_file: Some(PathBuf::from("/run/wifi-captive/presence")),
+            presence_validity: Duration::from_secs(600),
+        };
+        assert!(window.decide(Some(9 * 60), None).is_ok());
+        assert!(window.decide(Some(21 * 60), Some(Duration::from_secs(60))).is_ok());
+        let reason = window.decide(Some(21 * 60), Some(Duration::from_secs(3600))).unwrap_err();
+        assert!(reason.contains("08:00-20:00") && reason.contains("10 minutes"));
+        assert!(SetupWindow::default().decide(None, None).is_ok());
+    }
+}
@@ -0,0 +1,78 @@
+//! # Portal smoke test
+//! A hotspot can be active while clients still cannot use it: The gateway address is missing on the interface,
+//! or the dns or dhcp server failed to bind. Clients would connect and then see neither an address nor the portal.
+//!
+//! After the portal services are started, the state machine checks them from the device itself ([`run`]):
+//! A socket is bound to the gateway address, the own dns server is queried and a dhcp offer is requested
+//! by a test client. A service that does not answer is restarted. If the gateway address is missing,
+//! the hotspot is restarted.
+
+use crate::config::Config;
+use std::fmt;
+use std::net::UdpSocket;
+#[cfg(any(feature = "dns", feature = "dhcp"))]
+use std::net::SocketAddrV4;
+#[cfg(any(feature = "dns", feature = "dhcp"))]
+use std::time::Duration;
+
+/// The time a service has to answer the smoke test
+#[cfg(any(feature = "dns", feature = "dhcp"))]
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A part of the portal that is checked by the smoke test
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Subsystem {
+    /// The gateway address on the hotspot interface
+    Address,
+    Dns,
+    Dhcp,
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Subsystem::Address => write!(f, "gateway address"),
+            Subsystem::Dns => write!(f, "dns server"),
+            Subsystem::Dhcp => write!(f, "dhcp server"),
+        }
+    }
+}
+
+/// Checks the portal services. Returns the failed ones, empty if the portal is serving.
+/// If the gateway address cannot be bound, the services are not checked.
+pub async fn run(config: &Config) -> Vec<Subsystem> {
+    if let Err(e) = UdpSocket::bind((config.gateway, 0)) {
+        warn!("Smoke test: Cannot bind to the gateway address {}: {}", config.gateway, e);
+        return vec![Subsystem::Address];
+    }
+    #[allow(unused_mut)]
+    let mut failed = Vec::new();
+    #[cfg(feature = "dns")]
+    {
+        let probe = crate::dns_server::probe(SocketAddrV4::new(config.gateway, config.dns_port));
+        if let Err(e) = with_timeout(probe).await {
+            warn!("Smoke test: The dns server does not answer: {}", e);
+            failed.push(Subsystem::Dns);
+        }
+    }
+    #[cfg(feature = "dhcp")]
+    {
+        let probe = crate::dhcp_server::probe(SocketAddrV4::new(config.gateway, config.dhcp_port));
+        if let Err(e) = with_timeout(probe).await {
+            warn!("Smoke test: The dhcp server does not answer: {}", e);
+            failed.push(Subsystem::Dhcp);
+        }
+    }
+    failed
+}
+
+#[cfg(any(feature = "dns", feature = "dhcp"))]
+async fn with_timeout<F>(probe: F) -> Result<(), String>
+where
+    F: std::future::Future<Output = Result<(), crate::CaptivePortalError>>,
+{
+    match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+        Ok(r) => r.map_err(|e| e.to_string()),
+        Err(_) => Err("Timeout".to_owned()),
+    }
+}
@@ -0,0 +1,193 @@
+//! # Runtime state directory
+//!
+//! All files that are generated at runtime (like the dhcp leases) are written to one configurable
+//! directory. This allows read-only root filesystems with a small writable overlay.
+
+use super::CaptivePortalError;
+use crate::runtime;
+
+use nix::unistd::{access, AccessFlags};
+use serde::Serialize;
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// File name of the persisted dhcp leases within the state directory
+pub const DHCP_LEASES_FILE: &str = "dhcp_leases.json";
+
+/// Creates the state directory with mode 0700 if it does not exist yet.
+/// Fails if the path is not a directory, is world writable or is not writable for this process.
+pub fn prepare_state_dir(path: &Path) -> Result<(), CaptivePortalError> {
+    if !path.exists() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(path)
+            .map_err(|e| CaptivePortalError::IO(e, "Failed to create the state directory"))?;
+        info!("Created state directory {}", path.display());
+    }
+
+    let metadata =
+        std::fs::metadata(path).map_err(|e| CaptivePortalError::IO(e, "Failed to access the state directory"))?;
+    if !metadata.is_dir() {
+        return Err(CaptivePortalError::Generic(format!(
+            "The state directory {} is not a directory",
+            path.display()
+        )));
+    }
+    if metadata.permissions().mode() & 0o002 != 0 {
+        return Err(CaptivePortalError::Generic(format!(
+            "The state directory {} must not be world writable",
+            path.display()
+        )));
+    }
+    access(path, AccessFlags::W_OK | AccessFlags::X_OK).map_err(|_| {
+        CaptivePortalError::Generic(format!("The state directory {} is not writable", path.display()))
+    })?;
+    Ok(())
+}
+
+/// Writes the data to a temporary file next to the given path, syncs it and renames it to the
+/// destination. Readers either see the old or the new content, never a partially written file.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<(), CaptivePortalError> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = std::fs::File::create(&tmp_path)
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to create temporary state file"))?;
+    file.write_all(data)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| CaptivePortalError::IO(e, "Failed to write temporary state file"))?;
+    std::fs::rename(&tmp_path, path).map_err(|e| CaptivePortalError::IO(e, "Failed to replace state file"))?;
+    Ok(())
+}
+
+struct Pending {
+    path: PathBuf,
+    flush_interval: Duration,
+    last_write: Option<Instant>,
+    /// The state that has not been written yet
+    data: Option<Vec<u8>>,
+    /// A flush task waits for the flush interval to pass
+    scheduled: bool,
+}
+
+impl Pending {
+    /// Writes the pending state, if any. Errors are logged only.
+    fn write(&mut self) {
+        if let Some(data) = self.data.take() {
+            if let Err(e) = write_atomic(&self.path, &data) {
+                warn!("{}: {}", self.path.display(), e);
+            }
+            self.last_write = Some(Instant::now());
+        }
+    }
+}
+
+/// A state file with debounced writes. A change is written right away if the last write is older than
+/// the flush interval. Otherwise a flush task writes the latest state once the interval has passed.
+/// The owner should call [`StateFile::flush`] on shutdown.
+///
+/// This reduces the wear of SD-card based devices.
+pub struct StateFile(Arc<Mutex<Pending>>);
+
+impl StateFile {
+    pub fn new(path: PathBuf, flush_interval: Duration) -> Self {
+        StateFile(Arc::new(Mutex::new(Pending {
+            path,
+            flush_interval,
+            last_write: None,
+            data: None,
+            scheduled: false,
+        })))
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.0.lock().expect("state file mutex lock").path.clone()
+    }
+
+    /// Sets the new state. It is written now or by the flush task, see [`StateFile`].
+    pub fn changed(&mut self, data: Vec<u8>) {
+        let mut pending = self.0.lock().expect("state file mutex lock");
+        pending.data = Some(data);
+        let now = Instant::now();
+        match pending.last_write.map(|last_write| last_write + pending.flush_interval) {
+            Some(due) if due > now => {
+                if pending.scheduled {
+                    return;
+                }
+                pending.scheduled = true;
+                let file = self.0.clone();
+                runtime::spawn(async move {
+                    runtime::delay_for(due - now).await;
+                    let mut pending = file.lock().expect("state file mutex lock");
+                    pending.scheduled = false;
+                    pending.write();
+                });
+            },
+            _ => pending.write(),
+        }
+    }
+
+    /// Serializes the state and passes it to [`StateFile::changed`]. With `force`, pending changes are
+    /// written right away instead. Errors are logged only.
+    pub fn store<T: Serialize + ?Sized>(&mut self, state: &T, force: bool) {
+        if force {
+            return self.flush();
+        }
+        match serde_json::to_vec(state) {
+            Ok(data) => self.changed(data),
+            Err(e) => warn!("{}: {}", self.path().display(), e),
+        }
+    }
+
+    /// Returns true if there are changes that have not been written yet
+    pub fn is_dirty(&self) -> bool {
+        self.0.lock().expect("state file mutex lock").data.is_some()
+    }
+
+    /// Writes pending changes right away. Errors are logged only.
+    pub fn flush(&mut self) {
+        self.0.lock().expect("state file mutex lock").write();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StateFile;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn debounced_state_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let read = || std::fs::read(&path).unwrap();
+        let mut file = StateFile::new(path.clone(), Duration::from_millis(100));
+
+        // The first change is written immediately
+        file.changed(b"first".to_vec());
+        assert!(!file.is_dirty());
+        assert_eq!(read(), b"first");
+
+        // Further changes within the flush interval are deferred. The flush task writes the latest one.
+        file.changed(b"second".to_vec());
+        file.changed(b"third".to_vec());
+        assert!(file.is_dirty());
+        assert_eq!(read(), b"first");
+        tokio::time::delay_for(Duration::from_millis(300)).await;
+        assert!(!file.is_dirty());
+        assert_eq!(read(), b"third");
+
+        file.changed(b"fourth".to_vec());
+        file.changed(b"fifth".to_vec());
+        file.flush();
+        assert_eq!(read(), b"fifth");
+        assert!(!path.with_extension("tmp").exists());
+
+        // Serialized state, written by a forced store
+        file.store(&[1, 2], false);
+        assert!(file.is_dirty());
+        file.store(&[3], true);
+        assert_eq!(read(), b"[1,2]");
+    }
+}
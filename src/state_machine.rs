@@ -1,29 +1,58 @@
 //! # The programs state machine. Each state carries its required data, no side-effects.
 
+use crate::arp_guard;
+use crate::attempts::{ConnectAttemptsSync, FailureStage};
+use crate::channels;
+use crate::commands::{Command, CommandQueue};
 use crate::config::Config;
-use crate::http_server::WifiConnectionRequest;
-use crate::network_backend::NetworkBackend;
-use crate::network_interface::credentials_from_data;
-use crate::utils::ctrl_c_or_future;
-use crate::{CaptivePortalError, verify_password, ctrl_c_with_exit_handler};
+use crate::dpp::DppUri;
+use crate::http_server::{WifiConnectionRequest, DPP_MODE, WPS_MODE};
+use crate::mac_filter::MacFilterSync;
+use crate::network_backend::{NetworkBackend, WifiBackend};
+use crate::network_interface::{
+    credentials_from_data, ipv4_from_data, ipv6_from_data, ActiveConnection, ConnectOptions, ConnectionQuality,
+    HotspotBand, HotspotRadio, SSID,
+};
+use crate::quality;
+use crate::regulatory;
+use crate::rogue_ap;
+use crate::smoke_test::{self, Subsystem};
+use crate::stats;
+use crate::subnet;
+use crate::status::{Phase, Progress, StatusSender};
+use crate::trace::{self, AttemptTrace};
+use crate::watchdog::Watchdog;
+use crate::utils::{ctrl_c_or_future, interface_ipv4, interface_ipv6};
+use crate::{CaptivePortalError, verify_password};
 use crate::ConnectionState;
+use futures_util::future::{select, Either, FutureExt};
+use futures_util::stream::StreamExt;
+use pin_utils::pin_mut;
 use crate::NetworkManagerState;
 use log::info;
 use std::convert::TryInto;
 use std::time::Duration;
-use tokio::time::timeout;
+use tokio::time::{delay_for, timeout};
 
 /// The programs state machine. Each state carries its required data, no side-effects.
 /// The configuration and network manager connection are moved between states.
 ///
 /// All states transition into StartUp if the dbus connection to the network manager got lost.
-pub enum StateMachine {
+///
+/// Generic over the network backend, the compiled-in [`NetworkBackend`] by default.
+pub enum StateMachine<B: WifiBackend = NetworkBackend> {
     /// Starts a dbus connection to the system bus.
     /// Connects to network manager, starts the service if necessary.
     ///
+    /// Within the boot grace period after system boot, the service only waits for network manager
+    /// to reconnect on its own. The hotspot is not started during that time.
+    ///
+    /// In safe mode (see [`crate::boot_loop`]) only the portal is offered.
+    ///
     /// # Transitions:
     /// **Connected** -> If network manager reports active connections and a "connected" state.
     /// **TryReconnect** -> If no connection is active
+    /// **ActivatePortal** -> In safe mode
     ///
     /// # Errors:
     /// Error out if network manager cannot be reached.
@@ -34,58 +63,170 @@ pub enum StateMachine {
     /// # Transitions:
     /// **Connected** -> If network manager transitioned into a connected state.
     /// **ActivatePortal** -> If no connection can be established
-    /// **Exit** ->  On ctrl+c
+    /// **Exit** ->  On ctrl+c or SIGTERM
     ///
     /// # Errors:
     /// Fails if network manager permissions do not allow to issue wifi scans or connect to
     /// access points. Error out if network manager cannot be reached.
-    TryReconnect(Config, NetworkBackend),
+    TryReconnect(Config, B),
 
     /// The device is connected, as reported by network manager
     ///
     /// # Events:
-    /// Listens to network manager for connection state changes
+    /// Listens to network manager for connection state changes.
+    /// Executes queued commands, see [`crate::commands`].
     ///
     /// # Transitions:
-    /// **TryReconnect** -> On connection lost
-    /// **Exit** ->  On ctrl+c
-    Connected(Config, NetworkBackend),
+    /// **TryReconnect** -> On connection lost. **ActivatePortal** in safe mode.
+    /// **Connect** -> On a queued connect command
+    /// **Connected** -> On a queued restart hotspot command. There is no hotspot to restart.
+    /// **StartUp** -> If network manager stopped answering, see [`crate::watchdog`]
+    /// **Exit** ->  On ctrl+c or SIGTERM
+    Connected(Config, B),
 
     /// Activates a wifi hotspot and portal page.
     /// Starts up an http server, a dns server and a dhcp server.
     ///
     /// **IF** network manager reported connections:
-    /// Starts a timer to periodically (5 min) check if a connection to an already configured wifi
+    /// Starts a timer to periodically (`--retry-in`) check if a connection to an already configured wifi
     /// can be re-established. The portal must be disabled for a few seconds to perform the wifi scan.
     ///
+    /// Executes queued commands while the portal is online, see [`crate::commands`].
+    ///
+    /// The hotspot is not started outside of the configured setup window, see [`crate::setup_window`].
+    ///
+    /// In the concurrent access point and station mode, connect commands are executed on a station
+    /// interface while the portal stays online. A failed attempt does not close the portal. The saved networks
+    /// are retried there as well, instead of closing the portal on timeout.
+    ///
     /// # Transitions:
-    /// **Connect** -> When the user requests to connect to a wifi access point via the http server.
-    /// **Connected** -> When a connection could be established
-    /// **Exit** ->  On ctrl+c
-    ActivatePortal(Config, NetworkBackend),
+    /// **Connect** -> On a queued connect command, for example when the user requests to connect
+    /// to a wifi access point via the http server.
+    /// **ActivatePortal** -> On a queued restart hotspot command
+    /// **Connected** -> When a connection could be established, also outside of the portal
+    /// (reported by the NetworkManager dispatcher, see [`crate::dispatcher`]) or on the station interface
+    /// **TryReconnect** -> On timeout or if the hotspot cannot be started. Not in safe mode,
+    /// the portal is restarted instead. After 30 seconds if the setup window does not allow the hotspot.
+    /// **StartUp** -> If network manager stopped answering, see [`crate::watchdog`]
+    /// **Exit** ->  On ctrl+c or SIGTERM
+    ActivatePortal(Config, B),
 
-    /// Tries to connect to the given access point.
+    /// Tries to connect to the given access point. The attempt can be cancelled or replaced
+    /// by another connect request, see [`crate::attempts`].
+    /// Whether the request came from a client paired via the device QR code is published in the status.
+    ///
+    /// The established connection is measured against the quality gate, unless the request accepts
+    /// a weak connection. See [`crate::quality`].
     ///
     /// # Transitions:
     /// **Connected** First stores the ssid+passphrase+identity in Config then transition in the connected state.
-    /// **ActivatePortal** If the connection fails after a few attempts
-    Connect(Config, NetworkBackend, WifiConnectionRequest),
+    /// **Connect** If the attempt got replaced by another request
+    /// **ActivatePortal** If the connection fails after a few attempts, the attempt got cancelled
+    /// or the connection failed the quality gate. The connection is removed again in the last case.
+    Connect(Config, B, WifiConnectionRequest),
 
     /// Quits the program
     ///
     /// Shuts down the network manager connection.
-    Exit(NetworkBackend),
+    Exit(B),
 }
 
-impl StateMachine {
-    pub async fn progress(self) -> Result<Option<StateMachine>, CaptivePortalError> {
+impl<B: WifiBackend + Clone + 'static> StateMachine<B> {
+    /// The phase that is published on the status bus while in this state
+    fn phase(&self) -> (Phase, Option<String>) {
+        match self {
+            StateMachine::StartUp(_) => (Phase::StartUp, None),
+            StateMachine::TryReconnect(_, _) => (Phase::TryReconnect, None),
+            StateMachine::Connected(_, _) => (Phase::Connected, None),
+            StateMachine::ActivatePortal(_, _) => (Phase::Portal, None),
+            StateMachine::Connect(_, _, request) => (Phase::Connecting, Some(request.ssid.to_string())),
+            StateMachine::Exit(_) => (Phase::Exit, None),
+        }
+    }
+
+    /// Performs the current state and returns the next one. The current phase is published via `status`.
+    /// Connect requests of the portal are tracked in `attempts`. Commands of the control surfaces
+    /// are taken from `commands`.
+    pub async fn progress(
+        self,
+        status: &StatusSender,
+        attempts: &ConnectAttemptsSync,
+        commands: &mut CommandQueue,
+    ) -> Result<Option<StateMachine<B>>, CaptivePortalError> {
+        let (phase, ssid) = self.phase();
+        status.set(phase, ssid);
+        stats::log_summary();
         match self {
             StateMachine::StartUp(config) => {
-                let nm = NetworkBackend::new(&config.interface).await?;
+                if config.interface.is_some() && config.interface == config.station_interface {
+                    return Err(CaptivePortalError::Generic(
+                        "The station interface must be another wifi adapter than the portal interface".to_owned(),
+                    ));
+                }
+                let mut nm = match B::new(&config.interface).await {
+                    Err(CaptivePortalError::ApModeUnsupported(interface_name)) if config.adhoc_fallback => {
+                        warn!("{} does not support access point mode. Using an ad-hoc network", interface_name);
+                        B::new_adhoc(&config.interface).await?
+                    },
+                    nm => nm?,
+                };
+                if let Some(station_interface) = config.station_interface.as_ref() {
+                    nm.set_station_adapter(station_interface).await?;
+                }
+                stats::set_link_interface(nm.interface_name());
+                nm.set_in_memory(config.in_memory);
+                nm.set_owe_transition(config.hotspot_owe)?;
+                nm.set_pmf(config.pmf);
+                nm.set_max_clients(config.max_clients);
+                nm.set_mac_filter(config.mac_filter.clone());
+                nm.set_hotspot_security(config.hotspot_security);
                 nm.enable_networking_and_wifi().await?;
 
-                let state = nm.state().await?;
+                let mut state = nm.state().await?;
                 info!("Starting up. Network manager reports state {:?}", state);
+                log_device_info(&nm).await;
+                status.set_degraded(false);
+
+                if config.safe_mode {
+                    info!("Safe mode: Starting the portal without touching connections");
+                    return Ok(Some(StateMachine::ActivatePortal(config, nm)));
+                }
+
+                let grace = std::fs::read_to_string("/proc/uptime")
+                    .map(|uptime| boot_grace_remaining(&uptime, Duration::from_secs(config.boot_grace_period)))
+                    .unwrap_or_default();
+                let connected =
+                    state == NetworkManagerState::Connected || state == NetworkManagerState::ConnectedLimited;
+                if !connected && grace > Duration::from_secs(0) {
+                    info!("Boot grace period: Waiting up to {:?} for an automatic reconnect", grace);
+                    match ctrl_c_or_future(nm.wait_for_connectivity(false, grace)).await? {
+                        // Ctrl+C
+                        None => return Ok(Some(StateMachine::Exit(nm))),
+                        Some(Ok(s)) => state = s,
+                        Some(Err(CaptivePortalError::NotRequiredConnectivity(s))) => {
+                            info!("No reconnect within the boot grace period");
+                            state = s;
+                        },
+                        Some(Err(e)) => return Err(e),
+                    }
+                }
+
+                let disconnected = state == NetworkManagerState::Unknown
+                    || state == NetworkManagerState::Asleep
+                    || state == NetworkManagerState::Disconnected;
+                if let Some(uri) = config.dpp_uri.as_ref().filter(|_| disconnected) {
+                    info!("Trying Easy Connect with {}", uri.info.as_deref().unwrap_or("the configured URI"));
+                    let request = WifiConnectionRequest::dpp(uri.to_string());
+                    return Ok(Some(StateMachine::Connect(config, nm, request)));
+                }
+                if disconnected && config.wps {
+                    if let Some(request) = wps_request(&nm).await {
+                        info!("Trying WPS push-button with {}", request.ssid);
+                        return Ok(Some(StateMachine::Connect(config, nm, request)));
+                    }
+                    info!("No access point offers WPS push-button");
+                }
+
                 Ok(match state {
                     NetworkManagerState::Unknown | NetworkManagerState::Asleep | NetworkManagerState::Disconnected => {
                         Some(StateMachine::ActivatePortal(config, nm))
@@ -114,7 +255,14 @@ impl StateMachine {
                         }
                     }
                 }
-                return Ok(Some(StateMachine::ActivatePortal(config, nm)));
+
+                // Try the saved networks in range by priority before falling back to the portal
+                match ctrl_c_or_future(nm.connect_saved(SAVED_NETWORK_TIMEOUT)).await? {
+                    // Ctrl+C
+                    None => Ok(Some(StateMachine::Exit(nm))),
+                    Some(true) => Ok(Some(StateMachine::Connected(config, nm))),
+                    Some(false) => Ok(Some(StateMachine::ActivatePortal(config, nm))),
+                }
             }
             StateMachine::Connected(config, nm) => {
                 nm.deactivate_hotspots().await?;
@@ -127,27 +275,74 @@ impl StateMachine {
                 match c_state {
                     Ok(_) => {}
                     Err(CaptivePortalError::NotRequiredConnectivity(_)) => {
-                        return Ok(Some(StateMachine::TryReconnect(config, nm)));
+                        return Ok(Some(reconnect_or_portal(config, nm)));
                     }
                     Err(e) => return Err(e),
                 }
 
+                let ssid = match nm.active_ssid().await {
+                    Ok(ssid) => ssid.map(|s| s.to_string()),
+                    Err(e) => {
+                        warn!("Failed to read the SSID of the connection: {}", e);
+                        None
+                    },
+                };
+                status.set(Phase::Connected, ssid);
+                let interface_name = station_interface_name(&nm);
+                status.set_ip(interface_ipv4(&interface_name));
+                status.set_ipv6(interface_ipv6(&interface_name));
+
                 if config.quit_after_connected {
                     return Ok(Some(StateMachine::Exit(nm)));
                 }
 
-                // Await a connectivity change, ctrl+c or the timeout
-                let r = ctrl_c_or_future(nm
-                    .wait_for_connectivity_lost(config.internet_connectivity, Duration::from_secs(config.retry_in))
-                ).await?;
+                // Await a connectivity change, a connect command, ctrl+c or the timeout
+                let r = {
+                    let lost = nm
+                        .wait_for_connectivity_lost(config.internet_connectivity, Duration::from_secs(config.retry_in));
+                    let next = execute_until_transition(commands, &nm, status, &config.mac_filter, config.watchdog());
+                    pin_mut!(lost);
+                    pin_mut!(next);
+                    ctrl_c_or_future(async {
+                        match select(lost, next).await {
+                            Either::Left((r, _)) => r.map(|_| None),
+                            Either::Right((transition, _)) => Ok(Some(transition)),
+                        }
+                    })
+                    .await?
+                };
 
                 match r {
                     // Ctrl+C
                     None => Ok(Some(StateMachine::Exit(nm))),
-                    Some(_) => Ok(Some(StateMachine::TryReconnect(config, nm))),
+                    Some(Some(Transition::Connect(request))) => Ok(Some(StateMachine::Connect(config, nm, request))),
+                    Some(Some(Transition::RestartHotspot)) => {
+                        info!("No hotspot to restart");
+                        Ok(Some(StateMachine::Connected(config, nm)))
+                    },
+                    Some(Some(Transition::RestartWifi)) => {
+                        restart_wifi(&nm).await;
+                        Ok(Some(reconnect_or_portal(config, nm)))
+                    },
+                    // The connection may have been replaced, for example with nmcli
+                    Some(Some(Transition::ExternalConnection(_))) | Some(Some(Transition::Connected)) => {
+                        Ok(Some(StateMachine::Connected(config, nm)))
+                    },
+                    Some(Some(Transition::BackendUnresponsive)) => Ok(Some(restart_backend(config, nm).await)),
+                    Some(None) => Ok(Some(reconnect_or_portal(config, nm))),
                 }
             }
             StateMachine::ActivatePortal(mut config, nm) => {
+                if let Err(reason) = config.setup_window().check() {
+                    info!("The setup hotspot is not allowed: {}", reason);
+                    status.set_error(Some("setup_not_allowed"));
+                    return match ctrl_c_or_future(delay_for(SETUP_WINDOW_RECHECK).map(Ok)).await? {
+                        // Ctrl+C
+                        None => Ok(Some(StateMachine::Exit(nm))),
+                        Some(()) => Ok(Some(reconnect_or_portal(config, nm))),
+                    };
+                }
+
                 nm.enable_networking_and_wifi().await?;
                 nm.deactivate_hotspots().await?;
 
@@ -156,74 +351,193 @@ impl StateMachine {
                 info!("Acquire wifi access point list. This may take a minute ...");
                 let wifi_access_points = nm.list_access_points(Duration::from_secs(7)).await?;
 
+                let twin_aps = rogue_ap::twins(&wifi_access_points, &config.ssid);
+                let mut hotspot_ssid = config.ssid.clone();
+                if !twin_aps.is_empty() {
+                    warn!("Other access points broadcast the portal SSID {}: {}", config.ssid, twin_aps.join(", "));
+                    if config.randomize_ssid {
+                        hotspot_ssid = rogue_ap::randomized_ssid(&config.ssid);
+                        info!("The hotspot uses the SSID {} instead", hotspot_ssid);
+                    }
+                }
+                status.set_twin_aps(twin_aps);
+
+                // Validated on start up
+                let mut radio = config.hotspot_radio().unwrap_or_default();
+                if !config.no_auto_channel && radio.map_or(true, |r| r.channel.is_none()) {
+                    let band = radio.map_or(HotspotBand::Bg, |r| r.band);
+                    let channel = channels::least_congested(&wifi_access_points, band);
+                    info!("Least congested hotspot channel: {}", channel);
+                    radio = Some(HotspotRadio { band, channel: Some(channel) });
+                }
+                if let Some(requested) = radio.filter(|r| r.channel.is_some()) {
+                    match regulatory::query() {
+                        Ok(domain) => {
+                            let corrected = regulatory::correct_radio(&domain, requested, &wifi_access_points);
+                            if corrected != requested {
+                                warn!(
+                                    "Channel {:?} is not permitted in the regulatory domain {}. Using channel {:?}",
+                                    requested.channel, domain.country, corrected.channel
+                                );
+                                radio = Some(corrected);
+                            }
+                        }
+                        Err(e) => debug!("Hotspot channel not validated: {}", e),
+                    }
+                }
+                if !config.fixed_gateway && nm.assigns_hotspot_address() {
+                    let upstream = subnet::upstream_subnets(nm.interface_name());
+                    match subnet::gateway_avoiding(config.gateway, &upstream) {
+                        Some(gateway) if gateway != config.gateway => {
+                            warn!(
+                                "The hotspot subnet of {} clashes with an upstream subnet. Using the gateway {}",
+                                config.gateway, gateway
+                            );
+                            config.gateway = gateway;
+                        },
+                        Some(_) => {},
+                        None => warn!("All hotspot subnets clash with upstream subnets: {:?}", upstream),
+                    }
+                }
                 let r = timeout(Duration::from_secs(25),nm
-                    .hotspot_start(config.ssid.clone(), config.passphrase.clone(), Some(config.gateway)))
+                    .hotspot_start(hotspot_ssid.into(), config.passphrase.clone(), Some(config.gateway), radio))
                     .await;
 
                 let active_connection = match r {
                     Ok(Ok(r)) => r.active_connection_path,
                     Err(_) => {
                         warn!("Failed to create hotspot: Timeout. Trying to establish a connection instead.");
-                        return Ok(Some(StateMachine::TryReconnect(config, nm)));
+                        return Ok(Some(reconnect_or_portal(config, nm)));
                     }
                     Ok(Err(e)) => {
                         warn!(
                             "Failed to create hotspot: {}. Trying to establish a connection instead.",
                             e
                         );
-                        return Ok(Some(StateMachine::TryReconnect(config, nm)));
+                        return Ok(Some(reconnect_or_portal(config, nm)));
                     }
                 };
 
+                // Connection attempts keep the portal online on a dedicated station adapter or if a station
+                // interface can be added
+                let station = (config.concurrent_ap_sta || config.station_interface.is_some())
+                    && add_station_interface(&nm).await;
+                // Saved networks are retried beside the portal then, otherwise the portal closes for the retry
+                let retry_in = Duration::from_secs(config.retry_in);
+
                 info!("Activating portal services");
-                use super::portal::Portal;
-                let (portal, exit_handler) = Portal::new(
-                    &nm,
-                    &config,
-                    active_connection,
-                    wifi_access_points,
-                    Duration::from_secs(config.retry_in),
-                )?;
-
-                let r = ctrl_c_with_exit_handler(portal,exit_handler).await?;
+                use super::portal::{Portal, PortalLinks};
+                let r = {
+                    let links = PortalLinks {
+                        status: status.subscribe(),
+                        events: status.events(),
+                        attempts: attempts.clone(),
+                        commands: commands.sender(),
+                    };
+                    let timeout = Some(retry_in).filter(|_| !station);
+                    let (mut portal, exit_handler) =
+                        Portal::new(&nm, &config, active_connection, wifi_access_points, timeout, links)?;
+                    let serving = smoke_test_portal(&config, nm.interface_name(), &mut portal).await;
+                    pin_mut!(portal);
+
+                    // The portal closed itself (timeout, hotspot stopped) if it does not need to be closed
+                    let mut closed = false;
+                    let r = if !serving {
+                        Some(Some(Transition::RestartHotspot))
+                    } else {
+                        loop {
+                            let watchdog = config.watchdog();
+                            let next = execute_until_transition(commands, &nm, status, &config.mac_filter, watchdog);
+                            // Watches the hotspot clients as long as the portal is open
+                            let arp_guard = async {
+                                arp_guard::watch(nm.interface_name(), config.gateway, status).await;
+                                futures_util::future::pending::<Transition>().await
+                            };
+                            let reconnect = async {
+                                match station {
+                                    true => reconnect_beside_portal(&nm, retry_in).await,
+                                    false => futures_util::future::pending::<Transition>().await,
+                                }
+                            };
+                            let next = async {
+                                pin_mut!(next);
+                                pin_mut!(arp_guard);
+                                pin_mut!(reconnect);
+                                match select(select(next, arp_guard), reconnect).await {
+                                    Either::Left((transition, _)) => transition.factor_first().0,
+                                    Either::Right((transition, _)) => transition,
+                                }
+                            };
+                            pin_mut!(next);
+                            let r = ctrl_c_or_future(async {
+                                match select(portal.as_mut(), next).await {
+                                    Either::Left((r, _)) => r.map(|_| None),
+                                    Either::Right((transition, _)) => Ok(Some(transition)),
+                                }
+                            })
+                            .await?;
+                            let request = match r {
+                                Some(Some(Transition::Connect(request))) if station => request,
+                                r => break r,
+                            };
+                            let beside = connect_beside_portal(&config, &nm, status, attempts, request, portal.as_mut());
+                            let (outcome, portal_closed) = match ctrl_c_or_future(beside).await? {
+                                // Ctrl+C
+                                None => break None,
+                                Some(r) => r?,
+                            };
+                            closed = portal_closed;
+                            match outcome {
+                                Attempt::Connected => break Some(Some(Transition::Connected)),
+                                Attempt::Replaced(replacement) => break Some(Some(Transition::Connect(replacement))),
+                                Attempt::Failed if closed => break Some(None),
+                                Attempt::Failed => status.set(Phase::Portal, None),
+                            }
+                        }
+                    };
+                    if let Some(None) = r {
+                        closed = true;
+                    }
+                    if !closed {
+                        let _ = exit_handler.send(());
+                        portal.await?;
+                    }
+                    r
+                };
                 info!("Portal closed");
+                // A dedicated station adapter keeps its connection
+                if station && config.station_interface.is_none() {
+                    match r {
+                        Some(Some(Transition::Connected)) => station_handover(&nm).await,
+                        _ => remove_station_interface(&nm).await,
+                    }
+                }
                 match r {
                     // Ctrl+C
                     None => Ok(Some(StateMachine::Exit(nm))),
-                    // Either the user has entered a wifi connection or a timeout happened
-                    Some(wifi_connection) => {
-                        match wifi_connection {
-                            // The user has entered a wifi connection
-                            Some(wifi_connection) => Ok(Some(StateMachine::Connect(config, nm, wifi_connection))),
-                            // Timeout
-                            None => Ok(Some(StateMachine::TryReconnect(config, nm))),
-                        }
-                    }
+                    // A connect command, for example the user has entered a wifi connection
+                    Some(Some(Transition::Connect(request))) => Ok(Some(StateMachine::Connect(config, nm, request))),
+                    Some(Some(Transition::RestartHotspot)) => Ok(Some(StateMachine::ActivatePortal(config, nm))),
+                    Some(Some(Transition::RestartWifi)) => {
+                        restart_wifi(&nm).await;
+                        Ok(Some(StateMachine::ActivatePortal(config, nm)))
+                    },
+                    Some(Some(Transition::ExternalConnection(connection))) => {
+                        info!("Connection {} has been established outside of the portal", connection);
+                        Ok(Some(StateMachine::Connected(config, nm)))
+                    },
+                    Some(Some(Transition::Connected)) => Ok(Some(StateMachine::Connected(config, nm))),
+                    Some(Some(Transition::BackendUnresponsive)) => Ok(Some(restart_backend(config, nm).await)),
+                    // Timeout
+                    Some(None) => Ok(Some(reconnect_or_portal(config, nm))),
                 }
             }
             StateMachine::Connect(config, nm, network) => {
-                info!("Connecting ...");
-
-                let connection = nm
-                    .connect_to(
-                        network.ssid,
-                        credentials_from_data(
-                            network.passphrase.unwrap_or_default(),
-                            network.identity,
-                            network.mode.try_into()?,
-                        )?,
-                        network.hw,
-                        true,
-                    )
-                    .await?;
-                if let Some(connection) = connection {
-                    match connection.state {
-                        ConnectionState::Activated => Ok(Some(StateMachine::Connected(config, nm))),
-                        _ => Ok(Some(StateMachine::ActivatePortal(config, nm))),
-                    }
-                } else {
-                    Ok(Some(StateMachine::ActivatePortal(config, nm)))
-                }
+                Ok(Some(match attempt_connection(&config, &nm, status, attempts, network).await? {
+                    Attempt::Connected => StateMachine::Connected(config, nm),
+                    Attempt::Replaced(replacement) => StateMachine::Connect(config, nm, replacement),
+                    Attempt::Failed => StateMachine::ActivatePortal(config, nm),
+                }))
             }
             StateMachine::Exit(nm) => {
                 info!("Exiting");
@@ -234,6 +548,588 @@ impl StateMachine {
     }
 }
 
+/// How often a refused setup hotspot is checked again, see [`crate::setup_window`]
+const SETUP_WINDOW_RECHECK: Duration = Duration::from_secs(30);
+
+/// How long each saved network may take to connect before the next one is tried, see [`WifiBackend::connect_saved`]
+const SAVED_NETWORK_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How long an established connection may take to reach the internet, see [`internet_reachable`]
+const INTERNET_PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long the connection may take to come up on the wifi interface after a connection attempt
+/// on the station interface
+const STATION_HANDOVER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A queued command that leaves the current state
+enum Transition {
+    Connect(WifiConnectionRequest),
+    RestartHotspot,
+    /// Switch wifi off and on again, see [`Command::RestartWifi`]
+    RestartWifi,
+    /// A connection went up on the wifi interface, see [`crate::dispatcher`]
+    ExternalConnection(String),
+    /// Network manager stopped answering, see [`crate::watchdog`]
+    BackendUnresponsive,
+    /// A connection attempt or a retry of the saved networks on the station interface succeeded
+    /// while the portal stayed online, see [`reconnect_beside_portal`]
+    Connected,
+}
+
+/// Executes queued commands until a command is taken from the queue that leaves the current state,
+/// or until the watchdog reports an unresponsive network manager.
+async fn execute_until_transition<B: WifiBackend>(
+    commands: &mut CommandQueue,
+    nm: &B,
+    status: &StatusSender,
+    mac_filter: &MacFilterSync,
+    watchdog: Watchdog,
+) -> Transition {
+    let commands = execute_commands(commands, nm, status, mac_filter);
+    let unresponsive = watch_backend(nm, status, &watchdog);
+    pin_mut!(commands);
+    pin_mut!(unresponsive);
+    match select(commands, unresponsive).await {
+        Either::Left((transition, _)) => transition,
+        Either::Right((transition, _)) => transition,
+    }
+}
+
+/// Pings network manager every watchdog interval. Resolves if it stopped answering.
+/// Backends without a ping are not watched.
+async fn watch_backend<B: WifiBackend>(nm: &B, status: &StatusSender, watchdog: &Watchdog) -> Transition {
+    let interval = match watchdog.interval {
+        Some(interval) => interval,
+        None => return futures_util::future::pending().await,
+    };
+    use crate::watchdog::{Health, Verdict, PING_TIMEOUT};
+    let mut health = Health::default();
+    loop {
+        delay_for(interval).await;
+        let answered = match nm.ping(PING_TIMEOUT).await {
+            Ok(()) => true,
+            Err(CaptivePortalError::Unsupported(_)) => return futures_util::future::pending().await,
+            Err(e) => {
+                warn!("Watchdog: {}", e);
+                false
+            },
+        };
+        match health.record(answered) {
+            Verdict::Healthy => status.set_degraded(false),
+            Verdict::Degraded => status.set_degraded(true),
+            Verdict::Unresponsive => return Transition::BackendUnresponsive,
+        }
+    }
+}
+
+/// Network manager stopped answering: Restarts it if configured and starts over with a new D-Bus connection
+async fn restart_backend<B: WifiBackend>(config: Config, nm: B) -> StateMachine<B> {
+    warn!("Network manager does not answer. Re-establishing the D-Bus connection");
+    let watchdog = config.watchdog();
+    if watchdog.restart_service {
+        match nm.restart_service(watchdog.restart_timeout).await {
+            Ok(true) => info!("Network manager has been restarted"),
+            Ok(false) => warn!("Network manager did not come back within {:?}", watchdog.restart_timeout),
+            Err(e) => warn!("Failed to restart network manager: {}", e),
+        }
+    }
+    nm.quit();
+    StateMachine::StartUp(config)
+}
+
+/// Switches wifi off and on again. A failure is logged, the state machine carries on.
+async fn restart_wifi<B: WifiBackend>(nm: &B) {
+    if let Err(e) = nm.restart_wifi().await {
+        warn!("Failed to restart wifi: {}", e);
+    }
+}
+
+/// Logs driver, firmware and capabilities of the wifi device, for bug reports
+async fn log_device_info<B: WifiBackend>(nm: &B) {
+    match nm.device_info().await {
+        Ok(info) => info!(
+            "Wifi device {}: Driver {} {}, firmware {}, capabilities {}",
+            nm.interface_name(),
+            info.driver,
+            info.driver_version,
+            info.firmware_version,
+            info.capabilities.join(" ")
+        ),
+        Err(CaptivePortalError::Unsupported(_)) => {},
+        Err(e) => warn!("Failed to read the wifi device info: {}", e),
+    }
+}
+
+/// Checks the portal services after the hotspot is up and restarts the failed ones, see [`smoke_test`].
+/// Returns false if the portal still cannot serve clients and the hotspot should be restarted.
+/// If the dhcp server still fails, clients can reach the portal with link-local addresses and mDNS.
+async fn smoke_test_portal(config: &Config, interface_name: &str, portal: &mut crate::portal::Portal<'_>) -> bool {
+    let failed = smoke_test::run(config).await;
+    if failed.is_empty() {
+        info!("Portal serving OK");
+        return true;
+    }
+    if failed.contains(&Subsystem::Address) {
+        warn!("The hotspot has no gateway address. Restarting the hotspot");
+        return false;
+    }
+    for subsystem in failed {
+        warn!("Restarting the {}", subsystem);
+        portal.restart(config, subsystem).await;
+    }
+    let failed = smoke_test::run(config).await;
+    if failed.is_empty() {
+        info!("Portal serving OK");
+        return true;
+    }
+    // Restarting the hotspot does not help if a port is taken by another service
+    let names: Vec<String> = failed.iter().map(|s| s.to_string()).collect();
+    warn!("Portal serving without: {}", names.join(", "));
+    #[cfg(feature = "dhcp")]
+    {
+        if failed.contains(&Subsystem::Dhcp) {
+            match portal.link_local_fallback(config, interface_name).await {
+                Ok(url) => info!("Portal reachable for link-local clients at {}", url),
+                Err(e) => warn!("No link-local fallback: {}", e),
+            }
+        }
+    }
+    #[cfg(not(feature = "dhcp"))]
+    let _ = interface_name;
+    !failed.contains(&Subsystem::Address)
+}
+
+/// Executes queued commands until a command is taken from the queue that leaves the current state.
+async fn execute_commands<B: WifiBackend>(
+    commands: &mut CommandQueue,
+    nm: &B,
+    status: &StatusSender,
+    mac_filter: &MacFilterSync,
+) -> Transition {
+    loop {
+        let queued = commands.next().await;
+        match queued.command {
+            Command::Connect { request, .. } => return Transition::Connect(request),
+            Command::RestartHotspot => return Transition::RestartHotspot,
+            Command::RestartWifi => return Transition::RestartWifi,
+            Command::Reboot => match nm.reboot().await {
+                Ok(()) => info!("Command {}: Rebooting", queued.id),
+                Err(e) => warn!("Command {}: Failed to reboot: {}", queued.id, e),
+            },
+            Command::Forget(ssid) => match nm.forget(&ssid).await {
+                Ok(removed) => info!("Command {}: Forget {}: {}", queued.id, ssid, removed),
+                Err(e) => warn!("Command {}: Failed to forget {}: {}", queued.id, ssid, e),
+            },
+            Command::SetUpdateNotice(notice) => status.set_update(notice),
+            Command::SetMacFilter(filter) => {
+                info!(
+                    "Command {}: Hotspot mac filter: {} allowed, {} denied",
+                    queued.id,
+                    filter.allow.len(),
+                    filter.deny.len()
+                );
+                *mac_filter.write().expect("mac filter lock") = filter;
+            },
+            Command::NetworkEvent(event) => {
+                info!("Command {}: Network event {}", queued.id, event);
+                if event.is_connection_up(nm.interface_name()) {
+                    return Transition::ExternalConnection(event.connection.unwrap_or_default());
+                }
+            },
+        }
+    }
+}
+
+/// The outcome of a connection attempt
+enum Attempt {
+    Connected,
+    /// The attempt got replaced by another request
+    Replaced(WifiConnectionRequest),
+    /// The connection failed, failed the quality gate or the attempt got cancelled
+    Failed,
+}
+
+/// Tries to connect to the given access point, see [`StateMachine::Connect`]
+async fn attempt_connection<B: WifiBackend>(
+    config: &Config,
+    nm: &B,
+    status: &StatusSender,
+    attempts: &ConnectAttemptsSync,
+    network: WifiConnectionRequest,
+) -> Result<Attempt, CaptivePortalError> {
+    let started = {
+        let mut attempts = attempts.lock().expect("attempts mutex lock");
+        // The request might have been replaced before the portal closed
+        let network = attempts.take_replacement().unwrap_or(network);
+        let started = attempts.begin();
+        if started.is_none() {
+            attempts.finish();
+        }
+        started.map(|(attempt, cancelled)| (network, attempt, cancelled))
+    };
+    let (network, attempt, cancelled) = match started {
+        Some(started) => started,
+        None => {
+            info!("Connection attempt cancelled");
+            status.set_error(Some("cancelled"));
+            return Ok(Attempt::Failed);
+        },
+    };
+    info!("Connecting ... (attempt {})", attempt);
+    let attempt_trace = AttemptTrace::start(attempt, &network.ssid.to_string());
+    status.set_error(None);
+    status.set_quality(None);
+    status.set_paired(network.paired);
+    status.set_progress(Some(Progress::Scanning));
+
+    let request = network.clone();
+    let connection = {
+        let connect = connect_with_progress(nm, status, network);
+        pin_mut!(connect);
+        match select(connect, cancelled).await {
+            Either::Left((connection, _)) => Some(connection),
+            Either::Right(_) => None,
+        }
+    };
+
+    let replacement = {
+        let mut attempts = attempts.lock().expect("attempts mutex lock");
+        let replacement = attempts.take_replacement();
+        if replacement.is_none() {
+            attempts.finish();
+        }
+        replacement
+    };
+    if let Some(replacement) = replacement {
+        info!("Connection attempt {} replaced", attempt);
+        finish_attempt(config, status, attempts, attempt, attempt_trace, "replaced");
+        return Ok(Attempt::Replaced(replacement));
+    }
+
+    // Cancelled: Clean up and open the portal again
+    if connection.is_none() {
+        info!("Connection attempt {} cancelled", attempt);
+        if let Err(e) = nm.abort_connect().await {
+            warn!("Failed to abort the connection attempt: {}", e);
+        }
+        status.set_error(Some("cancelled"));
+        finish_attempt(config, status, attempts, attempt, attempt_trace, "cancelled");
+        return Ok(Attempt::Failed);
+    }
+
+    let connection = match connection.transpose() {
+        Ok(connection) => connection,
+        Err(e) => {
+            finish_attempt(config, status, attempts, attempt, attempt_trace, "connection_failed");
+            return Err(e);
+        },
+    };
+    if let Some(Some(connection)) = connection {
+        if connection.state == ConnectionState::Activated {
+            if config.internet_connectivity && !internet_reachable(nm).await {
+                info!("Connected to {}, but the internet is not reachable", request.ssid);
+                status.set_error(Some("no_internet"));
+                finish_attempt(config, status, attempts, attempt, attempt_trace, "no_internet");
+                return Ok(Attempt::Failed);
+            }
+            let gate = config.quality_gate();
+            if gate.is_disabled() {
+                finish_attempt(config, status, attempts, attempt, attempt_trace, "connected");
+                return Ok(Attempt::Connected);
+            }
+            trace::begin(trace::Phase::ConnectivityCheck);
+            let quality = measure_quality(nm).await;
+            let failed = gate.check(&quality);
+            info!("Connection quality {:?}", quality);
+            status.set_quality(Some(quality));
+            if failed.is_empty() || request.accept_weak {
+                trace::end(trace::Phase::ConnectivityCheck);
+                finish_attempt(config, status, attempts, attempt, attempt_trace, "connected");
+                return Ok(Attempt::Connected);
+            }
+            trace::fail(trace::Phase::ConnectivityCheck);
+            info!("Connection to {} is too weak: {}", request.ssid, failed.join(", "));
+            if let Err(e) = nm.forget(&request.ssid).await {
+                warn!("Failed to remove the weak connection: {}", e);
+            }
+            status.set_error(Some("weak_connection"));
+            attempts.lock().expect("attempts mutex lock").set_weak(request);
+            finish_attempt(config, status, attempts, attempt, attempt_trace, "weak_connection");
+            return Ok(Attempt::Failed);
+        }
+    }
+    let reason = nm.last_failure().unwrap_or("connection_failed");
+    status.set_error(Some(reason));
+    finish_attempt(config, status, attempts, attempt, attempt_trace, reason);
+    Ok(Attempt::Failed)
+}
+
+/// Connects to the requested network and publishes the phases that the network backend reports meanwhile
+async fn connect_with_progress<B: WifiBackend>(
+    nm: &B,
+    status: &StatusSender,
+    network: WifiConnectionRequest,
+) -> Result<Option<ActiveConnection>, CaptivePortalError> {
+    let mut phases = trace::subscribe();
+    let connect = connect(nm, network);
+    pin_mut!(connect);
+    loop {
+        match select(connect.as_mut(), phases.next()).await {
+            Either::Left((connection, _)) => return connection,
+            Either::Right((Some(phase), _)) => {
+                if let Some(progress) = Progress::of(phase) {
+                    status.set_progress(Some(progress));
+                }
+            },
+            Either::Right((None, _)) => return connect.await,
+        }
+    }
+}
+
+/// Connects to the requested network. The "wps" mode enrolls via WPS push-button, the "dpp" mode via
+/// Easy Connect instead of a passphrase.
+async fn connect<B: WifiBackend>(
+    nm: &B,
+    network: WifiConnectionRequest,
+) -> Result<Option<ActiveConnection>, CaptivePortalError> {
+    let ipv4 = ipv4_from_data(
+        network.ipv4_address,
+        network.ipv4_prefix,
+        network.ipv4_gateway,
+        network.ipv4_dns,
+    )?;
+    let ipv6 = ipv6_from_data(
+        network.ipv6_mode,
+        network.ipv6_address,
+        network.ipv6_prefix,
+        network.ipv6_gateway,
+        network.ipv6_dns,
+    )?;
+    if network.mode == DPP_MODE {
+        let uri: DppUri = network.dpp_uri.as_deref().unwrap_or_default().parse()?;
+        return nm.connect_dpp(&uri, ipv4, ipv6).await;
+    }
+    let (ssid, priority) = (network.ssid.clone(), network.priority);
+    let connection = if network.mode == WPS_MODE {
+        nm.connect_wps(network.ssid, network.hw, ipv4, ipv6).await?
+    } else {
+        let credentials = credentials_from_data(
+            network.passphrase.unwrap_or_default(),
+            network.identity,
+            network.anonymous_identity,
+            network.eap,
+            network.mode.try_into()?,
+        )?;
+        let options = ConnectOptions { ipv4, ipv6, hidden: network.hidden, overwrite_same_ssid_connection: true };
+        nm.connect_to(network.ssid, credentials, network.hw, options).await?
+    };
+    if let Some(priority) = priority.filter(|_| connection.is_some()) {
+        set_priority(nm, &ssid, priority).await;
+    }
+    Ok(connection)
+}
+
+async fn set_priority<B: WifiBackend>(nm: &B, ssid: &SSID, priority: i32) {
+    match nm.set_priority(ssid, priority).await {
+        Ok(true) => info!("The saved network {} has the priority {}", ssid, priority),
+        Ok(false) => warn!("No saved connection for {}", ssid),
+        Err(e) => warn!("Failed to set the priority of {}: {}", ssid, e),
+    }
+}
+
+/// A WPS connect request for the strongest access point that advertises WPS push-button, see `--wps`
+async fn wps_request<B: WifiBackend>(nm: &B) -> Option<WifiConnectionRequest> {
+    match nm.wps_access_points().await {
+        Ok(access_points) => access_points
+            .into_iter()
+            .next()
+            .map(|ap| WifiConnectionRequest::wps(ap.ssid, Some(ap.hw))),
+        Err(e) => {
+            warn!("Failed to list the WPS access points: {}", e);
+            None
+        },
+    }
+}
+
+/// Runs connection attempts on the station interface while the portal stays online. Replacing requests are
+/// attempted as well. Returns the outcome of the last attempt and whether the portal closed itself meanwhile.
+async fn connect_beside_portal<B: WifiBackend>(
+    config: &Config,
+    nm: &B,
+    status: &StatusSender,
+    attempts: &ConnectAttemptsSync,
+    mut request: WifiConnectionRequest,
+    mut portal: std::pin::Pin<&mut crate::portal::Portal<'_>>,
+) -> Result<(Attempt, bool), CaptivePortalError> {
+    let mut closed = false;
+    loop {
+        status.set(Phase::Connecting, Some(request.ssid.to_string()));
+        let connect = attempt_connection(config, nm, status, attempts, request);
+        pin_mut!(connect);
+        let outcome = match closed {
+            true => connect.await?,
+            false => match select(portal.as_mut(), connect).await {
+                Either::Left((r, connect)) => {
+                    if let Err(e) = r {
+                        warn!("The portal closed during the connection attempt: {}", e);
+                    }
+                    closed = true;
+                    connect.await?
+                },
+                Either::Right((outcome, _)) => outcome?,
+            },
+        };
+        match outcome {
+            Attempt::Replaced(replacement) if !closed => request = replacement,
+            outcome => return Ok((outcome, closed)),
+        }
+    }
+}
+
+/// Retries the saved networks in range on the station interface every interval while the portal stays online,
+/// so that the device recovers when its network comes back. Resolves if one of them connected.
+async fn reconnect_beside_portal<B: WifiBackend>(nm: &B, interval: Duration) -> Transition {
+    loop {
+        delay_for(interval).await;
+        info!("Retrying the saved networks beside the portal");
+        match nm.connect_saved(SAVED_NETWORK_TIMEOUT).await {
+            Ok(true) => return Transition::Connected,
+            Ok(false) => info!("No saved network connected. Retrying in {:?}", interval),
+            Err(e) => warn!("Failed to retry the saved networks: {}", e),
+        }
+    }
+}
+
+/// Adds the station interface of the concurrent access point and station mode.
+/// Returns false if connection attempts have to close the portal.
+async fn add_station_interface<B: WifiBackend>(nm: &B) -> bool {
+    match nm.station_interface_add().await {
+        Ok(Some(interface_name)) => {
+            info!("Connection attempts use the station interface {}", interface_name);
+            true
+        },
+        Ok(None) => false,
+        Err(e) => {
+            warn!("Failed to add the station interface: {}", e);
+            false
+        },
+    }
+}
+
+/// The interface of the wifi connection: The station interface or adapter, if there is one
+fn station_interface_name<B: WifiBackend>(nm: &B) -> String {
+    nm.station_interface().unwrap_or_else(|| nm.interface_name().to_owned())
+}
+
+/// Removes the station interface after the portal closed without a connection
+async fn remove_station_interface<B: WifiBackend>(nm: &B) {
+    if let Err(e) = nm.station_interface_remove().await {
+        warn!("Failed to remove the station interface: {}", e);
+    }
+}
+
+/// Moves the established connection from the station interface to the wifi interface
+async fn station_handover<B: WifiBackend>(nm: &B) {
+    match nm.station_handover(STATION_HANDOVER_TIMEOUT).await {
+        Ok(true) => info!("Connection moved to {}", nm.interface_name()),
+        Ok(false) => warn!("The connection could not be moved to {}", nm.interface_name()),
+        Err(e) => warn!("Failed to move the connection to {}: {}", nm.interface_name(), e),
+    }
+}
+
+/// Ends the trace of the attempt, publishes its final progress
+/// and records the outcome in the attempt history of the client
+fn finish_attempt(
+    config: &Config,
+    status: &StatusSender,
+    attempts: &ConnectAttemptsSync,
+    attempt: u64,
+    attempt_trace: AttemptTrace,
+    outcome: &'static str,
+) {
+    let error = Some(outcome).filter(|o| *o != "connected");
+    let stage = FailureStage::of(outcome, trace::failed_phase());
+    if let Some(stage) = stage {
+        info!("Connection attempt {} failed during {}: {}", attempt, stage.name(), outcome);
+    }
+    attempts.lock().expect("attempts mutex lock").record_outcome(attempt, error, stage);
+    match outcome {
+        "connected" => status.set_progress(Some(Progress::Online)),
+        // The replacing attempt publishes its own progress
+        "replaced" => {},
+        _ => status.set_progress(Some(Progress::Failed)),
+    }
+    finish_trace(config, attempt_trace, outcome);
+}
+
+/// The connectivity probe of `--internet-connectivity` after a connection has been established.
+/// The network backend reports whether the internet is reachable, network manager with its connectivity check.
+async fn internet_reachable<B: WifiBackend>(nm: &B) -> bool {
+    trace::begin(trace::Phase::ConnectivityCheck);
+    match nm.wait_for_connectivity(true, INTERNET_PROBE_TIMEOUT).await {
+        Ok(_) => {
+            trace::end(trace::Phase::ConnectivityCheck);
+            true
+        },
+        Err(e) => {
+            debug!("Connectivity probe failed: {}", e);
+            trace::fail(trace::Phase::ConnectivityCheck);
+            false
+        },
+    }
+}
+
+/// Ends the trace of a connect attempt and exports it, if an OTLP endpoint is configured
+fn finish_trace(config: &Config, attempt_trace: AttemptTrace, outcome: &'static str) {
+    let finished = attempt_trace.finish(outcome);
+    debug!("Connect attempt trace: {:?}", finished);
+    #[cfg(feature = "otlp")]
+    {
+        if let Some(endpoint) = config.otlp_endpoint.clone() {
+            crate::runtime::spawn_tokio(trace::export(endpoint, finished));
+        }
+    }
+    #[cfg(not(feature = "otlp"))]
+    {
+        if config.otlp_endpoint.is_some() {
+            warn!("Exporting connect traces requires the otlp feature");
+        }
+    }
+}
+
+/// Measures the quality of the established connection. Values the backend cannot report are left out.
+async fn measure_quality<B: WifiBackend>(nm: &B) -> ConnectionQuality {
+    let mut quality = match nm.connection_quality().await {
+        Ok(quality) => quality,
+        Err(e) => {
+            warn!("Failed to read the connection quality: {}", e);
+            ConnectionQuality::default()
+        },
+    };
+    quality.gateway_rtt = quality::gateway_rtt().await;
+    quality
+}
+
+/// The state to fall back to if there is neither a connection nor a portal: Known connections are
+/// tried again, except in safe mode. The portal is restarted then.
+fn reconnect_or_portal<B: WifiBackend>(config: Config, nm: B) -> StateMachine<B> {
+    match config.safe_mode {
+        true => StateMachine::ActivatePortal(config, nm),
+        false => StateMachine::TryReconnect(config, nm),
+    }
+}
+
+/// The rest of the boot grace period. `uptime` is the content of "/proc/uptime", the first value are the
+/// seconds since boot.
+fn boot_grace_remaining(uptime: &str, grace: Duration) -> Duration {
+    uptime
+        .split_whitespace()
+        .next()
+        .and_then(|seconds| seconds.parse::<f64>().ok())
+        .and_then(|seconds| grace.checked_sub(Duration::from_secs_f64(seconds)))
+        .unwrap_or_default()
+}
+
 /// Updates SSID/Password via passphrase file, if option is set in config.
 ///
 /// This is not async, because current async file io implementations use the sync API with a background thread.
@@ -274,6 +1170,15 @@ fn update_portal_info_via_file(config: &mut Config) {
 mod tests {
     use std::io::Write;
     use std::io::BufWriter;
+    use std::time::Duration;
+
+    #[test]
+    fn boot_grace_remaining() {
+        let grace = Duration::from_secs(120);
+        assert_eq!(super::boot_grace_remaining("30.50 55.01\n", grace), Duration::from_millis(89500));
+        assert_eq!(super::boot_grace_remaining("3600.00 7000.00\n", grace), Duration::from_secs(0));
+        assert_eq!(super::boot_grace_remaining("", grace), Duration::from_secs(0));
+    }
 
     #[test]
     fn update_portal_info_via_file() {
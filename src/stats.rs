@@ -0,0 +1,212 @@
+//! # Memory budget instrumentation
+//! The portal runs for months on small devices. Slow leaks, like a dbus reply callback that is never
+//! removed or a hotspot session that is never cleaned up, show up as long-lived collections that only grow.
+//!
+//! The owners of these collections report the current size with [`record`]. A warning is logged when
+//! a collection exceeds its budget, and again if it exceeds it after having been within the budget.
+//! Sizes and budgets are exported at "/api/stats" and logged with every state machine transition.
+//!
+//! The state and counters of the wifi interface are read from the kernel with every request, see [`link`].
+//! They are exported at "/api/link" and "/metrics".
+
+use crate::netlink::{self, LinkStats};
+use serde::Serialize;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Collection {
+    /// Method calls of the dbus connection that wait for a reply
+    DbusReplies,
+    /// Signal match rules of the dbus connection
+    DbusFilters,
+    /// Hotspot clients
+    Sessions,
+    /// The dhcp lease table
+    DhcpLeases,
+    /// Change log of the network list
+    NetworkHistory,
+    /// Server send event streams
+    SseClients,
+}
+
+const COLLECTIONS: [Collection; 6] = [
+    Collection::DbusReplies,
+    Collection::DbusFilters,
+    Collection::Sessions,
+    Collection::DhcpLeases,
+    Collection::NetworkHistory,
+    Collection::SseClients,
+];
+
+static SIZES: [AtomicUsize; 6] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// Default budgets, see [`COLLECTIONS`] for the order
+static BUDGETS: [AtomicUsize; 6] = [
+    AtomicUsize::new(64),
+    AtomicUsize::new(64),
+    AtomicUsize::new(1024),
+    AtomicUsize::new(1024),
+    AtomicUsize::new(512),
+    AtomicUsize::new(32),
+];
+
+static EXCEEDED: [AtomicBool; 6] = [
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
+impl Collection {
+    pub fn name(self) -> &'static str {
+        match self {
+            Collection::DbusReplies => "dbus_replies",
+            Collection::DbusFilters => "dbus_filters",
+            Collection::Sessions => "sessions",
+            Collection::DhcpLeases => "dhcp_leases",
+            Collection::NetworkHistory => "network_history",
+            Collection::SseClients => "sse_clients",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl FromStr for Collection {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        COLLECTIONS
+            .iter()
+            .find(|c| c.name() == name)
+            .copied()
+            .ok_or_else(|| format!("Unknown collection {}", name))
+    }
+}
+
+/// A size limit for a collection. Parsed from "name=limit", for example "sessions=500".
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Budget {
+    pub collection: Collection,
+    pub limit: usize,
+}
+
+impl FromStr for Budget {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.splitn(2, '=');
+        let collection = parts.next().unwrap_or_default().trim().parse()?;
+        let limit = parts
+            .next()
+            .ok_or_else(|| format!("Expected name=limit, got {}", value))?
+            .trim()
+            .parse()
+            .map_err(|e| format!("{}: {}", value, e))?;
+        Ok(Budget { collection, limit })
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct CollectionStats {
+    pub name: &'static str,
+    pub size: usize,
+    pub budget: usize,
+}
+
+pub fn set_budget(budget: Budget) {
+    BUDGETS[budget.collection.index()].store(budget.limit, Ordering::Relaxed);
+}
+
+/// Reports the current size of a collection
+pub fn record(collection: Collection, size: usize) {
+    let i = collection.index();
+    SIZES[i].store(size, Ordering::Relaxed);
+    let budget = BUDGETS[i].load(Ordering::Relaxed);
+    if size <= budget {
+        EXCEEDED[i].store(false, Ordering::Relaxed);
+    } else if !EXCEEDED[i].swap(true, Ordering::Relaxed) {
+        warn!(
+            "Memory budget exceeded: {} has {} entries, the budget is {}",
+            collection.name(),
+            size,
+            budget
+        );
+    }
+}
+
+/// The last reported sizes and the budgets of all collections
+pub fn snapshot() -> Vec<CollectionStats> {
+    COLLECTIONS
+        .iter()
+        .map(|c| CollectionStats {
+            name: c.name(),
+            size: SIZES[c.index()].load(Ordering::Relaxed),
+            budget: BUDGETS[c.index()].load(Ordering::Relaxed),
+        })
+        .collect()
+}
+
+/// The index of the wifi interface. 0 until the network backend is started.
+static LINK_INDEX: AtomicU32 = AtomicU32::new(0);
+
+/// Sets the interface for [`link`]
+pub fn set_link_interface(interface_name: &str) {
+    match netlink::interface_index(interface_name) {
+        Ok(index) => LINK_INDEX.store(index, Ordering::Relaxed),
+        Err(e) => warn!("No link statistics for {}: {}", interface_name, e),
+    }
+}
+
+/// The state and counters of the wifi interface. None if the interface is not known (yet) or has vanished.
+pub fn link() -> Option<LinkStats> {
+    match LINK_INDEX.load(Ordering::Relaxed) {
+        0 => None,
+        index => netlink::link_stats(index).map_err(|e| debug!("{}", e)).ok(),
+    }
+}
+
+pub fn log_summary() {
+    let summary: Vec<String> = snapshot()
+        .iter()
+        .map(|c| format!("{}={}/{}", c.name, c.size, c.budget))
+        .collect();
+    debug!("Collection sizes: {}", summary.join(", "));
+    if let Some(link) = link() {
+        debug!(
+            "Link: up={} carrier={} rx={}B tx={}B errors={}/{}",
+            link.up, link.carrier, link.rx_bytes, link.tx_bytes, link.rx_errors, link.tx_errors
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_budget() {
+        assert_eq!(
+            "sessions=500".parse::<Budget>(),
+            Ok(Budget {
+                collection: Collection::Sessions,
+                limit: 500
+            })
+        );
+        assert!("sessions".parse::<Budget>().is_err());
+        assert!("unknown=5".parse::<Budget>().is_err());
+        assert!("dhcp_leases=-1".parse::<Budget>().is_err());
+    }
+}
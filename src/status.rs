@@ -0,0 +1,364 @@
+//! # Status event bus
+//!
+//! The state machine publishes its current phase. Consumers (the long-poll status endpoint,
+//! the server-send-events stream) subscribe to changes. Every change increments the revision,
+//! so that a client can ask for "anything newer than what I have seen".
+//! Every change is also recorded in the event history, see [`crate::events`].
+
+use crate::boot_loop::SafeMode;
+use crate::events::{EventLog, EventLogSync};
+use crate::network_interface::ConnectionQuality;
+use crate::trace::Phase as TracePhase;
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+#[derive(Serialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    StartUp,
+    TryReconnect,
+    Connected,
+    Portal,
+    Connecting,
+    Exit,
+}
+
+/// The stage of the current connection attempt, shown by the portal page while it waits for the outcome.
+/// The failure reason is the `error` of the status.
+#[derive(Serialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Progress {
+    /// Looking for the network and creating the connection profile
+    Scanning,
+    /// Association and authentication with the access point
+    Associating,
+    /// Ip configuration, usually DHCP
+    GettingIp,
+    Online,
+    Failed,
+}
+
+impl Progress {
+    /// The stage of a phase that the network backend reports, see [`crate::trace`].
+    /// None for phases that do not change the stage, like storing the profile.
+    pub fn of(phase: TracePhase) -> Option<Progress> {
+        match phase {
+            TracePhase::FindConnection | TracePhase::Activate => Some(Progress::Scanning),
+            TracePhase::Associate => Some(Progress::Associating),
+            TracePhase::Dhcp => Some(Progress::GettingIp),
+            TracePhase::Save | TracePhase::ConnectivityCheck => None,
+        }
+    }
+}
+
+/// The state of a firmware or OS update, published by the embedding firmware
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateState {
+    Available,
+    /// Provisioning is usually blocked until the update is installed
+    InProgress,
+}
+
+impl FromStr for UpdateState {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "available" => Ok(UpdateState::Available),
+            "in_progress" => Ok(UpdateState::InProgress),
+            _ => Err(format!("Expected available or in_progress, got {}", value)),
+        }
+    }
+}
+
+/// An update banner for the portal, see [`crate::commands::Command::SetUpdateNotice`]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UpdateNotice {
+    pub state: UpdateState,
+    /// An optional text of the firmware, for example the version or the expected duration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Status {
+    pub phase: Phase,
+    /// The SSID of the current connection attempt or of the established connection, if any
+    pub ssid: Option<String>,
+    /// The IPv4 address of the established connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<Ipv4Addr>,
+    /// The global IPv6 addresses of the established connection
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ipv6: Vec<Ipv6Addr>,
+    /// The message key of the reason why the last connection attempt failed, see [`crate::i18n`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+    /// The stage of the current or last connection attempt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<Progress>,
+    /// The measured quality of the last established connection, if the quality gate is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<ConnectionQuality>,
+    /// Set if the service runs in safe mode after repeated crashes, see [`crate::boot_loop`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub safe_mode: Option<SafeMode>,
+    /// An update banner of the embedding firmware
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update: Option<UpdateNotice>,
+    /// The current connection attempt or established connection has been requested by a client that
+    /// opened the deep link of the device QR code
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub paired: bool,
+    /// Network manager did not answer the last watchdog ping, see [`crate::watchdog`]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub degraded: bool,
+    /// The hardware address of a hotspot client that claims the gateway address, see [`crate::arp_guard`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arp_spoof: Option<String>,
+    /// The hardware addresses of foreign access points that broadcast the setup SSID, see [`crate::rogue_ap`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub twin_aps: Vec<String>,
+    /// Incremented with every change
+    pub revision: u64,
+}
+
+pub type StatusReceiver = watch::Receiver<Status>;
+
+/// The publishing side of the status bus. Owned by the state machine loop.
+pub struct StatusSender {
+    tx: watch::Sender<Status>,
+    rx: StatusReceiver,
+    events: EventLogSync,
+}
+
+impl StatusSender {
+    pub fn new() -> Self {
+        StatusSender::with_event_log(EventLog::default())
+    }
+
+    /// Records the initial status and every change in the given event history
+    pub fn with_event_log(mut events: EventLog) -> Self {
+        let (tx, rx) = watch::channel(Status {
+            phase: Phase::StartUp,
+            ssid: None,
+            ip: None,
+            ipv6: Vec::new(),
+            error: None,
+            progress: None,
+            quality: None,
+            safe_mode: None,
+            update: None,
+            paired: false,
+            degraded: false,
+            arp_spoof: None,
+            twin_aps: Vec::new(),
+            revision: 0,
+        });
+        events.record(&rx.borrow());
+        StatusSender {
+            tx,
+            rx,
+            events: Arc::new(Mutex::new(events)),
+        }
+    }
+
+    pub fn subscribe(&self) -> StatusReceiver {
+        self.rx.clone()
+    }
+
+    /// The event history, served at "/api/events"
+    pub fn events(&self) -> EventLogSync {
+        self.events.clone()
+    }
+
+    fn publish(&self, status: Status) {
+        self.events.lock().expect("event log mutex lock").record(&status);
+        let _ = self.tx.broadcast(status);
+    }
+
+    /// Publishes a new phase. Nothing is published if neither phase nor ssid changed.
+    /// The ip addresses belong to the previous connection and are cleared.
+    pub fn set(&self, phase: Phase, ssid: Option<String>) {
+        let current = self.rx.borrow().clone();
+        if current.phase == phase && current.ssid == ssid {
+            return;
+        }
+        self.publish(Status {
+            phase,
+            ssid,
+            ip: None,
+            ipv6: Vec::new(),
+            revision: current.revision + 1,
+            ..current
+        });
+    }
+
+    /// Publishes the ip address of the established connection, or clears it.
+    pub fn set_ip(&self, ip: Option<Ipv4Addr>) {
+        let current = self.rx.borrow().clone();
+        if current.ip == ip {
+            return;
+        }
+        self.publish(Status {
+            ip,
+            revision: current.revision + 1,
+            ..current
+        });
+    }
+
+    /// Publishes the IPv6 addresses of the established connection
+    pub fn set_ipv6(&self, ipv6: Vec<Ipv6Addr>) {
+        let current = self.rx.borrow().clone();
+        if current.ipv6 == ipv6 {
+            return;
+        }
+        self.publish(Status {
+            ipv6,
+            revision: current.revision + 1,
+            ..current
+        });
+    }
+
+    /// Publishes the failure reason of a connection attempt, or clears it.
+    pub fn set_error(&self, error: Option<&'static str>) {
+        let current = self.rx.borrow().clone();
+        if current.error == error {
+            return;
+        }
+        self.publish(Status {
+            error,
+            revision: current.revision + 1,
+            ..current
+        });
+    }
+
+    /// Publishes the stage of the current connection attempt
+    pub fn set_progress(&self, progress: Option<Progress>) {
+        let current = self.rx.borrow().clone();
+        if current.progress == progress {
+            return;
+        }
+        self.publish(Status {
+            progress,
+            revision: current.revision + 1,
+            ..current
+        });
+    }
+
+    /// Publishes that the service runs in safe mode
+    pub fn set_safe_mode(&self, safe_mode: Option<SafeMode>) {
+        let current = self.rx.borrow().clone();
+        if current.safe_mode == safe_mode {
+            return;
+        }
+        self.publish(Status {
+            safe_mode,
+            revision: current.revision + 1,
+            ..current
+        });
+    }
+
+    /// Publishes the update banner of the embedding firmware, or clears it.
+    pub fn set_update(&self, update: Option<UpdateNotice>) {
+        let current = self.rx.borrow().clone();
+        if current.update == update {
+            return;
+        }
+        self.publish(Status {
+            update,
+            revision: current.revision + 1,
+            ..current
+        });
+    }
+
+    /// Publishes whether the current connection has been requested by a paired client
+    pub fn set_paired(&self, paired: bool) {
+        let current = self.rx.borrow().clone();
+        if current.paired == paired {
+            return;
+        }
+        self.publish(Status {
+            paired,
+            revision: current.revision + 1,
+            ..current
+        });
+    }
+
+    /// Publishes the measured quality of an established connection, or clears it.
+    pub fn set_quality(&self, quality: Option<ConnectionQuality>) {
+        let current = self.rx.borrow().clone();
+        if current.quality == quality {
+            return;
+        }
+        self.publish(Status {
+            quality,
+            revision: current.revision + 1,
+            ..current
+        });
+    }
+
+    /// Publishes the hardware address of a client that spoofs the gateway address, or clears it.
+    pub fn set_arp_spoof(&self, arp_spoof: Option<String>) {
+        let current = self.rx.borrow().clone();
+        if current.arp_spoof == arp_spoof {
+            return;
+        }
+        self.publish(Status {
+            arp_spoof,
+            revision: current.revision + 1,
+            ..current
+        });
+    }
+
+    /// Publishes the foreign access points that broadcast the setup SSID
+    pub fn set_twin_aps(&self, twin_aps: Vec<String>) {
+        let current = self.rx.borrow().clone();
+        if current.twin_aps == twin_aps {
+            return;
+        }
+        self.publish(Status {
+            twin_aps,
+            revision: current.revision + 1,
+            ..current
+        });
+    }
+
+    /// Marks the network backend as degraded, see [`crate::watchdog`]
+    pub fn set_degraded(&self, degraded: bool) {
+        let current = self.rx.borrow().clone();
+        if current.degraded == degraded {
+            return;
+        }
+        self.publish(Status {
+            degraded,
+            revision: current.revision + 1,
+            ..current
+        });
+    }
+}
+
+impl Default for StatusSender {
+    fn default() -> Self {
+        StatusSender::new()
+    }
+}
+
+/// Returns the current status as soon as its revision differs from `since`,
+/// or after the timeout.
+pub async fn wait_for_change(rx: &mut StatusReceiver, since: u64, timeout: Duration) -> Status {
+    let changed = async {
+        while let Some(status) = rx.recv().await {
+            if status.revision != since {
+                return;
+            }
+        }
+    };
+    let _ = tokio::time::timeout(timeout, changed).await;
+    rx.borrow().clone()
+}
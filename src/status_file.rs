@@ -0,0 +1,88 @@
+//! # Status file
+//! Writes the status to a small json file whenever it changes, so that shell scripts and status bars
+//! on the device can show the network state without using the http or D-Bus APIs:
+//!
+//! ```json
+//! {"phase":"connected","ssid":"Home","ip":"192.168.1.23","error":null,"updated":1577836800}
+//! ```
+//!
+//! The file is replaced atomically, readers never see a partially written file.
+//! Put it on a tmpfs like "/run" on devices with flash storage, it is written on every change.
+
+use crate::state_dir::write_atomic;
+use crate::status::{Phase, Status, StatusReceiver};
+use crate::CaptivePortalError;
+use serde::Serialize;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize)]
+struct StatusFile<'a> {
+    phase: Phase,
+    ssid: Option<&'a str>,
+    ip: Option<Ipv4Addr>,
+    /// The connection has been requested by a client that opened the deep link of the device QR code.
+    /// The firmware can complete the pairing with a companion app then.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    paired: bool,
+    /// The message key of the reason why the last connection attempt failed, see [`crate::i18n`]
+    error: Option<&'static str>,
+    /// Unix timestamp in seconds of the last change
+    updated: u64,
+}
+
+/// Encodes the file content of the given status
+pub fn encode(status: &Status, updated: u64) -> Result<Vec<u8>, CaptivePortalError> {
+    let file = StatusFile {
+        phase: status.phase,
+        ssid: status.ssid.as_ref().map(|s| s.as_str()),
+        ip: status.ip,
+        paired: status.paired,
+        error: status.error,
+        updated,
+    };
+    Ok(serde_json::to_vec(&file)?)
+}
+
+/// Writes the current status and every change to the given path. Resolves when the status bus is closed.
+pub async fn run(path: PathBuf, mut status: StatusReceiver) {
+    while let Some(current) = status.recv().await {
+        let updated = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        if let Err(e) = encode(&current, updated).and_then(|data| write_atomic(&path, &data)) {
+            warn!("Failed to write the status file {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content() {
+        let status = Status {
+            phase: Phase::Connected,
+            ssid: Some("Home".to_owned()),
+            ip: Some(Ipv4Addr::new(192, 168, 1, 23)),
+            ipv6: Vec::new(),
+            error: None,
+            progress: None,
+            quality: None,
+            safe_mode: None,
+            update: None,
+            paired: false,
+            degraded: false,
+            arp_spoof: None,
+            twin_aps: Vec::new(),
+            revision: 4,
+        };
+        assert_eq!(
+            String::from_utf8(encode(&status, 1577836800).unwrap()).unwrap(),
+            r#"{"phase":"connected","ssid":"Home","ip":"192.168.1.23","error":null,"updated":1577836800}"#
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! # Hotspot subnet
+//! The hotspot uses the /24 subnet of the portal gateway (`--portal-gateway`, default 192.168.42.1).
+//! If an upstream connection of the device, for example the ethernet port behind a 10.0.0.0/24 router,
+//! uses an overlapping subnet, hotspot clients and the upstream network cannot be told apart and routing breaks.
+//!
+//! Before the hotspot starts, the subnets of the other interfaces are read ([`upstream_subnets`]).
+//! On a clash, another private (RFC 1918) range is picked, see [`gateway_avoiding`].
+//! `--fixed-portal-gateway` disables the automatic choice. It is also disabled for backends that do not assign
+//! the hotspot address themselves (iwd, ConnMan).
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// The prefix length of the hotspot subnet. The dhcp server serves a /24.
+pub const HOTSPOT_PREFIX: u8 = 24;
+
+/// Alternative hotspot subnets, tried in this order if the configured one clashes
+const ALTERNATIVES: [[u8; 3]; 6] = [
+    [192, 168, 42],
+    [192, 168, 142],
+    [10, 42, 0],
+    [10, 142, 0],
+    [172, 16, 42],
+    [172, 31, 42],
+];
+
+/// An IPv4 subnet
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Subnet {
+    pub address: Ipv4Addr,
+    pub prefix: u8,
+}
+
+impl Subnet {
+    pub fn new(address: Ipv4Addr, prefix: u8) -> Self {
+        Subnet {
+            address,
+            prefix: prefix.min(32),
+        }
+    }
+
+    fn mask(&self) -> u32 {
+        match self.prefix {
+            0 => 0,
+            prefix => u32::max_value() << (32 - prefix as u32),
+        }
+    }
+
+    /// Whether both subnets have addresses in common
+    pub fn overlaps(&self, other: &Subnet) -> bool {
+        let mask = self.mask() & other.mask();
+        u32::from(self.address) & mask == u32::from(other.address) & mask
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let network = Ipv4Addr::from(u32::from(self.address) & self.mask());
+        write!(f, "{}/{}", network, self.prefix)
+    }
+}
+
+/// The IPv4 subnets of all interfaces except the given hotspot interface and loopback interfaces
+pub fn upstream_subnets(hotspot_interface: &str) -> Vec<Subnet> {
+    use nix::sys::socket::SockAddr;
+    let addresses = match nix::ifaddrs::getifaddrs() {
+        Ok(addresses) => addresses,
+        Err(e) => {
+            warn!("Failed to list the interface addresses: {}", e);
+            return Vec::new();
+        },
+    };
+    addresses
+        .filter(|a| a.interface_name != hotspot_interface)
+        .filter_map(|a| match (a.address, a.netmask) {
+            (Some(SockAddr::Inet(address)), Some(SockAddr::Inet(netmask))) => {
+                match (address.to_std().ip(), netmask.to_std().ip()) {
+                    (IpAddr::V4(address), IpAddr::V4(netmask)) if !address.is_loopback() => {
+                        Some(Subnet::new(address, u32::from(netmask).count_ones() as u8))
+                    },
+                    _ => None,
+                }
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// A gateway whose hotspot subnet does not overlap the given upstream subnets. The given gateway if it does
+/// not clash. Otherwise the first free alternative subnet, with the same host part. None if all clash.
+pub fn gateway_avoiding(gateway: Ipv4Addr, upstream: &[Subnet]) -> Option<Ipv4Addr> {
+    let host = gateway.octets()[3];
+    let free = |gateway: &Ipv4Addr| {
+        let subnet = Subnet::new(*gateway, HOTSPOT_PREFIX);
+        !upstream.iter().any(|u| u.overlaps(&subnet))
+    };
+    std::iter::once(gateway)
+        .chain(ALTERNATIVES.iter().map(|n| Ipv4Addr::new(n[0], n[1], n[2], host)))
+        .find(free)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subnet_conflicts() {
+        let gateway = Ipv4Addr::new(10, 0, 0, 1);
+        let upstream = [Subnet::new(Ipv4Addr::new(10, 0, 0, 17), 24)];
+        assert_eq!(upstream[0].to_string(), "10.0.0.0/24");
+        assert_eq!(gateway_avoiding(gateway, &upstream), Some(Ipv4Addr::new(192, 168, 42, 1)));
+        assert_eq!(gateway_avoiding(gateway, &[]), Some(gateway));
+
+        // A /8 upstream covers both 10.x alternatives
+        let gateway = Ipv4Addr::new(192, 168, 42, 1);
+        let upstream = [
+            Subnet::new(Ipv4Addr::new(192, 168, 0, 2), 16),
+            Subnet::new(Ipv4Addr::new(10, 1, 2, 3), 8),
+        ];
+        assert_eq!(gateway_avoiding(gateway, &upstream), Some(Ipv4Addr::new(172, 16, 42, 1)));
+
+        let upstream = [Subnet::new(Ipv4Addr::new(0, 0, 0, 0), 0)];
+        assert_eq!(gateway_avoiding(gateway, &upstream), None);
+    }
+}
@@ -0,0 +1,312 @@
+//! # Connect attempt traces
+//! Each connect attempt is a trace: A root span for the attempt and child spans for its phases.
+//! The network backend marks the phases with [`begin`] and [`end`], like the metrics of [`crate::stats`]
+//! the timestamps are kept in static atomics. There is only one connect attempt at a time.
+//! The state machine follows the phases with [`subscribe`] to publish the progress of the attempt.
+//!
+//! The NetworkManager backend reports all phases. Other backends only report the connectivity check,
+//! which is measured by the state machine.
+//!
+//! With the "otlp" feature and `--otlp-endpoint`, traces are exported via OTLP/HTTP with the json encoding,
+//! for example to "http://collector:4318/v1/traces" of an OpenTelemetry collector.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use futures_channel::mpsc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Phase {
+    /// Looking up an existing connection profile for the network
+    FindConnection,
+    /// The D-Bus call that creates or updates the profile and activates it
+    Activate,
+    /// Scanning for the access point, association and authentication
+    Associate,
+    /// Ip configuration, usually DHCP
+    Dhcp,
+    /// Storing the profile
+    Save,
+    /// Connection quality measurement, see [`crate::quality`]
+    ConnectivityCheck,
+}
+
+const PHASES: [Phase; 6] = [
+    Phase::FindConnection,
+    Phase::Activate,
+    Phase::Associate,
+    Phase::Dhcp,
+    Phase::Save,
+    Phase::ConnectivityCheck,
+];
+
+/// Unix timestamps in nanoseconds, 0 if not recorded. See [`PHASES`] for the order.
+static STARTED: [AtomicU64; 6] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+static ENDED: [AtomicU64; 6] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+static FAILED: [AtomicBool; 6] = [
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
+/// The receiver of the phases of the current connect attempt, if any
+static SUBSCRIBER: Mutex<Option<mpsc::UnboundedSender<Phase>>> = Mutex::new(None);
+
+impl Phase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::FindConnection => "find_connection",
+            Phase::Activate => "activate",
+            Phase::Associate => "associate",
+            Phase::Dhcp => "dhcp",
+            Phase::Save => "save",
+            Phase::ConnectivityCheck => "connectivity_check",
+        }
+    }
+
+    fn index(&self) -> usize {
+        PHASES.iter().position(|p| p == self).expect("all phases are listed")
+    }
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(1)
+}
+
+/// Returns the phases that begin from now on. Replaces the previous subscriber.
+pub fn subscribe() -> mpsc::UnboundedReceiver<Phase> {
+    let (tx, rx) = mpsc::unbounded();
+    *SUBSCRIBER.lock().expect("trace subscriber lock") = Some(tx);
+    rx
+}
+
+/// The given phase of the current connect attempt starts
+pub fn begin(phase: Phase) {
+    STARTED[phase.index()].store(now_nanos(), Ordering::Relaxed);
+    if let Some(tx) = SUBSCRIBER.lock().expect("trace subscriber lock").as_ref() {
+        let _ = tx.unbounded_send(phase);
+    }
+}
+
+/// The given phase of the current connect attempt ended
+pub fn end(phase: Phase) {
+    ENDED[phase.index()].store(now_nanos(), Ordering::Relaxed);
+}
+
+/// The given phase of the current connect attempt ended unsuccessfully
+pub fn fail(phase: Phase) {
+    FAILED[phase.index()].store(true, Ordering::Relaxed);
+    end(phase);
+}
+
+/// The first phase of the current connect attempt that ended unsuccessfully
+pub fn failed_phase() -> Option<Phase> {
+    PHASES.iter().copied().find(|phase| FAILED[phase.index()].load(Ordering::Relaxed))
+}
+
+/// A finished span
+#[derive(Clone, Debug)]
+pub struct Span {
+    pub span_id: u64,
+    /// None for the root span
+    pub parent: Option<u64>,
+    pub name: &'static str,
+    pub start: u64,
+    pub end: u64,
+    pub ok: bool,
+    pub attributes: Vec<(&'static str, String)>,
+}
+
+/// The trace of a connect attempt
+#[derive(Clone, Debug)]
+pub struct Trace {
+    pub trace_id: u128,
+    pub spans: Vec<Span>,
+}
+
+/// The trace of the connect attempt in progress
+pub struct AttemptTrace {
+    ids: RandomState,
+    attempt: u64,
+    ssid: String,
+    start: u64,
+}
+
+impl AttemptTrace {
+    /// Starts the trace of a connect attempt. Phases of a previous attempt are discarded.
+    pub fn start(attempt: u64, ssid: &str) -> Self {
+        for i in 0..PHASES.len() {
+            STARTED[i].store(0, Ordering::Relaxed);
+            ENDED[i].store(0, Ordering::Relaxed);
+            FAILED[i].store(false, Ordering::Relaxed);
+        }
+        AttemptTrace {
+            ids: RandomState::new(),
+            attempt,
+            ssid: ssid.to_owned(),
+            start: now_nanos(),
+        }
+    }
+
+    fn id(&self, value: u64) -> u64 {
+        let mut hasher = self.ids.build_hasher();
+        hasher.write_u64(self.start);
+        hasher.write_u64(value);
+        hasher.finish()
+    }
+
+    /// Ends the trace. The outcome is a message key like "connected" or "cancelled".
+    /// Phases that have not ended are ended now and marked as failed.
+    pub fn finish(self, outcome: &'static str) -> Trace {
+        let end = now_nanos();
+        let root_id = self.id(0);
+        let mut spans = vec![Span {
+            span_id: root_id,
+            parent: None,
+            name: "connect_attempt",
+            start: self.start,
+            end,
+            ok: outcome == "connected",
+            attributes: vec![
+                ("wifi.attempt", self.attempt.to_string()),
+                ("wifi.ssid", self.ssid.clone()),
+                ("wifi.outcome", outcome.to_owned()),
+            ],
+        }];
+        for (i, phase) in PHASES.iter().enumerate() {
+            let started = STARTED[i].load(Ordering::Relaxed);
+            if started == 0 {
+                continue;
+            }
+            let ended = ENDED[i].load(Ordering::Relaxed);
+            spans.push(Span {
+                span_id: self.id(i as u64 + 1),
+                parent: Some(root_id),
+                name: phase.name(),
+                start: started,
+                end: if ended >= started { ended } else { end },
+                ok: ended >= started && !FAILED[i].load(Ordering::Relaxed),
+                attributes: Vec::new(),
+            });
+        }
+        Trace {
+            trace_id: (self.id(u64::max_value()) as u128) << 64 | root_id as u128,
+            spans,
+        }
+    }
+}
+
+impl Trace {
+    /// The trace as OTLP json (ExportTraceServiceRequest)
+    pub fn to_otlp_json(&self) -> serde_json::Value {
+        let spans: Vec<serde_json::Value> = self
+            .spans
+            .iter()
+            .map(|span| {
+                let attributes: Vec<serde_json::Value> = span
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| serde_json::json!({ "key": key, "value": { "stringValue": value } }))
+                    .collect();
+                serde_json::json!({
+                    "traceId": format!("{:032x}", self.trace_id),
+                    "spanId": format!("{:016x}", span.span_id),
+                    "parentSpanId": span.parent.map(|p| format!("{:016x}", p)).unwrap_or_default(),
+                    "name": span.name,
+                    // SPAN_KIND_INTERNAL
+                    "kind": 1,
+                    "startTimeUnixNano": span.start.to_string(),
+                    "endTimeUnixNano": span.end.to_string(),
+                    "attributes": attributes,
+                    // STATUS_CODE_OK, STATUS_CODE_ERROR
+                    "status": { "code": if span.ok { 1 } else { 2 } },
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [{ "key": "service.name", "value": { "stringValue": "wifi-captive" } }]
+                },
+                "scopeSpans": [{ "scope": { "name": "wifi-captive" }, "spans": spans }]
+            }]
+        })
+    }
+}
+
+/// Sends the trace to the OTLP/HTTP endpoint. Failures are logged, traces are not retried.
+#[cfg(feature = "otlp")]
+pub async fn export(endpoint: String, trace: Trace) {
+    use hyper::{Body, Client, Method, Request};
+
+    let body = trace.to_otlp_json().to_string();
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(endpoint.as_str())
+        .header("Content-Type", "application/json")
+        .body(Body::from(body));
+    let result = match request {
+        Ok(request) => Client::new().request(request).await.map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    match result {
+        Ok(response) if response.status().is_success() => {},
+        Ok(response) => warn!("Exporting the connect trace failed: {}", response.status()),
+        Err(e) => warn!("Exporting the connect trace failed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attempt_trace() {
+        let trace = AttemptTrace::start(3, "Home");
+        begin(Phase::Associate);
+        end(Phase::Associate);
+        begin(Phase::Dhcp);
+        let trace = trace.finish("connection_failed");
+
+        assert_eq!(trace.spans.len(), 3);
+        let root = &trace.spans[0];
+        assert!(root.parent.is_none() && !root.ok);
+        assert_eq!(trace.spans[1].name, "associate");
+        assert!(trace.spans[1].ok);
+        assert_eq!(trace.spans[1].parent, Some(root.span_id));
+        // Not ended: Failed
+        assert_eq!(trace.spans[2].name, "dhcp");
+        assert!(!trace.spans[2].ok);
+
+        let json = trace.to_otlp_json();
+        let spans = &json["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        assert_eq!(spans[0]["traceId"].as_str().unwrap().len(), 32);
+        assert_eq!(spans[0]["parentSpanId"], "");
+        assert_eq!(spans[1]["spanId"].as_str().unwrap().len(), 16);
+        assert_eq!(spans[0]["attributes"][2]["value"]["stringValue"], "connection_failed");
+    }
+}
@@ -2,16 +2,21 @@
 use super::CaptivePortalError;
 
 use futures_util::future::Either;
-use futures_util::future::try_select;
+use futures_util::future::{select, try_select};
 use pin_utils::pin_mut;
 use std::future::Future;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::task::{self, Poll};
 use std::time::Duration;
 use tokio::net::UdpSocket;
 use tokio::time::Delay;
 use tokio::signal::ctrl_c;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Set once SIGINT or SIGTERM has been received, see [`watch_exit_signals`]
+static EXIT_REQUESTED: AtomicBool = AtomicBool::new(false);
 
 /// A wifi password must be between 8 and 32 characters
 pub fn verify_password(password: &str) -> Result<(), CaptivePortalError> {
@@ -30,9 +35,65 @@ pub fn verify_password(password: &str) -> Result<(), CaptivePortalError> {
     }
 }
 
+/// Compares two secrets, like the admin token, in a time that does not depend on where they differ.
+/// Only the length can be learned from the timing.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The first IPv4 address of the given network interface
+pub fn interface_ipv4(interface_name: &str) -> Option<Ipv4Addr> {
+    use nix::sys::socket::SockAddr;
+    let addresses = match nix::ifaddrs::getifaddrs() {
+        Ok(addresses) => addresses,
+        Err(e) => {
+            warn!("Failed to list the interface addresses: {}", e);
+            return None;
+        },
+    };
+    addresses
+        .filter(|a| a.interface_name == interface_name)
+        .filter_map(|a| match a.address {
+            Some(SockAddr::Inet(inet)) => match inet.to_std().ip() {
+                IpAddr::V4(ip) => Some(ip),
+                IpAddr::V6(_) => None,
+            },
+            _ => None,
+        })
+        .next()
+}
+
+/// The global IPv6 addresses of the given network interface. Link-local addresses are skipped.
+pub fn interface_ipv6(interface_name: &str) -> Vec<Ipv6Addr> {
+    use nix::sys::socket::SockAddr;
+    let addresses = match nix::ifaddrs::getifaddrs() {
+        Ok(addresses) => addresses,
+        Err(e) => {
+            warn!("Failed to list the interface addresses: {}", e);
+            return Vec::new();
+        },
+    };
+    addresses
+        .filter(|a| a.interface_name == interface_name)
+        .filter_map(|a| match a.address {
+            Some(SockAddr::Inet(inet)) => match inet.to_std().ip() {
+                IpAddr::V6(ip) if !is_link_local(&ip) && !ip.is_loopback() => Some(ip),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// fe80::/10. `Ipv6Addr::is_unicast_link_local` is not stable.
+fn is_link_local(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
+}
+
 /// Takes an optional field member of the portal and sets the optional to None.
 ///
 /// Safety: Because the optional fields are never moved, this is considered safe, albeit the pinning.
+#[cfg(any(feature = "ui", feature = "iwd"))]
 pub(crate) fn take_optional<F, X, S>(mut subject: Pin<&mut S>, fun: F)
     where
         F: for<'r> FnOnce(&'r mut S) -> &'r mut Option<X>,
@@ -44,6 +105,26 @@ pub(crate) fn take_optional<F, X, S>(mut subject: Pin<&mut S>, fun: F)
     let _ = field.get_mut().take();
 }
 
+/// Restricts the socket to the given network interface (SO_BINDTODEVICE). Replies, also to broadcast addresses,
+/// leave via that interface instead of the interface of the default route. Requires `CAP_NET_RAW`.
+pub(crate) fn bind_to_device(socket: &UdpSocket, interface_name: &str) -> std::io::Result<()> {
+    use nix::libc;
+    use std::os::unix::io::AsRawFd;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            interface_name.as_ptr() as *const libc::c_void,
+            interface_name.len() as libc::socklen_t,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
 /// Receives the next packet on a udp socket. The future resolves if either a packet got received,
 /// an error occurred or the exit handler that belongs to the given exit_receiver has been triggered.
 pub async fn receive_or_exit(
@@ -75,14 +156,39 @@ pub async fn receive_or_exit(
     }
 }
 
-/// Wraps the given future with a ctrl+c signal listener. Returns None if the signal got caught
+/// Resolves on SIGINT (ctrl+c) or SIGTERM, which systemd sends to stop, restart or reboot. Resolves right away if
+/// one of them has been received before.
+async fn exit_signal() -> std::io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let interrupt = ctrl_c();
+    pin_mut!(interrupt);
+    let terminated = terminate.recv();
+    pin_mut!(terminated);
+    if !EXIT_REQUESTED.load(Ordering::Relaxed) {
+        if let Either::Left((Err(e), _)) = select(interrupt, terminated).await {
+            return Err(e);
+        }
+        EXIT_REQUESTED.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Listens for SIGINT and SIGTERM for the whole runtime of the service. Without a listener, a signal that
+/// arrives between two [`ctrl_c_or_future`] calls would be lost, see [`exit_signal`].
+pub async fn watch_exit_signals() {
+    if let Err(e) = exit_signal().await {
+        warn!("Cannot listen for exit signals: {}", e);
+    }
+}
+
+/// Wraps the given future with a SIGINT (ctrl+c) and SIGTERM signal listener. Returns None if a signal got caught
 /// and Some(return_value) otherwise.
 pub async fn ctrl_c_or_future<F, R>(connect_future: F) -> Result<Option<R>, CaptivePortalError>
     where
         F: std::future::Future<Output=Result<R, CaptivePortalError>>,
         R: Sized,
 {
-    let ctrlc = ctrl_c();
+    let ctrlc = exit_signal();
     pin_utils::pin_mut!(ctrlc);
     pin_utils::pin_mut!(connect_future);
 
@@ -103,19 +209,19 @@ pub async fn ctrl_c_or_future<F, R>(connect_future: F) -> Result<Option<R>, Capt
         }
     }
 
-    info!("SIGKILL: Graceful shutdown initialized ...");
+    info!("Exit signal: Graceful shutdown initialized ...");
     Ok(None)
 }
 
 
-/// Wraps the given future with a ctrl+c signal listener. Returns None if the signal got caught
+/// Wraps the given future with a SIGINT (ctrl+c) and SIGTERM signal listener. Returns None if a signal got caught
 /// and Some(return_value) otherwise.
 pub async fn ctrl_c_with_exit_handler<F, R>(connect_future: F, exit_handler: tokio::sync::oneshot::Sender<()>) -> Result<Option<R>, CaptivePortalError>
     where
         F: std::future::Future<Output=Result<R, CaptivePortalError>>,
         R: Sized,
 {
-    let ctrlc = ctrl_c();
+    let ctrlc = exit_signal();
     pin_utils::pin_mut!(ctrlc);
     pin_utils::pin_mut!(connect_future);
 
@@ -140,7 +246,7 @@ pub async fn ctrl_c_with_exit_handler<F, R>(connect_future: F, exit_handler: tok
         }
     }
 
-    info!("SIGKILL: Graceful shutdown initialized ...");
+    info!("Exit signal: Graceful shutdown initialized ...");
     Ok(None)
 }
 
@@ -208,3 +314,24 @@ pub trait FutureWithTimeout: Future {
 }
 
 impl<T: ?Sized> FutureWithTimeout for T where T: Future {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// systemd stops the service with SIGTERM. It must take the same clean exit path as ctrl+c.
+    #[tokio::test]
+    async fn terminate_signal() {
+        let future = ctrl_c_or_future(futures_util::future::pending::<Result<(), CaptivePortalError>>());
+        pin_mut!(future);
+        // The first poll registers the signal listeners
+        assert!(futures_util::poll!(&mut future).is_pending());
+        nix::sys::signal::raise(nix::sys::signal::Signal::SIGTERM).unwrap();
+        assert!(future.await.unwrap().is_none());
+        // A signal that has been received before is not lost
+        assert!(ctrl_c_or_future(futures_util::future::pending::<Result<(), CaptivePortalError>>())
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
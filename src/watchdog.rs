@@ -0,0 +1,76 @@
+//! # Network manager watchdog
+//! Network manager may hang without crashing, for example in a driver call. The service would then wait
+//! forever for state changes that never arrive.
+//!
+//! While connected and while the portal is running, the state machine reads the network manager version
+//! every `--watchdog-interval` seconds. A call that does not answer within [`PING_TIMEOUT`] counts as failure.
+//! The status is marked as degraded after the first failure. After [`MAX_FAILURES`] consecutive failures
+//! the D-Bus connection is re-established by starting over. With `--watchdog-restart`, network manager is
+//! restarted via the systemd D-Bus API first. The state machine starts over as soon as the network manager
+//! bus name is owned again, or after `--watchdog-restart-timeout` seconds.
+//!
+//! Only the NetworkManager backend is watched.
+
+use std::time::Duration;
+
+/// How long network manager has to answer a ping
+pub const PING_TIMEOUT: Duration = Duration::from_secs(10);
+/// Consecutive failed pings until the D-Bus connection is re-established
+pub const MAX_FAILURES: u32 = 3;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Watchdog {
+    /// None if disabled
+    pub interval: Option<Duration>,
+    /// Restart network manager before re-establishing the connection
+    pub restart_service: bool,
+    /// How long to wait for network manager to come back after a restart
+    pub restart_timeout: Duration,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Verdict {
+    Healthy,
+    /// Network manager did not answer, but is given another chance
+    Degraded,
+    /// Re-establish the D-Bus connection
+    Unresponsive,
+}
+
+/// Counts consecutive failed pings
+#[derive(Default)]
+pub struct Health {
+    failures: u32,
+}
+
+impl Health {
+    /// Records the result of a ping
+    pub fn record(&mut self, answered: bool) -> Verdict {
+        if answered {
+            self.failures = 0;
+            return Verdict::Healthy;
+        }
+        self.failures += 1;
+        match self.failures >= MAX_FAILURES {
+            true => Verdict::Unresponsive,
+            false => Verdict::Degraded,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consecutive_failures() {
+        let mut health = Health::default();
+        assert_eq!(health.record(false), Verdict::Degraded);
+        assert_eq!(health.record(false), Verdict::Degraded);
+        assert_eq!(health.record(true), Verdict::Healthy);
+        for _ in 1..MAX_FAILURES {
+            assert_eq!(health.record(false), Verdict::Degraded);
+        }
+        assert_eq!(health.record(false), Verdict::Unresponsive);
+    }
+}
@@ -0,0 +1,102 @@
+// Maintenance actions of the admin page. The requests carry the admin token as bearer token
+// and repeat the action name as confirmation, see "/api/admin/restart-wifi" and "/api/admin/reboot".
+// Saved networks are forgotten with "DELETE /api/saved?ssid=". Their stored passwords are read with
+// "/api/saved/secret?ssid=" and shown masked until revealed.
+
+const token_input = document.getElementById("admin_token");
+const result = document.getElementById("admin_result");
+
+document.querySelectorAll("[data-action]").forEach(button => button.addEventListener("click", ev => {
+    ev.preventDefault();
+    if (!confirm(button.dataset.question)) return;
+    device_action(button.dataset.action).catch(e => result.innerText = "Failed: " + e);
+}));
+
+async function show_result(response) {
+    if (response.ok) {
+        result.innerText = "Command " + (await response.json()).command + " queued.";
+    } else {
+        result.innerText = (await response.json().catch(() => ({title: response.statusText}))).title;
+    }
+    return response.ok;
+}
+
+// SSIDs that are not valid utf8 are arrays of octets
+function ssid_name(ssid) {
+    return Array.isArray(ssid) ? new TextDecoder().decode(new Uint8Array(ssid)) : ssid;
+}
+
+function ssid_query(ssid) {
+    if (!Array.isArray(ssid)) return encodeURIComponent(ssid);
+    return ssid.map(octet => "%" + octet.toString(16).padStart(2, "0")).join("");
+}
+
+async function forget(ssid, item) {
+    if (!confirm("Forget " + ssid_name(ssid) + "?")) return;
+    let response = await fetch("/api/saved?ssid=" + ssid_query(ssid), {
+        method: 'DELETE',
+        headers: {"Authorization": "Bearer " + token_input.value}
+    });
+    if (await show_result(response)) item.remove();
+}
+
+// The first click reads the password into the masked field, further clicks reveal and mask it
+async function show_secret(ssid, item) {
+    const input = item.querySelector(".saved_secret");
+    const button = item.querySelector(".saved_show");
+    if (!input.classList.contains("hide")) {
+        const masked = input.type === "password";
+        input.type = masked ? "text" : "password";
+        button.innerText = masked ? "Hide" : "Show";
+        return;
+    }
+    let response = await fetch("/api/saved/secret?ssid=" + ssid_query(ssid), {
+        headers: {"Authorization": "Bearer " + token_input.value}
+    });
+    if (!response.ok) {
+        result.innerText = (await response.json().catch(() => ({title: response.statusText}))).title;
+        return;
+    }
+    const secret = (await response.json()).secret;
+    if (secret === null) {
+        result.innerText = "No password is stored for " + ssid_name(ssid) + ".";
+        return;
+    }
+    input.value = secret;
+    input.classList.remove("hide");
+    button.innerText = "Show";
+}
+
+async function show_saved_networks() {
+    const template = document.getElementById("saved_item");
+    const list = document.getElementById("saved_list");
+    const saved = await fetch("/api/saved").then(r => r.ok ? r.json() : []).catch(() => []);
+    list.innerHTML = "";
+    for (const ssid of saved) {
+        const item = template.content.firstElementChild.cloneNode(true);
+        item.querySelector(".saved_name").innerText = ssid_name(ssid);
+        item.querySelector(".saved_show").addEventListener("click", ev => {
+            ev.preventDefault();
+            show_secret(ssid, item).catch(e => result.innerText = "Failed: " + e);
+        });
+        item.querySelector(".saved_forget").addEventListener("click", ev => {
+            ev.preventDefault();
+            forget(ssid, item).catch(e => result.innerText = "Failed: " + e);
+        });
+        list.appendChild(item);
+    }
+}
+
+show_saved_networks();
+
+async function device_action(action) {
+    let response = await fetch("/api/admin/" + action, {
+        method: 'POST',
+        headers: {
+            "Authorization": "Bearer " + token_input.value,
+            "Content-Type": "application/json"
+        },
+        body: JSON.stringify({confirm: action})
+    });
+    await show_result(response);
+}
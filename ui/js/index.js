@@ -6,17 +6,62 @@ const ssid_input = document.getElementById("ssid");
 const passphrase_input = document.getElementById("passphrase");
 const hw_input = document.getElementById("hw"); // wifi hw -> used as unique id
 const submit_button = document.getElementById('submit_btn');
+const wps_button = document.getElementById('wps_btn');
+const dpp_input = document.getElementById("dpp_uri");
+const dpp_button = document.getElementById('dpp_btn');
 const refresh_button = document.getElementById("refresh_button");
 const refresh_text = document.getElementById("refresh_text");
 const input_mode = document.getElementById("mode");
 const identity_input = document.getElementById("identity");
+const hidden_input = document.getElementById("hidden");
+const ipv6_mode_input = document.getElementById("ipv6_mode");
+const search_input = document.getElementById("network_search");
+const more_button = document.getElementById("more_networks");
+
+// Dense environments have a hundred networks and more. The list is requested in pages, strongest first.
+const PAGE_SIZE = 20;
+let shown_networks = PAGE_SIZE;
+let search_timer = null;
+
+// The SSIDs of the saved connections. Updated by the "saved" server sent event.
+let saved_networks = [];
+
+// The selected network if its SSID is not valid UTF-8: The displayed SSID and the octets to connect with
+let selected_ssid_bytes = null;
 
 refresh_button.addEventListener("click", handle_refresh_button);
 
+// The search is done by the server. Wait for a pause in typing before requesting the list again.
+search_input.addEventListener("input", () => {
+    clearTimeout(search_timer);
+    search_timer = setTimeout(() => {
+        shown_networks = PAGE_SIZE;
+        load_networks().catch(e => console.error("Failed to fetch", e));
+    }, 300);
+});
+
+more_button.addEventListener("click", async ev => {
+    ev.preventDefault();
+    let response = await fetch(network_list_url(selectBox.children.length, PAGE_SIZE));
+    if (!response.ok) return;
+    shown_networks += PAGE_SIZE;
+    receive_list_of_networks(await response.json());
+    update_more_button(response);
+});
+
+// The static IPv6 fields are only sent in static mode, hidden fields are not part of the form data
+ipv6_mode_input.addEventListener("change", () => {
+    const group = document.querySelector('#ipv6-static-group');
+    const is_static = ipv6_mode_input.value === "static";
+    group.classList.toggle("hide", !is_static);
+    group.querySelectorAll("input").forEach(e => e.disabled = !is_static);
+});
+
 // Enable the submit button if a SSID (or ssid+password) is entered.
 // The password must be optional to accommodate the case of an open wifi.
 ssid_input.addEventListener("input", ev => {
     submit_button.disabled = ev.target.value.length === 0;
+    wps_button.disabled = ev.target.value.length === 0;
     if (!input_mode.value) input_mode.value = ssid_input.value.length > 0 ? "wpa" : "open";
     unselect_entry();
 });
@@ -31,10 +76,18 @@ passphrase_input.addEventListener("input", ev => {
  */
 function unselect_entry() {
     document.querySelectorAll(".target_link").forEach(e => delete e.dataset.selected);
+    document.querySelector('#channel-warning').classList.add('hide');
     hw_input.value = "";
     input_mode.value = "open";
 }
 
+/**
+ * A network from the list is visible, "hidden" only applies to manually entered SSIDs.
+ */
+function select_visible_entry() {
+    hidden_input.checked = false;
+}
+
 /**
  * Callback for when an entry in the wifi list has been selected.
  *
@@ -46,22 +99,48 @@ function entrySelected(selected, network) {
         submit_button.disabled = ssid_input.value.length === 0 || passphrase_input.value.length === 0;
         document.querySelector('#identity-group').classList.remove("hide");
         document.querySelector('#identity').classList.remove("hide");
-    } else if (network.security === 'wpa' || network.security === 'wep') {
+        document.querySelector('#enterprise-group').classList.remove("hide");
+    } else if (network.security === 'wpa' || network.security === 'wpa3' || network.security === 'wep') {
         submit_button.disabled = ssid_input.value.length === 0 || passphrase_input.value.length === 0;
+        document.querySelector('#identity-group').classList.add("hide");
+        document.querySelector('#identity').classList.add("hide");
+        document.querySelector('#enterprise-group').classList.add("hide");
         document.querySelector('#passphrase-group').classList.remove("hide");
         document.querySelector('#passphrase').classList.remove("hide");
     } else {
         submit_button.disabled = ssid_input.value.length === 0;
         document.querySelector('#identity-group').classList.add("hide");
         document.querySelector('#identity').classList.add("hide");
+        document.querySelector('#enterprise-group').classList.add("hide");
         document.querySelector('#passphrase-group').classList.add("hide");
         document.querySelector('#passphrase').classList.add("hide");
     }
 
+    select_visible_entry();
+    // WPS push-button only exists for WPA networks
+    wps_button.disabled = network.security !== 'wpa';
     input_mode.value = network.security;
     ssid_input.value = network.ssid;
+    selected_ssid_bytes = network.ssid_bytes ? {ssid: network.ssid, bytes: network.ssid_bytes} : null;
     hw_input.value = network.hw;
     passphrase_input.focus();
+    show_channel_warning(network).catch(e => console.error("Failed to fetch the channel report", e));
+}
+
+/**
+ * Warns if the channel of the selected network is congested. The user may connect anyway.
+ *
+ * @param network {Object} The network data
+ */
+async function show_channel_warning(network) {
+    let response = await fetch("/api/channels");
+    if (!response.ok) return;
+    let channel = (await response.json()).find(c => c.frequency === network.frequency);
+    if (!channel || !channel.congested || hw_input.value !== network.hw) return;
+    let el = document.querySelector('#channel-warning');
+    el.innerText = "Channel " + channel.channel + " is congested by " + channel.access_points +
+        " networks. The connection may be slow. If your access point offers another band, consider using that.";
+    el.classList.remove('hide');
 }
 
 /**
@@ -72,8 +151,10 @@ function entrySelected(selected, network) {
  * @param network.strength {int} The strength of the network in percent
  * @param network.frequency {int} The frequency of the network in Mhz
  * @param network.ssid {string} The SSID
+ * @param network.ssid_bytes {int[]} Optional. The octets of a SSID that is not valid UTF-8
  * @param network.hw {string} The unique address (mac) of the wifi network
- * @param network.security {string} The security. May be "professional", "wpa", "wep", "open"
+ * @param network.security {string} The security. May be "enterprise", "wpa", "wpa3", "wep", "none"
+ * @param network.vendor {string} Optional. The access point vendor
  */
 function createOption(id, network) {
     let option = document.getElementById(id);
@@ -100,15 +181,14 @@ function createOption(id, network) {
     strength.title = "Signal: " + network.strength + "%";
     strength.classList.add("waveStrength-" + Math.floor((network.strength + 10) * 4 / 100));
 
-    const freq = network.frequency > 5000 ? "5 Ghz" : "2 Ghz";
     const label = option.querySelector(".target_name");
     label.innerHTML = network.ssid;
 
-    const subtitle = option.querySelector(".target_subtitle");
-    subtitle.innerHTML = "Signal: " + network.strength + "% - " + freq;
+    option.network = network;
+    option.querySelector(".target_subtitle").innerHTML = subtitle_text(network);
 
     const encrypted = option.querySelector(".encrypted");
-    if (network.security !== "wpa" && network.security !== "enterprise" && network.security !== "wep")
+    if (network.security === "none")
         encrypted.classList.add("hide");
 
     if (is_new) selectBox.appendChild(option);
@@ -144,6 +224,20 @@ function connection_lost(error) {
     document.querySelectorAll(".pure-button").forEach(e => e.classList.add("pure-button-disabled"));
 }
 
+function subtitle_text(network) {
+    const freq = network.frequency > 5000 ? "5 Ghz" : "2 Ghz";
+    return "Signal: " + network.strength + "% - " + freq + (network.vendor ? " - " + network.vendor : "")
+        + (saved_networks.includes(network.ssid) ? " - Saved" : "");
+}
+
+// Saved connections may be added or removed outside of the portal, for example with nmcli
+function receive_saved_networks(saved) {
+    saved_networks = saved;
+    for (let option of selectBox.children) {
+        if (option.network) option.querySelector(".target_subtitle").innerHTML = subtitle_text(option.network);
+    }
+}
+
 function receive_list_of_networks(networks) {
     networks.sort((b, a) => {
         if (a.strength < b.strength) return -1;
@@ -155,6 +249,38 @@ function receive_list_of_networks(networks) {
     }
 }
 
+// The url of a page of the network list, with the entered search text
+function network_list_url(offset, limit) {
+    const params = new URLSearchParams({offset: offset, limit: limit});
+    const search = search_input.value.trim();
+    if (search) params.set("search", search);
+    return "/networks?" + params.toString();
+}
+
+function matches_search(network) {
+    return network.ssid.toLowerCase().includes(search_input.value.trim().toLowerCase());
+}
+
+// The "x-total-count" header is the number of all networks that match the search
+function update_more_button(response) {
+    const total = parseInt(response.headers.get("x-total-count") || "0", 10);
+    more_button.classList.toggle("hide", selectBox.children.length >= total);
+}
+
+// Replace the list with the pages shown so far
+async function load_networks() {
+    let response = await fetch(network_list_url(0, shown_networks));
+    if (!response.ok) {
+        document.querySelector('#connect-error').classList.remove("hide");
+        return;
+    }
+    while (selectBox.hasChildNodes()) {
+        selectBox.removeChild(selectBox.lastChild);
+    }
+    receive_list_of_networks(await response.json());
+    update_more_button(response);
+}
+
 // Remove everything in the list so far, show the selection page and refresh the network list.
 // Networks are sorted by signal strength
 async function get_networks() {
@@ -176,28 +302,168 @@ async function get_networks() {
     });
 
 
-    let response = await fetch("/networks");
-    if (!response.ok) {
-        document.querySelector('#connect-error').classList.remove("hide");
+    saved_networks = await fetch("/api/saved").then(r => r.ok ? r.json() : []).catch(() => []);
+    await load_networks();
+}
+
+// Show why the last connection attempt failed, in the language of the browser
+async function show_last_error() {
+    let response = await fetch("/api/status");
+    if (!response.ok) return;
+    let status = await response.json();
+    if (status.safe_mode) {
+        let el = document.querySelector('#safe-mode');
+        el.innerText = "Safe mode: The service crashed " + status.safe_mode.crashes + " times within "
+            + status.safe_mode.window_minutes + " minutes. Automatic reconnects are disabled.";
+        el.classList.remove('hide');
+    }
+    if (status.arp_spoof) {
+        let el = document.querySelector('#arp-spoof');
+        el.innerText = "Warning: The device " + status.arp_spoof + " impersonates this hotspot. "
+            + "Your data may be intercepted. Do not enter passwords until the device is gone.";
+        el.classList.remove('hide');
+    }
+    if (status.twin_aps) {
+        let el = document.querySelector('#twin-aps');
+        el.innerText = "Warning: Other access points use the name of this hotspot (" + status.twin_aps.join(", ")
+            + "). Make sure to set up the device via this page only.";
+        el.classList.remove('hide');
+    }
+    if (status.update) {
+        let el = document.querySelector('#update-notice');
+        el.innerText = (status.update.state === "in_progress"
+            ? "An update is being installed. Setting up a network is possible again afterwards."
+            : "An update is available for this device.")
+            + (status.update.message ? " " + status.update.message : "");
+        el.classList.remove('hide');
+    }
+    if (status.message) {
+        let el = document.querySelector('#last-error');
+        el.innerText = status.message;
+        el.classList.remove('hide');
+    }
+    if (status.error === "weak_connection") {
+        document.querySelector('#accept_weak_btn').classList.remove('hide');
+    }
+}
+
+const progress_texts = {
+    scanning: "Looking for the network",
+    associating: "Authenticating with the access point",
+    getting_ip: "Obtaining an IP address",
+    online: "Connected",
+};
+
+// The stage of the connection attempt. The portal stays reachable during attempts on a station interface,
+// show the form and the failure reason again if the attempt failed.
+function show_progress(progress) {
+    let el = document.querySelector('#progress');
+    if (progress.stage === "failed") {
+        el.classList.add('hide');
+        get_networks()
+            .then(() => preselect_last_attempt())
+            .catch(e => console.error("Failed to fetch", e));
+        show_last_error().catch(e => console.error("Failed to fetch the status", e));
         return;
     }
-    receive_list_of_networks(await response.json());
+    el.innerText = progress_texts[progress.stage] + (progress.ssid ? " (" + progress.ssid + ")" : "")
+        + (progress.stage === "online" ? "" : " ...");
+    el.classList.remove('hide');
 }
 
+// Connect again to the network that failed the quality gate, accepting the weak connection
+document.querySelector('#accept_weak_btn').addEventListener("click", ev => {
+    ev.preventDefault();
+    document.querySelector('#choose_wifi').classList.add('hide');
+    document.querySelector('#connect-error').classList.add('hide');
+    document.querySelector('#applying').classList.remove('hide');
+
+    fetch("/api/connect/accept-weak", {method: 'POST'}).then(v => {
+        if (!v.ok) throw Error("Server error " + v.status);
+    }).catch(err => {
+        document.querySelector('#applying').classList.add('hide');
+        get_networks().catch(e => console.error("Failed to fetch", e));
+        document.querySelector('#connect-error').classList.remove('hide');
+        console.log("Failed to submit", err);
+    });
+});
+
+// Show the device the client pairs with, if it opened the deep link of the device QR code
+async function show_pairing() {
+    let response = await fetch("/api/pairing");
+    if (!response.ok) return;
+    let pairing = await response.json();
+    if (!pairing.paired) return;
+    let device = pairing.device || {};
+    let name = [device.model, device.serial ? "(" + device.serial + ")" : null].filter(v => v).join(" ");
+    let el = document.querySelector('#pairing');
+    el.innerText = "Setting up " + (name || "this device") + ". Choose the network it should connect to.";
+    el.classList.remove('hide');
+}
+
+// Select the network of the client's last attempt again if it failed, and show why next to the network.
+// A network that is not on the shown pages is searched for.
+async function preselect_last_attempt() {
+    let response = await fetch("/api/last-attempt");
+    if (!response.ok) return;
+    let last = await response.json();
+    if (!last || !last.error) return;
+    const find = () => Array.from(selectBox.children)
+        .find(o => o.network && (last.hw ? o.network.hw === last.hw : o.network.ssid === last.ssid));
+    let option = find();
+    if (!option) {
+        search_input.value = last.ssid;
+        await load_networks();
+        option = find();
+    }
+    if (!option) {
+        // A hidden network or out of reach now
+        ssid_input.value = last.ssid;
+        submit_button.disabled = false;
+        return;
+    }
+    unselect_entry();
+    option.querySelector(".target_link").dataset.selected = "true";
+    entrySelected(option, option.network);
+    let reason = document.createElement("div");
+    reason.className = "target_error";
+    reason.innerText = last.message || "The last attempt failed";
+    option.querySelector(".target_subtitle").parentElement.appendChild(reason);
+    if (last.error === "wrong_password") {
+        // The network manager rejected the passphrase. Ask for it again right away.
+        passphrase_input.value = "";
+        passphrase_input.focus();
+    }
+    option.scrollIntoView({block: "nearest"});
+    document.querySelector('#last-error').classList.add('hide');
+}
+
+show_last_error().catch(e => console.error("Failed to fetch the status", e));
+show_pairing().catch(e => console.error("Failed to fetch the pairing context", e));
+
 get_networks()
     .then(() => {
-        // There are three types of events coming form the backend: Added, Removed, List
+        preselect_last_attempt().catch(e => console.error("Failed to fetch the last attempt", e));
+
+        // There are five types of events coming form the backend: Added, Removed, List, saved and progress
         const evtSource = new EventSource("/events");
 
-        evtSource.addEventListener("List", async event => {
-            receive_list_of_networks(JSON.parse(event.data));
+        // The full list. Request the shown pages again instead, for the search and the paging.
+        evtSource.addEventListener("List", async () => {
+            await load_networks();
         });
 
         evtSource.addEventListener("Added", async event => {
             let event_data = JSON.parse(event.data);
             let id = "ssid_" + event_data.hw.replace(":", "_");
             console.log("Wifi added/updated", event_data);
-            createOption(id, event_data)
+            // Only networks of the search and as long as the shown pages are not full
+            if (matches_search(event_data) && (document.getElementById(id) || selectBox.children.length < shown_networks))
+                createOption(id, event_data)
+        });
+
+        evtSource.addEventListener("saved", async event => {
+            receive_saved_networks(JSON.parse(event.data));
         });
 
         evtSource.addEventListener("Removed", async event => {
@@ -206,6 +472,10 @@ get_networks()
             if (el) el.remove();
             console.log("Wifi removed", event_data);
         });
+        evtSource.addEventListener("progress", async event => {
+            show_progress(JSON.parse(event.data));
+        });
+
         // Display an error message if connection lost
         evtSource.onerror = connection_lost;
         evtSource.onopen = connection_reestablished;
@@ -249,11 +519,35 @@ function handle_refresh_button(ev) {
 
 /// Handle the form submit
 let form = document.querySelector('form');
+
+// Connect without the passphrase: The router hands it out after its WPS button has been pushed
+wps_button.addEventListener("click", ev => {
+    ev.preventDefault();
+    input_mode.value = "wps";
+    passphrase_input.value = "";
+    form.dispatchEvent(new Event("submit", {cancelable: true}));
+});
+
+// Easy Connect: The router hands out wifi name and passphrase. Phones show the QR code content after scanning it.
+dpp_input.addEventListener("input", () => {
+    dpp_button.disabled = !dpp_input.value.trim().startsWith("DPP:");
+});
+
+dpp_button.addEventListener("click", ev => {
+    ev.preventDefault();
+    input_mode.value = "dpp";
+    passphrase_input.value = "";
+    form.dispatchEvent(new Event("submit", {cancelable: true}));
+});
+
 form.addEventListener("submit", ev => {
     ev.preventDefault();
     ev.stopPropagation();
     document.querySelector('#choose_wifi').classList.add('hide');
     document.querySelector('#connect-error').classList.add('hide');
+    document.querySelector('#credential-error').classList.add('hide');
+    document.querySelector('#wps-hint').classList.toggle('hide', input_mode.value !== "wps");
+    document.querySelector('#dpp-hint').classList.toggle('hide', input_mode.value !== "dpp");
     document.querySelector('#applying').classList.remove('hide');
 
     const formData = new FormData(form);
@@ -261,18 +555,54 @@ form.addEventListener("submit", ev => {
     formData.forEach((value, key) => {
         if (value && value.length) object[key] = value
     });
+    if (input_mode.value !== "dpp") delete object.dpp_uri;
+    // The displayed SSID is lossy if it is not valid UTF-8. Connect with the octets unless the user edited it.
+    if (selected_ssid_bytes && object.ssid === selected_ssid_bytes.ssid) object.ssid = selected_ssid_bytes.bytes;
+    // The network does not broadcast its SSID. Network manager probes for it.
+    if (hidden_input.checked) object.hidden = true;
+    // Static IP: The prefixes are numbers and the DNS servers lists
+    for (const version of ["ipv4", "ipv6"]) {
+        const prefix = version + "_prefix", dns = version + "_dns";
+        if (object[prefix]) object[prefix] = parseInt(object[prefix], 10);
+        if (object[dns]) object[dns] = object[dns].split(",").map(s => s.trim()).filter(s => s.length);
+    }
     const json = JSON.stringify(object);
 
     fetch("/connect", {
         method: 'POST', headers: {
             'Content-Type': 'application/json'
         }, body: json
-    }).then(v => {
-        if (!v.ok) throw Error("Server error " + v.status);
+    }).then(async v => {
+        if (v.ok) return;
+        const problem = await v.json().catch(() => ({}));
+        if (problem.violation) {
+            show_violation(problem.violation);
+            return;
+        }
+        if (problem.type && problem.type.endsWith("#invalid-ip-configuration")) {
+            show_violation({message: problem.detail, field: "ipv4_address"});
+            return;
+        }
+        if (problem.type && problem.type.endsWith("#invalid-dpp-uri")) {
+            show_violation({message: problem.detail, field: "dpp_uri"});
+            return;
+        }
+        throw Error("Server error " + v.status);
     }).catch(err => {
         document.querySelector('#applying').classList.add('hide');
         get_networks().catch(e => console.error("Failed to fetch", e));
         document.querySelector('#connect-error').classList.remove('hide');
         console.log("Failed to submit", err);
     });
-});
\ No newline at end of file
+});
+
+// The entered credentials are rejected by the credential policy. Show the form again with the message.
+function show_violation(violation) {
+    document.querySelector('#applying').classList.add('hide');
+    document.querySelector('#choose_wifi').classList.remove('hide');
+    let el = document.querySelector('#credential-error');
+    el.innerText = violation.message;
+    el.classList.remove('hide');
+    let field = document.getElementById(violation.field);
+    if (field) field.focus();
+}